@@ -0,0 +1,253 @@
+use crate::onion::Onion;
+use crate::secp::{self, ComSigError, ComSignature, Commitment, PublicKey, SecretKey, SigDomain};
+
+use grin_core::ser;
+use grin_util::ToHex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Params of the 'swap' API
+#[derive(Serialize, Deserialize)]
+pub struct SwapReq {
+	pub onion: Onion,
+	#[serde(with = "secp::comsig_serde")]
+	pub comsig: ComSignature,
+	pub expiry_height: u64,
+}
+
+/// Params of the 'cancel_swap' API
+#[derive(Serialize, Deserialize)]
+pub struct CancelReq {
+	/// hex-encoded commitment of the swap's input
+	pub commit: String,
+	#[serde(with = "secp::comsig_serde")]
+	pub comsig: ComSignature,
+	pub expiry_height: u64,
+}
+
+/// Params of the 'check_swap' API
+#[derive(Serialize, Deserialize)]
+pub struct CheckReq {
+	/// hex-encoded commitment of the swap's input
+	pub commit: String,
+	#[serde(with = "secp::comsig_serde")]
+	pub comsig: ComSignature,
+	pub expiry_height: u64,
+}
+
+/// Params of the 'get_receipt' API
+#[derive(Serialize, Deserialize)]
+pub struct ReceiptReq {
+	/// hex-encoded commitment of the swap's input
+	pub commit: String,
+	#[serde(with = "secp::comsig_serde")]
+	pub comsig: ComSignature,
+	pub expiry_height: u64,
+}
+
+/// Error types for building requests
+#[derive(Error, Debug)]
+pub enum RequestError {
+	#[error("Failed to serialize onion: {0:?}")]
+	SerializationError(ser::Error),
+	#[error("Failed to sign request: {0}")]
+	SignatureError(ComSigError),
+}
+
+impl From<ser::Error> for RequestError {
+	fn from(err: ser::Error) -> RequestError {
+		RequestError::SerializationError(err)
+	}
+}
+
+impl From<ComSigError> for RequestError {
+	fn from(err: ComSigError) -> RequestError {
+		RequestError::SignatureError(err)
+	}
+}
+
+impl SwapReq {
+	/// Signs the onion for submission to the server with 'server_pubkey', proving ownership of
+	/// the onion's input, whose value and blinding factor are 'value' and 'blind'.
+	/// The signature is only valid until 'expiry_height'.
+	pub fn sign(
+		onion: Onion,
+		value: u64,
+		blind: &SecretKey,
+		server_pubkey: &PublicKey,
+		expiry_height: u64,
+	) -> Result<SwapReq, RequestError> {
+		let domain = SigDomain::new(server_pubkey, expiry_height);
+		let comsig = ComSignature::sign(value, blind, &domain, &onion.serialize()?)?;
+		Ok(SwapReq {
+			onion,
+			comsig,
+			expiry_height,
+		})
+	}
+}
+
+impl CancelReq {
+	/// Signs the cancellation of the swap of 'input_commit' (worth 'value', with blinding factor
+	/// 'blind') to 'output_commit', for the server with 'server_pubkey'
+	pub fn sign(
+		input_commit: &Commitment,
+		output_commit: &Commitment,
+		value: u64,
+		blind: &SecretKey,
+		server_pubkey: &PublicKey,
+		expiry_height: u64,
+	) -> Result<CancelReq, RequestError> {
+		let domain = SigDomain::new(server_pubkey, expiry_height);
+		let msg = cancel_message(input_commit, output_commit);
+		let comsig = ComSignature::sign(value, blind, &domain, &msg)?;
+		Ok(CancelReq {
+			commit: input_commit.to_hex(),
+			comsig,
+			expiry_height,
+		})
+	}
+}
+
+impl CheckReq {
+	/// Signs a status query for the swap of 'input_commit' (worth 'value', with blinding factor
+	/// 'blind'), for the server with 'server_pubkey'
+	pub fn sign(
+		input_commit: &Commitment,
+		value: u64,
+		blind: &SecretKey,
+		server_pubkey: &PublicKey,
+		expiry_height: u64,
+	) -> Result<CheckReq, RequestError> {
+		let domain = SigDomain::new(server_pubkey, expiry_height);
+		let comsig = ComSignature::sign(value, blind, &domain, &check_message(input_commit))?;
+		Ok(CheckReq {
+			commit: input_commit.to_hex(),
+			comsig,
+			expiry_height,
+		})
+	}
+}
+
+impl ReceiptReq {
+	/// Signs a request for the receipt of the swap of 'input_commit' (worth 'value', with
+	/// blinding factor 'blind'), for the server with 'server_pubkey'
+	pub fn sign(
+		input_commit: &Commitment,
+		value: u64,
+		blind: &SecretKey,
+		server_pubkey: &PublicKey,
+		expiry_height: u64,
+	) -> Result<ReceiptReq, RequestError> {
+		let domain = SigDomain::new(server_pubkey, expiry_height);
+		let comsig = ComSignature::sign(value, blind, &domain, &receipt_message(input_commit))?;
+		Ok(ReceiptReq {
+			commit: input_commit.to_hex(),
+			comsig,
+			expiry_height,
+		})
+	}
+}
+
+/// The message a swap's owner signs to cancel it: "MWIXNET_CANCEL" || input_commit || output_commit.
+/// Covering the output means a cancellation can't be replayed against a later swap of the same input.
+pub fn cancel_message(input_commit: &Commitment, output_commit: &Commitment) -> Vec<u8> {
+	let mut msg = b"MWIXNET_CANCEL".to_vec();
+	msg.extend_from_slice(&input_commit.0);
+	msg.extend_from_slice(&output_commit.0);
+	msg
+}
+
+/// The message a swap's owner signs to look up its status: "MWIXNET_CHECK" || input_commit.
+/// Only the owner can learn whether an input is queued, since inputs are public on chain.
+pub fn check_message(input_commit: &Commitment) -> Vec<u8> {
+	let mut msg = b"MWIXNET_CHECK".to_vec();
+	msg.extend_from_slice(&input_commit.0);
+	msg
+}
+
+/// The message a swap's owner signs to fetch its receipt: "MWIXNET_GET_RECEIPT" || input_commit.
+/// The receipt names the swap's output, so it's only handed to the input's owner.
+pub fn receipt_message(input_commit: &Commitment) -> Vec<u8> {
+	let mut msg = b"MWIXNET_GET_RECEIPT".to_vec();
+	msg.extend_from_slice(&input_commit.0);
+	msg
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		cancel_message, check_message, receipt_message, CancelReq, CheckReq, ReceiptReq, SwapReq,
+	};
+	use crate::onion::test_util;
+	use crate::secp::{self, PublicKey, Secp256k1, SigDomain};
+
+	/// Signed requests verify against the server's key and expiry height, and nothing else
+	#[test]
+	fn sign_requests() -> Result<(), Box<dyn std::error::Error>> {
+		let secp = Secp256k1::new();
+		let server_pubkey = PublicKey::from_secret_key(&secp, &secp::random_secret())?;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(1234, &blind)?;
+		let domain = SigDomain::new(&server_pubkey, 1000);
+
+		let mut onion = test_util::rand_onion();
+		onion.commit = input_commit.clone();
+		let swap = SwapReq::sign(onion.clone(), 1234, &blind, &server_pubkey, 1000)?;
+		assert!(swap
+			.comsig
+			.verify(&input_commit, &domain, &onion.serialize()?)
+			.is_ok());
+		assert!(swap
+			.comsig
+			.verify(
+				&input_commit,
+				&SigDomain::new(&server_pubkey, 1001),
+				&onion.serialize()?
+			)
+			.is_err());
+
+		let output_commit = secp::test_util::rand_commit();
+		let cancel = CancelReq::sign(
+			&input_commit,
+			&output_commit,
+			1234,
+			&blind,
+			&server_pubkey,
+			1000,
+		)?;
+		assert!(cancel
+			.comsig
+			.verify(
+				&input_commit,
+				&domain,
+				&cancel_message(&input_commit, &output_commit)
+			)
+			.is_ok());
+
+		let check = CheckReq::sign(&input_commit, 1234, &blind, &server_pubkey, 1000)?;
+		assert!(check
+			.comsig
+			.verify(&input_commit, &domain, &check_message(&input_commit))
+			.is_ok());
+		assert!(check
+			.comsig
+			.verify(
+				&input_commit,
+				&domain,
+				&cancel_message(&input_commit, &output_commit)
+			)
+			.is_err());
+
+		let receipt = ReceiptReq::sign(&input_commit, 1234, &blind, &server_pubkey, 1000)?;
+		assert!(receipt
+			.comsig
+			.verify(&input_commit, &domain, &receipt_message(&input_commit))
+			.is_ok());
+		assert!(receipt
+			.comsig
+			.verify(&input_commit, &domain, &check_message(&input_commit))
+			.is_err());
+		Ok(())
+	}
+}
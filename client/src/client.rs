@@ -0,0 +1,341 @@
+use crate::api::{CancelReq, CheckReq, ReceiptReq, SwapReq};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::time::Duration;
+use thiserror::Error;
+
+/// How long to wait on the server before giving up on a request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Error types for calls to a server's API
+#[derive(Error, Debug)]
+pub enum ClientError {
+	#[error("Invalid server url {0}")]
+	InvalidUrl(String),
+	#[error("Request failed: {0}")]
+	RequestFailed(std::io::Error),
+	#[error("Request failed with status {0}")]
+	BadStatus(u16),
+	#[error("Invalid response: {0}")]
+	InvalidResponse(String),
+	/// The server answered with a JSON-RPC error. 'data.name' holds the kind of swap error,
+	/// as listed in the server's 'get_info'.
+	#[error("Server error {code}: {message}")]
+	RpcError {
+		code: i64,
+		message: String,
+		data: Option<Value>,
+	},
+}
+
+impl From<std::io::Error> for ClientError {
+	fn from(err: std::io::Error) -> ClientError {
+		ClientError::RequestFailed(err)
+	}
+}
+
+/// Where the server listens
+#[derive(Clone, Debug)]
+enum Endpoint {
+	Tcp { host: String, port: u16 },
+	Unix(PathBuf),
+}
+
+/// A connection to the server, over TCP or a unix socket
+trait Stream: Read + Write {}
+
+impl<S: Read + Write> Stream for S {}
+
+/// Client for a mix server's public API, at a plain 'http' url such as 'http://127.0.0.1:3000/v1',
+/// or, for a server listening on a unix socket, an 'http+unix' url with the socket's path
+/// percent-encoded in place of the host, such as 'http+unix://%2Frun%2Fmwixnet.sock/v1'.
+/// Documents the server defines (info, swap checks and receipts) are returned as JSON, so the
+/// client keeps working as servers add fields to them.
+#[derive(Clone, Debug)]
+pub struct SwapClient {
+	endpoint: Endpoint,
+	path: String,
+}
+
+impl SwapClient {
+	pub fn new(url: &str) -> Result<SwapClient, ClientError> {
+		let invalid = || ClientError::InvalidUrl(url.to_string());
+		if let Some(rest) = url.strip_prefix("http+unix://") {
+			let (socket_path, path) = match rest.find('/') {
+				Some(i) => (&rest[..i], &rest[i..]),
+				None => (rest, "/"),
+			};
+			let socket_path = percent_decode(socket_path).ok_or_else(invalid)?;
+			if socket_path.is_empty() {
+				return Err(invalid());
+			}
+			return Ok(SwapClient {
+				endpoint: Endpoint::Unix(PathBuf::from(socket_path)),
+				path: path.to_string(),
+			});
+		}
+
+		let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+		let (authority, path) = match rest.find('/') {
+			Some(i) => (&rest[..i], &rest[i..]),
+			None => (rest, "/"),
+		};
+		let (host, port) = match authority.rsplit_once(':') {
+			Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+			None => (authority, 80),
+		};
+		if host.is_empty() {
+			return Err(invalid());
+		}
+		Ok(SwapClient {
+			endpoint: Endpoint::Tcp {
+				host: host.to_string(),
+				port,
+			},
+			path: path.to_string(),
+		})
+	}
+
+	/// Submits the swap. The server accepting it doesn't mean it's been included in a round yet.
+	pub fn swap(&self, swap: &SwapReq) -> Result<(), ClientError> {
+		self.call::<Value>("swap", json!([swap]))?;
+		Ok(())
+	}
+
+	/// The server's status, fees, keys and limits
+	pub fn get_info(&self) -> Result<Value, ClientError> {
+		self.call("get_info", json!([]))
+	}
+
+	/// The server's round history in aggregate: rounds completed, average anonymity set,
+	/// outputs mixed, and uptime
+	pub fn get_stats(&self) -> Result<Value, ClientError> {
+		self.call("get_stats", json!([]))
+	}
+
+	/// The status of a swap, queried with a request signed by its owner
+	pub fn check_swap(&self, check: &CheckReq) -> Result<Value, ClientError> {
+		self.call("check_swap", json!([check]))
+	}
+
+	/// The signed receipt for a swap, once it's been included in a round, requested by its owner
+	pub fn get_receipt(&self, receipt: &ReceiptReq) -> Result<Value, ClientError> {
+		self.call("get_receipt", json!([receipt]))
+	}
+
+	/// Withdraws a swap that hasn't been included in a round yet
+	pub fn cancel_swap(&self, cancel: &CancelReq) -> Result<(), ClientError> {
+		self.call::<Value>("cancel_swap", json!([cancel]))?;
+		Ok(())
+	}
+
+	/// Calls the JSON-RPC method, returning its result
+	fn call<OUT: DeserializeOwned>(&self, method: &str, params: Value) -> Result<OUT, ClientError> {
+		let req = json!({
+			"jsonrpc": "2.0",
+			"method": method,
+			"params": params,
+			"id": 1,
+		});
+		let res: Value = self.post(&req)?;
+		if let Some(error) = res.get("error") {
+			return Err(ClientError::RpcError {
+				code: error.get("code").and_then(Value::as_i64).unwrap_or(0),
+				message: error
+					.get("message")
+					.and_then(Value::as_str)
+					.unwrap_or("")
+					.to_string(),
+				data: error.get("data").cloned(),
+			});
+		}
+		let result = res
+			.get("result")
+			.cloned()
+			.ok_or_else(|| ClientError::InvalidResponse(format!("No result in {}", res)))?;
+		serde_json::from_value(result).map_err(|e| ClientError::InvalidResponse(e.to_string()))
+	}
+
+	fn post<IN: Serialize>(&self, input: &IN) -> Result<Value, ClientError> {
+		let body =
+			serde_json::to_vec(input).map_err(|e| ClientError::InvalidResponse(e.to_string()))?;
+		let (mut stream, host) = self.connect()?;
+
+		let req = format!(
+			"POST {} HTTP/1.1\r\nHost: {}\r\nAccept: application/json\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+			self.path,
+			host,
+			body.len()
+		);
+		let mut data = req.into_bytes();
+		data.extend_from_slice(&body);
+		stream.write_all(&data)?;
+
+		let mut res = Vec::new();
+		stream.read_to_end(&mut res)?;
+		let body = parse_response(&res)?;
+		serde_json::from_slice(body).map_err(|e| ClientError::InvalidResponse(e.to_string()))
+	}
+
+	/// Opens a connection to the server, returning it with the Host header to send
+	fn connect(&self) -> Result<(Box<dyn Stream>, String), ClientError> {
+		match &self.endpoint {
+			Endpoint::Tcp { host, port } => {
+				let stream = TcpStream::connect((host.as_str(), *port))?;
+				stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+				stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+				Ok((Box::new(stream), format!("{}:{}", host, port)))
+			}
+			#[cfg(unix)]
+			Endpoint::Unix(socket_path) => {
+				let stream = std::os::unix::net::UnixStream::connect(socket_path)?;
+				stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+				stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+				Ok((Box::new(stream), "localhost".to_string()))
+			}
+			#[cfg(not(unix))]
+			Endpoint::Unix(_) => Err(ClientError::RequestFailed(std::io::Error::new(
+				std::io::ErrorKind::Unsupported,
+				"Unix sockets aren't supported on this platform",
+			))),
+		}
+	}
+}
+
+/// Decodes the '%XX' escapes in a url's host, or None if one is malformed
+fn percent_decode(encoded: &str) -> Option<String> {
+	let mut bytes = Vec::new();
+	let mut rest = encoded.as_bytes();
+	while let Some((&b, tail)) = rest.split_first() {
+		if b == b'%' {
+			let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+			bytes.push(u8::from_str_radix(hex, 16).ok()?);
+			rest = &tail[2..];
+		} else {
+			bytes.push(b);
+			rest = tail;
+		}
+	}
+	String::from_utf8(bytes).ok()
+}
+
+/// Splits an HTTP/1.1 response, returning its body if the status is successful.
+/// JSON-RPC errors come with a successful status, so they're left to the caller.
+fn parse_response(res: &[u8]) -> Result<&[u8], ClientError> {
+	let header_end = res
+		.windows(4)
+		.position(|w| w == b"\r\n\r\n")
+		.ok_or_else(|| ClientError::InvalidResponse("Truncated response headers".to_string()))?;
+	let head = String::from_utf8_lossy(&res[..header_end]);
+	let status = head
+		.split("\r\n")
+		.next()
+		.and_then(|line| line.split_whitespace().nth(1))
+		.and_then(|code| code.parse::<u16>().ok())
+		.ok_or_else(|| ClientError::InvalidResponse("Invalid response status line".to_string()))?;
+	if !(200..300).contains(&status) {
+		return Err(ClientError::BadStatus(status));
+	}
+	Ok(&res[header_end + 4..])
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{ClientError, SwapClient};
+	use crate::api::{CheckReq, ReceiptReq};
+	use crate::secp::{self, PublicKey, Secp256k1};
+
+	use grin_util::ToHex;
+	use serde_json::json;
+	use std::io::{Read, Write};
+	use std::net::TcpListener;
+	use std::thread;
+
+	/// Answers a single request with 'response', returning the server's url and the request
+	fn fake_server(response: String) -> (String, thread::JoinHandle<String>) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}/v1", listener.local_addr().unwrap());
+		let handle = thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			let mut request = Vec::new();
+			let mut buf = [0u8; 1024];
+			while !String::from_utf8_lossy(&request).ends_with('}') {
+				let n = stream.read(&mut buf).unwrap();
+				request.extend_from_slice(&buf[..n]);
+			}
+			let body = response;
+			stream
+				.write_all(
+					format!(
+						"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+						body.len(),
+						body
+					)
+					.as_bytes(),
+				)
+				.unwrap();
+			String::from_utf8_lossy(&request).to_string()
+		});
+		(url, handle)
+	}
+
+	#[test]
+	fn check_swap() -> Result<(), Box<dyn std::error::Error>> {
+		let (url, handle) = fake_server(
+			json!({"jsonrpc": "2.0", "result": {"status": "Unprocessed"}, "id": 1}).to_string(),
+		);
+		let blind = secp::random_secret();
+		let commit = secp::commit(1234, &blind)?;
+		let server_pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &secp::random_secret())?;
+		let req = CheckReq::sign(&commit, 1234, &blind, &server_pubkey, 1000)?;
+		let check = SwapClient::new(&url)?.check_swap(&req)?;
+		assert_eq!(json!({"status": "Unprocessed"}), check);
+
+		let request = handle.join().unwrap();
+		assert!(request.starts_with("POST /v1 HTTP/1.1\r\n"));
+		assert!(request.contains("\"method\":\"check_swap\""));
+		assert!(request.contains(&commit.to_hex()));
+		Ok(())
+	}
+
+	/// Errors the server answers with keep their code and data
+	#[test]
+	fn rpc_error() -> Result<(), Box<dyn std::error::Error>> {
+		let (url, _handle) = fake_server(
+			json!({
+				"jsonrpc": "2.0",
+				"error": {"code": -32030, "message": "Swap not found", "data": {"name": "swap_not_found"}},
+				"id": 1
+			})
+			.to_string(),
+		);
+		let blind = secp::random_secret();
+		let commit = secp::commit(1234, &blind)?;
+		let server_pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &secp::random_secret())?;
+		let req = ReceiptReq::sign(&commit, 1234, &blind, &server_pubkey, 1000)?;
+		match SwapClient::new(&url)?.get_receipt(&req) {
+			Err(ClientError::RpcError { code, data, .. }) => {
+				assert_eq!(-32030, code);
+				assert_eq!(Some(json!({"name": "swap_not_found"})), data);
+			}
+			res => panic!("Unexpected result {:?}", res),
+		}
+		Ok(())
+	}
+
+	#[test]
+	fn invalid_urls() {
+		assert!(SwapClient::new("https://mix.example.com/v1").is_err());
+		assert!(SwapClient::new("http://:3000/v1").is_err());
+		assert!(SwapClient::new("http://mix.example.com:port/v1").is_err());
+		assert!(SwapClient::new("http://mix.example.com").is_ok());
+		assert!(SwapClient::new("http+unix://%2Frun%2Fmwixnet.sock/v1").is_ok());
+		assert!(SwapClient::new("http+unix:///v1").is_err());
+		assert!(SwapClient::new("http+unix://%2Frun%2/v1").is_err());
+	}
+}
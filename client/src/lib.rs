@@ -0,0 +1,9 @@
+//! Everything a wallet needs to swap coins through mwixnet servers: building onions
+//! ('onion::create_onion'), signing swap and cancel requests ('api'), and calling a server's
+//! public API ('client::SwapClient'). None of the server, its store or its listener is included.
+
+pub mod api;
+pub mod client;
+pub mod onion;
+pub mod secp;
+pub mod types;
@@ -0,0 +1,958 @@
+use crate::secp::{self, Commitment, PublicKey, Secp256k1, SecretKey, SharedSecret};
+use crate::types::Payload;
+
+use crate::onion::OnionError::{InvalidKeyLength, SerializationError};
+use chacha20::cipher::{NewCipher, StreamCipher};
+use chacha20::{ChaCha20, Key, Nonce};
+use grin_core::ser::{self, ProtocolVersion, Readable, Reader, Writeable, Writer};
+use grin_util::{self, ToHex};
+use hmac::digest::InvalidLength;
+use hmac::{Hmac, Mac};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::result::Result;
+use thiserror::Error;
+
+type HmacSha256 = Hmac<Sha256>;
+type RawBytes = Vec<u8>;
+
+/// Most hops a route may have, and so the most encrypted payloads an onion may carry (one per hop).
+/// Part of the protocol: route builders and servers must agree on it, so it's published in 'get_info'.
+pub const MAX_HOPS: usize = 16;
+
+/// Largest an encrypted payload may be, in bytes. A payload with a rangeproof and an owner kernel
+/// is well under 1KB.
+pub const MAX_PAYLOAD_SIZE: usize = 4096;
+
+/// Set in the serialized payload count of onions that carry a view tag, which follows the count.
+/// Servers that don't know about view tags reject such onions as having too many payloads.
+const VIEW_TAG_FLAG: u64 = 1 << 63;
+
+/// Set in the serialized payload count of onions encrypted with a suite other than the original
+/// 'CipherSuite::ChaCha20', whose id follows the count (and the view tag, if there is one).
+const CIPHER_SUITE_FLAG: u64 = 1 << 62;
+
+/// How the layers of an onion are encrypted. Every layer of an onion uses the same suite.
+/// Suites are identified by a byte in the onion, so new ones can be added, and old ones phased out
+/// by servers after a deprecation window, without breaking clients that haven't upgraded yet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CipherSuite {
+	/// ChaCha20 keyed with HMAC-SHA256("MWIXNET", shared secret), with a fixed nonce and one
+	/// keystream across all of a layer's payloads. Onions without a suite id use it.
+	ChaCha20,
+	/// ChaCha20 rekeyed for every payload, with a key derived from the shared secret, the suite's
+	/// label and the payload's position, so no key is used for more than one payload
+	ChaCha20Rekeyed,
+}
+
+impl CipherSuite {
+	/// Every suite this version can read, oldest first
+	pub const ALL: [CipherSuite; 2] = [CipherSuite::ChaCha20, CipherSuite::ChaCha20Rekeyed];
+
+	/// The suite's id in serialized onions
+	pub fn id(&self) -> u8 {
+		match self {
+			CipherSuite::ChaCha20 => 0,
+			CipherSuite::ChaCha20Rekeyed => 1,
+		}
+	}
+
+	pub fn from_id(id: u8) -> Option<CipherSuite> {
+		CipherSuite::ALL.iter().find(|s| s.id() == id).copied()
+	}
+
+	fn layer_cipher(
+		&self,
+		shared_secret: &SharedSecret,
+	) -> Result<Box<dyn LayerCipher>, OnionError> {
+		match self {
+			CipherSuite::ChaCha20 => Ok(Box::new(new_stream_cipher(shared_secret)?)),
+			CipherSuite::ChaCha20Rekeyed => Ok(Box::new(RekeyedChaCha20::new(shared_secret))),
+		}
+	}
+}
+
+impl std::str::FromStr for CipherSuite {
+	type Err = OnionError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"chacha20" => Ok(CipherSuite::ChaCha20),
+			"chacha20_rekeyed" => Ok(CipherSuite::ChaCha20Rekeyed),
+			_ => Err(OnionError::UnknownCipherSuite(s.to_string())),
+		}
+	}
+}
+
+impl Default for CipherSuite {
+	fn default() -> CipherSuite {
+		CipherSuite::ChaCha20
+	}
+}
+
+/// Encrypts and decrypts the payloads of one layer of an onion. Each payload from the layer's
+/// own onwards is passed through in order, both when building and when peeling the layer.
+trait LayerCipher {
+	fn apply_next(&mut self, payload: &mut [u8]) -> Result<(), OnionError>;
+}
+
+impl LayerCipher for ChaCha20 {
+	fn apply_next(&mut self, payload: &mut [u8]) -> Result<(), OnionError> {
+		self.apply_keystream(payload);
+		Ok(())
+	}
+}
+
+/// 'CipherSuite::ChaCha20Rekeyed'
+struct RekeyedChaCha20 {
+	secret: [u8; 32],
+	position: u32,
+}
+
+impl RekeyedChaCha20 {
+	fn new(shared_secret: &SharedSecret) -> RekeyedChaCha20 {
+		let mut secret = [0u8; 32];
+		secret.copy_from_slice(&shared_secret[0..32]);
+		RekeyedChaCha20 {
+			secret,
+			position: 0,
+		}
+	}
+}
+
+impl LayerCipher for RekeyedChaCha20 {
+	fn apply_next(&mut self, payload: &mut [u8]) -> Result<(), OnionError> {
+		let mut key_hmac = HmacSha256::new_from_slice(b"MWIXNET_CHACHA20_REKEYED")?;
+		key_hmac.update(&self.secret);
+		key_hmac.update(&self.position.to_be_bytes());
+		let key = key_hmac.finalize().into_bytes();
+		self.position += 1;
+
+		// Every key encrypts a single payload, so a fixed nonce is safe
+		let mut cipher = ChaCha20::new(Key::from_slice(&key[0..32]), Nonce::from_slice(&[0u8; 12]));
+		cipher.apply_keystream(payload);
+		Ok(())
+	}
+}
+
+/// A data packet with layers of encryption
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Onion {
+	/// The onion originator's portion of the shared secret
+	pub ephemeral_pubkey: PublicKey,
+	/// The pedersen commitment before adjusting the excess and subtracting the fee
+	pub commit: Commitment,
+	/// The encrypted payloads which represent the layers of the onion
+	pub enc_payloads: Vec<RawBytes>,
+	/// Short tag derived from the secret shared with the intended recipient, which lets a server
+	/// rule out onions that aren't meant for it without decrypting them
+	pub view_tag: Option<u8>,
+	/// How the layers are encrypted
+	pub cipher_suite: CipherSuite,
+}
+
+impl Onion {
+	pub fn serialize(&self) -> Result<Vec<u8>, ser::Error> {
+		let mut vec = vec![];
+		ser::serialize_default(&mut vec, &self)?;
+		Ok(vec)
+	}
+
+	/// Whether the onion could be meant for the holder of 'secret_key': true unless its view tag
+	/// says otherwise. Costs a single ECDH, rather than decrypting every payload.
+	pub fn matches_view_tag(&self, secret_key: &SecretKey) -> bool {
+		let view_tag = match self.view_tag {
+			Some(view_tag) => view_tag,
+			None => return true,
+		};
+		let shared_secret =
+			SharedSecret::new(&Secp256k1::new(), &self.ephemeral_pubkey, &secret_key);
+		calc_view_tag(&shared_secret)
+			.map(|t| t == view_tag)
+			.unwrap_or(false)
+	}
+
+	/// Peel a single layer off of the Onion, returning the peeled Onion and decrypted Payload
+	pub fn peel_layer(&self, secret_key: &SecretKey) -> Result<(Payload, Onion), OnionError> {
+		// Fully peeled onions deserialize fine, but have nothing left for this server
+		if self.enc_payloads.is_empty() {
+			return Err(OnionError::NoPayloads);
+		}
+		let secp = Secp256k1::new();
+
+		let shared_secret = SharedSecret::new(&secp, &self.ephemeral_pubkey, &secret_key);
+		if let Some(view_tag) = self.view_tag {
+			if view_tag != calc_view_tag(&shared_secret)? {
+				return Err(OnionError::ViewTagMismatch);
+			}
+		}
+		let mut cipher = self.cipher_suite.layer_cipher(&shared_secret)?;
+
+		let mut decrypted_bytes = self.enc_payloads[0].clone();
+		cipher.apply_next(&mut decrypted_bytes)?;
+		let decrypted_payload = Payload::deserialize(&decrypted_bytes)
+			.map_err(|e| OnionError::DeserializationError(e))?;
+
+		let enc_payloads: Vec<RawBytes> = self
+			.enc_payloads
+			.iter()
+			.enumerate()
+			.filter(|&(i, _)| i != 0)
+			.map(|(_, enc_payload)| {
+				let mut p = enc_payload.clone();
+				cipher.apply_next(&mut p)?;
+				Ok(p)
+			})
+			.collect::<Result<Vec<RawBytes>, OnionError>>()?;
+
+		let blinding_factor = calc_blinding_factor(&shared_secret, &self.ephemeral_pubkey)?;
+
+		let mut ephemeral_pubkey = self.ephemeral_pubkey.clone();
+		ephemeral_pubkey
+			.mul_assign(&secp, &blinding_factor)
+			.map_err(|e| OnionError::CalcPubKeyError(e))?;
+
+		let mut commitment = self.commit.clone();
+		commitment = secp::add_excess(&commitment, &decrypted_payload.excess)
+			.map_err(|e| OnionError::CalcCommitError(e))?;
+		commitment = secp::sub_value(&commitment, decrypted_payload.fee.into())
+			.map_err(|e| OnionError::CalcCommitError(e))?;
+		if let Some(owner_kernel) = &decrypted_payload.owner_kernel {
+			commitment = secp::add_commits(&commitment, &owner_kernel.excess)
+				.map_err(|e| OnionError::CalcCommitError(e))?;
+		}
+		// Change is split off the output. Its blinding factor is part of the owner kernel's excess.
+		if let Some(change) = &decrypted_payload.fee_change {
+			let change_blind = secp::sub_value(&change.output.commitment(), change.value)
+				.map_err(|e| OnionError::CalcCommitError(e))?;
+			commitment = secp::sub_commits(&commitment, &change_blind)
+				.map_err(|e| OnionError::CalcCommitError(e))?;
+		}
+
+		let peeled_onion = Onion {
+			ephemeral_pubkey,
+			commit: commitment.clone(),
+			enc_payloads,
+			view_tag: decrypted_payload.next_view_tag,
+			cipher_suite: self.cipher_suite,
+		};
+		Ok((decrypted_payload, peeled_onion))
+	}
+}
+
+fn calc_blinding_factor(
+	shared_secret: &SharedSecret,
+	ephemeral_pubkey: &PublicKey,
+) -> Result<SecretKey, OnionError> {
+	let serialized_pubkey = ser::ser_vec(&ephemeral_pubkey, ProtocolVersion::local())?;
+
+	let mut hasher = Sha256::default();
+	hasher.update(&serialized_pubkey);
+	hasher.update(&shared_secret[0..32]);
+
+	let secp = Secp256k1::new();
+	let blind = SecretKey::from_slice(&secp, &hasher.finalize())
+		.map_err(|e| OnionError::CalcBlindError(e))?;
+	Ok(blind)
+}
+
+fn calc_view_tag(shared_secret: &SharedSecret) -> Result<u8, OnionError> {
+	let mut tag_hmac = HmacSha256::new_from_slice(b"MWIXNET_VIEW_TAG")?;
+	tag_hmac.update(&shared_secret[0..32]);
+	Ok(tag_hmac.finalize().into_bytes()[0])
+}
+
+fn new_stream_cipher(shared_secret: &SharedSecret) -> Result<ChaCha20, OnionError> {
+	let mut mu_hmac = HmacSha256::new_from_slice(b"MWIXNET")?;
+	mu_hmac.update(&shared_secret[0..32]);
+	let mukey = mu_hmac.finalize().into_bytes();
+
+	let key = Key::from_slice(&mukey[0..32]);
+	let nonce = Nonce::from_slice(b"NONCE1234567");
+
+	Ok(ChaCha20::new(&key, &nonce))
+}
+
+impl Writeable for Onion {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.ephemeral_pubkey.write(writer)?;
+		writer.write_fixed_bytes(&self.commit)?;
+		let mut len = self.enc_payloads.len() as u64;
+		if self.view_tag.is_some() {
+			len |= VIEW_TAG_FLAG;
+		}
+		if self.cipher_suite != CipherSuite::ChaCha20 {
+			len |= CIPHER_SUITE_FLAG;
+		}
+		writer.write_u64(len)?;
+		if let Some(view_tag) = self.view_tag {
+			writer.write_u8(view_tag)?;
+		}
+		if self.cipher_suite != CipherSuite::ChaCha20 {
+			writer.write_u8(self.cipher_suite.id())?;
+		}
+		for p in &self.enc_payloads {
+			writer.write_u64(p.len() as u64)?;
+			p.write(writer)?;
+		}
+		Ok(())
+	}
+}
+
+impl Readable for Onion {
+	fn read<R: Reader>(reader: &mut R) -> Result<Onion, ser::Error> {
+		let ephemeral_pubkey = PublicKey::read(reader)?;
+		let commit = Commitment::read(reader)?;
+		// Lengths are checked before anything is allocated for them
+		let mut enc_payloads: Vec<RawBytes> = Vec::new();
+		let mut len = reader.read_u64()?;
+		let view_tag = if len & VIEW_TAG_FLAG != 0 {
+			len &= !VIEW_TAG_FLAG;
+			Some(reader.read_u8()?)
+		} else {
+			None
+		};
+		let cipher_suite = if len & CIPHER_SUITE_FLAG != 0 {
+			len &= !CIPHER_SUITE_FLAG;
+			CipherSuite::from_id(reader.read_u8()?).ok_or(ser::Error::CorruptedData)?
+		} else {
+			CipherSuite::ChaCha20
+		};
+		if len > MAX_HOPS as u64 {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		for _ in 0..len {
+			let size = reader.read_u64()?;
+			if size > MAX_PAYLOAD_SIZE as u64 {
+				return Err(ser::Error::TooLargeReadErr);
+			}
+			let bytes = reader.read_fixed_bytes(size as usize)?;
+			enc_payloads.push(bytes);
+		}
+		Ok(Onion {
+			ephemeral_pubkey,
+			commit,
+			enc_payloads,
+			view_tag,
+			cipher_suite,
+		})
+	}
+}
+
+impl serde::ser::Serialize for Onion {
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: serde::ser::Serializer,
+	{
+		let num_fields = 3
+			+ self.view_tag.is_some() as usize
+			+ (self.cipher_suite != CipherSuite::ChaCha20) as usize;
+		let mut state = serializer.serialize_struct("Onion", num_fields)?;
+
+		let secp = Secp256k1::new();
+		state.serialize_field(
+			"pubkey",
+			&self.ephemeral_pubkey.serialize_vec(&secp, true).to_hex(),
+		)?;
+		state.serialize_field("commit", &self.commit.to_hex())?;
+
+		let hex_payloads: Vec<String> = self.enc_payloads.iter().map(|v| v.to_hex()).collect();
+		state.serialize_field("data", &hex_payloads)?;
+		if let Some(view_tag) = self.view_tag {
+			state.serialize_field("view_tag", &view_tag)?;
+		}
+		if self.cipher_suite != CipherSuite::ChaCha20 {
+			state.serialize_field("cipher_suite", &self.cipher_suite)?;
+		}
+		state.end()
+	}
+}
+
+impl<'de> serde::de::Deserialize<'de> for Onion {
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+	where
+		D: serde::de::Deserializer<'de>,
+	{
+		#[derive(Deserialize)]
+		#[serde(field_identifier, rename_all = "snake_case")]
+		enum Field {
+			Pubkey,
+			Commit,
+			Data,
+			ViewTag,
+			CipherSuite,
+		}
+
+		struct OnionVisitor;
+
+		impl<'de> serde::de::Visitor<'de> for OnionVisitor {
+			type Value = Onion;
+
+			fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+				formatter.write_str("an Onion")
+			}
+
+			fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+			where
+				A: serde::de::MapAccess<'de>,
+			{
+				let mut pubkey = None;
+				let mut commit = None;
+				let mut data = None;
+				let mut view_tag = None;
+				let mut cipher_suite = None;
+
+				while let Some(key) = map.next_key()? {
+					match key {
+						Field::Pubkey if pubkey.is_some() => {
+							return Err(serde::de::Error::duplicate_field("pubkey"));
+						}
+						Field::Commit if commit.is_some() => {
+							return Err(serde::de::Error::duplicate_field("commit"));
+						}
+						Field::Data if data.is_some() => {
+							return Err(serde::de::Error::duplicate_field("data"));
+						}
+						Field::ViewTag if view_tag.is_some() => {
+							return Err(serde::de::Error::duplicate_field("view_tag"));
+						}
+						Field::CipherSuite if cipher_suite.is_some() => {
+							return Err(serde::de::Error::duplicate_field("cipher_suite"));
+						}
+						Field::ViewTag => {
+							view_tag = Some(map.next_value()?);
+						}
+						Field::CipherSuite => {
+							cipher_suite = Some(map.next_value()?);
+						}
+						Field::Pubkey => {
+							let val: String = map.next_value()?;
+							let vec =
+								grin_util::from_hex(&val).map_err(serde::de::Error::custom)?;
+							let secp = Secp256k1::new();
+							pubkey = Some(
+								PublicKey::from_slice(&secp, &vec[..])
+									.map_err(serde::de::Error::custom)?,
+							);
+						}
+						Field::Commit => {
+							let val: String = map.next_value()?;
+							let vec =
+								grin_util::from_hex(&val).map_err(serde::de::Error::custom)?;
+							commit = Some(Commitment::from_vec(vec));
+						}
+						Field::Data => {
+							let val: Vec<String> = map.next_value()?;
+							if val.len() > MAX_HOPS {
+								return Err(serde::de::Error::custom(format!(
+									"onion has {} payloads, more than the maximum of {}",
+									val.len(),
+									MAX_HOPS
+								)));
+							}
+							let mut vec: Vec<Vec<u8>> = Vec::new();
+							for hex in val {
+								if hex.len() > MAX_PAYLOAD_SIZE * 2 {
+									return Err(serde::de::Error::custom(format!(
+										"onion payload is {} bytes, more than the maximum of {}",
+										hex.len() / 2,
+										MAX_PAYLOAD_SIZE
+									)));
+								}
+								vec.push(
+									grin_util::from_hex(&hex).map_err(serde::de::Error::custom)?,
+								);
+							}
+							data = Some(vec);
+						}
+					}
+				}
+
+				Ok(Onion {
+					ephemeral_pubkey: pubkey
+						.ok_or_else(|| serde::de::Error::missing_field("pubkey"))?,
+					commit: commit.ok_or_else(|| serde::de::Error::missing_field("commit"))?,
+					enc_payloads: data.ok_or_else(|| serde::de::Error::missing_field("data"))?,
+					view_tag,
+					cipher_suite: cipher_suite.unwrap_or_default(),
+				})
+			}
+		}
+
+		const FIELDS: &[&str] = &["pubkey", "commit", "data", "view_tag", "cipher_suite"];
+		deserializer.deserialize_struct("Onion", &FIELDS, OnionVisitor)
+	}
+}
+
+/// A server in the onion's route, and the payload to encrypt for it
+#[derive(Clone)]
+pub struct Hop {
+	pub pubkey: PublicKey,
+	pub payload: Payload,
+}
+
+/// Create an Onion for the Commitment, encrypting the payload for each hop
+pub fn create_onion(commitment: &Commitment, hops: &Vec<Hop>) -> Result<Onion, OnionError> {
+	build_onion(commitment, hops, false, CipherSuite::ChaCha20)
+}
+
+/// Create an Onion for the Commitment like 'create_onion', but with a view tag for each hop,
+/// so servers can cheaply reject onions that weren't meant for them.
+/// Only servers that support view tags can read the onion.
+pub fn create_tagged_onion(commitment: &Commitment, hops: &Vec<Hop>) -> Result<Onion, OnionError> {
+	build_onion(commitment, hops, true, CipherSuite::ChaCha20)
+}
+
+/// Create an Onion for the Commitment, with view tags if 'view_tags' is set, and its layers
+/// encrypted with 'cipher_suite'. Every server in the route must accept the suite.
+pub fn build_onion(
+	commitment: &Commitment,
+	hops: &Vec<Hop>,
+	view_tags: bool,
+	cipher_suite: CipherSuite,
+) -> Result<Onion, OnionError> {
+	let secp = Secp256k1::new();
+	let session_key = secp::random_secret();
+	let mut ephemeral_key = session_key.clone();
+
+	let mut shared_secrets: Vec<SharedSecret> = Vec::new();
+	let mut enc_payloads: Vec<RawBytes> = Vec::new();
+	for hop in hops {
+		let shared_secret = SharedSecret::new(&secp, &hop.pubkey, &ephemeral_key);
+
+		let ephemeral_pubkey = PublicKey::from_secret_key(&secp, &ephemeral_key)
+			.map_err(|e| OnionError::CalcPubKeyError(e))?;
+		let blinding_factor = calc_blinding_factor(&shared_secret, &ephemeral_pubkey)?;
+
+		shared_secrets.push(shared_secret);
+		ephemeral_key
+			.mul_assign(&secp, &blinding_factor)
+			.map_err(|e| OnionError::CalcPubKeyError(e))?;
+	}
+
+	// Each hop's payload carries the view tag of the onion it passes on to the next hop
+	let tags = match view_tags {
+		true => shared_secrets
+			.iter()
+			.map(|s| calc_view_tag(s).map(Some))
+			.collect::<Result<Vec<Option<u8>>, OnionError>>()?,
+		false => vec![None; hops.len()],
+	};
+	for (i, hop) in hops.iter().enumerate() {
+		let mut payload = hop.payload.clone();
+		payload.next_view_tag = tags.get(i + 1).copied().flatten();
+		enc_payloads.push(payload.serialize()?);
+	}
+
+	for i in (0..shared_secrets.len()).rev() {
+		let mut cipher = cipher_suite.layer_cipher(&shared_secrets[i])?;
+		for j in i..shared_secrets.len() {
+			cipher.apply_next(&mut enc_payloads[j])?;
+		}
+	}
+
+	let onion = Onion {
+		ephemeral_pubkey: PublicKey::from_secret_key(&secp, &session_key)
+			.map_err(|e| OnionError::CalcPubKeyError(e))?,
+		commit: commitment.clone(),
+		enc_payloads,
+		view_tag: tags.first().copied().flatten(),
+		cipher_suite,
+	};
+	Ok(onion)
+}
+
+/// Error types for creating and peeling Onions
+#[derive(Clone, Error, Debug, PartialEq)]
+pub enum OnionError {
+	#[error("Invalid key length for MAC initialization")]
+	InvalidKeyLength,
+	#[error("Serialization error occurred: {0:?}")]
+	SerializationError(ser::Error),
+	#[error("Deserialization error occurred: {0:?}")]
+	DeserializationError(ser::Error),
+	#[error("Error calculating blinding factor: {0:?}")]
+	CalcBlindError(secp256k1zkp::Error),
+	#[error("Error calculating ephemeral pubkey: {0:?}")]
+	CalcPubKeyError(secp256k1zkp::Error),
+	#[error("Error calculating commitment: {0:?}")]
+	CalcCommitError(secp256k1zkp::Error),
+	#[error("View tag doesn't match, so the onion isn't meant for this server")]
+	ViewTagMismatch,
+	#[error("Unknown cipher suite {0}")]
+	UnknownCipherSuite(String),
+	#[error("Onion has no payloads left to peel")]
+	NoPayloads,
+}
+
+impl From<InvalidLength> for OnionError {
+	fn from(_err: InvalidLength) -> OnionError {
+		InvalidKeyLength
+	}
+}
+
+impl From<ser::Error> for OnionError {
+	fn from(err: ser::Error) -> OnionError {
+		SerializationError(err)
+	}
+}
+
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util {
+	pub use super::{create_onion, Hop};
+	use super::{Onion, OnionError};
+	use crate::secp::test_util::{rand_commit, rand_proof, rand_pubkey};
+	use crate::secp::{self, PublicKey, Secp256k1, SecretKey, SharedSecret};
+	use crate::types::Payload;
+
+	use grin_core::core::FeeFields;
+	use rand::RngCore;
+
+	pub fn rand_onion() -> Onion {
+		let commit = rand_commit();
+		let mut hops = Vec::new();
+		let k = (rand::thread_rng().next_u64() % 5) + 1;
+		for i in 0..k {
+			let hop = Hop {
+				pubkey: rand_pubkey(),
+				payload: Payload {
+					excess: secp::random_secret(),
+					fee: FeeFields::from(rand::thread_rng().next_u32()),
+					rangeproof: if i == (k - 1) {
+						Some(rand_proof())
+					} else {
+						None
+					},
+					owner_kernel: None,
+					min_value_proof: None,
+					next_view_tag: None,
+					fee_change: None,
+				},
+			};
+			hops.push(hop);
+		}
+
+		create_onion(&commit, &hops).unwrap()
+	}
+
+	/// Calculates the expected next ephemeral pubkey after peeling a layer off of the Onion.
+	pub fn next_ephemeral_pubkey(
+		onion: &Onion,
+		server_key: &SecretKey,
+	) -> Result<PublicKey, OnionError> {
+		let secp = Secp256k1::new();
+		let mut ephemeral_pubkey = onion.ephemeral_pubkey.clone();
+		let shared_secret = SharedSecret::new(&secp, &ephemeral_pubkey, &server_key);
+		let blinding_factor = super::calc_blinding_factor(&shared_secret, &ephemeral_pubkey)?;
+		ephemeral_pubkey
+			.mul_assign(&secp, &blinding_factor)
+			.map_err(|e| OnionError::CalcPubKeyError(e))?;
+		Ok(ephemeral_pubkey)
+	}
+}
+
+#[cfg(test)]
+pub mod tests {
+	use super::test_util::{self, Hop};
+	use super::{
+		build_onion, create_tagged_onion, CipherSuite, Onion, OnionError, MAX_HOPS,
+		MAX_PAYLOAD_SIZE,
+	};
+	use crate::secp;
+	use crate::types::Payload;
+
+	use grin_core::core::FeeFields;
+	use grin_core::ser::{self, ProtocolVersion};
+
+	/// Test end-to-end Onion creation and unwrapping logic.
+	#[test]
+	fn onion() {
+		let total_fee: u64 = 10;
+		let fee_per_hop: u64 = 2;
+		let in_value: u64 = 1000;
+		let out_value: u64 = in_value - total_fee;
+		let blind = secp::random_secret();
+		let commitment = secp::commit(in_value, &blind).unwrap();
+
+		let mut hops: Vec<Hop> = Vec::new();
+		let mut keys: Vec<secp::SecretKey> = Vec::new();
+		let mut final_commit = secp::commit(out_value, &blind).unwrap();
+		let mut final_blind = blind.clone();
+		for i in 0..5 {
+			keys.push(secp::random_secret());
+
+			let excess = secp::random_secret();
+
+			let secp = secp256k1zkp::Secp256k1::with_caps(secp256k1zkp::ContextFlag::Commit);
+			final_blind.add_assign(&secp, &excess).unwrap();
+			final_commit = secp::add_excess(&final_commit, &excess).unwrap();
+			let proof = if i == 4 {
+				let n1 = secp::random_secret();
+				let rp = secp.bullet_proof(
+					out_value,
+					final_blind.clone(),
+					n1.clone(),
+					n1.clone(),
+					None,
+					None,
+				);
+				assert!(secp.verify_bullet_proof(final_commit, rp, None).is_ok());
+				Some(rp)
+			} else {
+				None
+			};
+
+			hops.push(Hop {
+				pubkey: secp::PublicKey::from_secret_key(&secp, &keys[i]).unwrap(),
+				payload: Payload {
+					excess,
+					fee: FeeFields::from(fee_per_hop as u32),
+					rangeproof: proof,
+					owner_kernel: None,
+					min_value_proof: None,
+					next_view_tag: None,
+					fee_change: None,
+				},
+			});
+		}
+
+		let mut onion_packet = test_util::create_onion(&commitment, &hops).unwrap();
+
+		let mut payload = Payload {
+			excess: secp::random_secret(),
+			fee: FeeFields::from(fee_per_hop as u32),
+			rangeproof: None,
+			owner_kernel: None,
+			min_value_proof: None,
+			next_view_tag: None,
+			fee_change: None,
+		};
+		for i in 0..5 {
+			let peeled = onion_packet.peel_layer(&keys[i]).unwrap();
+			payload = peeled.0;
+			onion_packet = peeled.1;
+		}
+
+		assert!(payload.rangeproof.is_some());
+		assert_eq!(
+			payload.rangeproof.unwrap(),
+			hops[4].payload.rangeproof.unwrap()
+		);
+		assert_eq!(secp::commit(out_value, &final_blind).unwrap(), final_commit);
+		assert_eq!(payload.fee, FeeFields::from(fee_per_hop as u32));
+	}
+
+	/// Each layer of a tagged onion carries the tag of its recipient, which survives both
+	/// serializations, and a wrong tag is rejected before the layer is decrypted
+	#[test]
+	fn onion_view_tags() -> Result<(), Box<dyn std::error::Error>> {
+		let keys: Vec<secp::SecretKey> = (0..3).map(|_| secp::random_secret()).collect();
+		let secp = secp256k1zkp::Secp256k1::new();
+		let hops: Vec<Hop> = keys
+			.iter()
+			.map(|key| Hop {
+				pubkey: secp::PublicKey::from_secret_key(&secp, key).unwrap(),
+				payload: Payload {
+					excess: secp::random_secret(),
+					fee: FeeFields::from(2u32),
+					rangeproof: None,
+					owner_kernel: None,
+					min_value_proof: None,
+					next_view_tag: None,
+					fee_change: None,
+				},
+			})
+			.collect();
+		let commitment = secp::commit(1000, &secp::random_secret())?;
+		assert_eq!(None, test_util::create_onion(&commitment, &hops)?.view_tag);
+
+		let mut onion = create_tagged_onion(&commitment, &hops)?;
+		for (i, key) in keys.iter().enumerate() {
+			assert!(onion.view_tag.is_some());
+			assert!(onion.matches_view_tag(key));
+
+			let bytes = ser::ser_vec(&onion, ProtocolVersion::local())?;
+			assert_eq!(onion, ser::deserialize_default::<Onion>(&mut &bytes[..])?);
+			assert_eq!(
+				onion,
+				serde_json::from_value::<Onion>(serde_json::to_value(&onion)?)?
+			);
+
+			let wrong_tag = Onion {
+				view_tag: onion.view_tag.map(|t| t.wrapping_add(1)),
+				..onion.clone()
+			};
+			assert!(!wrong_tag.matches_view_tag(key));
+			assert_eq!(
+				Err(OnionError::ViewTagMismatch),
+				wrong_tag.peel_layer(key).map(|_| ())
+			);
+
+			let (payload, peeled) = onion.peel_layer(key)?;
+			assert_eq!(payload.next_view_tag, peeled.view_tag);
+			assert_eq!(i == keys.len() - 1, peeled.view_tag.is_none());
+			onion = peeled;
+		}
+		Ok(())
+	}
+
+	/// Onions can be built and peeled with every suite, which survives both serializations, and
+	/// a layer can't be decrypted with a different suite than it was encrypted with
+	#[test]
+	fn onion_cipher_suites() -> Result<(), Box<dyn std::error::Error>> {
+		let keys: Vec<secp::SecretKey> = (0..3).map(|_| secp::random_secret()).collect();
+		let secp = secp256k1zkp::Secp256k1::new();
+		let hops: Vec<Hop> = keys
+			.iter()
+			.map(|key| Hop {
+				pubkey: secp::PublicKey::from_secret_key(&secp, key).unwrap(),
+				payload: Payload {
+					excess: secp::random_secret(),
+					fee: FeeFields::from(2u32),
+					rangeproof: None,
+					owner_kernel: None,
+					min_value_proof: None,
+					next_view_tag: None,
+					fee_change: None,
+				},
+			})
+			.collect();
+		let commitment = secp::commit(1000, &secp::random_secret())?;
+
+		for suite in CipherSuite::ALL {
+			assert_eq!(Some(suite), CipherSuite::from_id(suite.id()));
+			let mut onion = build_onion(&commitment, &hops, true, suite)?;
+			for (i, key) in keys.iter().enumerate() {
+				assert_eq!(suite, onion.cipher_suite);
+				let bytes = ser::ser_vec(&onion, ProtocolVersion::local())?;
+				assert_eq!(onion, ser::deserialize_default::<Onion>(&mut &bytes[..])?);
+				assert_eq!(
+					onion,
+					serde_json::from_value::<Onion>(serde_json::to_value(&onion)?)?
+				);
+
+				let (payload, peeled) = onion.peel_layer(key)?;
+				assert_eq!(hops[i].payload.excess, payload.excess);
+				onion = peeled;
+			}
+		}
+
+		// Onions in the original suite serialize exactly as they did before suites existed
+		let onion = build_onion(&commitment, &hops, false, CipherSuite::ChaCha20)?;
+		assert!(serde_json::to_value(&onion)?.get("cipher_suite").is_none());
+		let bytes = ser::ser_vec(&onion, ProtocolVersion::local())?;
+		assert_eq!(3u64.to_be_bytes(), bytes[66..74]);
+
+		let rekeyed = build_onion(&commitment, &hops, false, CipherSuite::ChaCha20Rekeyed)?;
+		let misread = Onion {
+			cipher_suite: CipherSuite::ChaCha20,
+			..rekeyed.clone()
+		};
+		assert!(misread.peel_layer(&keys[0]).is_err());
+
+		// Suites this version doesn't know can't be read
+		let mut bytes = ser::ser_vec(&rekeyed, ProtocolVersion::local())?;
+		bytes[74] = 0xff;
+		assert!(ser::deserialize_default::<Onion>(&mut &bytes[..]).is_err());
+		Ok(())
+	}
+
+	/// Onions with too many or too large payloads are rejected by both deserializers
+	/// before the payloads are allocated.
+	#[test]
+	fn onion_size_limits() {
+		let onion = test_util::rand_onion();
+		let bytes = ser::ser_vec(&onion, ProtocolVersion::local()).unwrap();
+		let read: Onion = ser::deserialize_default(&mut &bytes[..]).unwrap();
+		assert_eq!(onion, read);
+
+		let too_many = Onion {
+			enc_payloads: vec![vec![0u8; 32]; MAX_HOPS + 1],
+			..onion.clone()
+		};
+		let bytes = ser::ser_vec(&too_many, ProtocolVersion::local()).unwrap();
+		assert!(matches!(
+			ser::deserialize_default::<Onion>(&mut &bytes[..]),
+			Err(ser::Error::TooLargeReadErr)
+		));
+		let err =
+			serde_json::from_value::<Onion>(serde_json::to_value(&too_many).unwrap()).unwrap_err();
+		assert!(err.to_string().contains("payloads"));
+
+		let too_large = Onion {
+			enc_payloads: vec![vec![0u8; MAX_PAYLOAD_SIZE + 1]],
+			..onion.clone()
+		};
+		let bytes = ser::ser_vec(&too_large, ProtocolVersion::local()).unwrap();
+		assert!(matches!(
+			ser::deserialize_default::<Onion>(&mut &bytes[..]),
+			Err(ser::Error::TooLargeReadErr)
+		));
+		assert!(
+			serde_json::from_value::<Onion>(serde_json::to_value(&too_large).unwrap()).is_err()
+		);
+
+		// A length prefix claiming an enormous payload fails without trying to allocate it
+		let mut bytes = ser::ser_vec(&onion, ProtocolVersion::local()).unwrap();
+		let len_pos = 33 + 33;
+		bytes.truncate(len_pos);
+		bytes.extend_from_slice(&1u64.to_be_bytes());
+		bytes.extend_from_slice(&u64::MAX.to_be_bytes());
+		assert!(ser::deserialize_default::<Onion>(&mut &bytes[..]).is_err());
+
+		// Missing fields are an error rather than a panic
+		let mut json = serde_json::to_value(&onion).unwrap();
+		json.as_object_mut().unwrap().remove("commit");
+		assert!(serde_json::from_value::<Onion>(json).is_err());
+	}
+
+	/// Hostile inputs are rejected with an error rather than a panic or an oversized allocation
+	#[test]
+	fn hostile_inputs() {
+		let server_key = secp::random_secret();
+		let onion = Onion {
+			enc_payloads: vec![],
+			..test_util::rand_onion()
+		};
+		assert_eq!(
+			Err(OnionError::NoPayloads),
+			onion.peel_layer(&server_key).map(|_| ())
+		);
+
+		let payload = Payload {
+			excess: secp::random_secret(),
+			fee: FeeFields::from(1),
+			rangeproof: None,
+			owner_kernel: None,
+			min_value_proof: None,
+			next_view_tag: None,
+			fee_change: None,
+		};
+		let bytes = payload.serialize().unwrap();
+		assert!(Payload::deserialize(&bytes).is_ok());
+
+		// Trailing bytes, and optional fields flagged with anything but 0 or 1
+		let mut trailing = bytes.clone();
+		trailing.push(0);
+		assert!(matches!(
+			Payload::deserialize(&trailing),
+			Err(ser::Error::CorruptedData)
+		));
+		let mut bad_flag = bytes.clone();
+		let flag_pos = 1 + 32 + 8;
+		bad_flag[flag_pos] = 2;
+		assert!(matches!(
+			Payload::deserialize(&bad_flag),
+			Err(ser::Error::CorruptedData)
+		));
+
+		let oversized = vec![0u8; MAX_PAYLOAD_SIZE + 1];
+		assert!(matches!(
+			Payload::deserialize(&oversized),
+			Err(ser::Error::TooLargeReadErr)
+		));
+	}
+}
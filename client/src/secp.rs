@@ -0,0 +1,653 @@
+pub use secp256k1zkp::aggsig;
+pub use secp256k1zkp::constants::{
+	AGG_SIGNATURE_SIZE, COMPRESSED_PUBLIC_KEY_SIZE, MAX_PROOF_SIZE, PEDERSEN_COMMITMENT_SIZE,
+	SECRET_KEY_SIZE,
+};
+pub use secp256k1zkp::ecdh::SharedSecret;
+pub use secp256k1zkp::key::{PublicKey, SecretKey, ZERO_KEY};
+pub use secp256k1zkp::pedersen::{Commitment, RangeProof};
+pub use secp256k1zkp::{ContextFlag, Message, Secp256k1, Signature};
+
+use blake2::blake2b::Blake2b;
+use byteorder::{BigEndian, ByteOrder};
+use grin_core::ser::{self, Readable, Reader, Writeable, Writer};
+use secp256k1zkp::rand::thread_rng;
+use thiserror::Error;
+
+/// A generalized Schnorr signature with a pedersen commitment value & blinding factors as the keys
+#[derive(Clone)]
+pub struct ComSignature {
+	pub_nonce: Commitment,
+	s: SecretKey,
+	t: SecretKey,
+}
+
+/// Binds a commitment signature to the server it's intended for and the height it expires at,
+/// so a captured signature can't be replayed to another server, or after it expires.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SigDomain {
+	pub server_pubkey: PublicKey,
+	pub expiry_height: u64,
+}
+
+impl SigDomain {
+	pub fn new(server_pubkey: &PublicKey, expiry_height: u64) -> SigDomain {
+		SigDomain {
+			server_pubkey: server_pubkey.clone(),
+			expiry_height,
+		}
+	}
+}
+
+/// Error types for Commitment Signatures
+#[derive(Error, Debug)]
+pub enum ComSigError {
+	#[error("Commitment signature is invalid")]
+	InvalidSig,
+	#[error("No partial signatures to combine")]
+	NoPartialSigs,
+	#[error("Public nonce {0} doesn't match the commitment its party shared")]
+	NonceMismatch(usize),
+	#[error("Secp256k1zkp error: {0:?}")]
+	Secp256k1zkp(secp256k1zkp::Error),
+}
+
+impl From<secp256k1zkp::Error> for ComSigError {
+	fn from(err: secp256k1zkp::Error) -> ComSigError {
+		ComSigError::Secp256k1zkp(err)
+	}
+}
+
+impl ComSignature {
+	pub fn new(pub_nonce: &Commitment, s: &SecretKey, t: &SecretKey) -> ComSignature {
+		ComSignature {
+			pub_nonce: pub_nonce.to_owned(),
+			s: s.to_owned(),
+			t: t.to_owned(),
+		}
+	}
+
+	pub fn sign(
+		amount: u64,
+		blind: &SecretKey,
+		domain: &SigDomain,
+		msg: &Vec<u8>,
+	) -> Result<ComSignature, ComSigError> {
+		let secp = Secp256k1::with_caps(ContextFlag::Commit);
+
+		let mut amt_bytes = [0; 32];
+		BigEndian::write_u64(&mut amt_bytes[24..32], amount);
+		let k_amt = SecretKey::from_slice(&secp, &amt_bytes)?;
+
+		let k_1 = SecretKey::new(&secp, &mut thread_rng());
+		let k_2 = SecretKey::new(&secp, &mut thread_rng());
+
+		let commitment = secp.commit(amount, blind.clone())?;
+		let nonce_commitment = secp.commit_blind(k_1.clone(), k_2.clone())?;
+
+		let e = ComSignature::calc_challenge(&secp, &commitment, &nonce_commitment, &domain, &msg)?;
+
+		// s = k_1 + (e * amount)
+		let mut s = k_amt.clone();
+		s.mul_assign(&secp, &e)?;
+		s.add_assign(&secp, &k_1)?;
+
+		// t = k_2 + (e * blind)
+		let mut t = blind.clone();
+		t.mul_assign(&secp, &e)?;
+		t.add_assign(&secp, &k_2)?;
+
+		Ok(ComSignature::new(&nonce_commitment, &s, &t))
+	}
+
+	/// Adds up the public nonces of every party signing for a commitment whose blinding factor
+	/// (and optionally, amount) is split between them, after checking each against the
+	/// commitment its party shared first.
+	///
+	/// Signing takes three rounds. First, each party shares 'ComSigNonce::commitment'. Only once
+	/// it has every other party's commitment does it share 'ComSigNonce::pub_nonce'. Then each
+	/// signs with 'partial_sign', and the partial signatures are combined.
+	/// Committing first stops a party from picking its nonce after seeing the others'. Without
+	/// it, a party running several signing sessions with the same co-signer at once could
+	/// combine them to forge a signature (Wagner's algorithm, the ROS attack). Sessions may run
+	/// concurrently only because of that ordering, so a party must never reveal its nonce before
+	/// it has received every commitment, and must discard the nonce of an aborted session.
+	pub fn sum_nonces(
+		pub_nonces: &Vec<Commitment>,
+		nonce_commitments: &Vec<[u8; 32]>,
+	) -> Result<Commitment, ComSigError> {
+		if pub_nonces.is_empty() {
+			return Err(ComSigError::NoPartialSigs);
+		}
+		for (i, pub_nonce) in pub_nonces.iter().enumerate() {
+			if nonce_commitments.get(i) != Some(&ComSigNonce::commit_to(pub_nonce)) {
+				return Err(ComSigError::NonceMismatch(i));
+			}
+		}
+		if nonce_commitments.len() != pub_nonces.len() {
+			return Err(ComSigError::NonceMismatch(pub_nonces.len()));
+		}
+		let secp = Secp256k1::with_caps(ContextFlag::Commit);
+		Ok(secp.commit_sum(pub_nonces.clone(), Vec::new())?)
+	}
+
+	/// Signs with one party's share of the amount and blinding factor.
+	/// 'commit' is the full commitment being signed for, and 'pub_nonce' the sum of every
+	/// party's public nonce. The nonce is consumed, since reusing it would leak the share.
+	pub fn partial_sign(
+		amount_share: u64,
+		blind_share: &SecretKey,
+		nonce: ComSigNonce,
+		commit: &Commitment,
+		pub_nonce: &Commitment,
+		domain: &SigDomain,
+		msg: &Vec<u8>,
+	) -> Result<PartialComSignature, ComSigError> {
+		let secp = Secp256k1::with_caps(ContextFlag::Commit);
+		let e = ComSignature::calc_challenge(&secp, &commit, &pub_nonce, &domain, &msg)?;
+
+		// s_i = k_1_i + (e * amount_i)
+		let mut s = nonce.k_1.clone();
+		if amount_share > 0 {
+			let mut amt_bytes = [0; 32];
+			BigEndian::write_u64(&mut amt_bytes[24..32], amount_share);
+			let mut e_amt = SecretKey::from_slice(&secp, &amt_bytes)?;
+			e_amt.mul_assign(&secp, &e)?;
+			s.add_assign(&secp, &e_amt)?;
+		}
+
+		// t_i = k_2_i + (e * blind_i)
+		let mut t = blind_share.clone();
+		t.mul_assign(&secp, &e)?;
+		t.add_assign(&secp, &nonce.k_2)?;
+
+		Ok(PartialComSignature { s, t })
+	}
+
+	/// Combines every party's partial signature into a signature for the full commitment.
+	/// This is the last round.
+	pub fn combine(
+		pub_nonce: &Commitment,
+		partial_sigs: &Vec<PartialComSignature>,
+	) -> Result<ComSignature, ComSigError> {
+		let secp = Secp256k1::with_caps(ContextFlag::Commit);
+		let (first, rest) = partial_sigs
+			.split_first()
+			.ok_or(ComSigError::NoPartialSigs)?;
+
+		let mut s = first.s.clone();
+		let mut t = first.t.clone();
+		for partial_sig in rest {
+			s.add_assign(&secp, &partial_sig.s)?;
+			t.add_assign(&secp, &partial_sig.t)?;
+		}
+
+		Ok(ComSignature::new(&pub_nonce, &s, &t))
+	}
+
+	#[allow(non_snake_case)]
+	pub fn verify(
+		&self,
+		commit: &Commitment,
+		domain: &SigDomain,
+		msg: &Vec<u8>,
+	) -> Result<(), ComSigError> {
+		let secp = Secp256k1::with_caps(ContextFlag::Commit);
+
+		let S1 = secp.commit_blind(self.s.clone(), self.t.clone())?;
+
+		let mut Ce = commit.to_pubkey(&secp)?;
+		let e = ComSignature::calc_challenge(&secp, &commit, &self.pub_nonce, &domain, &msg)?;
+		Ce.mul_assign(&secp, &e)?;
+
+		let commits = vec![Commitment::from_pubkey(&secp, &Ce)?, self.pub_nonce.clone()];
+		let S2 = secp.commit_sum(commits, Vec::new())?;
+
+		if S1 != S2 {
+			return Err(ComSigError::InvalidSig);
+		}
+
+		Ok(())
+	}
+
+	fn calc_challenge(
+		secp: &Secp256k1,
+		commit: &Commitment,
+		nonce_commit: &Commitment,
+		domain: &SigDomain,
+		msg: &Vec<u8>,
+	) -> Result<SecretKey, ComSigError> {
+		let mut expiry_bytes = [0; 8];
+		BigEndian::write_u64(&mut expiry_bytes, domain.expiry_height);
+
+		let mut challenge_hasher = Blake2b::new(32);
+		challenge_hasher.update(b"MWIXNET_COMSIG");
+		challenge_hasher.update(&commit.0);
+		challenge_hasher.update(&nonce_commit.0);
+		challenge_hasher.update(&domain.server_pubkey.serialize_vec(secp, true)[..]);
+		challenge_hasher.update(&expiry_bytes);
+		challenge_hasher.update(msg);
+
+		let mut challenge = [0; 32];
+		challenge.copy_from_slice(challenge_hasher.finalize().as_bytes());
+
+		Ok(SecretKey::from_slice(&secp, &challenge)?)
+	}
+}
+
+/// One party's secret nonces for jointly signing with a split blinding factor.
+/// Deliberately not 'Clone': a nonce must only ever be used for a single partial signature.
+pub struct ComSigNonce {
+	k_1: SecretKey,
+	k_2: SecretKey,
+	pub_nonce: Commitment,
+}
+
+impl ComSigNonce {
+	pub fn new() -> Result<ComSigNonce, ComSigError> {
+		let secp = Secp256k1::with_caps(ContextFlag::Commit);
+		let k_1 = SecretKey::new(&secp, &mut thread_rng());
+		let k_2 = SecretKey::new(&secp, &mut thread_rng());
+		let pub_nonce = secp.commit_blind(k_1.clone(), k_2.clone())?;
+		Ok(ComSigNonce {
+			k_1,
+			k_2,
+			pub_nonce,
+		})
+	}
+
+	/// The commitment to the public nonce, shared with the other parties before the nonce itself
+	pub fn commitment(&self) -> [u8; 32] {
+		ComSigNonce::commit_to(&self.pub_nonce)
+	}
+
+	/// The public nonce to share with the other parties, once every party's commitment is in
+	pub fn pub_nonce(&self) -> Commitment {
+		self.pub_nonce.clone()
+	}
+
+	/// blake2b("MWIXNET_COMSIG_NONCE" || pub_nonce)
+	fn commit_to(pub_nonce: &Commitment) -> [u8; 32] {
+		let mut hasher = Blake2b::new(32);
+		hasher.update(b"MWIXNET_COMSIG_NONCE");
+		hasher.update(&pub_nonce.0);
+		let mut commitment = [0; 32];
+		commitment.copy_from_slice(hasher.finalize().as_bytes());
+		commitment
+	}
+}
+
+/// One party's share of a ComSignature, made with 'ComSignature::partial_sign'
+#[derive(Clone, Debug, PartialEq)]
+pub struct PartialComSignature {
+	s: SecretKey,
+	t: SecretKey,
+}
+
+impl PartialComSignature {
+	/// Checks the partial signature was made with the shares behind 'commit_share' and the nonce
+	/// behind 'nonce_share', so a misbehaving party is caught before the signatures are combined.
+	/// 'commit' and 'pub_nonce' are the full commitment and the sum of every party's public nonce.
+	#[allow(non_snake_case)]
+	pub fn verify(
+		&self,
+		commit_share: &Commitment,
+		nonce_share: &Commitment,
+		commit: &Commitment,
+		pub_nonce: &Commitment,
+		domain: &SigDomain,
+		msg: &Vec<u8>,
+	) -> Result<(), ComSigError> {
+		let secp = Secp256k1::with_caps(ContextFlag::Commit);
+
+		let S1 = secp.commit_blind(self.s.clone(), self.t.clone())?;
+
+		let mut Ce = commit_share.to_pubkey(&secp)?;
+		let e = ComSignature::calc_challenge(&secp, &commit, &pub_nonce, &domain, &msg)?;
+		Ce.mul_assign(&secp, &e)?;
+
+		let commits = vec![Commitment::from_pubkey(&secp, &Ce)?, nonce_share.clone()];
+		let S2 = secp.commit_sum(commits, Vec::new())?;
+
+		if S1 != S2 {
+			return Err(ComSigError::InvalidSig);
+		}
+
+		Ok(())
+	}
+}
+
+impl Readable for PartialComSignature {
+	fn read<R: Reader>(reader: &mut R) -> Result<Self, ser::Error> {
+		let s = read_secret_key(reader)?;
+		let t = read_secret_key(reader)?;
+		Ok(PartialComSignature { s, t })
+	}
+}
+
+impl Writeable for PartialComSignature {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_fixed_bytes(self.s.0)?;
+		writer.write_fixed_bytes(self.t.0)?;
+		Ok(())
+	}
+}
+
+/// Serializes a ComSignature to and from hex
+pub mod comsig_serde {
+	use super::ComSignature;
+	use grin_core::ser::{self, ProtocolVersion};
+	use grin_util::ToHex;
+	use serde::{Deserialize, Serializer};
+
+	/// Serializes a ComSignature as a hex string
+	pub fn serialize<S>(comsig: &ComSignature, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: Serializer,
+	{
+		use serde::ser::Error;
+		let bytes = ser::ser_vec(&comsig, ProtocolVersion::local()).map_err(Error::custom)?;
+		serializer.serialize_str(&bytes.to_hex())
+	}
+
+	/// Creates a ComSignature from a hex string
+	pub fn deserialize<'de, D>(deserializer: D) -> Result<ComSignature, D::Error>
+	where
+		D: serde::Deserializer<'de>,
+	{
+		use serde::de::Error;
+		let bytes = String::deserialize(deserializer)
+			.and_then(|string| grin_util::from_hex(&string).map_err(Error::custom))?;
+		let sig: ComSignature = ser::deserialize_default(&mut &bytes[..]).map_err(Error::custom)?;
+		Ok(sig)
+	}
+}
+
+#[allow(non_snake_case)]
+impl Readable for ComSignature {
+	fn read<R: Reader>(reader: &mut R) -> Result<Self, ser::Error> {
+		let R = Commitment::read(reader)?;
+		let s = read_secret_key(reader)?;
+		let t = read_secret_key(reader)?;
+		Ok(ComSignature::new(&R, &s, &t))
+	}
+}
+
+impl Writeable for ComSignature {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_fixed_bytes(self.pub_nonce.0)?;
+		writer.write_fixed_bytes(self.s.0)?;
+		writer.write_fixed_bytes(self.t.0)?;
+		Ok(())
+	}
+}
+
+/// Generate a random SecretKey.
+pub fn random_secret() -> SecretKey {
+	let secp = Secp256k1::new();
+	SecretKey::new(&secp, &mut thread_rng())
+}
+
+/// Deserialize a SecretKey from a Reader
+pub fn read_secret_key<R: Reader>(reader: &mut R) -> Result<SecretKey, ser::Error> {
+	let buf = reader.read_fixed_bytes(SECRET_KEY_SIZE)?;
+	let secp = Secp256k1::with_caps(ContextFlag::None);
+	let pk = SecretKey::from_slice(&secp, &buf).map_err(|_| ser::Error::CorruptedData)?;
+	Ok(pk)
+}
+
+/// Parses a hex-encoded commitment, or None if it isn't one
+pub fn commitment_from_hex(hex: &str) -> Option<Commitment> {
+	let bytes = grin_util::from_hex(hex).ok()?;
+	if bytes.len() != PEDERSEN_COMMITMENT_SIZE {
+		return None;
+	}
+	Some(Commitment::from_vec(bytes))
+}
+
+/// Build a Pedersen Commitment using the provided value and blinding factor
+pub fn commit(value: u64, blind: &SecretKey) -> Result<Commitment, secp256k1zkp::Error> {
+	let secp = Secp256k1::with_caps(ContextFlag::Commit);
+	let commit = secp.commit(value, blind.clone())?;
+	Ok(commit)
+}
+
+/// Add a blinding factor to an existing Commitment
+pub fn add_excess(
+	commitment: &Commitment,
+	excess: &SecretKey,
+) -> Result<Commitment, secp256k1zkp::Error> {
+	let secp = Secp256k1::with_caps(ContextFlag::Commit);
+	let excess_commit: Commitment = secp.commit(0, excess.clone())?;
+
+	let commits = vec![commitment.clone(), excess_commit.clone()];
+	let sum = secp.commit_sum(commits, Vec::new())?;
+	Ok(sum)
+}
+
+/// Adds one commitment to another, e.g. a kernel's excess to an output commitment
+pub fn add_commits(
+	commitment: &Commitment,
+	other: &Commitment,
+) -> Result<Commitment, secp256k1zkp::Error> {
+	let secp = Secp256k1::with_caps(ContextFlag::Commit);
+	let sum = secp.commit_sum(vec![commitment.clone(), other.clone()], Vec::new())?;
+	Ok(sum)
+}
+
+/// Subtracts one commitment from another, e.g. a change output's blinding factor from an output
+/// commitment
+pub fn sub_commits(
+	commitment: &Commitment,
+	other: &Commitment,
+) -> Result<Commitment, secp256k1zkp::Error> {
+	let secp = Secp256k1::with_caps(ContextFlag::Commit);
+	let sum = secp.commit_sum(vec![commitment.clone()], vec![other.clone()])?;
+	Ok(sum)
+}
+
+/// Subtracts a value (v*H) from an existing commitment
+pub fn sub_value(commitment: &Commitment, value: u64) -> Result<Commitment, secp256k1zkp::Error> {
+	let secp = Secp256k1::with_caps(ContextFlag::Commit);
+	let neg_commit: Commitment = secp.commit(value, ZERO_KEY)?;
+	let sum = secp.commit_sum(vec![commitment.clone()], vec![neg_commit.clone()])?;
+	Ok(sum)
+}
+
+/// Signs the message with the provided SecretKey
+pub fn sign(sk: &SecretKey, msg: &Message) -> Result<Signature, secp256k1zkp::Error> {
+	let secp = Secp256k1::with_caps(ContextFlag::Full);
+	let pubkey = PublicKey::from_secret_key(&secp, &sk)?;
+	let sig = aggsig::sign_single(&secp, &msg, &sk, None, None, None, Some(&pubkey), None)?;
+	Ok(sig)
+}
+
+/// Verifies a signature made with 'sign' by the owner of the PublicKey
+pub fn verify(pubkey: &PublicKey, sig: &Signature, msg: &Message) -> bool {
+	let secp = Secp256k1::with_caps(ContextFlag::Full);
+	aggsig::verify_single(&secp, &sig, &msg, None, &pubkey, Some(&pubkey), None, false)
+}
+
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util {
+	use crate::secp::{self, Commitment, PublicKey, RangeProof, Secp256k1};
+	use grin_core::core::hash::Hash;
+	use grin_util::ToHex;
+	use rand::RngCore;
+
+	pub fn rand_commit() -> Commitment {
+		secp::commit(rand::thread_rng().next_u64(), &secp::random_secret()).unwrap()
+	}
+
+	pub fn rand_hash() -> Hash {
+		Hash::from_hex(secp::random_secret().to_hex().as_str()).unwrap()
+	}
+
+	pub fn rand_proof() -> RangeProof {
+		let secp = Secp256k1::new();
+		secp.bullet_proof(
+			rand::thread_rng().next_u64(),
+			secp::random_secret(),
+			secp::random_secret(),
+			secp::random_secret(),
+			None,
+			None,
+		)
+	}
+
+	pub fn rand_pubkey() -> PublicKey {
+		let secp = Secp256k1::new();
+		PublicKey::from_secret_key(&secp, &secp::random_secret()).unwrap()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		ComSigError, ComSigNonce, ComSignature, ContextFlag, PartialComSignature, PublicKey,
+		Secp256k1, SecretKey, SigDomain,
+	};
+	use grin_core::ser::{self, ProtocolVersion};
+
+	use rand::Rng;
+	use secp256k1zkp::rand::{thread_rng, RngCore};
+
+	/// Test signing and verification of ComSignatures
+	#[test]
+	fn verify_comsig() -> Result<(), ComSigError> {
+		let secp = Secp256k1::with_caps(ContextFlag::Commit);
+
+		let amount = thread_rng().next_u64();
+		let blind = SecretKey::new(&secp, &mut thread_rng());
+		let msg: [u8; 16] = rand::thread_rng().gen();
+		let server_key = SecretKey::new(&secp, &mut thread_rng());
+		let domain = SigDomain::new(&PublicKey::from_secret_key(&secp, &server_key)?, 1000);
+		let comsig = ComSignature::sign(amount, &blind, &domain, &msg.to_vec())?;
+
+		let commit = secp.commit(amount, blind.clone())?;
+		assert!(comsig.verify(&commit, &domain, &msg.to_vec()).is_ok());
+
+		let wrong_msg: [u8; 16] = rand::thread_rng().gen();
+		assert!(comsig
+			.verify(&commit, &domain, &wrong_msg.to_vec())
+			.is_err());
+
+		let wrong_commit = secp.commit(amount, SecretKey::new(&secp, &mut thread_rng()))?;
+		assert!(comsig
+			.verify(&wrong_commit, &domain, &msg.to_vec())
+			.is_err());
+
+		// Signatures can't be replayed to another server, or with a different expiry
+		let other_key = SecretKey::new(&secp, &mut thread_rng());
+		let other_server = SigDomain::new(&PublicKey::from_secret_key(&secp, &other_key)?, 1000);
+		assert!(comsig
+			.verify(&commit, &other_server, &msg.to_vec())
+			.is_err());
+
+		let later_expiry = SigDomain::new(&domain.server_pubkey, 2000);
+		assert!(comsig
+			.verify(&commit, &later_expiry, &msg.to_vec())
+			.is_err());
+
+		Ok(())
+	}
+	/// Two parties with split amounts and blinding factors jointly sign for their commitment,
+	/// without either learning the other's share
+	#[test]
+	fn multisig_comsig() -> Result<(), Box<dyn std::error::Error>> {
+		let secp = Secp256k1::with_caps(ContextFlag::Commit);
+
+		let (amount_a, amount_b) = (thread_rng().next_u32() as u64, 0);
+		let blind_a = SecretKey::new(&secp, &mut thread_rng());
+		let blind_b = SecretKey::new(&secp, &mut thread_rng());
+		let commit_a = secp.commit(amount_a, blind_a.clone())?;
+		let commit_b = secp.commit(amount_b, blind_b.clone())?;
+		let commit = secp.commit_sum(vec![commit_a.clone(), commit_b.clone()], Vec::new())?;
+
+		let msg: [u8; 16] = rand::thread_rng().gen();
+		let server_key = SecretKey::new(&secp, &mut thread_rng());
+		let domain = SigDomain::new(&PublicKey::from_secret_key(&secp, &server_key)?, 1000);
+
+		// Round 1: exchange commitments to the public nonces
+		let (nonce_a, nonce_b) = (ComSigNonce::new()?, ComSigNonce::new()?);
+		let nonce_commitments = vec![nonce_a.commitment(), nonce_b.commitment()];
+
+		// Round 2: reveal the public nonces, which must match their commitments
+		let (pub_nonce_a, pub_nonce_b) = (nonce_a.pub_nonce(), nonce_b.pub_nonce());
+		let pub_nonce = ComSignature::sum_nonces(
+			&vec![pub_nonce_a.clone(), pub_nonce_b.clone()],
+			&nonce_commitments,
+		)?;
+		let substituted = ComSigNonce::new()?.pub_nonce();
+		assert!(matches!(
+			ComSignature::sum_nonces(&vec![pub_nonce_a.clone(), substituted], &nonce_commitments),
+			Err(ComSigError::NonceMismatch(1))
+		));
+		assert!(matches!(
+			ComSignature::sum_nonces(&vec![pub_nonce_a.clone()], &nonce_commitments),
+			Err(ComSigError::NonceMismatch(1))
+		));
+
+		// Round 3: exchange partial signatures, check them, and combine
+		let partial_a = ComSignature::partial_sign(
+			amount_a,
+			&blind_a,
+			nonce_a,
+			&commit,
+			&pub_nonce,
+			&domain,
+			&msg.to_vec(),
+		)?;
+		let partial_b = ComSignature::partial_sign(
+			amount_b,
+			&blind_b,
+			nonce_b,
+			&commit,
+			&pub_nonce,
+			&domain,
+			&msg.to_vec(),
+		)?;
+		let bytes = ser::ser_vec(&partial_b, ProtocolVersion::local())?;
+		let partial_b: PartialComSignature = ser::deserialize_default(&mut &bytes[..])?;
+
+		partial_a.verify(
+			&commit_a,
+			&pub_nonce_a,
+			&commit,
+			&pub_nonce,
+			&domain,
+			&msg.to_vec(),
+		)?;
+		partial_b.verify(
+			&commit_b,
+			&pub_nonce_b,
+			&commit,
+			&pub_nonce,
+			&domain,
+			&msg.to_vec(),
+		)?;
+		assert!(partial_b
+			.verify(
+				&commit_a,
+				&pub_nonce_a,
+				&commit,
+				&pub_nonce,
+				&domain,
+				&msg.to_vec()
+			)
+			.is_err());
+
+		let comsig = ComSignature::combine(&pub_nonce, &vec![partial_a.clone(), partial_b])?;
+		assert!(comsig.verify(&commit, &domain, &msg.to_vec()).is_ok());
+
+		// Missing a party's share, the signature doesn't verify
+		let incomplete = ComSignature::combine(&pub_nonce, &vec![partial_a])?;
+		assert!(incomplete.verify(&commit, &domain, &msg.to_vec()).is_err());
+		assert!(matches!(
+			ComSignature::combine(&pub_nonce, &vec![]),
+			Err(ComSigError::NoPartialSigs)
+		));
+
+		Ok(())
+	}
+}
@@ -0,0 +1,196 @@
+use crate::onion::MAX_PAYLOAD_SIZE;
+use crate::secp::{self, RangeProof, SecretKey};
+
+use grin_core::core::{FeeFields, Output, TxKernel};
+use grin_core::ser::{self, Readable, Reader, Writeable, Writer};
+use serde::{Deserialize, Serialize};
+
+const CURRENT_VERSION: u8 = 0;
+
+/// Version of payloads that carry an owner kernel.
+/// Payloads without one are still written as version 0, so older servers can read them.
+const OWNER_KERNEL_VERSION: u8 = 1;
+
+/// Version of payloads that carry a minimum value proof, along with an optional owner kernel
+const MIN_VALUE_PROOF_VERSION: u8 = 2;
+
+/// Version of payloads that carry the next hop's view tag, along with the optional fields above
+const VIEW_TAG_VERSION: u8 = 3;
+
+/// Version of payloads that carry fee change, along with the optional fields above
+const FEE_CHANGE_VERSION: u8 = 4;
+
+/// Writes an optional value as '1' + value if Some, or '0' if None
+pub fn write_optional<O: Writeable, W: Writer>(
+	writer: &mut W,
+	o: &Option<O>,
+) -> Result<(), ser::Error> {
+	match &o {
+		Some(o) => {
+			writer.write_u8(1)?;
+			o.write(writer)?;
+		}
+		None => writer.write_u8(0)?,
+	};
+	Ok(())
+}
+
+/// Reads an optional value as '1' + value if Some, or '0' if None
+pub fn read_optional<O: Readable, R: Reader>(reader: &mut R) -> Result<Option<O>, ser::Error> {
+	match reader.read_u8()? {
+		0 => Ok(None),
+		1 => Ok(Some(O::read(reader)?)),
+		_ => Err(ser::Error::CorruptedData),
+	}
+}
+
+/// An output paying the swap's owner back the part of the fee they over-paid, so wallets can
+/// state a fee computed the standard way. Only the last hop's payload may carry one, alongside an
+/// owner kernel: the change has no kernel of its own, its blinding factor being signed for by the
+/// owner kernel, so it can't be matched to a kernel on chain.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FeeChange {
+	/// value of the change (in nanogrin), which is deducted from the fee the server collects
+	pub value: u64,
+	/// the change output, with its rangeproof
+	pub output: Output,
+}
+
+impl Readable for FeeChange {
+	fn read<R: Reader>(reader: &mut R) -> Result<FeeChange, ser::Error> {
+		Ok(FeeChange {
+			value: reader.read_u64()?,
+			output: Output::read(reader)?,
+		})
+	}
+}
+
+impl Writeable for FeeChange {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u64(self.value)?;
+		self.output.write(writer)
+	}
+}
+
+// todo: Belongs in Onion
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Payload {
+	pub excess: SecretKey,
+	pub fee: FeeFields,
+	pub rangeproof: Option<RangeProof>,
+	/// A fee-free kernel signed by the swap's owner, for the part of the blinding delta they keep
+	/// to themselves. Its excess is added to the output commitment along with 'excess',
+	/// so the server never learns the full difference between the input and output blinds.
+	pub owner_kernel: Option<TxKernel>,
+	/// A rangeproof over the output commitment minus the server's 'min_output_value' (times H),
+	/// showing the output is worth at least that much without revealing its value
+	pub min_value_proof: Option<RangeProof>,
+	/// View tag of the onion passed on to the next hop, set by the route builder
+	pub next_view_tag: Option<u8>,
+	/// Change for an over-paid fee, paid out by the last hop
+	pub fee_change: Option<FeeChange>,
+}
+
+impl Payload {
+	/// Reads a payload that fills 'bytes' exactly. Payloads are decrypted from onions, so
+	/// anything left over means the layer was tampered with.
+	pub fn deserialize(bytes: &Vec<u8>) -> Result<Payload, ser::Error> {
+		if bytes.len() > MAX_PAYLOAD_SIZE {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut remaining = &bytes[..];
+		let payload: Payload = ser::deserialize_default(&mut remaining)?;
+		if !remaining.is_empty() {
+			return Err(ser::Error::CorruptedData);
+		}
+		Ok(payload)
+	}
+
+	pub fn serialize(&self) -> Result<Vec<u8>, ser::Error> {
+		let mut vec = vec![];
+		ser::serialize_default(&mut vec, &self)?;
+		Ok(vec)
+	}
+}
+
+impl Readable for Payload {
+	fn read<R: Reader>(reader: &mut R) -> Result<Payload, ser::Error> {
+		let version = reader.read_u8()?;
+		if version > FEE_CHANGE_VERSION {
+			return Err(ser::Error::UnsupportedProtocolVersion);
+		}
+
+		let excess = secp::read_secret_key(reader)?;
+		let fee = FeeFields::try_from(reader.read_u64()?).map_err(|_| ser::Error::CorruptedData)?;
+		let rangeproof = read_optional(reader)?;
+		let (owner_kernel, min_value_proof) = match version {
+			OWNER_KERNEL_VERSION => (Some(TxKernel::read(reader)?), None),
+			MIN_VALUE_PROOF_VERSION | VIEW_TAG_VERSION | FEE_CHANGE_VERSION => {
+				(read_optional(reader)?, read_optional(reader)?)
+			}
+			_ => (None, None),
+		};
+		let next_view_tag = match version {
+			VIEW_TAG_VERSION | FEE_CHANGE_VERSION => read_optional(reader)?,
+			_ => None,
+		};
+		let fee_change = match version {
+			FEE_CHANGE_VERSION => read_optional(reader)?,
+			_ => None,
+		};
+		Ok(Payload {
+			excess,
+			fee,
+			rangeproof,
+			owner_kernel,
+			min_value_proof,
+			next_view_tag,
+			fee_change,
+		})
+	}
+}
+
+impl Writeable for Payload {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		// Written with the oldest version that can hold the payload, so older servers can read it
+		let version = if self.fee_change.is_some() {
+			FEE_CHANGE_VERSION
+		} else if self.next_view_tag.is_some() {
+			VIEW_TAG_VERSION
+		} else if self.min_value_proof.is_some() {
+			MIN_VALUE_PROOF_VERSION
+		} else if self.owner_kernel.is_some() {
+			OWNER_KERNEL_VERSION
+		} else {
+			CURRENT_VERSION
+		};
+		writer.write_u8(version)?;
+		writer.write_fixed_bytes(&self.excess)?;
+		writer.write_u64(self.fee.into())?;
+		write_optional(writer, &self.rangeproof)?;
+		match version {
+			MIN_VALUE_PROOF_VERSION => {
+				write_optional(writer, &self.owner_kernel)?;
+				write_optional(writer, &self.min_value_proof)?;
+			}
+			VIEW_TAG_VERSION => {
+				write_optional(writer, &self.owner_kernel)?;
+				write_optional(writer, &self.min_value_proof)?;
+				write_optional(writer, &self.next_view_tag)?;
+			}
+			FEE_CHANGE_VERSION => {
+				write_optional(writer, &self.owner_kernel)?;
+				write_optional(writer, &self.min_value_proof)?;
+				write_optional(writer, &self.next_view_tag)?;
+				write_optional(writer, &self.fee_change)?;
+			}
+			OWNER_KERNEL_VERSION => {
+				if let Some(owner_kernel) = &self.owner_kernel {
+					owner_kernel.write(writer)?;
+				}
+			}
+			_ => {}
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,17 @@
+//! Commitment signatures, as read from swap and cancel requests
+
+#![no_main]
+
+use grin_core::ser::{self, ProtocolVersion};
+use libfuzzer_sys::fuzz_target;
+use mwixnet_client::secp::ComSignature;
+
+fuzz_target!(|data: &[u8]| {
+	let comsig: ComSignature = match ser::deserialize_default(&mut &data[..]) {
+		Ok(comsig) => comsig,
+		Err(_) => return,
+	};
+
+	let bytes = ser::ser_vec(&comsig, ProtocolVersion::local()).unwrap();
+	assert_eq!(&data[..bytes.len()], &bytes[..]);
+});
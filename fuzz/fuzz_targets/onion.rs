@@ -0,0 +1,24 @@
+//! Onions as read off the wire, then peeled by a server
+
+#![no_main]
+
+use grin_core::ser::{self, ProtocolVersion};
+use libfuzzer_sys::fuzz_target;
+use mwixnet_client::onion::Onion;
+use mwixnet_client::secp::{ContextFlag, Secp256k1, SecretKey};
+
+fuzz_target!(|data: &[u8]| {
+	let onion: Onion = match ser::deserialize_default(&mut &data[..]) {
+		Ok(onion) => onion,
+		Err(_) => return,
+	};
+
+	// Anything read writes back out as the same onion
+	let bytes = ser::ser_vec(&onion, ProtocolVersion::local()).unwrap();
+	let reread: Onion = ser::deserialize_default(&mut &bytes[..]).unwrap();
+	assert_eq!(onion, reread);
+
+	let secp = Secp256k1::with_caps(ContextFlag::None);
+	let server_key = SecretKey::from_slice(&secp, &[1u8; 32]).unwrap();
+	let _ = onion.peel_layer(&server_key);
+});
@@ -0,0 +1,19 @@
+//! Payloads as decrypted from an onion's layer
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mwixnet_client::types::Payload;
+
+fuzz_target!(|data: &[u8]| {
+	let payload = match Payload::deserialize(&data.to_vec()) {
+		Ok(payload) => payload,
+		Err(_) => return,
+	};
+
+	// Payloads are written with the oldest version that holds them, which needn't be the version
+	// they were read with, but what's written reads back and writes out the same
+	let bytes = payload.serialize().unwrap();
+	let reread = Payload::deserialize(&bytes).unwrap();
+	assert_eq!(bytes, reread.serialize().unwrap());
+});
@@ -0,0 +1,718 @@
+use crate::config::ConfigChanges;
+use crate::features::{Feature, FeatureError, FeatureFlags};
+use crate::metrics::AnonymityStats;
+use crate::reload::ConfigReloader;
+use crate::report::{self, DailyReport, SECONDS_PER_DAY};
+use crate::secp::{Secp256k1, SecretKey};
+use crate::server::{
+	KernelLocation, RoundPhase, RoundState, Server, ServerKeys, ServerStatus, SwapError,
+};
+use crate::store::{FeeOutputData, RejectionData, RoundData, SwapData, SwapStatus};
+use crate::wallet;
+
+use grin_core::core::hash::Hash;
+use grin_util::ToHex;
+use jsonrpc_core::{Error, ErrorCode, IoHandler, Value};
+use jsonrpc_derive::rpc;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+const TOKEN_FILE_EXTENSION: &str = "token";
+
+/// Rejections listed by 'list_rejections' when no limit is given
+const DEFAULT_REJECTIONS_LISTED: usize = 100;
+
+/// A summary of a single swap, safe to display to operators (contains no secret keys)
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SwapSummary {
+	pub input_commit: String,
+	pub output_commit: String,
+	pub fee: u64,
+	pub status: String,
+	pub kernel_hash: Option<String>,
+	pub block_hash: Option<String>,
+	pub expiry_height: u64,
+	/// unix time (in seconds) the swap was accepted, or 0 if it was accepted by an older version
+	#[serde(default)]
+	pub accepted_at: u64,
+	#[serde(default)]
+	pub accepted_round: u64,
+}
+
+impl From<&SwapData> for SwapSummary {
+	fn from(swap: &SwapData) -> Self {
+		let (kernel_hash, block_hash) = match &swap.status {
+			SwapStatus::Unprocessed | SwapStatus::Failed => (None, None),
+			SwapStatus::InProcess { kernel_hash } => (Some(kernel_hash.to_hex()), None),
+			SwapStatus::Completed {
+				kernel_hash,
+				block_hash,
+			} => (Some(kernel_hash.to_hex()), Some(block_hash.to_hex())),
+		};
+
+		SwapSummary {
+			input_commit: swap.input.commit.to_hex(),
+			output_commit: swap.output_commit.to_hex(),
+			fee: swap.fee,
+			status: swap.status.name().to_string(),
+			kernel_hash,
+			block_hash,
+			expiry_height: swap.expiry_height,
+			accepted_at: swap.accepted_at,
+			accepted_round: swap.accepted_round,
+		}
+	}
+}
+
+/// A summary of a round the server executed
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RoundSummary {
+	pub round_id: u64,
+	pub kernel_hash: String,
+	pub tx_weight: u64,
+	pub swap_count: usize,
+	/// distinct outputs the round's participants received, not counting decoys
+	pub anonymity_set: u64,
+	pub total_fees: u64,
+	/// unix time (in seconds) the round transaction was posted
+	pub posted_at: u64,
+	/// height of the block the round was confirmed in, if it has been
+	pub confirmed_height: Option<u64>,
+}
+
+impl From<&RoundData> for RoundSummary {
+	fn from(round: &RoundData) -> Self {
+		RoundSummary {
+			round_id: round.round_id,
+			kernel_hash: round.kernel_hash.to_hex(),
+			tx_weight: round.tx_weight,
+			swap_count: round.swap_count(),
+			anonymity_set: round.anonymity_set,
+			total_fees: round.total_fees(),
+			posted_at: round.timestamp,
+			confirmed_height: round.confirmed_height,
+		}
+	}
+}
+
+/// A round the server executed, with the swaps it included
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RoundDetails {
+	#[serde(flatten)]
+	pub round: RoundSummary,
+	pub swaps: Vec<SwapSummary>,
+}
+
+/// Fees collected by the server's wallet in a single round
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RoundFees {
+	pub timestamp: u64,
+	pub kernel_hash: String,
+	pub output_commit: String,
+	pub amount: u64,
+	/// where to find the round's kernel on chain
+	pub kernel: Option<KernelLocation>,
+}
+
+/// Fees collected by the server's wallet over a single (UTC) day
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct DailyFees {
+	pub date: String,
+	pub rounds: u64,
+	pub amount: u64,
+}
+
+/// Summary of all fees earned by the server, by day and by round
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct FeeReport {
+	pub total: u64,
+	pub days: Vec<DailyFees>,
+	pub rounds: Vec<RoundFees>,
+}
+
+impl FeeReport {
+	/// Builds a report from the recorded fee outputs, ordered from oldest to newest
+	pub fn new(fee_outputs: &Vec<FeeOutputData>) -> FeeReport {
+		let mut rounds: Vec<RoundFees> = fee_outputs
+			.iter()
+			.map(|f| RoundFees {
+				timestamp: f.timestamp,
+				kernel_hash: f.kernel_hash.to_hex(),
+				output_commit: f.output_commit.to_hex(),
+				amount: f.amount,
+				kernel: None,
+			})
+			.collect();
+		rounds.sort_by_key(|r| r.timestamp);
+
+		let mut days: BTreeMap<u64, DailyFees> = BTreeMap::new();
+		for round in &rounds {
+			let day = round.timestamp / SECONDS_PER_DAY;
+			let daily = days.entry(day).or_insert_with(|| DailyFees {
+				date: report::format_date(day),
+				rounds: 0,
+				amount: 0,
+			});
+			daily.rounds += 1;
+			daily.amount += round.amount;
+		}
+
+		FeeReport {
+			total: rounds.iter().map(|r| r.amount).sum(),
+			days: days.into_iter().map(|(_, d)| d).collect(),
+			rounds,
+		}
+	}
+}
+
+/// Administrative API, only available to the server's operator.
+/// Every method requires the token written to disk when the admin listener starts.
+#[rpc(server)]
+pub trait AdminAPI {
+	#[rpc(name = "status")]
+	fn status(&self, token: String) -> jsonrpc_core::Result<ServerStatus>;
+
+	#[rpc(name = "trigger_round")]
+	fn trigger_round(&self, token: String) -> jsonrpc_core::Result<Value>;
+
+	#[rpc(name = "list_swaps")]
+	fn list_swaps(&self, token: String) -> jsonrpc_core::Result<Vec<SwapSummary>>;
+
+	#[rpc(name = "list_rounds")]
+	fn list_rounds(&self, token: String) -> jsonrpc_core::Result<Vec<RoundSummary>>;
+
+	/// The round with the hex-encoded 'kernel_hash' and the swaps it included, or null if there's none
+	#[rpc(name = "get_round")]
+	fn get_round(
+		&self,
+		token: String,
+		kernel_hash: String,
+	) -> jsonrpc_core::Result<Option<RoundDetails>>;
+
+	#[rpc(name = "fee_report")]
+	fn fee_report(&self, token: String) -> jsonrpc_core::Result<FeeReport>;
+
+	/// Distribution of the anonymity sets of all rounds executed
+	#[rpc(name = "anonymity_stats")]
+	fn anonymity_stats(&self, token: String) -> jsonrpc_core::Result<AnonymityStats>;
+
+	/// Report for the given 'YYYY-MM-DD' date, or today (so far) if no date is provided
+	#[rpc(name = "daily_report")]
+	fn daily_report(
+		&self,
+		token: String,
+		date: Option<String>,
+	) -> jsonrpc_core::Result<DailyReport>;
+
+	/// Promotes a standby to primary, once its primary has stopped replicating
+	#[rpc(name = "promote")]
+	fn promote(&self, token: String) -> jsonrpc_core::Result<Value>;
+
+	/// Switches the running server to the hex-encoded 'key', which must already be saved to the
+	/// config file. The old key is still accepted until 'expires_at' (unix time, in seconds).
+	#[rpc(name = "rotate_key")]
+	fn rotate_key(
+		&self,
+		token: String,
+		key: String,
+		expires_at: u64,
+	) -> jsonrpc_core::Result<ServerKeys>;
+
+	/// Lists which features are enabled
+	#[rpc(name = "features")]
+	fn features(&self, token: String) -> jsonrpc_core::Result<FeatureFlags>;
+
+	/// Enables or disables the named feature (e.g. 'accept_submissions'), until changed again
+	#[rpc(name = "set_feature")]
+	fn set_feature(
+		&self,
+		token: String,
+		feature: String,
+		enabled: bool,
+	) -> jsonrpc_core::Result<FeatureFlags>;
+
+	/// Re-reads the config file, applying every change that doesn't require a restart
+	#[rpc(name = "reload_config")]
+	fn reload_config(&self, token: String) -> jsonrpc_core::Result<ConfigChanges>;
+
+	/// The most recently rejected swaps, newest first (the last 100 unless 'limit' is given)
+	#[rpc(name = "list_rejections")]
+	fn list_rejections(
+		&self,
+		token: String,
+		limit: Option<usize>,
+	) -> jsonrpc_core::Result<Vec<RejectionData>>;
+}
+
+#[derive(Clone)]
+struct AdminServer {
+	server: Arc<Mutex<dyn Server>>,
+	/// checked before triggering a round, since the server is held while one executes
+	round_state: Arc<RoundState>,
+	reloader: ConfigReloader,
+	token: String,
+}
+
+impl AdminServer {
+	/// Rejects the request unless the token matches the one generated at startup
+	fn authorize(&self, token: &str) -> jsonrpc_core::Result<()> {
+		ring::constant_time::verify_slices_are_equal(token.as_bytes(), self.token.as_bytes())
+			.map_err(|_| Error {
+				code: ErrorCode::ServerError(-32001),
+				message: "Invalid admin token".to_string(),
+				data: None,
+			})
+	}
+}
+
+impl AdminAPI for AdminServer {
+	/// Implements the 'status' API
+	fn status(&self, token: String) -> jsonrpc_core::Result<ServerStatus> {
+		self.authorize(&token)?;
+		let status = self.server.lock().unwrap().status()?;
+		Ok(status)
+	}
+
+	/// Implements the 'trigger_round' API
+	fn trigger_round(&self, token: String) -> jsonrpc_core::Result<Value> {
+		self.authorize(&token)?;
+		let phase = self.round_state.phase();
+		if phase != RoundPhase::Idle {
+			return Err(SwapError::RoundInProgress { phase }.into());
+		}
+		let tx = self
+			.server
+			.lock()
+			.unwrap()
+			.execute_round()
+			.map_err(|e| Error {
+				code: ErrorCode::InternalError,
+				message: e.to_string(),
+				data: None,
+			})?;
+		let kernel_excess = tx
+			.as_ref()
+			.and_then(|tx| wallet::round_kernel(tx).map(|k| k.excess.to_hex()));
+		Ok(json!({ "kernel_excess": kernel_excess }))
+	}
+
+	/// Implements the 'list_swaps' API
+	fn list_swaps(&self, token: String) -> jsonrpc_core::Result<Vec<SwapSummary>> {
+		self.authorize(&token)?;
+		let swaps = self.server.lock().unwrap().list_swaps()?;
+		Ok(swaps.iter().map(SwapSummary::from).collect())
+	}
+
+	/// Implements the 'list_rounds' API
+	fn list_rounds(&self, token: String) -> jsonrpc_core::Result<Vec<RoundSummary>> {
+		self.authorize(&token)?;
+		let rounds = self.server.lock().unwrap().list_rounds()?;
+		Ok(rounds.iter().map(RoundSummary::from).collect())
+	}
+
+	/// Implements the 'get_round' API
+	fn get_round(
+		&self,
+		token: String,
+		kernel_hash: String,
+	) -> jsonrpc_core::Result<Option<RoundDetails>> {
+		self.authorize(&token)?;
+		let kernel_hash = Hash::from_hex(&kernel_hash)
+			.map_err(|_| Error::invalid_params("Invalid kernel hash"))?;
+		let round = self.server.lock().unwrap().get_round(&kernel_hash)?;
+		Ok(round.map(|(round, swaps)| RoundDetails {
+			round: RoundSummary::from(&round),
+			swaps: swaps.iter().map(SwapSummary::from).collect(),
+		}))
+	}
+
+	/// Implements the 'fee_report' API
+	fn fee_report(&self, token: String) -> jsonrpc_core::Result<FeeReport> {
+		self.authorize(&token)?;
+		let server = self.server.lock().unwrap();
+		let fee_outputs = server.fee_outputs()?;
+		let mut report = FeeReport::new(&fee_outputs);
+		for fee_output in &fee_outputs {
+			let kernel = server.locate_kernel(&fee_output.kernel_hash)?;
+			let kernel_hash = fee_output.kernel_hash.to_hex();
+			for round in report.rounds.iter_mut() {
+				if round.kernel_hash == kernel_hash {
+					round.kernel = kernel.clone();
+				}
+			}
+		}
+		Ok(report)
+	}
+
+	/// Implements the 'anonymity_stats' API
+	fn anonymity_stats(&self, token: String) -> jsonrpc_core::Result<AnonymityStats> {
+		self.authorize(&token)?;
+		let rounds = self.server.lock().unwrap().list_rounds()?;
+		Ok(AnonymityStats::new(&rounds))
+	}
+
+	/// Implements the 'daily_report' API
+	fn daily_report(
+		&self,
+		token: String,
+		date: Option<String>,
+	) -> jsonrpc_core::Result<DailyReport> {
+		self.authorize(&token)?;
+		let day = match date {
+			Some(date) => report::parse_date(&date)
+				.ok_or(Error::invalid_params("Invalid date. Expected YYYY-MM-DD"))?,
+			None => report::today(),
+		};
+		let report = self.server.lock().unwrap().daily_report(day)?;
+		Ok(report)
+	}
+
+	/// Implements the 'promote' API
+	fn promote(&self, token: String) -> jsonrpc_core::Result<Value> {
+		self.authorize(&token)?;
+		self.server.lock().unwrap().promote()?;
+		Ok(Value::String("success".into()))
+	}
+
+	/// Implements the 'rotate_key' API
+	fn rotate_key(
+		&self,
+		token: String,
+		key: String,
+		expires_at: u64,
+	) -> jsonrpc_core::Result<ServerKeys> {
+		self.authorize(&token)?;
+		let key = grin_util::from_hex(&key)
+			.ok()
+			.and_then(|bytes| SecretKey::from_slice(&Secp256k1::new(), &bytes).ok())
+			.ok_or(Error::invalid_params("Invalid key"))?;
+
+		let server = self.server.lock().unwrap();
+		server.rotate_key(key, expires_at)?;
+		let keys = server.server_keys()?;
+		Ok(keys)
+	}
+
+	/// Implements the 'features' API
+	fn features(&self, token: String) -> jsonrpc_core::Result<FeatureFlags> {
+		self.authorize(&token)?;
+		let flags = self.server.lock().unwrap().feature_flags()?;
+		Ok(flags)
+	}
+
+	/// Implements the 'set_feature' API
+	fn set_feature(
+		&self,
+		token: String,
+		feature: String,
+		enabled: bool,
+	) -> jsonrpc_core::Result<FeatureFlags> {
+		self.authorize(&token)?;
+		let feature: Feature = feature
+			.parse()
+			.map_err(|e: FeatureError| Error::invalid_params(e.to_string()))?;
+		let flags = self.server.lock().unwrap().set_feature(feature, enabled)?;
+		Ok(flags)
+	}
+
+	/// Implements the 'reload_config' API
+	fn reload_config(&self, token: String) -> jsonrpc_core::Result<ConfigChanges> {
+		self.authorize(&token)?;
+		self.reloader.reload().map_err(|e| Error {
+			code: ErrorCode::InternalError,
+			message: e.to_string(),
+			data: None,
+		})
+	}
+
+	/// Implements the 'list_rejections' API
+	fn list_rejections(
+		&self,
+		token: String,
+		limit: Option<usize>,
+	) -> jsonrpc_core::Result<Vec<RejectionData>> {
+		self.authorize(&token)?;
+		let limit = limit.unwrap_or(DEFAULT_REJECTIONS_LISTED);
+		let rejections = self.server.lock().unwrap().list_rejections(limit)?;
+		Ok(rejections)
+	}
+}
+
+/// Error types for the admin listener and client
+#[derive(Error, Debug)]
+pub enum AdminError {
+	#[error("Admin socket error: {0:?}")]
+	IoError(std::io::Error),
+	#[error("Error encoding or decoding admin message: {0:?}")]
+	JsonError(serde_json::Error),
+	#[error("Admin request failed: {0}")]
+	RequestError(String),
+	#[error("Admin sockets are not supported on this platform")]
+	Unsupported,
+}
+
+/// Path of the file containing the admin token for the socket at 'socket_path'
+pub fn token_path(socket_path: &Path) -> PathBuf {
+	socket_path.with_extension(TOKEN_FILE_EXTENSION)
+}
+
+/// Path of the file containing the admin token when the admin API is only served over HTTP:
+/// next to the config file, named after the profile and instance
+pub fn http_token_path(
+	config_path: &Path,
+	profile: Option<&str>,
+	instance: Option<&str>,
+) -> PathBuf {
+	let name: Vec<&str> = std::iter::once("admin")
+		.chain(profile)
+		.chain(instance)
+		.collect();
+	config_path.with_file_name(format!("{}.{}", name.join("-"), TOKEN_FILE_EXTENSION))
+}
+
+/// Generates a new random token, writing it to a file readable only by the current user
+pub fn create_token_file(path: &Path) -> Result<String, AdminError> {
+	let token_bytes: [u8; 32] = thread_rng().gen();
+	let token = token_bytes.to_hex();
+
+	// Remove any existing token file, since the mode only applies to newly created files
+	let _ = std::fs::remove_file(path);
+
+	let mut options = OpenOptions::new();
+	options.write(true).create_new(true);
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::OpenOptionsExt;
+		options.mode(0o600);
+	}
+
+	let mut file = options.open(path).map_err(AdminError::IoError)?;
+	file.write_all(token.as_bytes())
+		.map_err(AdminError::IoError)?;
+	Ok(token)
+}
+
+fn admin_handler(
+	server: Arc<Mutex<dyn Server>>,
+	reloader: ConfigReloader,
+	token: String,
+) -> IoHandler {
+	let mut io = IoHandler::new();
+	let round_state = server.lock().unwrap().round_state();
+	let admin_server = AdminServer {
+		server,
+		round_state,
+		reloader,
+		token,
+	};
+	io.extend_with(admin_server.to_delegate());
+	io
+}
+
+/// Starts the admin JSON-RPC server on the unix socket (or named pipe) at 'socket_path'.
+/// 'token' must be supplied with every request.
+pub fn start_ipc(
+	socket_path: &str,
+	server: Arc<Mutex<dyn Server>>,
+	reloader: ConfigReloader,
+	token: String,
+) -> Result<jsonrpc_ipc_server::Server, AdminError> {
+	jsonrpc_ipc_server::ServerBuilder::new(admin_handler(server, reloader, token))
+		.start(socket_path)
+		.map_err(AdminError::IoError)
+}
+
+/// Starts the admin JSON-RPC server over HTTP at 'addr', on its own interface so it can be kept
+/// off the public network. Requests are sent to '/v1', with 'token' as the first param.
+pub fn start_http(
+	addr: &SocketAddr,
+	server: Arc<Mutex<dyn Server>>,
+	reloader: ConfigReloader,
+	token: String,
+) -> Result<jsonrpc_http_server::Server, AdminError> {
+	jsonrpc_http_server::ServerBuilder::new(admin_handler(server, reloader, token))
+		.cors(jsonrpc_http_server::DomainsValidation::Disabled)
+		.request_middleware(|request: hyper::Request<hyper::Body>| {
+			if request.uri() == "/v1" {
+				request.into()
+			} else {
+				jsonrpc_http_server::Response::bad_request("Only v1 supported").into()
+			}
+		})
+		.start_http(addr)
+		.map_err(AdminError::IoError)
+}
+
+/// Sends an admin request to the server listening at 'socket_path', using the token on disk.
+/// Any 'params' are sent after the token.
+#[cfg(unix)]
+pub fn send_command(
+	socket_path: &Path,
+	method: &str,
+	params: Vec<Value>,
+) -> Result<Value, AdminError> {
+	use std::io::BufReader;
+	use std::os::unix::net::UnixStream;
+
+	let token = std::fs::read_to_string(token_path(socket_path)).map_err(AdminError::IoError)?;
+	let mut all_params = vec![Value::String(token.trim().to_string())];
+	all_params.extend(params);
+	let req = json!({
+		"jsonrpc": "2.0",
+		"method": method,
+		"params": all_params,
+		"id": 1,
+	});
+
+	let mut stream = UnixStream::connect(socket_path).map_err(AdminError::IoError)?;
+	let req = serde_json::to_vec(&req).map_err(AdminError::JsonError)?;
+	stream.write_all(&req).map_err(AdminError::IoError)?;
+
+	let mut line = String::new();
+	BufReader::new(stream)
+		.read_line(&mut line)
+		.map_err(AdminError::IoError)?;
+	let response: Value = serde_json::from_str(&line).map_err(AdminError::JsonError)?;
+
+	if let Some(error) = response.get("error") {
+		let message = error
+			.get("message")
+			.and_then(|m| m.as_str())
+			.unwrap_or("unknown error");
+		return Err(AdminError::RequestError(message.to_string()));
+	}
+
+	Ok(response.get("result").cloned().unwrap_or(Value::Null))
+}
+
+/// Sends an admin request to the server listening at 'socket_path', using the token on disk.
+#[cfg(not(unix))]
+pub fn send_command(
+	_socket_path: &Path,
+	_method: &str,
+	_params: Vec<Value>,
+) -> Result<Value, AdminError> {
+	Err(AdminError::Unsupported)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{AdminAPI, AdminServer, FeeReport};
+	use crate::config::test_util::test_config;
+	use crate::node::mock::MockGrinNode;
+	use crate::reload::{ConfigReloader, ConfigSource};
+	use crate::secp;
+	use crate::secp::test_util::{rand_commit, rand_hash};
+	use crate::server::mock::MockServer;
+	use crate::server::{RoundState, Server};
+	use crate::store::FeeOutputData;
+
+	use grin_util::ToHex;
+	use std::path::PathBuf;
+	use std::sync::atomic::AtomicUsize;
+	use std::sync::{Arc, Mutex};
+
+	/// Requests with an invalid token are rejected before reaching the server
+	#[test]
+	fn admin_token() {
+		let server: Arc<Mutex<dyn Server>> = Arc::new(Mutex::new(MockServer::new()));
+		let source = ConfigSource {
+			config_path: PathBuf::from("./target/tmp/.admin_token/missing.toml"),
+			profile: None,
+			instance: None,
+			reload_requests: Arc::new(AtomicUsize::new(0)),
+		};
+		let reloader = ConfigReloader::new(
+			source,
+			test_config(),
+			server.clone(),
+			Arc::new(MockGrinNode::new()),
+		);
+		let admin = AdminServer {
+			server,
+			round_state: Arc::new(RoundState::default()),
+			reloader,
+			token: "secret".to_string(),
+		};
+
+		assert!(admin.status("wrong".to_string()).is_err());
+		assert!(admin.list_swaps("".to_string()).is_err());
+		assert!(admin.status("secret".to_string()).is_ok());
+		assert!(admin.list_swaps("secret".to_string()).unwrap().is_empty());
+		assert!(admin.list_rounds("wrong".to_string()).is_err());
+		assert!(admin.list_rounds("secret".to_string()).unwrap().is_empty());
+		let kernel_hash = "ab".repeat(32);
+		assert!(admin
+			.get_round("wrong".to_string(), kernel_hash.clone())
+			.is_err());
+		assert_eq!(
+			None,
+			admin.get_round("secret".to_string(), kernel_hash).unwrap()
+		);
+		assert!(admin
+			.get_round("secret".to_string(), "not hex".to_string())
+			.is_err());
+		assert!(admin
+			.daily_report("secret".to_string(), Some("2022-01-08".to_string()))
+			.is_ok());
+		assert!(admin
+			.daily_report("secret".to_string(), Some("01/08/2022".to_string()))
+			.is_err());
+		assert!(admin
+			.rotate_key("secret".to_string(), "not hex".to_string(), 0)
+			.is_err());
+		assert!(admin
+			.rotate_key("wrong".to_string(), secp::random_secret().0.to_hex(), 0)
+			.is_err());
+		assert!(admin
+			.rotate_key("secret".to_string(), secp::random_secret().0.to_hex(), 0)
+			.is_ok());
+		assert!(admin.features("wrong".to_string()).is_err());
+		assert!(admin.features("secret".to_string()).is_ok());
+		assert!(admin
+			.set_feature("secret".to_string(), "bogus".to_string(), false)
+			.is_err());
+		assert!(admin.reload_config("wrong".to_string()).is_err());
+		// There's no config file to reload from
+		assert!(admin.reload_config("secret".to_string()).is_err());
+	}
+
+	/// Fees are totaled per day, with rounds ordered by time
+	#[test]
+	fn fee_report() {
+		let fee_output = |amount: u64, timestamp: u64| FeeOutputData {
+			output_commit: rand_commit(),
+			amount,
+			kernel_hash: rand_hash(),
+			timestamp,
+		};
+		let day = 19_000 * 24 * 60 * 60;
+		let report = FeeReport::new(&vec![
+			fee_output(300, day + 24 * 60 * 60),
+			fee_output(100, day + 60),
+			fee_output(200, day + 120),
+		]);
+
+		assert_eq!(600, report.total);
+		assert_eq!(
+			vec![100, 200, 300],
+			report.rounds.iter().map(|r| r.amount).collect::<Vec<_>>()
+		);
+		assert_eq!(2, report.days.len());
+		assert_eq!("2022-01-08", report.days[0].date);
+		assert_eq!(2, report.days[0].rounds);
+		assert_eq!(300, report.days[0].amount);
+		assert_eq!("2022-01-09", report.days[1].date);
+		assert_eq!(300, report.days[1].amount);
+
+		assert_eq!(FeeReport::default(), FeeReport::new(&vec![]));
+	}
+}
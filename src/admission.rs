@@ -0,0 +1,181 @@
+use std::sync::{Condvar, Mutex};
+use thiserror::Error;
+
+/// How urgently an RPC request should be served when the server is busy
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Priority {
+	/// Queries from clients with existing swaps (status checks, receipts)
+	High,
+	/// New swap submissions presenting a valid API key. Their key's rate limit already bounds
+	/// them, so they're never shed, and go ahead of submissions without a key.
+	Keyed,
+	/// New swap submissions
+	Low,
+}
+
+/// Error types for request admission
+#[derive(Clone, Error, Debug, PartialEq)]
+pub enum AdmissionError {
+	#[error("Server is busy executing a round. Retry shortly.")]
+	Busy,
+}
+
+#[derive(Default)]
+struct QueueState {
+	round_in_progress: bool,
+	high_active: usize,
+	keyed_waiting: usize,
+	low_waiting: usize,
+}
+
+/// Orders RPC requests by priority, so status queries stay responsive while a round is executing.
+/// Submissions wait for the round and any high priority requests to finish first. Those without
+/// a key also wait for keyed ones, and are shed outright once too many of them are already waiting.
+pub struct AdmissionQueue {
+	state: Mutex<QueueState>,
+	changed: Condvar,
+	max_low_waiting: usize,
+}
+
+/// Held for the duration of an admitted request
+pub struct Admission<'a> {
+	queue: &'a AdmissionQueue,
+	priority: Priority,
+}
+
+impl AdmissionQueue {
+	pub fn new(max_low_waiting: usize) -> AdmissionQueue {
+		AdmissionQueue {
+			state: Mutex::new(QueueState::default()),
+			changed: Condvar::new(),
+			max_low_waiting,
+		}
+	}
+
+	/// Flags whether a round is executing. Submissions are held until it's done.
+	pub fn set_round_in_progress(&self, in_progress: bool) {
+		self.state.lock().unwrap().round_in_progress = in_progress;
+		self.changed.notify_all();
+	}
+
+	/// Waits until the request may be served, or fails if it's being shed
+	pub fn admit(&self, priority: Priority) -> Result<Admission, AdmissionError> {
+		let mut state = self.state.lock().unwrap();
+		match priority {
+			Priority::High => state.high_active += 1,
+			Priority::Keyed => {
+				let blocked = |s: &QueueState| s.round_in_progress || s.high_active > 0;
+				if blocked(&state) {
+					state.keyed_waiting += 1;
+					while blocked(&state) {
+						state = self.changed.wait(state).unwrap();
+					}
+					state.keyed_waiting -= 1;
+					self.changed.notify_all();
+				}
+			}
+			Priority::Low => {
+				let blocked = |s: &QueueState| {
+					s.round_in_progress || s.high_active > 0 || s.keyed_waiting > 0
+				};
+				if blocked(&state) {
+					if state.low_waiting >= self.max_low_waiting {
+						return Err(AdmissionError::Busy);
+					}
+					state.low_waiting += 1;
+					while blocked(&state) {
+						state = self.changed.wait(state).unwrap();
+					}
+					state.low_waiting -= 1;
+				}
+			}
+		}
+
+		Ok(Admission {
+			queue: self,
+			priority,
+		})
+	}
+}
+
+impl<'a> Drop for Admission<'a> {
+	fn drop(&mut self) {
+		if self.priority == Priority::High {
+			self.queue.state.lock().unwrap().high_active -= 1;
+			self.queue.changed.notify_all();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{AdmissionError, AdmissionQueue, Priority};
+
+	use std::sync::Arc;
+	use std::thread::{sleep, spawn};
+	use std::time::Duration;
+
+	/// Submissions wait for the round and status queries, and are shed once too many are waiting.
+	#[test]
+	fn low_priority_waits() -> Result<(), Box<dyn std::error::Error>> {
+		let queue = Arc::new(AdmissionQueue::new(1));
+		assert!(queue.admit(Priority::Low).is_ok());
+
+		queue.set_round_in_progress(true);
+		assert!(queue.admit(Priority::High).is_ok());
+
+		let waiting_queue = queue.clone();
+		let waiting = spawn(move || waiting_queue.admit(Priority::Low).map(|_| ()));
+		sleep(Duration::from_millis(100));
+		assert!(!waiting.is_finished());
+		assert_eq!(
+			Err(AdmissionError::Busy),
+			queue.admit(Priority::Low).map(|_| ())
+		);
+
+		// Still held while a status query is being served
+		let high = queue.admit(Priority::High)?;
+		queue.set_round_in_progress(false);
+		sleep(Duration::from_millis(100));
+		assert!(!waiting.is_finished());
+
+		drop(high);
+		assert_eq!(Ok(()), waiting.join().unwrap());
+
+		Ok(())
+	}
+
+	/// Keyed submissions are never shed, and are let through before submissions without a key.
+	#[test]
+	fn keyed_not_shed() -> Result<(), Box<dyn std::error::Error>> {
+		let queue = Arc::new(AdmissionQueue::new(0));
+		queue.set_round_in_progress(true);
+		assert_eq!(
+			Err(AdmissionError::Busy),
+			queue.admit(Priority::Low).map(|_| ())
+		);
+
+		let keyed: Vec<_> = (0..2)
+			.map(|_| {
+				let waiting_queue = queue.clone();
+				spawn(move || waiting_queue.admit(Priority::Keyed).map(|_| ()))
+			})
+			.collect();
+		sleep(Duration::from_millis(100));
+		assert!(keyed.iter().all(|k| !k.is_finished()));
+
+		// Only shed while keyed submissions are still waiting
+		assert_eq!(
+			Err(AdmissionError::Busy),
+			queue.admit(Priority::Low).map(|_| ())
+		);
+
+		queue.set_round_in_progress(false);
+		for k in keyed {
+			assert_eq!(Ok(()), k.join().unwrap());
+		}
+		assert!(queue.admit(Priority::Low).is_ok());
+
+		Ok(())
+	}
+}
@@ -0,0 +1,148 @@
+use crate::config::ApiKey;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use thiserror::Error;
+
+/// Header clients present their API key in
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// Error types for checking API keys
+#[derive(Clone, Error, Debug, PartialEq)]
+pub enum ApiKeyError {
+	#[error("Invalid API key")]
+	InvalidKey,
+	#[error("API key name '{0}' must be non-empty, and only contain letters, digits, '-' and '_'")]
+	InvalidName(String),
+	#[error("API key '{0}' is configured more than once")]
+	DuplicateKey(String),
+	#[error("API key '{name}' has used its {limit} swaps for this minute. Retry shortly.")]
+	RateLimited { name: String, limit: u32 },
+}
+
+/// The API keys wallets identify their swaps with, and the swaps each has submitted in the
+/// current minute
+pub struct ApiKeys {
+	keys: Vec<ApiKey>,
+	/// (minute, swaps submitted in it) by key name
+	usage: Mutex<HashMap<String, (u64, u32)>>,
+}
+
+impl ApiKeys {
+	/// Checks the configured keys. Names end up in metric labels, so they're kept to a safe set
+	/// of characters.
+	pub fn new(keys: Vec<ApiKey>) -> Result<ApiKeys, ApiKeyError> {
+		for (i, key) in keys.iter().enumerate() {
+			let valid_name = !key.name.is_empty()
+				&& key
+					.name
+					.chars()
+					.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+			if !valid_name {
+				return Err(ApiKeyError::InvalidName(key.name.clone()));
+			}
+			if keys[..i]
+				.iter()
+				.any(|k| k.name == key.name || k.key == key.key)
+			{
+				return Err(ApiKeyError::DuplicateKey(key.name.clone()));
+			}
+		}
+		Ok(ApiKeys {
+			keys,
+			usage: Mutex::new(HashMap::new()),
+		})
+	}
+
+	/// Looks up the presented key, and counts a swap submission against its limit for the minute
+	/// containing 'now' (unix time, in seconds). Returns the key's name.
+	pub fn admit(&self, presented: &str, now: u64) -> Result<String, ApiKeyError> {
+		let key = self
+			.keys
+			.iter()
+			.find(|k| {
+				ring::constant_time::verify_slices_are_equal(k.key.as_bytes(), presented.as_bytes())
+					.is_ok()
+			})
+			.ok_or(ApiKeyError::InvalidKey)?;
+
+		if let Some(limit) = key.swaps_per_minute {
+			let minute = now / 60;
+			let mut usage = self.usage.lock().unwrap();
+			let used = usage.entry(key.name.clone()).or_insert((minute, 0));
+			if used.0 != minute {
+				*used = (minute, 0);
+			}
+			if used.1 >= limit {
+				return Err(ApiKeyError::RateLimited {
+					name: key.name.clone(),
+					limit,
+				});
+			}
+			used.1 += 1;
+		}
+		Ok(key.name.clone())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{ApiKeyError, ApiKeys};
+	use crate::config::ApiKey;
+
+	fn api_key(name: &str, key: &str, swaps_per_minute: Option<u32>) -> ApiKey {
+		ApiKey {
+			name: name.to_string(),
+			key: key.to_string(),
+			swaps_per_minute,
+		}
+	}
+
+	/// Keys are matched to their name, and limited to their swaps per minute
+	#[test]
+	fn admit() -> Result<(), ApiKeyError> {
+		let keys = ApiKeys::new(vec![
+			api_key("partner", "secret", Some(2)),
+			api_key("unlimited", "other", None),
+		])?;
+
+		assert_eq!(Err(ApiKeyError::InvalidKey), keys.admit("wrong", 60));
+		assert_eq!(Ok("partner".to_string()), keys.admit("secret", 60));
+		assert_eq!(Ok("partner".to_string()), keys.admit("secret", 119));
+		assert_eq!(
+			Err(ApiKeyError::RateLimited {
+				name: "partner".to_string(),
+				limit: 2,
+			}),
+			keys.admit("secret", 119)
+		);
+		// The limit is per key, and starts over each minute
+		for _ in 0..10 {
+			assert_eq!(Ok("unlimited".to_string()), keys.admit("other", 119));
+		}
+		assert_eq!(Ok("partner".to_string()), keys.admit("secret", 120));
+
+		Ok(())
+	}
+
+	/// Names must be safe to use as metric labels, and keys can't be configured twice
+	#[test]
+	fn invalid_keys() {
+		assert_eq!(
+			Some(ApiKeyError::InvalidName("partner\"".to_string())),
+			ApiKeys::new(vec![api_key("partner\"", "secret", None)]).err()
+		);
+		assert_eq!(
+			Some(ApiKeyError::InvalidName(String::new())),
+			ApiKeys::new(vec![api_key("", "secret", None)]).err()
+		);
+		assert_eq!(
+			Some(ApiKeyError::DuplicateKey("b".to_string())),
+			ApiKeys::new(vec![
+				api_key("a", "secret", None),
+				api_key("b", "secret", None)
+			])
+			.err()
+		);
+	}
+}
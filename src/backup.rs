@@ -0,0 +1,279 @@
+use crate::secp::SecretKey;
+use crate::store::{self, StoreError, SwapStore};
+
+use grin_core::ser::{self, ProtocolVersion, Readable, Reader, Writeable, Writer};
+use hmac::{Hmac, Mac};
+use rand::{thread_rng, Rng};
+use ring::aead;
+use sha2::Sha256;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use thiserror::Error;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Identifies a file as a swap store backup
+const MAGIC: &[u8; 8] = b"MWIXBKUP";
+
+/// Version of the archive format. Bumped whenever the layout below the header changes.
+const ARCHIVE_VERSION: u8 = 1;
+
+const NONCE_LEN: usize = 12;
+
+/// Error types for backing up and restoring a swap store
+#[derive(Error, Debug)]
+pub enum BackupError {
+	#[error("Error accessing backup file: {0}")]
+	IoError(std::io::Error),
+	#[error("Not a swap store backup")]
+	NotABackup,
+	#[error("Backup archive version {0} is not supported")]
+	UnsupportedVersion(u8),
+	#[error("Backup was written by a newer server (store version {0})")]
+	NewerStore(u8),
+	#[error("Unable to encrypt backup")]
+	EncryptionError,
+	#[error("Unable to decrypt backup. Was it made with a different server key?")]
+	DecryptionError,
+	#[error("Backup contents are malformed: {0:?}")]
+	SerializationError(ser::Error),
+	#[error("Swap store is not empty. Restore into a fresh db_root.")]
+	StoreNotEmpty,
+	#[error("Swap store error: {0}")]
+	StoreError(StoreError),
+}
+
+/// Every record of a swap store, along with the version of the store that wrote them
+#[derive(Clone, Debug, PartialEq)]
+struct StoreRecords {
+	store_version: u8,
+	records: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Writeable for StoreRecords {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u8(self.store_version)?;
+		writer.write_u64(self.records.len() as u64)?;
+		for (key, value) in &self.records {
+			writer.write_bytes(key)?;
+			writer.write_bytes(value)?;
+		}
+		Ok(())
+	}
+}
+
+impl Readable for StoreRecords {
+	fn read<R: Reader>(reader: &mut R) -> Result<StoreRecords, ser::Error> {
+		let store_version = reader.read_u8()?;
+		let len = reader.read_u64()?;
+		let mut records = Vec::new();
+		for _ in 0..len {
+			let key = reader.read_bytes_len_prefix()?;
+			let value = reader.read_bytes_len_prefix()?;
+			records.push((key, value));
+		}
+		Ok(StoreRecords {
+			store_version,
+			records,
+		})
+	}
+}
+
+/// The key backups are encrypted with. It's derived from the server key, so a backup can only be
+/// restored by a server configured with the same key.
+fn backup_key(server_key: &SecretKey) -> aead::LessSafeKey {
+	let mut hmac = HmacSha256::new_from_slice(b"MWIXNET_BACKUP").unwrap();
+	hmac.update(&server_key.0);
+	let key = hmac.finalize().into_bytes();
+	let unbound_key = aead::UnboundKey::new(&aead::CHACHA20_POLY1305, &key).unwrap();
+	aead::LessSafeKey::new(unbound_key)
+}
+
+/// The unencrypted header: MAGIC || ARCHIVE_VERSION, which is also authenticated with the contents
+fn header(version: u8) -> Vec<u8> {
+	let mut header = MAGIC.to_vec();
+	header.push(version);
+	header
+}
+
+/// Encrypts every record in the store into an archive: header || nonce || sealed records
+fn seal(store: &SwapStore, server_key: &SecretKey) -> Result<(Vec<u8>, usize), BackupError> {
+	let records = StoreRecords {
+		store_version: store::CURRENT_VERSION,
+		records: store.export_records().map_err(BackupError::StoreError)?,
+	};
+	let mut contents = ser::ser_vec(&records, ProtocolVersion::local())
+		.map_err(BackupError::SerializationError)?;
+
+	let header = header(ARCHIVE_VERSION);
+	let nonce: [u8; NONCE_LEN] = thread_rng().gen();
+	backup_key(server_key)
+		.seal_in_place_append_tag(
+			aead::Nonce::assume_unique_for_key(nonce),
+			aead::Aad::from(&header),
+			&mut contents,
+		)
+		.map_err(|_| BackupError::EncryptionError)?;
+
+	let mut archive = header;
+	archive.extend_from_slice(&nonce);
+	archive.extend_from_slice(&contents);
+	Ok((archive, records.records.len()))
+}
+
+/// Checks and decrypts an archive written by 'seal'
+fn open(archive: &[u8], server_key: &SecretKey) -> Result<StoreRecords, BackupError> {
+	if archive.len() < MAGIC.len() + 1 + NONCE_LEN || &archive[..MAGIC.len()] != MAGIC {
+		return Err(BackupError::NotABackup);
+	}
+	let version = archive[MAGIC.len()];
+	if version != ARCHIVE_VERSION {
+		return Err(BackupError::UnsupportedVersion(version));
+	}
+
+	let nonce_start = MAGIC.len() + 1;
+	let mut nonce = [0u8; NONCE_LEN];
+	nonce.copy_from_slice(&archive[nonce_start..nonce_start + NONCE_LEN]);
+	let mut contents = archive[nonce_start + NONCE_LEN..].to_vec();
+	let plaintext = backup_key(server_key)
+		.open_in_place(
+			aead::Nonce::assume_unique_for_key(nonce),
+			aead::Aad::from(&header(version)),
+			&mut contents,
+		)
+		.map_err(|_| BackupError::DecryptionError)?;
+
+	let records: StoreRecords =
+		ser::deserialize_default(&mut &plaintext[..]).map_err(BackupError::SerializationError)?;
+	if records.store_version > store::CURRENT_VERSION {
+		return Err(BackupError::NewerStore(records.store_version));
+	}
+	Ok(records)
+}
+
+/// Writes every swap, round, receipt, fee output and decoy in the store to an encrypted archive
+/// at 'path', which must not already exist. Returns the number of records written.
+pub fn write_backup(
+	store: &SwapStore,
+	server_key: &SecretKey,
+	path: &Path,
+) -> Result<usize, BackupError> {
+	let (archive, count) = seal(store, server_key)?;
+
+	let mut options = OpenOptions::new();
+	options.write(true).create_new(true);
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::OpenOptionsExt;
+		options.mode(0o600);
+	}
+	let mut file = options.open(path).map_err(BackupError::IoError)?;
+	file.write_all(&archive).map_err(BackupError::IoError)?;
+	file.sync_all().map_err(BackupError::IoError)?;
+	Ok(count)
+}
+
+/// Restores the records in the archive at 'path' into an empty store.
+/// Records written by an older store are migrated as they're read, just like the store's own.
+/// Returns the number of records restored.
+pub fn restore_backup(
+	store: &SwapStore,
+	server_key: &SecretKey,
+	path: &Path,
+) -> Result<usize, BackupError> {
+	let archive = std::fs::read(path).map_err(BackupError::IoError)?;
+	let records = open(&archive, server_key)?;
+
+	if !store.is_empty().map_err(BackupError::StoreError)? {
+		return Err(BackupError::StoreNotEmpty);
+	}
+	store
+		.import_records(&records.records)
+		.map_err(BackupError::StoreError)?;
+	Ok(records.records.len())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{restore_backup, write_backup, BackupError};
+	use crate::secp;
+	use crate::store::test_util::TestStore;
+	use crate::store::{FeeOutputData, RoundData};
+
+	use grin_core::core::hash::Hash;
+	use std::path::PathBuf;
+
+	/// A backup restores every record into a fresh store, but only with the same server key,
+	/// and never over an existing store
+	#[test]
+	fn backup_and_restore() -> Result<(), Box<dyn std::error::Error>> {
+		let dir = PathBuf::from("./target/tmp/.backup_and_restore");
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir)?;
+		let path = dir.join("swaps.bak");
+
+		let server_key = secp::random_secret();
+		let store = TestStore::new("backup_and_restore_from");
+		let round = RoundData {
+			round_id: 7,
+			kernel_hash: Hash::from_vec(&[1; 32]),
+			timestamp: 1_700_000_000,
+			fees: vec![100, 200],
+			anonymity_set: 2,
+			tx_weight: 42,
+			confirmed_height: None,
+		};
+		store.save_round(&round)?;
+		let fee_output = FeeOutputData {
+			output_commit: secp::test_util::rand_commit(),
+			amount: 300,
+			kernel_hash: round.kernel_hash,
+			timestamp: round.timestamp,
+		};
+		store.save_fee_output(&fee_output)?;
+		store.save_last_round_id(7)?;
+
+		assert_eq!(3, write_backup(&store, &server_key, &path)?);
+		assert!(matches!(
+			write_backup(&store, &server_key, &path),
+			Err(BackupError::IoError(_))
+		));
+
+		let restored = TestStore::new("backup_and_restore_to");
+		assert!(matches!(
+			restore_backup(&restored, &secp::random_secret(), &path),
+			Err(BackupError::DecryptionError)
+		));
+		assert!(matches!(
+			restore_backup(&store, &server_key, &path),
+			Err(BackupError::StoreNotEmpty)
+		));
+
+		assert_eq!(3, restore_backup(&restored, &server_key, &path)?);
+		assert_eq!(Some(round.clone()), restored.get_round(&round.kernel_hash)?);
+		assert_eq!(
+			vec![fee_output],
+			restored.fee_outputs_iter()?.collect::<Vec<_>>()
+		);
+		assert_eq!(7, restored.last_round_id()?);
+
+		// Tampering with the header or contents is detected
+		let mut archive = std::fs::read(&path)?;
+		let last = archive.len() - 1;
+		archive[last] ^= 1;
+		std::fs::write(&path, &archive)?;
+		let fresh = TestStore::new("backup_and_restore_tampered");
+		assert!(matches!(
+			restore_backup(&fresh, &server_key, &path),
+			Err(BackupError::DecryptionError)
+		));
+		std::fs::write(&path, b"not a backup")?;
+		assert!(matches!(
+			restore_backup(&fresh, &server_key, &path),
+			Err(BackupError::NotABackup)
+		));
+
+		Ok(())
+	}
+}
@@ -0,0 +1,58 @@
+use std::io::{self, Read};
+
+/// Content-Encoding for request bodies compressed with zstd
+pub const ZSTD_ENCODING: &str = "zstd";
+
+/// Compression level used for everything the server compresses. Onions are mostly ciphertext and
+/// rangeproofs, so higher levels gain little.
+const ZSTD_LEVEL: i32 = 3;
+
+/// Every zstd frame starts with these bytes. No record the store writes uncompressed does,
+/// since they start with a small version number.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Compresses 'data' into a single zstd frame
+pub fn compress(data: &[u8]) -> io::Result<Vec<u8>> {
+	zstd::stream::encode_all(data, ZSTD_LEVEL)
+}
+
+/// Decompresses the zstd frame in 'data', failing if it holds more than 'max_size' bytes,
+/// so a small request can't expand into an arbitrarily large one
+pub fn decompress(data: &[u8], max_size: usize) -> io::Result<Vec<u8>> {
+	let mut decompressed = Vec::new();
+	zstd::stream::read::Decoder::new(data)?
+		.take(max_size as u64 + 1)
+		.read_to_end(&mut decompressed)?;
+	if decompressed.len() > max_size {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			format!("Decompressed data is larger than {} bytes", max_size),
+		));
+	}
+	Ok(decompressed)
+}
+
+/// Whether 'data' is a zstd frame
+pub fn is_compressed(data: &[u8]) -> bool {
+	data.starts_with(&ZSTD_MAGIC)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{compress, decompress, is_compressed};
+
+	/// Data survives a round trip, but not past the size limit
+	#[test]
+	fn compress_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+		let data = vec![7u8; 10_000];
+		let compressed = compress(&data)?;
+		assert!(compressed.len() < data.len());
+		assert!(is_compressed(&compressed));
+		assert!(!is_compressed(&data));
+
+		assert_eq!(data, decompress(&compressed, data.len())?);
+		assert!(decompress(&compressed, data.len() - 1).is_err());
+		assert!(decompress(&data, data.len()).is_err());
+		Ok(())
+	}
+}
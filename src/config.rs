@@ -1,3 +1,7 @@
+use crate::http::{HttpClient, RetryPolicy, DEFAULT_REQUEST_TIMEOUT};
+use crate::interval::{ChainInterval, DEFAULT_BLOCK_TIME_S};
+use crate::leader::RoundPeer;
+use crate::onion::CipherSuite;
 use crate::secp::SecretKey;
 
 use core::num::NonZeroU32;
@@ -6,11 +10,13 @@ use grin_util::{file, ToHex, ZeroingString};
 use rand::{thread_rng, Rng};
 use ring::{aead, pbkdf2};
 use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::prelude::*;
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
 use std::path::PathBuf;
 use std::result::Result;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 
 const GRIN_HOME: &str = ".grin";
@@ -22,10 +28,11 @@ const WALLET_OWNER_API_SECRET_FILE_NAME: &str = ".owner_api_secret";
 pub struct ServerConfig {
 	/// private key used by the server to decrypt onion packets
 	pub key: SecretKey,
-	/// interval (in seconds) to wait before each mixing round
-	pub interval_s: u32,
-	/// socket address the server listener should bind to
-	pub addr: SocketAddr,
+	/// time between mixing rounds. when counted in blocks, rounds are scheduled on block height
+	/// boundaries of the chain tip instead of by the clock
+	pub round_interval: ChainInterval,
+	/// socket address, or unix socket path, the server listener should bind to
+	pub addr: ListenAddr,
 	/// foreign api address of the grin node
 	pub grin_node_url: SocketAddr,
 	/// path to file containing api secret for the grin node
@@ -34,9 +41,358 @@ pub struct ServerConfig {
 	pub wallet_owner_url: SocketAddr,
 	/// path to file containing secret for the grin wallet's owner api
 	pub wallet_owner_secret_path: Option<String>,
+	/// path of the unix socket to listen on for admin commands. the admin api is disabled when None
+	pub admin_socket_path: Option<String>,
+	/// the wallet implementation used to build the server's fee outputs
+	pub wallet_backend: WalletBackend,
+	/// time between signed commitments to the pending swaps. disabled when None
+	pub pending_commitment_interval: Option<ChainInterval>,
+	/// how long the node's chain tip may go without advancing before the node is considered stalled
+	/// and rounds are paused. disabled when None
+	pub tip_stall_timeout: Option<ChainInterval>,
+	/// directory to write a summary report to at the end of each (UTC) day. disabled when None
+	pub report_dir: Option<String>,
+	/// percentage by which each round interval is randomly lengthened or shortened (e.g. 10 for ±10%)
+	pub round_jitter_percent: Option<u8>,
+	/// once a round is due, it executes after a random delay of up to this long
+	pub round_delay_window: Option<ChainInterval>,
+	/// post round transactions through the node's dandelion stem relay,
+	/// instead of broadcasting (fluffing) them immediately
+	pub dandelion_stem: bool,
+	/// replication listener of the standby that accepted swaps are sent to
+	pub standby_addr: Option<SocketAddr>,
+	/// listen for swaps replicated from a primary on this address, running as its standby
+	pub replication_addr: Option<SocketAddr>,
+	/// how long a primary may go without hearing from its standby before it stops executing rounds
+	pub replication_lease: Option<ChainInterval>,
+	/// key used before the last rotation, still accepted for onions until it expires
+	pub previous_key: Option<PreviousKey>,
+	/// seed the 'seed' wallet backend derives fee outputs from, kept encrypted like the server key
+	pub fee_seed: Option<SecretKey>,
+	/// secret the pending commitment's salts and the replication channel's keys are derived from,
+	/// so rotating the server key doesn't change them. The server key is used when unset.
+	pub state_key: Option<SecretKey>,
+	/// Argon2id parameters the keys are encrypted with, or None for a config written before they
+	/// were (whose keys are re-encrypted with the defaults when it's next saved)
+	pub kdf: Option<KdfParams>,
+	/// reject swaps that arrive after the acceptance window for the upcoming round has closed,
+	/// while the round waits out its random delay
+	pub acceptance_windows: bool,
+	/// block explorer link to a round's kernel, with '{excess}' replaced by the hex-encoded kernel excess
+	pub explorer_kernel_url: Option<String>,
+	/// block explorer link to the block a round confirmed in, with '{height}' replaced by its height
+	pub explorer_block_url: Option<String>,
+	/// how long an accepted swap waits to be included in a round before it expires and is deleted.
+	/// defaults to a day's worth of blocks
+	pub swap_ttl: Option<ChainInterval>,
+	/// canonical URL clients reach the server at (e.g. 'https://mix.example.com/v1' or an .onion address),
+	/// when it differs from 'addr' because the server is behind NAT or a proxy
+	pub public_url: Option<String>,
+	/// minimum level of log messages to output ('trace', 'debug', 'info', 'warn' or 'error'),
+	/// or a filter directive such as 'info,mwixnet::server=debug'. defaults to 'info'
+	pub log_level: Option<String>,
+	/// directory to also write logs to, in files rotated daily
+	pub log_dir: Option<String>,
+	/// accept swaps spending outputs that are still in the node's mempool, holding at most this many
+	/// at once. such swaps join a round once their input confirms. disabled when unset
+	pub zero_conf_max_pending: Option<u32>,
+	/// (experimental) websocket urls of Nostr relays to listen on for armored swap submissions
+	/// addressed to the server's key, in addition to the JSON-RPC API
+	pub nostr_relays: Option<Vec<String>>,
+	/// directory the swap database is kept in. defaults to the 'db' directory in the grin home dir,
+	/// and an existing database there is moved when this is changed
+	pub db_root: Option<String>,
+	/// most swaps to include in a round. when more are waiting, 'round_selection' picks which
+	/// are included, and the rest wait for a later round. unlimited when unset
+	pub max_swaps_per_round: Option<u32>,
+	/// how swaps are picked for a round when more than 'max_swaps_per_round' are waiting
+	pub round_selection: RoundSelection,
+	/// pad rounds with fewer swaps than this with decoy self-swaps of the server's own outputs.
+	/// the outputs collecting the server's fees fund the decoys. disabled when unset
+	pub min_anonymity_set: Option<u32>,
+	/// SOCKS5 proxy (e.g. 'socks5://127.0.0.1:9050' for Tor) that requests to the node, wallet,
+	/// standby and other servers are sent through. host names are resolved by the proxy
+	pub proxy: Option<String>,
+	/// serve swap and round events to WebSocket subscribers on this address
+	pub events_addr: Option<SocketAddr>,
+	/// database the swap store is kept in
+	pub store_backend: StoreBackend,
+	/// reject swaps unless the client proves (with a rangeproof over the output commitment
+	/// minus this many nanogrin) that their output is worth at least this much. disabled when unset
+	pub min_output_value: Option<u64>,
+	/// serve Prometheus metrics on this address, at /metrics
+	pub metrics_addr: Option<SocketAddr>,
+	/// URLs of other mix nodes' APIs to ping, whose availability is published in get_info
+	pub peers: Option<Vec<String>>,
+	/// also serve the admin API over HTTP on this address, which should only be reachable by the operator
+	pub admin_addr: Option<SocketAddr>,
+	/// sign round transactions with NoRecentDuplicate kernels, locked for this many blocks against
+	/// duplicates of their excess, rather than plain kernels. requires NRD kernels enabled on chain
+	pub nrd_relative_height: Option<u16>,
+	/// zstd-compress swaps once they've completed or failed, when they're only kept as history
+	pub compress_archived_swaps: bool,
+	/// onion cipher suites swaps may be encrypted with. every suite this version supports when unset,
+	/// so a suite can be phased out by leaving it off once clients have moved to a newer one
+	pub accepted_cipher_suites: Option<Vec<CipherSuite>>,
+	/// hex-encoded public keys of the mix nodes allowed to make requests at /v1/peer
+	pub peer_pubkeys: Option<Vec<String>>,
+	/// other mix nodes rounds are coordinated with. each round's transactions are aggregated and
+	/// posted by one leader, picked in turn from these nodes and this one
+	pub round_peers: Option<Vec<RoundPeer>>,
+	/// stop taking swaps for a round this long before it's scheduled. swaps arriving later are
+	/// accepted for the round after it
+	pub intake_cutoff: Option<ChainInterval>,
+	/// how many of the most recently rejected swaps to keep in the rejection log (1000 when unset).
+	/// 0 turns the log off
+	pub rejection_log_size: Option<u64>,
+	/// how long to wait on the node or wallet (or another server) before giving up on a request.
+	/// defaults to 60 seconds
+	pub http_timeout: Option<ChainInterval>,
+	/// times a request to the node or wallet is retried after failing to get a response,
+	/// with exponential backoff. defaults to 2
+	pub http_retries: Option<u32>,
+	/// time to wait before the first retry of a request, doubled for each one after it.
+	/// defaults to 1 second
+	pub http_backoff: Option<ChainInterval>,
+	/// file of hex-encoded commitments, one per line, whose swaps are refused (as inputs or outputs).
+	/// edits to the file are picked up without a restart
+	pub deny_list_path: Option<String>,
+	/// confirmations a swap's input must have, both when the swap is submitted and when it's included
+	/// in a round. swaps of unconfirmed inputs (see 'zero_conf_max_pending') wait until they have
+	/// this many. any confirmed input is accepted when unset
+	pub min_input_confirmations: Option<u64>,
+	/// keys partner wallets present in the 'X-Api-Key' header, for priority access to the API
+	/// within their own rate limits. swaps submitted with each key are counted in metrics
+	pub api_keys: Option<Vec<ApiKey>>,
+	/// addresses of the reverse proxies in front of the API, whose 'X-Forwarded-For' and
+	/// 'X-Real-IP' headers are trusted to say where a request came from. requests from anyone else
+	/// are taken to come from the address they arrived from
+	pub trusted_proxies: Option<Vec<IpAddr>>,
+}
+
+/// An API key given to a partner wallet
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ApiKey {
+	/// name the key's swaps are counted under, e.g. the partner's name
+	pub name: String,
+	/// the secret the partner presents
+	pub key: String,
+	/// the most swaps the key may submit each minute. unlimited when unset
+	pub swaps_per_minute: Option<u32>,
+}
+
+/// A server key that has been rotated out, but is still accepted during a grace window
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct PreviousKey {
+	pub key: SecretKey,
+	/// unix time (in seconds) after which onions encrypted to this key are rejected
+	pub expires_at: u64,
+}
+
+/// The supported wallet implementations
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WalletBackend {
+	/// a running grin-wallet, accessed through its owner api
+	OwnerApi,
+	/// keys derived locally from the server key, so no grin-wallet is needed
+	Embedded,
+	/// keys derived locally from a seed of the server's own ('fee_seed'), under the id of the
+	/// round each output is built in. the server key can still be rotated
+	Seed,
+}
+
+impl Default for WalletBackend {
+	fn default() -> Self {
+		WalletBackend::OwnerApi
+	}
+}
+
+impl std::str::FromStr for WalletBackend {
+	type Err = ConfigError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"owner_api" => Ok(WalletBackend::OwnerApi),
+			"embedded" => Ok(WalletBackend::Embedded),
+			"seed" => Ok(WalletBackend::Seed),
+			_ => Err(ConfigError::InvalidValue("wallet_backend".to_string())),
+		}
+	}
+}
+
+/// The supported databases for the swap store
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StoreBackend {
+	/// LMDB, through grin_store
+	Lmdb,
+	/// sled, which needs no map size tuning
+	Sled,
+}
+
+impl Default for StoreBackend {
+	fn default() -> Self {
+		StoreBackend::Lmdb
+	}
+}
+
+impl std::str::FromStr for StoreBackend {
+	type Err = ConfigError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"lmdb" => Ok(StoreBackend::Lmdb),
+			"sled" => Ok(StoreBackend::Sled),
+			_ => Err(ConfigError::InvalidValue("store_backend".to_string())),
+		}
+	}
+}
+
+/// How swaps are picked for a round when more are waiting than 'max_swaps_per_round' allows
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundSelection {
+	/// the swaps accepted earliest, so a flood of new submissions can't hold back earlier ones
+	Fifo,
+	/// the swaps paying the highest fees, falling back to the earliest accepted between equal fees
+	FeePriority,
+}
+
+impl Default for RoundSelection {
+	fn default() -> Self {
+		RoundSelection::Fifo
+	}
+}
+
+/// Where the server's listener binds: a socket address, or a unix socket for single-host
+/// deployments, written as a path ('/run/mwixnet.sock' or 'unix:mwixnet.sock')
+#[derive(Clone, Debug, PartialEq)]
+pub enum ListenAddr {
+	Tcp(SocketAddr),
+	Unix(PathBuf),
+}
+
+impl std::str::FromStr for ListenAddr {
+	type Err = AddrError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.strip_prefix("unix:") {
+			Some(path) => Ok(ListenAddr::Unix(PathBuf::from(path))),
+			None if s.starts_with('/') => Ok(ListenAddr::Unix(PathBuf::from(s))),
+			None => parse_addr(s).map(ListenAddr::Tcp),
+		}
+	}
+}
+
+/// Why an address couldn't be parsed, worded so the operator can fix it
+#[derive(Clone, Error, Debug, PartialEq)]
+pub enum AddrError {
+	#[error("'{0}' has no port: expected an IP address and port, such as \"127.0.0.1:3000\"")]
+	MissingPort(String),
+	#[error(
+		"'{0}' looks like an IPv6 address without brackets: write it in brackets, followed by the port, such as \"[::1]:3000\""
+	)]
+	UnbracketedIpv6(String),
+	#[error("'{addr}' has an invalid port '{port}': expected a number from 0 to 65535")]
+	InvalidPort { addr: String, port: String },
+	#[error(
+		"'{addr}' doesn't start with an IP address: '{host}' isn't one, and host names aren't resolved"
+	)]
+	NotAnIp { addr: String, host: String },
+	#[error("'{addr}' is a URL, but an address is expected: use \"{suggested}\"")]
+	Url { addr: String, suggested: String },
+	#[error(
+		"'{0}' isn't a valid address: expected an IP address and port, such as \"127.0.0.1:3000\" or \"[::]:3000\""
+	)]
+	Invalid(String),
+}
+
+/// Parses an IPv4 or IPv6 socket address ('127.0.0.1:3000', '[::1]:3000'), explaining what's
+/// wrong with malformed ones
+pub fn parse_addr(s: &str) -> Result<SocketAddr, AddrError> {
+	if let Ok(addr) = s.parse::<SocketAddr>() {
+		return Ok(addr);
+	}
+	if let Some(rest) = s
+		.strip_prefix("http://")
+		.or_else(|| s.strip_prefix("https://"))
+	{
+		return Err(AddrError::Url {
+			addr: s.to_string(),
+			suggested: rest.split('/').next().unwrap_or(rest).to_string(),
+		});
+	}
+
+	let (host, port) = match s.strip_prefix('[') {
+		Some(bracketed) => match bracketed.split_once(']') {
+			Some((host, "")) if host.parse::<Ipv6Addr>().is_ok() => {
+				return Err(AddrError::MissingPort(s.to_string()))
+			}
+			Some((host, rest)) => match rest.strip_prefix(':') {
+				Some(port) => (host, port),
+				None => return Err(AddrError::Invalid(s.to_string())),
+			},
+			None => return Err(AddrError::Invalid(s.to_string())),
+		},
+		// An IPv6 address with its port appended is itself often a valid IPv6 address
+		None if s.matches(':').count() > 1 => {
+			return Err(AddrError::UnbracketedIpv6(s.to_string()))
+		}
+		None => match s.rsplit_once(':') {
+			Some(host_port) => host_port,
+			None if s.parse::<IpAddr>().is_ok() => {
+				return Err(AddrError::MissingPort(s.to_string()))
+			}
+			None => return Err(AddrError::Invalid(s.to_string())),
+		},
+	};
+	if port.parse::<u16>().is_err() {
+		return Err(AddrError::InvalidPort {
+			addr: s.to_string(),
+			port: port.to_string(),
+		});
+	}
+	Err(AddrError::NotAnIp {
+		addr: s.to_string(),
+		host: host.to_string(),
+	})
+}
+
+impl std::fmt::Display for ListenAddr {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ListenAddr::Tcp(addr) => write!(f, "{}", addr),
+			ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+		}
+	}
+}
+
+impl From<SocketAddr> for ListenAddr {
+	fn from(addr: SocketAddr) -> ListenAddr {
+		ListenAddr::Tcp(addr)
+	}
+}
+
+impl serde::Serialize for ListenAddr {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_str(&self.to_string())
+	}
+}
+
+impl<'de> serde::Deserialize<'de> for ListenAddr {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+		s.parse().map_err(serde::de::Error::custom)
+	}
 }
 
 impl ServerConfig {
+	/// The secret state that must outlive key rotation is derived from
+	pub fn state_key(&self) -> &SecretKey {
+		self.state_key.as_ref().unwrap_or(&self.key)
+	}
+
 	pub fn node_api_secret(&self) -> Option<String> {
 		file::get_first_line(self.grin_node_secret_path.clone())
 	}
@@ -44,6 +400,253 @@ impl ServerConfig {
 	pub fn wallet_owner_api_secret(&self) -> Option<String> {
 		file::get_first_line(self.wallet_owner_secret_path.clone())
 	}
+
+	/// Client for outbound requests, through the configured proxy if any, with the configured
+	/// timeout and retries
+	pub fn http_client(&self) -> HttpClient {
+		let secs =
+			|interval: ChainInterval| Duration::from_secs(interval.as_secs(DEFAULT_BLOCK_TIME_S));
+		let mut retry = RetryPolicy::default();
+		if let Some(retries) = self.http_retries {
+			retry.retries = retries;
+		}
+		if let Some(backoff) = self.http_backoff {
+			retry.backoff = secs(backoff);
+		}
+		HttpClient::new(self.proxy.as_deref())
+			.with_timeout(
+				self.http_timeout
+					.map(secs)
+					.unwrap_or(DEFAULT_REQUEST_TIMEOUT),
+			)
+			.with_retry(retry)
+	}
+
+	/// The URL advertised to clients: 'public_url' if configured, otherwise derived from 'addr'
+	pub fn advertised_url(&self) -> String {
+		match &self.public_url {
+			Some(public_url) => public_url.clone(),
+			None => match &self.addr {
+				ListenAddr::Tcp(addr) => format!("http://{}/v1", addr),
+				ListenAddr::Unix(path) => format!(
+					"http+unix://{}/v1",
+					path.to_string_lossy()
+						.replace('%', "%25")
+						.replace('/', "%2F")
+				),
+			},
+		}
+	}
+
+	/// Takes the settings that can be changed while the server runs from 'reloaded', keeping
+	/// the current keys and any settings that only take effect on restart.
+	/// Returns the resulting config, along with which settings changed.
+	pub fn reload(&self, reloaded: ServerConfig) -> (ServerConfig, ConfigChanges) {
+		let applied = [
+			(
+				"round_interval",
+				self.round_interval != reloaded.round_interval,
+			),
+			(
+				"grin_node_url",
+				self.grin_node_url != reloaded.grin_node_url,
+			),
+			(
+				"grin_node_secret_path",
+				self.grin_node_secret_path != reloaded.grin_node_secret_path,
+			),
+			(
+				"pending_commitment_interval",
+				self.pending_commitment_interval != reloaded.pending_commitment_interval,
+			),
+			(
+				"tip_stall_timeout",
+				self.tip_stall_timeout != reloaded.tip_stall_timeout,
+			),
+			("report_dir", self.report_dir != reloaded.report_dir),
+			(
+				"round_jitter_percent",
+				self.round_jitter_percent != reloaded.round_jitter_percent,
+			),
+			(
+				"round_delay_window",
+				self.round_delay_window != reloaded.round_delay_window,
+			),
+			(
+				"dandelion_stem",
+				self.dandelion_stem != reloaded.dandelion_stem,
+			),
+			(
+				"acceptance_windows",
+				self.acceptance_windows != reloaded.acceptance_windows,
+			),
+			(
+				"explorer_kernel_url",
+				self.explorer_kernel_url != reloaded.explorer_kernel_url,
+			),
+			(
+				"explorer_block_url",
+				self.explorer_block_url != reloaded.explorer_block_url,
+			),
+			("swap_ttl", self.swap_ttl != reloaded.swap_ttl),
+			("public_url", self.public_url != reloaded.public_url),
+			(
+				"zero_conf_max_pending",
+				self.zero_conf_max_pending != reloaded.zero_conf_max_pending,
+			),
+			(
+				"max_swaps_per_round",
+				self.max_swaps_per_round != reloaded.max_swaps_per_round,
+			),
+			(
+				"round_selection",
+				self.round_selection != reloaded.round_selection,
+			),
+			(
+				"min_anonymity_set",
+				self.min_anonymity_set != reloaded.min_anonymity_set,
+			),
+			(
+				"min_output_value",
+				self.min_output_value != reloaded.min_output_value,
+			),
+			("peers", self.peers != reloaded.peers),
+			(
+				"nrd_relative_height",
+				self.nrd_relative_height != reloaded.nrd_relative_height,
+			),
+			(
+				"accepted_cipher_suites",
+				self.accepted_cipher_suites != reloaded.accepted_cipher_suites,
+			),
+			(
+				"intake_cutoff",
+				self.intake_cutoff != reloaded.intake_cutoff,
+			),
+			(
+				"rejection_log_size",
+				self.rejection_log_size != reloaded.rejection_log_size,
+			),
+			(
+				"min_input_confirmations",
+				self.min_input_confirmations != reloaded.min_input_confirmations,
+			),
+		];
+		let restart_required = [
+			("addr", self.addr != reloaded.addr),
+			(
+				"wallet_owner_url",
+				self.wallet_owner_url != reloaded.wallet_owner_url,
+			),
+			(
+				"wallet_owner_secret_path",
+				self.wallet_owner_secret_path != reloaded.wallet_owner_secret_path,
+			),
+			(
+				"admin_socket_path",
+				self.admin_socket_path != reloaded.admin_socket_path,
+			),
+			(
+				"wallet_backend",
+				self.wallet_backend != reloaded.wallet_backend,
+			),
+			("standby_addr", self.standby_addr != reloaded.standby_addr),
+			(
+				"replication_addr",
+				self.replication_addr != reloaded.replication_addr,
+			),
+			(
+				"replication_lease",
+				self.replication_lease != reloaded.replication_lease,
+			),
+			("log_level", self.log_level != reloaded.log_level),
+			("log_dir", self.log_dir != reloaded.log_dir),
+			("nostr_relays", self.nostr_relays != reloaded.nostr_relays),
+			("db_root", self.db_root != reloaded.db_root),
+			("proxy", self.proxy != reloaded.proxy),
+			("events_addr", self.events_addr != reloaded.events_addr),
+			(
+				"store_backend",
+				self.store_backend != reloaded.store_backend,
+			),
+			("metrics_addr", self.metrics_addr != reloaded.metrics_addr),
+			("admin_addr", self.admin_addr != reloaded.admin_addr),
+			(
+				"compress_archived_swaps",
+				self.compress_archived_swaps != reloaded.compress_archived_swaps,
+			),
+			("peer_pubkeys", self.peer_pubkeys != reloaded.peer_pubkeys),
+			("round_peers", self.round_peers != reloaded.round_peers),
+			("api_keys", self.api_keys != reloaded.api_keys),
+			(
+				"trusted_proxies",
+				self.trusted_proxies != reloaded.trusted_proxies,
+			),
+			("http_timeout", self.http_timeout != reloaded.http_timeout),
+			("http_retries", self.http_retries != reloaded.http_retries),
+			("http_backoff", self.http_backoff != reloaded.http_backoff),
+			(
+				"deny_list_path",
+				self.deny_list_path != reloaded.deny_list_path,
+			),
+		];
+		let changed = |settings: &[(&str, bool)]| -> Vec<String> {
+			settings
+				.iter()
+				.filter(|(_, changed)| *changed)
+				.map(|(name, _)| name.to_string())
+				.collect()
+		};
+		let changes = ConfigChanges {
+			applied: changed(&applied[..]),
+			restart_required: changed(&restart_required[..]),
+		};
+
+		let server_config = ServerConfig {
+			key: self.key.clone(),
+			addr: self.addr.clone(),
+			wallet_owner_url: self.wallet_owner_url,
+			wallet_owner_secret_path: self.wallet_owner_secret_path.clone(),
+			admin_socket_path: self.admin_socket_path.clone(),
+			wallet_backend: self.wallet_backend,
+			standby_addr: self.standby_addr,
+			replication_addr: self.replication_addr,
+			replication_lease: self.replication_lease,
+			previous_key: self.previous_key.clone(),
+			fee_seed: self.fee_seed.clone(),
+			state_key: self.state_key.clone(),
+			kdf: self.kdf,
+			log_level: self.log_level.clone(),
+			log_dir: self.log_dir.clone(),
+			nostr_relays: self.nostr_relays.clone(),
+			db_root: self.db_root.clone(),
+			proxy: self.proxy.clone(),
+			events_addr: self.events_addr,
+			store_backend: self.store_backend,
+			metrics_addr: self.metrics_addr,
+			admin_addr: self.admin_addr,
+			compress_archived_swaps: self.compress_archived_swaps,
+			peer_pubkeys: self.peer_pubkeys.clone(),
+			round_peers: self.round_peers.clone(),
+			api_keys: self.api_keys.clone(),
+			trusted_proxies: self.trusted_proxies.clone(),
+			http_timeout: self.http_timeout,
+			http_retries: self.http_retries,
+			http_backoff: self.http_backoff,
+			deny_list_path: self.deny_list_path.clone(),
+			..reloaded
+		};
+		(server_config, changes)
+	}
+}
+
+/// The settings changed by reloading a running server's config
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct ConfigChanges {
+	/// settings now in effect
+	pub applied: Vec<String>,
+	/// settings that changed in the file, but are ignored until the server restarts
+	pub restart_required: Vec<String>,
 }
 
 /// Error types for saving or loading configs
@@ -61,10 +664,71 @@ pub enum ConfigError {
 	DecryptionError(ring::error::Unspecified),
 	#[error("Decrypted server key is invalid")]
 	InvalidServerKey,
+	#[error("Invalid value for {0}")]
+	InvalidValue(String),
+	#[error("Invalid key derivation parameters: {0}")]
+	KdfError(argon2::Error),
 	#[error(
 		"Unable to read server config. Perform init-config or pass in config path.\nError: {0:?}"
 	)]
 	ReadConfigError(std::io::Error),
+	#[error("Invalid config:\n{}", .0.iter().map(|i| i.to_string()).collect::<Vec<_>>().join("\n"))]
+	InvalidConfig(Vec<ConfigIssue>),
+}
+
+/// Parameters of the Argon2id key derivation the keys in a config are encrypted with. They're
+/// saved alongside the encrypted keys, so they can be raised for new configs without breaking
+/// older ones.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct KdfParams {
+	/// memory used, in KiB
+	pub memory_kib: u32,
+	/// passes over that memory
+	pub iterations: u32,
+	/// lanes the memory is split into
+	pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+	fn default() -> Self {
+		KdfParams {
+			memory_kib: 64 * 1024,
+			iterations: 3,
+			parallelism: 1,
+		}
+	}
+}
+
+/// Derives the key encrypting a config's keys from the password. Keys encrypted before Argon2id
+/// was used ('kdf' of None) were derived with 100 rounds of PBKDF2.
+fn derive_key(
+	password: &[u8],
+	salt: &[u8],
+	kdf: Option<&KdfParams>,
+) -> Result<[u8; 32], ConfigError> {
+	let mut key = [0; 32];
+	match kdf {
+		Some(kdf) => {
+			let params = argon2::Params::new(
+				kdf.memory_kib,
+				kdf.iterations,
+				kdf.parallelism,
+				Some(key.len()),
+			)
+			.map_err(|e| ConfigError::KdfError(e))?;
+			argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+				.hash_password_into(password, salt, &mut key)
+				.map_err(|e| ConfigError::KdfError(e))?;
+		}
+		None => pbkdf2::derive(
+			pbkdf2::PBKDF2_HMAC_SHA512,
+			NonZeroU32::new(100).unwrap(),
+			salt,
+			password,
+			&mut key,
+		),
+	}
+	Ok(key)
 }
 
 /// Encrypted server key, for storing on disk and decrypting with a password.
@@ -77,20 +741,16 @@ struct EncryptedServerKey {
 }
 
 impl EncryptedServerKey {
-	/// Generates a random salt for pbkdf2 key derivation and a random nonce for aead sealing.
+	/// Generates a random salt for key derivation and a random nonce for aead sealing.
 	/// Then derives an encryption key from the password and salt. Finally, it encrypts and seals
 	/// the server key with chacha20-poly1305 using the derived key and random nonce.
-	pub fn from_secret_key(server_key: &SecretKey, password: &ZeroingString) -> EncryptedServerKey {
-		let salt: [u8; 8] = thread_rng().gen();
-		let password = password.as_bytes();
-		let mut key = [0; 32];
-		pbkdf2::derive(
-			pbkdf2::PBKDF2_HMAC_SHA512,
-			NonZeroU32::new(100).unwrap(),
-			&salt,
-			password,
-			&mut key,
-		);
+	pub fn from_secret_key(
+		server_key: &SecretKey,
+		password: &ZeroingString,
+		kdf: Option<&KdfParams>,
+	) -> Result<EncryptedServerKey, ConfigError> {
+		let salt: [u8; 16] = thread_rng().gen();
+		let key = derive_key(password.as_bytes(), &salt, kdf)?;
 		let content = server_key.0.to_vec();
 		let mut enc_bytes = content;
 
@@ -106,30 +766,27 @@ impl EncryptedServerKey {
 			)
 			.unwrap();
 
-		EncryptedServerKey {
+		Ok(EncryptedServerKey {
 			encrypted_key: enc_bytes.to_hex(),
 			salt: salt.to_hex(),
 			nonce: nonce.to_hex(),
-		}
+		})
 	}
 
-	/// Decrypt the server secret key using the provided password.
-	pub fn decrypt(&self, password: &str) -> Result<SecretKey, ConfigError> {
+	/// Decrypt the server secret key using the provided password, and the parameters of the key
+	/// derivation it was encrypted with.
+	pub fn decrypt(
+		&self,
+		password: &str,
+		kdf: Option<&KdfParams>,
+	) -> Result<SecretKey, ConfigError> {
 		let mut encrypted_seed = grin_util::from_hex(&self.encrypted_key.clone())
 			.map_err(|_| ConfigError::InvalidHex("Seed".to_string()))?;
 		let salt = grin_util::from_hex(&self.salt.clone())
 			.map_err(|_| ConfigError::InvalidHex("Salt".to_string()))?;
 		let nonce = grin_util::from_hex(&self.nonce.clone())
 			.map_err(|_| ConfigError::InvalidHex("Nonce".to_string()))?;
-		let password = password.as_bytes();
-		let mut key = [0; 32];
-		pbkdf2::derive(
-			pbkdf2::PBKDF2_HMAC_SHA512,
-			NonZeroU32::new(100).unwrap(),
-			&salt,
-			password,
-			&mut key,
-		);
+		let key = derive_key(password.as_bytes(), &salt, kdf)?;
 
 		let mut n = [0u8; 12];
 		n.copy_from_slice(&nonce[0..12]);
@@ -161,35 +818,756 @@ struct RawConfig {
 	encrypted_key: String,
 	salt: String,
 	nonce: String,
-	interval_s: u32,
-	addr: SocketAddr,
+	#[serde(alias = "interval_s")]
+	round_interval: ChainInterval,
+	#[serde(alias = "public_addr")]
+	addr: ListenAddr,
 	grin_node_url: SocketAddr,
 	grin_node_secret_path: Option<String>,
 	wallet_owner_url: SocketAddr,
 	wallet_owner_secret_path: Option<String>,
+	admin_socket_path: Option<String>,
+	#[serde(default)]
+	wallet_backend: WalletBackend,
+	#[serde(alias = "pending_commitment_interval_s")]
+	pending_commitment_interval: Option<ChainInterval>,
+	#[serde(alias = "tip_stall_timeout_s")]
+	tip_stall_timeout: Option<ChainInterval>,
+	report_dir: Option<String>,
+	round_jitter_percent: Option<u8>,
+	#[serde(alias = "round_delay_window_s")]
+	round_delay_window: Option<ChainInterval>,
+	#[serde(default)]
+	dandelion_stem: bool,
+	standby_addr: Option<SocketAddr>,
+	replication_addr: Option<SocketAddr>,
+	#[serde(alias = "replication_lease_s")]
+	replication_lease: Option<ChainInterval>,
+	previous_key_expiry: Option<u64>,
+	#[serde(default)]
+	acceptance_windows: bool,
+	explorer_kernel_url: Option<String>,
+	explorer_block_url: Option<String>,
+	swap_ttl: Option<ChainInterval>,
+	public_url: Option<String>,
+	log_level: Option<String>,
+	log_dir: Option<String>,
+	zero_conf_max_pending: Option<u32>,
+	nostr_relays: Option<Vec<String>>,
+	db_root: Option<String>,
+	max_swaps_per_round: Option<u32>,
+	#[serde(default)]
+	round_selection: RoundSelection,
+	min_anonymity_set: Option<u32>,
+	proxy: Option<String>,
+	events_addr: Option<SocketAddr>,
+	#[serde(default)]
+	store_backend: StoreBackend,
+	min_output_value: Option<u64>,
+	metrics_addr: Option<SocketAddr>,
+	peers: Option<Vec<String>>,
+	admin_addr: Option<SocketAddr>,
+	nrd_relative_height: Option<u16>,
+	#[serde(default)]
+	compress_archived_swaps: bool,
+	accepted_cipher_suites: Option<Vec<CipherSuite>>,
+	peer_pubkeys: Option<Vec<String>>,
+	round_peers: Option<Vec<RoundPeer>>,
+	intake_cutoff: Option<ChainInterval>,
+	rejection_log_size: Option<u64>,
+	http_timeout: Option<ChainInterval>,
+	http_retries: Option<u32>,
+	http_backoff: Option<ChainInterval>,
+	deny_list_path: Option<String>,
+	min_input_confirmations: Option<u64>,
+	api_keys: Option<Vec<ApiKey>>,
+	trusted_proxies: Option<Vec<IpAddr>>,
+	/// key used before the last rotation, encrypted with the same password as the server key
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	previous_key: Option<EncryptedServerKey>,
+	/// seed of the 'seed' wallet backend, encrypted with the same password as the server key
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	fee_seed: Option<EncryptedServerKey>,
+	/// secret that state outliving key rotation is derived from, encrypted with the same password
+	/// as the server key
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	state_key: Option<EncryptedServerKey>,
+	/// how the keys' encryption key is derived from the password. Configs written before this
+	/// was saved used PBKDF2
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	kdf: Option<KdfParams>,
+	/// block counts from configs written before intervals had units, read in place of
+	/// 'round_interval' and 'swap_ttl'
+	#[serde(default, skip_serializing)]
+	interval_blocks: Option<u64>,
+	#[serde(default, skip_serializing)]
+	swap_ttl_blocks: Option<u64>,
+	/// additional server instances run in the same process, keyed by name
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	instances: BTreeMap<String, RawConfig>,
 }
 
-/// Writes the server config to the config_path given, encrypting the server_key first.
-pub fn write_config(
+impl RawConfig {
+	/// Builds the on-disk config, encrypting the server key with the password. Keys are always
+	/// encrypted with Argon2id, with the default parameters if the config has none yet.
+	fn encrypt(
+		server_config: &ServerConfig,
+		password: &ZeroingString,
+	) -> Result<RawConfig, ConfigError> {
+		let kdf = server_config.kdf.unwrap_or_default();
+		let encrypted =
+			EncryptedServerKey::from_secret_key(&server_config.key, &password, Some(&kdf))?;
+
+		Ok(RawConfig {
+			encrypted_key: encrypted.encrypted_key,
+			salt: encrypted.salt,
+			nonce: encrypted.nonce,
+			round_interval: server_config.round_interval,
+			addr: server_config.addr.clone(),
+			grin_node_url: server_config.grin_node_url,
+			grin_node_secret_path: server_config.grin_node_secret_path.clone(),
+			wallet_owner_url: server_config.wallet_owner_url,
+			wallet_owner_secret_path: server_config.wallet_owner_secret_path.clone(),
+			admin_socket_path: server_config.admin_socket_path.clone(),
+			wallet_backend: server_config.wallet_backend,
+			pending_commitment_interval: server_config.pending_commitment_interval,
+			tip_stall_timeout: server_config.tip_stall_timeout,
+			report_dir: server_config.report_dir.clone(),
+			round_jitter_percent: server_config.round_jitter_percent,
+			round_delay_window: server_config.round_delay_window,
+			dandelion_stem: server_config.dandelion_stem,
+			standby_addr: server_config.standby_addr,
+			replication_addr: server_config.replication_addr,
+			replication_lease: server_config.replication_lease,
+			previous_key_expiry: server_config.previous_key.as_ref().map(|p| p.expires_at),
+			acceptance_windows: server_config.acceptance_windows,
+			explorer_kernel_url: server_config.explorer_kernel_url.clone(),
+			explorer_block_url: server_config.explorer_block_url.clone(),
+			swap_ttl: server_config.swap_ttl,
+			public_url: server_config.public_url.clone(),
+			log_level: server_config.log_level.clone(),
+			log_dir: server_config.log_dir.clone(),
+			zero_conf_max_pending: server_config.zero_conf_max_pending,
+			nostr_relays: server_config.nostr_relays.clone(),
+			db_root: server_config.db_root.clone(),
+			max_swaps_per_round: server_config.max_swaps_per_round,
+			round_selection: server_config.round_selection,
+			min_anonymity_set: server_config.min_anonymity_set,
+			proxy: server_config.proxy.clone(),
+			events_addr: server_config.events_addr,
+			store_backend: server_config.store_backend,
+			min_output_value: server_config.min_output_value,
+			metrics_addr: server_config.metrics_addr,
+			peers: server_config.peers.clone(),
+			admin_addr: server_config.admin_addr,
+			nrd_relative_height: server_config.nrd_relative_height,
+			compress_archived_swaps: server_config.compress_archived_swaps,
+			accepted_cipher_suites: server_config.accepted_cipher_suites.clone(),
+			peer_pubkeys: server_config.peer_pubkeys.clone(),
+			round_peers: server_config.round_peers.clone(),
+			intake_cutoff: server_config.intake_cutoff,
+			rejection_log_size: server_config.rejection_log_size,
+			http_timeout: server_config.http_timeout,
+			http_retries: server_config.http_retries,
+			http_backoff: server_config.http_backoff,
+			deny_list_path: server_config.deny_list_path.clone(),
+			min_input_confirmations: server_config.min_input_confirmations,
+			api_keys: server_config.api_keys.clone(),
+			trusted_proxies: server_config.trusted_proxies.clone(),
+			previous_key: server_config
+				.previous_key
+				.as_ref()
+				.map(|p| EncryptedServerKey::from_secret_key(&p.key, &password, Some(&kdf)))
+				.transpose()?,
+			fee_seed: server_config
+				.fee_seed
+				.as_ref()
+				.map(|seed| EncryptedServerKey::from_secret_key(seed, &password, Some(&kdf)))
+				.transpose()?,
+			state_key: server_config
+				.state_key
+				.as_ref()
+				.map(|key| EncryptedServerKey::from_secret_key(key, &password, Some(&kdf)))
+				.transpose()?,
+			kdf: Some(kdf),
+			interval_blocks: None,
+			swap_ttl_blocks: None,
+			instances: BTreeMap::new(),
+		})
+	}
+
+	/// Decrypts the server key with the password, returning the usable server config
+	fn decrypt(self, password: &ZeroingString) -> Result<ServerConfig, ConfigError> {
+		let encrypted_key = EncryptedServerKey {
+			encrypted_key: self.encrypted_key.clone(),
+			salt: self.salt.clone(),
+			nonce: self.nonce.clone(),
+		};
+		let kdf = self.kdf.as_ref();
+		let secret_key = encrypted_key.decrypt(&password, kdf)?;
+		let previous_key = match (&self.previous_key, self.previous_key_expiry) {
+			(Some(previous_key), Some(expires_at)) => Some(PreviousKey {
+				key: previous_key.decrypt(&password, kdf)?,
+				expires_at,
+			}),
+			(None, None) => None,
+			_ => return Err(ConfigError::InvalidValue("previous_key_expiry".to_string())),
+		};
+		let fee_seed = match &self.fee_seed {
+			Some(fee_seed) => Some(fee_seed.decrypt(&password, kdf)?),
+			None if self.wallet_backend == WalletBackend::Seed => {
+				return Err(ConfigError::InvalidValue(
+					"wallet_backend (the seed wallet needs a fee_seed)".to_string(),
+				))
+			}
+			None => None,
+		};
+		let state_key = self
+			.state_key
+			.as_ref()
+			.map(|key| key.decrypt(&password, kdf))
+			.transpose()?;
+
+		Ok(self.into_server_config(secret_key, previous_key, fee_seed, state_key))
+	}
+
+	/// The server config, with keys that have already been decrypted
+	fn into_server_config(
+		self,
+		key: SecretKey,
+		previous_key: Option<PreviousKey>,
+		fee_seed: Option<SecretKey>,
+		state_key: Option<SecretKey>,
+	) -> ServerConfig {
+		ServerConfig {
+			key,
+			round_interval: match self.interval_blocks {
+				Some(blocks) => ChainInterval::Blocks(blocks),
+				None => self.round_interval,
+			},
+			addr: self.addr,
+			grin_node_url: self.grin_node_url,
+			grin_node_secret_path: self.grin_node_secret_path,
+			wallet_owner_url: self.wallet_owner_url,
+			wallet_owner_secret_path: self.wallet_owner_secret_path,
+			admin_socket_path: self.admin_socket_path,
+			wallet_backend: self.wallet_backend,
+			pending_commitment_interval: self.pending_commitment_interval,
+			tip_stall_timeout: self.tip_stall_timeout,
+			report_dir: self.report_dir,
+			round_jitter_percent: self.round_jitter_percent,
+			round_delay_window: self.round_delay_window,
+			dandelion_stem: self.dandelion_stem,
+			standby_addr: self.standby_addr,
+			replication_addr: self.replication_addr,
+			replication_lease: self.replication_lease,
+			previous_key,
+			fee_seed,
+			state_key,
+			kdf: self.kdf,
+			acceptance_windows: self.acceptance_windows,
+			explorer_kernel_url: self.explorer_kernel_url,
+			explorer_block_url: self.explorer_block_url,
+			swap_ttl: self
+				.swap_ttl_blocks
+				.map(ChainInterval::Blocks)
+				.or(self.swap_ttl),
+			public_url: self.public_url,
+			log_level: self.log_level,
+			log_dir: self.log_dir,
+			zero_conf_max_pending: self.zero_conf_max_pending,
+			nostr_relays: self.nostr_relays,
+			db_root: self.db_root,
+			max_swaps_per_round: self.max_swaps_per_round,
+			round_selection: self.round_selection,
+			min_anonymity_set: self.min_anonymity_set,
+			proxy: self.proxy,
+			events_addr: self.events_addr,
+			store_backend: self.store_backend,
+			min_output_value: self.min_output_value,
+			metrics_addr: self.metrics_addr,
+			peers: self.peers,
+			admin_addr: self.admin_addr,
+			nrd_relative_height: self.nrd_relative_height,
+			compress_archived_swaps: self.compress_archived_swaps,
+			accepted_cipher_suites: self.accepted_cipher_suites,
+			peer_pubkeys: self.peer_pubkeys,
+			round_peers: self.round_peers,
+			intake_cutoff: self.intake_cutoff,
+			rejection_log_size: self.rejection_log_size,
+			http_timeout: self.http_timeout,
+			http_retries: self.http_retries,
+			http_backoff: self.http_backoff,
+			deny_list_path: self.deny_list_path,
+			min_input_confirmations: self.min_input_confirmations,
+			api_keys: self.api_keys,
+			trusted_proxies: self.trusted_proxies,
+		}
+	}
+}
+
+/// Every field a config (or an instance's section of it) may have, including the names older
+/// versions wrote some of them under
+const KNOWN_FIELDS: [&str; 67] = [
+	"encrypted_key",
+	"salt",
+	"nonce",
+	"round_interval",
+	"interval_s",
+	"interval_blocks",
+	"addr",
+	"public_addr",
+	"grin_node_url",
+	"grin_node_secret_path",
+	"wallet_owner_url",
+	"wallet_owner_secret_path",
+	"admin_socket_path",
+	"wallet_backend",
+	"pending_commitment_interval",
+	"pending_commitment_interval_s",
+	"tip_stall_timeout",
+	"tip_stall_timeout_s",
+	"report_dir",
+	"round_jitter_percent",
+	"round_delay_window",
+	"round_delay_window_s",
+	"dandelion_stem",
+	"standby_addr",
+	"replication_addr",
+	"replication_lease",
+	"replication_lease_s",
+	"previous_key_expiry",
+	"acceptance_windows",
+	"explorer_kernel_url",
+	"explorer_block_url",
+	"swap_ttl",
+	"swap_ttl_blocks",
+	"public_url",
+	"log_level",
+	"log_dir",
+	"zero_conf_max_pending",
+	"nostr_relays",
+	"db_root",
+	"max_swaps_per_round",
+	"round_selection",
+	"min_anonymity_set",
+	"proxy",
+	"events_addr",
+	"store_backend",
+	"min_output_value",
+	"metrics_addr",
+	"peers",
+	"admin_addr",
+	"nrd_relative_height",
+	"compress_archived_swaps",
+	"accepted_cipher_suites",
+	"peer_pubkeys",
+	"round_peers",
+	"intake_cutoff",
+	"rejection_log_size",
+	"http_timeout",
+	"http_retries",
+	"http_backoff",
+	"deny_list_path",
+	"min_input_confirmations",
+	"api_keys",
+	"trusted_proxies",
+	"previous_key",
+	"fee_seed",
+	"state_key",
+	"kdf",
+	"instances",
+];
+
+/// Fields every config must have, each with the names it may also be found under, and a hint
+/// for adding it
+const REQUIRED_FIELDS: [(&str, &[&str], &str); 7] = [
+	(
+		"encrypted_key",
+		&[],
+		"run init-config to generate a server key",
+	),
+	("salt", &[], "run init-config to generate a server key"),
+	("nonce", &[], "run init-config to generate a server key"),
+	(
+		"round_interval",
+		&["interval_s", "interval_blocks"],
+		"e.g. round_interval = \"12h\"",
+	),
+	("addr", &["public_addr"], "e.g. addr = \"0.0.0.0:3000\""),
+	(
+		"grin_node_url",
+		&[],
+		"e.g. grin_node_url = \"127.0.0.1:3413\"",
+	),
+	(
+		"wallet_owner_url",
+		&[],
+		"e.g. wallet_owner_url = \"127.0.0.1:3420\"",
+	),
+];
+
+/// Fields holding a socket address
+const ADDR_FIELDS: [&str; 9] = [
+	"addr",
+	"public_addr",
+	"grin_node_url",
+	"wallet_owner_url",
+	"standby_addr",
+	"replication_addr",
+	"events_addr",
+	"metrics_addr",
+	"admin_addr",
+];
+
+/// Fields holding the path of an api secret file
+const SECRET_PATH_FIELDS: [&str; 2] = ["grin_node_secret_path", "wallet_owner_secret_path"];
+
+/// How serious a problem with a config is
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+	/// The server can't start with the config
+	Error,
+	/// The server starts, but probably not as intended
+	Warning,
+}
+
+/// A problem found in a config file
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigIssue {
+	pub severity: Severity,
+	/// the field the problem is with, prefixed with 'instances.<name>.' for an instance's fields
+	pub field: String,
+	/// what's wrong, and how to fix it
+	pub message: String,
+}
+
+impl std::fmt::Display for ConfigIssue {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let severity = match self.severity {
+			Severity::Error => "error",
+			Severity::Warning => "warning",
+		};
+		write!(f, "{}: {}: {}", severity, self.field, self.message)
+	}
+}
+
+/// The result of checking a config file
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConfigReport {
+	pub issues: Vec<ConfigIssue>,
+	/// settings missing from the file, which were filled with their defaults,
+	/// as (field, default value)
+	pub defaults: Vec<(String, String)>,
+}
+
+impl ConfigReport {
+	/// Whether the server can start with the config
+	pub fn is_valid(&self) -> bool {
+		self.issues.iter().all(|i| i.severity != Severity::Error)
+	}
+
+	fn push(&mut self, severity: Severity, field: String, message: String) {
+		self.issues.push(ConfigIssue {
+			severity,
+			field,
+			message,
+		});
+	}
+}
+
+/// Edit distance between two field names, for suggesting what a misspelled field was meant to be
+fn edit_distance(a: &str, b: &str) -> usize {
+	let b: Vec<char> = b.chars().collect();
+	let mut row: Vec<usize> = (0..=b.len()).collect();
+	for (i, ca) in a.chars().enumerate() {
+		let mut diagonal = row[0];
+		row[0] = i + 1;
+		for (j, cb) in b.iter().enumerate() {
+			let above = row[j + 1];
+			row[j + 1] = if ca == *cb {
+				diagonal
+			} else {
+				1 + diagonal.min(above).min(row[j])
+			};
+			diagonal = above;
+		}
+	}
+	row[b.len()]
+}
+
+/// Checks an address field, which must be an IP address and port
+fn check_addr(value: &toml::Value) -> Option<String> {
+	match value.as_str() {
+		Some(addr) => parse_addr(addr).err().map(|e| e.to_string()),
+		None => Some("expected a string, such as \"127.0.0.1:3000\"".to_string()),
+	}
+}
+
+/// Names of the profiles in a config file: its sections not named after a field
+fn profile_names(table: &toml::value::Table) -> Vec<String> {
+	table
+		.iter()
+		.filter(|(field, value)| !KNOWN_FIELDS.contains(&field.as_str()) && value.is_table())
+		.map(|(field, _)| field.clone())
+		.collect()
+}
+
+/// Whether the file only holds profiles, with no config of its own to use without '--profile'
+fn only_profiles(table: &toml::value::Table) -> bool {
+	!table.contains_key("encrypted_key") && !profile_names(table).is_empty()
+}
+
+/// Checks the fields of a config (or of the profile or instance whose fields are prefixed with
+/// 'prefix')
+fn check_fields(table: &toml::value::Table, prefix: &str, report: &mut ConfigReport) {
+	let profiles = match prefix {
+		"" => profile_names(table),
+		_ => vec![],
+	};
+	for (field, value) in table {
+		let name = format!("{}{}", prefix, field);
+		if profiles.contains(field) {
+			check_fields(value.as_table().unwrap(), &format!("{}.", field), report);
+		} else if field == "instances" && !prefix.contains("instances.") {
+			match value.as_table() {
+				Some(instances) => {
+					for (instance, section) in instances {
+						match section.as_table() {
+							Some(section) => check_fields(
+								section,
+								&format!("{}instances.{}.", prefix, instance),
+								report,
+							),
+							None => report.push(
+								Severity::Error,
+								format!("{}instances.{}", prefix, instance),
+								"expected a section of settings".to_string(),
+							),
+						}
+					}
+				}
+				None => report.push(
+					Severity::Error,
+					name,
+					"expected sections named after each instance".to_string(),
+				),
+			}
+		} else if !KNOWN_FIELDS.contains(&field.as_str()) {
+			let closest = KNOWN_FIELDS
+				.iter()
+				.map(|known| (edit_distance(field, known), known))
+				.min()
+				.filter(|(distance, _)| *distance <= 2);
+			let message = match closest {
+				Some((_, known)) => format!(
+					"unknown field, which is ignored (did you mean '{}'?)",
+					known
+				),
+				None => "unknown field, which is ignored".to_string(),
+			};
+			report.push(Severity::Warning, name, message);
+		} else if ADDR_FIELDS.contains(&field.as_str()) {
+			// The server's own listener may bind a unix socket instead
+			let unix_socket = field == "addr"
+				&& matches!(
+					value.as_str().map(str::parse::<ListenAddr>),
+					Some(Ok(ListenAddr::Unix(_)))
+				);
+			if !unix_socket {
+				if let Some(message) = check_addr(value) {
+					report.push(Severity::Error, name, message);
+				}
+			}
+		} else if SECRET_PATH_FIELDS.contains(&field.as_str()) {
+			if let Some(path) = value.as_str() {
+				if !std::path::Path::new(path).exists() {
+					report.push(
+						Severity::Warning,
+						name,
+						format!(
+							"'{}' doesn't exist, so requests are sent without an api secret, which fail if one is required",
+							path
+						),
+					);
+				}
+			}
+		}
+	}
+
+	if prefix.is_empty() && only_profiles(table) {
+		return;
+	}
+	for (field, aliases, hint) in REQUIRED_FIELDS.iter() {
+		let present = table.contains_key(*field) || aliases.iter().any(|a| table.contains_key(*a));
+		if !present {
+			report.push(
+				Severity::Error,
+				format!("{}{}", prefix, field),
+				format!("missing ({})", hint),
+			);
+		}
+	}
+}
+
+/// Lists the settings the file didn't have, which 'raw_config' filled with their defaults
+fn filled_defaults(
+	table: &toml::value::Table,
+	raw_config: &RawConfig,
+	prefix: &str,
+	report: &mut ConfigReport,
+) {
+	let filled = match toml::Value::try_from(raw_config) {
+		Ok(toml::Value::Table(filled)) => filled,
+		_ => return,
+	};
+	for (field, value) in filled {
+		let aliased = REQUIRED_FIELDS
+			.iter()
+			.any(|(f, aliases, _)| *f == field && aliases.iter().any(|a| table.contains_key(*a)));
+		if field != "instances" && !table.contains_key(&field) && !aliased {
+			report
+				.defaults
+				.push((format!("{}{}", prefix, field), value.to_string()));
+		}
+	}
+	for (name, instance) in &raw_config.instances {
+		if let Some(section) = table
+			.get("instances")
+			.and_then(|i| i.get(name))
+			.and_then(|s| s.as_table())
+		{
+			filled_defaults(
+				section,
+				instance,
+				&format!("{}instances.{}.", prefix, name),
+				report,
+			);
+		}
+	}
+}
+
+/// Parses the raw config (or that of the profile named 'profile'), checking the whole file for
+/// mistakes along the way. The config is only returned if it has no errors, and isn't returned
+/// for a file of profiles when none is chosen.
+fn parse_raw_config(
+	contents: &str,
+	profile: Option<&str>,
+) -> Result<(Option<RawConfig>, ConfigReport), ConfigError> {
+	let table: toml::value::Table =
+		toml::from_str(contents).map_err(|e| ConfigError::DecodingError(e))?;
+	let mut report = ConfigReport::default();
+	check_fields(&table, "", &mut report);
+	if !report.is_valid() {
+		return Ok((None, report));
+	}
+
+	let sections = match profile {
+		Some(name) => match table.get(name).and_then(|s| s.as_table()) {
+			Some(section) => vec![(section.clone(), format!("{}.", name))],
+			None => {
+				report.push(
+					Severity::Error,
+					name.to_string(),
+					format!(
+						"no such profile (the file has: {})",
+						profile_names(&table).join(", ")
+					),
+				);
+				return Ok((None, report));
+			}
+		},
+		None if only_profiles(&table) => profile_names(&table)
+			.into_iter()
+			.map(|name| {
+				(
+					table[&name].as_table().unwrap().clone(),
+					format!("{}.", name),
+				)
+			})
+			.collect(),
+		None => vec![(table.clone(), String::new())],
+	};
+
+	let mut parsed = None;
+	for (section, prefix) in sections {
+		match toml::Value::Table(section.clone()).try_into::<RawConfig>() {
+			Ok(raw_config) => {
+				filled_defaults(&section, &raw_config, &prefix, &mut report);
+				parsed = Some(raw_config);
+			}
+			Err(e) => {
+				let field = format!("{}config", prefix);
+				report.push(Severity::Error, field, e.to_string());
+				return Ok((None, report));
+			}
+		}
+	}
+	if profile.is_none() && only_profiles(&table) {
+		return Ok((None, report));
+	}
+	Ok((parsed, report))
+}
+
+/// Reads the raw (still encrypted) config, or that of the profile named 'profile', from the
+/// config_path given
+fn read_raw_config(config_path: &PathBuf, profile: Option<&str>) -> Result<RawConfig, ConfigError> {
+	let contents =
+		std::fs::read_to_string(config_path).map_err(|e| ConfigError::ReadConfigError(e))?;
+	match parse_raw_config(&contents, profile)? {
+		(Some(raw_config), _) => Ok(raw_config),
+		(None, report) if report.is_valid() => Err(ConfigError::InvalidValue(
+			"profile (the config file only has profiles, so one must be chosen with --profile)"
+				.to_string(),
+		)),
+		(None, report) => Err(ConfigError::InvalidConfig(
+			report
+				.issues
+				.into_iter()
+				.filter(|i| i.severity == Severity::Error)
+				.collect(),
+		)),
+	}
+}
+
+/// Checks the config at config_path for mistakes, without decrypting it. Every profile in the
+/// file is checked, with the defaults listed for 'profile' (or all of them, if none is given).
+pub fn check_config(
 	config_path: &PathBuf,
-	server_config: &ServerConfig,
-	password: &ZeroingString,
+	profile: Option<&str>,
+) -> Result<ConfigReport, ConfigError> {
+	let contents =
+		std::fs::read_to_string(config_path).map_err(|e| ConfigError::ReadConfigError(e))?;
+	Ok(parse_raw_config(&contents, profile)?.1)
+}
+
+/// The sections of the config file at config_path, or none if it doesn't exist yet
+fn read_table(config_path: &PathBuf) -> Result<toml::value::Table, ConfigError> {
+	match std::fs::read_to_string(config_path) {
+		Ok(contents) => toml::from_str(&contents).map_err(|e| ConfigError::DecodingError(e)),
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(toml::value::Table::new()),
+		Err(e) => Err(ConfigError::ReadConfigError(e)),
+	}
+}
+
+/// Writes the raw config to the config_path given. A profile's config replaces just its own
+/// section, leaving the rest of the file as it was.
+fn write_raw_config(
+	config_path: &PathBuf,
+	profile: Option<&str>,
+	raw_config: &RawConfig,
 ) -> Result<(), ConfigError> {
-	let encrypted = EncryptedServerKey::from_secret_key(&server_config.key, &password);
-
-	let raw_config = RawConfig {
-		encrypted_key: encrypted.encrypted_key,
-		salt: encrypted.salt,
-		nonce: encrypted.nonce,
-		interval_s: server_config.interval_s,
-		addr: server_config.addr,
-		grin_node_url: server_config.grin_node_url,
-		grin_node_secret_path: server_config.grin_node_secret_path.clone(),
-		wallet_owner_url: server_config.wallet_owner_url,
-		wallet_owner_secret_path: server_config.wallet_owner_secret_path.clone(),
-	};
-	let encoded: String =
-		toml::to_string(&raw_config).map_err(|e| ConfigError::EncodingError(e))?;
+	let encoded: String = match profile {
+		Some(name) => {
+			let mut table = read_table(config_path)?;
+			let section =
+				toml::Value::try_from(raw_config).map_err(|e| ConfigError::EncodingError(e))?;
+			table.insert(name.to_string(), section);
+			toml::to_string(&toml::Value::Table(table))
+		}
+		None => toml::to_string(&raw_config),
+	}
+	.map_err(|e| ConfigError::EncodingError(e))?;
 
 	let mut file = File::create(config_path).map_err(|e| ConfigError::FileWriteError(e))?;
 	file.write_all(encoded.as_bytes())
@@ -198,44 +1576,234 @@ pub fn write_config(
 	Ok(())
 }
 
-/// Reads the server config from the config_path given and decrypts it with the provided password.
+/// Writes the server config to the config_path given, encrypting the server_key first.
+/// A new profile is added to the file alongside any others, which must have been encrypted with
+/// the same password.
+pub fn write_config(
+	config_path: &PathBuf,
+	profile: Option<&str>,
+	server_config: &ServerConfig,
+	password: &ZeroingString,
+) -> Result<(), ConfigError> {
+	if let Some(name) = profile {
+		validate_name("profile", name)?;
+		let table = read_table(config_path)?;
+		if table.contains_key(name) {
+			return Err(ConfigError::InvalidValue(format!(
+				"profile name '{}' (already exists)",
+				name
+			)));
+		}
+
+		// All profiles share one password, so it's checked against one already in the file
+		if table.contains_key("encrypted_key") {
+			read_raw_config(config_path, None)?.decrypt(&password)?;
+		} else if let Some(existing) = profile_names(&table).first() {
+			read_raw_config(config_path, Some(existing))?.decrypt(&password)?;
+		}
+	}
+
+	let raw_config = RawConfig::encrypt(&server_config, &password)?;
+	write_raw_config(config_path, profile, &raw_config)
+}
+
+/// Reads the server config (or that of the profile named 'profile') from the config_path given
+/// and decrypts it with the provided password.
 pub fn load_config(
 	config_path: &PathBuf,
+	profile: Option<&str>,
 	password: &ZeroingString,
 ) -> Result<ServerConfig, ConfigError> {
-	let contents =
-		std::fs::read_to_string(config_path).map_err(|e| ConfigError::ReadConfigError(e))?;
-	let raw_config: RawConfig =
-		toml::from_str(&contents).map_err(|e| ConfigError::DecodingError(e))?;
+	read_raw_config(config_path, profile)?.decrypt(&password)
+}
 
-	let encrypted_key = EncryptedServerKey {
-		encrypted_key: raw_config.encrypted_key,
-		salt: raw_config.salt,
-		nonce: raw_config.nonce,
+/// Replaces the server key in the config at config_path with 'new_key', keeping the old key
+/// as the previous key until 'expires_at'. The whole config is re-encrypted with the password.
+/// Additional instances are left unchanged, other than being upgraded to Argon2id.
+pub fn rotate_config_key(
+	config_path: &PathBuf,
+	profile: Option<&str>,
+	new_key: &SecretKey,
+	expires_at: u64,
+	password: &ZeroingString,
+) -> Result<ServerConfig, ConfigError> {
+	let raw_config = read_raw_config(config_path, profile)?;
+	let instances = upgrade_kdf(raw_config.clone(), &password)?.instances;
+
+	let mut server_config = raw_config.decrypt(&password)?;
+	if server_config.wallet_backend == WalletBackend::Embedded {
+		// Fee outputs of the embedded wallet are derived from the server key, so rotating it
+		// would lose track of them
+		return Err(ConfigError::InvalidValue(
+			"wallet_backend (the embedded wallet's key can't be rotated)".to_string(),
+		));
+	}
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0);
+	if let Some(previous) = server_config
+		.previous_key
+		.as_ref()
+		.filter(|p| p.expires_at > now)
+	{
+		// Replacing it would stop accepting onions wallets may still be encrypting to
+		return Err(ConfigError::InvalidValue(format!(
+			"previous_key (still accepted until unix time {}, so the key can't be rotated again until then)",
+			previous.expires_at
+		)));
+	}
+	// Salts and replication keys stay derived from the key they were first derived from
+	if server_config.state_key.is_none() {
+		server_config.state_key = Some(server_config.key.clone());
+	}
+	server_config.previous_key = Some(PreviousKey {
+		key: server_config.key.clone(),
+		expires_at,
+	});
+	server_config.key = new_key.clone();
+
+	let mut raw_config = RawConfig::encrypt(&server_config, &password)?;
+	raw_config.instances = instances;
+	write_raw_config(config_path, profile, &raw_config)?;
+	Ok(server_config)
+}
+
+/// Re-encrypts the keys of a config (and of its instances) written before Argon2id was used, so
+/// that saving any change to the file upgrades it. Configs already using Argon2id are unchanged.
+fn upgrade_kdf(raw_config: RawConfig, password: &ZeroingString) -> Result<RawConfig, ConfigError> {
+	let mut instances = BTreeMap::new();
+	for (name, instance) in raw_config.instances.clone() {
+		instances.insert(name, upgrade_kdf(instance, password)?);
+	}
+
+	let mut upgraded = match raw_config.kdf {
+		Some(_) => raw_config,
+		None => RawConfig::encrypt(&raw_config.decrypt(password)?, password)?,
 	};
-	let secret_key = encrypted_key.decrypt(&password)?;
-
-	Ok(ServerConfig {
-		key: secret_key,
-		interval_s: raw_config.interval_s,
-		addr: raw_config.addr,
-		grin_node_url: raw_config.grin_node_url,
-		grin_node_secret_path: raw_config.grin_node_secret_path,
-		wallet_owner_url: raw_config.wallet_owner_url,
-		wallet_owner_secret_path: raw_config.wallet_owner_secret_path,
-	})
+	upgraded.instances = instances;
+	Ok(upgraded)
 }
 
-pub fn get_grin_path(chain_type: &ChainTypes) -> PathBuf {
-	let mut grin_path = match dirs::home_dir() {
+/// Instance names are used in file paths, so they're limited to alphanumerics, '-' and '_'.
+/// Profile names are held to the same rule.
+fn validate_name(kind: &str, name: &str) -> Result<(), ConfigError> {
+	let valid = !name.is_empty()
+		&& name
+			.chars()
+			.all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+	if !valid || (kind == "profile" && KNOWN_FIELDS.contains(&name)) {
+		return Err(ConfigError::InvalidValue(format!(
+			"{} name '{}'",
+			kind, name
+		)));
+	}
+	Ok(())
+}
+
+/// Adds a new server instance to the config at config_path, encrypting its key with the password.
+/// The password must be the same one used for the main server's key.
+pub fn add_instance_config(
+	config_path: &PathBuf,
+	profile: Option<&str>,
+	name: &str,
+	server_config: &ServerConfig,
+	password: &ZeroingString,
+) -> Result<(), ConfigError> {
+	validate_name("instance", name)?;
+
+	let mut raw_config = upgrade_kdf(read_raw_config(config_path, profile)?, &password)?;
+	raw_config.clone().decrypt(&password)?;
+	if raw_config.instances.contains_key(name) {
+		return Err(ConfigError::InvalidValue(format!(
+			"instance name '{}' (already exists)",
+			name
+		)));
+	}
+
+	raw_config.instances.insert(
+		name.to_string(),
+		RawConfig::encrypt(&server_config, &password)?,
+	);
+	write_raw_config(config_path, profile, &raw_config)
+}
+
+/// Reads the additional server instances from the config_path given, keyed by name.
+/// Each instance's key is decrypted with the same password as the main server's key.
+pub fn load_instance_configs(
+	config_path: &PathBuf,
+	profile: Option<&str>,
+	password: &ZeroingString,
+) -> Result<BTreeMap<String, ServerConfig>, ConfigError> {
+	let raw_config = read_raw_config(config_path, profile)?;
+
+	let mut instances = BTreeMap::new();
+	for (name, instance) in raw_config.instances {
+		validate_name("instance", &name)?;
+		if !instance.instances.is_empty() {
+			return Err(ConfigError::InvalidValue(format!(
+				"instances of '{}' (instances can't be nested)",
+				name
+			)));
+		}
+		instances.insert(name, instance.decrypt(&password)?);
+	}
+	Ok(instances)
+}
+
+/// Re-reads the config of a running server (or of its instance 'instance') from config_path,
+/// or from the section of the profile it was started with.
+/// The keys stay encrypted, so no password is needed. They're taken from 'current' instead.
+pub fn reload_config(
+	config_path: &PathBuf,
+	profile: Option<&str>,
+	instance: Option<&str>,
+	current: &ServerConfig,
+) -> Result<ServerConfig, ConfigError> {
+	let mut raw_config = read_raw_config(config_path, profile)?;
+	if let Some(name) = instance {
+		raw_config = raw_config.instances.remove(name).ok_or_else(|| {
+			ConfigError::InvalidValue(format!("instance name '{}' (not found)", name))
+		})?;
+	}
+	Ok(raw_config.into_server_config(
+		current.key.clone(),
+		current.previous_key.clone(),
+		current.fee_seed.clone(),
+		current.state_key.clone(),
+	))
+}
+
+/// Reads the admin socket path from the config_path given. The server key is left encrypted,
+/// so no password is needed.
+pub fn load_admin_socket_path(
+	config_path: &PathBuf,
+	profile: Option<&str>,
+) -> Result<Option<String>, ConfigError> {
+	Ok(read_raw_config(config_path, profile)?.admin_socket_path)
+}
+
+/// grin's home directory, holding a directory for each network
+pub fn get_grin_home() -> PathBuf {
+	let mut grin_home = match dirs::home_dir() {
 		Some(p) => p,
 		None => PathBuf::new(),
 	};
-	grin_path.push(GRIN_HOME);
+	grin_home.push(GRIN_HOME);
+	grin_home
+}
+
+pub fn get_grin_path(chain_type: &ChainTypes) -> PathBuf {
+	let mut grin_path = get_grin_home();
 	grin_path.push(chain_type.shortname());
 	grin_path
 }
 
+/// Where the swap database is kept when 'db_root' isn't configured
+pub fn default_db_root(chain_type: &ChainTypes) -> PathBuf {
+	get_grin_path(chain_type).join("db")
+}
+
 pub fn node_secret_path(chain_type: &ChainTypes) -> PathBuf {
 	let mut path = get_grin_path(chain_type);
 	path.push(NODE_API_SECRET_FILE_NAME);
@@ -260,8 +1828,85 @@ pub fn wallet_owner_url(_chain_type: &ChainTypes) -> SocketAddr {
 	"127.0.0.1:3420".parse().unwrap()
 }
 
+#[cfg(test)]
+pub mod test_util {
+	use super::{KdfParams, RoundSelection, ServerConfig, StoreBackend, WalletBackend};
+	use crate::interval::ChainInterval;
+	use crate::secp;
+
+	/// Key derivation cheap enough to run in every test that saves a config
+	pub const TEST_KDF: KdfParams = KdfParams {
+		memory_kib: 64,
+		iterations: 1,
+		parallelism: 1,
+	};
+
+	/// A config for a server on localhost, with every optional feature disabled
+	pub fn test_config() -> ServerConfig {
+		ServerConfig {
+			key: secp::random_secret(),
+			round_interval: ChainInterval::Seconds(1),
+			addr: "127.0.0.1:3000".parse().unwrap(),
+			grin_node_url: "127.0.0.1:3413".parse().unwrap(),
+			grin_node_secret_path: None,
+			wallet_owner_url: "127.0.0.1:3420".parse().unwrap(),
+			wallet_owner_secret_path: None,
+			admin_socket_path: None,
+			wallet_backend: WalletBackend::OwnerApi,
+			pending_commitment_interval: None,
+			tip_stall_timeout: None,
+			report_dir: None,
+			round_jitter_percent: None,
+			round_delay_window: None,
+			dandelion_stem: false,
+			standby_addr: None,
+			replication_addr: None,
+			replication_lease: None,
+			previous_key: None,
+			fee_seed: None,
+			state_key: None,
+			kdf: Some(TEST_KDF),
+			acceptance_windows: false,
+			explorer_kernel_url: None,
+			explorer_block_url: None,
+			swap_ttl: None,
+			public_url: None,
+			log_level: None,
+			log_dir: None,
+			zero_conf_max_pending: None,
+			nostr_relays: None,
+			db_root: None,
+			max_swaps_per_round: None,
+			round_selection: RoundSelection::Fifo,
+			min_anonymity_set: None,
+			proxy: None,
+			events_addr: None,
+			store_backend: StoreBackend::Lmdb,
+			min_output_value: None,
+			metrics_addr: None,
+			peers: None,
+			admin_addr: None,
+			nrd_relative_height: None,
+			compress_archived_swaps: false,
+			accepted_cipher_suites: None,
+			peer_pubkeys: None,
+			round_peers: None,
+			intake_cutoff: None,
+			rejection_log_size: None,
+			http_timeout: None,
+			http_retries: None,
+			http_backoff: None,
+			deny_list_path: None,
+			min_input_confirmations: None,
+			api_keys: None,
+			trusted_proxies: None,
+		}
+	}
+}
+
 #[cfg(test)]
 mod tests {
+	use super::test_util::{test_config, TEST_KDF};
 	use super::*;
 	use crate::secp;
 
@@ -269,17 +1914,527 @@ mod tests {
 	fn server_key_encrypt() {
 		let password = ZeroingString::from("password");
 		let server_key = secp::random_secret();
-		let mut enc_key = EncryptedServerKey::from_secret_key(&server_key, &password);
-		let decrypted_key = enc_key.decrypt(&password).unwrap();
+		let kdf = Some(&TEST_KDF);
+		let mut enc_key = EncryptedServerKey::from_secret_key(&server_key, &password, kdf).unwrap();
+		let decrypted_key = enc_key.decrypt(&password, kdf).unwrap();
 		assert_eq!(server_key, decrypted_key);
 
 		// Wrong password
-		let decrypted_key = enc_key.decrypt("wrongpass");
+		let decrypted_key = enc_key.decrypt("wrongpass", kdf);
 		assert!(decrypted_key.is_err());
 
+		// Wrong key derivation
+		assert!(enc_key.decrypt(&password, None).is_err());
+		let other_kdf = KdfParams {
+			iterations: 2,
+			..TEST_KDF
+		};
+		assert!(enc_key.decrypt(&password, Some(&other_kdf)).is_err());
+
 		// Wrong nonce
 		enc_key.nonce = "wrongnonce".to_owned();
-		let decrypted_key = enc_key.decrypt(&password);
+		let decrypted_key = enc_key.decrypt(&password, kdf);
 		assert!(decrypted_key.is_err());
+
+		// Invalid parameters
+		let invalid_kdf = KdfParams {
+			parallelism: 0,
+			..TEST_KDF
+		};
+		assert!(matches!(
+			EncryptedServerKey::from_secret_key(&server_key, &password, Some(&invalid_kdf)),
+			Err(ConfigError::KdfError(_))
+		));
+	}
+
+	/// Configs written before Argon2id was used still load, and are upgraded when next saved
+	#[test]
+	fn upgrade_kdf() -> Result<(), Box<dyn std::error::Error>> {
+		let dir = PathBuf::from("./target/tmp/.upgrade_kdf");
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir)?;
+		let config_path = dir.join("mwixnet-config.toml");
+
+		// A config whose keys were derived with PBKDF2
+		let password = ZeroingString::from("password");
+		let mut server_config = test_config();
+		server_config.wallet_backend = WalletBackend::Seed;
+		server_config.fee_seed = Some(secp::random_secret());
+		let mut raw_config = RawConfig::encrypt(&server_config, &password)?;
+		let legacy = |key: &SecretKey| EncryptedServerKey::from_secret_key(key, &password, None);
+		let encrypted = legacy(&server_config.key)?;
+		raw_config.encrypted_key = encrypted.encrypted_key;
+		raw_config.salt = encrypted.salt;
+		raw_config.nonce = encrypted.nonce;
+		raw_config.fee_seed = Some(legacy(server_config.fee_seed.as_ref().unwrap())?);
+		raw_config.kdf = None;
+		write_raw_config(&config_path, None, &raw_config)?;
+		assert!(!std::fs::read_to_string(&config_path)?.contains("[kdf]"));
+
+		let loaded = load_config(&config_path, None, &password)?;
+		assert_eq!(None, loaded.kdf);
+		assert_eq!(server_config.key, loaded.key);
+		assert_eq!(server_config.fee_seed, loaded.fee_seed);
+
+		// Adding an instance saves the file, re-encrypting the main config along the way
+		add_instance_config(&config_path, None, "second", &test_config(), &password)?;
+		assert!(std::fs::read_to_string(&config_path)?.contains("[kdf]"));
+		let upgraded = load_config(&config_path, None, &password)?;
+		assert_eq!(Some(KdfParams::default()), upgraded.kdf);
+		assert_eq!(server_config.key, upgraded.key);
+		assert_eq!(server_config.fee_seed, upgraded.fee_seed);
+
+		Ok(())
+	}
+
+	/// Instances are saved alongside the main config, each with its own key
+	#[test]
+	fn instance_configs() -> Result<(), Box<dyn std::error::Error>> {
+		let dir = PathBuf::from("./target/tmp/.instance_configs");
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir)?;
+		let config_path = dir.join("mwixnet-config.toml");
+
+		let password = ZeroingString::from("password");
+		let main_config = test_config();
+		write_config(&config_path, None, &main_config, &password)?;
+		assert!(load_instance_configs(&config_path, None, &password)?.is_empty());
+
+		let mut instance_config = test_config();
+		instance_config.addr = "127.0.0.1:3001".parse()?;
+		add_instance_config(&config_path, None, "second", &instance_config, &password)?;
+
+		assert_eq!(main_config, load_config(&config_path, None, &password)?);
+		let instances = load_instance_configs(&config_path, None, &password)?;
+		assert_eq!(1, instances.len());
+		assert_eq!(Some(&instance_config), instances.get("second"));
+
+		// Duplicate names, invalid names, and the wrong password are rejected
+		assert!(
+			add_instance_config(&config_path, None, "second", &test_config(), &password).is_err()
+		);
+		assert!(
+			add_instance_config(&config_path, None, "../x", &test_config(), &password).is_err()
+		);
+		let wrong_password = ZeroingString::from("wrong");
+		assert!(
+			add_instance_config(&config_path, None, "third", &test_config(), &wrong_password)
+				.is_err()
+		);
+		assert_eq!(
+			1,
+			load_instance_configs(&config_path, None, &password)?.len()
+		);
+
+		Ok(())
+	}
+
+	/// Profiles are saved as sections of one file, sharing its password, and one must be chosen
+	#[test]
+	fn profiles() -> Result<(), Box<dyn std::error::Error>> {
+		let dir = PathBuf::from("./target/tmp/.profiles");
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir)?;
+		let config_path = dir.join("mwixnet-config.toml");
+
+		let password = ZeroingString::from("password");
+		let mainnet_config = test_config();
+		let mut testnet_config = test_config();
+		testnet_config.grin_node_url = "127.0.0.1:13413".parse()?;
+		write_config(&config_path, Some("mainnet"), &mainnet_config, &password)?;
+		write_config(&config_path, Some("testnet"), &testnet_config, &password)?;
+		let mut instance_config = test_config();
+		instance_config.addr = "127.0.0.1:3001".parse()?;
+		add_instance_config(
+			&config_path,
+			Some("testnet"),
+			"second",
+			&instance_config,
+			&password,
+		)?;
+
+		let loaded = load_config(&config_path, Some("mainnet"), &password)?;
+		assert_eq!(mainnet_config, loaded);
+		let loaded = load_config(&config_path, Some("testnet"), &password)?;
+		assert_eq!(testnet_config, loaded);
+		let instances = load_instance_configs(&config_path, Some("testnet"), &password)?;
+		assert_eq!(Some(&instance_config), instances.get("second"));
+		assert!(load_instance_configs(&config_path, Some("mainnet"), &password)?.is_empty());
+
+		// Every profile is checked, and lists its own defaults
+		let written = std::fs::read_to_string(&config_path)?;
+		std::fs::write(
+			&config_path,
+			written.replace("dandelion_stem = false\n", ""),
+		)?;
+		let report = check_config(&config_path, None)?;
+		assert!(report.is_valid());
+		assert!(report.issues.is_empty());
+		let defaults: Vec<String> = report.defaults.into_iter().map(|(f, _)| f).collect();
+		assert_eq!(
+			vec![
+				"mainnet.dandelion_stem",
+				"testnet.dandelion_stem",
+				"testnet.instances.second.dandelion_stem",
+			],
+			defaults
+		);
+		let report = check_config(&config_path, Some("mainnet"))?;
+		assert_eq!(1, report.defaults.len());
+
+		// A profile must be chosen, and exist
+		assert!(load_config(&config_path, None, &password).is_err());
+		assert!(load_config(&config_path, Some("regtest"), &password).is_err());
+
+		// Existing profiles, invalid names, and a different password are rejected
+		let wrong_password = ZeroingString::from("wrong");
+		assert!(write_config(&config_path, Some("testnet"), &test_config(), &password).is_err());
+		assert!(write_config(&config_path, Some("salt"), &test_config(), &password).is_err());
+		assert!(
+			write_config(&config_path, Some("other"), &test_config(), &wrong_password).is_err()
+		);
+		assert_eq!(
+			testnet_config,
+			load_config(&config_path, Some("testnet"), &password)?
+		);
+
+		Ok(())
+	}
+
+	/// Rotating the key keeps the old one as the previous key, and leaves instances untouched
+	#[test]
+	fn rotate_key() -> Result<(), Box<dyn std::error::Error>> {
+		let dir = PathBuf::from("./target/tmp/.rotate_key");
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir)?;
+		let config_path = dir.join("mwixnet-config.toml");
+
+		let password = ZeroingString::from("password");
+		let main_config = test_config();
+		write_config(&config_path, None, &main_config, &password)?;
+		let instance_config = test_config();
+		add_instance_config(&config_path, None, "second", &instance_config, &password)?;
+
+		let new_key = secp::random_secret();
+		let rotated = rotate_config_key(&config_path, None, &new_key, 1_000, &password)?;
+		assert_eq!(new_key, rotated.key);
+		assert_eq!(
+			Some(PreviousKey {
+				key: main_config.key.clone(),
+				expires_at: 1_000,
+			}),
+			rotated.previous_key
+		);
+		assert_eq!(&main_config.key, rotated.state_key());
+		assert_eq!(rotated, load_config(&config_path, None, &password)?);
+
+		// The state key stays the original key across later rotations, but a previous key that's
+		// still accepted can't be replaced
+		let later_key = secp::random_secret();
+		let rotated = rotate_config_key(&config_path, None, &later_key, u64::MAX, &password)?;
+		assert_eq!(&main_config.key, rotated.state_key());
+		assert!(rotate_config_key(
+			&config_path,
+			None,
+			&secp::random_secret(),
+			u64::MAX,
+			&password
+		)
+		.is_err());
+		assert_eq!(later_key, load_config(&config_path, None, &password)?.key);
+		assert_eq!(
+			Some(&instance_config),
+			load_instance_configs(&config_path, None, &password)?.get("second")
+		);
+
+		// The embedded wallet's key can't be rotated
+		let mut embedded_config = test_config();
+		embedded_config.wallet_backend = WalletBackend::Embedded;
+		write_config(&config_path, None, &embedded_config, &password)?;
+		assert!(rotate_config_key(&config_path, None, &new_key, 1_000, &password).is_err());
+
+		// A wallet with a seed of its own keeps it across the rotation
+		let mut seed_config = test_config();
+		seed_config.wallet_backend = WalletBackend::Seed;
+		seed_config.fee_seed = Some(secp::random_secret());
+		write_config(&config_path, None, &seed_config, &password)?;
+		let rotated = rotate_config_key(&config_path, None, &new_key, 1_000, &password)?;
+		assert_eq!(seed_config.fee_seed, rotated.fee_seed);
+		assert_eq!(rotated, load_config(&config_path, None, &password)?);
+
+		Ok(())
+	}
+
+	/// Configs written before intervals had units are still read, with block counts kept in blocks
+	#[test]
+	fn legacy_intervals() -> Result<(), Box<dyn std::error::Error>> {
+		let dir = PathBuf::from("./target/tmp/.legacy_intervals");
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir)?;
+		let config_path = dir.join("mwixnet-config.toml");
+
+		let mut server_config = test_config();
+		server_config.round_interval = ChainInterval::Seconds(12 * 60 * 60);
+		server_config.tip_stall_timeout = Some(ChainInterval::Blocks(30));
+		let password = ZeroingString::from("password");
+		write_config(&config_path, None, &server_config, &password)?;
+		let written = std::fs::read_to_string(&config_path)?;
+		assert!(written.contains("round_interval = \"12h\""));
+		assert!(written.contains("tip_stall_timeout = \"30 blocks\""));
+		assert_eq!(server_config, load_config(&config_path, None, &password)?);
+
+		let legacy = written.replace(
+			"round_interval = \"12h\"",
+			"interval_s = 600\nreplication_lease_s = 90\nswap_ttl_blocks = 30",
+		);
+		std::fs::write(&config_path, &legacy)?;
+		let loaded = load_config(&config_path, None, &password)?;
+		assert_eq!(ChainInterval::Seconds(600), loaded.round_interval);
+		assert_eq!(Some(ChainInterval::Seconds(90)), loaded.replication_lease);
+		assert_eq!(Some(ChainInterval::Blocks(30)), loaded.swap_ttl);
+
+		std::fs::write(
+			&config_path,
+			legacy.replace(
+				"interval_s = 600",
+				"interval_s = 600\ninterval_blocks = 720",
+			),
+		)?;
+		let loaded = load_config(&config_path, None, &password)?;
+		assert_eq!(ChainInterval::Blocks(720), loaded.round_interval);
+
+		Ok(())
+	}
+
+	/// Mistakes in a config are reported by field, with a hint at how to fix each,
+	/// and settings missing from it are filled with their defaults
+	#[test]
+	fn check_config_issues() -> Result<(), Box<dyn std::error::Error>> {
+		let dir = PathBuf::from("./target/tmp/.check_config_issues");
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir)?;
+		let config_path = dir.join("mwixnet-config.toml");
+
+		let mut server_config = test_config();
+		server_config.grin_node_secret_path = Some("./target/tmp/.missing_secret".to_string());
+		let password = ZeroingString::from("password");
+		write_config(&config_path, None, &server_config, &password)?;
+		let written = std::fs::read_to_string(&config_path)?;
+		let serialized: toml::value::Table = toml::from_str(&written)?;
+		assert!(serialized
+			.keys()
+			.all(|field| KNOWN_FIELDS.contains(&field.as_str())));
+
+		std::fs::write(
+			&config_path,
+			written.replace("dandelion_stem = false\n", ""),
+		)?;
+		let report = check_config(&config_path, None)?;
+		assert!(report.is_valid());
+		assert_eq!(
+			vec![ConfigIssue {
+				severity: Severity::Warning,
+				field: "grin_node_secret_path".to_string(),
+				message: "'./target/tmp/.missing_secret' doesn't exist, so requests are sent without an api secret, which fail if one is required".to_string(),
+			}],
+			report.issues
+		);
+		assert_eq!(
+			vec![("dandelion_stem".to_string(), "false".to_string())],
+			report.defaults
+		);
+		assert!(load_config(&config_path, None, &password).is_ok());
+
+		let broken = format!(
+			"round_intervl = \"1h\"\n{}",
+			written
+				.replace(
+					"addr = \"127.0.0.1:3000\"",
+					"addr = \"http://127.0.0.1:3000/v1\""
+				)
+				.replace("grin_node_url = \"127.0.0.1:3413\"\n", "")
+		);
+		std::fs::write(&config_path, &broken)?;
+		let report = check_config(&config_path, None)?;
+		assert!(!report.is_valid());
+		let find = |field: &str| {
+			report
+				.issues
+				.iter()
+				.find(|i| i.field == field)
+				.map(|i| (i.severity, i.message.clone()))
+		};
+		assert_eq!(
+			Some((
+				Severity::Warning,
+				"unknown field, which is ignored (did you mean 'round_interval'?)".to_string()
+			)),
+			find("round_intervl")
+		);
+		assert_eq!(
+			Some((
+				Severity::Error,
+				"'http://127.0.0.1:3000/v1' is a URL, but an address is expected: use \"127.0.0.1:3000\"".to_string()
+			)),
+			find("addr")
+		);
+		assert_eq!(
+			Some((
+				Severity::Error,
+				"missing (e.g. grin_node_url = \"127.0.0.1:3413\")".to_string()
+			)),
+			find("grin_node_url")
+		);
+		match load_config(&config_path, None, &password) {
+			Err(ConfigError::InvalidConfig(errors)) => assert_eq!(2, errors.len()),
+			res => panic!("Unexpected result {:?}", res),
+		}
+
+		Ok(())
+	}
+
+	/// The configured public URL is persisted and advertised in place of the bind address
+	#[test]
+	fn public_url() -> Result<(), Box<dyn std::error::Error>> {
+		let dir = PathBuf::from("./target/tmp/.public_url");
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir)?;
+		let config_path = dir.join("mwixnet-config.toml");
+
+		let mut server_config = test_config();
+		assert_eq!("http://127.0.0.1:3000/v1", server_config.advertised_url());
+
+		let password = ZeroingString::from("password");
+		server_config.public_url = Some("http://mixabcdef.onion/v1".to_string());
+		write_config(&config_path, None, &server_config, &password)?;
+		let loaded = load_config(&config_path, None, &password)?;
+		assert_eq!("http://mixabcdef.onion/v1", loaded.advertised_url());
+
+		Ok(())
+	}
+
+	/// IPv6 addresses are accepted in brackets, and malformed addresses are explained
+	#[test]
+	fn ipv6_addr() -> Result<(), Box<dyn std::error::Error>> {
+		let addr = parse_addr("[::]:3000")?;
+		assert!(addr.is_ipv6());
+		assert_eq!(3000, addr.port());
+		let listen_addr: ListenAddr = "[::1]:3000".parse()?;
+		assert_eq!("[::1]:3000", listen_addr.to_string());
+
+		let mut server_config = test_config();
+		server_config.addr = listen_addr;
+		assert_eq!("http://[::1]:3000/v1", server_config.advertised_url());
+
+		let err = |s: &str| parse_addr(s).unwrap_err();
+		assert_eq!(
+			AddrError::UnbracketedIpv6("::1:3000".to_string()),
+			err("::1:3000")
+		);
+		assert_eq!(AddrError::MissingPort("[::1]".to_string()), err("[::1]"));
+		assert_eq!(
+			AddrError::MissingPort("127.0.0.1".to_string()),
+			err("127.0.0.1")
+		);
+		assert_eq!(
+			AddrError::InvalidPort {
+				addr: "[::1]:65536".to_string(),
+				port: "65536".to_string(),
+			},
+			err("[::1]:65536")
+		);
+		assert_eq!(
+			AddrError::NotAnIp {
+				addr: "localhost:3000".to_string(),
+				host: "localhost".to_string(),
+			},
+			err("localhost:3000")
+		);
+		assert_eq!(AddrError::Invalid("[::1".to_string()), err("[::1"));
+		assert_eq!(
+			Some(
+				"'::1:3000' looks like an IPv6 address without brackets: write it in brackets, followed by the port, such as \"[::1]:3000\"".to_string()
+			),
+			check_addr(&toml::Value::String("::1:3000".to_string()))
+		);
+
+		Ok(())
+	}
+
+	/// The listener may bind a unix socket, which is persisted and advertised as an http+unix URL
+	#[test]
+	fn unix_socket_addr() -> Result<(), Box<dyn std::error::Error>> {
+		let dir = PathBuf::from("./target/tmp/.unix_socket_addr");
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir)?;
+		let config_path = dir.join("mwixnet-config.toml");
+
+		assert_eq!(
+			ListenAddr::Unix(PathBuf::from("mwixnet.sock")),
+			"unix:mwixnet.sock".parse()?
+		);
+		assert!("127.0.0.1".parse::<ListenAddr>().is_err());
+
+		let mut server_config = test_config();
+		server_config.addr = "/run/mwixnet.sock".parse()?;
+		let password = ZeroingString::from("password");
+		write_config(&config_path, None, &server_config, &password)?;
+		let loaded = load_config(&config_path, None, &password)?;
+		assert_eq!(server_config.addr, loaded.addr);
+		assert_eq!(
+			"http+unix://%2Frun%2Fmwixnet.sock/v1",
+			loaded.advertised_url()
+		);
+		assert!(check_config(&config_path, None)?.is_valid());
+
+		Ok(())
+	}
+
+	/// Reloading applies changed settings without the password, except those needing a restart
+	#[test]
+	fn reload() -> Result<(), Box<dyn std::error::Error>> {
+		let dir = PathBuf::from("./target/tmp/.reload");
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir)?;
+		let config_path = dir.join("mwixnet-config.toml");
+
+		let password = ZeroingString::from("password");
+		let running = test_config();
+		write_config(&config_path, None, &running, &password)?;
+		let mut instance_config = test_config();
+		instance_config.swap_ttl = Some(ChainInterval::Blocks(60));
+		add_instance_config(&config_path, None, "second", &instance_config, &password)?;
+
+		// Edit the file as an operator would, including a new key that can't be hot-swapped
+		let mut edited = running.clone();
+		edited.key = secp::random_secret();
+		edited.round_interval = ChainInterval::Blocks(10);
+		edited.grin_node_url = "127.0.0.1:13413".parse()?;
+		edited.addr = "127.0.0.1:3001".parse()?;
+		let mut raw_config = RawConfig::encrypt(&edited, &password)?;
+		raw_config.instances = read_raw_config(&config_path, None)?.instances;
+		write_raw_config(&config_path, None, &raw_config)?;
+
+		let reloaded = reload_config(&config_path, None, None, &running)?;
+		assert_eq!(running.key, reloaded.key);
+		let (server_config, changes) = running.reload(reloaded);
+		assert_eq!(running.key, server_config.key);
+		assert_eq!(ChainInterval::Blocks(10), server_config.round_interval);
+		assert_eq!(edited.grin_node_url, server_config.grin_node_url);
+		assert_eq!(running.addr, server_config.addr);
+		assert_eq!(
+			ConfigChanges {
+				applied: vec!["round_interval".to_string(), "grin_node_url".to_string()],
+				restart_required: vec!["addr".to_string()],
+			},
+			changes
+		);
+
+		// Instances are reloaded from their own section
+		let reloaded = reload_config(&config_path, None, Some("second"), &instance_config)?;
+		assert_eq!(instance_config, reloaded);
+		assert!(reload_config(&config_path, None, Some("third"), &instance_config).is_err());
+
+		Ok(())
 	}
 }
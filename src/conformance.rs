@@ -0,0 +1,509 @@
+use crate::http::HttpClient;
+use crate::onion::{self, Hop, Onion};
+use crate::secp::{self, ComSignature, PublicKey, RangeProof, Secp256k1, SecretKey, SigDomain};
+use crate::types::Payload;
+
+use grin_core::core::{FeeFields, TransactionBody};
+use grin_core::global::DEFAULT_ACCEPT_FEE_BASE;
+use grin_core::ser::{self, ProtocolVersion};
+use grin_util::ToHex;
+use rand::RngCore;
+use serde_json::{json, Value};
+use std::fmt;
+use std::net::SocketAddr;
+
+/// Size of the payload used to check that oversized payloads are rejected
+const OVERSIZE_PAYLOAD_LEN: usize = 64 * 1024;
+
+/// JSON-RPC error code for invalid method parameters
+const INVALID_PARAMS_CODE: i64 = -32602;
+
+/// Number of blocks past the current height that test swaps expire at
+const SWAP_EXPIRY_BLOCKS: u64 = 60;
+
+/// The result of a single conformance check
+#[derive(Clone, Debug, PartialEq)]
+pub enum CheckResult {
+	Pass,
+	Fail(String),
+	Skipped(String),
+}
+
+/// A conformance check, along with its result
+#[derive(Clone, Debug, PartialEq)]
+pub struct CheckOutcome {
+	pub name: &'static str,
+	pub description: &'static str,
+	pub result: CheckResult,
+}
+
+impl fmt::Display for CheckOutcome {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match &self.result {
+			CheckResult::Pass => write!(f, "PASS  {}: {}", self.name, self.description),
+			CheckResult::Fail(reason) => write!(
+				f,
+				"FAIL  {}: {}\n      {}",
+				self.name, self.description, reason
+			),
+			CheckResult::Skipped(reason) => write!(
+				f,
+				"SKIP  {}: {}\n      {}",
+				self.name, self.description, reason
+			),
+		}
+	}
+}
+
+/// An unspent output owned by the tester, used for the checks that need a real coin.
+/// A valid swap of the coin is submitted, so it will be spent by the target server's next round.
+pub struct TestCoin {
+	pub value: u64,
+	pub blind: SecretKey,
+}
+
+/// An onion and the signature over it, as sent in a 'swap' request,
+/// along with the opening of the input so the onion can be signed again.
+struct SwapParams {
+	onion: Onion,
+	comsig: ComSignature,
+	expiry_height: u64,
+	value: u64,
+	blind: SecretKey,
+}
+
+impl SwapParams {
+	fn to_json(&self) -> Result<Value, String> {
+		let comsig =
+			ser::ser_vec(&self.comsig, ProtocolVersion::local()).map_err(|e| e.to_string())?;
+		Ok(json!([{
+			"onion": self.onion,
+			"comsig": comsig.to_hex(),
+			"expiry_height": self.expiry_height,
+		}]))
+	}
+
+	/// Signs the onion again after it or its expiry height has been modified,
+	/// binding the signature to 'server_pubkey'.
+	fn resign(&mut self, server_pubkey: &PublicKey) -> Result<(), String> {
+		let domain = SigDomain::new(server_pubkey, self.expiry_height);
+		self.comsig = sign_onion(&self.onion, self.value, &self.blind, &domain)?;
+		Ok(())
+	}
+}
+
+/// Runs conformance checks against a mwixnet server's public JSON-RPC API
+pub struct ConformanceRunner {
+	client: HttpClient,
+	server_addr: SocketAddr,
+	server_pubkey: Option<PublicKey>,
+	coin: Option<TestCoin>,
+	chain_height: Option<u64>,
+}
+
+impl ConformanceRunner {
+	/// 'server_pubkey' is looked up with 'get_server_keys' when not provided.
+	/// Without a 'coin', checks that need an unspent output are skipped, and rejections of invalid
+	/// onions can't be attributed to a specific cause, since the input doesn't exist either.
+	pub fn new(
+		server_addr: SocketAddr,
+		server_pubkey: Option<PublicKey>,
+		coin: Option<TestCoin>,
+		client: HttpClient,
+	) -> ConformanceRunner {
+		ConformanceRunner {
+			client,
+			server_addr,
+			server_pubkey,
+			coin,
+			chain_height: None,
+		}
+	}
+
+	/// Runs every check, in an order where earlier checks can't affect the results of later ones
+	pub fn run(&mut self) -> Vec<CheckOutcome> {
+		let mut outcomes = vec![
+			self.check(
+				"unknown_path",
+				"requests to paths other than /v1 are rejected",
+				|r| expect_rejected(r.call("/v2", "get_pending_commitment", json!([]))),
+			),
+			self.check(
+				"malformed_request",
+				"swap requests with malformed params are rejected as invalid params",
+				|r| {
+					let res = r.call("/v1", "swap", json!([{ "onion": "not an onion" }]))?;
+					match error_code(&res) {
+						Some(INVALID_PARAMS_CODE) => Ok(CheckResult::Pass),
+						Some(code) => Ok(CheckResult::Fail(format!(
+							"Expected error code {}, got {}",
+							INVALID_PARAMS_CODE, code
+						))),
+						None => Ok(CheckResult::Fail("Request was accepted".to_string())),
+					}
+				},
+			),
+		];
+
+		let server_keys = self.check(
+			"server_keys",
+			"get_server_keys publishes the key onions are encrypted to",
+			|r| {
+				let res = r.call("/v1", "get_server_keys", json!([]))?;
+				if error_code(&res).is_some() {
+					return Ok(CheckResult::Skipped(
+						"get_server_keys is not supported".to_string(),
+					));
+				}
+				let current = res
+					.get("result")
+					.and_then(|k| k.get("current"))
+					.and_then(|k| k.as_str())
+					.ok_or("Response has no current key")?;
+				let pubkey = parse_pubkey(current)?;
+				if r.server_pubkey.is_none() {
+					r.server_pubkey = Some(pubkey);
+				}
+				Ok(CheckResult::Pass)
+			},
+		);
+		outcomes.push(server_keys);
+
+		outcomes.push(self.check(
+			"server_info",
+			"get_info publishes the chain height that swap expiries are checked against",
+			|r| {
+				let res = r.call("/v1", "get_info", json!([]))?;
+				if error_code(&res).is_some() {
+					return Ok(CheckResult::Skipped(
+						"get_info is not supported".to_string(),
+					));
+				}
+				let height = res
+					.get("result")
+					.and_then(|i| i.get("chain_height"))
+					.and_then(|h| h.as_u64())
+					.ok_or("Response has no chain height")?;
+				r.chain_height = Some(height);
+				Ok(CheckResult::Pass)
+			},
+		));
+
+		outcomes.push(self.check(
+			"too_many_payloads",
+			"onions with more layers than the route has servers are rejected",
+			|r| {
+				let swap = r.build_swap(2, None, false)?;
+				expect_rejected(r.call("/v1", "swap", swap.to_json()?))
+			},
+		));
+		outcomes.push(self.check(
+			"oversize_payload",
+			"onions with an oversized payload are rejected",
+			|r| {
+				let mut swap = r.build_swap(1, None, false)?;
+				let mut payload = vec![0; OVERSIZE_PAYLOAD_LEN];
+				rand::thread_rng().fill_bytes(&mut payload);
+				swap.onion.enc_payloads = vec![payload];
+				swap.resign(&r.server_pubkey()?)?;
+				expect_rejected(r.call("/v1", "swap", swap.to_json()?))
+			},
+		));
+		outcomes.push(self.check(
+			"invalid_comsig",
+			"onions whose commitment signature doesn't verify are rejected",
+			|r| {
+				let mut swap = r.build_swap(1, None, false)?;
+				let domain = SigDomain::new(&r.server_pubkey()?, swap.expiry_height);
+				swap.comsig = ComSignature::sign(1, &secp::random_secret(), &domain, &vec![0; 32])
+					.map_err(|e| e.to_string())?;
+				expect_rejected(r.call("/v1", "swap", swap.to_json()?))
+			},
+		));
+		outcomes.push(self.check(
+			"other_server_comsig",
+			"onions whose signature is bound to a different server's key are rejected",
+			|r| {
+				let mut swap = r.build_swap(1, None, false)?;
+				swap.resign(&random_pubkey()?)?;
+				expect_rejected(r.call("/v1", "swap", swap.to_json()?))
+			},
+		));
+		outcomes.push(self.check(
+			"expired_swap",
+			"swaps whose expiry height has been reached are rejected",
+			|r| {
+				let mut swap = r.build_swap(1, None, false)?;
+				swap.expiry_height = r.chain_height()?;
+				swap.resign(&r.server_pubkey()?)?;
+				expect_rejected(r.call("/v1", "swap", swap.to_json()?))
+			},
+		));
+		outcomes.push(self.check(
+			"wrong_key",
+			"onions encrypted to a key the server doesn't hold are rejected",
+			|r| {
+				let swap = r.build_swap(1, Some(random_pubkey()?), false)?;
+				expect_rejected(r.call("/v1", "swap", swap.to_json()?))
+			},
+		));
+		outcomes.push(self.check(
+			"unknown_input",
+			"onions spending an output that doesn't exist are rejected",
+			|r| {
+				let swap = r.build_swap(1, None, true)?;
+				expect_rejected(r.call("/v1", "swap", swap.to_json()?))
+			},
+		));
+
+		// The valid swap goes last, since once it's accepted, the coin can't be used again
+		let mut valid_swap = None;
+		outcomes.push(self.check(
+			"valid_swap",
+			"a valid onion spending an unspent output is accepted",
+			|r| {
+				if r.coin.is_none() {
+					return Ok(CheckResult::Skipped("No test coin provided".to_string()));
+				}
+				let swap = r.build_swap(1, None, false)?;
+				let params = swap.to_json()?;
+				let res = r.call("/v1", "swap", params.clone())?;
+				match error_message(&res) {
+					Some(message) => Ok(CheckResult::Fail(format!("Swap rejected: {}", message))),
+					None => {
+						valid_swap = Some(params);
+						Ok(CheckResult::Pass)
+					}
+				}
+			},
+		));
+		outcomes.push(
+			self.check(
+				"replay",
+				"resubmitting an accepted swap is rejected",
+				|r| match &valid_swap {
+					Some(params) => expect_rejected(r.call("/v1", "swap", params.clone())),
+					None => Ok(CheckResult::Skipped(
+						"No valid swap was accepted".to_string(),
+					)),
+				},
+			),
+		);
+
+		outcomes
+	}
+
+	fn check<F>(&mut self, name: &'static str, description: &'static str, f: F) -> CheckOutcome
+	where
+		F: FnOnce(&mut Self) -> Result<CheckResult, String>,
+	{
+		let result = f(self).unwrap_or_else(|e| CheckResult::Fail(e));
+		CheckOutcome {
+			name,
+			description,
+			result,
+		}
+	}
+
+	/// Posts a JSON-RPC request, returning the whole response (including any error)
+	fn call(&self, path: &str, method: &str, params: Value) -> Result<Value, String> {
+		let url = format!("http://{}{}", self.server_addr, path);
+		let req = json!({
+			"jsonrpc": "2.0",
+			"method": method,
+			"params": params,
+			"id": 1,
+		});
+		self.client
+			.post::<Value, Value>(&url, None, &req)
+			.map_err(|e| e.to_string())
+	}
+
+	fn server_pubkey(&self) -> Result<PublicKey, String> {
+		self.server_pubkey
+			.ok_or("Server key unknown. Provide it with --server_pubkey".to_string())
+	}
+
+	fn chain_height(&self) -> Result<u64, String> {
+		self.chain_height
+			.ok_or("Chain height unknown. The server must support get_info".to_string())
+	}
+
+	/// Builds a swap with 'num_payloads' layers for the test coin (or a random coin, if there's
+	/// no test coin or 'unknown_input' is set), encrypted to 'pubkey' or the server's key.
+	/// The signature is always bound to the server's key.
+	fn build_swap(
+		&self,
+		num_payloads: usize,
+		pubkey: Option<PublicKey>,
+		unknown_input: bool,
+	) -> Result<SwapParams, String> {
+		let server_pubkey = self.server_pubkey()?;
+		let pubkey = pubkey.unwrap_or(server_pubkey);
+		let expiry_height = self.chain_height()? + SWAP_EXPIRY_BLOCKS;
+
+		let (value, blind) = match (&self.coin, unknown_input) {
+			(Some(coin), false) => (coin.value, coin.blind.clone()),
+			_ => (200_000_000, secp::random_secret()),
+		};
+		let commit = secp::commit(value, &blind).map_err(|e| e.to_string())?;
+
+		let fee = TransactionBody::weight_by_iok(1, 1, 1) * DEFAULT_ACCEPT_FEE_BASE;
+		let hops: Vec<Hop> = (0..num_payloads)
+			.map(|i| {
+				let excess = secp::random_secret();
+				let rangeproof = if i == num_payloads - 1 {
+					Some(build_proof(value, fee, &blind, &excess)?)
+				} else {
+					None
+				};
+				Ok(Hop {
+					pubkey: pubkey.clone(),
+					payload: Payload {
+						excess,
+						fee: FeeFields::from(fee as u32),
+						rangeproof,
+						owner_kernel: None,
+						min_value_proof: None,
+						next_view_tag: None,
+						fee_change: None,
+					},
+				})
+			})
+			.collect::<Result<Vec<Hop>, String>>()?;
+
+		let onion = onion::create_onion(&commit, &hops).map_err(|e| e.to_string())?;
+		let domain = SigDomain::new(&server_pubkey, expiry_height);
+		let comsig = sign_onion(&onion, value, &blind, &domain)?;
+		Ok(SwapParams {
+			onion,
+			comsig,
+			expiry_height,
+			value,
+			blind,
+		})
+	}
+}
+
+/// Signs the onion as the owner of the coin it spends
+fn sign_onion(
+	onion: &Onion,
+	value: u64,
+	blind: &SecretKey,
+	domain: &SigDomain,
+) -> Result<ComSignature, String> {
+	let msg = onion.serialize().map_err(|e| e.to_string())?;
+	ComSignature::sign(value, blind, domain, &msg).map_err(|e| e.to_string())
+}
+
+fn random_pubkey() -> Result<PublicKey, String> {
+	PublicKey::from_secret_key(&Secp256k1::new(), &secp::random_secret()).map_err(|e| e.to_string())
+}
+
+/// Rangeproof for the output left after the hop's excess is added and the fee is subtracted
+fn build_proof(
+	value: u64,
+	fee: u64,
+	input_blind: &SecretKey,
+	hop_excess: &SecretKey,
+) -> Result<RangeProof, String> {
+	if value <= fee {
+		return Err(format!("Test coin value must exceed the fee of {}", fee));
+	}
+
+	let secp = Secp256k1::new();
+	let nonce = secp::random_secret();
+	let mut blind = input_blind.clone();
+	blind
+		.add_assign(&secp, &hop_excess)
+		.map_err(|e| e.to_string())?;
+	Ok(secp.bullet_proof(value - fee, blind, nonce.clone(), nonce, None, None))
+}
+
+fn parse_pubkey(hex: &str) -> Result<PublicKey, String> {
+	let bytes = grin_util::from_hex(hex).map_err(|e| e.to_string())?;
+	PublicKey::from_slice(&Secp256k1::new(), &bytes).map_err(|e| e.to_string())
+}
+
+fn error_code(res: &Value) -> Option<i64> {
+	res.get("error")
+		.and_then(|e| e.get("code"))
+		.and_then(|c| c.as_i64())
+}
+
+fn error_message(res: &Value) -> Option<String> {
+	res.get("error").map(|e| {
+		e.get("message")
+			.and_then(|m| m.as_str())
+			.unwrap_or("unknown error")
+			.to_string()
+	})
+}
+
+/// Passes if the request failed outright, or the server responded with an error
+fn expect_rejected(res: Result<Value, String>) -> Result<CheckResult, String> {
+	match res {
+		Err(_) => Ok(CheckResult::Pass),
+		Ok(res) if error_code(&res).is_some() => Ok(CheckResult::Pass),
+		Ok(_) => Ok(CheckResult::Fail("Request was accepted".to_string())),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{expect_rejected, CheckResult, ConformanceRunner, TestCoin};
+	use crate::http::HttpClient;
+	use crate::secp::{self, PublicKey, Secp256k1, SigDomain};
+
+	use serde_json::json;
+
+	#[test]
+	fn rejections() {
+		assert_eq!(
+			CheckResult::Pass,
+			expect_rejected(Err("connection refused".to_string())).unwrap()
+		);
+		assert_eq!(
+			CheckResult::Pass,
+			expect_rejected(Ok(json!({"error": {"code": -32602, "message": "bad"}}))).unwrap()
+		);
+		assert!(matches!(
+			expect_rejected(Ok(json!({"result": "success"}))).unwrap(),
+			CheckResult::Fail(_)
+		));
+	}
+
+	/// Swaps built for the test coin are signed by its owner, and can be peeled by the server
+	#[test]
+	fn build_swap() -> Result<(), Box<dyn std::error::Error>> {
+		let server_key = secp::random_secret();
+		let server_pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &server_key)?;
+		let coin = TestCoin {
+			value: 200_000_000,
+			blind: secp::random_secret(),
+		};
+		let commit = secp::commit(coin.value, &coin.blind)?;
+		let mut runner = ConformanceRunner::new(
+			"127.0.0.1:3000".parse()?,
+			Some(server_pubkey),
+			Some(coin),
+			HttpClient::default(),
+		);
+		assert!(runner.build_swap(1, None, false).is_err());
+		runner.chain_height = Some(100);
+
+		let swap = runner.build_swap(1, None, false)?;
+		assert_eq!(commit, swap.onion.commit);
+		assert_eq!(160, swap.expiry_height);
+		let domain = SigDomain::new(&server_pubkey, swap.expiry_height);
+		swap.comsig
+			.verify(&commit, &domain, &swap.onion.serialize()?)?;
+		let (payload, _) = swap.onion.peel_layer(&server_key)?;
+		assert!(payload.rangeproof.is_some());
+
+		let swap = runner.build_swap(1, None, true)?;
+		assert_ne!(commit, swap.onion.commit);
+
+		Ok(())
+	}
+}
@@ -0,0 +1,575 @@
+use crate::http::HttpClient;
+use crate::onion::{self, CipherSuite, Hop, Onion, OnionError};
+use crate::secp::{
+	self, ComSignature, Message, PublicKey, RangeProof, Secp256k1, SecretKey, SigDomain, Signature,
+};
+use crate::types::Payload;
+use crate::wallet;
+
+use blake2::blake2b::Blake2b;
+use byteorder::{BigEndian, ByteOrder};
+use grin_core::core::FeeFields;
+use grin_core::libtx::secp_ser;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use thiserror::Error;
+
+/// Oldest a directory may be before clients refuse to build routes from it
+pub const MAX_DIRECTORY_AGE_S: u64 = 7 * 24 * 60 * 60;
+
+/// A mix node listed in a directory
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Mixnode {
+	#[serde(with = "secp_ser::pubkey_serde")]
+	pub pubkey: PublicKey,
+	/// URL of the node's JSON-RPC API
+	pub url: String,
+	/// fee the node takes from each swap routed through it, in nanogrin
+	pub fee: u64,
+}
+
+/// A list of mix nodes, signed by the directory authority
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Directory {
+	pub mixnodes: Vec<Mixnode>,
+	/// unix time (in seconds) the directory was signed at
+	pub timestamp: u64,
+	#[serde(with = "secp_ser::pubkey_serde")]
+	pub pubkey: PublicKey,
+	#[serde(with = "secp_ser::sig_serde")]
+	pub signature: Signature,
+}
+
+/// Error types for fetching directories and building routes from them
+#[derive(Clone, Error, Debug, PartialEq)]
+pub enum DirectoryError {
+	#[error("Error fetching directory: {0}")]
+	FetchError(String),
+	#[error("Directory was not signed by the expected authority")]
+	InvalidSignature,
+	#[error("Directory is {age_s} seconds old, but may be at most {max_age_s}")]
+	Expired { age_s: u64, max_age_s: u64 },
+	#[error("Mix node {0} is listed more than once")]
+	DuplicateMixnode(String),
+	#[error("Route needs {requested} hops, but the directory only lists {available} mix nodes")]
+	NotEnoughMixnodes { requested: usize, available: usize },
+	#[error("Route must have at least one hop")]
+	EmptyRoute,
+	#[error("Route has {0} hops, more than the maximum of {max}", max = onion::MAX_HOPS)]
+	TooManyHops(usize),
+	#[error("Fee of {0} is too large for a single hop")]
+	FeeTooLarge(u64),
+	#[error("Coin value {value} doesn't cover the route's fees of {fees}")]
+	InsufficientValue { value: u64, fees: u64 },
+	#[error("Output value {value} is below the minimum of {min_output_value}")]
+	OutputTooSmall { value: u64, min_output_value: u64 },
+	#[error("Error building onion: {0}")]
+	OnionError(OnionError),
+	#[error("Error building owner kernel: {0}")]
+	OwnerKernelError(String),
+	#[error("Secp error: {0}")]
+	SecpError(String),
+}
+
+impl Directory {
+	/// The message signed by the authority:
+	/// Blake2b("MWIXNET_DIRECTORY" || timestamp || (pubkey || url_len || url || fee)*)
+	pub fn message(mixnodes: &Vec<Mixnode>, timestamp: u64) -> Result<Message, DirectoryError> {
+		let secp = Secp256k1::new();
+		let mut hasher = Blake2b::new(32);
+		hasher.update(b"MWIXNET_DIRECTORY");
+		let mut int_bytes = [0; 8];
+		BigEndian::write_u64(&mut int_bytes, timestamp);
+		hasher.update(&int_bytes);
+		for mixnode in mixnodes {
+			hasher.update(&mixnode.pubkey.serialize_vec(&secp, true)[..]);
+			BigEndian::write_u64(&mut int_bytes, mixnode.url.len() as u64);
+			hasher.update(&int_bytes);
+			hasher.update(mixnode.url.as_bytes());
+			BigEndian::write_u64(&mut int_bytes, mixnode.fee);
+			hasher.update(&int_bytes);
+		}
+
+		Message::from_slice(hasher.finalize().as_bytes())
+			.map_err(|e| DirectoryError::SecpError(e.to_string()))
+	}
+
+	/// Signs the list of mix nodes with the authority's key
+	pub fn sign(
+		mixnodes: Vec<Mixnode>,
+		timestamp: u64,
+		authority_key: &SecretKey,
+	) -> Result<Directory, DirectoryError> {
+		let msg = Directory::message(&mixnodes, timestamp)?;
+		let signature = secp::sign(authority_key, &msg)
+			.map_err(|e| DirectoryError::SecpError(e.to_string()))?;
+		let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), authority_key)
+			.map_err(|e| DirectoryError::SecpError(e.to_string()))?;
+		Ok(Directory {
+			mixnodes,
+			timestamp,
+			pubkey,
+			signature,
+		})
+	}
+
+	/// Checks that the directory was signed by 'authority', is no older than 'max_age_s'
+	/// as of unix time 'now', and lists each mix node only once
+	pub fn validate(
+		&self,
+		authority: &PublicKey,
+		max_age_s: u64,
+		now: u64,
+	) -> Result<(), DirectoryError> {
+		let msg = Directory::message(&self.mixnodes, self.timestamp)?;
+		if &self.pubkey != authority || !secp::verify(&self.pubkey, &self.signature, &msg) {
+			return Err(DirectoryError::InvalidSignature);
+		}
+
+		let age_s = now.saturating_sub(self.timestamp);
+		if age_s > max_age_s {
+			return Err(DirectoryError::Expired { age_s, max_age_s });
+		}
+
+		let secp = Secp256k1::new();
+		let mut seen = HashSet::new();
+		for mixnode in &self.mixnodes {
+			let pubkey = mixnode.pubkey.serialize_vec(&secp, true);
+			if !seen.insert(pubkey.to_vec()) {
+				return Err(DirectoryError::DuplicateMixnode(grin_util::to_hex(
+					&pubkey[..],
+				)));
+			}
+		}
+		Ok(())
+	}
+
+	/// Picks 'num_hops' distinct mix nodes at random, in the order the swap will visit them.
+	/// Nodes whose URL is in 'unavailable' are skipped.
+	pub fn pick_route(
+		&self,
+		num_hops: usize,
+		unavailable: &HashSet<String>,
+	) -> Result<Vec<Mixnode>, DirectoryError> {
+		if num_hops == 0 {
+			return Err(DirectoryError::EmptyRoute);
+		}
+		if num_hops > onion::MAX_HOPS {
+			return Err(DirectoryError::TooManyHops(num_hops));
+		}
+		let candidates: Vec<&Mixnode> = self
+			.mixnodes
+			.iter()
+			.filter(|m| !unavailable.contains(&m.url))
+			.collect();
+		if num_hops > candidates.len() {
+			return Err(DirectoryError::NotEnoughMixnodes {
+				requested: num_hops,
+				available: candidates.len(),
+			});
+		}
+
+		Ok(candidates
+			.choose_multiple(&mut rand::thread_rng(), num_hops)
+			.map(|m| (*m).clone())
+			.collect())
+	}
+}
+
+/// Fetches a directory from a URL, or reads it from a local file
+pub fn fetch(location: &str, client: &HttpClient) -> Result<Directory, DirectoryError> {
+	if location.starts_with("http://") || location.starts_with("https://") {
+		client
+			.get::<Directory>(location, None)
+			.map_err(|e| DirectoryError::FetchError(e.to_string()))
+	} else {
+		let json = std::fs::read_to_string(location)
+			.map_err(|e| DirectoryError::FetchError(e.to_string()))?;
+		serde_json::from_str(&json).map_err(|e| DirectoryError::FetchError(e.to_string()))
+	}
+}
+
+/// A swap of a coin along a route of mix nodes, ready to submit to the route's first node
+#[derive(Clone)]
+pub struct RouteSwap {
+	pub route: Vec<Mixnode>,
+	pub onion: Onion,
+	pub comsig: ComSignature,
+	pub expiry_height: u64,
+	/// value of the output the swap creates, after every hop's fee
+	pub output_value: u64,
+	/// blinding factor of the output the swap creates: the coin's plus every hop's excess
+	pub output_blind: SecretKey,
+}
+
+/// Builds an onion that moves the coin (value, blind) through each mix node in 'route'.
+/// Every hop gets a random excess and the fee its node asks for, and the last hop carries
+/// the rangeproof for the final output.
+/// With 'owner_kernel', part of the output's blinding factor is kept from every node,
+/// and signed for with a kernel carried by the last hop instead.
+/// With 'view_tags', each layer is tagged for its node, which every node must support.
+/// Layers are encrypted with 'cipher_suite', which every node must accept.
+pub fn build_swap(
+	route: &Vec<Mixnode>,
+	value: u64,
+	blind: &SecretKey,
+	expiry_height: u64,
+	owner_kernel: bool,
+	min_output_value: Option<u64>,
+	view_tags: bool,
+	cipher_suite: CipherSuite,
+) -> Result<RouteSwap, DirectoryError> {
+	let first = route.first().ok_or(DirectoryError::EmptyRoute)?;
+	if route.len() > onion::MAX_HOPS {
+		return Err(DirectoryError::TooManyHops(route.len()));
+	}
+	let fees: u64 = route.iter().map(|m| m.fee).sum();
+	if value <= fees {
+		return Err(DirectoryError::InsufficientValue { value, fees });
+	}
+	let output_value = value - fees;
+	if let Some(min_output_value) = min_output_value {
+		if output_value < min_output_value {
+			return Err(DirectoryError::OutputTooSmall {
+				value: output_value,
+				min_output_value,
+			});
+		}
+	}
+
+	let secp = Secp256k1::new();
+	let excesses: Vec<SecretKey> = route.iter().map(|_| secp::random_secret()).collect();
+	let mut output_blind = blind.clone();
+	for excess in &excesses {
+		output_blind
+			.add_assign(&secp, excess)
+			.map_err(|e| DirectoryError::SecpError(e.to_string()))?;
+	}
+	let owner_kernel = if owner_kernel {
+		let owner_excess = secp::random_secret();
+		output_blind
+			.add_assign(&secp, &owner_excess)
+			.map_err(|e| DirectoryError::SecpError(e.to_string()))?;
+		let kernel = wallet::build_owner_kernel(&owner_excess)
+			.map_err(|e| DirectoryError::OwnerKernelError(e.to_string()))?;
+		Some(kernel)
+	} else {
+		None
+	};
+
+	let hops = route
+		.iter()
+		.zip(excesses)
+		.enumerate()
+		.map(|(i, (mixnode, excess))| {
+			let fee =
+				u32::try_from(mixnode.fee).map_err(|_| DirectoryError::FeeTooLarge(mixnode.fee))?;
+			let last = i == route.len() - 1;
+			Ok(Hop {
+				pubkey: mixnode.pubkey.clone(),
+				payload: Payload {
+					excess,
+					fee: FeeFields::from(fee),
+					rangeproof: if last {
+						Some(build_proof(output_value, &output_blind))
+					} else {
+						None
+					},
+					owner_kernel: if last { owner_kernel.clone() } else { None },
+					min_value_proof: match min_output_value {
+						Some(min) if last => Some(build_proof(output_value - min, &output_blind)),
+						_ => None,
+					},
+					next_view_tag: None,
+					fee_change: None,
+				},
+			})
+		})
+		.collect::<Result<Vec<Hop>, DirectoryError>>()?;
+
+	let commit =
+		secp::commit(value, blind).map_err(|e| DirectoryError::SecpError(e.to_string()))?;
+	let onion = onion::build_onion(&commit, &hops, view_tags, cipher_suite)
+		.map_err(DirectoryError::OnionError)?;
+	let msg = onion
+		.serialize()
+		.map_err(|e| DirectoryError::OnionError(OnionError::SerializationError(e)))?;
+	let comsig = ComSignature::sign(
+		value,
+		blind,
+		&SigDomain::new(&first.pubkey, expiry_height),
+		&msg,
+	)
+	.map_err(|e| DirectoryError::SecpError(e.to_string()))?;
+
+	Ok(RouteSwap {
+		route: route.clone(),
+		onion,
+		comsig,
+		expiry_height,
+		output_value,
+		output_blind,
+	})
+}
+
+fn build_proof(value: u64, blind: &SecretKey) -> RangeProof {
+	let secp = Secp256k1::new();
+	let nonce = secp::random_secret();
+	secp.bullet_proof(value, blind.clone(), nonce.clone(), nonce, None, None)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{build_swap, Directory, DirectoryError, Mixnode};
+	use crate::onion::{self, CipherSuite};
+	use crate::secp::{self, PublicKey, Secp256k1, SecretKey, SigDomain};
+
+	use std::collections::HashSet;
+
+	fn mixnodes(n: usize) -> (Vec<SecretKey>, Vec<Mixnode>) {
+		let secp = Secp256k1::new();
+		let keys: Vec<SecretKey> = (0..n).map(|_| secp::random_secret()).collect();
+		let mixnodes = keys
+			.iter()
+			.enumerate()
+			.map(|(i, key)| Mixnode {
+				pubkey: PublicKey::from_secret_key(&secp, key).unwrap(),
+				url: format!("http://mix{}.example.com:3000", i),
+				fee: 50_000_000,
+			})
+			.collect();
+		(keys, mixnodes)
+	}
+
+	/// Only untampered, recent directories from the expected authority are accepted
+	#[test]
+	fn validate() -> Result<(), Box<dyn std::error::Error>> {
+		let authority_key = secp::random_secret();
+		let authority = PublicKey::from_secret_key(&Secp256k1::new(), &authority_key)?;
+		let (_, nodes) = mixnodes(3);
+		let directory = Directory::sign(nodes.clone(), 1_000, &authority_key)?;
+		directory.validate(&authority, 100, 1_050)?;
+
+		assert_eq!(
+			Err(DirectoryError::Expired {
+				age_s: 200,
+				max_age_s: 100
+			}),
+			directory.validate(&authority, 100, 1_200)
+		);
+
+		let other = PublicKey::from_secret_key(&Secp256k1::new(), &secp::random_secret())?;
+		assert_eq!(
+			Err(DirectoryError::InvalidSignature),
+			directory.validate(&other, 100, 1_050)
+		);
+
+		let mut tampered = directory.clone();
+		tampered.mixnodes[1].fee = 0;
+		assert_eq!(
+			Err(DirectoryError::InvalidSignature),
+			tampered.validate(&authority, 100, 1_050)
+		);
+
+		let mut duplicated = nodes.clone();
+		duplicated.push(nodes[0].clone());
+		let directory = Directory::sign(duplicated, 1_000, &authority_key)?;
+		assert!(matches!(
+			directory.validate(&authority, 100, 1_050),
+			Err(DirectoryError::DuplicateMixnode(_))
+		));
+
+		Ok(())
+	}
+
+	#[test]
+	fn pick_route() -> Result<(), Box<dyn std::error::Error>> {
+		let (_, nodes) = mixnodes(5);
+		let directory = Directory::sign(nodes, 1_000, &secp::random_secret())?;
+
+		let none = HashSet::new();
+		let route = directory.pick_route(3, &none)?;
+		assert_eq!(3, route.len());
+		let urls: HashSet<&String> = route.iter().map(|m| &m.url).collect();
+		assert_eq!(3, urls.len());
+
+		assert_eq!(
+			Err(DirectoryError::NotEnoughMixnodes {
+				requested: 6,
+				available: 5
+			}),
+			directory.pick_route(6, &none)
+		);
+		assert_eq!(
+			Err(DirectoryError::EmptyRoute),
+			directory.pick_route(0, &none)
+		);
+		assert_eq!(
+			Err(DirectoryError::TooManyHops(onion::MAX_HOPS + 1)),
+			directory.pick_route(onion::MAX_HOPS + 1, &none)
+		);
+
+		// Unavailable nodes are left out of the route
+		let unavailable: HashSet<String> = directory.mixnodes[..2]
+			.iter()
+			.map(|m| m.url.clone())
+			.collect();
+		let route = directory.pick_route(3, &unavailable)?;
+		assert!(route.iter().all(|m| !unavailable.contains(&m.url)));
+		assert_eq!(
+			Err(DirectoryError::NotEnoughMixnodes {
+				requested: 4,
+				available: 3
+			}),
+			directory.pick_route(4, &unavailable)
+		);
+
+		Ok(())
+	}
+
+	/// Each node along the route can peel its layer, and the final output matches the one returned
+	#[test]
+	fn build_route_swap() -> Result<(), Box<dyn std::error::Error>> {
+		let (keys, route) = mixnodes(3);
+		let value = 1_000_000_000;
+		let blind = secp::random_secret();
+		let commit = secp::commit(value, &blind)?;
+
+		let swap = build_swap(
+			&route,
+			value,
+			&blind,
+			500,
+			false,
+			None,
+			true,
+			CipherSuite::ChaCha20Rekeyed,
+		)?;
+		assert_eq!(850_000_000, swap.output_value);
+		swap.comsig.verify(
+			&commit,
+			&SigDomain::new(&route[0].pubkey, 500),
+			&swap.onion.serialize()?,
+		)?;
+
+		let mut onion = swap.onion.clone();
+		let mut output = commit;
+		for (i, key) in keys.iter().enumerate() {
+			assert!(onion.matches_view_tag(key));
+			let (payload, peeled) = onion.peel_layer(key)?;
+			output = secp::add_excess(&output, &payload.excess)?;
+			output = secp::sub_value(&output, route[i].fee)?;
+			assert_eq!(i == keys.len() - 1, payload.rangeproof.is_some());
+			onion = peeled;
+		}
+		assert!(onion.enc_payloads.is_empty());
+		assert_eq!(secp::commit(swap.output_value, &swap.output_blind)?, output);
+
+		assert_eq!(
+			Err(DirectoryError::InsufficientValue {
+				value: 100_000_000,
+				fees: 150_000_000
+			}),
+			build_swap(
+				&route,
+				100_000_000,
+				&blind,
+				500,
+				false,
+				None,
+				false,
+				CipherSuite::ChaCha20
+			)
+			.map(|_| ())
+		);
+
+		Ok(())
+	}
+
+	/// The last hop gets a proof that the output is worth at least the minimum
+	#[test]
+	fn build_route_swap_min_value() -> Result<(), Box<dyn std::error::Error>> {
+		let (keys, route) = mixnodes(2);
+		let value = 1_000_000_000;
+		let blind = secp::random_secret();
+
+		let swap = build_swap(
+			&route,
+			value,
+			&blind,
+			500,
+			false,
+			Some(800_000_000),
+			false,
+			CipherSuite::ChaCha20,
+		)?;
+		let (first, onion) = swap.onion.peel_layer(&keys[0])?;
+		let (last, onion) = onion.peel_layer(&keys[1])?;
+		assert!(first.min_value_proof.is_none());
+		let proof = last.min_value_proof.unwrap();
+		let commit = secp::sub_value(&onion.commit, 800_000_000)?;
+		assert!(Secp256k1::new()
+			.verify_bullet_proof(commit, proof, None)
+			.is_ok());
+
+		assert_eq!(
+			Err(DirectoryError::OutputTooSmall {
+				value: 900_000_000,
+				min_output_value: 950_000_000
+			}),
+			build_swap(
+				&route,
+				value,
+				&blind,
+				500,
+				false,
+				Some(950_000_000),
+				false,
+				CipherSuite::ChaCha20
+			)
+			.map(|_| ())
+		);
+
+		Ok(())
+	}
+
+	/// The owner kernel's excess makes up the part of the output's blind the nodes don't know
+	#[test]
+	fn build_route_swap_owner_kernel() -> Result<(), Box<dyn std::error::Error>> {
+		let (keys, route) = mixnodes(2);
+		let value = 1_000_000_000;
+		let blind = secp::random_secret();
+
+		let swap = build_swap(
+			&route,
+			value,
+			&blind,
+			500,
+			true,
+			None,
+			false,
+			CipherSuite::ChaCha20,
+		)?;
+		let (first, onion) = swap.onion.peel_layer(&keys[0])?;
+		let (last, onion) = onion.peel_layer(&keys[1])?;
+		assert!(first.owner_kernel.is_none());
+		let owner_kernel = last.owner_kernel.unwrap();
+
+		let mut nodes_blind = blind.clone();
+		nodes_blind.add_assign(&Secp256k1::new(), &first.excess)?;
+		nodes_blind.add_assign(&Secp256k1::new(), &last.excess)?;
+		let known = secp::commit(swap.output_value, &nodes_blind)?;
+		assert_ne!(known, onion.commit);
+		assert_eq!(
+			secp::add_commits(&known, &owner_kernel.excess)?,
+			onion.commit
+		);
+		assert_eq!(
+			secp::commit(swap.output_value, &swap.output_blind)?,
+			onion.commit
+		);
+
+		Ok(())
+	}
+}
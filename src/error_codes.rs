@@ -0,0 +1,428 @@
+use crate::server::{SwapError, MAX_EXPIRY_BLOCKS};
+
+use grin_util::ToHex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// JSON-RPC error code for errors the server doesn't expect, e.g. a failing dependency
+const INTERNAL_ERROR_CODE: i64 = -32603;
+
+/// Stable JSON-RPC error code for each kind of swap error, by name. Codes are never reused, so
+/// clients can rely on them across versions. Kinds that should be retried share the codes of the
+/// other "busy" responses.
+const SWAP_ERROR_CODES: [(&str, i64, &str); 31] = [
+	(
+		"invalid_payload_length",
+		-32010,
+		"The onion has the wrong number of payloads",
+	),
+	(
+		"invalid_comsig",
+		-32011,
+		"The commitment signature doesn't verify",
+	),
+	(
+		"invalid_expiry",
+		-32012,
+		"The expiry height has passed, or is too far away",
+	),
+	(
+		"invalid_rangeproof",
+		-32013,
+		"The rangeproof doesn't verify",
+	),
+	(
+		"missing_rangeproof",
+		-32014,
+		"The last hop's payload has no rangeproof",
+	),
+	(
+		"output_too_small",
+		-32015,
+		"The output isn't proven to be worth min_output_value",
+	),
+	(
+		"invalid_owner_kernel",
+		-32016,
+		"The owner kernel isn't a valid, fee-less plain kernel",
+	),
+	(
+		"peel_onion_failure",
+		-32017,
+		"The onion's layer can't be decrypted or decoded",
+	),
+	(
+		"fee_too_low",
+		-32018,
+		"The fee is below the server's minimum",
+	),
+	(
+		"unsupported_cipher_suite",
+		-32019,
+		"The onion is encrypted with a cipher suite the server doesn't accept",
+	),
+	(
+		"coin_not_found",
+		-32020,
+		"The input doesn't exist, or is already spent",
+	),
+	(
+		"coin_not_mature",
+		-32021,
+		"The input is an immature coinbase output",
+	),
+	(
+		"unconfirmed_limit_reached",
+		-32022,
+		"Too many swaps of unconfirmed inputs are pending",
+	),
+	(
+		"already_swapped",
+		-32023,
+		"The input already has a swap pending",
+	),
+	(
+		"invalid_fee_change",
+		-32024,
+		"The fee change isn't on the last hop with an owner kernel, leaves none of the fee, or doesn't verify",
+	),
+	(
+		"policy_rejected",
+		-32025,
+		"The input or output is refused by the server's policy, e.g. its deny list",
+	),
+	(
+		"duplicate_output",
+		-32026,
+		"A swap from another input already creates the output",
+	),
+	(
+		"insufficient_confirmations",
+		-32027,
+		"The input has fewer confirmations than the server requires",
+	),
+	("swap_not_found", -32030, "No swap exists for the output"),
+	(
+		"receipt_not_found",
+		-32031,
+		"The swap hasn't been included in a round yet",
+	),
+	(
+		"swap_not_cancellable",
+		-32032,
+		"The swap has already been included in a round",
+	),
+	(
+		"node_stalled",
+		-32040,
+		"Rounds are paused while the node's chain tip is stalled",
+	),
+	(
+		"not_primary",
+		-32041,
+		"The server is a standby, not the active primary",
+	),
+	(
+		"window_closed",
+		-32042,
+		"The round's acceptance window has closed",
+	),
+	(
+		"submissions_paused",
+		-32043,
+		"The operator has paused accepting swaps",
+	),
+	(
+		"rounds_paused",
+		-32044,
+		"The operator has paused executing rounds",
+	),
+	(
+		"wallet_not_ready",
+		-32045,
+		"Rounds are held back while the server's wallet is unreachable or locked",
+	),
+	(
+		"round_in_progress",
+		-32046,
+		"A round is still executing, so another can't start",
+	),
+	(
+		"store_error",
+		-32050,
+		"The swap couldn't be saved to the server's database",
+	),
+	(
+		"verifier_busy",
+		-32001,
+		"Too many swaps are waiting to be verified. Retry shortly",
+	),
+	("unknown", INTERNAL_ERROR_CODE, "An unexpected error"),
+];
+
+/// A kind of error, and the code clients receive for it, as published by 'get_info'
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ErrorCodeInfo {
+	pub name: String,
+	pub code: i64,
+	pub description: String,
+}
+
+/// The table of error codes swaps may be rejected with
+pub fn error_codes() -> Vec<ErrorCodeInfo> {
+	SWAP_ERROR_CODES
+		.iter()
+		.map(|(name, code, description)| ErrorCodeInfo {
+			name: name.to_string(),
+			code: *code,
+			description: description.to_string(),
+		})
+		.collect()
+}
+
+/// Short name for the kind of swap error
+pub fn error_name(e: &SwapError) -> &'static str {
+	match e {
+		SwapError::InvalidPayloadLength { .. } => "invalid_payload_length",
+		SwapError::InvalidComSignature => "invalid_comsig",
+		SwapError::InvalidExpiry { .. } => "invalid_expiry",
+		SwapError::InvalidRangeproof => "invalid_rangeproof",
+		SwapError::MissingRangeproof => "missing_rangeproof",
+		SwapError::OutputTooSmall { .. } => "output_too_small",
+		SwapError::InvalidOwnerKernel => "invalid_owner_kernel",
+		SwapError::InvalidFeeChange(_) => "invalid_fee_change",
+		SwapError::CoinNotFound { .. } => "coin_not_found",
+		SwapError::CoinNotMature { .. } => "coin_not_mature",
+		SwapError::UnconfirmedLimitReached { .. } => "unconfirmed_limit_reached",
+		SwapError::InsufficientConfirmations { .. } => "insufficient_confirmations",
+		SwapError::AlreadySwapped { .. } => "already_swapped",
+		SwapError::DuplicateOutput { .. } => "duplicate_output",
+		SwapError::PeelOnionFailure(_) => "peel_onion_failure",
+		SwapError::PolicyRejected { .. } => "policy_rejected",
+		SwapError::UnsupportedCipherSuite { .. } => "unsupported_cipher_suite",
+		SwapError::FeeTooLow { .. } => "fee_too_low",
+		SwapError::StoreError(_) => "store_error",
+		SwapError::SwapNotFound { .. } => "swap_not_found",
+		SwapError::ReceiptNotFound { .. } => "receipt_not_found",
+		SwapError::SwapNotCancellable { .. } => "swap_not_cancellable",
+		SwapError::NodeStalled => "node_stalled",
+		SwapError::NotPrimary(_) => "not_primary",
+		SwapError::WindowClosed { .. } => "window_closed",
+		SwapError::SubmissionsPaused => "submissions_paused",
+		SwapError::RoundsPaused => "rounds_paused",
+		SwapError::WalletNotReady(_) => "wallet_not_ready",
+		SwapError::RoundInProgress { .. } => "round_in_progress",
+		SwapError::VerifierBusy => "verifier_busy",
+		SwapError::UnknownError(_) => "unknown",
+	}
+}
+
+/// The JSON-RPC error code for the swap error
+pub fn error_code(e: &SwapError) -> i64 {
+	let name = error_name(e);
+	SWAP_ERROR_CODES
+		.iter()
+		.find(|(n, _, _)| *n == name)
+		.map(|(_, code, _)| *code)
+		.unwrap_or(INTERNAL_ERROR_CODE)
+}
+
+/// Machine-readable details of the swap error: its name, plus any values a client needs to
+/// correct the request (e.g. the minimum fee)
+pub fn error_data(e: &SwapError) -> Value {
+	let details = match e {
+		SwapError::InvalidPayloadLength { expected, found } => {
+			json!({ "expected": expected, "found": found })
+		}
+		SwapError::InvalidExpiry {
+			expiry_height,
+			height,
+		} => json!({
+			"expiry_height": expiry_height,
+			"height": height,
+			"max_expiry_blocks": MAX_EXPIRY_BLOCKS,
+		}),
+		SwapError::OutputTooSmall { min_output_value } => {
+			json!({ "min_output_value": min_output_value })
+		}
+		SwapError::CoinNotFound { commit }
+		| SwapError::AlreadySwapped { commit }
+		| SwapError::DuplicateOutput { commit }
+		| SwapError::SwapNotFound { commit }
+		| SwapError::ReceiptNotFound { commit }
+		| SwapError::SwapNotCancellable { commit } => json!({ "commit": commit.to_hex() }),
+		SwapError::UnconfirmedLimitReached { commit, limit } => {
+			json!({ "commit": commit.to_hex(), "limit": limit })
+		}
+		SwapError::CoinNotMature {
+			commit,
+			spendable_height,
+		} => json!({ "commit": commit.to_hex(), "spendable_height": spendable_height }),
+		SwapError::InsufficientConfirmations {
+			commit,
+			confirmations,
+			required,
+		} => json!({
+			"commit": commit.to_hex(),
+			"confirmations": confirmations,
+			"required": required,
+		}),
+		SwapError::FeeTooLow {
+			minimum_fee,
+			actual_fee,
+		} => json!({ "minimum_fee": minimum_fee, "actual_fee": actual_fee }),
+		SwapError::WindowClosed { round_id } => json!({ "round_id": round_id }),
+		SwapError::RoundInProgress { phase } => json!({ "phase": phase }),
+		SwapError::PolicyRejected { commit, reason } => {
+			json!({ "commit": commit.to_hex(), "reason": reason })
+		}
+		SwapError::UnsupportedCipherSuite {
+			cipher_suite,
+			accepted,
+		} => json!({ "cipher_suite": cipher_suite, "accepted": accepted }),
+		_ => json!({}),
+	};
+	let mut data = json!({ "name": error_name(e) });
+	if let (Some(data), Value::Object(details)) = (data.as_object_mut(), details) {
+		data.extend(details);
+	}
+	data
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{error_code, error_codes, error_data, error_name, SWAP_ERROR_CODES};
+	use crate::onion::CipherSuite;
+	use crate::secp::{self, Commitment};
+	use crate::server::{RoundPhase, SwapError};
+	use crate::store::StoreError;
+
+	use grin_util::ToHex;
+	use serde_json::json;
+	use std::collections::HashSet;
+
+	/// Every kind of error has exactly one entry in the table, with a code of its own
+	#[test]
+	fn codes_are_unique() {
+		let commit = Commitment::from_vec(vec![0u8; 33]);
+		let errors = vec![
+			SwapError::InvalidPayloadLength {
+				expected: 1,
+				found: 2,
+			},
+			SwapError::InvalidComSignature,
+			SwapError::InvalidExpiry {
+				expiry_height: 1,
+				height: 2,
+			},
+			SwapError::InvalidRangeproof,
+			SwapError::MissingRangeproof,
+			SwapError::OutputTooSmall {
+				min_output_value: 1,
+			},
+			SwapError::InvalidOwnerKernel,
+			SwapError::InvalidFeeChange("change".to_string()),
+			SwapError::CoinNotFound {
+				commit: commit.clone(),
+			},
+			SwapError::CoinNotMature {
+				commit: commit.clone(),
+				spendable_height: 1,
+			},
+			SwapError::UnconfirmedLimitReached {
+				commit: commit.clone(),
+				limit: 1,
+			},
+			SwapError::InsufficientConfirmations {
+				commit: commit.clone(),
+				confirmations: 1,
+				required: 10,
+			},
+			SwapError::AlreadySwapped {
+				commit: commit.clone(),
+			},
+			SwapError::DuplicateOutput {
+				commit: commit.clone(),
+			},
+			SwapError::PeelOnionFailure(crate::onion::OnionError::InvalidKeyLength),
+			SwapError::PolicyRejected {
+				commit: commit.clone(),
+				reason: "denied".to_string(),
+			},
+			SwapError::UnsupportedCipherSuite {
+				cipher_suite: CipherSuite::ChaCha20,
+				accepted: vec![CipherSuite::ChaCha20Rekeyed],
+			},
+			SwapError::FeeTooLow {
+				minimum_fee: 1,
+				actual_fee: 0,
+			},
+			SwapError::StoreError(StoreError::NotFound("swap".to_string())),
+			SwapError::SwapNotFound {
+				commit: commit.clone(),
+			},
+			SwapError::ReceiptNotFound {
+				commit: commit.clone(),
+			},
+			SwapError::SwapNotCancellable {
+				commit: commit.clone(),
+			},
+			SwapError::NodeStalled,
+			SwapError::NotPrimary("standby".to_string()),
+			SwapError::WindowClosed { round_id: 1 },
+			SwapError::SubmissionsPaused,
+			SwapError::RoundsPaused,
+			SwapError::WalletNotReady("locked".to_string()),
+			SwapError::RoundInProgress {
+				phase: RoundPhase::Posting,
+			},
+			SwapError::VerifierBusy,
+			SwapError::UnknownError("oops".to_string()),
+		];
+		assert_eq!(SWAP_ERROR_CODES.len(), errors.len());
+
+		let names: HashSet<&str> = errors.iter().map(error_name).collect();
+		assert_eq!(errors.len(), names.len());
+		assert!(names
+			.iter()
+			.all(|name| SWAP_ERROR_CODES.iter().any(|(n, _, _)| n == name)));
+
+		let codes: HashSet<i64> = errors.iter().map(error_code).collect();
+		assert_eq!(errors.len(), codes.len());
+		assert_eq!(
+			codes,
+			error_codes()
+				.iter()
+				.map(|c| c.code)
+				.collect::<HashSet<i64>>()
+		);
+	}
+
+	/// Errors carry the values needed to correct the request
+	#[test]
+	fn structured_data() -> Result<(), Box<dyn std::error::Error>> {
+		let fee_too_low = SwapError::FeeTooLow {
+			minimum_fee: 500,
+			actual_fee: 100,
+		};
+		assert_eq!(-32018, error_code(&fee_too_low));
+		assert_eq!(
+			json!({ "name": "fee_too_low", "minimum_fee": 500, "actual_fee": 100 }),
+			error_data(&fee_too_low)
+		);
+
+		let commit = secp::commit(1234, &secp::random_secret())?;
+		assert_eq!(
+			json!({ "name": "coin_not_found", "commit": commit.to_hex() }),
+			error_data(&SwapError::CoinNotFound {
+				commit: commit.clone()
+			})
+		);
+
+		assert_eq!(
+			json!({ "name": "node_stalled" }),
+			error_data(&SwapError::NodeStalled)
+		);
+		Ok(())
+	}
+}
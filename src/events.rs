@@ -0,0 +1,394 @@
+use crate::secp::{self, Commitment};
+
+use grin_util::{StopState, ToHex};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+use tracing::{debug, warn};
+use tungstenite::{Message, WebSocket};
+
+/// Most connections the event listener serves at once
+const MAX_CONNECTIONS: usize = 256;
+
+/// Most commitments a single connection may subscribe to
+const MAX_SWAP_SUBSCRIPTIONS: usize = 32;
+
+/// How long a connection waits for a client message before checking for new events
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An event published to WebSocket subscribers, serialized with its name in the 'event' field
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+	/// A swap was accepted into the swap list
+	SwapAccepted { commit: String },
+	/// A swap was included in a round transaction posted to the node
+	SwapIncluded {
+		commit: String,
+		round_id: u64,
+		kernel_hash: String,
+	},
+	/// The round transaction including the swap was confirmed on chain
+	SwapConfirmed {
+		commit: String,
+		round_id: u64,
+		height: u64,
+	},
+	/// The node rejected the round transaction because of the swap, so it was dropped from the round
+	SwapFailed { commit: String, round_id: u64 },
+	/// A round started building its transaction
+	RoundStarted { round_id: u64, swap_count: usize },
+	/// A round's transaction was posted to the node
+	RoundPosted {
+		round_id: u64,
+		kernel_hash: String,
+		swap_count: usize,
+	},
+	/// A round's transaction could not be built or posted
+	RoundFailed { round_id: u64, reason: String },
+	/// A round's transaction was confirmed on chain
+	RoundConfirmed {
+		round_id: u64,
+		kernel_hash: String,
+		height: u64,
+	},
+}
+
+impl Event {
+	/// The hex-encoded input commitment the event concerns, for swap events
+	fn commit(&self) -> Option<&str> {
+		match self {
+			Event::SwapAccepted { commit }
+			| Event::SwapIncluded { commit, .. }
+			| Event::SwapConfirmed { commit, .. }
+			| Event::SwapFailed { commit, .. } => Some(commit),
+			_ => None,
+		}
+	}
+}
+
+/// What a subscriber receives events for
+#[derive(Clone, Debug, PartialEq)]
+enum Topic {
+	/// events for the swap with this hex-encoded input commitment
+	Swap(String),
+	/// round lifecycle events
+	Rounds,
+}
+
+impl Topic {
+	fn matches(&self, event: &Event) -> bool {
+		match self {
+			Topic::Swap(commit) => event.commit() == Some(commit.as_str()),
+			Topic::Rounds => event.commit().is_none(),
+		}
+	}
+}
+
+/// Delivers published events to everyone subscribed to them
+#[derive(Clone, Default)]
+pub struct EventBus {
+	subscribers: Arc<Mutex<Vec<(Topic, Sender<Event>)>>>,
+}
+
+impl EventBus {
+	pub fn new() -> EventBus {
+		EventBus::default()
+	}
+
+	/// Sends the event to each matching subscriber, forgetting subscribers that have gone away
+	pub fn publish(&self, event: Event) {
+		let mut subscribers = self.subscribers.lock().unwrap();
+		subscribers
+			.retain(|(topic, sender)| !topic.matches(&event) || sender.send(event.clone()).is_ok());
+	}
+
+	/// Events for the swap spending 'commit' are sent to 'sender'
+	pub fn subscribe_swap(&self, commit: &Commitment, sender: Sender<Event>) {
+		self.subscribe(Topic::Swap(commit.to_hex()), sender);
+	}
+
+	/// Round lifecycle events are sent to 'sender'
+	pub fn subscribe_rounds(&self, sender: Sender<Event>) {
+		self.subscribe(Topic::Rounds, sender);
+	}
+
+	fn subscribe(&self, topic: Topic, sender: Sender<Event>) {
+		self.subscribers.lock().unwrap().push((topic, sender));
+	}
+}
+
+/// A subscription request sent by a WebSocket client
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "subscribe", rename_all = "snake_case")]
+enum SubscribeReq {
+	/// events for the swap with this hex-encoded input commitment
+	Swap { commit: String },
+	/// round lifecycle events, which are only for the operator holding the admin token
+	Rounds { token: String },
+}
+
+/// Handles one client message, subscribing the connection's 'sender' as requested.
+/// Returns the reply to send back to the client.
+fn handle_request(
+	text: &str,
+	bus: &EventBus,
+	sender: &Sender<Event>,
+	admin_token: Option<&str>,
+	swap_subscriptions: &mut usize,
+) -> serde_json::Value {
+	let req: SubscribeReq = match serde_json::from_str(text) {
+		Ok(req) => req,
+		Err(e) => return json!({ "error": format!("Invalid request: {}", e) }),
+	};
+
+	match req {
+		SubscribeReq::Swap { commit } => {
+			if *swap_subscriptions >= MAX_SWAP_SUBSCRIPTIONS {
+				return json!({ "error": "Too many subscriptions" });
+			}
+			match secp::commitment_from_hex(&commit) {
+				Some(commit) => {
+					bus.subscribe_swap(&commit, sender.clone());
+					*swap_subscriptions += 1;
+					json!({ "subscribed": "swap", "commit": commit.to_hex() })
+				}
+				None => json!({ "error": "Invalid commitment" }),
+			}
+		}
+		SubscribeReq::Rounds { token } => {
+			let authorized = admin_token
+				.map(|admin_token| {
+					ring::constant_time::verify_slices_are_equal(
+						token.as_bytes(),
+						admin_token.as_bytes(),
+					)
+					.is_ok()
+				})
+				.unwrap_or(false);
+			if authorized {
+				bus.subscribe_rounds(sender.clone());
+				json!({ "subscribed": "rounds" })
+			} else {
+				json!({ "error": "Invalid admin token" })
+			}
+		}
+	}
+}
+
+/// Whether the error only means there was nothing to read before the timeout
+fn is_timeout(e: &tungstenite::Error) -> bool {
+	match e {
+		tungstenite::Error::Io(e) => {
+			e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut
+		}
+		_ => false,
+	}
+}
+
+/// Completes the WebSocket handshake with a new client, then serves it
+fn serve_stream(
+	stream: TcpStream,
+	bus: EventBus,
+	admin_token: Option<String>,
+	stop_state: Arc<StopState>,
+) -> Result<(), Box<dyn std::error::Error>> {
+	stream.set_nonblocking(false)?;
+	let ws = tungstenite::accept(stream).map_err(|e| e.to_string())?;
+	ws.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+	serve_connection(ws, bus, admin_token, stop_state)
+}
+
+/// Serves a single client, until it disconnects or the server stops
+fn serve_connection(
+	mut ws: WebSocket<TcpStream>,
+	bus: EventBus,
+	admin_token: Option<String>,
+	stop_state: Arc<StopState>,
+) -> Result<(), Box<dyn std::error::Error>> {
+	let (sender, receiver): (Sender<Event>, Receiver<Event>) = channel();
+	let mut swap_subscriptions = 0;
+	while !stop_state.is_stopped() {
+		match ws.read_message() {
+			Ok(Message::Text(text)) => {
+				let reply = handle_request(
+					&text,
+					&bus,
+					&sender,
+					admin_token.as_deref(),
+					&mut swap_subscriptions,
+				);
+				ws.write_message(Message::Text(reply.to_string()))?;
+			}
+			Ok(Message::Close(_)) => break,
+			Ok(_) => {}
+			Err(e) if is_timeout(&e) => {}
+			Err(e) => return Err(e.into()),
+		}
+
+		while let Ok(event) = receiver.try_recv() {
+			let text = serde_json::to_string(&event).unwrap_or_default();
+			ws.write_message(Message::Text(text))?;
+		}
+	}
+
+	let _ = ws.close(None);
+	Ok(())
+}
+
+/// Listens for WebSocket clients on 'addr', serving each one events published on 'bus'.
+/// Clients subscribe to a swap's events by sending '{"subscribe": "swap", "commit": <hex>}',
+/// and to round events by sending '{"subscribe": "rounds", "token": <admin token>}'.
+/// Round subscriptions are refused when no 'admin_token' is given.
+pub fn start_ws(
+	addr: &SocketAddr,
+	bus: EventBus,
+	admin_token: Option<String>,
+	stop_state: Arc<StopState>,
+) -> std::io::Result<()> {
+	let listener = TcpListener::bind(addr)?;
+	listener.set_nonblocking(true)?;
+
+	let connections = Arc::new(AtomicUsize::new(0));
+	spawn(move || {
+		while !stop_state.is_stopped() {
+			let stream = match listener.accept() {
+				Ok((stream, _)) => stream,
+				Err(e) if e.kind() == ErrorKind::WouldBlock => {
+					sleep(POLL_INTERVAL);
+					continue;
+				}
+				Err(e) => {
+					warn!("Failed to accept event subscriber: {}", e);
+					continue;
+				}
+			};
+
+			if connections.load(Ordering::SeqCst) >= MAX_CONNECTIONS {
+				debug!("Too many event subscribers. Dropping connection.");
+				continue;
+			}
+			connections.fetch_add(1, Ordering::SeqCst);
+
+			let bus = bus.clone();
+			let admin_token = admin_token.clone();
+			let stop_state = stop_state.clone();
+			let connections = connections.clone();
+			spawn(move || {
+				if let Err(e) = serve_stream(stream, bus, admin_token, stop_state) {
+					debug!("Event subscriber disconnected: {}", e);
+				}
+				connections.fetch_sub(1, Ordering::SeqCst);
+			});
+		}
+	});
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{start_ws, Event, EventBus};
+	use crate::secp;
+
+	use grin_util::{StopState, ToHex};
+	use serde_json::{json, Value};
+	use std::net::TcpListener;
+	use std::sync::mpsc::channel;
+	use std::sync::Arc;
+	use std::thread::sleep;
+	use std::time::Duration;
+	use tungstenite::Message;
+
+	/// Subscribers only receive the events they subscribed to, and are forgotten once they go away
+	#[test]
+	fn event_bus() {
+		let bus = EventBus::new();
+		let commit = secp::test_util::rand_commit();
+		let (swap_sender, swap_receiver) = channel();
+		let (round_sender, round_receiver) = channel();
+		bus.subscribe_swap(&commit, swap_sender);
+		bus.subscribe_rounds(round_sender);
+
+		let accepted = Event::SwapAccepted {
+			commit: commit.to_hex(),
+		};
+		bus.publish(accepted.clone());
+		bus.publish(Event::SwapAccepted {
+			commit: secp::test_util::rand_commit().to_hex(),
+		});
+		let started = Event::RoundStarted {
+			round_id: 1,
+			swap_count: 2,
+		};
+		bus.publish(started.clone());
+
+		assert_eq!(vec![accepted], swap_receiver.try_iter().collect::<Vec<_>>());
+		assert_eq!(vec![started], round_receiver.try_iter().collect::<Vec<_>>());
+
+		drop(swap_receiver);
+		bus.publish(Event::SwapAccepted {
+			commit: commit.to_hex(),
+		});
+		assert_eq!(1, bus.subscribers.lock().unwrap().len());
+	}
+
+	/// Clients subscribe over a WebSocket, and only the admin may subscribe to round events
+	#[test]
+	fn websocket_subscription() -> Result<(), Box<dyn std::error::Error>> {
+		let addr = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+		let bus = EventBus::new();
+		let stop_state = Arc::new(StopState::new());
+		start_ws(
+			&addr,
+			bus.clone(),
+			Some("secret".to_string()),
+			stop_state.clone(),
+		)?;
+
+		let (mut ws, _) = tungstenite::connect(format!("ws://{}", addr))?;
+		let mut request = |req: Value| -> Result<Value, Box<dyn std::error::Error>> {
+			ws.write_message(Message::Text(req.to_string()))?;
+			Ok(serde_json::from_str(ws.read_message()?.to_text()?)?)
+		};
+
+		let commit = secp::test_util::rand_commit();
+		let reply = request(json!({"subscribe": "swap", "commit": commit.to_hex()}))?;
+		assert_eq!(Some("swap"), reply["subscribed"].as_str());
+		let reply = request(json!({"subscribe": "swap", "commit": "00"}))?;
+		assert_eq!(Some("Invalid commitment"), reply["error"].as_str());
+		let reply = request(json!({"subscribe": "rounds", "token": "wrong"}))?;
+		assert_eq!(Some("Invalid admin token"), reply["error"].as_str());
+		let reply = request(json!({"subscribe": "rounds", "token": "secret"}))?;
+		assert_eq!(Some("rounds"), reply["subscribed"].as_str());
+
+		let included = Event::SwapIncluded {
+			commit: commit.to_hex(),
+			round_id: 3,
+			kernel_hash: "ab".repeat(32),
+		};
+		bus.publish(included.clone());
+		let event: Event = serde_json::from_str(ws.read_message()?.to_text()?)?;
+		assert_eq!(included, event);
+
+		let confirmed = Event::RoundConfirmed {
+			round_id: 3,
+			kernel_hash: "ab".repeat(32),
+			height: 100,
+		};
+		bus.publish(confirmed.clone());
+		let event: Value = serde_json::from_str(ws.read_message()?.to_text()?)?;
+		assert_eq!(Some("round_confirmed"), event["event"].as_str());
+		assert_eq!(confirmed, serde_json::from_value(event)?);
+
+		stop_state.stop();
+		sleep(Duration::from_millis(200));
+		Ok(())
+	}
+}
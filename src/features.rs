@@ -0,0 +1,157 @@
+use grin_core::ser::{self, Readable, Reader, Writeable, Writer};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// A part of the server that operators can pause at runtime, without a restart
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feature {
+	/// accepting new swaps, whether over the JSON-RPC API or Nostr
+	AcceptSubmissions,
+	/// executing rounds, whether scheduled or triggered over the admin API
+	ExecuteRounds,
+}
+
+pub const ALL_FEATURES: [Feature; 2] = [Feature::AcceptSubmissions, Feature::ExecuteRounds];
+
+impl Feature {
+	pub fn name(&self) -> &'static str {
+		match self {
+			Feature::AcceptSubmissions => "accept_submissions",
+			Feature::ExecuteRounds => "execute_rounds",
+		}
+	}
+
+	/// The bit recorded in the store while the feature is disabled.
+	/// Bits must never be reused, so flags saved by other versions are read correctly.
+	fn disabled_bit(&self) -> u64 {
+		match self {
+			Feature::AcceptSubmissions => 1 << 0,
+			Feature::ExecuteRounds => 1 << 1,
+		}
+	}
+}
+
+/// Error types for parsing features
+#[derive(Clone, Error, Debug, PartialEq)]
+pub enum FeatureError {
+	#[error("Unknown feature '{0}'")]
+	UnknownFeature(String),
+}
+
+impl FromStr for Feature {
+	type Err = FeatureError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		ALL_FEATURES
+			.iter()
+			.find(|f| f.name() == s.replace('-', "_"))
+			.cloned()
+			.ok_or_else(|| FeatureError::UnknownFeature(s.to_string()))
+	}
+}
+
+impl fmt::Display for Feature {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.name())
+	}
+}
+
+/// Which features are enabled. Everything is enabled until an operator disables it.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FeatureFlags {
+	pub accept_submissions: bool,
+	pub execute_rounds: bool,
+}
+
+impl Default for FeatureFlags {
+	fn default() -> Self {
+		FeatureFlags {
+			accept_submissions: true,
+			execute_rounds: true,
+		}
+	}
+}
+
+impl FeatureFlags {
+	pub fn is_enabled(&self, feature: Feature) -> bool {
+		match feature {
+			Feature::AcceptSubmissions => self.accept_submissions,
+			Feature::ExecuteRounds => self.execute_rounds,
+		}
+	}
+
+	pub fn set(&mut self, feature: Feature, enabled: bool) {
+		match feature {
+			Feature::AcceptSubmissions => self.accept_submissions = enabled,
+			Feature::ExecuteRounds => self.execute_rounds = enabled,
+		}
+	}
+}
+
+/// Only disabled features are recorded, so features added later start out enabled
+impl Writeable for FeatureFlags {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		let disabled = ALL_FEATURES
+			.iter()
+			.filter(|f| !self.is_enabled(**f))
+			.fold(0, |bits, f| bits | f.disabled_bit());
+		writer.write_u64(disabled)
+	}
+}
+
+impl Readable for FeatureFlags {
+	fn read<R: Reader>(reader: &mut R) -> Result<FeatureFlags, ser::Error> {
+		let disabled = reader.read_u64()?;
+		let mut flags = FeatureFlags::default();
+		for feature in ALL_FEATURES {
+			flags.set(feature, disabled & feature.disabled_bit() == 0);
+		}
+		Ok(flags)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Feature, FeatureError, FeatureFlags};
+
+	use grin_core::ser::{self, DeserializationMode, ProtocolVersion};
+
+	#[test]
+	fn parse() {
+		assert_eq!(Ok(Feature::AcceptSubmissions), "accept_submissions".parse());
+		assert_eq!(Ok(Feature::ExecuteRounds), "execute-rounds".parse());
+		assert_eq!(
+			Err(FeatureError::UnknownFeature("rounds".to_string())),
+			"rounds".parse::<Feature>()
+		);
+	}
+
+	/// Flags survive serialization, and unknown disabled bits are ignored
+	#[test]
+	fn serialization() -> Result<(), Box<dyn std::error::Error>> {
+		let mut flags = FeatureFlags::default();
+		flags.set(Feature::ExecuteRounds, false);
+		assert!(flags.is_enabled(Feature::AcceptSubmissions));
+		assert!(!flags.is_enabled(Feature::ExecuteRounds));
+
+		let bytes = ser::ser_vec(&flags, ProtocolVersion::local())?;
+		let read: FeatureFlags = ser::deserialize(
+			&mut &bytes[..],
+			ProtocolVersion::local(),
+			DeserializationMode::default(),
+		)?;
+		assert_eq!(flags, read);
+
+		let bytes = ser::ser_vec(&(1u64 << 40), ProtocolVersion::local())?;
+		let read: FeatureFlags = ser::deserialize(
+			&mut &bytes[..],
+			ProtocolVersion::local(),
+			DeserializationMode::default(),
+		)?;
+		assert_eq!(FeatureFlags::default(), read);
+
+		Ok(())
+	}
+}
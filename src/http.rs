@@ -0,0 +1,599 @@
+use grin_api::client;
+use grin_api::Error;
+use grin_util::to_base64;
+use hyper::Uri;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// How long to wait on the server, or the proxy in front of it, before giving up on a request
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Longest wait between two attempts at a request, however many retries came before
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+const SOCKS_VERSION: u8 = 5;
+const SOCKS_NO_AUTH: u8 = 0;
+const SOCKS_CONNECT: u8 = 1;
+const SOCKS_DOMAIN_NAME: u8 = 3;
+
+/// How often, and how patiently, a request is retried when it gets no response
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+	/// times a request is retried after its first attempt
+	pub retries: u32,
+	/// wait before the first retry, doubled for each one after it (up to a minute)
+	pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+	fn default() -> RetryPolicy {
+		RetryPolicy {
+			retries: 2,
+			backoff: Duration::from_secs(1),
+		}
+	}
+}
+
+impl RetryPolicy {
+	/// Wait before retry number 'retry' (counting from 0)
+	pub fn delay(&self, retry: u32) -> Duration {
+		self.backoff
+			.checked_mul(1u32.checked_shl(retry).unwrap_or(u32::MAX))
+			.unwrap_or(MAX_BACKOFF)
+			.min(MAX_BACKOFF)
+	}
+}
+
+/// Client for the server's outbound HTTP requests (to the node, wallet and other servers).
+/// Requests are sent directly, or tunnelled through a SOCKS5 proxy (e.g. Tor) when one is configured.
+/// Requests that fail to get a response, or get a server error, are retried with exponential backoff.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpClient {
+	/// address of the SOCKS5 proxy, as 'host:port'
+	proxy: Option<String>,
+	/// how long to wait on a connection, or for a response, before giving up
+	timeout: Duration,
+	retry: RetryPolicy,
+}
+
+impl Default for HttpClient {
+	fn default() -> HttpClient {
+		HttpClient::new(None)
+	}
+}
+
+impl HttpClient {
+	/// A client using the proxy at 'proxy' ('socks5://host:port', or just 'host:port'), if any.
+	/// Host names are resolved by the proxy, so .onion addresses can be reached through Tor.
+	pub fn new(proxy: Option<&str>) -> HttpClient {
+		let proxy = proxy.map(|p| {
+			p.trim_start_matches("socks5h://")
+				.trim_start_matches("socks5://")
+				.to_string()
+		});
+		HttpClient {
+			proxy,
+			timeout: DEFAULT_REQUEST_TIMEOUT,
+			retry: RetryPolicy::default(),
+		}
+	}
+
+	/// Gives up on connecting, or on waiting for a response, after 'timeout'
+	pub fn with_timeout(mut self, timeout: Duration) -> HttpClient {
+		self.timeout = timeout;
+		self
+	}
+
+	/// Retries requests that get no response as per 'retry'
+	pub fn with_retry(mut self, retry: RetryPolicy) -> HttpClient {
+		self.retry = retry;
+		self
+	}
+
+	/// Runs 'request' until it succeeds, fails for good, or runs out of retries.
+	/// Requests that got an answer, even an unsuccessful one, aren't worth retrying.
+	fn retrying<OUT>(
+		&self,
+		url: &str,
+		request: impl Fn() -> Result<OUT, Error>,
+	) -> Result<OUT, Error> {
+		let mut retry = 0;
+		loop {
+			match request() {
+				Err(Error::RequestError(e)) if retry < self.retry.retries => {
+					let delay = self.retry.delay(retry);
+					warn!(
+						"Request to {} failed, retrying in {}ms: {}",
+						url,
+						delay.as_millis(),
+						e
+					);
+					thread::sleep(delay);
+					retry += 1;
+				}
+				res => return res,
+			}
+		}
+	}
+
+	/// Sends the request itself, unless it's a direct request for an 'https' url,
+	/// which is left to the node's client so TLS is handled
+	fn send(
+		&self,
+		method: &str,
+		url: &str,
+		api_secret: Option<String>,
+		headers: &[(&str, String)],
+		body: Option<&[u8]>,
+	) -> Option<Result<Vec<u8>, Error>> {
+		if self.proxy.is_none() && !url.starts_with("http://") {
+			return None;
+		}
+		Some(self.retrying(url, || {
+			raw_request(
+				self.proxy.as_deref(),
+				self.timeout,
+				method,
+				url,
+				api_secret.clone(),
+				headers,
+				body,
+			)
+		}))
+	}
+
+	/// POSTs 'input' as JSON to 'url', returning the decoded JSON response
+	pub fn post<IN, OUT>(
+		&self,
+		url: &str,
+		api_secret: Option<String>,
+		input: &IN,
+	) -> Result<OUT, Error>
+	where
+		IN: Serialize,
+		OUT: DeserializeOwned,
+	{
+		let body = serde_json::to_vec(input)
+			.map_err(|e| Error::Internal(format!("Could not serialize request body: {}", e)))?;
+		match self.send("POST", url, api_secret.clone(), &[], Some(&body)) {
+			Some(res) => parse_json(&res?),
+			None => self.retrying(url, || client::post(url, api_secret.clone(), input)),
+		}
+	}
+
+	/// POSTs 'input' as JSON to 'url', ignoring any response body
+	pub fn post_no_ret<IN>(
+		&self,
+		url: &str,
+		api_secret: Option<String>,
+		input: &IN,
+	) -> Result<(), Error>
+	where
+		IN: Serialize,
+	{
+		let body = serde_json::to_vec(input)
+			.map_err(|e| Error::Internal(format!("Could not serialize request body: {}", e)))?;
+		match self.send("POST", url, api_secret.clone(), &[], Some(&body)) {
+			Some(res) => res.map(|_| ()),
+			None => self.retrying(url, || client::post_no_ret(url, api_secret.clone(), input)),
+		}
+	}
+
+	/// GETs the JSON document at 'url'
+	pub fn get<OUT>(&self, url: &str, api_secret: Option<String>) -> Result<OUT, Error>
+	where
+		OUT: DeserializeOwned,
+	{
+		match self.send("GET", url, api_secret.clone(), &[], None) {
+			Some(res) => parse_json(&res?),
+			None => self.retrying(url, || client::get(url, api_secret.clone())),
+		}
+	}
+
+	/// POSTs the JSON 'body' to 'url' with the extra 'headers', returning the decoded JSON response.
+	/// Used where the body has to be sent exactly as given, e.g. because the headers sign it.
+	pub fn post_with_headers<OUT>(
+		&self,
+		url: &str,
+		headers: &[(&str, String)],
+		body: &[u8],
+	) -> Result<OUT, Error>
+	where
+		OUT: DeserializeOwned,
+	{
+		let res = self.retrying(url, || {
+			raw_request(
+				self.proxy.as_deref(),
+				self.timeout,
+				"POST",
+				url,
+				None,
+				headers,
+				Some(body),
+			)
+		})?;
+		parse_json(&res)
+	}
+}
+
+fn parse_json<OUT: DeserializeOwned>(body: &[u8]) -> Result<OUT, Error> {
+	serde_json::from_slice(body)
+		.map_err(|e| Error::ResponseError(format!("Cannot parse response: {}", e)))
+}
+
+/// Sends the request through the SOCKS5 proxy, if any, returning the body of a successful response.
+/// Only plain 'http' urls are supported, since TLS would need to run inside the tunnel.
+#[allow(clippy::too_many_arguments)]
+fn raw_request(
+	proxy: Option<&str>,
+	timeout: Duration,
+	method: &str,
+	url: &str,
+	api_secret: Option<String>,
+	headers: &[(&str, String)],
+	body: Option<&[u8]>,
+) -> Result<Vec<u8>, Error> {
+	let uri: Uri = url
+		.parse()
+		.map_err(|e| Error::Argument(format!("Invalid url {}: {}", url, e)))?;
+	if uri.scheme_str() != Some("http") {
+		return Err(Error::Argument(format!(
+			"Only http urls can be requested here, not {}",
+			url
+		)));
+	}
+	let host = uri
+		.host()
+		.ok_or_else(|| Error::Argument(format!("Invalid url {}: missing host", url)))?;
+	let port = uri.port_u16().unwrap_or(80);
+	let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+
+	let mut stream = match proxy {
+		Some(proxy) => {
+			debug!("Requesting {} through proxy {}", url, proxy);
+			socks5_connect(proxy, host, port, timeout)?
+		}
+		None => direct_connect(host, port, timeout)?,
+	};
+
+	let mut req = format!(
+		"{} {} HTTP/1.1\r\nHost: {}:{}\r\nAccept: application/json\r\nConnection: close\r\n",
+		method, path, host, port
+	);
+	if let Some(api_secret) = api_secret {
+		let credentials = to_base64(&format!("grin:{}", api_secret));
+		req.push_str(&format!("Authorization: Basic {}\r\n", credentials));
+	}
+	for (name, value) in headers {
+		req.push_str(&format!("{}: {}\r\n", name, value));
+	}
+	if let Some(body) = body {
+		req.push_str("Content-Type: application/json\r\n");
+		req.push_str(&format!("Content-Length: {}\r\n", body.len()));
+	}
+	req.push_str("\r\n");
+
+	let mut data = req.into_bytes();
+	data.extend_from_slice(body.unwrap_or(&[]));
+	stream.write_all(&data).map_err(request_error)?;
+
+	let mut res = Vec::new();
+	stream.read_to_end(&mut res).map_err(request_error)?;
+	parse_response(&res)
+}
+
+/// Connects to the first of 'addr's addresses that accepts within 'timeout', and applies the
+/// timeout to reads and writes on the connection
+fn connect(addr: impl ToSocketAddrs, timeout: Duration) -> std::io::Result<TcpStream> {
+	let mut last_err = None;
+	for addr in addr.to_socket_addrs()? {
+		match TcpStream::connect_timeout(&addr, timeout) {
+			Ok(stream) => {
+				stream.set_read_timeout(Some(timeout))?;
+				stream.set_write_timeout(Some(timeout))?;
+				return Ok(stream);
+			}
+			Err(e) => last_err = Some(e),
+		}
+	}
+	Err(last_err.unwrap_or_else(|| {
+		std::io::Error::new(std::io::ErrorKind::NotFound, "No addresses to connect to")
+	}))
+}
+
+/// Opens a connection straight to 'host:port'
+fn direct_connect(host: &str, port: u16, timeout: Duration) -> Result<TcpStream, Error> {
+	// IPv6 hosts come bracketed in urls
+	let host = host.trim_start_matches('[').trim_end_matches(']');
+	connect((host, port), timeout)
+		.map_err(|e| Error::RequestError(format!("Cannot connect to {}:{}: {}", host, port, e)))
+}
+
+/// Opens a connection to 'host:port' through the SOCKS5 proxy, without authentication
+fn socks5_connect(
+	proxy: &str,
+	host: &str,
+	port: u16,
+	timeout: Duration,
+) -> Result<TcpStream, Error> {
+	let mut stream = connect(proxy, timeout)
+		.map_err(|e| Error::RequestError(format!("Cannot connect to proxy {}: {}", proxy, e)))?;
+
+	stream
+		.write_all(&[SOCKS_VERSION, 1, SOCKS_NO_AUTH])
+		.map_err(request_error)?;
+	let mut choice = [0u8; 2];
+	stream.read_exact(&mut choice).map_err(request_error)?;
+	if choice != [SOCKS_VERSION, SOCKS_NO_AUTH] {
+		return Err(Error::RequestError(
+			"Proxy requires an unsupported authentication method".to_string(),
+		));
+	}
+
+	if host.len() > u8::MAX as usize {
+		return Err(Error::Argument(format!("Host name too long: {}", host)));
+	}
+	let mut connect = vec![
+		SOCKS_VERSION,
+		SOCKS_CONNECT,
+		0,
+		SOCKS_DOMAIN_NAME,
+		host.len() as u8,
+	];
+	connect.extend_from_slice(host.as_bytes());
+	connect.extend_from_slice(&port.to_be_bytes());
+	stream.write_all(&connect).map_err(request_error)?;
+
+	// The reply ends with the proxy's bound address, whose length depends on its type
+	let mut reply = [0u8; 4];
+	stream.read_exact(&mut reply).map_err(request_error)?;
+	if reply[1] != 0 {
+		return Err(Error::RequestError(format!(
+			"Proxy could not connect to {}:{} (SOCKS reply {})",
+			host, port, reply[1]
+		)));
+	}
+	let addr_len = match reply[3] {
+		1 => 4,
+		4 => 16,
+		SOCKS_DOMAIN_NAME => {
+			let mut len = [0u8; 1];
+			stream.read_exact(&mut len).map_err(request_error)?;
+			len[0] as usize
+		}
+		atyp => {
+			return Err(Error::RequestError(format!(
+				"Proxy replied with unknown address type {}",
+				atyp
+			)))
+		}
+	};
+	let mut bound = vec![0u8; addr_len + 2];
+	stream.read_exact(&mut bound).map_err(request_error)?;
+
+	Ok(stream)
+}
+
+fn request_error(e: std::io::Error) -> Error {
+	Error::RequestError(format!("Request failed: {}", e))
+}
+
+/// Splits an HTTP/1.1 response, returning its body if the status is successful.
+/// Server errors are reported as failed requests, so they're retried, while the
+/// request's own errors (4xx) are not.
+fn parse_response(res: &[u8]) -> Result<Vec<u8>, Error> {
+	let header_end = res
+		.windows(4)
+		.position(|w| w == b"\r\n\r\n")
+		.ok_or_else(|| Error::ResponseError("Truncated response headers".to_string()))?;
+	let head = String::from_utf8_lossy(&res[..header_end]);
+	let body = &res[header_end + 4..];
+
+	let mut lines = head.split("\r\n");
+	let status = lines
+		.next()
+		.and_then(|line| line.split_whitespace().nth(1))
+		.and_then(|code| code.parse::<u16>().ok())
+		.ok_or_else(|| Error::ResponseError("Invalid response status line".to_string()))?;
+	if (400..500).contains(&status) {
+		return Err(Error::ResponseError(format!(
+			"Request failed with status {}",
+			status
+		)));
+	}
+	if !(200..300).contains(&status) {
+		return Err(Error::RequestError(format!(
+			"Request failed with status {}",
+			status
+		)));
+	}
+
+	let chunked = lines.any(|line| {
+		let line = line.to_ascii_lowercase();
+		line.starts_with("transfer-encoding:") && line.contains("chunked")
+	});
+	if chunked {
+		decode_chunked(body)
+	} else {
+		Ok(body.to_vec())
+	}
+}
+
+/// Decodes a body sent with 'Transfer-Encoding: chunked'
+fn decode_chunked(mut body: &[u8]) -> Result<Vec<u8>, Error> {
+	let invalid = || Error::ResponseError("Invalid chunked response body".to_string());
+	let mut decoded = Vec::new();
+	loop {
+		let line_end = body
+			.windows(2)
+			.position(|w| w == b"\r\n")
+			.ok_or_else(invalid)?;
+		let size_line = String::from_utf8_lossy(&body[..line_end]);
+		let size_hex = size_line.split(';').next().unwrap_or("").trim();
+		let size = usize::from_str_radix(size_hex, 16).map_err(|_| invalid())?;
+		body = &body[line_end + 2..];
+		if size == 0 {
+			return Ok(decoded);
+		}
+		if body.len() < size + 2 {
+			return Err(invalid());
+		}
+		decoded.extend_from_slice(&body[..size]);
+		body = &body[size + 2..];
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{HttpClient, RetryPolicy, MAX_BACKOFF};
+
+	use serde_json::{json, Value};
+	use std::io::{Read, Write};
+	use std::net::TcpListener;
+	use std::thread;
+	use std::time::Duration;
+
+	/// Answers one request per response, in order, as the destination server itself.
+	/// Returns the server's address, and the number of requests it answered.
+	fn fake_server(responses: Vec<&'static str>) -> (String, thread::JoinHandle<usize>) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap().to_string();
+		let handle = thread::spawn(move || {
+			for response in &responses {
+				let (mut stream, _) = listener.accept().unwrap();
+				let mut request = Vec::new();
+				let mut buf = [0u8; 1024];
+				while !String::from_utf8_lossy(&request).contains("\r\n\r\n") {
+					let n = stream.read(&mut buf).unwrap();
+					request.extend_from_slice(&buf[..n]);
+				}
+				stream.write_all(response.as_bytes()).unwrap();
+			}
+			responses.len()
+		});
+		(addr, handle)
+	}
+
+	/// Serves a single request as a SOCKS5 proxy, answering it itself in place of the
+	/// destination. Returns the proxy's address, and the destination and request it received.
+	fn fake_proxy(response: &'static str) -> (String, thread::JoinHandle<(String, String)>) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap().to_string();
+		let handle = thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			let mut greeting = [0u8; 3];
+			stream.read_exact(&mut greeting).unwrap();
+			assert_eq!([5, 1, 0], greeting);
+			stream.write_all(&[5, 0]).unwrap();
+
+			let mut connect = [0u8; 5];
+			stream.read_exact(&mut connect).unwrap();
+			assert_eq!([5, 1, 0, 3], connect[..4]);
+			let mut host = vec![0u8; connect[4] as usize + 2];
+			stream.read_exact(&mut host).unwrap();
+			let port = u16::from_be_bytes([host[host.len() - 2], host[host.len() - 1]]);
+			let destination = format!(
+				"{}:{}",
+				String::from_utf8_lossy(&host[..host.len() - 2]),
+				port
+			);
+			stream
+				.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 80])
+				.unwrap();
+
+			let mut request = Vec::new();
+			let mut buf = [0u8; 1024];
+			while !String::from_utf8_lossy(&request).contains("\r\n\r\n{") {
+				let n = stream.read(&mut buf).unwrap();
+				request.extend_from_slice(&buf[..n]);
+			}
+			stream.write_all(response.as_bytes()).unwrap();
+			(destination, String::from_utf8_lossy(&request).to_string())
+		});
+		(addr, handle)
+	}
+
+	#[test]
+	fn post_through_proxy() -> Result<(), Box<dyn std::error::Error>> {
+		let (proxy, handle) = fake_proxy(
+			"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nTransfer-Encoding: chunked\r\n\r\n\
+			7\r\n{\"ok\": \r\n5\r\ntrue}\r\n0\r\n\r\n",
+		);
+		let client = HttpClient::new(Some(&format!("socks5://{}", proxy)));
+		let res: Value = client.post(
+			"http://abcdefgh.onion:3413/v2/foreign",
+			Some("secret".to_string()),
+			&json!({"method": "get_tip"}),
+		)?;
+		assert_eq!(json!({"ok": true}), res);
+
+		let (destination, request) = handle.join().unwrap();
+		assert_eq!("abcdefgh.onion:3413", destination);
+		assert!(request.starts_with("POST /v2/foreign HTTP/1.1\r\n"));
+		assert!(request.contains("Authorization: Basic Z3JpbjpzZWNyZXQ=\r\n"));
+
+		Ok(())
+	}
+
+	#[test]
+	fn proxied_error_status() {
+		let (proxy, _handle) = fake_proxy("HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n");
+		let client = HttpClient::new(Some(&proxy));
+		let res: Result<Value, _> = client.post("http://127.0.0.1:3420/v3/owner", None, &json!({}));
+		assert!(res.is_err());
+
+		// TLS can't be tunnelled, so https urls are refused before connecting
+		let res: Result<Value, _> = client.get("https://example.com/directory.json", None);
+		assert!(res.is_err());
+	}
+
+	/// Server errors are retried until a response succeeds, while the request's own errors aren't
+	#[test]
+	fn retry_server_errors() -> Result<(), Box<dyn std::error::Error>> {
+		let retry = RetryPolicy {
+			retries: 2,
+			backoff: Duration::from_millis(10),
+		};
+		let (addr, handle) = fake_server(vec![
+			"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n",
+			"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n",
+			"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\n{\"ok\":true}",
+		]);
+		let client = HttpClient::new(None).with_retry(retry);
+		let res: Value = client.get(&format!("http://{}/v2/foreign", addr), None)?;
+		assert_eq!(json!({"ok": true}), res);
+		assert_eq!(3, handle.join().unwrap());
+
+		let (addr, handle) = fake_server(vec![
+			"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n",
+		]);
+		let res: Result<Value, _> = client.get(&format!("http://{}/v2/foreign", addr), None);
+		assert!(res.is_err());
+		assert_eq!(1, handle.join().unwrap());
+
+		// Nothing is listening once the server's gone, and the retries run out
+		let client = client.with_timeout(Duration::from_millis(100));
+		let res: Result<Value, _> = client.get(&format!("http://{}/v2/foreign", addr), None);
+		assert!(res.is_err());
+		Ok(())
+	}
+
+	#[test]
+	fn backoff() {
+		let retry = RetryPolicy::default();
+		assert_eq!(Duration::from_secs(1), retry.delay(0));
+		assert_eq!(Duration::from_secs(4), retry.delay(2));
+		assert_eq!(MAX_BACKOFF, retry.delay(10));
+		assert_eq!(MAX_BACKOFF, retry.delay(40));
+		let retry = RetryPolicy {
+			retries: 1,
+			backoff: Duration::ZERO,
+		};
+		assert_eq!(Duration::ZERO, retry.delay(3));
+	}
+}
@@ -0,0 +1,146 @@
+//! Read-only views of the records in a swap store, printed by the 'db' subcommands so operators
+//! can debug a server without writing their own tools.
+
+use crate::onion::Onion;
+use crate::secp::{self, Commitment};
+use crate::store::{StoreError, SwapData, SwapStatus, SwapStore};
+
+use grin_core::core::OutputFeatures;
+use grin_util::ToHex;
+use serde::Serialize;
+
+/// Shown in place of a secret key, unless secrets are revealed
+pub const REDACTED: &str = "<redacted>";
+
+/// Every field of a stored swap, with its secret keys redacted unless asked for
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct SwapRecord {
+	pub input_commit: String,
+	pub input_features: String,
+	pub output_commit: String,
+	pub fee: u64,
+	pub status: String,
+	pub kernel_hash: Option<String>,
+	pub block_hash: Option<String>,
+	pub expiry_height: u64,
+	pub unconfirmed_input: bool,
+	pub accepted_at: u64,
+	pub accepted_round: u64,
+	/// the swap's excess, which is secret
+	pub excess: String,
+	pub rangeproof: Option<String>,
+	/// the remaining onion, passed on to the next server
+	pub onion: Onion,
+	/// excess of the owner's kernel, if any
+	pub owner_kernel: Option<String>,
+	/// value of the change paid for an over-paid fee, if any
+	pub fee_change: Option<u64>,
+}
+
+impl SwapRecord {
+	pub fn new(swap: &SwapData, reveal_secrets: bool) -> SwapRecord {
+		let secret = |key: &secp::SecretKey| {
+			if reveal_secrets {
+				key.0.to_hex()
+			} else {
+				REDACTED.to_string()
+			}
+		};
+		let block_hash = match &swap.status {
+			SwapStatus::Completed { block_hash, .. } => Some(block_hash.to_hex()),
+			_ => None,
+		};
+		let input_features = match swap.input.features {
+			OutputFeatures::Plain => "plain",
+			OutputFeatures::Coinbase => "coinbase",
+		};
+
+		SwapRecord {
+			input_commit: swap.input.commit.to_hex(),
+			input_features: input_features.to_string(),
+			output_commit: swap.output_commit.to_hex(),
+			fee: swap.fee,
+			status: swap.status.name().to_string(),
+			kernel_hash: swap.status.kernel_hash().map(|h| h.to_hex()),
+			block_hash,
+			expiry_height: swap.expiry_height,
+			unconfirmed_input: swap.unconfirmed_input,
+			accepted_at: swap.accepted_at,
+			accepted_round: swap.accepted_round,
+			excess: secret(&swap.excess),
+			rangeproof: swap
+				.rangeproof
+				.as_ref()
+				.map(|p| grin_util::to_hex(&p.proof[..p.plen])),
+			onion: swap.onion.clone(),
+			owner_kernel: swap.owner_kernel.as_ref().map(|k| k.excess.to_hex()),
+			fee_change: swap.fee_change.as_ref().map(|c| c.value),
+		}
+	}
+}
+
+/// Finds the swap spending the commitment, or else the one creating it
+pub fn find_swap(store: &SwapStore, commit: &Commitment) -> Result<Option<SwapData>, StoreError> {
+	match store.get_swap(commit) {
+		Ok(swap) => Ok(Some(swap)),
+		Err(StoreError::NotFound(_)) => store.get_swap_by_output(commit),
+		Err(e) => Err(e),
+	}
+}
+
+/// Lists the stored swaps, keeping only those with one of the named statuses if any are given
+pub fn list_swaps(store: &SwapStore, statuses: &[&str]) -> Result<Vec<SwapData>, StoreError> {
+	Ok(store
+		.swaps_iter()?
+		.filter(|s| statuses.is_empty() || statuses.contains(&s.status.name()))
+		.collect())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{find_swap, list_swaps, SwapRecord, REDACTED};
+	use crate::secp;
+	use crate::secp::test_util::rand_commit;
+	use crate::store::test_util::{rand_swap_with_status, TestStore};
+	use crate::store::SwapStatus;
+
+	use grin_util::ToHex;
+
+	/// Swaps are found by either commitment, listed by status, and only show secrets when asked
+	#[test]
+	fn inspect_swaps() -> Result<(), Box<dyn std::error::Error>> {
+		let store = TestStore::new("inspect_swaps");
+		let unprocessed = rand_swap_with_status(SwapStatus::Unprocessed);
+		let failed = rand_swap_with_status(SwapStatus::Failed);
+		store.save_swap(&unprocessed, false)?;
+		store.save_swap(&failed, false)?;
+
+		let input_commit = secp::commitment_from_hex(&unprocessed.input.commit.to_hex()).unwrap();
+		assert_eq!(Some(unprocessed.clone()), find_swap(&store, &input_commit)?);
+		assert_eq!(
+			Some(unprocessed.clone()),
+			find_swap(&store, &unprocessed.output_commit)?
+		);
+		assert_eq!(None, find_swap(&store, &rand_commit())?);
+		assert_eq!(None, secp::commitment_from_hex("not hex"));
+		assert_eq!(None, secp::commitment_from_hex("abcd"));
+
+		assert_eq!(2, list_swaps(&store, &[])?.len());
+		assert_eq!(vec![failed.clone()], list_swaps(&store, &["failed"])?);
+		assert_eq!(
+			2,
+			list_swaps(&store, &["unprocessed", "failed", "completed"])?.len()
+		);
+		assert!(list_swaps(&store, &["in_process"])?.is_empty());
+
+		let record = SwapRecord::new(&unprocessed, false);
+		assert_eq!(REDACTED, record.excess);
+		assert_eq!(unprocessed.output_commit.to_hex(), record.output_commit);
+		assert_eq!("unprocessed", record.status);
+		assert!(!serde_json::to_string(&record)?.contains(&unprocessed.excess.0.to_hex()));
+		let record = SwapRecord::new(&unprocessed, true);
+		assert_eq!(unprocessed.excess.0.to_hex(), record.excess);
+
+		Ok(())
+	}
+}
@@ -0,0 +1,221 @@
+use grin_core::consensus;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Block time assumed until enough blocks have been observed to estimate the node's actual one
+pub const DEFAULT_BLOCK_TIME_S: u64 = consensus::BLOCK_TIME_SEC;
+
+/// Fewest blocks the chain tip must advance by before its observed block time is trusted
+const MIN_OBSERVED_BLOCKS: u64 = 10;
+
+const UNITS: [(&str, u64); 4] = [("d", 24 * 60 * 60), ("h", 60 * 60), ("m", 60), ("s", 1)];
+
+/// A span of chain time, counted either in seconds or in blocks.
+/// Written as a number with a unit, e.g. '90s', '15m', '12h', '2d' or '720 blocks'.
+/// A bare number is a number of seconds, as in configs written before intervals had units.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChainInterval {
+	Seconds(u64),
+	Blocks(u64),
+}
+
+/// Error types for parsing intervals
+#[derive(Clone, Error, Debug, PartialEq)]
+pub enum IntervalError {
+	#[error("Invalid interval '{0}'. Expected e.g. '90s', '15m', '12h', '2d' or '720 blocks'")]
+	Invalid(String),
+}
+
+impl ChainInterval {
+	/// The interval in seconds, converting blocks at 'block_time_s' seconds each
+	pub fn as_secs(&self, block_time_s: u64) -> u64 {
+		match *self {
+			ChainInterval::Seconds(secs) => secs,
+			ChainInterval::Blocks(blocks) => blocks.saturating_mul(block_time_s),
+		}
+	}
+
+	/// The interval in blocks, converting seconds at 'block_time_s' seconds per block.
+	/// Partial blocks are rounded up, so a converted interval is never shorter than configured.
+	pub fn as_blocks(&self, block_time_s: u64) -> u64 {
+		match *self {
+			ChainInterval::Seconds(secs) => {
+				let block_time_s = block_time_s.max(1);
+				(secs + block_time_s - 1) / block_time_s
+			}
+			ChainInterval::Blocks(blocks) => blocks,
+		}
+	}
+
+	/// The interval as a duration, converting blocks at 'block_time_s' seconds each
+	pub fn as_duration(&self, block_time_s: u64) -> Duration {
+		Duration::from_secs(self.as_secs(block_time_s))
+	}
+}
+
+/// Estimates the average block time from the tip advancing 'blocks' blocks over 'elapsed'.
+/// Returns None until enough blocks have been seen for the estimate to be meaningful.
+pub fn observed_block_time(blocks: u64, elapsed: Duration) -> Option<u64> {
+	if blocks < MIN_OBSERVED_BLOCKS {
+		return None;
+	}
+	Some((elapsed.as_secs() / blocks).max(1))
+}
+
+impl FromStr for ChainInterval {
+	type Err = IntervalError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let invalid = || IntervalError::Invalid(s.to_string());
+		let trimmed = s.trim();
+		let split = trimmed
+			.find(|c: char| !c.is_ascii_digit())
+			.unwrap_or(trimmed.len());
+		let (number, unit) = trimmed.split_at(split);
+		let number: u64 = number.parse().map_err(|_| invalid())?;
+
+		match unit.trim() {
+			"" => Ok(ChainInterval::Seconds(number)),
+			"b" | "block" | "blocks" => Ok(ChainInterval::Blocks(number)),
+			unit => {
+				let (_, multiplier) = UNITS
+					.iter()
+					.find(|(name, _)| *name == unit)
+					.ok_or_else(invalid)?;
+				number
+					.checked_mul(*multiplier)
+					.map(ChainInterval::Seconds)
+					.ok_or_else(invalid)
+			}
+		}
+	}
+}
+
+impl fmt::Display for ChainInterval {
+	/// Formats seconds in the largest unit that divides them exactly
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match *self {
+			ChainInterval::Blocks(1) => write!(f, "1 block"),
+			ChainInterval::Blocks(blocks) => write!(f, "{} blocks", blocks),
+			ChainInterval::Seconds(0) => write!(f, "0s"),
+			ChainInterval::Seconds(secs) => {
+				let (name, multiplier) = UNITS
+					.iter()
+					.find(|(_, multiplier)| secs % multiplier == 0)
+					.unwrap();
+				write!(f, "{}{}", secs / multiplier, name)
+			}
+		}
+	}
+}
+
+impl Serialize for ChainInterval {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_str(&self.to_string())
+	}
+}
+
+struct ChainIntervalVisitor;
+
+impl<'de> Visitor<'de> for ChainIntervalVisitor {
+	type Value = ChainInterval;
+
+	fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+		formatter.write_str("an interval such as '12h' or '720 blocks', or a number of seconds")
+	}
+
+	fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+		Ok(ChainInterval::Seconds(value))
+	}
+
+	fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+		u64::try_from(value)
+			.map(ChainInterval::Seconds)
+			.map_err(|_| E::custom(IntervalError::Invalid(value.to_string())))
+	}
+
+	fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+		value.parse().map_err(E::custom)
+	}
+}
+
+impl<'de> Deserialize<'de> for ChainInterval {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		deserializer.deserialize_any(ChainIntervalVisitor)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{observed_block_time, ChainInterval, IntervalError};
+
+	use serde::Deserialize;
+	use std::time::Duration;
+
+	#[test]
+	fn parse_and_format() {
+		let cases = [
+			("90s", ChainInterval::Seconds(90), "90s"),
+			("15m", ChainInterval::Seconds(15 * 60), "15m"),
+			("12h", ChainInterval::Seconds(12 * 60 * 60), "12h"),
+			("2d", ChainInterval::Seconds(2 * 24 * 60 * 60), "2d"),
+			("90m", ChainInterval::Seconds(90 * 60), "90m"),
+			("120", ChainInterval::Seconds(120), "2m"),
+			("720 blocks", ChainInterval::Blocks(720), "720 blocks"),
+			("1b", ChainInterval::Blocks(1), "1 block"),
+			(" 3 h ", ChainInterval::Seconds(3 * 60 * 60), "3h"),
+		];
+		for (input, interval, formatted) in cases {
+			assert_eq!(Ok(interval), input.parse::<ChainInterval>());
+			assert_eq!(formatted, interval.to_string());
+			assert_eq!(Ok(interval), formatted.parse::<ChainInterval>());
+		}
+
+		for input in ["", "h", "-5s", "12 hours", "1.5h", "99999999999999999999d"] {
+			assert_eq!(
+				Err(IntervalError::Invalid(input.to_string())),
+				input.parse::<ChainInterval>()
+			);
+		}
+	}
+
+	#[test]
+	fn conversions() {
+		assert_eq!(600, ChainInterval::Blocks(10).as_secs(60));
+		assert_eq!(10, ChainInterval::Seconds(600).as_blocks(60));
+		assert_eq!(11, ChainInterval::Seconds(601).as_blocks(60));
+		assert_eq!(600, ChainInterval::Seconds(600).as_secs(1));
+		assert_eq!(10, ChainInterval::Blocks(10).as_blocks(1));
+		assert_eq!(5, ChainInterval::Seconds(5).as_blocks(0));
+
+		assert_eq!(None, observed_block_time(5, Duration::from_secs(300)));
+		assert_eq!(Some(62), observed_block_time(10, Duration::from_secs(620)));
+		assert_eq!(Some(1), observed_block_time(100, Duration::from_secs(10)));
+	}
+
+	/// Intervals are written as strings, and numbers of seconds are still accepted
+	#[test]
+	fn serde() -> Result<(), Box<dyn std::error::Error>> {
+		#[derive(Deserialize)]
+		struct Config {
+			interval: ChainInterval,
+		}
+
+		let config: Config = toml::from_str("interval = '720 blocks'")?;
+		assert_eq!(ChainInterval::Blocks(720), config.interval);
+		let config: Config = toml::from_str("interval = 43200")?;
+		assert_eq!(ChainInterval::Seconds(43200), config.interval);
+		assert!(toml::from_str::<Config>("interval = 'soon'").is_err());
+
+		assert_eq!(
+			"\"12h\"",
+			serde_json::to_string(&ChainInterval::Seconds(43200))?
+		);
+
+		Ok(())
+	}
+}
@@ -0,0 +1,348 @@
+//! End-to-end harness that runs several mix servers against one mock node.
+//!
+//! Servers don't forward onions to each other yet (milestone 3), so for multi-hop routes the
+//! harness plays the part of the network: each server in the route peels its layer through its
+//! 'Server' API, and the peeled swaps are aggregated into a single transaction the way the last
+//! server in the route will. Once forwarding lands, 'Mixnet::forward' is the piece to replace.
+//!
+//! Only built with the 'it' feature: `cargo test --features it it::`
+
+use crate::config::{RoundSelection, ServerConfig, StoreBackend, WalletBackend};
+use crate::interval::ChainInterval;
+use crate::node::mock::MockGrinNode;
+use crate::node::GrinNode;
+use crate::onion::test_util::{create_onion, Hop};
+use crate::onion::Onion;
+use crate::secp::{
+	self, ComSignature, Commitment, PublicKey, RangeProof, Secp256k1, SecretKey, SigDomain,
+};
+use crate::server::{Server, ServerImpl, SwapError};
+use crate::store::SwapStore;
+use crate::types::Payload;
+use crate::wallet::{self, mock::MockWallet, Wallet};
+
+use grin_core::core::{FeeFields, Input, Output, OutputFeatures, Transaction, Weighting};
+use grin_core::global::{self, ChainTypes, DEFAULT_ACCEPT_FEE_BASE};
+use std::net::TcpListener;
+use std::sync::Arc;
+
+/// Expiry height of the harness' swaps. The mock node's chain starts at height 100.
+const EXPIRY_HEIGHT: u64 = 200;
+
+/// A mix server in the harness, and the key onions are encrypted to
+struct MixServer {
+	key: SecretKey,
+	server: ServerImpl,
+}
+
+impl MixServer {
+	fn pubkey(&self) -> PublicKey {
+		PublicKey::from_secret_key(&Secp256k1::new(), &self.key).unwrap()
+	}
+}
+
+/// A coin owned by the harness' user
+struct Coin {
+	value: u64,
+	blind: SecretKey,
+	commit: Commitment,
+}
+
+/// A swap after every server in its route has peeled its layer
+struct Forwarded {
+	input: Input,
+	output: Output,
+	excesses: Vec<SecretKey>,
+	fee: u64,
+}
+
+/// Mix servers sharing one chain (mock node) and one wallet
+struct Mixnet {
+	servers: Vec<MixServer>,
+	node: Arc<MockGrinNode>,
+	wallet: Arc<MockWallet>,
+}
+
+impl Mixnet {
+	/// Starts 'count' servers, each with its own key and database under ./target/tmp
+	fn new(test_name: &str, count: usize) -> Mixnet {
+		global::set_local_chain_type(ChainTypes::AutomatedTesting);
+		let node = Arc::new(MockGrinNode::new());
+		let wallet = Arc::new(MockWallet::new());
+
+		let servers = (0..count)
+			.map(|i| {
+				let key = secp::random_secret();
+				let db_root = format!("./target/tmp/.it_{}_{}", test_name, i);
+				let _ = std::fs::remove_dir_all(db_root.as_str());
+				let store = SwapStore::new(db_root.as_str()).unwrap();
+				let server =
+					ServerImpl::new(server_config(&key), wallet.clone(), node.clone(), store);
+				MixServer { key, server }
+			})
+			.collect();
+
+		Mixnet {
+			servers,
+			node,
+			wallet,
+		}
+	}
+
+	/// Creates a coin worth 'value' in the node's UTXO set
+	fn new_coin(&self, value: u64) -> Coin {
+		let blind = secp::random_secret();
+		let commit = secp::commit(value, &blind).unwrap();
+		self.node.add_default_utxo(&commit);
+		Coin {
+			value,
+			blind,
+			commit,
+		}
+	}
+
+	/// Builds the onion and comsig for swapping 'coin' through the servers at 'route'
+	/// (indices into the mixnet), paying 'fee_per_hop' to each.
+	/// Returns them along with the commitment the swap's output should end up with.
+	fn build_swap(
+		&self,
+		coin: &Coin,
+		route: &[usize],
+		fee_per_hop: u64,
+	) -> (Onion, ComSignature, Commitment) {
+		let secp = Secp256k1::new();
+		let excesses: Vec<SecretKey> = route.iter().map(|_| secp::random_secret()).collect();
+
+		let mut output_blind = coin.blind.clone();
+		for excess in &excesses {
+			output_blind.add_assign(&secp, excess).unwrap();
+		}
+		let output_value = coin.value - fee_per_hop * route.len() as u64;
+		let output_commit = secp::commit(output_value, &output_blind).unwrap();
+		let nonce = secp::random_secret();
+		let proof = secp.bullet_proof(output_value, output_blind, nonce.clone(), nonce, None, None);
+
+		let hops: Vec<Hop> = route
+			.iter()
+			.zip(excesses.iter())
+			.enumerate()
+			.map(|(i, (index, excess))| Hop {
+				pubkey: self.servers[*index].pubkey(),
+				payload: Payload {
+					excess: excess.clone(),
+					fee: FeeFields::from(fee_per_hop as u32),
+					rangeproof: match i == route.len() - 1 {
+						true => Some(proof),
+						false => None,
+					},
+					owner_kernel: None,
+					min_value_proof: None,
+					next_view_tag: None,
+					fee_change: None,
+				},
+			})
+			.collect();
+		let onion = create_onion(&coin.commit, &hops).unwrap();
+
+		let domain = SigDomain::new(&self.servers[route[0]].pubkey(), EXPIRY_HEIGHT);
+		let comsig = ComSignature::sign(
+			coin.value,
+			&coin.blind,
+			&domain,
+			&onion.serialize().unwrap(),
+		)
+		.unwrap();
+		(onion, comsig, output_commit)
+	}
+
+	/// Passes the onion through the servers at 'route', each peeling its layer, and checks that
+	/// each layer's commitment follows from the previous one's excess and fee
+	fn forward(&self, onion: &Onion, route: &[usize]) -> Result<Forwarded, SwapError> {
+		let input = Input::new(OutputFeatures::Plain, onion.commit.clone());
+		let mut onion = onion.clone();
+		let mut excesses = Vec::new();
+		let mut fee = 0;
+		let mut rangeproof: Option<RangeProof> = None;
+
+		for (i, index) in route.iter().enumerate() {
+			let (payload, peeled) = self.servers[*index].server.peel_layer(&onion)?;
+			let hop_fee: u64 = payload.fee.into();
+			let expected = secp::add_excess(&onion.commit, &payload.excess)
+				.and_then(|c| secp::sub_value(&c, hop_fee))
+				.map_err(|e| SwapError::UnknownError(e.to_string()))?;
+			assert_eq!(
+				expected, peeled.commit,
+				"hop {} broke the commitment chain",
+				i
+			);
+
+			excesses.push(payload.excess);
+			fee += hop_fee;
+			rangeproof = payload.rangeproof;
+			onion = peeled;
+		}
+
+		// Only the last hop learns the output's rangeproof
+		let rangeproof = rangeproof.ok_or(SwapError::MissingRangeproof)?;
+		Ok(Forwarded {
+			input,
+			output: Output::new(OutputFeatures::Plain, onion.commit.clone(), rangeproof),
+			excesses,
+			fee,
+		})
+	}
+
+	/// Aggregates the forwarded swaps into one transaction, and posts it to the node
+	fn aggregate(&self, swaps: &[Forwarded]) -> Result<Transaction, Box<dyn std::error::Error>> {
+		let wallet: Arc<dyn Wallet> = self.wallet.clone();
+		let (tx, _) = wallet::assemble_tx(
+			&wallet,
+			&swaps.iter().map(|s| s.input).collect(),
+			&swaps.iter().map(|s| s.output).collect(),
+			DEFAULT_ACCEPT_FEE_BASE,
+			swaps.iter().map(|s| s.fee).sum(),
+			&swaps.iter().flat_map(|s| s.excesses.clone()).collect(),
+			&vec![],
+			wallet::RoundKernelKind::Plain,
+		)?;
+		self.node.post_tx(&tx, true)?;
+		Ok(tx)
+	}
+}
+
+fn server_config(key: &SecretKey) -> ServerConfig {
+	ServerConfig {
+		key: key.clone(),
+		round_interval: ChainInterval::Seconds(1),
+		addr: TcpListener::bind("127.0.0.1:0")
+			.unwrap()
+			.local_addr()
+			.unwrap()
+			.into(),
+		grin_node_url: "127.0.0.1:3413".parse().unwrap(),
+		grin_node_secret_path: None,
+		wallet_owner_url: "127.0.0.1:3420".parse().unwrap(),
+		wallet_owner_secret_path: None,
+		admin_socket_path: None,
+		wallet_backend: WalletBackend::OwnerApi,
+		pending_commitment_interval: None,
+		tip_stall_timeout: None,
+		report_dir: None,
+		round_jitter_percent: None,
+		round_delay_window: None,
+		dandelion_stem: false,
+		standby_addr: None,
+		replication_addr: None,
+		replication_lease: None,
+		previous_key: None,
+		fee_seed: None,
+		state_key: None,
+		kdf: None,
+		acceptance_windows: false,
+		explorer_kernel_url: None,
+		explorer_block_url: None,
+		swap_ttl: None,
+		public_url: None,
+		log_level: None,
+		log_dir: None,
+		zero_conf_max_pending: None,
+		nostr_relays: None,
+		db_root: None,
+		max_swaps_per_round: None,
+		round_selection: RoundSelection::Fifo,
+		min_anonymity_set: None,
+		proxy: None,
+		events_addr: None,
+		store_backend: StoreBackend::Lmdb,
+		min_output_value: None,
+		metrics_addr: None,
+		peers: None,
+		admin_addr: None,
+		nrd_relative_height: None,
+		compress_archived_swaps: false,
+		accepted_cipher_suites: None,
+		peer_pubkeys: None,
+		round_peers: None,
+		intake_cutoff: None,
+		rejection_log_size: None,
+		http_timeout: None,
+		http_retries: None,
+		http_backoff: None,
+		deny_list_path: None,
+		min_input_confirmations: None,
+		api_keys: None,
+		trusted_proxies: None,
+	}
+}
+
+/// Every server accepts single-hop swaps and executes its own round, all on the same chain
+#[test]
+fn single_hop_rounds() -> Result<(), Box<dyn std::error::Error>> {
+	let fee: u64 = 50_000_000;
+	let mixnet = Mixnet::new("single_hop_rounds", 3);
+
+	let mut expected_outputs = Vec::new();
+	for i in 0..mixnet.servers.len() {
+		let coin = mixnet.new_coin(200_000_000);
+		let (onion, comsig, output_commit) = mixnet.build_swap(&coin, &[i], fee);
+		mixnet.servers[i]
+			.server
+			.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		expected_outputs.push((coin.commit, output_commit));
+	}
+
+	for mix_server in &mixnet.servers {
+		assert!(mix_server.server.execute_round()?.is_some());
+	}
+
+	let posted = mixnet.node.get_posted_txns();
+	assert_eq!(mixnet.servers.len(), posted.len());
+	for (tx, (input_commit, output_commit)) in posted.iter().zip(expected_outputs.iter()) {
+		tx.validate(Weighting::AsTransaction)?;
+		assert!(tx.inputs_committed().contains(input_commit));
+		assert!(tx.outputs_committed().contains(output_commit));
+	}
+	Ok(())
+}
+
+/// Swaps routed through every server are forwarded hop by hop, then aggregated into one valid
+/// transaction that spends the original inputs to the outputs only their owners can open
+#[test]
+fn multi_hop_swaps() -> Result<(), Box<dyn std::error::Error>> {
+	let fee_per_hop: u64 = 50_000_000;
+	let mixnet = Mixnet::new("multi_hop_swaps", 3);
+	let route = [0, 1, 2];
+
+	let mut forwarded = Vec::new();
+	let mut expected = Vec::new();
+	for _ in 0..2 {
+		let coin = mixnet.new_coin(500_000_000);
+		let (onion, _, output_commit) = mixnet.build_swap(&coin, &route, fee_per_hop);
+		let swap = mixnet.forward(&onion, &route)?;
+		assert_eq!(output_commit, swap.output.commitment());
+		assert_eq!(fee_per_hop * route.len() as u64, swap.fee);
+		forwarded.push(swap);
+		expected.push((coin.commit, output_commit));
+	}
+
+	let tx = mixnet.aggregate(&forwarded)?;
+	tx.validate(Weighting::AsTransaction)?;
+	for (input_commit, output_commit) in &expected {
+		assert!(tx.inputs_committed().contains(input_commit));
+		assert!(tx.outputs_committed().contains(output_commit));
+	}
+	assert_eq!(vec![tx], mixnet.node.get_posted_txns());
+	Ok(())
+}
+
+/// A server can't peel a layer that was encrypted for a different server
+#[test]
+fn misrouted_onion() -> Result<(), Box<dyn std::error::Error>> {
+	let mixnet = Mixnet::new("misrouted_onion", 3);
+	let coin = mixnet.new_coin(500_000_000);
+	let (onion, _, _) = mixnet.build_swap(&coin, &[0, 1, 2], 50_000_000);
+
+	let result = mixnet.forward(&onion, &[0, 2, 1]);
+	assert!(matches!(result, Err(SwapError::PeelOnionFailure(_))));
+	Ok(())
+}
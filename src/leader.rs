@@ -0,0 +1,272 @@
+//! Rounds coordinated across several mix nodes.
+//!
+//! Each node still executes its own rounds, but rather than posting its round transaction, it
+//! hands it to the round's leader, which aggregates the transactions it receives with its own and
+//! posts them as one. The leader of each slot (the round interval's count of blocks or seconds
+//! since genesis) is picked round-robin from the participating nodes, ordered by public key, so
+//! every node agrees on it without any messages. Batches are handed over at '/v1/peer', signed
+//! with the sending node's key (see 'peer_auth').
+
+use crate::http::HttpClient;
+use crate::interval::{ChainInterval, DEFAULT_BLOCK_TIME_S};
+use crate::node::GrinNode;
+use crate::peer_auth;
+use crate::secp::{PublicKey, Secp256k1, SecretKey};
+
+use grin_core::core::{Transaction, Weighting};
+use grin_core::libtx::secp_ser;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// Seconds the leader waits, once its round is due, for the other nodes' batches
+pub const BATCH_COLLECTION_S: u64 = 30;
+
+/// Another mix node taking part in coordinated rounds
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RoundPeer {
+	#[serde(with = "secp_ser::pubkey_serde")]
+	pub pubkey: PublicKey,
+	/// URL of the node's API, e.g. 'http://mix1.example.com:3000'
+	pub url: String,
+}
+
+/// Error types for coordinated rounds
+#[derive(Error, Debug)]
+pub enum LeaderError {
+	#[error("This node is not the leader of round slot {0}")]
+	NotLeader(u64),
+	#[error("Round slot {slot} is not current (current slot is {current})")]
+	StaleSlot { slot: u64, current: u64 },
+	#[error("Invalid batch: {0}")]
+	InvalidBatch(String),
+	#[error("No URL is configured for the leader {0}")]
+	UnknownLeader(String),
+	#[error("Failed to reach the leader: {0}")]
+	RequestFailed(String),
+}
+
+/// Which node leads each round slot
+#[derive(Clone, Debug, PartialEq)]
+pub struct LeaderSchedule {
+	/// every participating node, this one included, ordered by compressed public key
+	nodes: Vec<PublicKey>,
+}
+
+impl LeaderSchedule {
+	pub fn new(own: &PublicKey, peers: &[PublicKey]) -> LeaderSchedule {
+		let secp = Secp256k1::new();
+		let mut nodes: Vec<PublicKey> = peers.to_vec();
+		nodes.push(own.clone());
+		nodes.sort_by_key(|k| k.serialize_vec(&secp, true).to_vec());
+		nodes.dedup();
+		LeaderSchedule { nodes }
+	}
+
+	/// The leader of round slot 'slot'
+	pub fn leader(&self, slot: u64) -> &PublicKey {
+		&self.nodes[(slot % self.nodes.len() as u64) as usize]
+	}
+}
+
+/// The round slot at chain height 'height' and unix time 'now', for rounds every 'interval'
+pub fn slot(interval: &ChainInterval, height: u64, now: u64) -> u64 {
+	match interval {
+		ChainInterval::Blocks(blocks) => height / (*blocks).max(1),
+		ChainInterval::Seconds(_) => now / interval.as_secs(DEFAULT_BLOCK_TIME_S).max(1),
+	}
+}
+
+/// Hands this node's round transactions to the leader, or, while leading, collects the others'
+pub struct Coordinator {
+	key: SecretKey,
+	pubkey: PublicKey,
+	peers: Vec<RoundPeer>,
+	schedule: LeaderSchedule,
+	round_interval: ChainInterval,
+	node: Arc<dyn GrinNode>,
+	client: HttpClient,
+	/// transactions received from the other nodes, aggregated into this node's next round
+	batches: Mutex<Vec<Transaction>>,
+}
+
+impl Coordinator {
+	pub fn new(
+		key: &SecretKey,
+		peers: Vec<RoundPeer>,
+		round_interval: ChainInterval,
+		node: Arc<dyn GrinNode>,
+		client: HttpClient,
+	) -> Result<Coordinator, secp256k1zkp::Error> {
+		let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), key)?;
+		let peer_keys: Vec<PublicKey> = peers.iter().map(|p| p.pubkey.clone()).collect();
+		Ok(Coordinator {
+			key: key.clone(),
+			pubkey: pubkey.clone(),
+			peers,
+			schedule: LeaderSchedule::new(&pubkey, &peer_keys),
+			round_interval,
+			node,
+			client,
+			batches: Mutex::new(Vec::new()),
+		})
+	}
+
+	/// The round slot as of now
+	pub fn current_slot(&self) -> u64 {
+		let height = self.node.get_chain_height().unwrap_or(0);
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0);
+		slot(&self.round_interval, height, now)
+	}
+
+	pub fn is_leader(&self, slot: u64) -> bool {
+		self.schedule.leader(slot) == &self.pubkey
+	}
+
+	/// Sends this node's round transaction to the leader of 'slot'. The leader may still fail to
+	/// post it, so it's up to the caller to check the node gets it.
+	pub fn submit(&self, slot: u64, tx: &Transaction) -> Result<(), LeaderError> {
+		let leader = self.schedule.leader(slot);
+		let secp = Secp256k1::new();
+		let leader_hex = grin_util::to_hex(&leader.serialize_vec(&secp, true));
+		let peer = self
+			.peers
+			.iter()
+			.find(|p| &p.pubkey == leader)
+			.ok_or_else(|| LeaderError::UnknownLeader(leader_hex.clone()))?;
+
+		let body = serde_json::to_vec(&json!({
+			"jsonrpc": "2.0",
+			"method": "submit_batch",
+			"params": [slot, tx],
+			"id": 1,
+		}))
+		.map_err(|e| LeaderError::InvalidBatch(e.to_string()))?;
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0);
+		let headers = peer_auth::sign_request(&self.key, peer_auth::PEER_PATH, &body, now)
+			.map_err(|e| LeaderError::RequestFailed(e.to_string()))?;
+		let url = format!("{}{}", peer.url.trim_end_matches('/'), peer_auth::PEER_PATH);
+		let res: Value = self
+			.client
+			.post_with_headers(&url, &headers, &body)
+			.map_err(|e| LeaderError::RequestFailed(e.to_string()))?;
+		if let Some(error) = res.get("error") {
+			return Err(LeaderError::RequestFailed(error.to_string()));
+		}
+		Ok(())
+	}
+
+	/// Accepts another node's round transaction for 'slot', as long as this node leads it.
+	/// Batches for the previous slot are still accepted, so a node whose round ran just before
+	/// the boundary isn't turned away. Before it's merged with anything, the batch is validated
+	/// in full: its kernels' signatures must verify, and their excesses (with its offset) must
+	/// account for exactly its inputs, outputs and fees.
+	pub fn receive(&self, slot: u64, tx: Transaction) -> Result<(), LeaderError> {
+		let current = self.current_slot();
+		if slot > current || slot + 1 < current {
+			return Err(LeaderError::StaleSlot { slot, current });
+		}
+		if !self.is_leader(slot) {
+			return Err(LeaderError::NotLeader(slot));
+		}
+		tx.validate(Weighting::AsTransaction)
+			.map_err(|e| LeaderError::InvalidBatch(e.to_string()))?;
+		self.batches.lock().unwrap().push(tx);
+		Ok(())
+	}
+
+	/// The batches received since the last call
+	pub fn take_batches(&self) -> Vec<Transaction> {
+		std::mem::take(&mut *self.batches.lock().unwrap())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{slot, Coordinator, LeaderError, LeaderSchedule, RoundPeer};
+	use crate::http::HttpClient;
+	use crate::interval::ChainInterval;
+	use crate::node::mock::MockGrinNode;
+	use crate::secp::{self, PublicKey, Secp256k1};
+
+	use std::sync::Arc;
+
+	/// Every node computes the same leader for a slot, and each node leads in turn
+	#[test]
+	fn leader_schedule() {
+		let secp = Secp256k1::new();
+		let keys: Vec<PublicKey> = (0..3)
+			.map(|_| PublicKey::from_secret_key(&secp, &secp::random_secret()).unwrap())
+			.collect();
+		let schedules: Vec<LeaderSchedule> = (0..3)
+			.map(|i| {
+				let others: Vec<PublicKey> = keys
+					.iter()
+					.enumerate()
+					.filter(|(j, _)| *j != i)
+					.map(|(_, k)| k.clone())
+					.collect();
+				LeaderSchedule::new(&keys[i], &others)
+			})
+			.collect();
+
+		let leaders: Vec<PublicKey> = (0..3).map(|s| schedules[0].leader(s).clone()).collect();
+		for schedule in &schedules {
+			assert_eq!(schedules[0], *schedule);
+		}
+		for key in &keys {
+			assert!(leaders.contains(key));
+		}
+		assert_eq!(leaders[0], *schedules[0].leader(3));
+	}
+
+	#[test]
+	fn slots() {
+		assert_eq!(12, slot(&ChainInterval::Blocks(10), 125, 0));
+		assert_eq!(33, slot(&ChainInterval::Seconds(30), 125, 1000));
+	}
+
+	/// Batches are only accepted by the leader of a current slot
+	#[test]
+	fn receive_batches() -> Result<(), Box<dyn std::error::Error>> {
+		let secp = Secp256k1::new();
+		let key = secp::random_secret();
+		let peer = RoundPeer {
+			pubkey: PublicKey::from_secret_key(&secp, &secp::random_secret())?,
+			url: "http://127.0.0.1:3000".to_string(),
+		};
+		// The mock node's chain is at height 100
+		let coordinator = Coordinator::new(
+			&key,
+			vec![peer],
+			ChainInterval::Blocks(1),
+			Arc::new(MockGrinNode::new()),
+			HttpClient::default(),
+		)?;
+		let current = coordinator.current_slot();
+		let led = (current - 1..=current)
+			.find(|s| coordinator.is_leader(*s))
+			.unwrap();
+		let not_led = if led == current { current - 1 } else { current };
+
+		let tx = grin_core::core::Transaction::empty();
+		assert!(matches!(
+			coordinator.receive(not_led, tx.clone()),
+			Err(LeaderError::NotLeader(_))
+		));
+		assert!(matches!(
+			coordinator.receive(current + 2, tx.clone()),
+			Err(LeaderError::StaleSlot { .. })
+		));
+		assert!(coordinator.take_batches().is_empty());
+		Ok(())
+	}
+}
@@ -0,0 +1,79 @@
+use crate::config::ServerConfig;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use thiserror::Error;
+use tracing::{info_span, Span};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, EnvFilter};
+
+/// Log level used when 'log_level' isn't configured
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+
+/// Prefix of the log files written to 'log_dir'. Each file is suffixed with its date.
+const LOG_FILE_PREFIX: &str = "mwixnet.log";
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Error types for setting up logging
+#[derive(Error, Debug)]
+pub enum LoggingError {
+	#[error("Invalid log_level '{0}': {1}")]
+	InvalidLevel(String, String),
+	#[error("Logging was already initialized: {0}")]
+	AlreadyInitialized(String),
+}
+
+/// Installs the global logger, writing to stdout and, when 'log_dir' is configured,
+/// to daily rotated files in that directory.
+/// The returned guard flushes the file logs when dropped, so it must live until shutdown.
+pub fn init(server_config: &ServerConfig) -> Result<Option<WorkerGuard>, LoggingError> {
+	let filter = level_filter(&server_config.log_level)?;
+	let (file_layer, guard) = match &server_config.log_dir {
+		Some(log_dir) => {
+			let appender = tracing_appender::rolling::daily(log_dir, LOG_FILE_PREFIX);
+			let (writer, guard) = tracing_appender::non_blocking(appender);
+			let layer = fmt::layer().with_ansi(false).with_writer(writer);
+			(Some(layer), Some(guard))
+		}
+		None => (None, None),
+	};
+
+	tracing_subscriber::registry()
+		.with(filter)
+		.with(fmt::layer())
+		.with(file_layer)
+		.try_init()
+		.map_err(|e| LoggingError::AlreadyInitialized(e.to_string()))?;
+	Ok(guard)
+}
+
+/// Parses the configured 'log_level', falling back to 'DEFAULT_LOG_LEVEL'
+fn level_filter(log_level: &Option<String>) -> Result<EnvFilter, LoggingError> {
+	let level = log_level.as_deref().unwrap_or(DEFAULT_LOG_LEVEL);
+	EnvFilter::try_new(level)
+		.map_err(|e| LoggingError::InvalidLevel(level.to_owned(), e.to_string()))
+}
+
+/// Span covering the handling of a single RPC request.
+/// Each request gets a unique id, so the events it logs can be told apart from concurrent requests.
+pub fn request_span(method: &'static str) -> Span {
+	let id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+	info_span!("request", id, method)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{level_filter, LoggingError};
+
+	#[test]
+	fn log_levels() {
+		assert!(level_filter(&None).is_ok());
+		assert!(level_filter(&Some("debug".to_string())).is_ok());
+		assert!(level_filter(&Some("info,mwixnet::server=trace".to_string())).is_ok());
+		match level_filter(&Some("mwixnet=loud".to_string())) {
+			Err(LoggingError::InvalidLevel(level, _)) => assert_eq!("mwixnet=loud", level),
+			_ => panic!("expected an invalid level error"),
+		}
+	}
+}
@@ -1,32 +1,82 @@
-use config::ServerConfig;
+use config::{parse_addr, KdfParams, RoundSelection, ServerConfig, StoreBackend, WalletBackend};
+use conformance::{CheckResult, ConformanceRunner, TestCoin};
+use directory::{Directory, Mixnode, MAX_DIRECTORY_AGE_S};
+use features::Feature;
+use http::HttpClient;
+use interval::ChainInterval;
 use node::HttpGrinNode;
+use password::{PasswordError, SERVER_PASSWORD_ENV, WALLET_PASSWORD_ENV};
+use reload::ConfigSource;
+use service::{InstanceStatus, Notifier};
 use store::SwapStore;
-use wallet::HttpWallet;
+use wallet::{EmbeddedWallet, HttpWallet, Wallet};
+use wizard::{Given, Setup, Wizard};
+
+use mwixnet_client::{onion, secp, types};
 
-use crate::store::StoreError;
 use clap::App;
 use grin_core::global;
 use grin_core::global::ChainTypes;
-use grin_util::{StopState, ZeroingString};
+use grin_util::{StopState, ToHex, ZeroingString};
 use rpassword;
+use serde_json::json;
+use std::collections::HashSet;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::runtime::Runtime;
+use tracing::{error, info, info_span, warn};
 
 #[macro_use]
 extern crate clap;
 
+mod admin;
+mod admission;
+mod api_keys;
+mod backup;
+mod compress;
 mod config;
+mod conformance;
+mod directory;
+mod error_codes;
+mod events;
+mod features;
+mod http;
+mod inspect;
+mod interval;
+#[cfg(all(test, feature = "it"))]
+mod it;
+mod leader;
+mod logging;
+mod merkle;
+mod metrics;
 mod node;
-mod onion;
+mod nostr;
+mod password;
+mod peer_auth;
+mod peers;
+mod policy;
+mod reload;
+mod replication;
+mod report;
 mod rpc;
-mod secp;
 mod server;
+mod service;
+mod storage;
 mod store;
-mod types;
+mod tcp;
+mod uds;
 mod wallet;
+mod wizard;
 
-const DEFAULT_INTERVAL: u32 = 12 * 60 * 60;
+const DEFAULT_ROUND_INTERVAL: ChainInterval = ChainInterval::Seconds(12 * 60 * 60);
+const DEFAULT_PENDING_COMMITMENT_INTERVAL: ChainInterval = ChainInterval::Seconds(10 * 60);
+const DEFAULT_TIP_STALL_TIMEOUT: ChainInterval = ChainInterval::Seconds(30 * 60);
+const DEFAULT_ROUND_JITTER_PERCENT: u8 = 10;
+const DEFAULT_INSTANCE: &str = "default";
+const DEFAULT_KEY_GRACE_HOURS: u64 = 24;
 
 fn main() {
 	real_main().unwrap();
@@ -43,79 +93,414 @@ fn real_main() -> Result<(), Box<dyn std::error::Error>> {
 	};
 	global::set_local_chain_type(chain_type);
 
+	let profile = args.value_of("profile");
 	let config_path = match args.value_of("config_file") {
 		Some(path) => PathBuf::from(path),
 		None => {
-			let mut grin_path = config::get_grin_path(&chain_type);
+			// A file of profiles can hold configs for both networks, so it's kept in grin's home
+			let mut grin_path = match profile {
+				Some(_) => config::get_grin_home(),
+				None => config::get_grin_path(&chain_type),
+			};
 			grin_path.push("mwixnet-config.toml");
 			grin_path
 		}
 	};
 
-	let round_time = args
-		.value_of("round_time")
-		.map(|t| t.parse::<u32>().unwrap());
+	let round_interval = match args.value_of("round_blocks") {
+		Some(blocks) => Some(ChainInterval::Blocks(blocks.parse()?)),
+		None => args
+			.value_of("round_interval")
+			.map(|i| i.parse::<ChainInterval>())
+			.transpose()?,
+	};
 	let bind_addr = args.value_of("bind_addr");
+	let public_url = args.value_of("public_url");
 	let grin_node_url = args.value_of("grin_node_url");
 	let grin_node_secret_path = args.value_of("grin_node_secret_path");
 	let wallet_owner_url = args.value_of("wallet_owner_url");
 	let wallet_owner_secret_path = args.value_of("wallet_owner_secret_path");
+	let admin_socket = args.value_of("admin_socket");
+	let db_root = args.value_of("db_root");
+	let wallet_backend = args
+		.value_of("wallet_backend")
+		.map(|b| b.parse::<WalletBackend>())
+		.transpose()?;
+
+	// Check a (possibly remote) server's conformance to the protocol
+	if let ("conformance", Some(conformance_args)) = args.subcommand() {
+		let server_addr = parse_addr(
+			conformance_args
+				.value_of("server")
+				.ok_or("Server address is required")?,
+		)?;
+		let server_pubkey = match conformance_args.value_of("server_pubkey") {
+			Some(hex) => Some(secp::PublicKey::from_slice(
+				&secp::Secp256k1::new(),
+				&grin_util::from_hex(hex)?,
+			)?),
+			None => None,
+		};
+		let coin = match (
+			conformance_args.value_of("coin_value"),
+			conformance_args.value_of("coin_blind"),
+		) {
+			(Some(value), Some(blind)) => Some(TestCoin {
+				value: value.parse()?,
+				blind: secp::SecretKey::from_slice(
+					&secp::Secp256k1::new(),
+					&grin_util::from_hex(blind)?,
+				)?,
+			}),
+			(None, None) => None,
+			_ => return Err("coin_value and coin_blind must be provided together".into()),
+		};
+
+		let client = HttpClient::new(conformance_args.value_of("proxy"));
+		let outcomes = ConformanceRunner::new(server_addr, server_pubkey, coin, client).run();
+		for outcome in &outcomes {
+			println!("{}", outcome);
+		}
+		let failed = outcomes
+			.iter()
+			.filter(|o| matches!(o.result, CheckResult::Fail(_)))
+			.count();
+		if failed > 0 {
+			return Err(format!("{} conformance checks failed", failed).into());
+		}
+		return Ok(());
+	}
+
+	// Build a swap along a random route of mix nodes picked from a signed directory
+	if let ("build-swap", Some(swap_args)) = args.subcommand() {
+		let authority = secp::PublicKey::from_slice(
+			&secp::Secp256k1::new(),
+			&grin_util::from_hex(swap_args.value_of("directory_pubkey").unwrap())?,
+		)?;
+		let client = HttpClient::new(swap_args.value_of("proxy"));
+		let directory = directory::fetch(swap_args.value_of("directory").unwrap(), &client)?;
+		let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+		directory.validate(&authority, MAX_DIRECTORY_AGE_S, now)?;
+
+		// Ask the mix nodes which of their peers are down, so the route avoids them
+		let unavailable = if swap_args.is_present("check_peers") {
+			peers::unavailable_mixnodes(&client, &directory.mixnodes)
+		} else {
+			HashSet::new()
+		};
+		let hops = swap_args.value_of("hops").unwrap_or("1").parse()?;
+		let route = directory.pick_route(hops, &unavailable)?;
+		let blind = secp::SecretKey::from_slice(
+			&secp::Secp256k1::new(),
+			&grin_util::from_hex(swap_args.value_of("coin_blind").unwrap())?,
+		)?;
+		let swap = directory::build_swap(
+			&route,
+			swap_args.value_of("coin_value").unwrap().parse()?,
+			&blind,
+			swap_args.value_of("expiry_height").unwrap().parse()?,
+			swap_args.is_present("owner_kernel"),
+			swap_args
+				.value_of("min_output_value")
+				.map(|v| v.parse())
+				.transpose()?,
+			swap_args.is_present("view_tags"),
+			swap_args
+				.value_of("cipher_suite")
+				.map(|s| s.parse())
+				.transpose()?
+				.unwrap_or_default(),
+		)?;
+
+		let comsig =
+			grin_core::ser::ser_vec(&swap.comsig, grin_core::ser::ProtocolVersion::local())?;
+		let result = json!({
+			"server": swap.route[0].url,
+			"route": swap.route,
+			"swap": {
+				"onion": swap.onion,
+				"comsig": comsig.to_hex(),
+				"expiry_height": swap.expiry_height,
+			},
+			"output_value": swap.output_value,
+			"output_blind": swap.output_blind.0.to_hex(),
+		});
+		println!("{}", serde_json::to_string_pretty(&result)?);
+		return Ok(());
+	}
+
+	// Check the config file for mistakes, without needing its password
+	if let ("check-config", Some(_)) = args.subcommand() {
+		let report = config::check_config(&config_path, profile)?;
+		for issue in &report.issues {
+			println!("{}", issue);
+		}
+		for (field, value) in &report.defaults {
+			println!("default: {} = {}", field, value);
+		}
+		if !report.is_valid() {
+			return Err(format!("{} is invalid", config_path.to_string_lossy()).into());
+		}
+		println!("{} is valid", config_path.to_string_lossy());
+		return Ok(());
+	}
+
+	// Forward admin commands to the running server over its admin socket
+	if let (
+		command @ ("status" | "trigger-round" | "list-swaps" | "list-rounds" | "fee-report"
+		| "anonymity-stats" | "daily-report" | "promote" | "reload-config" | "features"
+		| "list-rejections"),
+		Some(_),
+	) = args.subcommand()
+	{
+		let socket_path = admin_socket_path(admin_socket, &config_path, profile)?;
+		let result = admin::send_command(&socket_path, &command.replace("-", "_"), vec![])?;
+		println!("{}", serde_json::to_string_pretty(&result)?);
+		return Ok(());
+	}
+
+	// Look up a single round of the running server
+	if let ("get-round", Some(round_args)) = args.subcommand() {
+		let socket_path = admin_socket_path(admin_socket, &config_path, profile)?;
+		let params = vec![json!(round_args.value_of("kernel_hash").unwrap())];
+		let result = admin::send_command(&socket_path, "get_round", params)?;
+		println!("{}", serde_json::to_string_pretty(&result)?);
+		return Ok(());
+	}
+
+	// Pause or resume part of the running server
+	if let ("set-feature", Some(feature_args)) = args.subcommand() {
+		let feature: Feature = feature_args.value_of("feature").unwrap().parse()?;
+		let enabled = feature_args.value_of("state") == Some("on");
+		let socket_path = admin_socket_path(admin_socket, &config_path, profile)?;
+		let params = vec![json!(feature.name()), json!(enabled)];
+		let result = admin::send_command(&socket_path, "set_feature", params)?;
+		println!("{}", serde_json::to_string_pretty(&result)?);
+		return Ok(());
+	}
 
 	// Write a new config file if init-config command is supplied
-	if let ("init-config", Some(_)) = args.subcommand() {
-		if config_path.exists() {
+	if let ("init-config", Some(init_args)) = args.subcommand() {
+		let instance = init_args.value_of("instance");
+		let mut config_path = config_path;
+		if instance.is_none() && profile.is_none() && config_path.exists() {
 			panic!(
 				"Config file already exists at {}",
 				config_path.to_string_lossy()
 			);
 		}
 
+		// Harder key derivation may be asked for, slowing down anyone guessing the password
+		let defaults = KdfParams::default();
+		let kdf_param = |name: &str, default: u32| -> Result<u32, std::num::ParseIntError> {
+			init_args.value_of(name).map_or(Ok(default), |v| v.parse())
+		};
+		let kdf = KdfParams {
+			memory_kib: kdf_param("kdf_memory_kib", defaults.memory_kib)?,
+			iterations: kdf_param("kdf_iterations", defaults.iterations)?,
+			parallelism: kdf_param("kdf_parallelism", defaults.parallelism)?,
+		};
+
+		let given = Given {
+			grin_node_url: grin_node_url.map(parse_addr).transpose()?,
+			grin_node_secret_path: grin_node_secret_path.map(|p| p.to_owned()),
+			wallet_backend,
+			wallet_owner_url: wallet_owner_url.map(parse_addr).transpose()?,
+			wallet_owner_secret_path: wallet_owner_secret_path.map(|p| p.to_owned()),
+			addr: bind_addr.map(|a| a.parse()).transpose()?,
+			round_interval,
+		};
+
+		// Walk through the settings interactively, unless they're being scripted
+		let setup = if instance.is_none() && !init_args.is_present("no_wizard") && stdin_is_tty() {
+			let stdin = std::io::stdin();
+			let setup = Wizard::new(stdin.lock(), std::io::stdout()).run(
+				chain_type,
+				&given,
+				DEFAULT_ROUND_INTERVAL,
+			)?;
+
+			// The config for another network belongs in that network's directory
+			if setup.chain_type != chain_type {
+				if args.value_of("config_file").is_none() && profile.is_none() {
+					config_path = config::get_grin_path(&setup.chain_type);
+					config_path.push("mwixnet-config.toml");
+					if config_path.exists() {
+						panic!(
+							"Config file already exists at {}",
+							config_path.to_string_lossy()
+						);
+					}
+				}
+				if setup.chain_type == ChainTypes::Testnet {
+					println!("Remember to start the server with --testnet.");
+				}
+			}
+			setup
+		} else {
+			Setup::from_given(chain_type, &given, DEFAULT_ROUND_INTERVAL)
+		};
+
 		let server_config = ServerConfig {
 			key: secp::random_secret(),
-			interval_s: round_time.unwrap_or(DEFAULT_INTERVAL),
-			addr: bind_addr.unwrap_or("0.0.0.0:3000").parse()?,
-			grin_node_url: match grin_node_url {
-				Some(u) => u.parse()?,
-				None => config::grin_node_url(&chain_type),
-			},
-			grin_node_secret_path: match grin_node_secret_path {
-				Some(p) => Some(p.to_owned()),
-				None => config::node_secret_path(&chain_type)
-					.to_str()
-					.map(|p| p.to_owned()),
-			},
-			wallet_owner_url: match wallet_owner_url {
-				Some(u) => u.parse()?,
-				None => config::wallet_owner_url(&chain_type),
-			},
-			wallet_owner_secret_path: match wallet_owner_secret_path {
-				Some(p) => Some(p.to_owned()),
-				None => config::wallet_owner_secret_path(&chain_type)
-					.to_str()
-					.map(|p| p.to_owned()),
+			round_interval: setup.round_interval,
+			addr: setup.addr,
+			grin_node_url: setup.grin_node_url,
+			grin_node_secret_path: setup.grin_node_secret_path,
+			wallet_owner_url: setup.wallet_owner_url,
+			wallet_owner_secret_path: setup.wallet_owner_secret_path,
+			admin_socket_path: admin_socket.map(|p| p.to_owned()),
+			wallet_backend: setup.wallet_backend,
+			pending_commitment_interval: Some(DEFAULT_PENDING_COMMITMENT_INTERVAL),
+			tip_stall_timeout: Some(DEFAULT_TIP_STALL_TIMEOUT),
+			report_dir: None,
+			round_jitter_percent: Some(DEFAULT_ROUND_JITTER_PERCENT),
+			round_delay_window: None,
+			dandelion_stem: true,
+			standby_addr: None,
+			replication_addr: None,
+			replication_lease: None,
+			previous_key: None,
+			fee_seed: match setup.wallet_backend {
+				WalletBackend::Seed => Some(secp::random_secret()),
+				_ => None,
 			},
+			state_key: None,
+			kdf: Some(kdf),
+			acceptance_windows: false,
+			explorer_kernel_url: None,
+			explorer_block_url: None,
+			swap_ttl: None,
+			public_url: public_url.map(|u| u.to_owned()),
+			log_level: None,
+			log_dir: None,
+			zero_conf_max_pending: None,
+			nostr_relays: None,
+			db_root: db_root.map(|p| p.to_owned()),
+			max_swaps_per_round: None,
+			round_selection: RoundSelection::default(),
+			min_anonymity_set: None,
+			proxy: None,
+			events_addr: None,
+			store_backend: StoreBackend::default(),
+			min_output_value: None,
+			metrics_addr: None,
+			peers: None,
+			admin_addr: None,
+			nrd_relative_height: None,
+			compress_archived_swaps: false,
+			accepted_cipher_suites: None,
+			peer_pubkeys: None,
+			round_peers: None,
+			intake_cutoff: None,
+			rejection_log_size: None,
+			http_timeout: None,
+			http_retries: None,
+			http_backoff: None,
+			deny_list_path: None,
+			min_input_confirmations: None,
+			api_keys: None,
+			trusted_proxies: None,
 		};
 
-		let password = prompt_password_confirm();
-		config::write_config(&config_path, &server_config, &password)?;
+		match instance {
+			Some(name) => {
+				// Instance keys are encrypted with the same password as the main server key
+				let password = server_password(&args)?;
+				config::add_instance_config(
+					&config_path,
+					profile,
+					name,
+					&server_config,
+					&password,
+				)?;
+				println!(
+					"Instance '{}' added to {:?}. Please back this file up in a safe place.",
+					name, config_path
+				);
+			}
+			None => {
+				let password = server_password_confirm(&args)?;
+				config::write_config(&config_path, profile, &server_config, &password)?;
+				println!(
+					"Config file written to {:?}. Please back this file up in a safe place.",
+					config_path
+				);
+			}
+		}
+		return Ok(());
+	}
+
+	// Rotate the server key, saving it to the config file before switching the running server to it
+	if let ("rotate-key", Some(rotate_args)) = args.subcommand() {
+		let grace_hours = rotate_args
+			.value_of("grace_hours")
+			.map(|h| h.parse::<u64>())
+			.transpose()?
+			.unwrap_or(DEFAULT_KEY_GRACE_HOURS);
+		let expires_at =
+			SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() + grace_hours * 60 * 60;
+
+		let new_key = secp::random_secret();
+		let password = server_password(&args)?;
+		let server_config =
+			config::rotate_config_key(&config_path, profile, &new_key, expires_at, &password)?;
 		println!(
-			"Config file written to {:?}. Please back this file up in a safe place.",
+			"New server key saved to {:?}. Please back this file up in a safe place.",
 			config_path
 		);
+
+		let socket_path = admin_socket
+			.map(|p| p.to_owned())
+			.or(server_config.admin_socket_path);
+		match socket_path {
+			Some(socket_path) => {
+				let params = vec![json!(new_key.0.to_hex()), json!(expires_at)];
+				match admin::send_command(&PathBuf::from(socket_path), "rotate_key", params) {
+					Ok(keys) => println!("{}", serde_json::to_string_pretty(&keys)?),
+					Err(e) => println!(
+						"Unable to update the running server ({}). The new key will be used once it restarts.",
+						e
+					),
+				}
+			}
+			None => println!("The new key will be used once the server restarts."),
+		}
+		return Ok(());
+	}
+
+	let password = server_password(&args)?;
+	let mut server_config = config::load_config(&config_path, profile, &password)?;
+
+	// Sign a directory of mix nodes with the server key, making this server its authority
+	if let ("sign-directory", Some(directory_args)) = args.subcommand() {
+		let mixnodes: Vec<Mixnode> = serde_json::from_str(&std::fs::read_to_string(
+			directory_args.value_of("mixnodes").unwrap(),
+		)?)?;
+		let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+		let directory = Directory::sign(mixnodes, now, &server_config.key)?;
+		println!("{}", serde_json::to_string_pretty(&directory)?);
 		return Ok(());
 	}
 
-	let password = prompt_password();
-	let mut server_config = config::load_config(&config_path, &password)?;
+	// Override round_interval, if supplied
+	if let Some(round_interval) = round_interval {
+		server_config.round_interval = round_interval;
+	}
 
 	// Override bind_addr, if supplied
 	if let Some(bind_addr) = bind_addr {
 		server_config.addr = bind_addr.parse()?;
 	}
 
+	// Override public_url, if supplied
+	if let Some(public_url) = public_url {
+		server_config.public_url = Some(public_url.to_owned());
+	}
+
 	// Override grin_node_url, if supplied
 	if let Some(grin_node_url) = grin_node_url {
-		server_config.grin_node_url = grin_node_url.parse()?;
+		server_config.grin_node_url = parse_addr(grin_node_url)?;
 	}
 
 	// Override grin_node_secret_path, if supplied
@@ -125,7 +510,7 @@ fn real_main() -> Result<(), Box<dyn std::error::Error>> {
 
 	// Override wallet_owner_url, if supplied
 	if let Some(wallet_owner_url) = wallet_owner_url {
-		server_config.wallet_owner_url = wallet_owner_url.parse()?;
+		server_config.wallet_owner_url = parse_addr(wallet_owner_url)?;
 	}
 
 	// Override wallet_owner_secret_path, if supplied
@@ -133,29 +518,99 @@ fn real_main() -> Result<(), Box<dyn std::error::Error>> {
 		server_config.wallet_owner_secret_path = Some(wallet_owner_secret_path.to_owned());
 	}
 
-	// Open wallet
-	let wallet_pass = prompt_wallet_password(&args.value_of("wallet_pass"));
-	let wallet = HttpWallet::open_wallet(
-		&server_config.wallet_owner_url,
-		&server_config.wallet_owner_api_secret(),
-		&wallet_pass,
-	)?;
+	// Override admin_socket_path, if supplied
+	if let Some(admin_socket) = admin_socket {
+		server_config.admin_socket_path = Some(admin_socket.to_owned());
+	}
 
-	// Create GrinNode
-	let node = HttpGrinNode::new(
-		&server_config.grin_node_url,
-		&server_config.node_api_secret(),
-	);
-
-	// Open SwapStore
-	let store = SwapStore::new(
-		config::get_grin_path(&chain_type) // todo: load from config
-			.join("db")
-			.to_str()
-			.ok_or(StoreError::OpenError(grin_store::lmdb::Error::FileErr(
-				"db_root path error".to_string(),
-			)))?,
-	)?;
+	// Override db_root, if supplied
+	if let Some(db_root) = db_root {
+		server_config.db_root = Some(db_root.to_owned());
+	}
+
+	// Override wallet_backend, if supplied
+	if let Some(wallet_backend) = wallet_backend {
+		server_config.wallet_backend = wallet_backend;
+	}
+
+	// Export or import an instance's swap store, while the server is stopped
+	if let (command @ ("backup" | "restore"), Some(backup_args)) = args.subcommand() {
+		let (name, instance_config) = match backup_args.value_of("instance") {
+			Some(name) => config::load_instance_configs(&config_path, profile, &password)?
+				.into_iter()
+				.find(|(instance, _)| instance == name)
+				.ok_or(format!("No instance named '{}'", name))?,
+			None => (DEFAULT_INSTANCE.to_string(), server_config),
+		};
+		let store = open_store(&name, &instance_config, &chain_type)?;
+		if command == "backup" {
+			let out = PathBuf::from(backup_args.value_of("out").unwrap());
+			let count = backup::write_backup(&store, &instance_config.key, &out)?;
+			println!("Backed up {} records to {:?}", count, out);
+		} else {
+			let file = PathBuf::from(backup_args.value_of("file").unwrap());
+			let count = backup::restore_backup(&store, &instance_config.key, &file)?;
+			println!("Restored {} records from {:?}", count, file);
+		}
+		return Ok(());
+	}
+
+	// Print stored swaps for debugging, without writing to the store
+	if let ("db", Some(db_args)) = args.subcommand() {
+		let (command, command_args) = db_args.subcommand();
+		let command_args = command_args.ok_or("Expected 'get' or 'list'")?;
+		let (name, instance_config) = match command_args.value_of("instance") {
+			Some(name) => config::load_instance_configs(&config_path, profile, &password)?
+				.into_iter()
+				.find(|(instance, _)| instance == name)
+				.ok_or(format!("No instance named '{}'", name))?,
+			None => (DEFAULT_INSTANCE.to_string(), server_config),
+		};
+		let store = open_store_read_only(&name, &instance_config, &chain_type)?;
+		let reveal_secrets = command_args.is_present("reveal_secrets");
+		let result = if command == "get" {
+			let commitment = command_args.value_of("commitment").unwrap();
+			let commit = secp::commitment_from_hex(commitment).ok_or("Invalid commitment")?;
+			let swap = inspect::find_swap(&store, &commit)?
+				.ok_or(format!("No swap spends or creates {}", commitment))?;
+			json!(inspect::SwapRecord::new(&swap, reveal_secrets))
+		} else {
+			let statuses: Vec<&str> = command_args
+				.values_of("status")
+				.map(|v| v.collect())
+				.unwrap_or_default();
+			let swaps = inspect::list_swaps(&store, &statuses)?;
+			json!(swaps
+				.iter()
+				.map(|s| inspect::SwapRecord::new(s, reveal_secrets))
+				.collect::<Vec<_>>())
+		};
+		println!("{}", serde_json::to_string_pretty(&result)?);
+		return Ok(());
+	}
+
+	// Detach from the terminal before any threads are started. Passwords can't be prompted for
+	// once detached, so the wallet's must be given some other way.
+	if args.is_present("daemon") {
+		if server_config.wallet_backend == WalletBackend::OwnerApi
+			&& args.value_of("wallet_pass").is_none()
+			&& password::non_interactive_password(
+				args.value_of("wallet_password_file"),
+				WALLET_PASSWORD_ENV,
+			)?
+			.is_none()
+		{
+			return Err(format!(
+				"--daemon needs the wallet password from --wallet_password_file or {}",
+				WALLET_PASSWORD_ENV
+			)
+			.into());
+		}
+		service::daemonize()?;
+	}
+
+	// Instances all log through the main config's logger
+	let _log_guard = logging::init(&server_config)?;
 
 	let stop_state = Arc::new(StopState::new());
 	let stop_state_clone = stop_state.clone();
@@ -166,14 +621,256 @@ fn real_main() -> Result<(), Box<dyn std::error::Error>> {
 		stop_state_clone.stop();
 	});
 
-	// Start the mwixnet JSON-RPC HTTP server
-	rpc::listen(
+	// Every instance reloads its config on SIGHUP
+	let config_source = ConfigSource {
+		config_path: config_path.clone(),
+		profile: profile.map(|p| p.to_owned()),
+		instance: None,
+		reload_requests: Arc::new(AtomicUsize::new(0)),
+	};
+	rt.spawn(build_reload_fut(config_source.reload_requests.clone()));
+
+	// Start the main server, plus any additional instances configured
+	let mut handles = vec![start_instance(
+		DEFAULT_INSTANCE,
 		server_config,
-		Arc::new(wallet),
-		Arc::new(node),
-		store,
-		stop_state,
-	)
+		&config_source,
+		&args,
+		&chain_type,
+		&stop_state,
+	)?];
+	for (name, instance_config) in config::load_instance_configs(&config_path, profile, &password)?
+	{
+		let result = start_instance(
+			&name,
+			instance_config,
+			&config_source,
+			&args,
+			&chain_type,
+			&stop_state,
+		);
+		match result {
+			Ok(handle) => handles.push(handle),
+			Err(e) => {
+				stop_state.stop();
+				let _ = supervise(handles, &stop_state);
+				return Err(e);
+			}
+		}
+	}
+
+	supervise(handles, &stop_state)
+}
+
+/// The admin socket to send commands to: the one passed on the command line,
+/// or else the one in the config file
+fn admin_socket_path(
+	admin_socket: Option<&str>,
+	config_path: &PathBuf,
+	profile: Option<&str>,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+	match admin_socket {
+		Some(p) => Ok(PathBuf::from(p)),
+		None => Ok(PathBuf::from(
+			config::load_admin_socket_path(config_path, profile)?
+				.ok_or("Admin socket is not configured")?,
+		)),
+	}
+}
+
+/// The default location of an instance's SwapStore, and the one configured by 'db_root'
+fn db_roots(
+	name: &str,
+	server_config: &ServerConfig,
+	chain_type: &ChainTypes,
+) -> (PathBuf, PathBuf) {
+	let instance_root = |root: PathBuf| match name {
+		DEFAULT_INSTANCE => root,
+		_ => root.join("instances").join(name),
+	};
+	let legacy_root = instance_root(config::default_db_root(&chain_type));
+	let db_root = match &server_config.db_root {
+		Some(db_root) => instance_root(PathBuf::from(db_root)),
+		None => legacy_root.clone(),
+	};
+	(legacy_root, db_root)
+}
+
+/// Opens an instance's SwapStore, first moving it from the default location
+/// if 'db_root' points elsewhere
+fn open_store(
+	name: &str,
+	server_config: &ServerConfig,
+	chain_type: &ChainTypes,
+) -> Result<SwapStore, Box<dyn std::error::Error>> {
+	let (legacy_root, db_root) = db_roots(name, server_config, chain_type);
+	let backend = server_config.store_backend;
+	if db_root != legacy_root && SwapStore::migrate(&legacy_root, &db_root, backend)? {
+		info!(
+			"Moved swap database from {:?} to {:?}",
+			legacy_root, db_root
+		);
+	}
+	let store =
+		SwapStore::open(&db_root, backend)?.with_compression(server_config.compress_archived_swaps);
+	let indexed = store.index_outputs()?;
+	if indexed > 0 {
+		info!("Indexed the outputs of {} existing swaps", indexed);
+	}
+	let indexed = store.index_kernels()?;
+	if indexed > 0 {
+		info!("Indexed the swaps of {} existing rounds", indexed);
+	}
+	Ok(store)
+}
+
+/// Opens an instance's existing SwapStore only to read it: unlike 'open_store', nothing is moved,
+/// indexed, or created
+fn open_store_read_only(
+	name: &str,
+	server_config: &ServerConfig,
+	chain_type: &ChainTypes,
+) -> Result<SwapStore, Box<dyn std::error::Error>> {
+	let (legacy_root, db_root) = db_roots(name, server_config, chain_type);
+	let backend = server_config.store_backend;
+	// The store is only moved to 'db_root' once the server runs with it configured
+	let root = [db_root, legacy_root]
+		.into_iter()
+		.find(|root| SwapStore::exists(root, backend))
+		.ok_or("No swap store has been created yet")?;
+	Ok(SwapStore::open(&root, backend)?)
+}
+
+/// A server instance running in its own thread
+type InstanceHandle = (String, JoinHandle<Result<(), String>>, Arc<InstanceStatus>);
+
+/// Opens the wallet, node, and store for a server instance, then starts its listener.
+/// Each instance's swaps are kept in a separate store, namespaced by the instance name.
+fn start_instance(
+	name: &str,
+	server_config: ServerConfig,
+	config_source: &ConfigSource,
+	args: &clap::ArgMatches,
+	chain_type: &ChainTypes,
+	stop_state: &Arc<StopState>,
+) -> Result<InstanceHandle, Box<dyn std::error::Error>> {
+	if name != DEFAULT_INSTANCE {
+		info!("Starting instance '{}'", name);
+	}
+
+	// Open wallet
+	let wallet: Arc<dyn Wallet> = match server_config.wallet_backend {
+		WalletBackend::OwnerApi => {
+			let wallet_pass = wallet_password(args)?;
+			Arc::new(HttpWallet::open_wallet(
+				&server_config.http_client(),
+				&server_config.wallet_owner_url,
+				&server_config.wallet_owner_api_secret(),
+				&wallet_pass,
+			)?)
+		}
+		WalletBackend::Embedded => Arc::new(EmbeddedWallet::from_secret_key(&server_config.key)?),
+		WalletBackend::Seed => {
+			let fee_seed = server_config
+				.fee_seed
+				.as_ref()
+				.ok_or("The seed wallet backend needs a fee_seed in the config")?;
+			Arc::new(EmbeddedWallet::from_fee_seed(fee_seed)?)
+		}
+	};
+
+	// Create GrinNode
+	let node = HttpGrinNode::connect(
+		&server_config.grin_node_url,
+		&server_config.node_api_secret(),
+		server_config.http_client(),
+	)?;
+
+	let store = open_store(name, &server_config, chain_type)?;
+
+	// Start the mwixnet JSON-RPC HTTP server
+	let config_source = ConfigSource {
+		instance: match name {
+			DEFAULT_INSTANCE => None,
+			_ => Some(name.to_string()),
+		},
+		..config_source.clone()
+	};
+	let stop_state = stop_state.clone();
+	let status = Arc::new(InstanceStatus::new());
+	let instance_status = status.clone();
+	let span = info_span!("instance", name);
+	let handle = spawn(move || {
+		let _instance = span.entered();
+		rpc::listen(
+			server_config,
+			config_source,
+			wallet,
+			Arc::new(node),
+			store,
+			stop_state,
+			instance_status,
+		)
+		.map_err(|e| e.to_string())
+	});
+	Ok((name.to_string(), handle, status))
+}
+
+/// Waits for all instances to exit. If any instance stops on its own (e.g. due to an error),
+/// the others are shut down too, so the process never keeps running partially.
+/// Meanwhile, the instances' state is reported to systemd, when run as a notify service.
+fn supervise(
+	mut handles: Vec<InstanceHandle>,
+	stop_state: &Arc<StopState>,
+) -> Result<(), Box<dyn std::error::Error>> {
+	let mut notifier = Notifier::from_env();
+	let mut stopping = false;
+	let mut failed = false;
+	while !handles.is_empty() {
+		sleep(Duration::from_millis(100));
+
+		if stop_state.is_stopped() {
+			if !stopping {
+				stopping = true;
+				if let Err(e) = notifier.stopping() {
+					warn!("Failed to notify service manager: {}", e);
+				}
+			}
+		} else {
+			let statuses: Vec<&InstanceStatus> =
+				handles.iter().map(|(_, _, s)| s.as_ref()).collect();
+			if let Err(e) = notifier.update(&statuses) {
+				warn!("Failed to notify service manager: {}", e);
+			}
+		}
+
+		let (finished, running): (Vec<_>, Vec<_>) =
+			handles.into_iter().partition(|(_, h, _)| h.is_finished());
+		handles = running;
+
+		for (name, handle, _) in finished {
+			match handle.join() {
+				Ok(Ok(())) => {}
+				Ok(Err(e)) => {
+					error!("Instance '{}' failed: {}", name, e);
+					failed = true;
+				}
+				Err(_) => {
+					error!("Instance '{}' panicked", name);
+					failed = true;
+				}
+			}
+
+			if !stop_state.is_stopped() {
+				stop_state.stop();
+			}
+		}
+	}
+
+	if failed {
+		return Err("One or more server instances failed".into());
+	}
+	Ok(())
 }
 
 async fn build_signals_fut() {
@@ -200,6 +897,59 @@ async fn build_signals_fut() {
 	}
 }
 
+/// Counts each SIGHUP received as a request to reload the config
+async fn build_reload_fut(reload_requests: Arc<AtomicUsize>) {
+	if cfg!(unix) {
+		use tokio::signal::unix::{signal, SignalKind};
+
+		let mut hangup_signal =
+			signal(SignalKind::hangup()).expect("failed to create hangup signal");
+		while hangup_signal.recv().await.is_some() {
+			info!("Received SIGHUP. Reloading config.");
+			reload_requests.fetch_add(1, Ordering::Relaxed);
+		}
+	}
+}
+
+/// Whether someone is at a terminal to answer prompts
+#[cfg(unix)]
+fn stdin_is_tty() -> bool {
+	unsafe { libc::isatty(libc::STDIN_FILENO) == 1 }
+}
+
+#[cfg(not(unix))]
+fn stdin_is_tty() -> bool {
+	false
+}
+
+/// The server password, read from '--password_file' or the environment if provided,
+/// and prompted for otherwise
+fn server_password(args: &clap::ArgMatches) -> Result<ZeroingString, PasswordError> {
+	let password =
+		password::non_interactive_password(args.value_of("password_file"), SERVER_PASSWORD_ENV)?;
+	Ok(password.unwrap_or_else(prompt_password))
+}
+
+/// A new server password, which must be entered twice when prompted for
+fn server_password_confirm(args: &clap::ArgMatches) -> Result<ZeroingString, PasswordError> {
+	let password =
+		password::non_interactive_password(args.value_of("password_file"), SERVER_PASSWORD_ENV)?;
+	Ok(password.unwrap_or_else(prompt_password_confirm))
+}
+
+/// The wallet password, from '--wallet_pass', '--wallet_password_file' or the environment
+/// if provided, and prompted for otherwise
+fn wallet_password(args: &clap::ArgMatches) -> Result<ZeroingString, PasswordError> {
+	if let Some(wallet_pass) = args.value_of("wallet_pass") {
+		return Ok(ZeroingString::from(wallet_pass));
+	}
+	let password = password::non_interactive_password(
+		args.value_of("wallet_password_file"),
+		WALLET_PASSWORD_ENV,
+	)?;
+	Ok(password.unwrap_or_else(prompt_wallet_password))
+}
+
 fn prompt_password() -> ZeroingString {
 	ZeroingString::from(rpassword::prompt_password_stdout("Server password: ").unwrap())
 }
@@ -214,11 +964,6 @@ fn prompt_password_confirm() -> ZeroingString {
 	ZeroingString::from(first)
 }
 
-fn prompt_wallet_password(wallet_pass: &Option<&str>) -> ZeroingString {
-	match *wallet_pass {
-		Some(wallet_pass) => ZeroingString::from(wallet_pass),
-		None => {
-			ZeroingString::from(rpassword::prompt_password_stdout("Wallet password: ").unwrap())
-		}
-	}
+fn prompt_wallet_password() -> ZeroingString {
+	ZeroingString::from(rpassword::prompt_password_stdout("Wallet password: ").unwrap())
 }
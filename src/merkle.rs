@@ -0,0 +1,173 @@
+use blake2::blake2b::Blake2b;
+use grin_util::ToHex;
+use serde::{Deserialize, Serialize};
+
+/// A node of a Merkle tree
+pub type MerkleHash = [u8; 32];
+
+const LEAF_TAG: u8 = 0;
+const NODE_TAG: u8 = 1;
+
+/// Hashes the data of a leaf. Leaves and internal nodes are tagged differently,
+/// so a leaf can never be mistaken for an internal node.
+pub fn hash_leaf(data: &[u8]) -> MerkleHash {
+	let mut hasher = Blake2b::new(32);
+	hasher.update(&[LEAF_TAG]);
+	hasher.update(data);
+
+	let mut hash = [0; 32];
+	hash.copy_from_slice(hasher.finalize().as_bytes());
+	hash
+}
+
+fn hash_node(left: &MerkleHash, right: &MerkleHash) -> MerkleHash {
+	let mut hasher = Blake2b::new(32);
+	hasher.update(&[NODE_TAG]);
+	hasher.update(left);
+	hasher.update(right);
+
+	let mut hash = [0; 32];
+	hash.copy_from_slice(hasher.finalize().as_bytes());
+	hash
+}
+
+/// A binary Merkle tree. When a level has an odd number of nodes, the last node is paired with itself.
+#[derive(Clone, Debug)]
+pub struct MerkleTree {
+	levels: Vec<Vec<MerkleHash>>,
+}
+
+impl MerkleTree {
+	pub fn new(leaves: Vec<MerkleHash>) -> MerkleTree {
+		let mut levels = vec![leaves];
+		while levels.last().unwrap().len() > 1 {
+			let level = levels.last().unwrap();
+			let next: Vec<MerkleHash> = level
+				.chunks(2)
+				.map(|pair| hash_node(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+				.collect();
+			levels.push(next);
+		}
+		MerkleTree { levels }
+	}
+
+	/// The root of the tree, or all zeroes if the tree is empty
+	pub fn root(&self) -> MerkleHash {
+		self.levels
+			.last()
+			.and_then(|l| l.first())
+			.cloned()
+			.unwrap_or([0; 32])
+	}
+
+	/// Builds a proof that the leaf at 'index' is included in the tree
+	pub fn proof(&self, index: usize) -> Option<MerkleProof> {
+		if index >= self.levels[0].len() {
+			return None;
+		}
+
+		let mut siblings = Vec::new();
+		let mut i = index;
+		for level in &self.levels[..self.levels.len() - 1] {
+			let sibling = if i % 2 == 0 {
+				level.get(i + 1).unwrap_or(&level[i])
+			} else {
+				&level[i - 1]
+			};
+			siblings.push(sibling.clone());
+			i /= 2;
+		}
+
+		Some(MerkleProof { index, siblings })
+	}
+}
+
+/// Proof that a leaf is included in a Merkle tree
+#[derive(Clone, Debug, PartialEq)]
+pub struct MerkleProof {
+	pub index: usize,
+	pub siblings: Vec<MerkleHash>,
+}
+
+impl MerkleProof {
+	/// Checks that the leaf hashes up to the expected root
+	pub fn verify(&self, leaf: &MerkleHash, root: &MerkleHash) -> bool {
+		let mut hash = leaf.clone();
+		let mut i = self.index;
+		for sibling in &self.siblings {
+			hash = if i % 2 == 0 {
+				hash_node(&hash, sibling)
+			} else {
+				hash_node(sibling, &hash)
+			};
+			i /= 2;
+		}
+		hash == *root
+	}
+}
+
+/// JSON representation of a 'MerkleProof', with hashes hex-encoded
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct MerkleProofJson {
+	pub index: usize,
+	pub siblings: Vec<String>,
+}
+
+impl MerkleProofJson {
+	/// Decodes the hex-encoded proof, returning None if any of the hashes are invalid
+	pub fn to_proof(&self) -> Option<MerkleProof> {
+		let siblings: Option<Vec<MerkleHash>> =
+			self.siblings.iter().map(|s| hash_from_hex(s)).collect();
+		Some(MerkleProof {
+			index: self.index,
+			siblings: siblings?,
+		})
+	}
+}
+
+impl From<&MerkleProof> for MerkleProofJson {
+	fn from(proof: &MerkleProof) -> Self {
+		MerkleProofJson {
+			index: proof.index,
+			siblings: proof.siblings.iter().map(|s| s.to_hex()).collect(),
+		}
+	}
+}
+
+/// Decodes a hex-encoded 'MerkleHash'
+pub fn hash_from_hex(hex: &str) -> Option<MerkleHash> {
+	let bytes = grin_util::from_hex(hex).ok()?;
+	if bytes.len() != 32 {
+		return None;
+	}
+
+	let mut hash = [0; 32];
+	hash.copy_from_slice(&bytes);
+	Some(hash)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{hash_leaf, MerkleHash, MerkleTree};
+
+	/// Every leaf has a valid proof, for trees of both even and odd sizes,
+	/// and proofs don't verify for other leaves or roots.
+	#[test]
+	fn merkle_proofs() {
+		for size in 1..10u8 {
+			let leaves: Vec<MerkleHash> = (0..size).map(|i| hash_leaf(&[i])).collect();
+			let tree = MerkleTree::new(leaves.clone());
+			let root = tree.root();
+
+			for (i, leaf) in leaves.iter().enumerate() {
+				let proof = tree.proof(i).unwrap();
+				assert!(proof.verify(leaf, &root));
+				assert!(!proof.verify(&hash_leaf(&[size]), &root));
+				assert!(!proof.verify(leaf, &hash_leaf(&[])));
+			}
+			assert!(tree.proof(leaves.len()).is_none());
+		}
+
+		assert_eq!([0; 32], MerkleTree::new(vec![]).root());
+	}
+}
@@ -0,0 +1,300 @@
+use crate::server::Server;
+use crate::store::{ApiKeySwaps, RoundData};
+
+use hyper::header::HeaderValue;
+use hyper::StatusCode;
+use jsonrpc_core::IoHandler;
+use jsonrpc_http_server::{DomainsValidation, ServerBuilder};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tracing::error;
+
+/// Upper bounds of the buckets rounds are counted in, by anonymity set
+const ANONYMITY_SET_BUCKETS: [u64; 9] = [1, 2, 5, 10, 20, 50, 100, 200, 500];
+
+/// Rounds whose anonymity set was above the previous bucket's bound, and at most 'le'.
+/// The last bucket has no bound.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct AnonymityBucket {
+	pub le: Option<u64>,
+	pub rounds: u64,
+}
+
+/// Distribution of the anonymity sets (distinct participant outputs) of every round executed
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct AnonymityStats {
+	pub rounds: u64,
+	pub min: u64,
+	pub median: u64,
+	pub max: u64,
+	/// sum of the anonymity sets of all rounds
+	pub sum: u64,
+	/// anonymity set of the most recently posted round
+	pub last: Option<u64>,
+	pub buckets: Vec<AnonymityBucket>,
+}
+
+impl AnonymityStats {
+	/// Builds the distribution from the recorded rounds
+	pub fn new(rounds: &[RoundData]) -> AnonymityStats {
+		if rounds.is_empty() {
+			return AnonymityStats::default();
+		}
+
+		let mut sizes: Vec<u64> = rounds.iter().map(|r| r.anonymity_set).collect();
+		sizes.sort_unstable();
+		let last = rounds
+			.iter()
+			.max_by_key(|r| (r.timestamp, r.round_id))
+			.map(|r| r.anonymity_set);
+
+		let mut buckets: Vec<AnonymityBucket> = ANONYMITY_SET_BUCKETS
+			.iter()
+			.map(|le| AnonymityBucket {
+				le: Some(*le),
+				rounds: 0,
+			})
+			.collect();
+		buckets.push(AnonymityBucket {
+			le: None,
+			rounds: 0,
+		});
+		for size in &sizes {
+			let bucket = buckets
+				.iter_mut()
+				.find(|b| b.le.map(|le| *size <= le).unwrap_or(true))
+				.unwrap();
+			bucket.rounds += 1;
+		}
+
+		AnonymityStats {
+			rounds: sizes.len() as u64,
+			min: sizes[0],
+			median: sizes[sizes.len() / 2],
+			max: sizes[sizes.len() - 1],
+			sum: sizes.iter().sum(),
+			last,
+			buckets,
+		}
+	}
+
+	/// The stats in Prometheus' text format, as a histogram plus a gauge for the last round
+	pub fn to_prometheus(&self) -> String {
+		let mut out = String::new();
+		let _ = writeln!(
+			out,
+			"# HELP mwixnet_round_anonymity_set Distinct participant outputs per round"
+		);
+		let _ = writeln!(out, "# TYPE mwixnet_round_anonymity_set histogram");
+		let mut cumulative = 0;
+		for bucket in &self.buckets {
+			cumulative += bucket.rounds;
+			let le = bucket
+				.le
+				.map(|le| le.to_string())
+				.unwrap_or_else(|| "+Inf".to_string());
+			let _ = writeln!(
+				out,
+				"mwixnet_round_anonymity_set_bucket{{le=\"{}\"}} {}",
+				le, cumulative
+			);
+		}
+		let _ = writeln!(out, "mwixnet_round_anonymity_set_sum {}", self.sum);
+		let _ = writeln!(out, "mwixnet_round_anonymity_set_count {}", self.rounds);
+
+		if let Some(last) = self.last {
+			let _ = writeln!(
+				out,
+				"# HELP mwixnet_last_round_anonymity_set Distinct participant outputs in the last round"
+			);
+			let _ = writeln!(out, "# TYPE mwixnet_last_round_anonymity_set gauge");
+			let _ = writeln!(out, "mwixnet_last_round_anonymity_set {}", last);
+		}
+		out
+	}
+}
+
+/// Rounds the preflight checks held back or found short, by reason, since the server started
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct PreflightCounts {
+	/// rounds skipped because the wallet couldn't be reached
+	pub wallet_unreachable: u64,
+	/// rounds skipped because the wallet wasn't open
+	pub wallet_locked: u64,
+	/// rounds executed with fewer spendable decoys than 'min_anonymity_set' called for
+	pub decoys_short: u64,
+}
+
+impl PreflightCounts {
+	/// The counts in Prometheus' text format, as a counter labelled by reason
+	pub fn to_prometheus(&self) -> String {
+		let mut out = String::new();
+		let _ = writeln!(
+			out,
+			"# HELP mwixnet_round_preflight_failures_total Rounds held back or short of decoys, by reason"
+		);
+		let _ = writeln!(out, "# TYPE mwixnet_round_preflight_failures_total counter");
+		for (reason, count) in [
+			("wallet_unreachable", self.wallet_unreachable),
+			("wallet_locked", self.wallet_locked),
+			("decoys_short", self.decoys_short),
+		] {
+			let _ = writeln!(
+				out,
+				"mwixnet_round_preflight_failures_total{{reason=\"{}\"}} {}",
+				reason, count
+			);
+		}
+		out
+	}
+}
+
+/// The swaps submitted with each API key in Prometheus' text format, as a counter labelled by key
+/// and by whether the swap was accepted
+pub fn api_key_swaps_prometheus(swaps: &[ApiKeySwaps]) -> String {
+	let mut out = String::new();
+	if swaps.is_empty() {
+		return out;
+	}
+	let _ = writeln!(
+		out,
+		"# HELP mwixnet_api_key_swaps_total Swaps submitted with each API key, by result"
+	);
+	let _ = writeln!(out, "# TYPE mwixnet_api_key_swaps_total counter");
+	for key in swaps {
+		for (result, count) in [("accepted", key.accepted), ("rejected", key.rejected)] {
+			let _ = writeln!(
+				out,
+				"mwixnet_api_key_swaps_total{{key=\"{}\",result=\"{}\"}} {}",
+				key.name, result, count
+			);
+		}
+	}
+	out
+}
+
+/// Serves the server's metrics to Prometheus at '/metrics'
+pub fn start_http(
+	addr: &SocketAddr,
+	server: Arc<Mutex<dyn Server>>,
+) -> std::io::Result<jsonrpc_http_server::Server> {
+	ServerBuilder::new(IoHandler::new())
+		.cors(DomainsValidation::Disabled)
+		.request_middleware(move |request: hyper::Request<hyper::Body>| {
+			if request.uri() != "/metrics" {
+				return jsonrpc_http_server::Response::bad_request("Only /metrics is supported")
+					.into();
+			}
+			let (rounds, preflight, api_key_swaps) = {
+				let locked = server.lock().unwrap();
+				(
+					locked.list_rounds(),
+					locked.preflight_counts(),
+					locked.api_key_swaps(),
+				)
+			};
+			match rounds.and_then(|rounds| Ok((rounds, api_key_swaps?))) {
+				Ok((rounds, api_key_swaps)) => jsonrpc_http_server::Response {
+					code: StatusCode::OK,
+					content_type: HeaderValue::from_static("text/plain; version=0.0.4"),
+					content: AnonymityStats::new(&rounds).to_prometheus()
+						+ &preflight.to_prometheus()
+						+ &api_key_swaps_prometheus(&api_key_swaps),
+				}
+				.into(),
+				Err(e) => {
+					error!("Failed to read rounds for metrics: {}", e);
+					jsonrpc_http_server::Response::internal_error(e.to_string()).into()
+				}
+			}
+		})
+		.start_http(addr)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{api_key_swaps_prometheus, AnonymityStats, PreflightCounts};
+	use crate::secp::test_util::rand_hash;
+	use crate::store::{ApiKeySwaps, RoundData};
+
+	fn round(round_id: u64, anonymity_set: u64) -> RoundData {
+		RoundData {
+			round_id,
+			kernel_hash: rand_hash(),
+			timestamp: 1_700_000_000 + round_id,
+			fees: vec![100; anonymity_set as usize],
+			anonymity_set,
+			tx_weight: 0,
+			confirmed_height: None,
+		}
+	}
+
+	/// Rounds are counted in the bucket of their anonymity set, and exported cumulatively
+	#[test]
+	fn anonymity_stats() {
+		assert_eq!(AnonymityStats::default(), AnonymityStats::new(&[]));
+
+		let rounds = [round(1, 3), round(2, 12), round(3, 4), round(4, 900)];
+		let stats = AnonymityStats::new(&rounds);
+		assert_eq!(4, stats.rounds);
+		assert_eq!(3, stats.min);
+		assert_eq!(12, stats.median);
+		assert_eq!(900, stats.max);
+		assert_eq!(919, stats.sum);
+		assert_eq!(Some(900), stats.last);
+		assert_eq!(
+			vec![0, 0, 2, 0, 1, 0, 0, 0, 0, 1],
+			stats.buckets.iter().map(|b| b.rounds).collect::<Vec<_>>()
+		);
+		assert_eq!(None, stats.buckets.last().unwrap().le);
+
+		let text = stats.to_prometheus();
+		assert!(text.contains("mwixnet_round_anonymity_set_bucket{le=\"2\"} 0\n"));
+		assert!(text.contains("mwixnet_round_anonymity_set_bucket{le=\"5\"} 2\n"));
+		assert!(text.contains("mwixnet_round_anonymity_set_bucket{le=\"500\"} 3\n"));
+		assert!(text.contains("mwixnet_round_anonymity_set_bucket{le=\"+Inf\"} 4\n"));
+		assert!(text.contains("mwixnet_round_anonymity_set_sum 919\n"));
+		assert!(text.contains("mwixnet_round_anonymity_set_count 4\n"));
+		assert!(text.contains("mwixnet_last_round_anonymity_set 900\n"));
+	}
+
+	/// Every reason is exported, including those that haven't happened
+	#[test]
+	fn preflight_counts() {
+		let counts = PreflightCounts {
+			wallet_unreachable: 2,
+			wallet_locked: 0,
+			decoys_short: 1,
+		};
+		let text = counts.to_prometheus();
+		assert!(text
+			.contains("mwixnet_round_preflight_failures_total{reason=\"wallet_unreachable\"} 2\n"));
+		assert!(
+			text.contains("mwixnet_round_preflight_failures_total{reason=\"wallet_locked\"} 0\n")
+		);
+		assert!(
+			text.contains("mwixnet_round_preflight_failures_total{reason=\"decoys_short\"} 1\n")
+		);
+	}
+
+	/// Each key's swaps are exported by result, and nothing is exported until a key is used
+	#[test]
+	fn api_key_swaps() {
+		assert_eq!("", api_key_swaps_prometheus(&[]));
+
+		let text = api_key_swaps_prometheus(&[ApiKeySwaps {
+			name: "partner".to_string(),
+			accepted: 3,
+			rejected: 1,
+		}]);
+		assert!(text.contains("# TYPE mwixnet_api_key_swaps_total counter\n"));
+		assert!(
+			text.contains("mwixnet_api_key_swaps_total{key=\"partner\",result=\"accepted\"} 3\n")
+		);
+		assert!(
+			text.contains("mwixnet_api_key_swaps_total{key=\"partner\",result=\"rejected\"} 1\n")
+		);
+	}
+}
@@ -1,16 +1,21 @@
+use crate::http::HttpClient;
+use crate::interval;
 use crate::secp::Commitment;
 
-use grin_api::client;
 use grin_api::json_rpc::{build_request, Request, Response};
-use grin_api::{OutputPrintable, OutputType, Tip};
-use grin_core::consensus::COINBASE_MATURITY;
+use grin_api::{OutputPrintable, OutputType, Tip, Version};
+use grin_core::core::hash::Hash;
 use grin_core::core::{Input, OutputFeatures, Transaction};
+use grin_core::global;
+use grin_core::ser;
 use grin_util::ToHex;
 
 use serde_json::json;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 use thiserror::Error;
+use tracing::{debug, error, info, warn};
 
 pub trait GrinNode: Send + Sync {
 	/// Retrieves the unspent output with a matching commitment
@@ -19,8 +24,24 @@ pub trait GrinNode: Send + Sync {
 	/// Gets the height of the chain tip
 	fn get_chain_height(&self) -> Result<u64, NodeError>;
 
-	/// Posts a transaction to the grin node
-	fn post_tx(&self, tx: &Transaction) -> Result<(), NodeError>;
+	/// Posts a transaction to the grin node.
+	/// When 'fluff' is false, the transaction is relayed through the node's dandelion stem phase
+	/// before being broadcast, rather than broadcast to all peers immediately.
+	fn post_tx(&self, tx: &Transaction, fluff: bool) -> Result<(), NodeError>;
+
+	/// Gets the height of the block containing the kernel with a matching excess,
+	/// or None if the kernel isn't on chain (yet)
+	fn get_kernel_height(&self, excess: &Commitment) -> Result<Option<u64>, NodeError>;
+
+	/// Checks whether an output is created by a transaction in the node's mempool
+	fn is_output_in_pool(&self, output_commit: &Commitment) -> Result<bool, NodeError>;
+
+	/// Gets the hash of the block at 'height' on the node's chain, or None if there's no block
+	/// at that height (yet)
+	fn get_block_hash(&self, height: u64) -> Result<Option<Hash>, NodeError>;
+
+	/// Sends all further requests to the node at 'node_url', e.g. after the config is reloaded
+	fn set_endpoint(&self, node_url: &SocketAddr, node_api_secret: &Option<String>);
 }
 
 /// Error types for interacting with nodes
@@ -32,6 +53,10 @@ pub enum NodeError {
 	ApiCommError(grin_api::Error),
 	#[error("Error decoding JSON-RPC response: {0:?}")]
 	ResponseParseError(grin_api::json_rpc::Error),
+	#[error("Could not detect the node's version: {0}")]
+	VersionUnknown(String),
+	#[error("grin-node {0} is not supported. Run grin-node 2.x to {max}.x", max = MAX_SUPPORTED_MAJOR)]
+	UnsupportedVersion(String),
 }
 
 /// Checks if a commitment is in the UTXO set
@@ -40,6 +65,18 @@ pub fn is_unspent(node: &Arc<dyn GrinNode>, commit: &Commitment) -> Result<bool,
 	Ok(utxo.is_some())
 }
 
+/// The first block height an output can be spent in, or None if it can't be determined.
+/// Coinbase outputs must mature before they can be spent. Outputs carry no other locks in grin:
+/// NRD relative locks apply to kernels, and round transactions only ever have plain kernels.
+pub fn spendable_height(output: &OutputPrintable) -> Option<u64> {
+	match output.output_type {
+		OutputType::Coinbase => output
+			.block_height
+			.map(|block_height| block_height + global::coinbase_maturity()),
+		OutputType::Transaction => Some(0),
+	}
+}
+
 /// Checks whether a commitment is spendable at the block height provided
 pub fn is_spendable(
 	node: &Arc<dyn GrinNode>,
@@ -47,26 +84,103 @@ pub fn is_spendable(
 	next_block_height: u64,
 ) -> Result<bool, NodeError> {
 	let output = node.get_utxo(&output_commit)?;
-	if let Some(out) = output {
-		let is_coinbase = match out.output_type {
-			OutputType::Coinbase => true,
-			OutputType::Transaction => false,
-		};
+	Ok(output
+		.and_then(|out| spendable_height(&out))
+		.map(|height| height <= next_block_height)
+		.unwrap_or(false))
+}
 
-		if is_coinbase {
-			if let Some(block_height) = out.block_height {
-				if block_height + COINBASE_MATURITY < next_block_height {
-					return Ok(false);
-				}
-			} else {
-				return Ok(false);
-			}
+/// Counts the confirmations of a commitment's output at the chain height provided.
+/// Outputs not in the UTXO set, or whose block isn't known, have none.
+pub fn confirmations(
+	node: &Arc<dyn GrinNode>,
+	output_commit: &Commitment,
+	height: u64,
+) -> Result<u64, NodeError> {
+	let output = node.get_utxo(&output_commit)?;
+	Ok(output
+		.and_then(|out| out.block_height)
+		.map(|block_height| (height + 1).saturating_sub(block_height))
+		.unwrap_or(0))
+}
+
+/// A change in the height of the chain tip, as observed by a 'TipSubscription'
+#[derive(Clone, Debug, PartialEq)]
+pub struct TipChange {
+	/// The last height observed, or None if this is the first poll
+	pub previous: Option<u64>,
+	/// The current height of the chain tip
+	pub height: u64,
+}
+
+impl TipChange {
+	/// Checks whether the tip moved past a multiple of 'interval' blocks.
+	/// The first observed tip never crosses a boundary, since there is nothing to compare it to.
+	pub fn crossed_boundary(&self, interval: u64) -> bool {
+		match self.previous {
+			Some(previous) if interval > 0 => (self.height / interval) > (previous / interval),
+			_ => false,
+		}
+	}
+
+	/// Whether the tip moved back below the last height observed, meaning blocks were reorged out.
+	/// A reorg to a chain of the same or greater height doesn't show up in the height alone.
+	pub fn is_reorg(&self) -> bool {
+		self.previous.map(|p| self.height < p).unwrap_or(false)
+	}
+}
+
+/// Polls a node for changes to the chain tip
+pub struct TipSubscription {
+	node: Arc<dyn GrinNode>,
+	height: Option<u64>,
+	changed_at: Instant,
+	/// the first height observed, and when, for estimating the block time
+	first_seen: Option<(u64, Instant)>,
+}
+
+impl TipSubscription {
+	pub fn new(node: &Arc<dyn GrinNode>) -> TipSubscription {
+		TipSubscription {
+			node: node.clone(),
+			height: None,
+			changed_at: Instant::now(),
+			first_seen: None,
 		}
+	}
 
-		return Ok(true);
+	/// Average seconds per block since the subscription was created,
+	/// once the tip has advanced far enough for the estimate to be meaningful
+	pub fn block_time_s(&self) -> Option<u64> {
+		let (first_height, first_seen_at) = self.first_seen?;
+		let blocks = self.height?.saturating_sub(first_height);
+		interval::observed_block_time(blocks, self.changed_at - first_seen_at)
 	}
 
-	Ok(false)
+	/// Time elapsed since the tip last changed (or since the subscription was created).
+	/// Failed polls don't reset this, so an unreachable node also appears stalled.
+	pub fn stalled_for(&self) -> Duration {
+		self.changed_at.elapsed()
+	}
+
+	/// Queries the node for the chain height, returning a 'TipChange' if it differs from the last poll
+	pub fn poll(&mut self) -> Result<Option<TipChange>, NodeError> {
+		let height = self.node.get_chain_height()?;
+		if self.height == Some(height) {
+			return Ok(None);
+		}
+
+		let change = TipChange {
+			previous: self.height,
+			height,
+		};
+		self.height = Some(height);
+		self.changed_at = Instant::now();
+		if self.first_seen.is_none() {
+			self.first_seen = Some((height, self.changed_at));
+		}
+		Ok(Some(change))
+	}
 }
 
 /// Builds an input for an unspent output commitment
@@ -89,43 +203,184 @@ pub fn build_input(
 	Ok(None)
 }
 
-/// HTTP (JSON-RPC) implementation of the 'GrinNode' trait
+/// Newest major grin-node version 'HttpGrinNode' has been tested against
+const MAX_SUPPORTED_MAJOR: u64 = 5;
+
+/// The node APIs 'HttpGrinNode' can talk to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NodeApi {
+	/// the JSON-RPC foreign API at '/v2/foreign', served by grin-node 3.0 and later
+	V2Foreign,
+	/// the REST API at '/v1', served by grin-node 2.x. It has no view of the transactions in
+	/// the mempool, so inputs created by unconfirmed transactions are never accepted.
+	V1Rest,
+}
+
+/// Picks the API to talk to a node of the given version with, failing for versions
+/// there's no adapter for
+pub fn select_api(version: &Version) -> Result<NodeApi, NodeError> {
+	let major = version
+		.node_version
+		.split('.')
+		.next()
+		.and_then(|major| major.parse::<u64>().ok());
+	match major {
+		Some(2) => Ok(NodeApi::V1Rest),
+		Some(major) if (3..=MAX_SUPPORTED_MAJOR).contains(&major) => Ok(NodeApi::V2Foreign),
+		_ => Err(NodeError::UnsupportedVersion(version.node_version.clone())),
+	}
+}
+
+/// HTTP implementation of the 'GrinNode' trait, using whichever API the node's version serves
 #[derive(Clone)]
 pub struct HttpGrinNode {
-	node_url: SocketAddr,
-	node_api_secret: Option<String>,
+	/// the node's api address and secret, replaced together when the config is reloaded
+	endpoint: RwLock<(SocketAddr, Option<String>)>,
+	api: RwLock<NodeApi>,
+	client: HttpClient,
 }
 
 const ENDPOINT: &str = "/v2/foreign";
+const V1_ENDPOINT: &str = "/v1";
 
 impl HttpGrinNode {
-	pub fn new(node_url: &SocketAddr, node_api_secret: &Option<String>) -> HttpGrinNode {
+	pub fn new(
+		node_url: &SocketAddr,
+		node_api_secret: &Option<String>,
+		client: HttpClient,
+	) -> HttpGrinNode {
 		HttpGrinNode {
-			node_url: node_url.to_owned(),
-			node_api_secret: node_api_secret.to_owned(),
+			endpoint: RwLock::new((node_url.to_owned(), node_api_secret.to_owned())),
+			api: RwLock::new(NodeApi::V2Foreign),
+			client,
 		}
 	}
 
+	/// Creates a node client using the API the node's version serves.
+	/// Fails if the node runs a version that isn't supported. If its version can't be
+	/// detected (e.g. because it isn't running yet), the v2 foreign API is assumed.
+	pub fn connect(
+		node_url: &SocketAddr,
+		node_api_secret: &Option<String>,
+		client: HttpClient,
+	) -> Result<HttpGrinNode, NodeError> {
+		let node = HttpGrinNode::new(node_url, node_api_secret, client);
+		match node.detect_api() {
+			Ok(api) => *node.api.write().unwrap() = api,
+			Err(NodeError::UnsupportedVersion(version)) => {
+				return Err(NodeError::UnsupportedVersion(version))
+			}
+			Err(e) => warn!("{}. Assuming the node serves the v2 foreign API.", e),
+		}
+		Ok(node)
+	}
+
+	/// Asks the node for its version, over the v2 foreign API or, failing that, the v1 API
+	fn detect_api(&self) -> Result<NodeApi, NodeError> {
+		let version = match self.send_json_request::<serde_json::Value>("get_version", &json!([])) {
+			Ok(version) => serde_json::from_value::<Version>(version["Ok"].clone())
+				.map_err(NodeError::DecodeResponseError)?,
+			Err(v2_error) => self.get_v1::<Version>("version").map_err(|v1_error| {
+				NodeError::VersionUnknown(format!("{} (v1: {})", v2_error, v1_error))
+			})?,
+		};
+		let api = select_api(&version)?;
+		info!(
+			"Node is running grin-node {} (block header version {}), using {:?} API",
+			version.node_version, version.block_header_version, api
+		);
+		Ok(api)
+	}
+
+	fn api(&self) -> NodeApi {
+		*self.api.read().unwrap()
+	}
+
 	fn send_json_request<D: serde::de::DeserializeOwned>(
 		&self,
 		method: &str,
 		params: &serde_json::Value,
 	) -> Result<D, NodeError> {
-		let url = format!("http://{}{}", self.node_url, ENDPOINT);
+		debug!("Calling node API '{}'", method);
+		let (node_url, node_api_secret) = self.endpoint.read().unwrap().clone();
+		let url = format!("http://{}{}", node_url, ENDPOINT);
 		let req = build_request(method, params);
-		let res =
-			client::post::<Request, Response>(url.as_str(), self.node_api_secret.clone(), &req)
-				.map_err(NodeError::ApiCommError)?;
+		let res = self
+			.client
+			.post::<Request, Response>(url.as_str(), node_api_secret, &req)
+			.map_err(NodeError::ApiCommError)?;
 		let parsed = res
 			.clone()
 			.into_result()
 			.map_err(NodeError::ResponseParseError)?;
 		Ok(parsed)
 	}
+
+	fn get_v1<D: serde::de::DeserializeOwned>(&self, path: &str) -> Result<D, NodeError> {
+		debug!("Calling node v1 API '{}'", path);
+		let (node_url, node_api_secret) = self.endpoint.read().unwrap().clone();
+		let url = format!("http://{}{}/{}", node_url, V1_ENDPOINT, path);
+		self.client
+			.get::<D>(url.as_str(), node_api_secret)
+			.map_err(NodeError::ApiCommError)
+	}
+}
+
+/// Adapter for grin-node 2.x's v1 REST API
+impl HttpGrinNode {
+	/// 'outputs/byids' only reports an output's height, so the full output (including whether
+	/// it's a coinbase) is read from the outputs of the block at that height
+	fn v1_get_utxo(
+		&self,
+		output_commit: &Commitment,
+	) -> Result<Option<OutputPrintable>, NodeError> {
+		let commit_hex = output_commit.to_hex();
+		let outputs = self
+			.get_v1::<Vec<serde_json::Value>>(&format!("chain/outputs/byids?id={}", commit_hex))?;
+		let height = match outputs.first().and_then(|o| o["height"].as_u64()) {
+			Some(height) => height,
+			None => return Ok(None),
+		};
+
+		let blocks = self.get_v1::<Vec<serde_json::Value>>(&format!(
+			"chain/outputs/byheight?start_height={}&end_height={}",
+			height, height
+		))?;
+		let output = blocks
+			.iter()
+			.filter_map(|block| block["outputs"].as_array())
+			.flatten()
+			.find(|o| o["commit"] == commit_hex.as_str());
+		match output {
+			Some(output) => serde_json::from_value(output.clone())
+				.map(Some)
+				.map_err(NodeError::DecodeResponseError),
+			None => Ok(None),
+		}
+	}
+
+	fn v1_post_tx(&self, tx: &Transaction, fluff: bool) -> Result<(), NodeError> {
+		let (node_url, node_api_secret) = self.endpoint.read().unwrap().clone();
+		let url = format!(
+			"http://{}{}/pool/push_tx{}",
+			node_url,
+			V1_ENDPOINT,
+			if fluff { "?fluff" } else { "" }
+		);
+		let tx_bin = ser::ser_vec(tx, ser::ProtocolVersion(1))
+			.map_err(|e| NodeError::ApiCommError(grin_api::Error::Internal(e.to_string())))?;
+		self.client
+			.post_no_ret(&url, node_api_secret, &json!({ "tx_hex": tx_bin.to_hex() }))
+			.map_err(NodeError::ApiCommError)
+	}
 }
 
 impl GrinNode for HttpGrinNode {
 	fn get_utxo(&self, output_commit: &Commitment) -> Result<Option<OutputPrintable>, NodeError> {
+		if self.api() == NodeApi::V1Rest {
+			return self.v1_get_utxo(output_commit);
+		}
+
 		let commits: Vec<String> = vec![output_commit.to_hex()];
 		let start_height: Option<u64> = None;
 		let end_height: Option<u64> = None;
@@ -148,6 +403,10 @@ impl GrinNode for HttpGrinNode {
 	}
 
 	fn get_chain_height(&self) -> Result<u64, NodeError> {
+		if self.api() == NodeApi::V1Rest {
+			return Ok(self.get_v1::<Tip>("chain")?.height);
+		}
+
 		let params = json!([]);
 		let tip_json = self.send_json_request::<serde_json::Value>("get_tip", &params)?;
 
@@ -157,11 +416,79 @@ impl GrinNode for HttpGrinNode {
 		Ok(tip.height)
 	}
 
-	fn post_tx(&self, tx: &Transaction) -> Result<(), NodeError> {
-		let params = json!([tx, true]);
+	fn post_tx(&self, tx: &Transaction, fluff: bool) -> Result<(), NodeError> {
+		if self.api() == NodeApi::V1Rest {
+			return self.v1_post_tx(tx, fluff);
+		}
+
+		let params = json!([tx, fluff]);
 		self.send_json_request::<serde_json::Value>("push_transaction", &params)?;
 		Ok(())
 	}
+
+	fn get_kernel_height(&self, excess: &Commitment) -> Result<Option<u64>, NodeError> {
+		if self.api() == NodeApi::V1Rest {
+			// The v1 API responds with a 404 when the kernel can't be found, which the client
+			// doesn't tell apart from other failures. Either way, the kernel isn't known on chain.
+			let path = format!("chain/kernels/{}", excess.to_hex());
+			return match self.get_v1::<serde_json::Value>(&path) {
+				Ok(located) => Ok(located["height"].as_u64()),
+				Err(e) => {
+					debug!("Kernel lookup failed: {}", e);
+					Ok(None)
+				}
+			};
+		}
+
+		let min_height: Option<u64> = None;
+		let max_height: Option<u64> = None;
+		let params = json!([excess.to_hex(), min_height, max_height]);
+		let located = self.send_json_request::<serde_json::Value>("get_kernel", &params)?;
+
+		// The node responds with an 'Err' when the kernel can't be found
+		Ok(located["Ok"]["height"].as_u64())
+	}
+
+	fn is_output_in_pool(&self, output_commit: &Commitment) -> Result<bool, NodeError> {
+		if self.api() == NodeApi::V1Rest {
+			return Ok(false);
+		}
+
+		let params = json!([]);
+		let pool =
+			self.send_json_request::<serde_json::Value>("get_unconfirmed_transactions", &params)?;
+
+		let commit_hex = output_commit.to_hex();
+		let entries = pool["Ok"].as_array().cloned().unwrap_or_default();
+		Ok(entries.iter().any(|entry| {
+			entry["tx"]["body"]["outputs"]
+				.as_array()
+				.map(|outputs| outputs.iter().any(|o| o["commit"] == commit_hex.as_str()))
+				.unwrap_or(false)
+		}))
+	}
+
+	fn get_block_hash(&self, height: u64) -> Result<Option<Hash>, NodeError> {
+		let header = if self.api() == NodeApi::V1Rest {
+			self.get_v1::<serde_json::Value>(&format!("headers/{}", height))?
+		} else {
+			let hash: Option<String> = None;
+			let commit: Option<String> = None;
+			let params = json!([height, hash, commit]);
+			// The node responds with an 'Err' when there's no block at the height
+			self.send_json_request::<serde_json::Value>("get_header", &params)?["Ok"].clone()
+		};
+		Ok(header["hash"].as_str().and_then(|h| Hash::from_hex(h).ok()))
+	}
+
+	/// The new node's version is detected again, keeping the current API if that fails
+	fn set_endpoint(&self, node_url: &SocketAddr, node_api_secret: &Option<String>) {
+		*self.endpoint.write().unwrap() = (node_url.to_owned(), node_api_secret.to_owned());
+		match self.detect_api() {
+			Ok(api) => *self.api.write().unwrap() = api,
+			Err(e) => error!("{}. Still using the {:?} API.", e, self.api()),
+		}
+	}
 }
 
 #[cfg(test)]
@@ -170,27 +497,56 @@ pub mod mock {
 	use crate::secp::Commitment;
 
 	use grin_api::{OutputPrintable, OutputType};
+	use grin_core::core::hash::{Hash, Hashed};
 	use grin_core::core::Transaction;
+	use grin_util::ToHex;
 	use std::collections::HashMap;
+	use std::net::SocketAddr;
 	use std::sync::RwLock;
 
 	/// Implementation of 'GrinNode' trait that mocks a grin node instance.
 	/// Use only for testing purposes.
 	pub struct MockGrinNode {
-		utxos: HashMap<Commitment, OutputPrintable>,
+		utxos: RwLock<HashMap<Commitment, OutputPrintable>>,
+		chain_height: RwLock<u64>,
+		post_tx_fails: RwLock<bool>,
+		post_tx_failures: RwLock<u32>,
 		txns_posted: RwLock<Vec<Transaction>>,
+		fluffed: RwLock<Vec<bool>>,
+		kernels: RwLock<HashMap<Commitment, u64>>,
+		pool: RwLock<Vec<Commitment>>,
+		double_spent: RwLock<Vec<Commitment>>,
 	}
 
 	impl MockGrinNode {
 		pub fn new() -> MockGrinNode {
 			MockGrinNode {
-				utxos: HashMap::new(),
+				utxos: RwLock::new(HashMap::new()),
+				chain_height: RwLock::new(100),
+				post_tx_fails: RwLock::new(false),
+				post_tx_failures: RwLock::new(0),
 				txns_posted: RwLock::new(Vec::new()),
+				fluffed: RwLock::new(Vec::new()),
+				kernels: RwLock::new(HashMap::new()),
+				pool: RwLock::new(Vec::new()),
+				double_spent: RwLock::new(Vec::new()),
 			}
 		}
 
+		/// Makes 'post_tx' reject transactions spending 'input_commit', naming it in the error,
+		/// as a node does once the input is spent by another transaction in its pool
+		pub fn double_spend(&self, input_commit: &Commitment) {
+			self.double_spent
+				.write()
+				.unwrap()
+				.push(input_commit.clone());
+		}
+
 		pub fn add_utxo(&mut self, output_commit: &Commitment, utxo: &OutputPrintable) {
-			self.utxos.insert(output_commit.clone(), utxo.clone());
+			self.utxos
+				.get_mut()
+				.unwrap()
+				.insert(output_commit.clone(), utxo.clone());
 		}
 
 		pub fn add_default_utxo(&mut self, output_commit: &Commitment) {
@@ -208,10 +564,103 @@ pub mod mock {
 			self.add_utxo(&output_commit, &utxo);
 		}
 
+		pub fn add_confirmed_utxo(&mut self, output_commit: &Commitment, block_height: u64) {
+			let utxo = OutputPrintable {
+				output_type: OutputType::Transaction,
+				commit: output_commit.to_owned(),
+				spent: false,
+				proof: None,
+				proof_hash: String::from(""),
+				block_height: Some(block_height),
+				merkle_proof: None,
+				mmr_index: 0,
+			};
+
+			self.add_utxo(&output_commit, &utxo);
+		}
+
+		pub fn add_coinbase_utxo(&mut self, output_commit: &Commitment, block_height: u64) {
+			let utxo = OutputPrintable {
+				output_type: OutputType::Coinbase,
+				commit: output_commit.to_owned(),
+				spent: false,
+				proof: None,
+				proof_hash: String::from(""),
+				block_height: Some(block_height),
+				merkle_proof: None,
+				mmr_index: 0,
+			};
+
+			self.add_utxo(&output_commit, &utxo);
+		}
+
+		pub fn set_chain_height(&self, height: u64) {
+			let mut write = self.chain_height.write().unwrap();
+			*write = height;
+		}
+
+		/// When set, 'post_tx' returns an error instead of accepting the transaction
+		pub fn set_post_tx_fails(&self, fails: bool) {
+			let mut write = self.post_tx_fails.write().unwrap();
+			*write = fails;
+		}
+
+		/// Makes the next 'failures' calls to 'post_tx' return an error, then accept again
+		pub fn fail_next_posts(&self, failures: u32) {
+			let mut write = self.post_tx_failures.write().unwrap();
+			*write = failures;
+		}
+
 		pub fn get_posted_txns(&self) -> Vec<Transaction> {
 			let read = self.txns_posted.read().unwrap();
 			read.clone()
 		}
+
+		/// Whether each posted transaction was fluffed (true) or stemmed (false)
+		pub fn get_posted_fluffed(&self) -> Vec<bool> {
+			let read = self.fluffed.read().unwrap();
+			read.clone()
+		}
+
+		/// Marks the kernel with the given excess as confirmed at 'height'
+		pub fn confirm_kernel(&self, excess: &Commitment, height: u64) {
+			let mut write = self.kernels.write().unwrap();
+			write.insert(excess.clone(), height);
+		}
+
+		/// Drops the kernel with the given excess from the chain, as a reorg would
+		pub fn unconfirm_kernel(&self, excess: &Commitment) {
+			self.kernels.write().unwrap().remove(excess);
+		}
+
+		/// Adds an output created by a transaction that's still in the mempool
+		pub fn add_pool_output(&self, output_commit: &Commitment) {
+			self.pool.write().unwrap().push(output_commit.clone());
+		}
+
+		/// Removes an output from the UTXO set, as if it was spent by another transaction
+		pub fn spend_utxo(&self, output_commit: &Commitment) {
+			self.utxos.write().unwrap().remove(output_commit);
+		}
+
+		/// Moves an output from the mempool into the UTXO set, as if its transaction was mined
+		pub fn confirm_pool_output(&self, output_commit: &Commitment) {
+			self.pool.write().unwrap().retain(|c| c != output_commit);
+			let utxo = OutputPrintable {
+				output_type: OutputType::Transaction,
+				commit: output_commit.to_owned(),
+				spent: false,
+				proof: None,
+				proof_hash: String::from(""),
+				block_height: None,
+				merkle_proof: None,
+				mmr_index: 0,
+			};
+			self.utxos
+				.write()
+				.unwrap()
+				.insert(output_commit.clone(), utxo);
+		}
 	}
 
 	impl GrinNode for MockGrinNode {
@@ -219,7 +668,7 @@ pub mod mock {
 			&self,
 			output_commit: &Commitment,
 		) -> Result<Option<OutputPrintable>, NodeError> {
-			if let Some(utxo) = self.utxos.get(&output_commit) {
+			if let Some(utxo) = self.utxos.read().unwrap().get(&output_commit) {
 				return Ok(Some(utxo.clone()));
 			}
 
@@ -227,13 +676,173 @@ pub mod mock {
 		}
 
 		fn get_chain_height(&self) -> Result<u64, NodeError> {
-			Ok(100)
+			Ok(*self.chain_height.read().unwrap())
 		}
 
-		fn post_tx(&self, tx: &Transaction) -> Result<(), NodeError> {
+		fn post_tx(&self, tx: &Transaction, fluff: bool) -> Result<(), NodeError> {
+			let mut failures = self.post_tx_failures.write().unwrap();
+			if *self.post_tx_fails.read().unwrap() || *failures > 0 {
+				*failures = failures.saturating_sub(1);
+				return Err(NodeError::ApiCommError(grin_api::Error::Internal(
+					"post_tx failed".to_string(),
+				)));
+			}
+			let inputs = tx.inputs_committed();
+			if let Some(spent) = self
+				.double_spent
+				.read()
+				.unwrap()
+				.iter()
+				.find(|commit| inputs.contains(commit))
+			{
+				return Err(NodeError::ApiCommError(grin_api::Error::Argument(format!(
+					"Input {} is already spent",
+					spent.to_hex()
+				))));
+			}
+
 			let mut write = self.txns_posted.write().unwrap();
 			write.push(tx.clone());
+			self.fluffed.write().unwrap().push(fluff);
 			Ok(())
 		}
+
+		fn get_kernel_height(&self, excess: &Commitment) -> Result<Option<u64>, NodeError> {
+			Ok(self.kernels.read().unwrap().get(&excess).cloned())
+		}
+
+		fn is_output_in_pool(&self, output_commit: &Commitment) -> Result<bool, NodeError> {
+			Ok(self.pool.read().unwrap().contains(output_commit))
+		}
+
+		/// Blocks up to the chain height have the hash of their height
+		fn get_block_hash(&self, height: u64) -> Result<Option<Hash>, NodeError> {
+			if height > *self.chain_height.read().unwrap() {
+				return Ok(None);
+			}
+			Ok(Some(height.hash()))
+		}
+
+		fn set_endpoint(&self, _node_url: &SocketAddr, _node_api_secret: &Option<String>) {}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::mock::MockGrinNode;
+	use super::{
+		confirmations, is_spendable, select_api, GrinNode, NodeApi, NodeError, TipChange,
+		TipSubscription,
+	};
+	use crate::secp::test_util::rand_commit;
+
+	use grin_api::Version;
+	use grin_core::global::{self, ChainTypes};
+	use std::sync::Arc;
+
+	/// Tip changes are only reported when the height changes, and boundaries are only crossed
+	/// when the height moves past a multiple of the interval.
+	#[test]
+	fn tip_subscription() -> Result<(), Box<dyn std::error::Error>> {
+		let mock_node = Arc::new(MockGrinNode::new());
+		let node: Arc<dyn GrinNode> = mock_node.clone();
+		let mut subscription = TipSubscription::new(&node);
+
+		let first = subscription.poll()?.unwrap();
+		assert_eq!(
+			TipChange {
+				previous: None,
+				height: 100
+			},
+			first
+		);
+		assert!(!first.crossed_boundary(10));
+		assert!(!first.is_reorg());
+		assert!(subscription.poll()?.is_none());
+
+		mock_node.set_chain_height(105);
+		let change = subscription.poll()?.unwrap();
+		assert!(!change.crossed_boundary(10));
+		assert!(change.crossed_boundary(5));
+		assert_eq!(None, subscription.block_time_s());
+
+		mock_node.set_chain_height(112);
+		assert!(subscription.poll()?.unwrap().crossed_boundary(10));
+		// Blocks are arriving far faster than on mainnet, but the estimate is at least a second
+		assert_eq!(Some(1), subscription.block_time_s());
+
+		mock_node.set_chain_height(110);
+		let change = subscription.poll()?.unwrap();
+		assert!(change.is_reorg());
+		assert!(!change.crossed_boundary(10));
+
+		Ok(())
+	}
+
+	/// Coinbase outputs only become spendable once they've matured
+	#[test]
+	fn coinbase_maturity() -> Result<(), Box<dyn std::error::Error>> {
+		global::set_local_chain_type(ChainTypes::AutomatedTesting);
+		let maturity = global::coinbase_maturity();
+
+		let plain = rand_commit();
+		let coinbase = rand_commit();
+		let mut mock_node = MockGrinNode::new();
+		mock_node.add_default_utxo(&plain);
+		mock_node.add_coinbase_utxo(&coinbase, 50);
+		let node: Arc<dyn GrinNode> = Arc::new(mock_node);
+
+		assert!(is_spendable(&node, &plain, 1)?);
+		assert!(!is_spendable(&node, &coinbase, 50 + maturity - 1)?);
+		assert!(is_spendable(&node, &coinbase, 50 + maturity)?);
+		assert!(!is_spendable(&node, &rand_commit(), 1_000)?);
+
+		Ok(())
+	}
+
+	/// An output has one confirmation at the height of its block, and none if its block isn't known
+	#[test]
+	fn output_confirmations() -> Result<(), Box<dyn std::error::Error>> {
+		let confirmed = rand_commit();
+		let unknown = rand_commit();
+		let mut mock_node = MockGrinNode::new();
+		mock_node.add_confirmed_utxo(&confirmed, 100);
+		mock_node.add_default_utxo(&unknown);
+		let node: Arc<dyn GrinNode> = Arc::new(mock_node);
+
+		assert_eq!(0, confirmations(&node, &confirmed, 99)?);
+		assert_eq!(1, confirmations(&node, &confirmed, 100)?);
+		assert_eq!(10, confirmations(&node, &confirmed, 109)?);
+		assert_eq!(0, confirmations(&node, &unknown, 109)?);
+		assert_eq!(0, confirmations(&node, &rand_commit(), 109)?);
+
+		Ok(())
+	}
+
+	/// Nodes are talked to over the API their version serves, and unknown versions are refused
+	#[test]
+	fn node_api_versions() {
+		let version = |node_version: &str| Version {
+			node_version: node_version.to_string(),
+			block_header_version: 2,
+		};
+		assert_eq!(
+			Ok(NodeApi::V1Rest),
+			select_api(&version("2.1.1")).map_err(|e| e.to_string())
+		);
+		assert_eq!(
+			Ok(NodeApi::V2Foreign),
+			select_api(&version("3.0.0")).map_err(|e| e.to_string())
+		);
+		assert_eq!(
+			Ok(NodeApi::V2Foreign),
+			select_api(&version("5.3.2")).map_err(|e| e.to_string())
+		);
+		for unsupported in ["1.1.0", "6.0.0", "unknown"] {
+			assert!(matches!(
+				select_api(&version(unsupported)),
+				Err(NodeError::UnsupportedVersion(v)) if v == unsupported
+			));
+		}
 	}
 }
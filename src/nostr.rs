@@ -0,0 +1,312 @@
+use crate::rpc::SwapReq;
+use crate::secp::{PublicKey, Secp256k1};
+
+use grin_util::{StopState, ToHex};
+use jsonrpc_core::Value;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use std::thread::{sleep, spawn};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tracing::{debug, info, info_span, warn, Span};
+use tungstenite::Message;
+
+/// Kind of the Nostr events carrying swap submissions
+pub const SWAP_EVENT_KIND: u64 = 7_317;
+
+const ARMOR_HEADER: &str = "-----BEGIN MWIXNET SWAP-----";
+const ARMOR_FOOTER: &str = "-----END MWIXNET SWAP-----";
+
+/// Id the server subscribes to each relay with
+const SUBSCRIPTION_ID: &str = "mwixnet-swaps";
+
+/// How far back to ask relays for submissions when (re)connecting,
+/// so swaps published while the server was briefly unreachable aren't missed
+const LOOKBACK_S: u64 = 60 * 60;
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Error types for the Nostr transport
+#[derive(Error, Debug)]
+pub enum NostrError {
+	#[error("Swap submission is not armored")]
+	NotArmored,
+	#[error("Invalid armored swap submission: {0}")]
+	InvalidArmor(String),
+	#[error("Relay connection error: {0}")]
+	RelayError(String),
+}
+
+/// Submits a dearmored swap through the same admission pipeline as the JSON-RPC 'swap' API
+pub type SwapSubmitter = Arc<dyn Fn(SwapReq) -> jsonrpc_core::Result<Value> + Send + Sync>;
+
+/// The fields of a Nostr event needed to pick out swap submissions.
+/// Event signatures aren't checked, since each swap is authenticated by its own comsig.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Event {
+	pub id: String,
+	pub kind: u64,
+	pub tags: Vec<Vec<String>>,
+	pub content: String,
+}
+
+impl Event {
+	/// Whether the event is a swap submission addressed to the given 'p' tag
+	fn is_swap_for(&self, recipient: &str) -> bool {
+		self.kind == SWAP_EVENT_KIND
+			&& self
+				.tags
+				.iter()
+				.any(|t| t.len() >= 2 && t[0] == "p" && t[1] == recipient)
+	}
+}
+
+/// Encodes a swap request as text suitable for publishing in an event's content
+pub fn armor(swap: &SwapReq) -> Result<String, NostrError> {
+	let json = serde_json::to_vec(swap).map_err(|e| NostrError::InvalidArmor(e.to_string()))?;
+	Ok(format!(
+		"{}\n{}\n{}",
+		ARMOR_HEADER,
+		json.to_hex(),
+		ARMOR_FOOTER
+	))
+}
+
+/// Decodes a swap request armored by 'armor'. Whitespace within the body is ignored.
+pub fn dearmor(text: &str) -> Result<SwapReq, NostrError> {
+	let start = text.find(ARMOR_HEADER).ok_or(NostrError::NotArmored)?;
+	let body = &text[start + ARMOR_HEADER.len()..];
+	let end = body.find(ARMOR_FOOTER).ok_or(NostrError::NotArmored)?;
+	let hex: String = body[..end].split_whitespace().collect();
+
+	let json = grin_util::from_hex(&hex).map_err(|e| NostrError::InvalidArmor(e.to_string()))?;
+	serde_json::from_slice(&json).map_err(|e| NostrError::InvalidArmor(e.to_string()))
+}
+
+/// The 'p' tag value that submissions to the server must carry: its x-only public key, hex-encoded
+pub fn recipient_tag(pubkey: &PublicKey) -> String {
+	let secp = Secp256k1::new();
+	pubkey.serialize_vec(&secp, true)[1..].to_hex()
+}
+
+/// The 'REQ' message subscribing to swap submissions addressed to 'recipient' since 'since'
+fn subscription(recipient: &str, since: u64) -> String {
+	json!([
+		"REQ",
+		SUBSCRIPTION_ID,
+		{ "kinds": [SWAP_EVENT_KIND], "#p": [recipient], "since": since }
+	])
+	.to_string()
+}
+
+/// Extracts the event from a relay's 'EVENT' message, ignoring any other message types
+fn parse_relay_message(text: &str) -> Option<Event> {
+	let message: Vec<Value> = serde_json::from_str(text).ok()?;
+	match message.as_slice() {
+		[kind, sub_id, event] if kind == "EVENT" && sub_id == SUBSCRIPTION_ID => {
+			serde_json::from_value(event.clone()).ok()
+		}
+		_ => None,
+	}
+}
+
+/// Handles a single relay message, submitting any swap it carries
+fn handle_message(text: &str, recipient: &str, submit: &SwapSubmitter) {
+	let event = match parse_relay_message(text) {
+		Some(event) if event.is_swap_for(recipient) => event,
+		_ => return,
+	};
+
+	match dearmor(&event.content) {
+		Ok(swap) => match submit(swap) {
+			Ok(_) => info!("Accepted swap from event {}", event.id),
+			// The same event is often delivered by several relays
+			Err(e) => debug!("Swap from event {} rejected: {}", event.id, e.message),
+		},
+		Err(e) => debug!("Ignoring event {}: {}", event.id, e),
+	}
+}
+
+/// Connects to the relay and feeds submissions to 'submit' until the connection drops
+fn subscribe(
+	relay_url: &str,
+	recipient: &str,
+	submit: &SwapSubmitter,
+	stop_state: &StopState,
+) -> Result<(), NostrError> {
+	let (mut socket, _) =
+		tungstenite::connect(relay_url).map_err(|e| NostrError::RelayError(e.to_string()))?;
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0);
+	socket
+		.write_message(Message::Text(subscription(
+			recipient,
+			now.saturating_sub(LOOKBACK_S),
+		)))
+		.map_err(|e| NostrError::RelayError(e.to_string()))?;
+	info!("Subscribed to swap submissions");
+
+	while !stop_state.is_stopped() {
+		let message = socket
+			.read_message()
+			.map_err(|e| NostrError::RelayError(e.to_string()))?;
+		match message {
+			Message::Text(text) => handle_message(&text, recipient, submit),
+			Message::Close(_) => return Err(NostrError::RelayError("closed by relay".into())),
+			_ => {}
+		}
+	}
+	Ok(())
+}
+
+/// Listens for swap submissions addressed to 'pubkey' on each of the relays, in the background.
+/// Connections are retried with increasing delays for as long as the server runs.
+pub fn start(
+	relay_urls: &[String],
+	pubkey: &PublicKey,
+	submit: SwapSubmitter,
+	stop_state: Arc<StopState>,
+) {
+	let recipient = recipient_tag(pubkey);
+	for relay_url in relay_urls {
+		let relay_url = relay_url.clone();
+		let recipient = recipient.clone();
+		let submit = submit.clone();
+		let stop_state = stop_state.clone();
+		let span = info_span!(parent: Span::current(), "relay", url = relay_url.as_str());
+		spawn(move || {
+			let _relay = span.entered();
+			let mut delay = MIN_RECONNECT_DELAY;
+			while !stop_state.is_stopped() {
+				match subscribe(&relay_url, &recipient, &submit, &stop_state) {
+					Ok(()) => break,
+					Err(e) => warn!("{}. Reconnecting in {}s.", e, delay.as_secs()),
+				}
+				sleep(delay);
+				delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+			}
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		armor, dearmor, handle_message, parse_relay_message, recipient_tag, NostrError,
+		SwapSubmitter, SUBSCRIPTION_ID, SWAP_EVENT_KIND,
+	};
+	use crate::onion::test_util::rand_onion;
+	use crate::rpc::SwapReq;
+	use crate::secp::{self, ComSignature, PublicKey, Secp256k1, SigDomain};
+
+	use jsonrpc_core::Value;
+	use serde_json::json;
+	use std::sync::{Arc, Mutex};
+
+	fn rand_swap_req() -> SwapReq {
+		let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &secp::random_secret()).unwrap();
+		let onion = rand_onion();
+		let comsig = ComSignature::sign(
+			100,
+			&secp::random_secret(),
+			&SigDomain::new(&pubkey, 50),
+			&onion.serialize().unwrap(),
+		)
+		.unwrap();
+		SwapReq {
+			onion,
+			comsig,
+			expiry_height: 50,
+		}
+	}
+
+	#[test]
+	fn armor_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+		let swap = rand_swap_req();
+		let armored = armor(&swap)?;
+		assert_eq!(
+			serde_json::to_value(&swap)?,
+			serde_json::to_value(&dearmor(&armored)?)?
+		);
+
+		// Wrapped lines and surrounding text are tolerated
+		let (header, rest) = armored.split_once('\n').unwrap();
+		let (body, footer) = rest.rsplit_once('\n').unwrap();
+		let (first, second) = body.split_at(body.len() / 2);
+		let wrapped = format!("swap:\n{}\n{}\n{}\n{}\n", header, first, second, footer);
+		assert_eq!(
+			serde_json::to_value(&swap)?,
+			serde_json::to_value(&dearmor(&wrapped)?)?
+		);
+
+		assert!(matches!(dearmor(body), Err(NostrError::NotArmored)));
+		assert!(matches!(
+			dearmor(&format!("{}\nzz\n{}", header, footer)),
+			Err(NostrError::InvalidArmor(_))
+		));
+
+		Ok(())
+	}
+
+	/// Only swap events addressed to the server are submitted
+	#[test]
+	fn relay_events() -> Result<(), Box<dyn std::error::Error>> {
+		let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &secp::random_secret()).unwrap();
+		let recipient = recipient_tag(&pubkey);
+		assert_eq!(64, recipient.len());
+
+		let submitted = Arc::new(Mutex::new(Vec::new()));
+		let submitted_clone = submitted.clone();
+		let submit: SwapSubmitter = Arc::new(move |swap: SwapReq| {
+			submitted_clone.lock().unwrap().push(swap.expiry_height);
+			Ok(Value::String("success".into()))
+		});
+
+		let message = |kind: u64, recipient: &str, content: &str| {
+			json!([
+				"EVENT",
+				SUBSCRIPTION_ID,
+				{
+					"id": "abcd",
+					"pubkey": "ef01",
+					"created_at": 1_700_000_000,
+					"kind": kind,
+					"tags": [["p", recipient]],
+					"content": content,
+					"sig": "2345"
+				}
+			])
+			.to_string()
+		};
+		let armored = armor(&rand_swap_req())?;
+		assert!(parse_relay_message(&message(SWAP_EVENT_KIND, &recipient, &armored)).is_some());
+		assert!(parse_relay_message(&json!(["EOSE", SUBSCRIPTION_ID]).to_string()).is_none());
+		assert!(parse_relay_message("not json").is_none());
+
+		handle_message(&message(1, &recipient, &armored), &recipient, &submit);
+		handle_message(
+			&message(SWAP_EVENT_KIND, "00", &armored),
+			&recipient,
+			&submit,
+		);
+		handle_message(
+			&message(SWAP_EVENT_KIND, &recipient, "hi"),
+			&recipient,
+			&submit,
+		);
+		assert!(submitted.lock().unwrap().is_empty());
+
+		handle_message(
+			&message(SWAP_EVENT_KIND, &recipient, &armored),
+			&recipient,
+			&submit,
+		);
+		assert_eq!(vec![50], *submitted.lock().unwrap());
+
+		Ok(())
+	}
+}
@@ -0,0 +1,148 @@
+use grin_util::ZeroingString;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Environment variable the server password is read from, when no password file is given
+pub const SERVER_PASSWORD_ENV: &str = "MWIXNET_PASSWORD";
+
+/// Environment variable the wallet password is read from, when no password file is given
+pub const WALLET_PASSWORD_ENV: &str = "MWIXNET_WALLET_PASSWORD";
+
+/// Error types for reading passwords non-interactively
+#[derive(Error, Debug)]
+pub enum PasswordError {
+	#[error("Password file {path:?} must only be accessible by its owner (mode is {mode:o}, expected 600 or 400)")]
+	InsecurePermissions { path: PathBuf, mode: u32 },
+	#[error("Password file {0:?} is not a regular file")]
+	NotAFile(PathBuf),
+	#[error("Error reading password file {0:?}: {1}")]
+	ReadError(PathBuf, std::io::Error),
+	#[error("Password in {0} is not valid unicode")]
+	InvalidPassword(String),
+}
+
+/// Checks that only the file's owner can read or write it
+#[cfg(unix)]
+fn check_permissions(path: &Path, metadata: &std::fs::Metadata) -> Result<(), PasswordError> {
+	use std::os::unix::fs::PermissionsExt;
+
+	let mode = metadata.permissions().mode() & 0o777;
+	if mode & 0o077 != 0 {
+		return Err(PasswordError::InsecurePermissions {
+			path: path.to_path_buf(),
+			mode,
+		});
+	}
+	Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path, _metadata: &std::fs::Metadata) -> Result<(), PasswordError> {
+	Ok(())
+}
+
+/// Reads a password from the first line of the file at 'path', which must not be accessible
+/// to anyone but its owner. The trailing newline, if any, isn't part of the password.
+pub fn read_password_file(path: &Path) -> Result<ZeroingString, PasswordError> {
+	let metadata =
+		std::fs::metadata(path).map_err(|e| PasswordError::ReadError(path.to_path_buf(), e))?;
+	if !metadata.is_file() {
+		return Err(PasswordError::NotAFile(path.to_path_buf()));
+	}
+	check_permissions(path, &metadata)?;
+
+	let contents = ZeroingString::from(
+		std::fs::read_to_string(path)
+			.map_err(|e| PasswordError::ReadError(path.to_path_buf(), e))?,
+	);
+	let password = contents.lines().next().unwrap_or("");
+	Ok(ZeroingString::from(password))
+}
+
+/// Reads a password from the environment variable 'name', if set
+pub fn read_password_env(name: &str) -> Result<Option<ZeroingString>, PasswordError> {
+	match std::env::var(name) {
+		Ok(password) => Ok(Some(ZeroingString::from(password))),
+		Err(std::env::VarError::NotPresent) => Ok(None),
+		Err(std::env::VarError::NotUnicode(_)) => {
+			Err(PasswordError::InvalidPassword(name.to_string()))
+		}
+	}
+}
+
+/// The password from 'file' if one is given, or else from the environment variable 'env_var'.
+/// Returns None when neither is available, in which case the password should be prompted for.
+pub fn non_interactive_password(
+	file: Option<&str>,
+	env_var: &str,
+) -> Result<Option<ZeroingString>, PasswordError> {
+	match file {
+		Some(file) => read_password_file(Path::new(file)).map(Some),
+		None => read_password_env(env_var),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{non_interactive_password, read_password_env, read_password_file, PasswordError};
+
+	use std::path::PathBuf;
+
+	#[cfg(unix)]
+	fn set_mode(path: &PathBuf, mode: u32) {
+		use std::os::unix::fs::PermissionsExt;
+		std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap();
+	}
+
+	#[test]
+	fn password_file() -> Result<(), Box<dyn std::error::Error>> {
+		let dir = PathBuf::from("./target/tmp/.password_file");
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir)?;
+		let path = dir.join("password");
+
+		std::fs::write(&path, "correct horse\n")?;
+		#[cfg(unix)]
+		set_mode(&path, 0o600);
+		assert_eq!("correct horse", &*read_password_file(&path)?);
+
+		#[cfg(unix)]
+		{
+			set_mode(&path, 0o644);
+			assert!(matches!(
+				read_password_file(&path),
+				Err(PasswordError::InsecurePermissions { mode: 0o644, .. })
+			));
+			set_mode(&path, 0o400);
+			assert!(read_password_file(&path).is_ok());
+		}
+
+		assert!(matches!(
+			read_password_file(&dir),
+			Err(PasswordError::NotAFile(_))
+		));
+		assert!(matches!(
+			read_password_file(&dir.join("missing")),
+			Err(PasswordError::ReadError(..))
+		));
+
+		Ok(())
+	}
+
+	/// A password file takes precedence over the environment
+	#[test]
+	fn password_env() -> Result<(), Box<dyn std::error::Error>> {
+		let name = "MWIXNET_TEST_PASSWORD";
+		std::env::set_var(name, "from env");
+		assert!(matches!(
+			non_interactive_password(Some("./target/tmp/.password_env/missing"), name),
+			Err(PasswordError::ReadError(..))
+		));
+		assert_eq!("from env", &*non_interactive_password(None, name)?.unwrap());
+
+		std::env::remove_var(name);
+		assert!(read_password_env(name)?.is_none());
+
+		Ok(())
+	}
+}
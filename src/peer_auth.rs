@@ -0,0 +1,268 @@
+//! Authentication of requests between mix nodes.
+//!
+//! The sender signs each request with its mixnode key, the same key its onion layers are
+//! encrypted to, and sends the signature in the request's headers:
+//!
+//! * 'X-Mwixnet-Peer': the sender's hex-encoded public key
+//! * 'X-Mwixnet-Timestamp': unix time (in seconds) the request was signed
+//! * 'X-Mwixnet-Signature': hex-encoded compact signature of
+//!   Blake2b("MWIXNET_PEER_AUTH" || path || timestamp || body)
+//!
+//! The receiver only accepts requests from the configured peer keys, signed within
+//! MAX_CLOCK_SKEW_S of its own clock, and never accepts the same signature twice.
+
+use crate::secp::{self, Message, PublicKey, Secp256k1, SecretKey, Signature};
+
+use blake2::blake2b::Blake2b;
+use byteorder::{BigEndian, ByteOrder};
+use grin_util::ToHex;
+use hyper::HeaderMap;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use thiserror::Error;
+
+/// Path of the API mix nodes call on each other
+pub const PEER_PATH: &str = "/v1/peer";
+
+pub const PEER_HEADER: &str = "X-Mwixnet-Peer";
+pub const TIMESTAMP_HEADER: &str = "X-Mwixnet-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Mwixnet-Signature";
+
+/// How far a request's timestamp may be from the receiver's clock, in either direction
+pub const MAX_CLOCK_SKEW_S: u64 = 60;
+
+/// Error types for authenticating a peer's request
+#[derive(Error, Debug, PartialEq)]
+pub enum PeerAuthError {
+	#[error("Missing {0} header")]
+	MissingHeader(&'static str),
+	#[error("Invalid {0} header")]
+	InvalidHeader(&'static str),
+	#[error("Invalid peer key {0}")]
+	InvalidPeerKey(String),
+	#[error("Unknown peer {0}")]
+	UnknownPeer(String),
+	#[error("Request signed at {timestamp} is too far from the current time {now}")]
+	StaleRequest { timestamp: u64, now: u64 },
+	#[error("Invalid request signature")]
+	InvalidSignature,
+	#[error("Request was already received")]
+	Replayed,
+	#[error("Secp error: {0}")]
+	SecpError(String),
+}
+
+impl PeerAuthError {
+	/// Whether the request was signed by a key that isn't one of the configured peers,
+	/// rather than not being properly signed at all
+	pub fn is_forbidden(&self) -> bool {
+		matches!(self, PeerAuthError::UnknownPeer(_))
+	}
+}
+
+/// The message signed for a request to 'path' with 'body', at unix time 'timestamp'
+fn message(path: &str, timestamp: u64, body: &[u8]) -> Result<Message, PeerAuthError> {
+	let mut hasher = Blake2b::new(32);
+	hasher.update(b"MWIXNET_PEER_AUTH");
+	let mut int_bytes = [0; 8];
+	BigEndian::write_u64(&mut int_bytes, path.len() as u64);
+	hasher.update(&int_bytes);
+	hasher.update(path.as_bytes());
+	BigEndian::write_u64(&mut int_bytes, timestamp);
+	hasher.update(&int_bytes);
+	hasher.update(body);
+
+	Message::from_slice(hasher.finalize().as_bytes())
+		.map_err(|e| PeerAuthError::SecpError(e.to_string()))
+}
+
+/// Signs a request to 'path' with 'body' at unix time 'now', returning the headers to send it with
+pub fn sign_request(
+	key: &SecretKey,
+	path: &str,
+	body: &[u8],
+	now: u64,
+) -> Result<Vec<(&'static str, String)>, PeerAuthError> {
+	let secp = Secp256k1::new();
+	let pubkey = PublicKey::from_secret_key(&secp, key)
+		.map_err(|e| PeerAuthError::SecpError(e.to_string()))?;
+	let signature = secp::sign(key, &message(path, now, body)?)
+		.map_err(|e| PeerAuthError::SecpError(e.to_string()))?;
+	Ok(vec![
+		(PEER_HEADER, pubkey.serialize_vec(&secp, true).to_hex()),
+		(TIMESTAMP_HEADER, now.to_string()),
+		(
+			SIGNATURE_HEADER,
+			signature.serialize_compact(&secp).to_hex(),
+		),
+	])
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &'static str) -> Result<&'a str, PeerAuthError> {
+	headers
+		.get(name)
+		.ok_or(PeerAuthError::MissingHeader(name))?
+		.to_str()
+		.map_err(|_| PeerAuthError::InvalidHeader(name))
+}
+
+/// Checks that requests were signed by one of the configured peers
+pub struct PeerAuthenticator {
+	peers: Vec<PublicKey>,
+	/// signatures of accepted requests, with the timestamps they were signed at,
+	/// kept until they're too old to be accepted anyway
+	seen: Mutex<HashMap<Vec<u8>, u64>>,
+}
+
+impl PeerAuthenticator {
+	pub fn new(peers: Vec<PublicKey>) -> PeerAuthenticator {
+		PeerAuthenticator {
+			peers,
+			seen: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Also accepts requests signed by 'pubkey'
+	pub fn trust(&mut self, pubkey: &PublicKey) {
+		if !self.peers.contains(pubkey) {
+			self.peers.push(pubkey.clone());
+		}
+	}
+
+	/// Parses the hex-encoded public keys of the peers
+	pub fn from_hex(peers: &[String]) -> Result<PeerAuthenticator, PeerAuthError> {
+		let secp = Secp256k1::new();
+		let peers = peers
+			.iter()
+			.map(|hex| {
+				grin_util::from_hex(hex)
+					.ok()
+					.and_then(|bytes| PublicKey::from_slice(&secp, &bytes).ok())
+					.ok_or_else(|| PeerAuthError::InvalidPeerKey(hex.clone()))
+			})
+			.collect::<Result<Vec<PublicKey>, PeerAuthError>>()?;
+		Ok(PeerAuthenticator::new(peers))
+	}
+
+	/// Verifies the signature in 'headers' of the request to 'path' with 'body',
+	/// as of unix time 'now'. Returns the key of the peer that sent it.
+	pub fn verify(
+		&self,
+		headers: &HeaderMap,
+		path: &str,
+		body: &[u8],
+		now: u64,
+	) -> Result<PublicKey, PeerAuthError> {
+		let secp = Secp256k1::new();
+		let peer_hex = header(headers, PEER_HEADER)?;
+		let pubkey = grin_util::from_hex(peer_hex)
+			.ok()
+			.and_then(|bytes| PublicKey::from_slice(&secp, &bytes).ok())
+			.ok_or(PeerAuthError::InvalidHeader(PEER_HEADER))?;
+		if !self.peers.contains(&pubkey) {
+			return Err(PeerAuthError::UnknownPeer(peer_hex.to_string()));
+		}
+
+		let timestamp: u64 = header(headers, TIMESTAMP_HEADER)?
+			.parse()
+			.map_err(|_| PeerAuthError::InvalidHeader(TIMESTAMP_HEADER))?;
+		if timestamp.abs_diff(now) > MAX_CLOCK_SKEW_S {
+			return Err(PeerAuthError::StaleRequest { timestamp, now });
+		}
+
+		let sig_bytes = grin_util::from_hex(header(headers, SIGNATURE_HEADER)?)
+			.map_err(|_| PeerAuthError::InvalidHeader(SIGNATURE_HEADER))?;
+		let signature = Signature::from_compact(&secp, &sig_bytes)
+			.map_err(|_| PeerAuthError::InvalidHeader(SIGNATURE_HEADER))?;
+		if !secp::verify(&pubkey, &signature, &message(path, timestamp, body)?) {
+			return Err(PeerAuthError::InvalidSignature);
+		}
+
+		let mut seen = self.seen.lock().unwrap();
+		seen.retain(|_, signed_at| signed_at.abs_diff(now) <= MAX_CLOCK_SKEW_S);
+		if seen.insert(sig_bytes, timestamp).is_some() {
+			return Err(PeerAuthError::Replayed);
+		}
+		Ok(pubkey)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{sign_request, PeerAuthError, PeerAuthenticator, MAX_CLOCK_SKEW_S};
+	use crate::secp::{self, PublicKey, Secp256k1, SecretKey};
+
+	use hyper::header::{HeaderName, HeaderValue};
+	use hyper::HeaderMap;
+
+	fn to_headers(signed: Vec<(&'static str, String)>) -> HeaderMap {
+		let mut headers = HeaderMap::new();
+		for (name, value) in signed {
+			headers.insert(
+				HeaderName::from_bytes(name.as_bytes()).unwrap(),
+				HeaderValue::from_str(&value).unwrap(),
+			);
+		}
+		headers
+	}
+
+	fn peer() -> (SecretKey, PublicKey) {
+		let key = secp::random_secret();
+		let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &key).unwrap();
+		(key, pubkey)
+	}
+
+	/// Requests signed by a configured peer are accepted once, and only for the path and body signed
+	#[test]
+	fn authenticate_peer() -> Result<(), Box<dyn std::error::Error>> {
+		let (key, pubkey) = peer();
+		let auth = PeerAuthenticator::new(vec![pubkey]);
+		let now = 1_700_000_000;
+
+		let headers = to_headers(sign_request(&key, "/v1/peer", b"{}", now)?);
+		assert_eq!(pubkey, auth.verify(&headers, "/v1/peer", b"{}", now + 5)?);
+		assert_eq!(
+			Err(PeerAuthError::Replayed),
+			auth.verify(&headers, "/v1/peer", b"{}", now + 5)
+		);
+
+		let headers = to_headers(sign_request(&key, "/v1/peer", b"{}", now + 1)?);
+		assert_eq!(
+			Err(PeerAuthError::InvalidSignature),
+			auth.verify(&headers, "/v1/peer", b"{\"a\":1}", now)
+		);
+		assert_eq!(
+			Err(PeerAuthError::InvalidSignature),
+			auth.verify(&headers, "/v1", b"{}", now)
+		);
+		assert_eq!(
+			Err(PeerAuthError::MissingHeader(super::PEER_HEADER)),
+			auth.verify(&HeaderMap::new(), "/v1/peer", b"{}", now)
+		);
+		Ok(())
+	}
+
+	/// Requests from unknown keys, or signed too long ago, are rejected
+	#[test]
+	fn reject_unknown_and_stale() -> Result<(), Box<dyn std::error::Error>> {
+		let (key, pubkey) = peer();
+		let (other_key, _) = peer();
+		let auth = PeerAuthenticator::new(vec![pubkey]);
+		let now = 1_700_000_000;
+
+		let headers = to_headers(sign_request(&other_key, "/v1/peer", b"{}", now)?);
+		let err = auth.verify(&headers, "/v1/peer", b"{}", now).unwrap_err();
+		assert!(err.is_forbidden());
+
+		let signed_at = now - MAX_CLOCK_SKEW_S - 1;
+		let headers = to_headers(sign_request(&key, "/v1/peer", b"{}", signed_at)?);
+		assert_eq!(
+			Err(PeerAuthError::StaleRequest {
+				timestamp: signed_at,
+				now
+			}),
+			auth.verify(&headers, "/v1/peer", b"{}", now)
+		);
+		Ok(())
+	}
+}
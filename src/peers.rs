@@ -0,0 +1,212 @@
+use crate::directory::Mixnode;
+use crate::http::HttpClient;
+
+use grin_util::StopState;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info};
+
+/// Seconds between pings of each peer
+pub const PEER_PING_INTERVAL_S: u64 = 60;
+
+/// Number of recent pings a peer's availability is calculated over
+const PING_HISTORY: usize = 10;
+
+/// A peer mix node's health, as seen by this server's pings
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct PeerStatus {
+	/// URL of the peer's API
+	pub url: String,
+	/// whether the peer answered the most recent ping
+	pub alive: bool,
+	/// fraction of recent pings the peer answered
+	pub availability: f64,
+	/// unix time (in seconds) the peer last answered a ping
+	pub last_seen: Option<u64>,
+}
+
+#[derive(Default)]
+struct PeerRecord {
+	/// results of the most recent pings, oldest first
+	pings: VecDeque<bool>,
+	last_seen: Option<u64>,
+}
+
+/// Tracks the availability of the configured peers
+#[derive(Default)]
+pub struct PeerHealth {
+	records: Mutex<HashMap<String, PeerRecord>>,
+}
+
+impl PeerHealth {
+	pub fn new() -> PeerHealth {
+		PeerHealth::default()
+	}
+
+	/// Records the result of pinging the peer at 'url' at unix time 'now'
+	pub fn record(&self, url: &str, ok: bool, now: u64) {
+		let mut records = self.records.lock().unwrap();
+		let record = records.entry(url.to_string()).or_default();
+		if record.pings.len() == PING_HISTORY {
+			record.pings.pop_front();
+		}
+		record.pings.push_back(ok);
+		if ok {
+			record.last_seen = Some(now);
+		}
+	}
+
+	/// The status of every peer that has been pinged, ordered by URL
+	pub fn statuses(&self) -> Vec<PeerStatus> {
+		let records = self.records.lock().unwrap();
+		let mut statuses: Vec<PeerStatus> = records
+			.iter()
+			.map(|(url, record)| PeerStatus {
+				url: url.clone(),
+				alive: record.pings.back().copied().unwrap_or(false),
+				availability: match record.pings.len() {
+					0 => 0.0,
+					n => record.pings.iter().filter(|ok| **ok).count() as f64 / n as f64,
+				},
+				last_seen: record.last_seen,
+			})
+			.collect();
+		statuses.sort_by(|a, b| a.url.cmp(&b.url));
+		statuses
+	}
+
+	/// Pings each of 'peers' at its '/health' endpoint, and forgets peers no longer configured
+	pub fn ping_all(&self, client: &HttpClient, peers: &[String], now: u64) {
+		self.records
+			.lock()
+			.unwrap()
+			.retain(|url, _| peers.contains(url));
+		for url in peers {
+			let health_url = format!("{}/health", url.trim_end_matches('/'));
+			let ok = match client.get::<Value>(&health_url, None) {
+				Ok(_) => true,
+				Err(e) => {
+					debug!("Peer {} failed health check: {}", url, e);
+					false
+				}
+			};
+			self.record(url, ok, now);
+		}
+	}
+}
+
+/// Pings the peers returned by 'peers' every PEER_PING_INTERVAL_S seconds, until stopped.
+/// 'peers' is called before each round of pings, so changes to the config are picked up.
+pub fn start<F>(health: Arc<PeerHealth>, peers: F, client: HttpClient, stop_state: Arc<StopState>)
+where
+	F: Fn() -> Vec<String> + Send + 'static,
+{
+	spawn(move || {
+		let mut secs_until_ping = 0;
+		while !stop_state.is_stopped() {
+			if secs_until_ping == 0 {
+				let peers = peers();
+				let now = SystemTime::now()
+					.duration_since(UNIX_EPOCH)
+					.map(|d| d.as_secs())
+					.unwrap_or(0);
+				health.ping_all(&client, &peers, now);
+				secs_until_ping = PEER_PING_INTERVAL_S;
+			}
+			sleep(Duration::from_secs(1));
+			secs_until_ping -= 1;
+		}
+	});
+}
+
+/// The URLs of the mix nodes a route shouldn't use.
+/// Nodes are asked for their peers' health (via 'get_info') in random order until one answers.
+/// Nodes that didn't answer, and peers the answering node last saw down, are unavailable.
+pub fn unavailable_mixnodes(client: &HttpClient, mixnodes: &[Mixnode]) -> HashSet<String> {
+	let mut unavailable = HashSet::new();
+	let mut order: Vec<&Mixnode> = mixnodes.iter().collect();
+	order.shuffle(&mut rand::thread_rng());
+	for mixnode in order {
+		match fetch_peer_statuses(client, &mixnode.url) {
+			Ok(statuses) => {
+				unavailable.extend(statuses.into_iter().filter(|s| !s.alive).map(|s| s.url));
+				break;
+			}
+			Err(e) => {
+				info!("Mix node {} is unavailable: {}", mixnode.url, e);
+				unavailable.insert(mixnode.url.clone());
+			}
+		}
+	}
+	unavailable
+}
+
+/// Fetches the health of the mix node's peers, as published by its 'get_info' API
+fn fetch_peer_statuses(client: &HttpClient, url: &str) -> Result<Vec<PeerStatus>, String> {
+	let req = json!({
+		"jsonrpc": "2.0",
+		"method": "get_info",
+		"params": [],
+		"id": 1,
+	});
+	let res = client
+		.post::<Value, Value>(&format!("{}/v1", url.trim_end_matches('/')), None, &req)
+		.map_err(|e| e.to_string())?;
+	let result = res
+		.get("result")
+		.ok_or_else(|| format!("get_info failed: {}", res))?;
+	match result.get("peers") {
+		Some(peers) => serde_json::from_value(peers.clone()).map_err(|e| e.to_string()),
+		None => Ok(vec![]),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{PeerHealth, PING_HISTORY};
+	use crate::http::HttpClient;
+
+	/// Availability covers the most recent pings, and 'alive' reflects the last one
+	#[test]
+	fn peer_availability() {
+		let health = PeerHealth::new();
+		assert!(health.statuses().is_empty());
+
+		let peer = "http://mix1.example.com:3000";
+		health.record(peer, true, 100);
+		health.record(peer, false, 160);
+		let status = &health.statuses()[0];
+		assert!(!status.alive);
+		assert_eq!(0.5, status.availability);
+		assert_eq!(Some(100), status.last_seen);
+
+		for i in 0..PING_HISTORY as u64 {
+			health.record(peer, true, 200 + i);
+		}
+		let status = &health.statuses()[0];
+		assert!(status.alive);
+		assert_eq!(1.0, status.availability);
+		assert_eq!(Some(200 + PING_HISTORY as u64 - 1), status.last_seen);
+	}
+
+	/// Unreachable peers are marked down, and peers removed from the config are forgotten
+	#[test]
+	fn ping_unreachable_peer() {
+		let health = PeerHealth::new();
+		health.record("http://removed.example.com:3000", true, 100);
+
+		let peers = vec!["http://127.0.0.1:1".to_string()];
+		health.ping_all(&HttpClient::new(None), &peers, 200);
+		let statuses = health.statuses();
+		assert_eq!(1, statuses.len());
+		assert_eq!(peers[0], statuses[0].url);
+		assert!(!statuses[0].alive);
+		assert_eq!(0.0, statuses[0].availability);
+		assert_eq!(None, statuses[0].last_seen);
+	}
+}
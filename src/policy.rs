@@ -0,0 +1,182 @@
+//! Operator policies on which swaps are accepted, beyond the protocol's own checks.
+//!
+//! A policy is consulted for every swap once its onion is peeled, with both the input being
+//! spent and the output it's swapped into, and may refuse it with a reason the client is told.
+//! The server ships with a deny-list policy, read from 'deny_list_path'. Other policies (e.g. one
+//! querying an external screening service) implement 'SwapPolicy' and are plugged in with
+//! 'ServerImpl::with_policy'.
+
+use crate::secp::{self, Commitment};
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::SystemTime;
+use thiserror::Error;
+use tracing::{info, warn};
+
+/// Decides whether a swap may be accepted
+pub trait SwapPolicy: Send + Sync {
+	/// Whether the swap of 'input' into 'output' may be accepted. A rejection names the
+	/// commitment objected to, and the reason the client is given.
+	fn check(&self, input: &Commitment, output: &Commitment) -> Result<(), PolicyRejection>;
+}
+
+/// Why a policy refused a swap
+#[derive(Clone, Debug, PartialEq)]
+pub struct PolicyRejection {
+	/// the commitment the policy objects to
+	pub commit: Commitment,
+	pub reason: String,
+}
+
+/// Error types for loading a policy
+#[derive(Error, Debug)]
+pub enum PolicyError {
+	#[error("Can't read deny list {path}: {error}")]
+	ReadError { path: String, error: std::io::Error },
+	#[error("Invalid commitment on line {line} of deny list {path}")]
+	InvalidCommitment { path: String, line: usize },
+}
+
+/// The commitments listed in a file, one hex-encoded commitment per line. Blank lines and lines
+/// starting with '#' are ignored. The file is read again whenever it's modified, so entries can
+/// be added without restarting the server. If an edit leaves the file unreadable, the entries
+/// read before it are kept.
+pub struct DenyList {
+	path: PathBuf,
+	state: RwLock<DenyListState>,
+}
+
+struct DenyListState {
+	denied: HashSet<Commitment>,
+	/// modification time and size of the file when it was last read
+	stamp: Option<(SystemTime, u64)>,
+}
+
+impl DenyList {
+	/// Reads the deny list at 'path', failing if it can't be read or holds invalid entries
+	pub fn open(path: &Path) -> Result<DenyList, PolicyError> {
+		let stamp = file_stamp(path);
+		let denied = read_deny_list(path)?;
+		info!(
+			"Loaded {} commitments from deny list {}",
+			denied.len(),
+			path.display()
+		);
+		Ok(DenyList {
+			path: path.to_path_buf(),
+			state: RwLock::new(DenyListState { denied, stamp }),
+		})
+	}
+
+	/// Number of commitments on the list
+	pub fn len(&self) -> usize {
+		self.state.read().unwrap().denied.len()
+	}
+
+	/// Reads the file again if it's been modified since it was last read
+	fn refresh(&self) {
+		let stamp = file_stamp(&self.path);
+		if stamp == self.state.read().unwrap().stamp {
+			return;
+		}
+
+		let mut state = self.state.write().unwrap();
+		state.stamp = stamp;
+		match read_deny_list(&self.path) {
+			Ok(denied) => {
+				info!(
+					"Reloaded {} commitments from deny list {}",
+					denied.len(),
+					self.path.display()
+				);
+				state.denied = denied;
+			}
+			Err(e) => warn!("Keeping the previous deny list: {}", e),
+		}
+	}
+}
+
+impl SwapPolicy for DenyList {
+	fn check(&self, input: &Commitment, output: &Commitment) -> Result<(), PolicyRejection> {
+		self.refresh();
+		let state = self.state.read().unwrap();
+		for commit in [input, output] {
+			if state.denied.contains(commit) {
+				return Err(PolicyRejection {
+					commit: commit.clone(),
+					reason: "commitment is on the server's deny list".to_string(),
+				});
+			}
+		}
+		Ok(())
+	}
+}
+
+/// The file's modification time and size. File times can be coarse, so the size is compared too.
+fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+	let metadata = std::fs::metadata(path).ok()?;
+	Some((metadata.modified().ok()?, metadata.len()))
+}
+
+fn read_deny_list(path: &Path) -> Result<HashSet<Commitment>, PolicyError> {
+	let contents = std::fs::read_to_string(path).map_err(|error| PolicyError::ReadError {
+		path: path.display().to_string(),
+		error,
+	})?;
+
+	let mut denied = HashSet::new();
+	for (i, line) in contents.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+		let invalid = || PolicyError::InvalidCommitment {
+			path: path.display().to_string(),
+			line: i + 1,
+		};
+		denied.insert(secp::commitment_from_hex(line).ok_or_else(invalid)?);
+	}
+	Ok(denied)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{DenyList, PolicyError, SwapPolicy};
+	use crate::secp::test_util::rand_commit;
+
+	use grin_util::ToHex;
+	use std::path::PathBuf;
+
+	/// Listed commitments are refused as inputs or outputs, and edits to the file are picked up
+	#[test]
+	fn deny_list() -> Result<(), Box<dyn std::error::Error>> {
+		let dir = PathBuf::from("./target/tmp/.deny_list");
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir)?;
+		let path = dir.join("deny-list.txt");
+
+		let (denied, other, added) = (rand_commit(), rand_commit(), rand_commit());
+		std::fs::write(&path, format!("# sanctioned\n\n{}\n", denied.to_hex()))?;
+		let policy = DenyList::open(&path)?;
+		assert_eq!(1, policy.len());
+		assert!(policy.check(&other, &other).is_ok());
+		assert_eq!(denied, policy.check(&denied, &other).unwrap_err().commit);
+		assert_eq!(denied, policy.check(&other, &denied).unwrap_err().commit);
+
+		std::fs::write(&path, format!("{}\n{}\n", denied.to_hex(), added.to_hex()))?;
+		assert!(policy.check(&added, &other).is_err());
+		assert_eq!(2, policy.len());
+
+		// A bad edit keeps the entries already loaded
+		std::fs::write(&path, "not a commitment\n")?;
+		assert!(policy.check(&added, &other).is_err());
+		assert!(matches!(
+			DenyList::open(&path),
+			Err(PolicyError::InvalidCommitment { line: 1, .. })
+		));
+
+		Ok(())
+	}
+}
@@ -0,0 +1,157 @@
+use crate::config::{self, ConfigChanges, ConfigError, ServerConfig};
+use crate::node::GrinNode;
+use crate::server::Server;
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tracing::{error, info, warn};
+
+/// Where a running server instance's config is reloaded from
+#[derive(Clone, Debug)]
+pub struct ConfigSource {
+	pub config_path: PathBuf,
+	/// name of the config file's profile the server was started with, if any
+	pub profile: Option<String>,
+	/// name of the instance's section in the config file, or None for the main server
+	pub instance: Option<String>,
+	/// number of reloads requested by SIGHUP, shared by all instances in the process
+	pub reload_requests: Arc<AtomicUsize>,
+}
+
+/// Applies the settings re-read from the config file to a running server instance
+#[derive(Clone)]
+pub struct ConfigReloader {
+	source: ConfigSource,
+	live_config: Arc<RwLock<ServerConfig>>,
+	server: Arc<Mutex<dyn Server>>,
+	node: Arc<dyn GrinNode>,
+	/// number of requested reloads already handled
+	handled: Arc<AtomicUsize>,
+}
+
+impl ConfigReloader {
+	pub fn new(
+		source: ConfigSource,
+		server_config: ServerConfig,
+		server: Arc<Mutex<dyn Server>>,
+		node: Arc<dyn GrinNode>,
+	) -> Self {
+		let handled = source.reload_requests.load(Ordering::Relaxed);
+		ConfigReloader {
+			source,
+			live_config: Arc::new(RwLock::new(server_config)),
+			server,
+			node,
+			handled: Arc::new(AtomicUsize::new(handled)),
+		}
+	}
+
+	/// The config currently in effect
+	pub fn config(&self) -> ServerConfig {
+		self.live_config.read().unwrap().clone()
+	}
+
+	/// Re-reads the config file and applies the settings that can change while running.
+	/// The server is locked while switching over, so no request sees a mix of old and new settings.
+	pub fn reload(&self) -> Result<ConfigChanges, ConfigError> {
+		let mut live_config = self.live_config.write().unwrap();
+		let reloaded = config::reload_config(
+			&self.source.config_path,
+			self.source.profile.as_deref(),
+			self.source.instance.as_deref(),
+			&live_config,
+		)?;
+		let (server_config, changes) = live_config.reload(reloaded);
+
+		let mut server = self.server.lock().unwrap();
+		self.node.set_endpoint(
+			&server_config.grin_node_url,
+			&server_config.node_api_secret(),
+		);
+		server.update_config(server_config.clone());
+		*live_config = server_config;
+		drop(server);
+
+		if changes.applied.is_empty() {
+			info!("Reloaded config. No changes to apply.");
+		} else {
+			info!("Reloaded config. Applied {}.", changes.applied.join(", "));
+		}
+		if !changes.restart_required.is_empty() {
+			warn!(
+				"Changes to {} won't take effect until the server restarts.",
+				changes.restart_required.join(", ")
+			);
+		}
+		Ok(changes)
+	}
+
+	/// Reloads the config if SIGHUP was received since the last check
+	pub fn reload_if_requested(&self) {
+		let requested = self.source.reload_requests.load(Ordering::Relaxed);
+		if self.handled.swap(requested, Ordering::Relaxed) != requested {
+			if let Err(e) = self.reload() {
+				error!("Failed to reload config: {}", e);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{ConfigReloader, ConfigSource};
+	use crate::config::{self, test_util::test_config};
+	use crate::interval::ChainInterval;
+	use crate::node::mock::MockGrinNode;
+	use crate::server::mock::MockServer;
+
+	use grin_util::ZeroingString;
+	use std::path::PathBuf;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+	use std::sync::{Arc, Mutex};
+
+	/// Reloads only happen once per request, and leave the config untouched when the file is invalid
+	#[test]
+	fn reload_requests() -> Result<(), Box<dyn std::error::Error>> {
+		let dir = PathBuf::from("./target/tmp/.reload_requests");
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir)?;
+		let config_path = dir.join("mwixnet-config.toml");
+
+		let password = ZeroingString::from("password");
+		let running = test_config();
+		config::write_config(&config_path, None, &running, &password)?;
+
+		let source = ConfigSource {
+			config_path: config_path.clone(),
+			profile: None,
+			instance: None,
+			reload_requests: Arc::new(AtomicUsize::new(3)),
+		};
+		let reloader = ConfigReloader::new(
+			source.clone(),
+			running.clone(),
+			Arc::new(Mutex::new(MockServer::new())),
+			Arc::new(MockGrinNode::new()),
+		);
+
+		let mut edited = running.clone();
+		edited.round_interval = ChainInterval::Seconds(60);
+		config::write_config(&config_path, None, &edited, &password)?;
+		reloader.reload_if_requested();
+		assert_eq!(running, reloader.config());
+
+		source.reload_requests.fetch_add(1, Ordering::Relaxed);
+		reloader.reload_if_requested();
+		assert_eq!(edited, reloader.config());
+
+		std::fs::write(&config_path, "not toml")?;
+		source.reload_requests.fetch_add(1, Ordering::Relaxed);
+		reloader.reload_if_requested();
+		assert_eq!(edited, reloader.config());
+		assert!(reloader.reload().is_err());
+
+		Ok(())
+	}
+}
@@ -0,0 +1,526 @@
+use crate::http::HttpClient;
+use crate::secp::SecretKey;
+use crate::server::ServerImpl;
+use crate::store::{StoreError, SwapData};
+
+use byteorder::{BigEndian, ByteOrder};
+use chacha20::cipher::{NewCipher, StreamCipher};
+use chacha20::{ChaCha20, Key, Nonce};
+use grin_api::json_rpc::{build_request, Request, Response};
+use grin_core::ser::{self, ProtocolVersion, Readable, Reader, Writeable, Writer};
+use grin_util::ToHex;
+use hmac::{Hmac, Mac};
+use jsonrpc_derive::rpc;
+use jsonrpc_http_server::jsonrpc_core::{Error, IoHandler};
+use jsonrpc_http_server::{DomainsValidation, ServerBuilder};
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::Sha256;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tracing::{error, warn};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const ENDPOINT: &str = "/v1";
+
+/// Lease used when 'replication_lease' isn't configured
+pub const DEFAULT_LEASE: Duration = Duration::from_secs(60);
+
+/// Error types for replicating swaps to a standby
+#[derive(Error, Debug)]
+pub enum ReplicationError {
+	#[error("Replication message failed authentication")]
+	InvalidMac,
+	#[error("Replication message is older than one already received")]
+	StaleMessage,
+	#[error("Replication message is malformed: {0}")]
+	MalformedMessage(String),
+	#[error("Error serializing replicated swaps: {0:?}")]
+	SerializationError(ser::Error),
+	#[error("JSON-RPC API communication error: {0:?}")]
+	ApiCommError(grin_api::Error),
+	#[error("Error decoding JSON-RPC response: {0:?}")]
+	ResponseParseError(grin_api::json_rpc::Error),
+	#[error("Error saving replicated swaps: {0}")]
+	StoreError(StoreError),
+	#[error("Server is not a standby")]
+	NotStandby,
+	#[error("Primary was heard from {0} seconds ago. It must be silent for at least {1} seconds before promotion.")]
+	PrimaryAlive(u64, u64),
+}
+
+/// Keys for the channel between a primary and its standby.
+/// Both servers are configured with the same state key (the server key, until it's first
+/// rotated), so they derive the same channel keys, and only a server holding that key can send
+/// or acknowledge replicated swaps.
+#[derive(Clone)]
+struct ChannelKeys {
+	enc_key: [u8; 32],
+	mac_key: [u8; 32],
+}
+
+impl ChannelKeys {
+	fn new(server_key: &SecretKey) -> ChannelKeys {
+		let derive = |label: &[u8]| {
+			let mut hmac = HmacSha256::new_from_slice(label).unwrap();
+			hmac.update(&server_key.0);
+			let mut key = [0; 32];
+			key.copy_from_slice(&hmac.finalize().into_bytes()[0..32]);
+			key
+		};
+
+		ChannelKeys {
+			enc_key: derive(b"MWIXNET_REPLICATION_ENC"),
+			mac_key: derive(b"MWIXNET_REPLICATION_MAC"),
+		}
+	}
+
+	fn mac(&self, parts: &[&[u8]]) -> HmacSha256 {
+		let mut hmac = HmacSha256::new_from_slice(&self.mac_key).unwrap();
+		for part in parts {
+			hmac.update(part);
+		}
+		hmac
+	}
+
+	/// Encrypts the payload, then authenticates the ciphertext along with the sequence number
+	fn seal(&self, seq: u64, payload: &[u8]) -> ReplicationMessage {
+		let nonce: [u8; 12] = thread_rng().gen();
+		let mut ciphertext = payload.to_vec();
+		ChaCha20::new(Key::from_slice(&self.enc_key), Nonce::from_slice(&nonce))
+			.apply_keystream(&mut ciphertext);
+
+		let mut seq_bytes = [0; 8];
+		BigEndian::write_u64(&mut seq_bytes, seq);
+		let mac = self.mac(&[&seq_bytes, &nonce, &ciphertext]).finalize();
+
+		ReplicationMessage {
+			seq,
+			nonce: nonce.to_hex(),
+			payload: ciphertext.to_hex(),
+			mac: mac.into_bytes().to_hex(),
+		}
+	}
+
+	/// Authenticates the message, then decrypts its payload
+	fn open(&self, msg: &ReplicationMessage) -> Result<Vec<u8>, ReplicationError> {
+		let from_hex = |hex: &str| {
+			grin_util::from_hex(hex).map_err(|e| ReplicationError::MalformedMessage(e.to_string()))
+		};
+		let nonce = from_hex(&msg.nonce)?;
+		let mut payload = from_hex(&msg.payload)?;
+		let mac = from_hex(&msg.mac)?;
+		if nonce.len() != 12 {
+			return Err(ReplicationError::MalformedMessage(
+				"Invalid nonce length".to_string(),
+			));
+		}
+
+		let mut seq_bytes = [0; 8];
+		BigEndian::write_u64(&mut seq_bytes, msg.seq);
+		self.mac(&[&seq_bytes, &nonce, &payload])
+			.verify_slice(&mac)
+			.map_err(|_| ReplicationError::InvalidMac)?;
+
+		ChaCha20::new(Key::from_slice(&self.enc_key), Nonce::from_slice(&nonce))
+			.apply_keystream(&mut payload);
+		Ok(payload)
+	}
+
+	fn ack_mac(&self, seq: u64, promoted: bool) -> HmacSha256 {
+		let mut seq_bytes = [0; 8];
+		BigEndian::write_u64(&mut seq_bytes, seq);
+		self.mac(&[b"ACK", &seq_bytes, &[promoted as u8]])
+	}
+
+	fn seal_ack(&self, seq: u64, promoted: bool) -> ReplicationAck {
+		ReplicationAck {
+			seq,
+			promoted,
+			mac: self.ack_mac(seq, promoted).finalize().into_bytes().to_hex(),
+		}
+	}
+
+	fn verify_ack(&self, ack: &ReplicationAck, seq: u64) -> Result<(), ReplicationError> {
+		let mac = grin_util::from_hex(&ack.mac)
+			.map_err(|e| ReplicationError::MalformedMessage(e.to_string()))?;
+		if ack.seq != seq {
+			return Err(ReplicationError::StaleMessage);
+		}
+		self.ack_mac(ack.seq, ack.promoted)
+			.verify_slice(&mac)
+			.map_err(|_| ReplicationError::InvalidMac)
+	}
+}
+
+/// A batch of swaps, serialized as the payload of a 'ReplicationMessage'
+struct SwapBatch(Vec<SwapData>);
+
+impl Writeable for SwapBatch {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u64(self.0.len() as u64)?;
+		for swap in &self.0 {
+			swap.write(writer)?;
+		}
+		Ok(())
+	}
+}
+
+impl Readable for SwapBatch {
+	fn read<R: Reader>(reader: &mut R) -> Result<SwapBatch, ser::Error> {
+		let len = reader.read_u64()?;
+		let mut swaps = Vec::new();
+		for _ in 0..len {
+			swaps.push(SwapData::read(reader)?);
+		}
+		Ok(SwapBatch(swaps))
+	}
+}
+
+/// Encrypted and authenticated batch of swaps sent from a primary to its standby.
+/// A message with no swaps serves as a heartbeat.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ReplicationMessage {
+	pub seq: u64,
+	pub nonce: String,
+	pub payload: String,
+	pub mac: String,
+}
+
+/// Standby's authenticated acknowledgement of a 'ReplicationMessage'
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ReplicationAck {
+	pub seq: u64,
+	pub promoted: bool,
+	pub mac: String,
+}
+
+/// Sends accepted swaps (and status updates) to a standby in the background.
+///
+/// Rounds may only be executed while the standby has acknowledged a message within the lease,
+/// and hasn't been promoted. A standby only allows promotion after it hasn't heard from the
+/// primary for twice the lease, so the two servers can never both execute a round.
+pub struct Replicator {
+	sender: Mutex<Sender<SwapData>>,
+	lease: Duration,
+	last_ack: Arc<Mutex<Option<Instant>>>,
+	standby_promoted: Arc<AtomicBool>,
+}
+
+impl Replicator {
+	/// Starts replicating to the standby listening at 'standby_url'
+	pub fn start(
+		standby_url: &SocketAddr,
+		server_key: &SecretKey,
+		lease: Duration,
+		client: HttpClient,
+	) -> Replicator {
+		let (sender, receiver) = channel::<SwapData>();
+		let last_ack = Arc::new(Mutex::new(None));
+		let standby_promoted = Arc::new(AtomicBool::new(false));
+
+		let keys = ChannelKeys::new(server_key);
+		let url = format!("http://{}{}", standby_url, ENDPOINT);
+		let heartbeat_interval = (lease / 3).max(Duration::from_secs(1));
+		let worker_last_ack = last_ack.clone();
+		let worker_promoted = standby_promoted.clone();
+		spawn(move || {
+			// Sequence numbers start at the current time, so they keep increasing across restarts
+			let mut seq = SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.map(|d| d.as_nanos() as u64)
+				.unwrap_or(0);
+			let mut pending: Vec<SwapData> = Vec::new();
+			loop {
+				match receiver.recv_timeout(heartbeat_interval) {
+					Ok(swap) => {
+						pending.push(swap);
+						pending.extend(receiver.try_iter());
+					}
+					Err(RecvTimeoutError::Timeout) => {}
+					Err(RecvTimeoutError::Disconnected) => break,
+				}
+
+				seq += 1;
+				match send_batch(&client, &url, &keys, seq, &pending) {
+					Ok(ack) => {
+						pending.clear();
+						*worker_last_ack.lock().unwrap() = Some(Instant::now());
+						if ack.promoted && !worker_promoted.swap(true, Ordering::Relaxed) {
+							warn!("ALERT: Standby has been promoted. No longer executing rounds.");
+						}
+					}
+					Err(e) => {
+						// Keep the swaps queued, and try again on the next heartbeat
+						error!(
+							"Failed to replicate {} swaps to standby: {}",
+							pending.len(),
+							e
+						);
+						sleep(Duration::from_secs(1));
+					}
+				}
+			}
+		});
+
+		Replicator {
+			sender: Mutex::new(sender),
+			lease,
+			last_ack,
+			standby_promoted,
+		}
+	}
+
+	/// Queues the swap to be sent to the standby
+	pub fn replicate(&self, swap: &SwapData) {
+		let _ = self.sender.lock().unwrap().send(swap.clone());
+	}
+
+	/// Checks that this server still holds the lease, and so may execute rounds.
+	/// Returns the reason if it doesn't.
+	pub fn check_lease(&self) -> Result<(), String> {
+		if self.standby_promoted.load(Ordering::Relaxed) {
+			return Err("Standby has been promoted".to_string());
+		}
+
+		match *self.last_ack.lock().unwrap() {
+			Some(last_ack) if last_ack.elapsed() < self.lease => Ok(()),
+			_ => Err(format!(
+				"Standby has not acknowledged replication within the last {} seconds",
+				self.lease.as_secs()
+			)),
+		}
+	}
+}
+
+fn send_batch(
+	client: &HttpClient,
+	url: &str,
+	keys: &ChannelKeys,
+	seq: u64,
+	swaps: &Vec<SwapData>,
+) -> Result<ReplicationAck, ReplicationError> {
+	let payload = ser::ser_vec(&SwapBatch(swaps.clone()), ProtocolVersion::local())
+		.map_err(ReplicationError::SerializationError)?;
+	let msg = keys.seal(seq, &payload);
+
+	let req = build_request("replicate", &json!([msg]));
+	let res = client
+		.post::<Request, Response>(url, None, &req)
+		.map_err(ReplicationError::ApiCommError)?;
+	let ack: ReplicationAck = res
+		.into_result()
+		.map_err(ReplicationError::ResponseParseError)?;
+
+	keys.verify_ack(&ack, seq)?;
+	Ok(ack)
+}
+
+/// State of a standby server, which receives swaps from its primary until it's promoted
+pub struct Standby {
+	keys: ChannelKeys,
+	lease: Duration,
+	last_seq: Mutex<u64>,
+	last_heard: Mutex<Instant>,
+	promoted: AtomicBool,
+}
+
+impl Standby {
+	pub fn new(server_key: &SecretKey, lease: Duration) -> Standby {
+		Standby {
+			keys: ChannelKeys::new(server_key),
+			lease,
+			last_seq: Mutex::new(0),
+			last_heard: Mutex::new(Instant::now()),
+			promoted: AtomicBool::new(false),
+		}
+	}
+
+	pub fn is_promoted(&self) -> bool {
+		self.promoted.load(Ordering::Relaxed)
+	}
+
+	/// Authenticates and decrypts a message from the primary, returning the swaps it contains.
+	/// Once promoted, messages are still acknowledged (so the old primary learns it was replaced),
+	/// but their swaps are ignored.
+	pub fn receive(
+		&self,
+		msg: &ReplicationMessage,
+	) -> Result<(Vec<SwapData>, ReplicationAck), ReplicationError> {
+		let payload = self.keys.open(msg)?;
+
+		let mut last_seq = self.last_seq.lock().unwrap();
+		if msg.seq <= *last_seq {
+			return Err(ReplicationError::StaleMessage);
+		}
+		*last_seq = msg.seq;
+		*self.last_heard.lock().unwrap() = Instant::now();
+
+		let promoted = self.is_promoted();
+		let ack = self.keys.seal_ack(msg.seq, promoted);
+		if promoted {
+			return Ok((vec![], ack));
+		}
+
+		let batch: SwapBatch = ser::deserialize_default(&mut &payload[..])
+			.map_err(ReplicationError::SerializationError)?;
+		Ok((batch.0, ack))
+	}
+
+	/// Promotes the standby to primary, as long as the primary has been silent for twice the lease.
+	/// By then, the primary has stopped executing rounds, since its lease has expired.
+	pub fn promote(&self) -> Result<(), ReplicationError> {
+		let silent_for = self.last_heard.lock().unwrap().elapsed();
+		if silent_for < self.lease * 2 {
+			return Err(ReplicationError::PrimaryAlive(
+				silent_for.as_secs(),
+				(self.lease * 2).as_secs(),
+			));
+		}
+
+		self.promoted.store(true, Ordering::Relaxed);
+		Ok(())
+	}
+}
+
+#[rpc(server)]
+pub trait ReplicationAPI {
+	/// Receives a batch of swaps (or a heartbeat) from the primary
+	#[rpc(name = "replicate")]
+	fn replicate(&self, msg: ReplicationMessage) -> jsonrpc_core::Result<ReplicationAck>;
+}
+
+#[derive(Clone)]
+struct ReplicationServer {
+	server: ServerImpl,
+}
+
+impl ReplicationAPI for ReplicationServer {
+	/// Implements the 'replicate' API
+	fn replicate(&self, msg: ReplicationMessage) -> jsonrpc_core::Result<ReplicationAck> {
+		self.server
+			.receive_replicated(&msg)
+			.map_err(|e| Error::invalid_params(e.to_string()))
+	}
+}
+
+/// Spin up the standby's replication listener
+pub fn start_http(
+	addr: &SocketAddr,
+	server: ServerImpl,
+) -> std::io::Result<jsonrpc_http_server::Server> {
+	let mut io = IoHandler::new();
+	io.extend_with(ReplicationServer { server }.to_delegate());
+
+	ServerBuilder::new(io)
+		.cors(DomainsValidation::Disabled)
+		.request_middleware(|request: hyper::Request<hyper::Body>| {
+			if request.uri() == ENDPOINT {
+				request.into()
+			} else {
+				jsonrpc_http_server::Response::bad_request("Only v1 supported").into()
+			}
+		})
+		.start_http(addr)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{ChannelKeys, ReplicationError, Standby, SwapBatch};
+	use crate::onion::test_util::rand_onion;
+	use crate::secp::{self, test_util::rand_commit};
+	use crate::store::{SwapData, SwapStatus};
+
+	use grin_core::core::{Input, OutputFeatures};
+	use grin_core::ser::{self, ProtocolVersion};
+	use std::time::Duration;
+
+	fn rand_swap() -> SwapData {
+		SwapData {
+			excess: secp::random_secret(),
+			output_commit: rand_commit(),
+			rangeproof: None,
+			input: Input::new(OutputFeatures::Plain, rand_commit()),
+			fee: 1000,
+			onion: rand_onion(),
+			status: SwapStatus::Unprocessed,
+			expiry_height: 1000,
+			unconfirmed_input: false,
+			owner_kernel: None,
+			accepted_at: 1_700_000_000,
+			accepted_round: 1,
+			fee_change: None,
+		}
+	}
+
+	/// Messages can only be read by a server with the same key, and can't be tampered with or replayed
+	#[test]
+	fn replication_channel() -> Result<(), Box<dyn std::error::Error>> {
+		let server_key = secp::random_secret();
+		let primary = ChannelKeys::new(&server_key);
+		let standby = Standby::new(&server_key, Duration::from_secs(60));
+
+		let swaps = vec![rand_swap(), rand_swap()];
+		let payload = ser::ser_vec(&SwapBatch(swaps.clone()), ProtocolVersion::local())?;
+		let msg = primary.seal(1, &payload);
+
+		let (received, ack) = standby.receive(&msg)?;
+		assert_eq!(swaps, received);
+		primary.verify_ack(&ack, 1)?;
+		assert!(!ack.promoted);
+
+		// Replayed
+		assert!(matches!(
+			standby.receive(&msg),
+			Err(ReplicationError::StaleMessage)
+		));
+
+		// Tampered
+		let mut tampered = primary.seal(2, &payload);
+		tampered.seq = 3;
+		assert!(matches!(
+			standby.receive(&tampered),
+			Err(ReplicationError::InvalidMac)
+		));
+
+		// Different key
+		let other = Standby::new(&secp::random_secret(), Duration::from_secs(60));
+		assert!(matches!(
+			other.receive(&primary.seal(4, &payload)),
+			Err(ReplicationError::InvalidMac)
+		));
+
+		Ok(())
+	}
+
+	/// A standby can't be promoted while it's still hearing from the primary,
+	/// and stops applying swaps once promoted.
+	#[test]
+	fn standby_promotion() -> Result<(), Box<dyn std::error::Error>> {
+		let server_key = secp::random_secret();
+		let primary = ChannelKeys::new(&server_key);
+		let payload = ser::ser_vec(&SwapBatch(vec![rand_swap()]), ProtocolVersion::local())?;
+
+		let standby = Standby::new(&server_key, Duration::from_secs(60));
+		assert!(standby.promote().is_err());
+		assert!(!standby.is_promoted());
+
+		let standby = Standby::new(&server_key, Duration::from_secs(0));
+		standby.promote()?;
+		assert!(standby.is_promoted());
+
+		let (received, ack) = standby.receive(&primary.seal(1, &payload))?;
+		assert!(received.is_empty());
+		assert!(ack.promoted);
+		primary.verify_ack(&ack, 1)?;
+
+		Ok(())
+	}
+}
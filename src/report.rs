@@ -0,0 +1,242 @@
+use crate::error_codes::error_name;
+use crate::server::SwapError;
+use crate::store::FeeOutputData;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Number of days of activity kept in memory
+const ACTIVITY_HISTORY_DAYS: u64 = 31;
+
+/// The current (UTC) day, as the number of days since the unix epoch
+pub fn today() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs() / SECONDS_PER_DAY)
+		.unwrap_or(0)
+}
+
+/// Formats the number of days since the unix epoch as a 'YYYY-MM-DD' date.
+/// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+pub fn format_date(days: u64) -> String {
+	let z = days + 719_468;
+	let era = z / 146_097;
+	let doe = z - era * 146_097;
+	let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+	format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Parses a 'YYYY-MM-DD' date (on or after 1970-01-01) into the number of days since the unix epoch.
+/// See http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+pub fn parse_date(date: &str) -> Option<u64> {
+	let parts: Vec<&str> = date.split('-').collect();
+	if parts.len() != 3 {
+		return None;
+	}
+
+	let year: u64 = parts[0].parse().ok()?;
+	let month: u64 = parts[1].parse().ok()?;
+	let day: u64 = parts[2].parse().ok()?;
+	if year < 1970 || month < 1 || month > 12 || day < 1 || day > 31 {
+		return None;
+	}
+
+	let y = if month <= 2 { year - 1 } else { year };
+	let era = y / 400;
+	let yoe = y - era * 400;
+	let mp = if month > 2 { month - 3 } else { month + 9 };
+	let doy = (153 * mp + 2) / 5 + day - 1;
+	let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+	let days = era * 146_097 + doe - 719_468;
+
+	// Reject days that don't exist in the month (e.g. 2022-02-30)
+	if format_date(days) != date {
+		return None;
+	}
+	Some(days)
+}
+
+/// Swap and round activity over a single day
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct DailyActivity {
+	pub rounds: u64,
+	pub swaps_accepted: u64,
+	pub swaps_processed: u64,
+	pub rejections: BTreeMap<String, u64>,
+}
+
+/// Counts activity by day. Only kept in memory, so counts reset when the server restarts.
+#[derive(Clone, Debug, Default)]
+pub struct ActivityLog {
+	days: BTreeMap<u64, DailyActivity>,
+}
+
+impl ActivityLog {
+	/// Records the result of a swap request
+	pub fn record_swap(&mut self, result: &Result<(), SwapError>) {
+		let activity = self.today_mut();
+		match result {
+			Ok(()) => activity.swaps_accepted += 1,
+			Err(e) => {
+				*activity
+					.rejections
+					.entry(error_name(e).to_string())
+					.or_insert(0) += 1
+			}
+		}
+	}
+
+	/// Records a round that processed 'num_swaps' swaps
+	pub fn record_round(&mut self, num_swaps: usize) {
+		let activity = self.today_mut();
+		activity.rounds += 1;
+		activity.swaps_processed += num_swaps as u64;
+	}
+
+	/// The activity recorded for the given day
+	pub fn get(&self, day: u64) -> DailyActivity {
+		self.days.get(&day).cloned().unwrap_or_default()
+	}
+
+	fn today_mut(&mut self) -> &mut DailyActivity {
+		let today = today();
+		self.days
+			.retain(|day, _| *day + ACTIVITY_HISTORY_DAYS > today);
+		self.days.entry(today).or_default()
+	}
+}
+
+/// Summary of a single day's rounds, swaps, rejections, and earnings
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct DailyReport {
+	pub date: String,
+	pub rounds: u64,
+	pub swaps_accepted: u64,
+	pub swaps_processed: u64,
+	pub rejections: BTreeMap<String, u64>,
+	pub earnings: u64,
+}
+
+impl DailyReport {
+	/// Builds the report for 'day', counting only the fee outputs recorded that day
+	pub fn new(
+		day: u64,
+		activity: &DailyActivity,
+		fee_outputs: &Vec<FeeOutputData>,
+	) -> DailyReport {
+		let earnings = fee_outputs
+			.iter()
+			.filter(|f| f.timestamp / SECONDS_PER_DAY == day)
+			.map(|f| f.amount)
+			.sum();
+
+		DailyReport {
+			date: format_date(day),
+			rounds: activity.rounds,
+			swaps_accepted: activity.swaps_accepted,
+			swaps_processed: activity.swaps_processed,
+			rejections: activity.rejections.clone(),
+			earnings,
+		}
+	}
+
+	/// Human-readable version of the report
+	pub fn to_text(&self) -> String {
+		let mut text = String::new();
+		let _ = writeln!(text, "MWixnet daily report for {}", self.date);
+		let _ = writeln!(text, "Rounds: {}", self.rounds);
+		let _ = writeln!(text, "Swaps accepted: {}", self.swaps_accepted);
+		let _ = writeln!(text, "Swaps processed: {}", self.swaps_processed);
+		let _ = writeln!(
+			text,
+			"Swaps rejected: {}",
+			self.rejections.values().sum::<u64>()
+		);
+		for (reason, count) in &self.rejections {
+			let _ = writeln!(text, "  {}: {}", reason, count);
+		}
+		let _ = writeln!(text, "Earnings: {} nanogrin", self.earnings);
+		text
+	}
+
+	/// Writes the report to 'dir' as both JSON and text, named by date (e.g. 'report-2022-01-08.json')
+	pub fn write(&self, dir: &Path) -> std::io::Result<()> {
+		std::fs::create_dir_all(dir)?;
+
+		let json = serde_json::to_string_pretty(&self)?;
+		std::fs::write(dir.join(format!("report-{}.json", self.date)), json)?;
+		std::fs::write(
+			dir.join(format!("report-{}.txt", self.date)),
+			self.to_text(),
+		)?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{format_date, parse_date, today, ActivityLog, DailyReport, SECONDS_PER_DAY};
+	use crate::secp::test_util::{rand_commit, rand_hash};
+	use crate::server::SwapError;
+	use crate::store::FeeOutputData;
+
+	#[test]
+	fn dates() {
+		assert_eq!("1970-01-01", format_date(0));
+		assert_eq!("1970-03-01", format_date(59));
+		assert_eq!("2000-02-29", format_date(11_016));
+		assert_eq!("2022-01-08", format_date(19_000));
+
+		for day in &[0, 59, 11_016, 19_000, 20_000] {
+			assert_eq!(Some(*day), parse_date(&format_date(*day)));
+		}
+		assert_eq!(None, parse_date("2022-02-30"));
+		assert_eq!(None, parse_date("2022-1-08"));
+		assert_eq!(None, parse_date("1969-12-31"));
+		assert_eq!(None, parse_date("yesterday"));
+	}
+
+	/// Today's activity and earnings are collected into the report
+	#[test]
+	fn daily_report() {
+		let mut activity = ActivityLog::default();
+		activity.record_swap(&Ok(()));
+		activity.record_swap(&Ok(()));
+		activity.record_swap(&Err(SwapError::InvalidComSignature));
+		activity.record_round(2);
+
+		let day = today();
+		let fee_output = |amount: u64, timestamp: u64| FeeOutputData {
+			output_commit: rand_commit(),
+			amount,
+			kernel_hash: rand_hash(),
+			timestamp,
+		};
+		let fee_outputs = vec![
+			fee_output(100, day * SECONDS_PER_DAY + 10),
+			fee_output(200, (day - 1) * SECONDS_PER_DAY + 10),
+		];
+
+		let report = DailyReport::new(day, &activity.get(day), &fee_outputs);
+		assert_eq!(format_date(day), report.date);
+		assert_eq!(1, report.rounds);
+		assert_eq!(2, report.swaps_accepted);
+		assert_eq!(2, report.swaps_processed);
+		assert_eq!(Some(&1), report.rejections.get("invalid_comsig"));
+		assert_eq!(100, report.earnings);
+
+		let yesterday = DailyReport::new(day - 1, &activity.get(day - 1), &fee_outputs);
+		assert_eq!(0, yesterday.rounds);
+		assert_eq!(200, yesterday.earnings);
+	}
+}
@@ -1,32 +1,179 @@
-use crate::config::ServerConfig;
-use crate::node::GrinNode;
-use crate::onion::Onion;
-use crate::secp::{self, ComSignature};
-use crate::server::{Server, ServerImpl, SwapError};
+use crate::admin;
+use crate::admission::{AdmissionError, AdmissionQueue, Priority};
+use crate::api_keys::{ApiKeyError, ApiKeys, API_KEY_HEADER};
+use crate::compress;
+use crate::config::{ListenAddr, ServerConfig};
+use crate::error_codes;
+use crate::events::{self, EventBus};
+use crate::interval::{ChainInterval, DEFAULT_BLOCK_TIME_S};
+use crate::leader::{self, Coordinator, LeaderError};
+use crate::logging;
+use crate::metrics;
+use crate::node::{GrinNode, TipSubscription};
+use crate::nostr;
+use crate::onion::MAX_HOPS;
+use crate::peer_auth::{PeerAuthenticator, PEER_PATH};
+use crate::peers::{self, PeerHealth};
+use crate::policy::DenyList;
+use crate::reload::{ConfigReloader, ConfigSource};
+use crate::replication::{self, Replicator, Standby};
+use crate::report;
+use crate::secp::{self, Commitment, PublicKey, Secp256k1};
+use crate::server::{
+	FeeEstimate, PendingCommitment, ReconciliationReport, RoundPhase, RoundStats, Server,
+	ServerImpl, ServerInfo, ServerKeys, SwapCheck, SwapError, SwapReceipt, POST_TX_RETRY_DELAY,
+};
+use crate::service::InstanceStatus;
 use crate::store::SwapStore;
+use crate::tcp::{self, PeerAddr};
+use crate::uds;
 use crate::wallet::Wallet;
 
+use grin_core::core::Transaction;
 use grin_util::StopState;
+use hyper::body::HttpBody;
+use hyper::header::HeaderValue;
+use hyper::StatusCode;
 use jsonrpc_core::Value;
 use jsonrpc_derive::rpc;
 use jsonrpc_http_server::jsonrpc_core::*;
 use jsonrpc_http_server::*;
-use serde::{Deserialize, Serialize};
-use std::sync::{Arc, Mutex};
+pub use mwixnet_client::api::{CancelReq, CheckReq, ReceiptReq, SwapReq};
+use rand::{thread_rng, Rng};
+use serde_json::json;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread::{sleep, spawn};
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, error, info, warn, Span};
 
-#[derive(Serialize, Deserialize)]
-pub struct SwapReq {
-	onion: Onion,
-	#[serde(with = "secp::comsig_serde")]
-	comsig: ComSignature,
+/// How long to wait for an in-progress round to finish when shutting down
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2 * 60);
+
+/// How often expired swaps are cleaned up between rounds
+const EXPIRY_CHECK_INTERVAL_S: u32 = 60;
+
+/// How many swap submissions may wait for an executing round before new ones are turned away
+const MAX_WAITING_SUBMISSIONS: usize = 16;
+
+/// Largest request body the RPC server will read. A swap with a full-size onion is well under this.
+const MAX_REQUEST_BODY_SIZE: usize = 128 * 1024;
+
+/// Header of every API response giving the seconds until the next round is due, when known
+const NEXT_ROUND_HEADER: &str = "X-Next-Round-In";
+
+/// Where a request came from, as far as the server can tell
+#[derive(Clone, Debug, Default)]
+pub struct RequestMeta {
+	/// the client's address: the peer the request arrived from, the address a trusted reverse
+	/// proxy reports for it, or the transport it arrived over
+	source: Option<String>,
+	/// the API key the client presented, if any
+	api_key: Option<String>,
+}
+
+impl Metadata for RequestMeta {}
+
+impl RequestMeta {
+	/// Where the request came from, from the address of the peer it arrived from, and the
+	/// client's API key
+	fn from_request(
+		request: &hyper::Request<hyper::Body>,
+		trusted_proxies: &[IpAddr],
+	) -> RequestMeta {
+		let peer = request.extensions().get::<PeerAddr>().map(|peer| peer.0);
+		RequestMeta::from_headers(request.headers(), peer, trusted_proxies)
+	}
+
+	/// The client's address is the 'peer' the request arrived from, unless the peer is one of the
+	/// 'trusted_proxies'. Then it's the last address in 'X-Forwarded-For' that isn't a trusted
+	/// proxy, or failing that 'X-Real-IP'. Anyone else could put whatever they like in those
+	/// headers, so they're ignored. Requests over a unix socket have no peer address.
+	fn from_headers(
+		headers: &hyper::HeaderMap,
+		peer: Option<SocketAddr>,
+		trusted_proxies: &[IpAddr],
+	) -> RequestMeta {
+		let header = |name: &str| {
+			headers
+				.get(name)
+				.and_then(|value| value.to_str().ok())
+				.and_then(|value| value.split(',').next())
+				.map(|addr| addr.trim().to_string())
+				.filter(|addr| !addr.is_empty())
+		};
+		let is_trusted = |addr: &str| {
+			addr.parse::<IpAddr>()
+				.map_or(false, |addr| trusted_proxies.contains(&addr))
+		};
+		// Each proxy appends the address it was reached from, so only the ones added by trusted
+		// proxies can be relied on
+		let forwarded = || {
+			headers
+				.get("X-Forwarded-For")
+				.and_then(|value| value.to_str().ok())
+				.and_then(|value| {
+					value
+						.rsplit(',')
+						.map(|addr| addr.trim())
+						.find(|addr| !addr.is_empty() && !is_trusted(addr))
+						.map(|addr| addr.to_string())
+				})
+				.or_else(|| header("X-Real-IP"))
+		};
+		let source = match peer {
+			Some(peer) if trusted_proxies.contains(&peer.ip()) => {
+				forwarded().or_else(|| Some(peer.ip().to_string()))
+			}
+			Some(peer) => Some(peer.ip().to_string()),
+			None => None,
+		};
+		RequestMeta {
+			source,
+			api_key: header(API_KEY_HEADER),
+		}
+	}
 }
 
 #[rpc(server)]
 pub trait API {
-	#[rpc(name = "swap")]
-	fn swap(&self, swap: SwapReq) -> jsonrpc_core::Result<Value>;
+	type Metadata;
+
+	#[rpc(meta, name = "swap")]
+	fn swap(&self, meta: Self::Metadata, swap: SwapReq) -> jsonrpc_core::Result<Value>;
+
+	#[rpc(name = "check_swap")]
+	fn check_swap(&self, check: CheckReq) -> jsonrpc_core::Result<SwapCheck>;
+
+	#[rpc(name = "get_receipt")]
+	fn get_receipt(&self, receipt: ReceiptReq) -> jsonrpc_core::Result<SwapReceipt>;
+
+	#[rpc(name = "cancel_swap")]
+	fn cancel_swap(&self, cancel: CancelReq) -> jsonrpc_core::Result<Value>;
+
+	#[rpc(name = "get_pending_commitment")]
+	fn get_pending_commitment(&self) -> jsonrpc_core::Result<Option<PendingCommitment>>;
+
+	#[rpc(name = "get_server_keys")]
+	fn get_server_keys(&self) -> jsonrpc_core::Result<ServerKeys>;
+
+	#[rpc(name = "get_info")]
+	fn get_info(&self) -> jsonrpc_core::Result<ServerInfo>;
+
+	#[rpc(name = "estimate_fee")]
+	fn estimate_fee(&self, hops: u64) -> jsonrpc_core::Result<FeeEstimate>;
+
+	#[rpc(name = "get_stats")]
+	fn get_stats(&self) -> jsonrpc_core::Result<RoundStats>;
+}
+
+/// Methods mix nodes call on each other, served at PEER_PATH
+#[rpc(server)]
+pub trait PeerAPI {
+	/// Hands the caller's round transaction for 'slot' to this node, as the slot's leader
+	#[rpc(name = "submit_batch")]
+	fn submit_batch(&self, slot: u64, tx: Transaction) -> jsonrpc_core::Result<Value>;
 
 	// milestone 3: Used by mwixnet coinswap servers to communicate with each other
 	// fn derive_outputs(&self, entries: Vec<Onion>) -> jsonrpc_core::Result<Value>;
@@ -37,151 +184,1241 @@ pub trait API {
 struct RPCServer {
 	server_config: ServerConfig,
 	server: Arc<Mutex<dyn Server>>,
+	stop_state: Arc<StopState>,
+	/// unix time (in seconds) the round scheduler will next execute a round, if scheduled by time
+	next_round_at: Arc<RwLock<Option<u64>>>,
+	/// unix time (in seconds) the next round is due: 'next_round_at' when scheduled by time, or an
+	/// estimate from the block time when scheduled by block height
+	next_round_due: Arc<RwLock<Option<u64>>>,
+	/// serves status queries ahead of new submissions while a round is executing
+	admission: Arc<AdmissionQueue>,
+	/// checks run for each '/ready' request
+	probes: Arc<Vec<Box<dyn ReadinessProbe>>>,
+	/// verifies requests made at PEER_PATH
+	peer_auth: Arc<PeerAuthenticator>,
+	/// collects other nodes' round transactions, when rounds are coordinated
+	coordinator: Option<Arc<Coordinator>>,
+	/// the configured API keys, and how many swaps each has submitted this minute
+	api_keys: Arc<ApiKeys>,
+}
+
+/// The running JSON-RPC listener
+enum RpcListener {
+	Tcp(tcp::TcpServer),
+	Unix(uds::UnixServer),
+}
+
+impl RpcListener {
+	fn close(self) {
+		match self {
+			RpcListener::Tcp(server) => server.close(),
+			RpcListener::Unix(server) => server.close(),
+		}
+	}
 }
 
 impl RPCServer {
-	/// Spin up an instance of the JSON-RPC HTTP server.
-	fn start_http(&self) -> jsonrpc_http_server::Server {
-		let mut io = IoHandler::new();
-		io.extend_with(RPCServer::to_delegate(self.clone()));
-
-		ServerBuilder::new(io)
-			.cors(DomainsValidation::Disabled)
-			.request_middleware(|request: hyper::Request<hyper::Body>| {
-				if request.uri() == "/v1" {
-					request.into()
-				} else {
-					jsonrpc_http_server::Response::bad_request("Only v1 supported").into()
+	/// Spin up an instance of the JSON-RPC HTTP server, on a socket address or a unix socket.
+	fn start_http(&self) -> std::io::Result<RpcListener> {
+		let mut io = MetaIoHandler::default();
+		io.extend_with(API::to_delegate(self.clone()));
+		let api_io = io.clone();
+		let next_round_due = self.next_round_due.clone();
+
+		let mut peer_io = IoHandler::new();
+		peer_io.extend_with(PeerAPI::to_delegate(self.clone()));
+
+		let probes = self.probes.clone();
+		let peer_auth = self.peer_auth.clone();
+		let trusted_proxies = Arc::new(
+			self.server_config
+				.trusted_proxies
+				.clone()
+				.unwrap_or_default(),
+		);
+		let extractor = {
+			let trusted_proxies = trusted_proxies.clone();
+			move |request: &hyper::Request<hyper::Body>| {
+				RequestMeta::from_request(request, &trusted_proxies)
+			}
+		};
+		let middleware = move |request: hyper::Request<hyper::Body>| {
+			if request.uri() == "/v1" {
+				match decode_request(request) {
+					Ok(request) => api_request(
+						request,
+						api_io.clone(),
+						next_round_due.clone(),
+						&trusted_proxies,
+					),
+					Err(response) => response.into(),
 				}
-			})
-			.start_http(&self.server_config.addr)
-			.expect("Unable to start RPC server")
+			} else if request.uri() == PEER_PATH {
+				peer_request(request, peer_io.clone(), peer_auth.clone())
+			} else if request.uri() == "/health" {
+				json_response(StatusCode::OK, json!({"status": "ok"})).into()
+			} else if request.uri() == "/ready" {
+				readiness_response(&probes).into()
+			} else {
+				jsonrpc_http_server::Response::bad_request("Only v1 supported").into()
+			}
+		};
+
+		match &self.server_config.addr {
+			ListenAddr::Tcp(addr) => {
+				tcp::start(addr, io, extractor, middleware, MAX_REQUEST_BODY_SIZE)
+					.map(RpcListener::Tcp)
+			}
+			ListenAddr::Unix(path) => {
+				uds::start(path, io, extractor, middleware, MAX_REQUEST_BODY_SIZE)
+					.map(RpcListener::Unix)
+			}
+		}
 	}
 }
 
 impl From<SwapError> for Error {
 	fn from(e: SwapError) -> Self {
-		match e {
-			SwapError::UnknownError(_) => Error {
-				message: e.to_string(),
-				code: ErrorCode::InternalError,
-				data: None,
-			},
-			_ => Error::invalid_params(e.to_string()),
+		Error {
+			code: ErrorCode::from(error_codes::error_code(&e)),
+			message: e.to_string(),
+			data: Some(error_codes::error_data(&e)),
+		}
+	}
+}
+
+impl From<LeaderError> for Error {
+	fn from(e: LeaderError) -> Self {
+		Error {
+			code: ErrorCode::ServerError(-32002),
+			message: e.to_string(),
+			data: None,
+		}
+	}
+}
+
+impl From<ApiKeyError> for Error {
+	fn from(e: ApiKeyError) -> Self {
+		let code = match e {
+			ApiKeyError::RateLimited { .. } => -32001,
+			_ => -32003,
+		};
+		Error {
+			code: ErrorCode::ServerError(code),
+			message: e.to_string(),
+			data: None,
+		}
+	}
+}
+
+impl From<AdmissionError> for Error {
+	fn from(e: AdmissionError) -> Self {
+		Error {
+			code: ErrorCode::ServerError(-32001),
+			message: e.to_string(),
+			data: None,
 		}
 	}
 }
 
 impl API for RPCServer {
+	type Metadata = RequestMeta;
+
 	/// Implements the 'swap' API
-	fn swap(&self, swap: SwapReq) -> jsonrpc_core::Result<Value> {
+	fn swap(&self, meta: RequestMeta, swap: SwapReq) -> jsonrpc_core::Result<Value> {
+		let _request = logging::request_span("swap").entered();
+		if self.stop_state.is_stopped() {
+			return Err(Error {
+				code: ErrorCode::ServerError(-32000),
+				message: "Server is shutting down".to_string(),
+				data: None,
+			});
+		}
+
+		// Onions with too many payloads are already rejected when they're parsed
+		if swap.onion.enc_payloads.is_empty() {
+			return Err(Error::invalid_params("Onion must have at least one hop"));
+		}
+
+		// Swaps submitted with an API key are counted against the key's limit, and in its metrics
+		let key_name = match &meta.api_key {
+			Some(key) => match self.api_keys.admit(key, secs_from_now(0)) {
+				Ok(name) => Some(name),
+				Err(e) => {
+					if let ApiKeyError::RateLimited { name, .. } = &e {
+						self.server.lock().unwrap().count_api_key_swap(name, false);
+					}
+					debug!("Swap refused: {}", e);
+					return Err(e.into());
+				}
+			},
+			None => None,
+		};
+
+		let priority = match key_name {
+			Some(_) => Priority::Keyed,
+			None => Priority::Low,
+		};
+		let _admission = self.admission.admit(priority).map_err(|e| {
+			debug!("Swap shed: {}", e);
+			e
+		})?;
+
+		// Verify the rangeproof before taking the server for the swap itself, so a burst of
+		// submissions is verified in parallel. The peeled layer is handed to 'swap_peeled', so
+		// the onion is only peeled once. Onions that can't be peeled are left for 'swap' to reject
+		// with the appropriate error.
+		let (peeled, verifier) = {
+			let server = self.server.lock().unwrap();
+			(server.peel_layer(&swap.onion).ok(), server.proof_verifier())
+		};
+		if let Some((payload, peeled)) = &peeled {
+			if let Some(rangeproof) = &payload.rangeproof {
+				verifier.verify(&peeled.commit, rangeproof).map_err(|e| {
+					debug!("Swap shed: {}", e);
+					e
+				})?;
+			}
+		}
+
+		// Rejections are logged for operators, so they can tell why a client's swaps are failing
+		let server = self.server.lock().unwrap();
+		let result = match peeled {
+			Some(peeled) => {
+				server.swap_peeled(&swap.onion, peeled, &swap.comsig, swap.expiry_height)
+			}
+			None => server.swap(&swap.onion, &swap.comsig, swap.expiry_height),
+		};
+		if let Some(name) = &key_name {
+			server.count_api_key_swap(name, result.is_ok());
+		}
+		if let Err(e) = result {
+			server.record_rejection(&swap.onion, meta.source, &e);
+			return Err(e.into());
+		}
+
+		// Swaps that arrived after the upcoming round's intake closed are held for the round
+		// after it. The client is told when that round is expected.
+		if let (Ok(accepted_round), Some(window)) = (
+			server.accepted_round(&swap.onion.commit),
+			server.acceptance_window(),
+		) {
+			if accepted_round > window.round_id {
+				let interval = self.server_config.round_interval;
+				return Ok(json!({
+					"status": "next_round",
+					"round_id": accepted_round,
+					"round_at": window
+						.closes_at
+						.map(|t| t + interval.as_secs(DEFAULT_BLOCK_TIME_S)),
+					"round_height": window
+						.closes_at_height
+						.map(|h| h + interval.as_blocks(DEFAULT_BLOCK_TIME_S)),
+				}));
+			}
+		}
+		Ok(Value::String("success".into()))
+	}
+
+	/// Implements the 'check_swap' API
+	fn check_swap(&self, check: CheckReq) -> jsonrpc_core::Result<SwapCheck> {
+		let _request = logging::request_span("check_swap").entered();
+		let commit = parse_commitment(&check.commit)?;
+		let _admission = self.admission.admit(Priority::High)?;
+		let check =
+			self.server
+				.lock()
+				.unwrap()
+				.check_swap(&commit, &check.comsig, check.expiry_height)?;
+		Ok(check)
+	}
+
+	/// Implements the 'get_receipt' API
+	fn get_receipt(&self, receipt: ReceiptReq) -> jsonrpc_core::Result<SwapReceipt> {
+		let _request = logging::request_span("get_receipt").entered();
+		let commit = parse_commitment(&receipt.commit)?;
+		let _admission = self.admission.admit(Priority::High)?;
+		let receipt = self.server.lock().unwrap().get_receipt(
+			&commit,
+			&receipt.comsig,
+			receipt.expiry_height,
+		)?;
+		Ok(receipt)
+	}
+
+	/// Implements the 'cancel_swap' API
+	fn cancel_swap(&self, cancel: CancelReq) -> jsonrpc_core::Result<Value> {
+		let _request = logging::request_span("cancel_swap").entered();
+		let commit = parse_commitment(&cancel.commit)?;
+		let _admission = self.admission.admit(Priority::High)?;
 		self.server
 			.lock()
 			.unwrap()
-			.swap(&swap.onion, &swap.comsig)?;
+			.cancel_swap(&commit, &cancel.comsig, cancel.expiry_height)?;
 		Ok(Value::String("success".into()))
 	}
+
+	/// Implements the 'get_pending_commitment' API
+	fn get_pending_commitment(&self) -> jsonrpc_core::Result<Option<PendingCommitment>> {
+		let _request = logging::request_span("get_pending_commitment").entered();
+		let _admission = self.admission.admit(Priority::High)?;
+		Ok(self.server.lock().unwrap().get_pending_commitment())
+	}
+
+	/// Implements the 'get_server_keys' API
+	fn get_server_keys(&self) -> jsonrpc_core::Result<ServerKeys> {
+		let _request = logging::request_span("get_server_keys").entered();
+		let _admission = self.admission.admit(Priority::High)?;
+		let keys = self.server.lock().unwrap().server_keys()?;
+		Ok(keys)
+	}
+
+	/// Implements the 'get_info' API
+	fn get_info(&self) -> jsonrpc_core::Result<ServerInfo> {
+		let _request = logging::request_span("get_info").entered();
+		let _admission = self.admission.admit(Priority::High)?;
+		let mut info = self.server.lock().unwrap().info()?;
+		info.next_round_at = *self.next_round_at.read().unwrap();
+		info.next_round_in_s = secs_until(&self.next_round_due);
+		Ok(info)
+	}
+
+	/// Implements the 'estimate_fee' API
+	fn estimate_fee(&self, hops: u64) -> jsonrpc_core::Result<FeeEstimate> {
+		let _request = logging::request_span("estimate_fee").entered();
+		let _admission = self.admission.admit(Priority::High)?;
+		if hops == 0 || hops > MAX_HOPS as u64 {
+			return Err(Error::invalid_params(format!(
+				"hops must be between 1 and {}",
+				MAX_HOPS
+			)));
+		}
+		Ok(self.server.lock().unwrap().estimate_fee(hops))
+	}
+
+	/// Implements the 'get_stats' API
+	fn get_stats(&self) -> jsonrpc_core::Result<RoundStats> {
+		let _request = logging::request_span("get_stats").entered();
+		let _admission = self.admission.admit(Priority::High)?;
+		let stats = self.server.lock().unwrap().stats()?;
+		Ok(stats)
+	}
+}
+
+impl PeerAPI for RPCServer {
+	/// Implements the 'submit_batch' API
+	fn submit_batch(&self, slot: u64, tx: Transaction) -> jsonrpc_core::Result<Value> {
+		let _request = logging::request_span("submit_batch").entered();
+		let coordinator = self
+			.coordinator
+			.as_ref()
+			.ok_or_else(|| Error::invalid_params("Rounds are not coordinated with other nodes"))?;
+		coordinator.receive(slot, tx)?;
+		Ok(Value::String("success".into()))
+	}
+}
+
+/// A check that one of the server's dependencies is usable, run for each '/ready' request.
+/// Further dependencies can be checked by adding a probe to those passed to the 'RPCServer'.
+pub trait ReadinessProbe: Send + Sync {
+	/// The name the probe's result is reported under
+	fn name(&self) -> &'static str;
+
+	/// Returns the reason the dependency isn't usable, if it isn't
+	fn check(&self) -> std::result::Result<(), String>;
+}
+
+/// Checks that the node's API responds
+struct NodeProbe(Arc<dyn GrinNode>);
+
+impl ReadinessProbe for NodeProbe {
+	fn name(&self) -> &'static str {
+		"node"
+	}
+
+	fn check(&self) -> std::result::Result<(), String> {
+		self.0
+			.get_chain_height()
+			.map(|_| ())
+			.map_err(|e| e.to_string())
+	}
+}
+
+/// Checks that the wallet responds
+struct WalletProbe(Arc<dyn Wallet>);
+
+impl ReadinessProbe for WalletProbe {
+	fn name(&self) -> &'static str {
+		"wallet"
+	}
+
+	fn check(&self) -> std::result::Result<(), String> {
+		self.0.ping().map_err(|e| e.to_string())
+	}
+}
+
+/// Checks that the swap store accepts writes
+struct StoreProbe(Arc<Mutex<SwapStore>>);
+
+impl ReadinessProbe for StoreProbe {
+	fn name(&self) -> &'static str {
+		"store"
+	}
+
+	fn check(&self) -> std::result::Result<(), String> {
+		self.0
+			.lock()
+			.unwrap()
+			.check_writable()
+			.map_err(|e| e.to_string())
+	}
+}
+
+/// Runs every probe, reporting each one's result by name.
+/// The server is only ready if all of them pass.
+fn check_readiness(probes: &[Box<dyn ReadinessProbe>]) -> (bool, serde_json::Map<String, Value>) {
+	let mut ready = true;
+	let mut checks = serde_json::Map::new();
+	for probe in probes {
+		let result = match probe.check() {
+			Ok(()) => "ok".to_string(),
+			Err(e) => {
+				warn!("Readiness check '{}' failed: {}", probe.name(), e);
+				ready = false;
+				e
+			}
+		};
+		checks.insert(probe.name().to_string(), Value::String(result));
+	}
+	(ready, checks)
+}
+
+/// Responds with 200 if every probe passes, otherwise 503, along with each probe's result
+fn readiness_response(probes: &[Box<dyn ReadinessProbe>]) -> jsonrpc_http_server::Response {
+	let (ready, checks) = check_readiness(probes);
+	let code = if ready {
+		StatusCode::OK
+	} else {
+		StatusCode::SERVICE_UNAVAILABLE
+	};
+	json_response(code, json!({ "ready": ready, "checks": checks }))
 }
 
-/// Spin up the JSON-RPC web server
+/// Undoes the request's Content-Encoding, if it has one. zstd-compressed bodies are decompressed
+/// as they're read, up to MAX_REQUEST_BODY_SIZE. Other encodings aren't supported.
+fn decode_request(
+	request: hyper::Request<hyper::Body>,
+) -> std::result::Result<hyper::Request<hyper::Body>, jsonrpc_http_server::Response> {
+	let encoding = match request.headers().get(hyper::header::CONTENT_ENCODING) {
+		Some(encoding) => encoding.to_str().unwrap_or("").trim().to_ascii_lowercase(),
+		None => return Ok(request),
+	};
+	if encoding == "identity" {
+		return Ok(request);
+	}
+	if encoding != compress::ZSTD_ENCODING {
+		return Err(jsonrpc_http_server::Response {
+			code: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+			content_type: HeaderValue::from_static("text/plain; charset=utf-8"),
+			content: format!("Unsupported Content-Encoding: {}", encoding),
+		});
+	}
+
+	let (mut parts, body) = request.into_parts();
+	parts.headers.remove(hyper::header::CONTENT_ENCODING);
+	parts.headers.remove(hyper::header::CONTENT_LENGTH);
+	let decompressed = futures::stream::once(read_decompressed(body));
+	Ok(hyper::Request::from_parts(
+		parts,
+		hyper::Body::wrap_stream(decompressed),
+	))
+}
+
+/// Reads the zstd-compressed body, failing if either it or its decompressed contents are larger
+/// than MAX_REQUEST_BODY_SIZE
+async fn read_decompressed(
+	mut body: hyper::Body,
+) -> std::result::Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+	let mut compressed = Vec::new();
+	while let Some(chunk) = body.data().await {
+		compressed.extend_from_slice(&chunk?);
+		if compressed.len() > MAX_REQUEST_BODY_SIZE {
+			return Err("Compressed request body is too large".into());
+		}
+	}
+	Ok(compress::decompress(&compressed, MAX_REQUEST_BODY_SIZE)?)
+}
+
+/// Serves a request to the API, adding the countdown to the next round to the response.
+/// jsonrpc_http_server can't add headers to its responses, so it's handled here instead.
+fn api_request(
+	request: hyper::Request<hyper::Body>,
+	io: MetaIoHandler<RequestMeta>,
+	next_round_due: Arc<RwLock<Option<u64>>>,
+	trusted_proxies: &[IpAddr],
+) -> RequestMiddlewareAction {
+	if request.method() != hyper::Method::POST {
+		return jsonrpc_http_server::Response {
+			code: StatusCode::METHOD_NOT_ALLOWED,
+			content_type: HeaderValue::from_static("text/plain; charset=utf-8"),
+			content: "Only POST requests are supported".to_string(),
+		}
+		.into();
+	}
+	let is_json = request
+		.headers()
+		.get(hyper::header::CONTENT_TYPE)
+		.and_then(|t| t.to_str().ok())
+		.map_or(false, |t| t.starts_with("application/json"));
+	if !is_json {
+		return jsonrpc_http_server::Response {
+			code: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+			content_type: HeaderValue::from_static("text/plain; charset=utf-8"),
+			content: "Content-Type must be application/json".to_string(),
+		}
+		.into();
+	}
+
+	let meta = RequestMeta::from_request(&request, trusted_proxies);
+	let mut body = request.into_body();
+	let response = async move {
+		let mut bytes = Vec::new();
+		while let Some(chunk) = body.data().await {
+			match chunk {
+				Ok(chunk) => bytes.extend_from_slice(&chunk),
+				Err(e) => return Ok(text_response(StatusCode::BAD_REQUEST, e.to_string())),
+			}
+			if bytes.len() > MAX_REQUEST_BODY_SIZE {
+				return Ok(text_response(
+					StatusCode::PAYLOAD_TOO_LARGE,
+					"Request body is too large".to_string(),
+				));
+			}
+		}
+
+		let content = match std::str::from_utf8(&bytes) {
+			Ok(request) => match io.handle_request(request, meta).await {
+				Some(response) => format!("{}\n", response),
+				None => String::new(),
+			},
+			Err(_) => {
+				return Ok(text_response(
+					StatusCode::BAD_REQUEST,
+					"Request body is not valid UTF-8".to_string(),
+				))
+			}
+		};
+		let mut response = hyper::Response::builder().status(StatusCode::OK).header(
+			hyper::header::CONTENT_TYPE,
+			"application/json; charset=utf-8",
+		);
+		if let Some(secs) = secs_until(&next_round_due) {
+			response = response.header(NEXT_ROUND_HEADER, secs);
+		}
+		Ok::<_, hyper::Error>(
+			response
+				.body(hyper::Body::from(content))
+				.expect("Valid response"),
+		)
+	};
+	RequestMiddlewareAction::Respond {
+		should_validate_hosts: true,
+		response: Box::pin(response),
+	}
+}
+
+/// Serves a request from another mix node, once its signature has been verified against the
+/// body. Unsigned requests are answered with 401, and ones signed by unknown keys with 403.
+fn peer_request(
+	request: hyper::Request<hyper::Body>,
+	peer_io: IoHandler,
+	peer_auth: Arc<PeerAuthenticator>,
+) -> RequestMiddlewareAction {
+	let (parts, mut body) = request.into_parts();
+	let response = async move {
+		let mut bytes = Vec::new();
+		while let Some(chunk) = body.data().await {
+			bytes.extend_from_slice(&chunk?);
+			if bytes.len() > MAX_REQUEST_BODY_SIZE {
+				return Ok(text_response(
+					StatusCode::PAYLOAD_TOO_LARGE,
+					"Request body is too large".to_string(),
+				));
+			}
+		}
+
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0);
+		if let Err(e) = peer_auth.verify(&parts.headers, PEER_PATH, &bytes, now) {
+			warn!("Rejected peer request: {}", e);
+			let code = if e.is_forbidden() {
+				StatusCode::FORBIDDEN
+			} else {
+				StatusCode::UNAUTHORIZED
+			};
+			return Ok(text_response(code, e.to_string()));
+		}
+
+		let content = match std::str::from_utf8(&bytes) {
+			Ok(request) => peer_io.handle_request(request).await.unwrap_or_default(),
+			Err(_) => {
+				return Ok(text_response(
+					StatusCode::BAD_REQUEST,
+					"Request body is not valid UTF-8".to_string(),
+				))
+			}
+		};
+		Ok::<_, hyper::Error>(
+			hyper::Response::builder()
+				.status(StatusCode::OK)
+				.header(
+					hyper::header::CONTENT_TYPE,
+					"application/json; charset=utf-8",
+				)
+				.body(hyper::Body::from(content))
+				.expect("Valid response"),
+		)
+	};
+	RequestMiddlewareAction::Respond {
+		should_validate_hosts: true,
+		response: Box::pin(response),
+	}
+}
+
+fn text_response(code: StatusCode, content: String) -> hyper::Response<hyper::Body> {
+	hyper::Response::builder()
+		.status(code)
+		.header(hyper::header::CONTENT_TYPE, "text/plain; charset=utf-8")
+		.body(hyper::Body::from(content))
+		.expect("Valid response")
+}
+
+fn json_response(code: StatusCode, body: Value) -> jsonrpc_http_server::Response {
+	jsonrpc_http_server::Response {
+		code,
+		content_type: HeaderValue::from_static("application/json; charset=utf-8"),
+		content: body.to_string(),
+	}
+}
+
+/// Parses a hex-encoded commitment
+fn parse_commitment(hex: &str) -> jsonrpc_core::Result<Commitment> {
+	secp::commitment_from_hex(hex).ok_or_else(|| Error::invalid_params("Invalid commitment"))
+}
+
+/// The round interval, randomly lengthened or shortened by up to 'jitter_percent' percent,
+/// so observers can't predict exactly when rounds will execute.
+fn jittered_interval(interval_s: u64, jitter_percent: u8) -> u64 {
+	let max_jitter = interval_s * jitter_percent.min(100) as u64 / 100;
+	if max_jitter == 0 {
+		return interval_s.max(1);
+	}
+
+	let offset = thread_rng().gen_range(0..=2 * max_jitter);
+	(interval_s + offset - max_jitter).max(1)
+}
+
+/// Unix time (in seconds) 'secs' seconds from now
+fn secs_from_now(secs: u64) -> u64 {
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0);
+	now + secs
+}
+
+/// Seconds from now until the unix time in 'at', if it's known
+fn secs_until(at: &RwLock<Option<u64>>) -> Option<u64> {
+	let at = (*at.read().unwrap())?;
+	Some(at.saturating_sub(secs_from_now(0)))
+}
+
+/// A random delay of up to 'window_s' seconds
+fn random_delay(window_s: u64) -> u64 {
+	thread_rng().gen_range(0..=window_s)
+}
+
+/// Spin up the JSON-RPC web server.
+/// The config is reloaded from 'config_source' whenever the process receives SIGHUP.
 pub fn listen(
 	server_config: ServerConfig,
+	config_source: ConfigSource,
 	wallet: Arc<dyn Wallet>,
 	node: Arc<dyn GrinNode>,
 	store: SwapStore,
 	stop_state: Arc<StopState>,
+	status: Arc<InstanceStatus>,
 ) -> std::result::Result<(), Box<dyn std::error::Error>> {
-	let server = ServerImpl::new(server_config.clone(), wallet.clone(), node.clone(), store);
+	let mut server = ServerImpl::new(server_config.clone(), wallet.clone(), node.clone(), store);
+
+	let lease = server_config
+		.replication_lease
+		.map(|l| l.as_duration(DEFAULT_BLOCK_TIME_S))
+		.unwrap_or(replication::DEFAULT_LEASE);
+	if let Some(standby_addr) = &server_config.standby_addr {
+		let replicator = Replicator::start(
+			standby_addr,
+			server_config.state_key(),
+			lease,
+			server_config.http_client(),
+		);
+		server = server.with_replicator(Arc::new(replicator));
+		info!("Replicating swaps to standby at {}", standby_addr);
+	}
+	let replication_server = match &server_config.replication_addr {
+		Some(replication_addr) => {
+			server = server.with_standby(Arc::new(Standby::new(server_config.state_key(), lease)));
+			let replication_server = replication::start_http(replication_addr, server.clone())?;
+			info!("Standby receiving swaps on {}", replication_addr);
+			Some(replication_server)
+		}
+		None => None,
+	};
+	if let Some(deny_list_path) = &server_config.deny_list_path {
+		let deny_list = DenyList::open(Path::new(deny_list_path))?;
+		server = server.with_policy(Arc::new(deny_list));
+	}
+	let events = server_config.events_addr.map(|_| EventBus::new());
+	if let Some(events) = &events {
+		server = server.with_events(events.clone());
+	}
+	let peer_health = Arc::new(PeerHealth::new());
+	server = server.with_peers(peer_health.clone());
+	let coordinator = match &server_config.round_peers {
+		Some(round_peers) => {
+			let coordinator = Arc::new(Coordinator::new(
+				&server_config.key,
+				round_peers.clone(),
+				server_config.round_interval,
+				node.clone(),
+				server_config.http_client(),
+			)?);
+			server = server.with_coordinator(coordinator.clone());
+			info!("Coordinating rounds with {} other nodes", round_peers.len());
+			Some(coordinator)
+		}
+		None => None,
+	};
+
+	// Other nodes taking part in coordinated rounds may hand over their batches
+	let mut peer_auth =
+		PeerAuthenticator::from_hex(server_config.peer_pubkeys.as_deref().unwrap_or(&[]))?;
+	for round_peer in server_config.round_peers.iter().flatten() {
+		peer_auth.trust(&round_peer.pubkey);
+	}
+
+	let probes: Vec<Box<dyn ReadinessProbe>> = vec![
+		Box::new(NodeProbe(node.clone())),
+		Box::new(WalletProbe(wallet.clone())),
+		Box::new(StoreProbe(server.store())),
+	];
+	let probes = Arc::new(probes);
+	server.clear_interrupted_round()?;
+	let recorded = server.reconcile_queued_txs()?;
+	if recorded > 0 {
+		info!(
+			"Recorded {} rounds posted before the server stopped",
+			recorded
+		);
+	}
+	let report = server.reconcile_in_process()?;
+	if report != ReconciliationReport::default() {
+		info!(
+			"Reconciled swaps left in process: {} completed, {} still pending, {} back to unprocessed, {} failed, {} unchecked",
+			report.completed, report.pending, report.unprocessed, report.failed, report.unchecked
+		);
+	}
+	let round_state = server.round_state();
 	let server = Arc::new(Mutex::new(server));
+	let reloader = ConfigReloader::new(
+		config_source.clone(),
+		server_config.clone(),
+		server.clone(),
+		node.clone(),
+	);
 
+	let next_round_at = Arc::new(RwLock::new(None));
+	let next_round_due = Arc::new(RwLock::new(None));
+	let admission = Arc::new(AdmissionQueue::new(MAX_WAITING_SUBMISSIONS));
 	let rpc_server = RPCServer {
 		server_config: server_config.clone(),
 		server: server.clone(),
+		stop_state: stop_state.clone(),
+		next_round_at: next_round_at.clone(),
+		next_round_due: next_round_due.clone(),
+		admission: admission.clone(),
+		probes: probes.clone(),
+		peer_auth: Arc::new(peer_auth),
+		coordinator: coordinator.clone(),
+		api_keys: Arc::new(ApiKeys::new(
+			server_config.api_keys.clone().unwrap_or_default(),
+		)?),
 	};
 
-	let http_server = rpc_server.start_http();
-	println!("Server listening on {}", server_config.addr);
+	let http_server = rpc_server.start_http()?;
+	info!("Server listening on {}", server_config.addr);
+
+	// Swaps submitted over Nostr go through the same admission pipeline as the 'swap' API
+	if let Some(relays) = &server_config.nostr_relays {
+		let relay_rpc = rpc_server.clone();
+		let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &server_config.key)?;
+		nostr::start(
+			relays,
+			&pubkey,
+			Arc::new(move |swap| {
+				let meta = RequestMeta {
+					source: Some("nostr".to_string()),
+					api_key: None,
+				};
+				relay_rpc.swap(meta, swap)
+			}),
+			stop_state.clone(),
+		);
+		info!("Listening for swaps on {} Nostr relays", relays.len());
+	}
+
+	// A fresh admin token is written each time the server starts: next to the admin socket,
+	// or next to the config file when the admin API is only served over HTTP
+	let admin_token = match (&server_config.admin_socket_path, &server_config.admin_addr) {
+		(Some(socket_path), _) => Some(admin::create_token_file(&admin::token_path(Path::new(
+			socket_path,
+		)))?),
+		(None, Some(_)) => Some(admin::create_token_file(&admin::http_token_path(
+			&config_source.config_path,
+			config_source.profile.as_deref(),
+			config_source.instance.as_deref(),
+		))?),
+		(None, None) => None,
+	};
+
+	let admin_server = match (&server_config.admin_socket_path, &admin_token) {
+		(Some(socket_path), Some(token)) => {
+			let admin_server =
+				admin::start_ipc(socket_path, server.clone(), reloader.clone(), token.clone())?;
+			info!("Admin listening on {}", socket_path);
+			Some(admin_server)
+		}
+		_ => None,
+	};
+	let admin_http_server = match (&server_config.admin_addr, &admin_token) {
+		(Some(admin_addr), Some(token)) => {
+			let admin_http_server =
+				admin::start_http(admin_addr, server.clone(), reloader.clone(), token.clone())?;
+			info!("Admin API listening on {}", admin_addr);
+			Some(admin_http_server)
+		}
+		_ => None,
+	};
 
-	let close_handle = http_server.close_handle();
+	// Round events are only served to the operator, who proves it with the admin token
+	if let (Some(events_addr), Some(events)) = (&server_config.events_addr, events) {
+		events::start_ws(events_addr, events, admin_token, stop_state.clone())?;
+		info!("Serving events on {}", events_addr);
+	}
+
+	// Peers are re-read from the config before each round of pings, so reloads add or drop them
+	let peers_reloader = reloader.clone();
+	peers::start(
+		peer_health,
+		move || peers_reloader.config().peers.unwrap_or_default(),
+		server_config.http_client(),
+		stop_state.clone(),
+	);
+
+	let metrics_server = match &server_config.metrics_addr {
+		Some(metrics_addr) => {
+			let metrics_server = metrics::start_http(metrics_addr, server.clone())?;
+			info!("Serving metrics on {}", metrics_addr);
+			Some(metrics_server)
+		}
+		None => None,
+	};
+
+	let round_server = server.clone();
+	let round_stop_state = stop_state.clone();
+	let instance_span = Span::current();
 	let round_handle = spawn(move || {
-		let mut secs = 0;
+		let _instance = instance_span.entered();
+		let server = round_server;
+		let round_state = round_state;
+		let mut server_config = reloader.config();
+		let interval_blocks = |server_config: &ServerConfig| match server_config.round_interval {
+			ChainInterval::Blocks(blocks) => Some(blocks),
+			ChainInterval::Seconds(_) => None,
+		};
+
+		// Returns the seconds until the next round, when scheduled by the wall clock
+		let schedule_round = |server_config: &ServerConfig| {
+			let interval_s = server_config.round_interval.as_secs(DEFAULT_BLOCK_TIME_S);
+			let jitter_percent = server_config.round_jitter_percent.unwrap_or(0);
+			let secs_until_round = jittered_interval(interval_s, jitter_percent);
+			*next_round_at.write().unwrap() = match interval_blocks(server_config) {
+				Some(_) => None,
+				None => Some(secs_from_now(secs_until_round)),
+			};
+			// Rounds scheduled by block height get an estimate once the tip is seen
+			*next_round_due.write().unwrap() = *next_round_at.read().unwrap();
+			secs_until_round
+		};
+		let mut secs_until_round = schedule_round(&server_config);
+
+		// Each round's acceptance window closes when the round is scheduled
+		let open_window = |closes_at: Option<u64>, interval_blocks: Option<u64>| {
+			let closes_at_height = match interval_blocks {
+				Some(interval_blocks) if interval_blocks > 0 => node
+					.get_chain_height()
+					.ok()
+					.map(|height| (height / interval_blocks + 1) * interval_blocks),
+				_ => None,
+			};
+			let result = server
+				.lock()
+				.unwrap()
+				.open_window(closes_at, closes_at_height);
+			if let Err(e) = result {
+				error!("Failed to open acceptance window: {}", e);
+			}
+		};
+		open_window(
+			*next_round_at.read().unwrap(),
+			interval_blocks(&server_config),
+		);
+		let mut secs_until_execution: Option<u64> = None;
+		let mut commitment_secs = 0;
+		let mut expiry_secs = 0;
+		let mut tip_subscription = TipSubscription::new(&node);
+		let mut node_stalled = false;
+		// lowest height the tip fell back to in a reorg that hasn't been handled yet
+		let mut reorg_height: Option<u64> = None;
+		let mut report_day = report::today();
 		loop {
-			if stop_state.is_stopped() {
-				close_handle.close();
+			if round_stop_state.is_stopped() {
 				break;
 			}
 
 			sleep(Duration::from_secs(1));
-			secs = (secs + 1) % server_config.interval_s;
+			status.heartbeat();
+
+			// The instance is ready once its wallet, node and store all respond
+			if !status.is_ready() && check_readiness(&probes).0 {
+				info!("Wallet, node and store are ready");
+				status.set_ready();
+			}
+
+			// Pick up any reloaded config, rescheduling the next round if its interval changed
+			reloader.reload_if_requested();
+			let reloaded = reloader.config();
+			if reloaded.round_interval != server_config.round_interval
+				|| reloaded.round_jitter_percent != server_config.round_jitter_percent
+			{
+				secs_until_round = schedule_round(&reloaded);
+				open_window(*next_round_at.read().unwrap(), interval_blocks(&reloaded));
+			}
+			server_config = reloaded;
+
+			let tip_change = tip_subscription.poll().unwrap_or_else(|e| {
+				error!("Failed to poll chain tip: {}", e);
+				None
+			});
+
+			// Intervals configured in one unit are converted to the other at the observed block time
+			if let (Some(_), Some(block_time_s)) = (&tip_change, tip_subscription.block_time_s()) {
+				server.lock().unwrap().set_block_time(block_time_s);
+			}
+			let block_time_s = tip_subscription
+				.block_time_s()
+				.unwrap_or(DEFAULT_BLOCK_TIME_S);
+
+			// A tip lower than before means blocks were reorged out, possibly with round kernels in them.
+			// Checking the rounds is retried every second until the node answers.
+			if let Some(change) = tip_change.as_ref().filter(|c| c.is_reorg()) {
+				warn!(
+					"Chain tip moved back from {} to {}. Checking confirmed rounds.",
+					change.previous.unwrap_or(0),
+					change.height
+				);
+				reorg_height = Some(reorg_height.map_or(change.height, |h| h.min(change.height)));
+			}
+			if let Some(height) = reorg_height {
+				match server.lock().unwrap().handle_reorg(height) {
+					Ok(reverted) => {
+						if reverted > 0 {
+							warn!("Reverted {} rounds dropped by the reorg", reverted);
+						}
+						reorg_height = None;
+					}
+					Err(e) => error!("Failed to check rounds after a reorg: {}", e),
+				}
+			}
+
+			// Pause rounds if the chain tip stops advancing for too long
+			if let Some(timeout) = server_config.tip_stall_timeout {
+				let timeout_s = timeout.as_secs(block_time_s);
+				let stalled = tip_subscription.stalled_for() >= Duration::from_secs(timeout_s);
+				if stalled != node_stalled {
+					if stalled {
+						warn!(
+							"ALERT: Chain tip has not advanced in {} seconds. Pausing rounds until the node recovers.",
+							timeout_s
+						);
+					} else {
+						info!("Chain tip is advancing again. Resuming rounds.");
+					}
+					node_stalled = stalled;
+					server.lock().unwrap().set_node_stalled(stalled);
+				}
+			}
+
+			// The next round of a block height schedule is expected at the observed block time
+			if let (Some(interval_blocks), Some(change)) =
+				(interval_blocks(&server_config), &tip_change)
+			{
+				if interval_blocks > 0 {
+					let blocks_left = interval_blocks - change.height % interval_blocks;
+					*next_round_due.write().unwrap() =
+						Some(secs_from_now(blocks_left * block_time_s));
+				}
+			}
+
+			// Rounds are scheduled by block height when the round interval is counted in blocks,
+			// and by the wall clock otherwise.
+			let round_due = match interval_blocks(&server_config) {
+				Some(interval_blocks) => tip_change
+					.map(|c| c.crossed_boundary(interval_blocks))
+					.unwrap_or(false),
+				None => {
+					secs_until_round = secs_until_round.saturating_sub(1);
+					if secs_until_round == 0 {
+						secs_until_round = schedule_round(&server_config);
+						true
+					} else {
+						false
+					}
+				}
+			};
+
+			// Delay execution by a random amount, so the broadcast time is unpredictable.
+			// The leader of a coordinated round also waits for the other nodes' batches.
+			if round_due && secs_until_execution.is_none() {
+				let window_s = server_config
+					.round_delay_window
+					.map(|w| w.as_secs(block_time_s))
+					.unwrap_or(0);
+				let collection_s = match &coordinator {
+					Some(c) if c.is_leader(c.current_slot()) => leader::BATCH_COLLECTION_S,
+					_ => 0,
+				};
+				secs_until_execution = Some(random_delay(window_s) + collection_s);
+			}
 
-			if secs == 0 {
-				let _ = server.lock().unwrap().execute_round();
+			match secs_until_execution {
+				Some(0) => {
+					secs_until_execution = None;
+					// A round triggered by the operator may still be executing
+					let phase = round_state.phase();
+					if phase != RoundPhase::Idle {
+						warn!("Round still executing ({:?}). Skipping this one.", phase);
+					} else {
+						admission.set_round_in_progress(true);
+						if let Err(e) = server.lock().unwrap().execute_round() {
+							error!("Round failed: {}", e);
+						}
+						admission.set_round_in_progress(false);
+					}
+					open_window(
+						*next_round_at.read().unwrap(),
+						interval_blocks(&server_config),
+					);
+				}
+				Some(secs) => secs_until_execution = Some(secs - 1),
+				None => {}
+			}
+
+			// A round the node didn't accept the transaction of is posted again once its backoff
+			// has passed, with the server released while it's waited out
+			if round_state.phase() == RoundPhase::Posting {
+				if let Err(e) = server.lock().unwrap().retry_round(POST_TX_RETRY_DELAY) {
+					error!("Round failed: {}", e);
+				}
+			}
+
+			expiry_secs = (expiry_secs + 1) % EXPIRY_CHECK_INTERVAL_S;
+			if expiry_secs == 0 {
+				match server.lock().unwrap().expire_swaps() {
+					Ok(0) => {}
+					Ok(expired) => info!("Deleted {} expired swaps", expired),
+					Err(e) => error!("Failed to delete expired swaps: {}", e),
+				}
+				if let Err(e) = server.lock().unwrap().check_confirmations() {
+					error!("Failed to check round confirmations: {}", e);
+				}
+			}
+
+			if let Some(interval) = server_config.pending_commitment_interval {
+				commitment_secs = (commitment_secs + 1) % interval.as_secs(block_time_s).max(1);
+				if commitment_secs == 0 {
+					if let Err(e) = server.lock().unwrap().commit_pending() {
+						error!("Failed to commit to pending swaps: {}", e);
+					}
+				}
+			}
+
+			// Write a report summarizing the previous day, once it's over
+			if let Some(report_dir) = &server_config.report_dir {
+				let today = report::today();
+				if today != report_day {
+					let result = server.lock().unwrap().daily_report(report_day);
+					match result {
+						Ok(daily_report) => match daily_report.write(Path::new(report_dir)) {
+							Ok(()) => info!("Wrote daily report for {}", daily_report.date),
+							Err(e) => error!("Failed to write daily report: {}", e),
+						},
+						Err(e) => error!("Failed to build daily report: {}", e),
+					}
+					report_day = today;
+				}
 			}
 		}
 	});
 
-	http_server.wait();
+	while !stop_state.is_stopped() {
+		sleep(Duration::from_millis(100));
+	}
+
+	// Stop accepting new swaps and admin commands
+	info!("Shutting down. No longer accepting swaps.");
+	http_server.close();
+	if let Some(admin_server) = admin_server {
+		admin_server.close();
+	}
+	if let Some(admin_http_server) = admin_http_server {
+		admin_http_server.close();
+	}
+	if let Some(replication_server) = replication_server {
+		replication_server.close();
+	}
+	if let Some(metrics_server) = metrics_server {
+		metrics_server.close();
+	}
+
+	// Give any in-progress round a chance to finish, so its swaps aren't left half-updated
+	let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+	while !round_handle.is_finished() && Instant::now() < deadline {
+		sleep(Duration::from_millis(100));
+	}
+	if !round_handle.is_finished() {
+		warn!(
+			"Round still in progress after {} seconds. Exiting anyway.",
+			SHUTDOWN_TIMEOUT.as_secs()
+		);
+		return Ok(());
+	}
 	round_handle.join().unwrap();
 
+	// Every store write is committed in its own transaction,
+	// so once the round is done there is nothing left to flush.
+	info!("Shutdown complete.");
+
 	Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-	use crate::config::ServerConfig;
-	use crate::onion::test_util;
-	use crate::rpc::{RPCServer, SwapReq};
-	use crate::secp::{self, ComSignature};
+	use crate::admission::AdmissionQueue;
+	use crate::api_keys::ApiKeys;
+	use crate::compress;
+	use crate::config::{self, ApiKey, ListenAddr, ServerConfig};
+	use crate::node::mock::MockGrinNode;
+	use crate::onion::{test_util, MAX_HOPS};
+	use crate::peer_auth::PeerAuthenticator;
+	use crate::rpc::{
+		check_readiness, jittered_interval, random_delay, readiness_response, secs_from_now,
+		CancelReq, NodeProbe, RPCServer, ReadinessProbe, RequestMeta, StoreProbe, SwapReq, API,
+		MAX_REQUEST_BODY_SIZE, MAX_WAITING_SUBMISSIONS, NEXT_ROUND_HEADER,
+	};
+	use crate::secp::{self, ComSignature, SigDomain};
 	use crate::server::mock::MockServer;
-	use crate::server::{Server, SwapError};
+	use crate::server::{cancel_message, Server, SwapError};
+	use crate::store::test_util::TestStore;
 
-	use std::net::TcpListener;
-	use std::sync::{Arc, Mutex};
+	use grin_util::{StopState, ToHex};
+	use hyper::StatusCode;
+	use jsonrpc_core::ErrorCode;
+	use serde_json::json;
+	use std::net::{IpAddr, SocketAddr, TcpListener};
+	use std::sync::{Arc, Mutex, RwLock};
 
 	use hyper::{Body, Client, Request, Response};
 	use tokio::runtime::Runtime;
 
+	const EXPIRY_HEIGHT: u64 = 200;
+
 	async fn body_to_string(req: Response<Body>) -> String {
 		let body_bytes = hyper::body::to_bytes(req.into_body()).await.unwrap();
 		String::from_utf8(body_bytes.to_vec()).unwrap()
 	}
 
+	/// An RPC server for 'server', configured with 'server_config', as 'listen' would build it
+	fn rpc_server(
+		server_config: ServerConfig,
+		server: Arc<Mutex<dyn Server>>,
+	) -> Result<RPCServer, Box<dyn std::error::Error>> {
+		let api_keys = ApiKeys::new(server_config.api_keys.clone().unwrap_or_default())?;
+		Ok(RPCServer {
+			server_config,
+			server,
+			stop_state: Arc::new(StopState::new()),
+			next_round_at: Arc::new(RwLock::new(None)),
+			next_round_due: Arc::new(RwLock::new(None)),
+			admission: Arc::new(AdmissionQueue::new(MAX_WAITING_SUBMISSIONS)),
+			probes: Arc::new(vec![]),
+			peer_auth: Arc::new(PeerAuthenticator::new(vec![])),
+			coordinator: None,
+			api_keys: Arc::new(api_keys),
+		})
+	}
+
 	/// Spin up a temporary web service, query the API, then cleanup and return response
 	fn make_request(
 		server: Arc<Mutex<dyn Server>>,
 		req: String,
 	) -> Result<String, Box<dyn std::error::Error>> {
-		let server_config = ServerConfig {
-			key: secp::random_secret(),
-			interval_s: 1,
-			addr: TcpListener::bind("127.0.0.1:0")?.local_addr()?,
-			grin_node_url: "127.0.0.1:3413".parse()?,
-			grin_node_secret_path: None,
-			wallet_owner_url: "127.0.0.1:3420".parse()?,
-			wallet_owner_secret_path: None,
-		};
+		let (_, response) = make_encoded_request(server, req.into_bytes(), None)?;
+		Ok(response)
+	}
 
-		let rpc_server = RPCServer {
-			server_config: server_config.clone(),
-			server: server.clone(),
-		};
+	/// Same as 'make_request', but sends the body with the given Content-Encoding,
+	/// and returns the response's status along with its body
+	fn make_encoded_request(
+		server: Arc<Mutex<dyn Server>>,
+		body: Vec<u8>,
+		content_encoding: Option<&'static str>,
+	) -> Result<(StatusCode, String), Box<dyn std::error::Error>> {
+		let mut server_config = config::test_util::test_config();
+		server_config.addr = TcpListener::bind("127.0.0.1:0")?.local_addr()?.into();
+		let rpc_server = rpc_server(server_config.clone(), server.clone())?;
 
 		// Start the JSON-RPC server
-		let http_server = rpc_server.start_http();
+		let http_server = rpc_server.start_http()?;
 
 		let uri = format!("http://{}/v1", server_config.addr);
 
 		let threaded_rt = Runtime::new()?;
 		let do_request = async move {
-			let request = Request::post(uri)
-				.header("Content-Type", "application/json")
-				.body(Body::from(req))
-				.unwrap();
+			let mut request = Request::post(uri).header("Content-Type", "application/json");
+			if let Some(encoding) = content_encoding {
+				request = request.header("Content-Encoding", encoding);
+			}
 
-			Client::new().request(request).await
+			Client::new()
+				.request(request.body(Body::from(body)).unwrap())
+				.await
 		};
 
 		let response = threaded_rt.block_on(do_request)?;
+		let status = response.status();
 		let response_str: String = threaded_rt.block_on(body_to_string(response));
 
 		// Wait for shutdown
@@ -193,20 +1430,117 @@ mod tests {
 		// Stop the server
 		http_server.close();
 
-		Ok(response_str)
+		Ok((status, response_str))
 	}
 
 	// todo: Test all error types
 
+	struct UnreachableProbe;
+
+	impl ReadinessProbe for UnreachableProbe {
+		fn name(&self) -> &'static str {
+			"unreachable"
+		}
+
+		fn check(&self) -> std::result::Result<(), String> {
+			Err("connection refused".to_string())
+		}
+	}
+
+	/// The server is only ready once every probe passes
+	#[test]
+	fn readiness() -> Result<(), Box<dyn std::error::Error>> {
+		let mut store = TestStore::new("readiness");
+		let mut probes: Vec<Box<dyn ReadinessProbe>> = vec![
+			Box::new(NodeProbe(Arc::new(MockGrinNode::new()))),
+			Box::new(StoreProbe(Arc::new(Mutex::new(store.take())))),
+		];
+		let (ready, checks) = check_readiness(&probes);
+		assert!(ready);
+		assert_eq!(Some(&json!("ok")), checks.get("node"));
+		assert_eq!(Some(&json!("ok")), checks.get("store"));
+		assert_eq!(StatusCode::OK, readiness_response(&probes).code);
+
+		probes.push(Box::new(UnreachableProbe));
+		let (ready, checks) = check_readiness(&probes);
+		assert!(!ready);
+		assert_eq!(
+			Some(&json!("connection refused")),
+			checks.get("unreachable")
+		);
+		assert_eq!(
+			StatusCode::SERVICE_UNAVAILABLE,
+			readiness_response(&probes).code
+		);
+
+		Ok(())
+	}
+
+	/// Jittered intervals and delays stay within their configured bounds
+	#[test]
+	fn round_jitter() {
+		assert_eq!(60, jittered_interval(60, 0));
+		assert_eq!(1, jittered_interval(0, 10));
+
+		for _ in 0..100 {
+			let interval = jittered_interval(60, 10);
+			assert!(interval >= 54 && interval <= 66);
+
+			let interval = jittered_interval(60, 255);
+			assert!(interval >= 1 && interval <= 120);
+
+			assert!(random_delay(30) <= 30);
+		}
+		assert_eq!(0, random_delay(0));
+	}
+
+	/// zstd-compressed requests are decompressed before they're handled, and other encodings
+	/// are refused
+	#[test]
+	fn swap_compressed() -> Result<(), Box<dyn std::error::Error>> {
+		let onion = test_util::rand_onion();
+		let domain = SigDomain::new(&secp::test_util::rand_pubkey(), EXPIRY_HEIGHT);
+		let comsig =
+			ComSignature::sign(1234, &secp::random_secret(), &domain, &onion.serialize()?)?;
+		let swap = SwapReq {
+			onion,
+			comsig,
+			expiry_height: EXPIRY_HEIGHT,
+		};
+		let req = json!({"jsonrpc": "2.0", "method": "swap", "params": [swap], "id": "1"});
+		let compressed = compress::compress(req.to_string().as_bytes())?;
+
+		let server: Arc<Mutex<dyn Server>> = Arc::new(Mutex::new(MockServer::new()));
+		let (status, response) = make_encoded_request(server, compressed.clone(), Some("zstd"))?;
+		assert_eq!(StatusCode::OK, status);
+		assert_eq!(
+			"{\"jsonrpc\":\"2.0\",\"result\":\"success\",\"id\":\"1\"}\n",
+			response
+		);
+
+		let server: Arc<Mutex<dyn Server>> = Arc::new(Mutex::new(MockServer::new()));
+		let (status, _) = make_encoded_request(server, compressed, Some("br"))?;
+		assert_eq!(StatusCode::UNSUPPORTED_MEDIA_TYPE, status);
+
+		// A small body can't expand past the size limit
+		let bomb = compress::compress(&vec![b' '; MAX_REQUEST_BODY_SIZE + 1])?;
+		let server: Arc<Mutex<dyn Server>> = Arc::new(Mutex::new(MockServer::new()));
+		let (_, response) = make_encoded_request(server, bomb, Some("zstd"))?;
+		assert!(!response.contains("\"result\""));
+		Ok(())
+	}
+
 	/// Demonstrates a successful swap response
 	#[test]
 	fn swap_success() -> Result<(), Box<dyn std::error::Error>> {
-		let commitment = secp::commit(1234, &secp::random_secret())?;
-		let onion = test_util::create_onion(&commitment, &vec![])?;
-		let comsig = ComSignature::sign(1234, &secp::random_secret(), &onion.serialize()?)?;
+		let onion = test_util::rand_onion();
+		let domain = SigDomain::new(&secp::test_util::rand_pubkey(), EXPIRY_HEIGHT);
+		let comsig =
+			ComSignature::sign(1234, &secp::random_secret(), &domain, &onion.serialize()?)?;
 		let swap = SwapReq {
 			onion: onion.clone(),
 			comsig,
+			expiry_height: EXPIRY_HEIGHT,
 		};
 
 		let server: Arc<Mutex<dyn Server>> = Arc::new(Mutex::new(MockServer::new()));
@@ -223,6 +1557,235 @@ mod tests {
 		Ok(())
 	}
 
+	/// Swaps are rejected once shutdown has begun
+	#[test]
+	fn swap_shutting_down() -> Result<(), Box<dyn std::error::Error>> {
+		let commitment = secp::commit(1234, &secp::random_secret())?;
+		let onion = test_util::create_onion(&commitment, &vec![])?;
+		let domain = SigDomain::new(&secp::test_util::rand_pubkey(), EXPIRY_HEIGHT);
+		let comsig =
+			ComSignature::sign(1234, &secp::random_secret(), &domain, &onion.serialize()?)?;
+
+		let stop_state = Arc::new(StopState::new());
+		let rpc_server = RPCServer {
+			stop_state: stop_state.clone(),
+			..rpc_server(
+				config::test_util::test_config(),
+				Arc::new(Mutex::new(MockServer::new())),
+			)?
+		};
+
+		let swap = SwapReq {
+			onion: onion.clone(),
+			comsig: comsig.clone(),
+			expiry_height: EXPIRY_HEIGHT,
+		};
+		assert!(rpc_server.swap(RequestMeta::default(), swap).is_ok());
+
+		stop_state.stop();
+		let swap = SwapReq {
+			onion,
+			comsig,
+			expiry_height: EXPIRY_HEIGHT,
+		};
+		assert!(rpc_server.swap(RequestMeta::default(), swap).is_err());
+
+		Ok(())
+	}
+
+	/// Swaps presented with an API key must use a configured key, within its limit
+	#[test]
+	fn swap_api_keys() -> Result<(), Box<dyn std::error::Error>> {
+		let commitment = secp::commit(1234, &secp::random_secret())?;
+		let onion = test_util::create_onion(&commitment, &vec![])?;
+		let domain = SigDomain::new(&secp::test_util::rand_pubkey(), EXPIRY_HEIGHT);
+		let comsig =
+			ComSignature::sign(1234, &secp::random_secret(), &domain, &onion.serialize()?)?;
+
+		let mut server_config = config::test_util::test_config();
+		server_config.api_keys = Some(vec![ApiKey {
+			name: "partner".to_string(),
+			key: "secret".to_string(),
+			swaps_per_minute: Some(1),
+		}]);
+		let rpc_server = rpc_server(server_config, Arc::new(Mutex::new(MockServer::new())))?;
+
+		let swap = || SwapReq {
+			onion: onion.clone(),
+			comsig: comsig.clone(),
+			expiry_height: EXPIRY_HEIGHT,
+		};
+		let meta = |key: &str| {
+			let mut headers = hyper::HeaderMap::new();
+			headers.insert("X-Api-Key", key.parse().unwrap());
+			RequestMeta::from_headers(&headers, None, &[])
+		};
+
+		let err = rpc_server.swap(meta("wrong"), swap()).unwrap_err();
+		assert_eq!(ErrorCode::ServerError(-32003), err.code);
+		assert!(rpc_server.swap(meta("secret"), swap()).is_ok());
+		let err = rpc_server.swap(meta("secret"), swap()).unwrap_err();
+		assert_eq!(ErrorCode::ServerError(-32001), err.code);
+
+		// Keys are optional
+		assert!(rpc_server.swap(RequestMeta::default(), swap()).is_ok());
+
+		Ok(())
+	}
+
+	/// The client's address is the peer the request arrived from, unless the peer is a trusted
+	/// reverse proxy, whose headers say where the request came from
+	#[test]
+	fn request_source() {
+		let proxy: SocketAddr = "10.0.0.1:41000".parse().unwrap();
+		let client: SocketAddr = "192.0.2.9:52000".parse().unwrap();
+		let trusted: Vec<IpAddr> = vec![proxy.ip()];
+
+		let mut headers = hyper::HeaderMap::new();
+		assert_eq!(
+			None,
+			RequestMeta::from_headers(&headers, None, &trusted).source
+		);
+		assert_eq!(
+			Some("10.0.0.1".to_string()),
+			RequestMeta::from_headers(&headers, Some(proxy), &trusted).source
+		);
+
+		headers.insert("X-Real-IP", "198.51.100.2".parse().unwrap());
+		assert_eq!(
+			Some("198.51.100.2".to_string()),
+			RequestMeta::from_headers(&headers, Some(proxy), &trusted).source
+		);
+
+		// The last address not added by a trusted proxy is the client's
+		headers.insert(
+			"X-Forwarded-For",
+			"203.0.113.7, 198.51.100.2".parse().unwrap(),
+		);
+		assert_eq!(
+			Some("198.51.100.2".to_string()),
+			RequestMeta::from_headers(&headers, Some(proxy), &trusted).source
+		);
+		let chained: Vec<IpAddr> = vec![proxy.ip(), "198.51.100.2".parse().unwrap()];
+		assert_eq!(
+			Some("203.0.113.7".to_string()),
+			RequestMeta::from_headers(&headers, Some(proxy), &chained).source
+		);
+
+		// Anyone else's headers are ignored
+		assert_eq!(
+			Some("192.0.2.9".to_string()),
+			RequestMeta::from_headers(&headers, Some(client), &trusted).source
+		);
+		assert_eq!(
+			None,
+			RequestMeta::from_headers(&headers, None, &trusted).source
+		);
+
+		assert_eq!(
+			None,
+			RequestMeta::from_headers(&headers, Some(client), &trusted).api_key
+		);
+		headers.insert("X-Api-Key", "secret".parse().unwrap());
+		assert_eq!(
+			Some("secret".to_string()),
+			RequestMeta::from_headers(&headers, Some(client), &trusted).api_key
+		);
+	}
+
+	/// Info includes the next round time tracked by the scheduler
+	#[test]
+	fn get_info() -> Result<(), Box<dyn std::error::Error>> {
+		let rpc_server = rpc_server(
+			config::test_util::test_config(),
+			Arc::new(Mutex::new(MockServer::new())),
+		)?;
+		assert_eq!(None, rpc_server.get_info()?.next_round_at);
+		assert_eq!(None, rpc_server.get_info()?.next_round_in_s);
+
+		*rpc_server.next_round_at.write().unwrap() = Some(1_700_000_000);
+		let info = rpc_server.get_info()?;
+		assert_eq!(Some(1_700_000_000), info.next_round_at);
+		assert_eq!(Some(1), info.interval_s);
+
+		// A round that's already due counts down to zero
+		*rpc_server.next_round_due.write().unwrap() = Some(1_700_000_000);
+		assert_eq!(Some(0), rpc_server.get_info()?.next_round_in_s);
+		*rpc_server.next_round_due.write().unwrap() = Some(secs_from_now(600));
+		let next_round_in_s = rpc_server.get_info()?.next_round_in_s.unwrap();
+		assert!(next_round_in_s <= 600 && next_round_in_s >= 599);
+
+		Ok(())
+	}
+
+	/// API responses, such as to swap submissions, carry the countdown to the next round once
+	/// it's known
+	#[test]
+	fn next_round_header() -> Result<(), Box<dyn std::error::Error>> {
+		let mut server_config = config::test_util::test_config();
+		server_config.addr = TcpListener::bind("127.0.0.1:0")?.local_addr()?.into();
+		let rpc_server = rpc_server(
+			server_config.clone(),
+			Arc::new(Mutex::new(MockServer::new())),
+		)?;
+		let http_server = rpc_server.start_http()?;
+
+		let uri = format!("http://{}/v1", server_config.addr);
+		let runtime = Runtime::new()?;
+		let countdown = |content_type: &'static str| {
+			let request = Request::post(uri.clone())
+				.header("Content-Type", content_type)
+				.body(Body::from(
+					json!({"jsonrpc": "2.0", "method": "get_info", "params": [], "id": "1"})
+						.to_string(),
+				))
+				.unwrap();
+			let response = runtime.block_on(Client::new().request(request)).unwrap();
+			let countdown = response
+				.headers()
+				.get(NEXT_ROUND_HEADER)
+				.map(|h| h.to_str().unwrap().parse::<u64>().unwrap());
+			(response.status(), countdown)
+		};
+
+		assert_eq!((StatusCode::OK, None), countdown("application/json"));
+		*rpc_server.next_round_due.write().unwrap() = Some(secs_from_now(600));
+		let (status, secs) = countdown("application/json");
+		assert_eq!(StatusCode::OK, status);
+		assert!(secs.unwrap() <= 600 && secs.unwrap() >= 599);
+		assert_eq!(
+			StatusCode::UNSUPPORTED_MEDIA_TYPE,
+			countdown("text/plain").0
+		);
+
+		runtime.shutdown_background();
+		http_server.close();
+		Ok(())
+	}
+
+	/// The API can be served on a unix socket, and reached there with the client library
+	#[cfg(unix)]
+	#[test]
+	fn unix_socket() -> Result<(), Box<dyn std::error::Error>> {
+		let dir = std::path::PathBuf::from("./target/tmp/.rpc_unix_socket");
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir)?;
+		let socket_path = dir.join("mwixnet.sock");
+
+		let mut server_config = config::test_util::test_config();
+		server_config.addr = ListenAddr::Unix(socket_path.clone());
+		let rpc_server = rpc_server(server_config, Arc::new(Mutex::new(MockServer::new())))?;
+		let http_server = rpc_server.start_http()?;
+
+		let url = rpc_server.server_config.advertised_url();
+		let info = mwixnet_client::client::SwapClient::new(&url)?.get_info()?;
+		assert_eq!(Some(1), info["interval_s"].as_u64());
+
+		http_server.close();
+		assert!(!socket_path.exists());
+		Ok(())
+	}
+
 	#[test]
 	fn swap_bad_request() -> Result<(), Box<dyn std::error::Error>> {
 		let server: Arc<Mutex<dyn Server>> = Arc::new(Mutex::new(MockServer::new()));
@@ -238,15 +1801,128 @@ mod tests {
 		Ok(())
 	}
 
+	/// Onions with more payloads than any route could need are rejected as invalid params,
+	/// and bodies over the size limit are never parsed.
+	#[test]
+	fn swap_too_large() -> Result<(), Box<dyn std::error::Error>> {
+		let commitment = secp::commit(1234, &secp::random_secret())?;
+		let mut onion = test_util::create_onion(&commitment, &vec![])?;
+		onion.enc_payloads = vec![vec![0u8; 32]; MAX_HOPS + 1];
+		let domain = SigDomain::new(&secp::test_util::rand_pubkey(), EXPIRY_HEIGHT);
+		let comsig =
+			ComSignature::sign(1234, &secp::random_secret(), &domain, &onion.serialize()?)?;
+		let swap = SwapReq {
+			onion,
+			comsig,
+			expiry_height: EXPIRY_HEIGHT,
+		};
+		let req = format!(
+			"{{\"jsonrpc\": \"2.0\", \"method\": \"swap\", \"params\": [{}], \"id\": \"1\"}}",
+			serde_json::json!(swap)
+		);
+		let server: Arc<Mutex<dyn Server>> = Arc::new(Mutex::new(MockServer::new()));
+		let response = make_request(server, req)?;
+		assert!(response.contains("-32602"));
+		assert!(response.contains("more than the maximum"));
+
+		let req = format!(
+			"{{\"jsonrpc\": \"2.0\", \"method\": \"swap\", \"params\": [\"{}\"], \"id\": \"1\"}}",
+			"0".repeat(MAX_REQUEST_BODY_SIZE)
+		);
+		let server: Arc<Mutex<dyn Server>> = Arc::new(Mutex::new(MockServer::new()));
+		let response = make_request(server, req)?;
+		assert!(!response.contains("\"result\""));
+		Ok(())
+	}
+
+	/// Onions without any hops are rejected before the server is involved
+	#[test]
+	fn swap_no_hops() -> Result<(), Box<dyn std::error::Error>> {
+		let commitment = secp::commit(1234, &secp::random_secret())?;
+		let onion = test_util::create_onion(&commitment, &vec![])?;
+		let domain = SigDomain::new(&secp::test_util::rand_pubkey(), EXPIRY_HEIGHT);
+		let comsig =
+			ComSignature::sign(1234, &secp::random_secret(), &domain, &onion.serialize()?)?;
+		let swap = SwapReq {
+			onion,
+			comsig,
+			expiry_height: EXPIRY_HEIGHT,
+		};
+		let req = format!(
+			"{{\"jsonrpc\": \"2.0\", \"method\": \"swap\", \"params\": [{}], \"id\": \"1\"}}",
+			serde_json::json!(swap)
+		);
+		let server: Arc<Mutex<dyn Server>> = Arc::new(Mutex::new(MockServer::new()));
+		let response = make_request(server, req)?;
+		let expected = "{\"jsonrpc\":\"2.0\",\"error\":{\"code\":-32602,\"message\":\"Onion must have at least one hop\"},\"id\":\"1\"}\n";
+		assert_eq!(response, expected);
+		Ok(())
+	}
+
+	/// Fees are estimated for routes of up to 'MAX_HOPS' hops
+	#[test]
+	fn estimate_fee() -> Result<(), Box<dyn std::error::Error>> {
+		let req =
+			"{\"jsonrpc\": \"2.0\", \"method\": \"estimate_fee\", \"params\": [3], \"id\": \"1\"}";
+		let server: Arc<Mutex<dyn Server>> = Arc::new(Mutex::new(MockServer::new()));
+		let response = make_request(server, req.to_string())?;
+		let expected = "{\"jsonrpc\":\"2.0\",\"result\":{\"fee_base\":1,\"hop_fee\":1,\"hops\":3,\"total_fee\":3},\"id\":\"1\"}\n";
+		assert_eq!(response, expected);
+
+		let req = format!(
+			"{{\"jsonrpc\": \"2.0\", \"method\": \"estimate_fee\", \"params\": [{}], \"id\": \"1\"}}",
+			MAX_HOPS + 1
+		);
+		let server: Arc<Mutex<dyn Server>> = Arc::new(Mutex::new(MockServer::new()));
+		let response = make_request(server, req)?;
+		assert!(response.contains("-32602"));
+		Ok(())
+	}
+
+	/// Cancelling a swap the server doesn't have is rejected
+	#[test]
+	fn cancel_swap_not_found() -> Result<(), Box<dyn std::error::Error>> {
+		let commitment = secp::commit(1234, &secp::random_secret())?;
+		let domain = SigDomain::new(&secp::test_util::rand_pubkey(), EXPIRY_HEIGHT);
+		let msg = cancel_message(&commitment, &secp::test_util::rand_commit());
+		let comsig = ComSignature::sign(1234, &secp::random_secret(), &domain, &msg)?;
+		let cancel = CancelReq {
+			commit: commitment.to_hex(),
+			comsig,
+			expiry_height: EXPIRY_HEIGHT,
+		};
+
+		let server: Arc<Mutex<dyn Server>> = Arc::new(Mutex::new(MockServer::new()));
+		let req = format!(
+			"{{\"jsonrpc\": \"2.0\", \"method\": \"cancel_swap\", \"params\": [{}], \"id\": \"1\"}}",
+			serde_json::json!(cancel)
+		);
+		let response: serde_json::Value = serde_json::from_str(&make_request(server, req)?)?;
+		let expected = json!({
+			"jsonrpc": "2.0",
+			"error": {
+				"code": -32030,
+				"message": format!("Swap for output {:?} not found.", commitment),
+				"data": { "name": "swap_not_found", "commit": commitment.to_hex() },
+			},
+			"id": "1",
+		});
+		assert_eq!(response, expected);
+		Ok(())
+	}
+
 	/// Returns "Commitment not found" when there's no matching output in the UTXO set.
 	#[test]
 	fn swap_utxo_missing() -> Result<(), Box<dyn std::error::Error>> {
 		let commitment = secp::commit(1234, &secp::random_secret())?;
-		let onion = test_util::create_onion(&commitment, &vec![])?;
-		let comsig = ComSignature::sign(1234, &secp::random_secret(), &onion.serialize()?)?;
+		let onion = test_util::rand_onion();
+		let domain = SigDomain::new(&secp::test_util::rand_pubkey(), EXPIRY_HEIGHT);
+		let comsig =
+			ComSignature::sign(1234, &secp::random_secret(), &domain, &onion.serialize()?)?;
 		let swap = SwapReq {
 			onion: onion.clone(),
 			comsig,
+			expiry_height: EXPIRY_HEIGHT,
 		};
 
 		let mut server = MockServer::new();
@@ -262,11 +1938,16 @@ mod tests {
 			"{{\"jsonrpc\": \"2.0\", \"method\": \"swap\", \"params\": [{}], \"id\": \"1\"}}",
 			serde_json::json!(swap)
 		);
-		let response = make_request(server, req)?;
-		let expected = format!(
-			"{{\"jsonrpc\":\"2.0\",\"error\":{{\"code\":-32602,\"message\":\"Output {:?} does not exist, or is already spent.\"}},\"id\":\"1\"}}\n",
-			commitment
-		);
+		let response: serde_json::Value = serde_json::from_str(&make_request(server, req)?)?;
+		let expected = json!({
+			"jsonrpc": "2.0",
+			"error": {
+				"code": -32020,
+				"message": format!("Output {:?} does not exist, or is already spent.", commitment),
+				"data": { "name": "coin_not_found", "commit": commitment.to_hex() },
+			},
+			"id": "1",
+		});
 		assert_eq!(response, expected);
 		Ok(())
 	}
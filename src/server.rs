@@ -1,17 +1,86 @@
-use crate::config::ServerConfig;
-use crate::node::{self, GrinNode};
-use crate::onion::{Onion, OnionError};
-use crate::secp::{ComSignature, Commitment, Secp256k1, SecretKey};
-use crate::store::{StoreError, SwapData, SwapStatus, SwapStore};
-use crate::wallet::{self, Wallet};
-
-use grin_core::core::hash::Hashed;
-use grin_core::core::{Input, Output, OutputFeatures, Transaction, TransactionBody};
+use crate::compress;
+use crate::config::{PreviousKey, RoundSelection, ServerConfig};
+use crate::error_codes::{self, ErrorCodeInfo};
+use crate::events::{Event, EventBus};
+use crate::features::{Feature, FeatureFlags};
+use crate::interval::{ChainInterval, DEFAULT_BLOCK_TIME_S};
+use crate::leader::Coordinator;
+use crate::merkle::{self, MerkleHash, MerkleProofJson, MerkleTree};
+use crate::metrics::PreflightCounts;
+use crate::node::{self, GrinNode, NodeError};
+use crate::onion::{CipherSuite, Onion, OnionError, MAX_HOPS};
+use crate::peers::{PeerHealth, PeerStatus};
+use crate::policy::SwapPolicy;
+use crate::replication::{
+	ReplicationAck, ReplicationError, ReplicationMessage, Replicator, Standby,
+};
+use crate::report::{ActivityLog, DailyReport};
+use crate::secp::{
+	self, ComSignature, Commitment, Message, PublicKey, RangeProof, Secp256k1, SecretKey,
+	SigDomain, Signature,
+};
+use crate::store::{
+	ApiKeySwaps, DecoyData, FeeOutputData, PendingTxData, ReceiptData, RejectionData, RoundData,
+	RoundDecoys, RoundMarker, StoreError, SwapData, SwapStatus, SwapStore,
+};
+use crate::types::{FeeChange, Payload};
+use crate::wallet::{self, PreflightFailure, Wallet, WalletOutput};
+pub use mwixnet_client::api::{cancel_message, check_message, receipt_message};
+
+use blake2::blake2b::Blake2b;
+use byteorder::{BigEndian, ByteOrder};
+use grin_core::consensus::DAY_HEIGHT;
+use grin_core::core::committed::Committed;
+use grin_core::core::hash::{Hash, Hashed};
+use grin_core::core::{
+	transaction, Input, Output, OutputFeatures, Transaction, TransactionBody, TxKernel,
+};
 use grin_core::global::DEFAULT_ACCEPT_FEE_BASE;
+use grin_core::libtx::secp_ser;
+use grin_util::ToHex;
 use itertools::Itertools;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
 use std::result::Result;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use thiserror::Error;
+use tracing::{debug, error, info, info_span, warn};
+
+/// Furthest a swap's expiry height may be beyond the current chain height
+pub const MAX_EXPIRY_BLOCKS: u64 = DAY_HEIGHT;
+
+/// Blocks an accepted swap is kept waiting for a round when 'swap_ttl' isn't configured
+pub const DEFAULT_SWAP_TTL_BLOCKS: u64 = DAY_HEIGHT;
+
+/// Rounds confirmed this many blocks below the tip are treated as final. Until then, their
+/// transactions are kept, to be posted again if a reorg drops them from the chain.
+const REORG_HORIZON_BLOCKS: u64 = DAY_HEIGHT;
+
+/// Number of most recent rounds the published fee statistics cover
+pub const FEE_STATS_ROUNDS: usize = 10;
+
+/// Significant digits published fees are rounded down to, so they can't be matched to a swap
+const FEE_BUCKET_DIGITS: u32 = 2;
+
+/// Rangeproofs that may wait for a verification worker before new ones are turned away
+const VERIFIER_QUEUE_SIZE: usize = 64;
+
+/// Verification results kept for swaps that have yet to be submitted to the server
+const VERIFIED_CACHE_SIZE: usize = 1024;
+
+/// Rejected swaps kept in the rejection log when 'rejection_log_size' isn't configured
+pub const DEFAULT_REJECTION_LOG_SIZE: u64 = 1000;
+
+/// Times a round's transaction is posted before the round is abandoned
+const POST_TX_ATTEMPTS: u32 = 4;
+
+/// Wait before the round scheduler posts a round's transaction again, doubled after each
+/// failed attempt
+pub const POST_TX_RETRY_DELAY: Duration = Duration::from_secs(2);
 
 /// Swap error types
 #[derive(Clone, Error, Debug, PartialEq)]
@@ -20,351 +89,5727 @@ pub enum SwapError {
 	InvalidPayloadLength { expected: usize, found: usize },
 	#[error("Commitment Signature is invalid")]
 	InvalidComSignature,
+	#[error("Expiry height {expiry_height:?} must be after the current height {height:?}, and no more than {MAX_EXPIRY_BLOCKS} blocks beyond it")]
+	InvalidExpiry { expiry_height: u64, height: u64 },
 	#[error("Rangeproof is invalid")]
 	InvalidRangeproof,
 	#[error("Rangeproof is required but was not supplied")]
 	MissingRangeproof,
+	#[error("Output must be proven to be worth at least {min_output_value} nanogrin")]
+	OutputTooSmall { min_output_value: u64 },
+	#[error("Owner kernel must be a plain kernel with no fee and a valid signature")]
+	InvalidOwnerKernel,
+	#[error("Invalid fee change: {0}")]
+	InvalidFeeChange(String),
 	#[error("Output {commit:?} does not exist, or is already spent.")]
 	CoinNotFound { commit: Commitment },
+	#[error("Output {commit:?} is unconfirmed, and the server already holds the most swaps of unconfirmed outputs it allows ({limit:?}). Retry once it confirms.")]
+	UnconfirmedLimitReached { commit: Commitment, limit: u32 },
+	#[error("Output {commit:?} is a coinbase output that can't be spent until height {spendable_height:?}.")]
+	CoinNotMature {
+		commit: Commitment,
+		spendable_height: u64,
+	},
+	#[error("Output {commit:?} has {confirmations} confirmations, but swaps need inputs with at least {required}. Retry once it has more.")]
+	InsufficientConfirmations {
+		commit: Commitment,
+		confirmations: u64,
+		required: u64,
+	},
 	#[error("Output {commit:?} is already in the swap list.")]
 	AlreadySwapped { commit: Commitment },
+	#[error("Output {commit:?} is already created by a swap from another input.")]
+	DuplicateOutput { commit: Commitment },
 	#[error("Failed to peel onion layer: {0:?}")]
 	PeelOnionFailure(OnionError),
+	#[error("Commitment {commit:?} is refused by the server's policy: {reason}")]
+	PolicyRejected { commit: Commitment, reason: String },
+	#[error("Onions encrypted with {cipher_suite:?} aren't accepted (accepted: {accepted:?})")]
+	UnsupportedCipherSuite {
+		cipher_suite: CipherSuite,
+		accepted: Vec<CipherSuite>,
+	},
 	#[error("Fee too low (expected >= {minimum_fee:?}, actual {actual_fee:?})")]
 	FeeTooLow { minimum_fee: u64, actual_fee: u64 },
 	#[error("Error saving swap to data store: {0}")]
 	StoreError(StoreError),
+	#[error("Swap for output {commit:?} not found.")]
+	SwapNotFound { commit: Commitment },
+	#[error("No receipt for output {commit:?}. Receipts are issued once the swap is included in a round.")]
+	ReceiptNotFound { commit: Commitment },
+	#[error("Swap for output {commit:?} can't be cancelled, since it's already been included in a round.")]
+	SwapNotCancellable { commit: Commitment },
+	#[error("Chain tip is not advancing. Rounds are paused until the node recovers.")]
+	NodeStalled,
+	#[error("Server is not the active primary: {0}")]
+	NotPrimary(String),
+	#[error("Acceptance window for round {round_id:?} has closed. Resubmit once the next round's window opens.")]
+	WindowClosed { round_id: u64 },
+	#[error("Server is not accepting swaps right now. Retry later.")]
+	SubmissionsPaused,
+	#[error("Rounds are paused by the server's operator.")]
+	RoundsPaused,
+	#[error("A round is still executing ({phase:?}), so this one is skipped.")]
+	RoundInProgress { phase: RoundPhase },
+	#[error("Round skipped: {0}")]
+	WalletNotReady(String),
+	#[error("Too many swaps are waiting to be verified. Retry shortly.")]
+	VerifierBusy,
 	#[error("{0}")]
 	UnknownError(String),
 }
 
-/// A MWixnet server
-pub trait Server: Send + Sync {
-	/// Submit a new output to be swapped.
-	fn swap(&self, onion: &Onion, comsig: &ComSignature) -> Result<(), SwapError>;
+/// How the swaps left in process by a previous run of the server were resolved at startup
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReconciliationReport {
+	/// swaps whose round transaction is on chain
+	pub completed: usize,
+	/// swaps whose round transaction is still in the node's mempool, or was accepted again
+	pub pending: usize,
+	/// swaps whose round transaction was dropped, and whose input is still unspent
+	pub unprocessed: usize,
+	/// swaps whose round transaction was dropped, and whose input has been spent elsewhere
+	pub failed: usize,
+	/// swaps left as they were, since the node couldn't be asked about them
+	pub unchecked: usize,
+}
 
-	/// Iterate through all saved submissions, filter out any inputs that are no longer spendable,
-	/// and assemble the coinswap transaction, posting the transaction to the configured node.
-	///
-	/// Currently only a single mix node is used. Milestone 3 will include support for multiple mix nodes.
-	fn execute_round(&self) -> Result<Option<Transaction>, Box<dyn std::error::Error>>;
+/// Where the server is in executing a round
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundPhase {
+	Idle,
+	/// Selecting the round's swaps and building its transaction
+	Building,
+	/// Posting the round transaction to the node, or waiting to post it again
+	Posting,
+	/// Recording the round, and its swaps' new statuses
+	Recording,
 }
 
-/// The standard MWixnet server implementation
-#[derive(Clone)]
-pub struct ServerImpl {
-	server_config: ServerConfig,
-	wallet: Arc<dyn Wallet>,
-	node: Arc<dyn GrinNode>,
-	store: Arc<Mutex<SwapStore>>,
+impl Default for RoundPhase {
+	fn default() -> Self {
+		RoundPhase::Idle
+	}
 }
 
-impl ServerImpl {
-	/// Create a new MWixnet server
-	pub fn new(
-		server_config: ServerConfig,
-		wallet: Arc<dyn Wallet>,
-		node: Arc<dyn GrinNode>,
-		store: SwapStore,
-	) -> Self {
-		ServerImpl {
-			server_config,
-			wallet,
-			node,
-			store: Arc::new(Mutex::new(store)),
+/// The phase of the executing round, if any. Shared so it can be checked without waiting for
+/// the server, which is held for the whole round.
+///
+/// Servers are only ever used behind a mutex, so a round started while another holds the server
+/// just waits for it, and runs once it's done, without ever seeing its phase. Callers that want
+/// an overlapping round skipped must check the phase before taking the server, as the scheduler
+/// and 'trigger_round' do. 'begin' itself only turns away rounds started while the last one's
+/// transaction is waiting to be posted again, which is done with the server released.
+#[derive(Default)]
+pub struct RoundState {
+	phase: Mutex<RoundPhase>,
+}
+
+impl RoundState {
+	pub fn phase(&self) -> RoundPhase {
+		*self.phase.lock().unwrap()
+	}
+
+	/// Starts building a round, unless one is already executing. The round lasts until the
+	/// returned guard is dropped.
+	fn begin<'a>(&'a self, store: &'a Mutex<SwapStore>) -> Result<RoundGuard<'a>, SwapError> {
+		let mut phase = self.phase.lock().unwrap();
+		if *phase != RoundPhase::Idle {
+			return Err(SwapError::RoundInProgress { phase: *phase });
 		}
+		*phase = RoundPhase::Building;
+		Ok(self.resume(store))
 	}
 
-	/// The fee base to use. For now, just using the default.
-	fn get_fee_base(&self) -> u64 {
-		DEFAULT_ACCEPT_FEE_BASE
+	/// Takes back the round a guard was kept for, once its deferred transaction is posted again
+	fn resume<'a>(&'a self, store: &'a Mutex<SwapStore>) -> RoundGuard<'a> {
+		RoundGuard {
+			state: self,
+			store,
+			kept: false,
+		}
 	}
 
-	/// Minimum fee to perform a swap.
-	/// Requires enough fee for the mwixnet server's kernel, 1 input and its output to swap.
-	fn get_minimum_swap_fee(&self) -> u64 {
-		TransactionBody::weight_by_iok(1, 1, 1) * self.get_fee_base()
+	fn advance(&self, phase: RoundPhase) {
+		*self.phase.lock().unwrap() = phase;
 	}
 }
 
-impl Server for ServerImpl {
-	fn swap(&self, onion: &Onion, comsig: &ComSignature) -> Result<(), SwapError> {
-		// milestone 3: check that enc_payloads length matches number of configured servers
-		if onion.enc_payloads.len() != 1 {
-			return Err(SwapError::InvalidPayloadLength {
-				expected: 1,
-				found: onion.enc_payloads.len(),
-			});
+/// The executing round. Once dropped, even while unwinding from a panic, the round's marker is
+/// removed and the phase goes back to 'Idle', unless the guard was kept because the round's
+/// transaction is deferred to be posted again.
+struct RoundGuard<'a> {
+	state: &'a RoundState,
+	store: &'a Mutex<SwapStore>,
+	kept: bool,
+}
+
+impl RoundGuard<'_> {
+	/// Leaves the round executing, for 'RoundState::resume' to take back
+	fn keep(mut self) {
+		self.kept = true;
+	}
+}
+
+impl Drop for RoundGuard<'_> {
+	fn drop(&mut self) {
+		if self.kept {
+			return;
 		}
+		// A round that panicked may have poisoned either lock, and panicking again would abort
+		let store = self.store.lock().unwrap_or_else(PoisonError::into_inner);
+		if let Err(e) = store.delete_round_marker() {
+			warn!("Failed to remove the round marker: {}", e);
+		}
+		drop(store);
+		*self
+			.state
+			.phase
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner) = RoundPhase::Idle;
+	}
+}
 
-		// Verify commitment signature to ensure caller owns the output
-		let serialized_onion = onion
-			.serialize()
-			.map_err(|e| SwapError::UnknownError(e.to_string()))?;
-		let _ = comsig
-			.verify(&onion.commit, &serialized_onion)
-			.map_err(|_| SwapError::InvalidComSignature)?;
+/// A rangeproof waiting for a verification worker
+struct VerifyJob {
+	commit: Commitment,
+	proof: RangeProof,
+	result: mpsc::Sender<bool>,
+}
 
-		// Verify that commitment is unspent
-		let input = node::build_input(&self.node, &onion.commit)
-			.map_err(|e| SwapError::UnknownError(e.to_string()))?;
-		let input = input.ok_or(SwapError::CoinNotFound {
-			commit: onion.commit.clone(),
-		})?;
+/// Results of verified rangeproofs, waiting for their swap to be submitted.
+/// Only the most recent 'VERIFIED_CACHE_SIZE' are kept.
+#[derive(Default)]
+struct VerifiedCache {
+	results: HashMap<(Commitment, Vec<u8>), bool>,
+	order: VecDeque<(Commitment, Vec<u8>)>,
+}
 
-		let peeled = onion
-			.peel_layer(&self.server_config.key)
-			.map_err(|e| SwapError::PeelOnionFailure(e))?;
+/// A pool of workers that verify the rangeproofs of submitted swaps, so a burst of submissions
+/// is verified in parallel before the server is locked to accept each of them.
+/// The queue is bounded, and once full further proofs are rejected rather than queued.
+pub struct ProofVerifier {
+	jobs: Mutex<mpsc::SyncSender<VerifyJob>>,
+	verified: Mutex<VerifiedCache>,
+}
 
-		// Verify the fee meets the minimum
-		let fee: u64 = peeled.0.fee.into();
-		if fee < self.get_minimum_swap_fee() {
-			return Err(SwapError::FeeTooLow {
-				minimum_fee: self.get_minimum_swap_fee(),
-				actual_fee: fee,
+impl ProofVerifier {
+	pub fn new(workers: usize, queue_size: usize) -> Self {
+		let (sender, receiver) = mpsc::sync_channel::<VerifyJob>(queue_size);
+		let receiver = Arc::new(Mutex::new(receiver));
+		for _ in 0..workers.max(1) {
+			let receiver = receiver.clone();
+			std::thread::spawn(move || loop {
+				// The queue is closed once the verifier is dropped
+				let job = match receiver.lock().unwrap().recv() {
+					Ok(job) => job,
+					Err(_) => break,
+				};
+				let _ = job.result.send(verify_proof(&job.commit, &job.proof));
 			});
 		}
 
-		// Verify the bullet proof and build the final output
-		if let Some(r) = peeled.0.rangeproof {
-			let secp = Secp256k1::with_caps(secp256k1zkp::ContextFlag::Commit);
-			secp.verify_bullet_proof(peeled.1.commit, r, None)
-				.map_err(|_| SwapError::InvalidRangeproof)?;
-		} else {
-			// milestone 3: only the last hop will have a rangeproof
-			return Err(SwapError::MissingRangeproof);
+		ProofVerifier {
+			jobs: Mutex::new(sender),
+			verified: Mutex::new(VerifiedCache::default()),
 		}
+	}
 
-		let locked = self.store.lock().unwrap();
-
-		locked
-			.save_swap(
-				&SwapData {
-					excess: peeled.0.excess,
-					output_commit: peeled.1.commit,
-					rangeproof: peeled.0.rangeproof,
-					input,
-					fee,
-					onion: peeled.1,
-					status: SwapStatus::Unprocessed,
-				},
-				false,
-			)
+	/// Verifies the rangeproof on one of the pool's workers, remembering the result
+	/// for when the swap is accepted. Fails with 'VerifierBusy' if the queue is full.
+	pub fn verify(&self, commit: &Commitment, proof: &RangeProof) -> Result<bool, SwapError> {
+		let (result_tx, result_rx) = mpsc::channel();
+		let job = VerifyJob {
+			commit: commit.clone(),
+			proof: proof.clone(),
+			result: result_tx,
+		};
+		self.jobs
+			.lock()
+			.unwrap()
+			.try_send(job)
 			.map_err(|e| match e {
-				StoreError::AlreadyExists(_) => SwapError::AlreadySwapped {
-					commit: onion.commit.clone(),
-				},
-				_ => SwapError::StoreError(e),
+				mpsc::TrySendError::Full(_) => SwapError::VerifierBusy,
+				mpsc::TrySendError::Disconnected(_) => {
+					SwapError::UnknownError("Verification workers stopped".to_string())
+				}
 			})?;
-		Ok(())
-	}
-
-	fn execute_round(&self) -> Result<Option<Transaction>, Box<dyn std::error::Error>> {
-		let locked_store = self.store.lock().unwrap();
-		let next_block_height = self.node.get_chain_height()? + 1;
-
-		let spendable: Vec<SwapData> = locked_store
-			.swaps_iter()?
-			.unique_by(|s| s.output_commit)
-			.filter(|s| match s.status {
-				SwapStatus::Unprocessed => true,
-				_ => false,
-			})
-			.filter(|s| {
-				node::is_spendable(&self.node, &s.input.commit, next_block_height).unwrap_or(false)
-			})
-			.filter(|s| !node::is_unspent(&self.node, &s.output_commit).unwrap_or(true))
-			.collect();
+		let valid = result_rx
+			.recv()
+			.map_err(|e| SwapError::UnknownError(e.to_string()))?;
 
-		if spendable.len() == 0 {
-			return Ok(None);
+		let mut verified = self.verified.lock().unwrap();
+		let key = (commit.clone(), proof.bytes().to_vec());
+		if verified.results.insert(key.clone(), valid).is_none() {
+			verified.order.push_back(key);
+		}
+		while verified.order.len() > VERIFIED_CACHE_SIZE {
+			if let Some(oldest) = verified.order.pop_front() {
+				verified.results.remove(&oldest);
+			}
 		}
+		Ok(valid)
+	}
 
-		let total_fee: u64 = spendable.iter().enumerate().map(|(_, s)| s.fee).sum();
+	/// Whether the rangeproof is valid, using the pool's result if it was already verified
+	/// or else verifying it on the calling thread
+	pub fn is_valid(&self, commit: &Commitment, proof: &RangeProof) -> bool {
+		let key = (commit.clone(), proof.bytes().to_vec());
+		let cached = {
+			let mut verified = self.verified.lock().unwrap();
+			let cached = verified.results.remove(&key);
+			if cached.is_some() {
+				verified.order.retain(|k| k != &key);
+			}
+			cached
+		};
+		cached.unwrap_or_else(|| verify_proof(commit, proof))
+	}
+}
 
-		let inputs: Vec<Input> = spendable.iter().enumerate().map(|(_, s)| s.input).collect();
+fn verify_proof(commit: &Commitment, proof: &RangeProof) -> bool {
+	let secp = Secp256k1::with_caps(secp256k1zkp::ContextFlag::Commit);
+	secp.verify_bullet_proof(commit.clone(), proof.clone(), None)
+		.is_ok()
+}
 
-		let outputs: Vec<Output> = spendable
-			.iter()
-			.enumerate()
-			.map(|(_, s)| {
-				Output::new(
-					OutputFeatures::Plain,
-					s.output_commit,
-					s.rangeproof.unwrap(),
-				)
-			})
-			.collect();
+/// Overall health of the server
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Health {
+	Healthy,
+	/// The server is running, but some functionality is unavailable for the reason given
+	Degraded(String),
+}
 
-		let excesses: Vec<SecretKey> = spendable
-			.iter()
-			.enumerate()
-			.map(|(_, s)| s.excess.clone())
-			.collect();
+impl Default for Health {
+	fn default() -> Self {
+		Health::Healthy
+	}
+}
 
-		let tx = wallet::assemble_tx(
-			&self.wallet,
-			&inputs,
-			&outputs,
-			self.get_fee_base(),
-			total_fee,
-			&excesses,
-		)?;
+/// Summary of the swaps held by a server, as reported to operators
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct ServerStatus {
+	pub health: Health,
+	/// number of swaps waiting for the next round
+	pub unprocessed: usize,
+	/// number of swaps included in a posted, but unconfirmed, transaction
+	pub in_process: usize,
+	/// number of swaps whose transaction has been confirmed
+	pub completed: usize,
+	/// number of swaps dropped from a round the node rejected because of them
+	pub failed: usize,
+	/// which parts of the server are enabled
+	pub features: FeatureFlags,
+}
 
-		self.node.post_tx(&tx)?;
+/// A signed commitment to the set of pending swaps, published periodically so that the server
+/// can't silently drop a submission. Each leaf is the hash of a salted input commitment.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct PendingCommitment {
+	/// hex-encoded merkle root over the leaves of all pending swaps
+	pub root: String,
+	/// number of pending swaps committed to
+	pub size: u64,
+	/// unix time (in seconds) the commitment was made
+	pub timestamp: u64,
+	/// the server's public key
+	#[serde(with = "secp_ser::pubkey_serde")]
+	pub pubkey: PublicKey,
+	/// the server's signature over the root, size and timestamp
+	#[serde(with = "secp_ser::sig_serde")]
+	pub signature: Signature,
+}
 
-		// Update status to in process
-		let kernel_hash = tx.kernels().first().unwrap().hash();
-		for mut swap in spendable {
-			swap.status = SwapStatus::InProcess { kernel_hash };
-			locked_store.save_swap(&swap, true)?;
-		}
+impl PendingCommitment {
+	/// The message signed by the server: Blake2b(root || size || timestamp)
+	pub fn message(root: &MerkleHash, size: u64, timestamp: u64) -> Result<Message, SwapError> {
+		let mut hasher = Blake2b::new(32);
+		hasher.update(root);
+		let mut int_bytes = [0; 8];
+		BigEndian::write_u64(&mut int_bytes, size);
+		hasher.update(&int_bytes);
+		BigEndian::write_u64(&mut int_bytes, timestamp);
+		hasher.update(&int_bytes);
 
-		Ok(Some(tx))
+		Message::from_slice(hasher.finalize().as_bytes())
+			.map_err(|e| SwapError::UnknownError(e.to_string()))
 	}
 }
 
-#[cfg(test)]
-pub mod mock {
-	use super::{Server, SwapError};
-	use crate::onion::Onion;
-	use crate::secp::ComSignature;
+/// The server's signed receipt for a swap's inclusion in a round,
+/// which the client can keep as proof that its output was mixed
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SwapReceipt {
+	/// hex-encoded commitment of the swap's input
+	pub input_commit: String,
+	/// hex-encoded commitment of the swap's output
+	pub output_commit: String,
+	/// hex-encoded hash of the round transaction's kernel
+	pub kernel_hash: String,
+	/// id of the round, and the acceptance window, the swap was included in
+	pub round_id: u64,
+	/// the server key the receipt was signed with
+	#[serde(with = "secp_ser::pubkey_serde")]
+	pub pubkey: PublicKey,
+	/// the server's signature over the commitments, kernel hash and round id
+	#[serde(with = "secp_ser::sig_serde")]
+	pub signature: Signature,
+	/// the URL of the server that issued the receipt. Not covered by the signature,
+	/// since the server may move without invalidating its receipts.
+	pub server_url: String,
+}
 
-	use grin_core::core::Transaction;
-	use std::collections::HashMap;
+impl SwapReceipt {
+	fn new(receipt: &ReceiptData, server_url: String) -> Self {
+		SwapReceipt {
+			input_commit: receipt.input_commit.to_hex(),
+			output_commit: receipt.output_commit.to_hex(),
+			kernel_hash: receipt.kernel_hash.to_hex(),
+			round_id: receipt.round_id,
+			pubkey: receipt.pubkey,
+			signature: receipt.signature.clone(),
+			server_url,
+		}
+	}
 
-	pub struct MockServer {
-		errors: HashMap<Onion, SwapError>,
+	/// The message signed by the server:
+	/// Blake2b("MWIXNET_RECEIPT" || input_commit || output_commit || kernel_hash || round_id)
+	pub fn message(
+		input_commit: &Commitment,
+		output_commit: &Commitment,
+		kernel_hash: &Hash,
+		round_id: u64,
+	) -> Result<Message, SwapError> {
+		let mut hasher = Blake2b::new(32);
+		hasher.update(b"MWIXNET_RECEIPT");
+		hasher.update(&input_commit.0);
+		hasher.update(&output_commit.0);
+		hasher.update(kernel_hash.as_ref());
+		let mut int_bytes = [0; 8];
+		BigEndian::write_u64(&mut int_bytes, round_id);
+		hasher.update(&int_bytes);
+
+		Message::from_slice(hasher.finalize().as_bytes())
+			.map_err(|e| SwapError::UnknownError(e.to_string()))
 	}
 
-	impl MockServer {
-		pub fn new() -> MockServer {
-			MockServer {
-				errors: HashMap::new(),
+	/// Checks that the receipt was signed by the owner of 'pubkey'
+	pub fn verify(&self) -> bool {
+		let parse_commit = |hex: &str| {
+			grin_util::from_hex(hex)
+				.ok()
+				.map(|b| Commitment::from_vec(b))
+		};
+		let input_commit = parse_commit(&self.input_commit);
+		let output_commit = parse_commit(&self.output_commit);
+		let kernel_hash = Hash::from_hex(&self.kernel_hash).ok();
+		match (input_commit, output_commit, kernel_hash) {
+			(Some(input_commit), Some(output_commit), Some(kernel_hash)) => {
+				match SwapReceipt::message(
+					&input_commit,
+					&output_commit,
+					&kernel_hash,
+					self.round_id,
+				) {
+					Ok(msg) => secp::verify(&self.pubkey, &self.signature, &msg),
+					Err(_) => false,
+				}
 			}
-		}
-
-		pub fn set_response(&mut self, onion: &Onion, e: SwapError) {
-			self.errors.insert(onion.clone(), e);
+			_ => false,
 		}
 	}
+}
 
-	impl Server for MockServer {
-		fn swap(&self, onion: &Onion, _comsig: &ComSignature) -> Result<(), SwapError> {
-			if let Some(e) = self.errors.get(&onion) {
-				return Err(e.clone());
-			}
+/// Proof that a swap was included in a 'PendingCommitment'
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct InclusionProof {
+	/// hex-encoded salt. the swap's leaf is merkle::hash_leaf(salt || input commitment)
+	pub salt: String,
+	/// merkle proof of the leaf's inclusion
+	pub proof: MerkleProofJson,
+	/// the commitment the proof is for
+	pub commitment: PendingCommitment,
+}
 
-			Ok(())
-		}
+impl InclusionProof {
+	/// Checks that the input commitment's leaf is included under the commitment's root
+	pub fn verify(&self, input_commit: &Commitment) -> bool {
+		let salt = match merkle::hash_from_hex(&self.salt) {
+			Some(salt) => salt,
+			None => return false,
+		};
+		let root = match merkle::hash_from_hex(&self.commitment.root) {
+			Some(root) => root,
+			None => return false,
+		};
 
-		fn execute_round(&self) -> Result<Option<Transaction>, Box<dyn std::error::Error>> {
-			Ok(None)
+		let leaf = merkle::hash_leaf(&[&salt[..], &input_commit.0[..]].concat());
+		match self.proof.to_proof() {
+			Some(proof) => proof.verify(&leaf, &root),
+			None => false,
 		}
 	}
 }
 
-#[cfg(test)]
-mod tests {
-	use crate::config::ServerConfig;
-	use crate::node::mock::MockGrinNode;
-	use crate::onion::test_util::{self, Hop};
-	use crate::onion::Onion;
-	use crate::secp::{
-		self, ComSignature, Commitment, PublicKey, RangeProof, Secp256k1, SecretKey,
-	};
-	use crate::server::{Server, ServerImpl, SwapError};
-	use crate::store::{SwapData, SwapStatus, SwapStore};
-	use crate::types::Payload;
-	use crate::wallet::mock::MockWallet;
+/// The status of a swap, as reported to the client that submitted it
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SwapCheck {
+	pub status: String,
+	/// proof of inclusion in the latest pending commitment, if the swap was pending when it was made
+	pub inclusion: Option<InclusionProof>,
+	/// where to find the round's kernel on chain, once the swap has been included in a round
+	pub kernel: Option<KernelLocation>,
+	/// unix time (in seconds) the swap was accepted
+	#[serde(default)]
+	pub accepted_at: u64,
+	/// id of the round whose acceptance window was open when the swap was accepted
+	#[serde(default)]
+	pub accepted_round: u64,
+}
 
-	use grin_core::core::hash::Hashed;
-	use grin_core::core::{Committed, FeeFields, Input, OutputFeatures, Transaction, Weighting};
-	use grin_core::global::{self, ChainTypes};
-	use std::net::TcpListener;
-	use std::sync::Arc;
+/// Where a round's kernel can be found on chain, with links to the configured block explorer
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct KernelLocation {
+	/// hex-encoded kernel excess, which explorers look kernels up by
+	pub excess: String,
+	/// height of the block the kernel was confirmed in, if it has been
+	pub height: Option<u64>,
+	/// link to the kernel, when 'explorer_kernel_url' is configured
+	pub kernel_url: Option<String>,
+	/// link to the confirming block, when 'explorer_block_url' is configured
+	pub block_url: Option<String>,
+}
 
-	macro_rules! assert_error_type {
-		($result:expr, $error_type:pat) => {
-			assert!($result.is_err());
-			assert!(if let $error_type = $result.unwrap_err() {
-				true
-			} else {
-				false
-			});
-		};
-	}
+/// A server key that onions may still be encrypted to until it expires
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct PreviousServerKey {
+	#[serde(with = "secp_ser::pubkey_serde")]
+	pub pubkey: PublicKey,
+	/// unix time (in seconds) after which onions encrypted to this key are rejected
+	pub expires_at: u64,
+}
 
-	fn new_server(
-		test_name: &str,
-		server_key: &SecretKey,
-		utxos: &Vec<&Commitment>,
-	) -> (ServerImpl, Arc<MockGrinNode>) {
-		global::set_local_chain_type(ChainTypes::AutomatedTesting);
-		let db_root = format!("./target/tmp/.{}", test_name);
-		let _ = std::fs::remove_dir_all(db_root.as_str());
+/// The public keys that onions may currently be encrypted to
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ServerKeys {
+	#[serde(with = "secp_ser::pubkey_serde")]
+	pub current: PublicKey,
+	/// the key used before the last rotation, during its grace window
+	pub previous: Option<PreviousServerKey>,
+}
 
-		let config = ServerConfig {
-			key: server_key.clone(),
-			interval_s: 1,
-			addr: TcpListener::bind("127.0.0.1:0")
-				.unwrap()
-				.local_addr()
-				.unwrap(),
-			grin_node_url: "127.0.0.1:3413".parse().unwrap(),
-			grin_node_secret_path: None,
-			wallet_owner_url: "127.0.0.1:3420".parse().unwrap(),
-			wallet_owner_secret_path: None,
-		};
-		let wallet = Arc::new(MockWallet {});
-		let mut mut_node = MockGrinNode::new();
-		for utxo in utxos {
-			mut_node.add_default_utxo(&utxo);
-		}
-		let node = Arc::new(mut_node);
-		let store = SwapStore::new(db_root.as_str()).unwrap();
+/// The period during which swaps are accepted for an upcoming round.
+/// A window opens once the previous round has executed, and closes when its round is scheduled.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct AcceptanceWindow {
+	/// sequential id of the round that swaps accepted in this window are included in
+	pub round_id: u64,
+	/// unix time (in seconds) the window closes, when rounds are scheduled by time
+	pub closes_at: Option<u64>,
+	/// chain height at which the window closes, when rounds are scheduled by block height
+	pub closes_at_height: Option<u64>,
+}
 
-		let server = ServerImpl::new(config, wallet.clone(), node.clone(), store);
-		(server, node)
+impl AcceptanceWindow {
+	/// Whether the window has closed, given the current unix time and chain height
+	pub fn is_closed(&self, now: u64, height: u64) -> bool {
+		self.closes_at.map(|t| now >= t).unwrap_or(false)
+			|| self.closes_at_height.map(|h| height >= h).unwrap_or(false)
 	}
 
-	fn proof(value: u64, fee: u64, input_blind: &SecretKey, hop_excess: &SecretKey) -> RangeProof {
-		let secp = Secp256k1::new();
-		let nonce = secp::random_secret();
+	/// Whether the window closes within 'secs' seconds of 'now', or 'blocks' blocks of 'height'
+	pub fn closes_within(&self, now: u64, height: u64, secs: u64, blocks: u64) -> bool {
+		self.closes_at.map(|t| now + secs >= t).unwrap_or(false)
+			|| self
+				.closes_at_height
+				.map(|h| height + blocks >= h)
+				.unwrap_or(false)
+	}
+}
 
-		let mut blind = input_blind.clone();
-		blind.add_assign(&secp, &hop_excess).unwrap();
+/// What clients need to know to build onions for the server, and when to expect rounds
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ServerInfo {
+	/// the URL clients should reach the server at
+	pub url: String,
+	/// the keys onions may be encrypted to
+	pub keys: ServerKeys,
+	/// fee (in nanogrin) required per unit of transaction weight
+	pub fee_base: u64,
+	/// minimum fee (in nanogrin) a swap must pay
+	pub minimum_fee: u64,
+	/// the server's view of the current chain height
+	pub chain_height: u64,
+	/// furthest a swap's expiry height may be beyond the current chain height
+	pub max_expiry_blocks: u64,
+	/// most hops a route may have
+	pub max_hops: usize,
+	/// smallest output (in nanogrin) the last hop must prove a swap is worth, when configured
+	pub min_output_value: Option<u64>,
+	/// the codes swaps may be rejected with
+	pub error_codes: Vec<ErrorCodeInfo>,
+	/// blocks an accepted swap waits to be included in a round before it expires
+	pub swap_ttl_blocks: u64,
+	/// seconds between rounds, when rounds are scheduled by time
+	pub interval_s: Option<u64>,
+	/// blocks between rounds, when rounds are scheduled by block height
+	pub interval_blocks: Option<u64>,
+	/// unix time (in seconds) the next round is scheduled for. Rounds may still execute up to
+	/// 'round_delay_window' later, so the exact broadcast time remains unpredictable.
+	pub next_round_at: Option<u64>,
+	/// seconds until the next round is due: exact when rounds are scheduled by time, and estimated
+	/// from the block time when they're scheduled by block height
+	#[serde(default)]
+	pub next_round_in_s: Option<u64>,
+	/// chain height at which the next round is due, when rounds are scheduled by block height
+	pub next_round_height: Option<u64>,
+	/// the window swaps are currently being accepted in, once the round scheduler has opened one
+	pub window: Option<AcceptanceWindow>,
+	/// fees paid in recent rounds, once any rounds have been executed
+	pub recent_fees: Option<FeeStats>,
+	/// health of the other mix nodes this server pings, so clients can route around dead hops
+	#[serde(default)]
+	pub peers: Vec<PeerStatus>,
+	/// Content-Encodings requests to the API may be compressed with
+	#[serde(default)]
+	pub content_encodings: Vec<String>,
+	/// cipher suites onions may be encrypted with
+	#[serde(default)]
+	pub cipher_suites: Vec<CipherSuite>,
+}
 
-		secp.bullet_proof(
-			value - fee,
-			blind.clone(),
-			nonce.clone(),
-			nonce.clone(),
-			None,
-			None,
-		)
-	}
+/// The fees a swap through a number of hops must pay, so clients don't have to guess them
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct FeeEstimate {
+	/// number of hops (mix nodes) the swap passes through
+	pub hops: u64,
+	/// fee (in nanogrin) per unit of transaction weight each hop enforces
+	pub fee_base: u64,
+	/// minimum fee (in nanogrin) each hop's payload must pay
+	pub hop_fee: u64,
+	/// minimum fee (in nanogrin) across all hops, deducted from the input's value
+	pub total_fee: u64,
+}
 
-	fn new_hop(
-		server_key: &SecretKey,
-		hop_excess: &SecretKey,
-		fee: u64,
-		proof: Option<RangeProof>,
-	) -> Hop {
-		let secp = Secp256k1::new();
-		Hop {
-			pubkey: PublicKey::from_secret_key(&secp, &server_key).unwrap(),
-			payload: Payload {
-				excess: hop_excess.clone(),
-				fee: FeeFields::from(fee as u32),
-				rangeproof: proof,
+/// Fees paid by the swaps included in recent rounds, so clients can choose competitive fees.
+/// Fees are bucketed (rounded down to two significant digits) so they can't identify a swap.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct FeeStats {
+	/// number of rounds the statistics cover
+	pub rounds: u64,
+	/// median fee (in nanogrin) paid by the swaps in those rounds
+	pub median_fee: u64,
+	/// lowest fee (in nanogrin) paid by the swaps in those rounds
+	pub minimum_fee: u64,
+}
+
+impl FeeStats {
+	/// Summarizes the fees of the 'FEE_STATS_ROUNDS' most recent rounds, if there have been any
+	pub fn from_rounds(mut rounds: Vec<RoundData>) -> Option<FeeStats> {
+		rounds.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+		rounds.truncate(FEE_STATS_ROUNDS);
+
+		let mut fees: Vec<u64> = rounds.iter().flat_map(|r| r.fees.clone()).collect();
+		if fees.is_empty() {
+			return None;
+		}
+		fees.sort();
+		Some(FeeStats {
+			rounds: rounds.len() as u64,
+			median_fee: bucket_fee(fees[fees.len() / 2]),
+			minimum_fee: bucket_fee(fees[0]),
+		})
+	}
+}
+
+/// Aggregates over every round the server has recorded, so users can judge how well it mixes.
+/// Nothing is broken down by round, so they reveal no more than the round transactions do.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RoundStats {
+	/// number of rounds the server has posted
+	pub rounds_completed: u64,
+	/// mean number of distinct outputs per round, not counting decoys
+	pub average_anonymity_set: f64,
+	/// number of outputs created across all rounds
+	pub total_outputs_mixed: u64,
+	/// seconds since the server was started
+	pub uptime_s: u64,
+}
+
+impl RoundStats {
+	/// Summarizes 'rounds', for a server that has been running for 'uptime_s' seconds
+	pub fn from_rounds(rounds: &[RoundData], uptime_s: u64) -> RoundStats {
+		let rounds_completed = rounds.len() as u64;
+		let anonymity_set: u64 = rounds.iter().map(|r| r.anonymity_set).sum();
+		RoundStats {
+			rounds_completed,
+			average_anonymity_set: match rounds_completed {
+				0 => 0.0,
+				n => anonymity_set as f64 / n as f64,
+			},
+			total_outputs_mixed: rounds.iter().map(|r| r.swap_count() as u64).sum(),
+			uptime_s,
+		}
+	}
+}
+
+/// Rounds a fee down to 'FEE_BUCKET_DIGITS' significant digits
+fn bucket_fee(fee: u64) -> u64 {
+	let digits = fee.to_string().len() as u32;
+	if digits <= FEE_BUCKET_DIGITS {
+		return fee;
+	}
+	let unit = 10u64.pow(digits - FEE_BUCKET_DIGITS);
+	fee / unit * unit
+}
+
+/// The server's current key, along with the key it replaced during its grace window
+struct KeyRing {
+	current: SecretKey,
+	previous: Option<PreviousKey>,
+}
+
+impl KeyRing {
+	/// The previous key, if it hasn't expired yet
+	fn unexpired_previous(&self, now: u64) -> Option<&PreviousKey> {
+		self.previous.as_ref().filter(|p| p.expires_at > now)
+	}
+}
+
+/// A published 'PendingCommitment', along with what's needed to build inclusion proofs for it
+struct PendingSnapshot {
+	commitment: PendingCommitment,
+	tree: MerkleTree,
+	indices: HashMap<Commitment, usize>,
+}
+
+/// A MWixnet server
+pub trait Server: Send + Sync {
+	/// Submit a new output to be swapped. The comsig must be bound to this server's key and
+	/// 'expiry_height', and the swap is only accepted before the chain reaches 'expiry_height'.
+	fn swap(
+		&self,
+		onion: &Onion,
+		comsig: &ComSignature,
+		expiry_height: u64,
+	) -> Result<(), SwapError>;
+
+	/// Like 'swap', for an onion whose layer the caller has already peeled with 'peel_layer',
+	/// so it isn't peeled a second time.
+	fn swap_peeled(
+		&self,
+		onion: &Onion,
+		peeled: (Payload, Onion),
+		comsig: &ComSignature,
+		expiry_height: u64,
+	) -> Result<(), SwapError>;
+
+	/// Iterate through all saved submissions, filter out any inputs that are no longer spendable,
+	/// and assemble the coinswap transaction, posting the transaction to the configured node.
+	///
+	/// Currently only a single mix node is used. Milestone 3 will include support for multiple mix nodes.
+	/// Fails with 'RoundInProgress' while the last round's transaction is waiting to be posted
+	/// again. Callers check 'round_state' before taking the server to skip rounds that would
+	/// otherwise wait for one that's executing.
+	fn execute_round(&self) -> Result<Option<Transaction>, Box<dyn std::error::Error>>;
+
+	/// Posts the transaction of a round the node didn't accept again, once 'retry_delay' has
+	/// passed since the last attempt, doubled for each attempt before it. Called by the round
+	/// scheduler each tick, so the server isn't held while the backoff is waited out.
+	/// Returns the transaction once the node accepts it.
+	fn retry_round(
+		&self,
+		retry_delay: Duration,
+	) -> Result<Option<Transaction>, Box<dyn std::error::Error>>;
+
+	/// The phase of the executing round, shared so it can be checked while the server is held.
+	fn round_state(&self) -> Arc<RoundState>;
+
+	/// Counts the saved swaps by status.
+	fn status(&self) -> Result<ServerStatus, SwapError>;
+
+	/// Lists all saved swaps, regardless of status.
+	fn list_swaps(&self) -> Result<Vec<SwapData>, SwapError>;
+
+	/// Deletes unprocessed swaps that have expired, so their inputs can be submitted again.
+	/// Returns the number of swaps deleted.
+	fn expire_swaps(&self) -> Result<usize, SwapError>;
+
+	/// Lists the wallet outputs that collected each round's fees.
+	fn fee_outputs(&self) -> Result<Vec<FeeOutputData>, SwapError>;
+
+	/// Lists the recorded rounds, newest first.
+	/// Rounds not yet known to be confirmed are looked up on chain, and their record updated once found.
+	fn list_rounds(&self) -> Result<Vec<RoundData>, SwapError>;
+
+	/// Reads the round with the given kernel hash, along with the swaps it included.
+	/// The round is looked up on chain if it's not yet known to be confirmed, like in 'list_rounds'.
+	fn get_round(
+		&self,
+		kernel_hash: &Hash,
+	) -> Result<Option<(RoundData, Vec<SwapData>)>, SwapError>;
+
+	/// Looks up the rounds not yet known to be confirmed on chain, updating the record of each one found.
+	/// Returns the number of rounds newly confirmed.
+	fn check_confirmations(&self) -> Result<usize, SwapError>;
+
+	/// Checks the kernels of recently confirmed rounds again, after the chain tip moved back to
+	/// 'height'. Rounds whose kernel is no longer on chain are marked unconfirmed, their swaps
+	/// reverted to in process, and their transaction posted again.
+	/// Returns the number of rounds reverted.
+	fn handle_reorg(&self, height: u64) -> Result<usize, SwapError>;
+
+	/// Summarizes the rounds, swaps, rejections, and earnings for the day
+	/// (counted in days since the unix epoch).
+	fn daily_report(&self, day: u64) -> Result<DailyReport, SwapError>;
+
+	/// How many rounds the wallet and decoy preflight checks held back or found short, by reason.
+	fn preflight_counts(&self) -> PreflightCounts;
+
+	/// The features currently enabled.
+	fn feature_flags(&self) -> Result<FeatureFlags, SwapError>;
+
+	/// Enables or disables a feature. The change is persisted, so it also survives restarts.
+	fn set_feature(&self, feature: Feature, enabled: bool) -> Result<FeatureFlags, SwapError>;
+
+	/// Flags whether the node's chain tip has stopped advancing.
+	/// Rounds are not executed while the node is stalled, since its UTXO view may be stale.
+	fn set_node_stalled(&self, stalled: bool);
+
+	/// Records the average block time observed on the node, used to convert configured intervals
+	/// between seconds and blocks.
+	fn set_block_time(&self, block_time_s: u64);
+
+	/// Replaces the server's settings with a reloaded config. The keys are left as they are,
+	/// since they're only changed through 'rotate_key'.
+	fn update_config(&mut self, server_config: ServerConfig);
+
+	/// Builds and signs a new commitment to all unprocessed swaps, replacing the previous one.
+	fn commit_pending(&self) -> Result<PendingCommitment, SwapError>;
+
+	/// The most recently published commitment to pending swaps, if any.
+	fn get_pending_commitment(&self) -> Option<PendingCommitment>;
+
+	/// Looks up the status of the swap for the input commitment,
+	/// including proof of its inclusion in the latest pending commitment.
+	/// The comsig must be over 'check_message' for the input, and bound to this server's key
+	/// and 'expiry_height' like a swap's, so only the input's owner learns whether it's queued.
+	fn check_swap(
+		&self,
+		input_commit: &Commitment,
+		comsig: &ComSignature,
+		expiry_height: u64,
+	) -> Result<SwapCheck, SwapError>;
+
+	/// The round the swap of the input commitment was accepted for
+	fn accepted_round(&self, input_commit: &Commitment) -> Result<u64, SwapError>;
+
+	/// The signed receipt for the swap of the input commitment, once it's been included in a round.
+	/// The receipt names the swap's output, so the comsig must be over 'receipt_message' for the
+	/// input, bound to this server's key and 'expiry_height' like a swap's.
+	fn get_receipt(
+		&self,
+		input_commit: &Commitment,
+		comsig: &ComSignature,
+		expiry_height: u64,
+	) -> Result<SwapReceipt, SwapError>;
+
+	/// Peels this server's layer off of the onion, without validating or saving anything.
+	fn peel_layer(&self, onion: &Onion) -> Result<(Payload, Onion), SwapError>;
+
+	/// The pool that verifies rangeproofs, shared so they can be verified without holding the server.
+	/// Proofs it has verified aren't verified again when their swap is submitted.
+	fn proof_verifier(&self) -> Arc<ProofVerifier>;
+
+	/// Withdraws the swap of the input commitment, as long as it hasn't been included in a round.
+	/// The comsig must be over 'cancel_message' for the swap, and bound to this server's key
+	/// and 'expiry_height' like a swap's.
+	fn cancel_swap(
+		&self,
+		input_commit: &Commitment,
+		comsig: &ComSignature,
+		expiry_height: u64,
+	) -> Result<(), SwapError>;
+
+	/// Promotes a standby server to primary, so it starts accepting swaps and executing rounds.
+	/// Only allowed once the primary has stopped replicating for long enough that it can no
+	/// longer be executing rounds itself.
+	fn promote(&self) -> Result<(), SwapError>;
+
+	/// The public keys that onions may currently be encrypted to.
+	fn server_keys(&self) -> Result<ServerKeys, SwapError>;
+
+	/// Switches to a new server key. Onions encrypted to the old key are still accepted until
+	/// 'expires_at' (unix time, in seconds). Refused while a previous key is still accepted,
+	/// since replacing it would turn away onions wallets may still be encrypting to.
+	fn rotate_key(&self, new_key: SecretKey, expires_at: u64) -> Result<(), SwapError>;
+
+	/// The server's keys, fee requirements, and round schedule.
+	/// 'next_round_at' and 'next_round_in_s' are left for the caller to fill in, since they're
+	/// tracked by the round scheduler.
+	fn info(&self) -> Result<ServerInfo, SwapError>;
+
+	/// The minimum fees for a swap through 'hops' mix nodes, assuming each enforces this server's
+	/// fee base.
+	fn estimate_fee(&self, hops: u64) -> FeeEstimate;
+
+	/// Aggregate statistics over the recorded rounds, and how long the server has been running.
+	fn stats(&self) -> Result<RoundStats, SwapError>;
+
+	/// Opens the acceptance window for the next round, which closes at unix time 'closes_at' or
+	/// chain height 'closes_at_height'. Called by the round scheduler each time a round is scheduled.
+	fn open_window(
+		&self,
+		closes_at: Option<u64>,
+		closes_at_height: Option<u64>,
+	) -> Result<AcceptanceWindow, SwapError>;
+
+	/// The window swaps are currently being accepted in, if one has been opened.
+	fn acceptance_window(&self) -> Option<AcceptanceWindow>;
+
+	/// Looks up the kernel of the round with the given kernel hash on chain.
+	/// Returns None for kernels that weren't built by this server.
+	fn locate_kernel(&self, kernel_hash: &Hash) -> Result<Option<KernelLocation>, SwapError>;
+
+	/// Records why the swap of 'onion' was rejected in the rejection log, along with where the
+	/// request came from. Nothing is recorded when the log is turned off.
+	fn record_rejection(&self, onion: &Onion, source: Option<String>, error: &SwapError);
+
+	/// The most recently rejected swaps, newest first.
+	fn list_rejections(&self, limit: usize) -> Result<Vec<RejectionData>, SwapError>;
+
+	/// Counts a swap submitted with the named API key, accepted or not.
+	/// A failure to save the count is logged rather than failing the request.
+	fn count_api_key_swap(&self, key_name: &str, accepted: bool);
+
+	/// The swaps submitted with each API key that has been used.
+	fn api_key_swaps(&self) -> Result<Vec<ApiKeySwaps>, SwapError>;
+}
+
+/// A round's assembled transaction, with what's needed to record the round once the node accepts
+/// it, or to roll it back once it's abandoned
+struct BuiltRound {
+	swaps: Vec<SwapData>,
+	pending: PendingTxData,
+	kernel: TxKernel,
+	wallet_output: Option<WalletOutput>,
+	decoys: Vec<(DecoyData, wallet::Decoy)>,
+	/// when the transaction was last posted
+	last_attempt: Instant,
+}
+
+/// The standard MWixnet server implementation
+#[derive(Clone)]
+pub struct ServerImpl {
+	server_config: ServerConfig,
+	wallet: Arc<dyn Wallet>,
+	node: Arc<dyn GrinNode>,
+	store: Arc<Mutex<SwapStore>>,
+	pending: Arc<RwLock<Option<PendingSnapshot>>>,
+	node_stalled: Arc<AtomicBool>,
+	/// average seconds per block, as last observed by the round scheduler
+	block_time_s: Arc<AtomicU64>,
+	activity: Arc<Mutex<ActivityLog>>,
+	preflight: Arc<Mutex<PreflightCounts>>,
+	replicator: Option<Arc<Replicator>>,
+	standby: Option<Arc<Standby>>,
+	keys: Arc<RwLock<KeyRing>>,
+	window: Arc<RwLock<Option<AcceptanceWindow>>>,
+	verifier: Arc<ProofVerifier>,
+	events: Option<EventBus>,
+	peers: Option<Arc<PeerHealth>>,
+	coordinator: Option<Arc<Coordinator>>,
+	policy: Option<Arc<dyn SwapPolicy>>,
+	/// when the server was created, for reporting its uptime
+	started_at: Instant,
+	round_state: Arc<RoundState>,
+	/// a round the node didn't accept the transaction of, waiting to be posted again
+	deferred_round: Arc<Mutex<Option<BuiltRound>>>,
+}
+
+impl ServerImpl {
+	/// Create a new MWixnet server
+	pub fn new(
+		server_config: ServerConfig,
+		wallet: Arc<dyn Wallet>,
+		node: Arc<dyn GrinNode>,
+		store: SwapStore,
+	) -> Self {
+		let keys = KeyRing {
+			current: server_config.key.clone(),
+			previous: server_config.previous_key.clone(),
+		};
+
+		ServerImpl {
+			server_config,
+			wallet,
+			node,
+			store: Arc::new(Mutex::new(store)),
+			pending: Arc::new(RwLock::new(None)),
+			node_stalled: Arc::new(AtomicBool::new(false)),
+			block_time_s: Arc::new(AtomicU64::new(DEFAULT_BLOCK_TIME_S)),
+			activity: Arc::new(Mutex::new(ActivityLog::default())),
+			preflight: Arc::new(Mutex::new(PreflightCounts::default())),
+			replicator: None,
+			standby: None,
+			keys: Arc::new(RwLock::new(keys)),
+			window: Arc::new(RwLock::new(None)),
+			verifier: Arc::new(ProofVerifier::new(
+				std::thread::available_parallelism()
+					.map(|n| n.get())
+					.unwrap_or(1),
+				VERIFIER_QUEUE_SIZE,
+			)),
+			events: None,
+			peers: None,
+			coordinator: None,
+			policy: None,
+			started_at: Instant::now(),
+			round_state: Arc::new(RoundState::default()),
+			deferred_round: Arc::new(Mutex::new(None)),
+		}
+	}
+
+	/// Looks for the marker of a round that never finished, left by the server crashing or being
+	/// killed part way through it, and removes it. The round's transaction, if it was queued,
+	/// is posted again (or recorded, if the node already has it) by the next round.
+	/// Returns the interrupted round's marker.
+	pub fn clear_interrupted_round(&self) -> Result<Option<RoundMarker>, StoreError> {
+		let locked_store = self.store.lock().unwrap();
+		let marker = locked_store.round_marker()?;
+		if let Some(marker) = &marker {
+			warn!(
+				"Round {} (started at {}) was interrupted before it finished",
+				marker.round_id, marker.started_at
+			);
+			locked_store.delete_round_marker()?;
+		}
+		Ok(marker)
+	}
+
+	/// Replicate accepted swaps and status updates to a standby server
+	pub fn with_replicator(mut self, replicator: Arc<Replicator>) -> Self {
+		self.replicator = Some(replicator);
+		self
+	}
+
+	/// Run as a standby, storing swaps replicated from the primary until promoted
+	pub fn with_standby(mut self, standby: Arc<Standby>) -> Self {
+		self.standby = Some(standby);
+		self
+	}
+
+	/// Refuse swaps the operator's policy objects to
+	pub fn with_policy(mut self, policy: Arc<dyn SwapPolicy>) -> Self {
+		self.policy = Some(policy);
+		self
+	}
+
+	/// Publish swap and round events to subscribers
+	pub fn with_events(mut self, events: EventBus) -> Self {
+		self.events = Some(events);
+		self
+	}
+
+	/// Publish the health of peer mix nodes in 'get_info'
+	pub fn with_peers(mut self, peers: Arc<PeerHealth>) -> Self {
+		self.peers = Some(peers);
+		self
+	}
+
+	/// Coordinate rounds with other mix nodes, handing round transactions to each round's leader
+	pub fn with_coordinator(mut self, coordinator: Arc<Coordinator>) -> Self {
+		self.coordinator = Some(coordinator);
+		self
+	}
+
+	/// The store swaps are persisted to
+	pub fn store(&self) -> Arc<Mutex<SwapStore>> {
+		self.store.clone()
+	}
+
+	/// Saves the swaps in a message replicated from the primary
+	pub fn receive_replicated(
+		&self,
+		msg: &ReplicationMessage,
+	) -> Result<ReplicationAck, ReplicationError> {
+		let standby = self.standby.as_ref().ok_or(ReplicationError::NotStandby)?;
+		let (swaps, ack) = standby.receive(msg)?;
+
+		self.store
+			.lock()
+			.unwrap()
+			.update_swaps_batch(&swaps)
+			.map_err(ReplicationError::StoreError)?;
+		Ok(ack)
+	}
+
+	/// Checks that this server is the one allowed to accept swaps and execute rounds,
+	/// and not a standby waiting to be promoted.
+	fn check_not_standby(&self) -> Result<(), SwapError> {
+		match &self.standby {
+			Some(standby) if !standby.is_promoted() => Err(SwapError::NotPrimary(
+				"Server is a standby that hasn't been promoted".to_string(),
+			)),
+			_ => Ok(()),
+		}
+	}
+
+	fn replicate(&self, swap: &SwapData) {
+		if let Some(replicator) = &self.replicator {
+			replicator.replicate(swap);
+		}
+	}
+
+	fn publish(&self, event: Event) {
+		if let Some(events) = &self.events {
+			events.publish(event);
+		}
+	}
+
+	/// Looks up each of the rounds that isn't yet known to be confirmed, saving the height of
+	/// those found on chain. Returns the newly confirmed rounds.
+	fn confirm_rounds(
+		&self,
+		locked_store: &SwapStore,
+		rounds: &mut Vec<RoundData>,
+	) -> Result<Vec<RoundData>, SwapError> {
+		let mut confirmed = Vec::new();
+		for round in rounds.iter_mut().filter(|r| r.confirmed_height.is_none()) {
+			let excess = locked_store
+				.get_kernel_excess(&round.kernel_hash)
+				.map_err(SwapError::StoreError)?;
+			// Leave the round unconfirmed if the node can't be reached, and check again next time
+			let height = match excess {
+				Some(excess) => self.node.get_kernel_height(&excess).unwrap_or(None),
+				None => None,
+			};
+			if height.is_some() {
+				round.confirmed_height = height;
+				locked_store
+					.save_round(round)
+					.map_err(SwapError::StoreError)?;
+				confirmed.push(round.clone());
+			}
+		}
+
+		for round in &confirmed {
+			let height = round.confirmed_height.unwrap_or(0);
+			let swaps = locked_store
+				.get_swaps_by_kernel(&round.kernel_hash)
+				.map_err(SwapError::StoreError)?;
+			for swap in &swaps {
+				self.publish(Event::SwapConfirmed {
+					commit: swap.input.commit.to_hex(),
+					round_id: round.round_id,
+					height,
+				});
+			}
+			self.publish(Event::RoundConfirmed {
+				round_id: round.round_id,
+				kernel_hash: round.kernel_hash.to_hex(),
+				height,
+			});
+		}
+
+		Ok(confirmed)
+	}
+
+	/// Blocks an accepted swap waits for a round before it expires
+	fn swap_ttl_blocks(&self) -> u64 {
+		let block_time_s = self.block_time_s.load(Ordering::Relaxed);
+		self.server_config
+			.swap_ttl
+			.map(|ttl| ttl.as_blocks(block_time_s))
+			.unwrap_or(DEFAULT_SWAP_TTL_BLOCKS)
+	}
+
+	/// The fee base to use. For now, just using the default.
+	fn get_fee_base(&self) -> u64 {
+		DEFAULT_ACCEPT_FEE_BASE
+	}
+
+	/// Minimum fee to perform a swap.
+	/// Requires enough fee for the mwixnet server's kernel, 1 input and its output to swap.
+	fn get_minimum_swap_fee(&self) -> u64 {
+		TransactionBody::weight_by_iok(1, 1, 1) * self.get_fee_base()
+	}
+
+	/// Posts the round transaction, or when rounds are coordinated with other nodes, hands it to
+	/// the round's leader. While leading, the batches received from the other nodes are posted
+	/// along with it. The transaction is posted on its own if it can't be handed over.
+	/// Returns the slot of the leader it was handed to, if it was.
+	fn post_round_tx(&self, tx: &Transaction) -> Result<Option<u64>, NodeError> {
+		if let Some(coordinator) = &self.coordinator {
+			let slot = coordinator.current_slot();
+			if !coordinator.is_leader(slot) {
+				match coordinator.submit(slot, tx) {
+					Ok(()) => {
+						info!("Handed round transaction to the leader of slot {}", slot);
+						return Ok(Some(slot));
+					}
+					Err(e) => warn!("Failed to hand round transaction to the leader: {}", e),
+				}
+			} else {
+				return self.post_batches(coordinator, Some(tx)).map(|_| None);
+			}
+		}
+		self.node
+			.post_tx(tx, !self.server_config.dandelion_stem)
+			.map(|_| None)
+	}
+
+	/// Posts the batches the other nodes have handed this node to lead, aggregated with its own
+	/// round transaction 'tx', if it has one. A batch that can't be aggregated with the rest
+	/// (e.g. one spending an input another already spends) is dropped on its own, and if the node
+	/// won't take the aggregate, each transaction is posted separately. Followers post a dropped
+	/// batch themselves once the slot has passed.
+	/// The result is that of posting 'tx'.
+	fn post_batches(
+		&self,
+		coordinator: &Coordinator,
+		tx: Option<&Transaction>,
+	) -> Result<(), NodeError> {
+		let fluff = !self.server_config.dandelion_stem;
+		let mut aggregate = tx.cloned();
+		let mut included = Vec::new();
+		for batch in coordinator.take_batches() {
+			let aggregated = match &aggregate {
+				Some(aggregate) => transaction::aggregate(&[aggregate.clone(), batch.clone()]),
+				None => Ok(batch.clone()),
+			};
+			match aggregated {
+				Ok(aggregated) => {
+					aggregate = Some(aggregated);
+					included.push(batch);
+				}
+				Err(e) => warn!("Dropping a batch that can't be aggregated: {}", e),
+			}
+		}
+		let aggregate = match aggregate {
+			Some(aggregate) => aggregate,
+			None => return Ok(()),
+		};
+		if included.is_empty() {
+			return self.node.post_tx(&aggregate, fluff);
+		}
+
+		match self.node.post_tx(&aggregate, fluff) {
+			Ok(()) => {
+				info!(
+					"Posted round transaction with {} other nodes' batches",
+					included.len()
+				);
+				Ok(())
+			}
+			Err(e) => {
+				warn!(
+					"Failed to post round with other nodes' batches: {}. Posting each on its own.",
+					e
+				);
+				for batch in &included {
+					if let Err(e) = self.node.post_tx(batch, fluff) {
+						warn!("Failed to post another node's batch: {}", e);
+					}
+				}
+				match tx {
+					Some(tx) => self.node.post_tx(tx, fluff),
+					None => Ok(()),
+				}
+			}
+		}
+	}
+
+	/// The swaps the node couldn't have accepted a round transaction with: those its 'error' names
+	/// the input or output commitment of, those whose input is no longer unspent (e.g. spent by
+	/// its owner in another transaction), and those whose output already exists.
+	/// Swaps can't be blamed while the node is unreachable, so none are returned then.
+	fn offending_swaps(&self, swaps: &[SwapData], error: &str) -> Vec<SwapData> {
+		swaps
+			.iter()
+			.filter(|s| {
+				error.contains(&s.input.commit.to_hex())
+					|| error.contains(&s.output_commit.to_hex())
+					|| !node::is_unspent(&self.node, &s.input.commit).unwrap_or(true)
+					|| node::is_unspent(&self.node, &s.output_commit).unwrap_or(false)
+			})
+			.cloned()
+			.collect()
+	}
+
+	/// Round transactions are only left in the queue when the server stopped before it finished
+	/// posting them, or when they were handed to the leader of a coordinated round (see
+	/// 'follow_up_handed_off_tx'). The rest are posted once more: if the node accepts it, or it's
+	/// already on chain, the round is recorded and its swaps marked in process, just without
+	/// receipts. Otherwise it's dropped, leaving its swaps unprocessed for the next round.
+	fn retry_queued_txs(&self, store: &SwapStore) -> Result<(), SwapError> {
+		let queued: Vec<PendingTxData> = store
+			.pending_txs_iter()
+			.map_err(SwapError::StoreError)?
+			.collect();
+		for pending in queued {
+			let kernel = match wallet::round_kernel(&pending.tx) {
+				Some(kernel) => kernel,
+				None => {
+					store
+						.delete_pending_tx(&pending.kernel_hash)
+						.map_err(SwapError::StoreError)?;
+					continue;
+				}
+			};
+			if let Some(slot) = pending.handed_off_slot {
+				self.follow_up_handed_off_tx(store, &pending, &kernel, slot)?;
+				continue;
+			}
+			// Posting a transaction the node already has fails, so it's checked for first
+			let accepted = self.node_has_tx(&pending.tx, &kernel)
+				|| self
+					.node
+					.post_tx(&pending.tx, !self.server_config.dandelion_stem)
+					.is_ok();
+			if !accepted {
+				warn!(
+					"Dropping queued transaction of round {}, which the node didn't accept",
+					pending.round_id
+				);
+				store
+					.delete_pending_tx(&pending.kernel_hash)
+					.map_err(SwapError::StoreError)?;
+				continue;
+			}
+			self.record_queued_tx(store, &pending, &kernel)?;
+		}
+		Ok(())
+	}
+
+	/// Follows up on a round transaction handed to the leader of 'slot', whose round was recorded
+	/// when it was handed over. Once the node has it, it's removed from the queue. If the slot
+	/// has passed without the leader posting it, it's posted directly, and if the node won't take
+	/// it either, its swaps are released as they would be at startup ('reconcile_in_process').
+	fn follow_up_handed_off_tx(
+		&self,
+		store: &SwapStore,
+		pending: &PendingTxData,
+		kernel: &TxKernel,
+		slot: u64,
+	) -> Result<(), SwapError> {
+		let has_tx = self.node_has_tx(&pending.tx, kernel);
+		if !has_tx {
+			let current = self.coordinator.as_ref().map(|c| c.current_slot());
+			if current.map_or(false, |current| current <= slot) {
+				return Ok(());
+			}
+			warn!(
+				"The leader of slot {} didn't post the transaction of round {}. Posting it directly.",
+				slot, pending.round_id
+			);
+			if let Err(e) = self
+				.node
+				.post_tx(&pending.tx, !self.server_config.dandelion_stem)
+			{
+				warn!(
+					"Releasing the swaps of round {}, whose transaction the node didn't accept: {}",
+					pending.round_id, e
+				);
+				let mut swaps = Vec::new();
+				for input_commit in &pending.input_commits {
+					if let Ok(swap) = store.get_swap(input_commit) {
+						if swap.status.kernel_hash() == Some(pending.kernel_hash) {
+							swaps.push(swap);
+						}
+					}
+				}
+				let swaps = self.release_swaps(swaps, &mut ReconciliationReport::default());
+				store
+					.update_swaps_batch(&swaps)
+					.map_err(SwapError::StoreError)?;
+				for swap in &swaps {
+					self.replicate(swap);
+				}
+			}
+		}
+		store
+			.delete_pending_tx(&pending.kernel_hash)
+			.map_err(SwapError::StoreError)
+	}
+
+	/// Records the rounds whose transaction was queued and posted, but not recorded before the
+	/// server stopped, leaving their swaps unprocessed though their inputs were already spent.
+	/// Only transactions the node has (on chain, or in its mempool) are recorded. The rest are
+	/// left queued, to be posted again by the next round. Transactions handed to a leader were
+	/// recorded when they were handed over, so they're just removed from the queue once the node
+	/// has them.
+	/// Returns the number of rounds recorded.
+	pub fn reconcile_queued_txs(&self) -> Result<usize, SwapError> {
+		let locked_store = self.store.lock().unwrap();
+		let queued: Vec<PendingTxData> = locked_store
+			.pending_txs_iter()
+			.map_err(SwapError::StoreError)?
+			.collect();
+		let mut recorded = 0;
+		for pending in queued {
+			let kernel = match wallet::round_kernel(&pending.tx) {
+				Some(kernel) => kernel,
+				None => continue,
+			};
+			if pending.handed_off_slot.is_some() {
+				if self.node_has_tx(&pending.tx, &kernel) {
+					locked_store
+						.delete_pending_tx(&pending.kernel_hash)
+						.map_err(SwapError::StoreError)?;
+				}
+			} else if self.node_has_tx(&pending.tx, &kernel) {
+				self.record_queued_tx(&locked_store, &pending, &kernel)?;
+				recorded += 1;
+			} else {
+				info!(
+					"Queued transaction of round {} isn't known to the node. It's posted again in the next round.",
+					pending.round_id
+				);
+			}
+		}
+		Ok(recorded)
+	}
+
+	/// Asks the node what became of each round with swaps still in process, since the server
+	/// doesn't know what happened to them while it was stopped. Swaps of rounds on chain are
+	/// completed. A round that's neither on chain nor in the mempool is posted again if its
+	/// transaction was kept, and otherwise its swaps go back to unprocessed, or fail if their
+	/// input has since been spent elsewhere.
+	pub fn reconcile_in_process(&self) -> Result<ReconciliationReport, SwapError> {
+		let locked_store = self.store.lock().unwrap();
+		let mut rounds: HashMap<Hash, Vec<SwapData>> = HashMap::new();
+		for swap in locked_store.swaps_iter().map_err(SwapError::StoreError)? {
+			if let SwapStatus::InProcess { kernel_hash } = swap.status {
+				rounds.entry(kernel_hash).or_default().push(swap);
+			}
+		}
+
+		let mut report = ReconciliationReport::default();
+		for (kernel_hash, mut swaps) in rounds {
+			let excess = locked_store
+				.get_kernel_excess(&kernel_hash)
+				.map_err(SwapError::StoreError)?;
+			let height = match excess.map(|excess| self.node.get_kernel_height(&excess)) {
+				Some(Ok(height)) => height,
+				Some(Err(e)) => {
+					warn!("Failed to look up kernel {}: {}", kernel_hash.to_hex(), e);
+					report.unchecked += swaps.len();
+					continue;
+				}
+				None => None,
+			};
+
+			if let Some(height) = height {
+				let block_hash = match self.node.get_block_hash(height) {
+					Ok(Some(block_hash)) => block_hash,
+					_ => {
+						report.unchecked += swaps.len();
+						continue;
+					}
+				};
+				for swap in &mut swaps {
+					swap.status = SwapStatus::Completed {
+						kernel_hash,
+						block_hash,
+					};
+				}
+				report.completed += swaps.len();
+			} else {
+				let in_pool = self
+					.node
+					.is_output_in_pool(&swaps[0].output_commit)
+					.unwrap_or(false);
+				let reposted = !in_pool
+					&& match locked_store
+						.get_round_tx(&kernel_hash)
+						.map_err(SwapError::StoreError)?
+					{
+						Some(tx) => self
+							.node
+							.post_tx(&tx, !self.server_config.dandelion_stem)
+							.is_ok(),
+						None => false,
+					};
+				if in_pool || reposted {
+					report.pending += swaps.len();
+					continue;
+				}
+
+				warn!(
+					"Round transaction with kernel {} was dropped by the node",
+					kernel_hash.to_hex()
+				);
+				swaps = self.release_swaps(swaps, &mut report);
+			}
+
+			locked_store
+				.update_swaps_batch(&swaps)
+				.map_err(SwapError::StoreError)?;
+			for swap in &swaps {
+				self.replicate(swap);
+			}
+		}
+		Ok(report)
+	}
+
+	/// Puts the swaps of a round transaction the node dropped back to unprocessed, or fails them if
+	/// their input has since been spent elsewhere. Swaps whose input can't be looked up are left
+	/// out, to be checked again later.
+	fn release_swaps(
+		&self,
+		swaps: Vec<SwapData>,
+		report: &mut ReconciliationReport,
+	) -> Vec<SwapData> {
+		let mut released = Vec::with_capacity(swaps.len());
+		for mut swap in swaps {
+			match node::is_unspent(&self.node, &swap.input.commit) {
+				Ok(true) => {
+					swap.status = SwapStatus::Unprocessed;
+					report.unprocessed += 1;
+				}
+				Ok(false) => {
+					swap.status = SwapStatus::Failed;
+					report.failed += 1;
+				}
+				Err(_) => {
+					report.unchecked += 1;
+					continue;
+				}
+			}
+			released.push(swap);
+		}
+		released
+	}
+
+	/// Whether the transaction's kernel is on chain, or its outputs are in the node's mempool.
+	/// A node that can't be reached is taken not to have it.
+	fn node_has_tx(&self, tx: &Transaction, kernel: &TxKernel) -> bool {
+		let on_chain = self
+			.node
+			.get_kernel_height(&kernel.excess)
+			.unwrap_or(None)
+			.is_some();
+		on_chain
+			|| tx.outputs().first().map_or(false, |output| {
+				self.node
+					.is_output_in_pool(&output.commitment())
+					.unwrap_or(false)
+			})
+	}
+
+	/// Records the round of a queued transaction the node has accepted, removing it from the queue
+	fn record_queued_tx(
+		&self,
+		store: &SwapStore,
+		pending: &PendingTxData,
+		kernel: &TxKernel,
+	) -> Result<(), SwapError> {
+		let mut swaps = Vec::new();
+		for input_commit in &pending.input_commits {
+			if let Ok(mut swap) = store.get_swap(input_commit) {
+				swap.status = SwapStatus::InProcess {
+					kernel_hash: pending.kernel_hash,
+				};
+				swaps.push(swap);
+			}
+		}
+		let round = RoundData {
+			round_id: pending.round_id,
+			kernel_hash: pending.kernel_hash,
+			timestamp: pending.queued_at,
+			fees: swaps.iter().map(|s| s.fee).sorted().collect(),
+			anonymity_set: anonymity_set(&swaps),
+			tx_weight: pending.tx.weight(),
+			confirmed_height: None,
+		};
+		// Any other inputs were decoys, which are now spent
+		let decoys = RoundDecoys {
+			spent: pending
+				.tx
+				.inputs_committed()
+				.into_iter()
+				.filter(|commit| !pending.input_commits.contains(commit))
+				.collect(),
+			created: vec![],
+		};
+		store
+			.save_round_batch(&round, &kernel.excess, &swaps, &vec![], None, &decoys)
+			.map_err(SwapError::StoreError)?;
+		info!(
+			"Recorded queued transaction of round {} with {} swaps",
+			pending.round_id,
+			swaps.len()
+		);
+		Ok(())
+	}
+
+	/// Whether round 'round_id' has stopped taking swaps, 'intake_cutoff' before it's scheduled.
+	/// Swaps accepted after that wait for the round after it, so a round never executes with
+	/// swaps that arrived moments before it and may not have been fully checked.
+	fn intake_closed(&self, now: u64, height: u64, round_id: u64) -> bool {
+		let cutoff = match self.server_config.intake_cutoff {
+			Some(cutoff) => cutoff,
+			None => return false,
+		};
+		let block_time_s = self.block_time_s.load(Ordering::Relaxed);
+		match self.acceptance_window() {
+			Some(window) if window.round_id == round_id => window.closes_within(
+				now,
+				height,
+				cutoff.as_secs(block_time_s),
+				cutoff.as_blocks(block_time_s),
+			),
+			_ => false,
+		}
+	}
+
+	/// Salt used to hide an input commitment in the pending commitment's leaves.
+	/// Derived from the server key, so it's stable for the life of the swap without being stored.
+	fn pending_salt(&self, input_commit: &Commitment) -> MerkleHash {
+		let mut hasher = Blake2b::with_key(32, &self.server_config.state_key().0);
+		hasher.update(b"PENDING_SALT");
+		hasher.update(&input_commit.0);
+
+		let mut salt = [0; 32];
+		salt.copy_from_slice(hasher.finalize().as_bytes());
+		salt
+	}
+
+	/// Mask for the blind of the decoy output 'commit', so the blinds of the server's own outputs
+	/// aren't stored as they are. Derived from the state key, like 'pending_salt'.
+	fn decoy_mask(&self, commit: &Commitment) -> Result<SecretKey, secp256k1zkp::Error> {
+		let mut hasher = Blake2b::with_key(32, &self.server_config.state_key().0);
+		hasher.update(b"DECOY_BLIND");
+		hasher.update(&commit.0);
+		SecretKey::from_slice(&Secp256k1::new(), hasher.finalize().as_bytes())
+	}
+
+	/// Masks the blind of a new decoy output before it's stored
+	fn mask_decoy_blind(
+		&self,
+		commit: &Commitment,
+		blind: &SecretKey,
+	) -> Result<SecretKey, secp256k1zkp::Error> {
+		let secp = Secp256k1::with_caps(secp256k1zkp::ContextFlag::Commit);
+		secp.blind_sum(vec![blind.clone(), self.decoy_mask(commit)?], vec![])
+	}
+
+	/// The blind of a stored decoy output
+	fn decoy_blind(&self, decoy: &DecoyData) -> Result<SecretKey, secp256k1zkp::Error> {
+		if !decoy.masked {
+			return Ok(decoy.blind.clone());
+		}
+		let secp = Secp256k1::with_caps(secp256k1zkp::ContextFlag::Commit);
+		secp.blind_sum(
+			vec![decoy.blind.clone()],
+			vec![self.decoy_mask(&decoy.commit)?],
+		)
+	}
+
+	/// Whether the onion's view tag (if it has one) matches either of the server's keys
+	fn matches_view_tag(&self, onion: &Onion) -> bool {
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0);
+
+		let keys = self.keys.read().unwrap();
+		onion.matches_view_tag(&keys.current)
+			|| keys
+				.unexpired_previous(now)
+				.map(|previous| onion.matches_view_tag(&previous.key))
+				.unwrap_or(false)
+	}
+
+	/// The cipher suites onions may be encrypted with
+	fn cipher_suites(&self) -> Vec<CipherSuite> {
+		self.server_config
+			.accepted_cipher_suites
+			.clone()
+			.unwrap_or_else(|| CipherSuite::ALL.to_vec())
+	}
+
+	/// Peels the onion with the current key, falling back to the previous key during its grace window
+	fn peel_onion(&self, onion: &Onion) -> Result<(Payload, Onion), SwapError> {
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0);
+
+		let keys = self.keys.read().unwrap();
+		match onion.peel_layer(&keys.current) {
+			Ok(peeled) => Ok(peeled),
+			Err(e) => match keys.unexpired_previous(now) {
+				Some(previous) => onion
+					.peel_layer(&previous.key)
+					.map_err(|_| SwapError::PeelOnionFailure(e)),
+				None => Err(SwapError::PeelOnionFailure(e)),
 			},
 		}
 	}
 
-	/// Single hop to demonstrate request validation and onion unwrapping.
+	/// Builds the input for an output that isn't in the UTXO set yet, if zero-conf swaps are enabled
+	/// and the output is created by a transaction in the node's mempool.
+	/// Only 'zero_conf_max_pending' such swaps are held at once, limiting the exposure to
+	/// unconfirmed transactions that never make it into a block.
+	fn check_unconfirmed_input(&self, commit: &Commitment) -> Result<Input, SwapError> {
+		let not_found = SwapError::CoinNotFound {
+			commit: commit.clone(),
+		};
+		let limit = match self.server_config.zero_conf_max_pending {
+			Some(limit) => limit,
+			None => return Err(not_found),
+		};
+		let in_pool = self
+			.node
+			.is_output_in_pool(commit)
+			.map_err(|e| SwapError::UnknownError(e.to_string()))?;
+		if !in_pool {
+			return Err(not_found);
+		}
+
+		let pending = self
+			.list_swaps()?
+			.iter()
+			.filter(|s| s.status == SwapStatus::Unprocessed && s.unconfirmed_input)
+			.count();
+		if pending >= limit as usize {
+			return Err(SwapError::UnconfirmedLimitReached {
+				commit: commit.clone(),
+				limit,
+			});
+		}
+
+		// Coinbase outputs are never in the mempool
+		Ok(Input::new(OutputFeatures::Plain, commit.clone()))
+	}
+
+	/// Checks that the comsig over 'msg' was made by the owner of 'commit',
+	/// and bound to this server (under either of its accepted keys) and 'expiry_height'
+	fn verify_comsig(
+		&self,
+		commit: &Commitment,
+		comsig: &ComSignature,
+		expiry_height: u64,
+		msg: &[u8],
+	) -> Result<(), SwapError> {
+		let keys = self.server_keys()?;
+		let verified = std::iter::once(keys.current)
+			.chain(keys.previous.map(|p| p.pubkey))
+			.any(|pubkey| {
+				let domain = SigDomain::new(&pubkey, expiry_height);
+				comsig.verify(commit, &domain, msg).is_ok()
+			});
+		if !verified {
+			return Err(SwapError::InvalidComSignature);
+		}
+		Ok(())
+	}
+
+	/// Builds decoys from the server's own outputs, enough to pad a round of 'num_swaps' swaps up to
+	/// 'min_anonymity_set' where there are spendable decoy outputs to do so.
+	/// Returns each decoy along with the record of the output it spends.
+	fn build_decoys(
+		&self,
+		store: &SwapStore,
+		num_swaps: usize,
+		next_block_height: u64,
+	) -> Result<Vec<(DecoyData, wallet::Decoy)>, Box<dyn std::error::Error>> {
+		let wanted = match self.server_config.min_anonymity_set {
+			Some(min) if (min as usize) > num_swaps => min as usize - num_swaps,
+			_ => return Ok(vec![]),
+		};
+
+		let mut decoys = Vec::new();
+		for spent in store.decoys_iter()? {
+			if decoys.len() == wanted {
+				break;
+			}
+			if !node::is_spendable(&self.node, &spent.commit, next_block_height).unwrap_or(false) {
+				continue;
+			}
+			let blind = match self.decoy_blind(&spent) {
+				Ok(blind) => blind,
+				Err(e) => {
+					warn!("Failed to unmask decoy {}: {}", spent.commit.to_hex(), e);
+					continue;
+				}
+			};
+			let decoy = wallet::build_decoy(
+				&self.wallet,
+				&spent.commit,
+				spent.amount,
+				&blind,
+				self.get_fee_base(),
+			);
+			match decoy {
+				Ok(Some(decoy)) => decoys.push((spent, decoy)),
+				Ok(None) => {}
+				Err(e) => {
+					for (_, decoy) in &decoys {
+						wallet::rollback_output(&self.wallet, &decoy.wallet_output);
+					}
+					return Err(e.into());
+				}
+			}
+		}
+		if decoys.len() < wanted {
+			self.preflight.lock().unwrap().decoys_short += 1;
+			warn!(
+				"Only {} of {} decoys available to pad round",
+				decoys.len(),
+				wanted
+			);
+		}
+		Ok(decoys)
+	}
+
+	/// Checks the change for an over-paid 'fee'. Only the last hop can pay it out, along with an
+	/// owner kernel signing for the change's blinding factor, it must leave some of the fee for
+	/// the server, and its output needs a valid rangeproof.
+	/// Peeling already split the change off the swap's output, so whatever the change output is
+	/// worth, the swap's output and change together are worth no more than was paid for.
+	fn check_fee_change(
+		&self,
+		change: &FeeChange,
+		fee: u64,
+		last_hop: bool,
+		owner_kernel: bool,
+	) -> Result<(), SwapError> {
+		if !last_hop {
+			return Err(SwapError::InvalidFeeChange(
+				"only the last hop can pay out change".to_string(),
+			));
+		}
+		// A kernel of the change's blinding factor alone would link it to the change output
+		if !owner_kernel {
+			return Err(SwapError::InvalidFeeChange(
+				"change must come with an owner kernel signing for its blinding factor".to_string(),
+			));
+		}
+		if change.value == 0 || change.value >= fee {
+			return Err(SwapError::InvalidFeeChange(format!(
+				"change of {} must be more than 0 and less than the fee of {}",
+				change.value, fee
+			)));
+		}
+		if change.output.features() != OutputFeatures::Plain {
+			return Err(SwapError::InvalidFeeChange(
+				"change output must be a plain output".to_string(),
+			));
+		}
+		if !self
+			.verifier
+			.is_valid(&change.output.commitment(), &change.output.proof)
+		{
+			return Err(SwapError::InvalidFeeChange(
+				"change output's rangeproof is invalid".to_string(),
+			));
+		}
+		Ok(())
+	}
+
+	/// Checks that the expiry height has not passed, and isn't so far off it could be replayed
+	fn check_expiry(&self, expiry_height: u64) -> Result<u64, SwapError> {
+		let height = self
+			.node
+			.get_chain_height()
+			.map_err(|e| SwapError::UnknownError(e.to_string()))?;
+		if expiry_height <= height || expiry_height > height + MAX_EXPIRY_BLOCKS {
+			return Err(SwapError::InvalidExpiry {
+				expiry_height,
+				height,
+			});
+		}
+		Ok(height)
+	}
+
+	/// Logs and counts the outcome of a swap submission
+	fn record_swap(&self, result: &Result<(), SwapError>) {
+		match result {
+			Ok(()) => debug!("Swap accepted"),
+			Err(e) => debug!("Swap rejected: {}", e),
+		}
+		self.activity.lock().unwrap().record_swap(result);
+	}
+
+	/// Validates the swap request and saves it to the store. The onion is peeled here unless
+	/// 'peeled' already holds its layer.
+	fn accept_swap(
+		&self,
+		onion: &Onion,
+		peeled: Option<(Payload, Onion)>,
+		comsig: &ComSignature,
+		expiry_height: u64,
+	) -> Result<(), SwapError> {
+		self.check_not_standby()?;
+		if !self.feature_flags()?.accept_submissions {
+			return Err(SwapError::SubmissionsPaused);
+		}
+
+		// milestone 3: check that enc_payloads length matches number of configured servers
+		if onion.enc_payloads.len() != 1 {
+			return Err(SwapError::InvalidPayloadLength {
+				expected: 1,
+				found: onion.enc_payloads.len(),
+			});
+		}
+
+		// Onions tagged for another server are turned away before any other work is done on them
+		if !self.matches_view_tag(onion) {
+			return Err(SwapError::PeelOnionFailure(OnionError::ViewTagMismatch));
+		}
+
+		let accepted = self.cipher_suites();
+		if !accepted.contains(&onion.cipher_suite) {
+			return Err(SwapError::UnsupportedCipherSuite {
+				cipher_suite: onion.cipher_suite,
+				accepted,
+			});
+		}
+
+		// Reject expired swaps, and expiries too far off to limit replays
+		let height = self.check_expiry(expiry_height)?;
+
+		// Once the upcoming round's window has closed, hold off new swaps until it executes
+		if self.server_config.acceptance_windows {
+			let now = SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.map(|d| d.as_secs())
+				.unwrap_or(0);
+			if let Some(window) = self.acceptance_window() {
+				if window.is_closed(now, height) {
+					return Err(SwapError::WindowClosed {
+						round_id: window.round_id,
+					});
+				}
+			}
+		}
+
+		// Verify commitment signature to ensure caller owns the output
+		let serialized_onion = onion
+			.serialize()
+			.map_err(|e| SwapError::UnknownError(e.to_string()))?;
+		self.verify_comsig(&onion.commit, comsig, expiry_height, &serialized_onion)?;
+
+		// Verify that commitment is unspent, or (if allowed) created by a transaction in the mempool
+		let input = node::build_input(&self.node, &onion.commit)
+			.map_err(|e| SwapError::UnknownError(e.to_string()))?;
+		let (input, unconfirmed_input) = match input {
+			Some(input) => (input, false),
+			None => (self.check_unconfirmed_input(&onion.commit)?, true),
+		};
+
+		// Coinbase outputs can't be swapped until they could be spent in the next block
+		if input.features == OutputFeatures::Coinbase {
+			let utxo = self
+				.node
+				.get_utxo(&onion.commit)
+				.map_err(|e| SwapError::UnknownError(e.to_string()))?;
+			let spendable_height = utxo
+				.and_then(|utxo| node::spendable_height(&utxo))
+				.unwrap_or(u64::MAX);
+			if spendable_height > height + 1 {
+				return Err(SwapError::CoinNotMature {
+					commit: onion.commit.clone(),
+					spendable_height,
+				});
+			}
+		}
+
+		// Confirmed inputs must be buried deep enough, while unconfirmed ones wait in the pool
+		if let (Some(required), false) = (
+			self.server_config.min_input_confirmations,
+			unconfirmed_input,
+		) {
+			let confirmations = node::confirmations(&self.node, &onion.commit, height)
+				.map_err(|e| SwapError::UnknownError(e.to_string()))?;
+			if confirmations < required {
+				return Err(SwapError::InsufficientConfirmations {
+					commit: onion.commit.clone(),
+					confirmations,
+					required,
+				});
+			}
+		}
+
+		let peeled = match peeled {
+			Some(peeled) => peeled,
+			None => self.peel_onion(&onion)?,
+		};
+
+		if let Some(policy) = &self.policy {
+			policy.check(&onion.commit, &peeled.1.commit).map_err(|r| {
+				SwapError::PolicyRejected {
+					commit: r.commit,
+					reason: r.reason,
+				}
+			})?;
+		}
+
+		// The owner's kernel can't take any of the round's fee, and must be signed by the owner
+		let mut minimum_fee = self.get_minimum_swap_fee();
+		if let Some(owner_kernel) = &peeled.0.owner_kernel {
+			if !wallet::is_owner_kernel(owner_kernel) {
+				return Err(SwapError::InvalidOwnerKernel);
+			}
+			minimum_fee += TransactionBody::weight_by_iok(0, 0, 1) * self.get_fee_base();
+		}
+
+		// Change for an over-paid fee comes out of the fee, so only what's left counts towards the
+		// minimum, which also covers the weight of the change output
+		let mut fee: u64 = peeled.0.fee.into();
+		if let Some(change) = &peeled.0.fee_change {
+			self.check_fee_change(
+				change,
+				fee,
+				peeled.0.rangeproof.is_some(),
+				peeled.0.owner_kernel.is_some(),
+			)?;
+			minimum_fee += TransactionBody::weight_by_iok(0, 1, 0) * self.get_fee_base();
+			fee -= change.value;
+		}
+
+		// Verify the fee meets the minimum, including the weight of any owner kernel
+		if fee < minimum_fee {
+			return Err(SwapError::FeeTooLow {
+				minimum_fee,
+				actual_fee: fee,
+			});
+		}
+
+		// Verify the bullet proof and build the final output
+		if let Some(r) = peeled.0.rangeproof {
+			if !self.verifier.is_valid(&peeled.1.commit, &r) {
+				return Err(SwapError::InvalidRangeproof);
+			}
+		} else {
+			// milestone 3: only the last hop will have a rangeproof
+			return Err(SwapError::MissingRangeproof);
+		}
+
+		// Verify the output is worth at least the configured minimum, by checking a rangeproof
+		// over the output commitment minus the minimum value
+		if let Some(min_output_value) = self.server_config.min_output_value.filter(|m| *m > 0) {
+			let proven = match &peeled.0.min_value_proof {
+				Some(proof) => secp::sub_value(&peeled.1.commit, min_output_value)
+					.map(|commit| self.verifier.is_valid(&commit, proof))
+					.unwrap_or(false),
+				None => false,
+			};
+			if !proven {
+				return Err(SwapError::OutputTooSmall { min_output_value });
+			}
+		}
+
+		let locked = self.store.lock().unwrap();
+		let mut accepted_round = locked.last_round_id().map_err(SwapError::StoreError)?;
+		let accepted_at = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0);
+		if self.intake_closed(accepted_at, height, accepted_round) {
+			accepted_round += 1;
+		}
+
+		let swap = SwapData {
+			excess: peeled.0.excess,
+			output_commit: peeled.1.commit,
+			rangeproof: peeled.0.rangeproof,
+			input,
+			fee,
+			onion: peeled.1,
+			status: SwapStatus::Unprocessed,
+			// The swap can't outlive the expiry its owner signed
+			expiry_height: expiry_height.min(height + self.swap_ttl_blocks()),
+			unconfirmed_input,
+			owner_kernel: peeled.0.owner_kernel,
+			accepted_at,
+			accepted_round,
+			fee_change: peeled.0.fee_change,
+		};
+		match locked.save_swap(&swap, false) {
+			Ok(()) => {}
+			Err(StoreError::AlreadyExists(_)) => {
+				// A client retrying a submission that was already accepted gets the same response,
+				// as long as the swap is still waiting for a round
+				let existing = locked
+					.get_swap(&onion.commit)
+					.map_err(SwapError::StoreError)?;
+				if is_resubmission(&existing, &swap) {
+					debug!("Swap for {:?} was already accepted", onion.commit);
+					return Ok(());
+				}
+				return Err(SwapError::AlreadySwapped {
+					commit: onion.commit.clone(),
+				});
+			}
+			Err(StoreError::DuplicateOutput(commit)) => {
+				return Err(SwapError::DuplicateOutput { commit });
+			}
+			Err(e) => return Err(SwapError::StoreError(e)),
+		}
+		self.replicate(&swap);
+		self.publish(Event::SwapAccepted {
+			commit: swap.input.commit.to_hex(),
+		});
+		Ok(())
+	}
+}
+
+/// Whether 'swap' was built from the same onion as the 'existing' swap, which hasn't been
+/// included in a round yet. The expiry is left out, since it depends on when each was submitted.
+fn is_resubmission(existing: &SwapData, swap: &SwapData) -> bool {
+	existing.status == SwapStatus::Unprocessed
+		&& existing.excess == swap.excess
+		&& existing.output_commit == swap.output_commit
+		&& existing.rangeproof == swap.rangeproof
+		&& existing.fee == swap.fee
+		&& existing.onion == swap.onion
+		&& existing.owner_kernel == swap.owner_kernel
+		&& existing.fee_change == swap.fee_change
+}
+
+/// The number of distinct outputs the swaps of a round create, which is the round's effective
+/// anonymity set
+fn anonymity_set(swaps: &[SwapData]) -> u64 {
+	swaps.iter().map(|s| s.output_commit).unique().count() as u64
+}
+
+/// Picks at most 'max_swaps' of the spendable swaps for the next round, leaving the rest for
+/// a later round. Swaps accepted at the same second are ordered by expiry, which is a fixed number
+/// of blocks after the height they were accepted at.
+fn select_for_round(
+	mut swaps: Vec<SwapData>,
+	max_swaps: Option<u32>,
+	selection: RoundSelection,
+) -> Vec<SwapData> {
+	let max_swaps = match max_swaps {
+		Some(max_swaps) if swaps.len() > max_swaps as usize => max_swaps as usize,
+		_ => return swaps,
+	};
+	match selection {
+		RoundSelection::Fifo => {
+			swaps.sort_by_key(|s| (s.accepted_at, s.expiry_height, s.input.commit))
+		}
+		RoundSelection::FeePriority => swaps.sort_by_key(|s| {
+			(
+				std::cmp::Reverse(s.fee),
+				s.accepted_at,
+				s.expiry_height,
+				s.input.commit,
+			)
+		}),
+	}
+	swaps.truncate(max_swaps);
+	swaps
+}
+
+/// Deletes unprocessed swaps that have expired as of 'height'
+fn delete_expired(store: &SwapStore, height: u64) -> Result<usize, StoreError> {
+	let expired: Vec<Commitment> = store
+		.swaps_iter()?
+		.filter(|s| s.status == SwapStatus::Unprocessed && s.expiry_height <= height)
+		.map(|s| s.input.commit)
+		.collect();
+	for input_commit in &expired {
+		store.delete_swap(input_commit)?;
+	}
+	Ok(expired.len())
+}
+
+impl ServerImpl {
+	/// Finishes the executing round, unless its transaction is deferred to be posted again
+	fn end_round(&self, round: RoundGuard<'_>) {
+		if self.deferred_round.lock().unwrap().is_some() {
+			round.keep();
+		}
+	}
+
+	/// Executes a round, while 'round_state' is held. Called again without the swaps the node
+	/// rejected the round transaction because of, if any.
+	fn run_round(&self) -> Result<Option<Transaction>, Box<dyn std::error::Error>> {
+		if self.node_stalled.load(Ordering::Relaxed) {
+			return Err(SwapError::NodeStalled.into());
+		}
+		if !self.feature_flags()?.execute_rounds {
+			return Err(SwapError::RoundsPaused.into());
+		}
+
+		// Never execute a round unless we're sure the standby can't be executing one too
+		self.check_not_standby()?;
+		if let Some(replicator) = &self.replicator {
+			replicator.check_lease().map_err(SwapError::NotPrimary)?;
+		}
+
+		// A wallet that can't build the fee output would only fail the round part way through
+		if let Err(failure) = wallet::preflight(&self.wallet) {
+			let mut counts = self.preflight.lock().unwrap();
+			match failure {
+				PreflightFailure::Unreachable(_) => counts.wallet_unreachable += 1,
+				PreflightFailure::Locked(_) => counts.wallet_locked += 1,
+			}
+			return Err(SwapError::WalletNotReady(failure.to_string()).into());
+		}
+
+		let locked_store = self.store.lock().unwrap();
+		let round_id = locked_store.last_round_id()?;
+		let _round = info_span!("round", round_id).entered();
+		self.round_state.advance(RoundPhase::Building);
+		locked_store.save_round_marker(&RoundMarker {
+			round_id,
+			started_at: SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.map(|d| d.as_secs())
+				.unwrap_or(0),
+		})?;
+		self.wallet.start_round(round_id);
+		let height = self.node.get_chain_height()?;
+		let next_block_height = height + 1;
+		let expired = delete_expired(&locked_store, height)?;
+		if expired > 0 {
+			info!("Deleted {} expired swaps", expired);
+		}
+		self.retry_queued_txs(&locked_store)?;
+
+		let spendable: Vec<SwapData> = locked_store
+			.swaps_iter()?
+			.unique_by(|s| s.output_commit)
+			.filter(|s| match s.status {
+				SwapStatus::Unprocessed => true,
+				_ => false,
+			})
+			// Swaps accepted after the round's intake closed wait for the next one
+			.filter(|s| s.accepted_round <= round_id)
+			.filter(|s| {
+				node::is_spendable(&self.node, &s.input.commit, next_block_height).unwrap_or(false)
+			})
+			// A reorg may have moved an input into a later block since its swap was accepted
+			.filter(|s| match self.server_config.min_input_confirmations {
+				Some(required) => node::confirmations(&self.node, &s.input.commit, height)
+					.map_or(false, |confirmations| confirmations >= required),
+				None => true,
+			})
+			.filter(|s| !node::is_unspent(&self.node, &s.output_commit).unwrap_or(true))
+			.collect();
+
+		if spendable.len() == 0 {
+			info!("No spendable swaps. Skipping round.");
+			// Batches handed to this node as leader are still posted, rather than left waiting
+			// for a round with swaps of its own
+			if let Some(coordinator) = &self.coordinator {
+				if let Err(e) = self.post_batches(coordinator, None) {
+					warn!("Failed to post other nodes' batches: {}", e);
+				}
+			}
+			return Ok(None);
+		}
+
+		let waiting = spendable.len();
+		let mut spendable = select_for_round(
+			spendable,
+			self.server_config.max_swaps_per_round,
+			self.server_config.round_selection,
+		);
+		if spendable.len() < waiting {
+			info!(
+				"Deferring {} swaps to a later round",
+				waiting - spendable.len()
+			);
+		}
+
+		// Selection may have ordered the swaps by when they were submitted, so shuffle them
+		// before they're used to build anything, or recorded
+		spendable.shuffle(&mut thread_rng());
+		info!("Executing round with {} swaps", spendable.len());
+		self.publish(Event::RoundStarted {
+			round_id,
+			swap_count: spendable.len(),
+		});
+
+		let mut total_fee: u64 = spendable.iter().enumerate().map(|(_, s)| s.fee).sum();
+
+		let mut inputs: Vec<Input> = spendable.iter().enumerate().map(|(_, s)| s.input).collect();
+
+		let mut outputs: Vec<Output> = spendable
+			.iter()
+			.enumerate()
+			.map(|(_, s)| {
+				Output::new(
+					OutputFeatures::Plain,
+					s.output_commit,
+					s.rangeproof.unwrap(),
+				)
+			})
+			.collect();
+
+		let mut excesses: Vec<SecretKey> = spendable
+			.iter()
+			.enumerate()
+			.map(|(_, s)| s.excess.clone())
+			.collect();
+
+		let owner_kernels: Vec<TxKernel> = spendable
+			.iter()
+			.filter_map(|s| s.owner_kernel.clone())
+			.collect();
+
+		// Change for over-paid fees is paid out alongside the swaps' outputs, balanced by their
+		// owner kernels
+		for change in spendable.iter().filter_map(|s| s.fee_change.as_ref()) {
+			outputs.push(change.output.clone());
+		}
+
+		// Pad the round with decoys, each paying for its own weight, so they don't change
+		// what the swaps pay or what the server collects
+		let decoys = self.build_decoys(&locked_store, spendable.len(), next_block_height)?;
+		for (_, decoy) in &decoys {
+			inputs.push(decoy.input);
+			outputs.push(decoy.output.clone());
+			excesses.push(decoy.excess.clone());
+			total_fee += decoy.fee;
+		}
+		let rollback_decoys = || {
+			for (_, decoy) in &decoys {
+				wallet::rollback_output(&self.wallet, &decoy.wallet_output);
+			}
+		};
+
+		let (tx, wallet_output) = wallet::assemble_tx(
+			&self.wallet,
+			&inputs,
+			&outputs,
+			self.get_fee_base(),
+			total_fee,
+			&excesses,
+			&owner_kernels,
+			wallet::RoundKernelKind::new(self.server_config.nrd_relative_height),
+		)
+		.map_err(|e| {
+			rollback_decoys();
+			self.publish(Event::RoundFailed {
+				round_id,
+				reason: e.to_string(),
+			});
+			e
+		})?;
+
+		let kernel = wallet::round_kernel(&tx).unwrap();
+		let kernel_hash = kernel.hash();
+		let timestamp = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0);
+
+		// Queue the transaction before it's posted, so it can't be lost if posting fails part way.
+		// Statuses are only updated once the node has accepted it.
+		let built = BuiltRound {
+			pending: PendingTxData {
+				round_id,
+				kernel_hash,
+				tx,
+				input_commits: spendable.iter().map(|s| s.input.commit).collect(),
+				attempts: 0,
+				queued_at: timestamp,
+				handed_off_slot: None,
+			},
+			swaps: spendable,
+			kernel,
+			wallet_output,
+			decoys,
+			last_attempt: Instant::now(),
+		};
+		if let Err(e) = locked_store.save_pending_tx(&built.pending) {
+			error!("Failed to queue round transaction: {}", e);
+			self.abandon_round(&locked_store, &built, &e.to_string());
+			return Err(e.into());
+		}
+		drop(_round);
+		self.post_round(locked_store, built)
+	}
+
+	/// Posts a built round's transaction once, recording the round if the node accepts it.
+	/// Otherwise the swaps the node rejected it because of are failed, and the round built again
+	/// without them. When none are to blame (e.g. the node is unreachable), the round is deferred
+	/// for the scheduler to post again with 'retry_round', until 'POST_TX_ATTEMPTS' attempts have
+	/// failed. The backoff is never waited out here, since the server and the store are both held.
+	fn post_round(
+		&self,
+		locked_store: MutexGuard<SwapStore>,
+		mut built: BuiltRound,
+	) -> Result<Option<Transaction>, Box<dyn std::error::Error>> {
+		let round_id = built.pending.round_id;
+		let _round = info_span!("round", round_id).entered();
+		self.round_state.advance(RoundPhase::Posting);
+		let e = match self.post_round_tx(&built.pending.tx) {
+			Ok(None) => return self.record_round(&locked_store, built).map(Some),
+			Ok(Some(slot)) => {
+				// The leader may never post it, so it's queued again until the node has it
+				let mut pending = built.pending.clone();
+				pending.handed_off_slot = Some(slot);
+				let tx = self.record_round(&locked_store, built)?;
+				if let Err(e) = locked_store.save_pending_tx(&pending) {
+					warn!("Failed to queue transaction handed to the leader: {}", e);
+				}
+				return Ok(Some(tx));
+			}
+			Err(e) => e,
+		};
+
+		built.pending.attempts += 1;
+		if let Err(e) = locked_store.save_pending_tx(&built.pending) {
+			warn!("Failed to record attempt to post round transaction: {}", e);
+		}
+		let offending = self.offending_swaps(&built.swaps, &e.to_string());
+		if offending.is_empty() && built.pending.attempts < POST_TX_ATTEMPTS {
+			warn!(
+				"Failed to post round transaction (attempt {}): {}. Posting it again later.",
+				built.pending.attempts, e
+			);
+			built.last_attempt = Instant::now();
+			*self.deferred_round.lock().unwrap() = Some(built);
+			return Ok(None);
+		}
+
+		error!("Failed to post round transaction: {}", e);
+		self.abandon_round(&locked_store, &built, &e.to_string());
+
+		// Retry the round without the swaps the node rejected it for. Each retry drops at
+		// least one swap, so this ends once the node accepts the rest, or none are left.
+		if offending.is_empty() {
+			return Err(e.into());
+		}
+		for mut swap in offending {
+			warn!(
+				"Dropping swap of {} from the round, which the node rejected because of it",
+				swap.input.commit.to_hex()
+			);
+			swap.status = SwapStatus::Failed;
+			locked_store.save_swap(&swap, true)?;
+			self.replicate(&swap);
+			self.publish(Event::SwapFailed {
+				commit: swap.input.commit.to_hex(),
+				round_id,
+			});
+		}
+		drop(locked_store);
+		drop(_round);
+		self.run_round()
+	}
+
+	/// Gives up on a built round: its transaction is removed from the queue, and the wallet
+	/// outputs it would have created are cancelled. Its swaps are left for a later round.
+	fn abandon_round(&self, store: &SwapStore, built: &BuiltRound, reason: &str) {
+		self.publish(Event::RoundFailed {
+			round_id: built.pending.round_id,
+			reason: reason.to_string(),
+		});
+		if let Some(wallet_output) = &built.wallet_output {
+			wallet::rollback_output(&self.wallet, wallet_output);
+		}
+		for (_, decoy) in &built.decoys {
+			wallet::rollback_output(&self.wallet, &decoy.wallet_output);
+		}
+		if let Err(e) = store.delete_pending_tx(&built.pending.kernel_hash) {
+			warn!("Failed to remove queued round transaction: {}", e);
+		}
+	}
+
+	/// Records a round the node accepted the transaction of, along with its swaps' new statuses,
+	/// their receipts, the fees collected and the decoys spent and created
+	fn record_round(
+		&self,
+		locked_store: &SwapStore,
+		built: BuiltRound,
+	) -> Result<Transaction, Box<dyn std::error::Error>> {
+		let BuiltRound {
+			swaps: spendable,
+			pending,
+			kernel,
+			wallet_output,
+			decoys,
+			..
+		} = built;
+		let PendingTxData {
+			round_id,
+			kernel_hash,
+			tx,
+			queued_at: timestamp,
+			..
+		} = pending;
+		self.activity.lock().unwrap().record_round(spendable.len());
+
+		info!(
+			"Posted round transaction with kernel {}",
+			kernel_hash.to_hex()
+		);
+		let round = RoundData {
+			round_id,
+			kernel_hash,
+			timestamp,
+			fees: spendable.iter().map(|s| s.fee).sorted().collect(),
+			anonymity_set: anonymity_set(&spendable),
+			tx_weight: tx.weight(),
+			confirmed_height: None,
+		};
+
+		// Update status to in process
+		let swaps: Vec<SwapData> = spendable
+			.into_iter()
+			.map(|mut swap| {
+				swap.status = SwapStatus::InProcess { kernel_hash };
+				swap
+			})
+			.collect();
+
+		// Each swap also gets a signed receipt, tagged with the round's acceptance window
+		let key = self.keys.read().unwrap().current.clone();
+		let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &key)?;
+		let mut receipts = Vec::with_capacity(swaps.len());
+		for swap in &swaps {
+			let msg = SwapReceipt::message(
+				&swap.input.commit,
+				&swap.output_commit,
+				&kernel_hash,
+				round_id,
+			)?;
+			receipts.push(ReceiptData {
+				input_commit: swap.input.commit,
+				output_commit: swap.output_commit,
+				kernel_hash,
+				round_id,
+				pubkey,
+				signature: secp::sign(&key, &msg)?,
+			});
+		}
+
+		// Replace the spent decoys with the new outputs. While decoys are enabled, the output
+		// collecting the fees also joins them, to fund decoys for later rounds.
+		let mut new_decoys: Vec<&WalletOutput> =
+			decoys.iter().map(|(_, d)| &d.wallet_output).collect();
+		if self.server_config.min_anonymity_set.is_some() {
+			new_decoys.extend(wallet_output.iter());
+		}
+		let round_decoys = RoundDecoys {
+			spent: decoys.iter().map(|(spent, _)| spent.commit).collect(),
+			created: new_decoys
+				.into_iter()
+				.filter_map(
+					|output| match self.mask_decoy_blind(&output.commit, &output.blind) {
+						Ok(blind) => Some(DecoyData {
+							commit: output.commit,
+							amount: output.amount,
+							blind,
+							masked: true,
+							kernel_hash,
+						}),
+						Err(e) => {
+							warn!("Failed to mask decoy {}: {}", output.commit.to_hex(), e);
+							None
+						}
+					},
+				)
+				.collect(),
+		};
+
+		// Record the fees we collected
+		let fee_output = wallet_output.map(|wallet_output| FeeOutputData {
+			output_commit: wallet_output.commit,
+			amount: wallet_output.amount,
+			kernel_hash,
+			timestamp,
+		});
+
+		self.round_state.advance(RoundPhase::Recording);
+		// The round, its swaps' statuses, receipts, fees and decoys are all written in one transaction
+		locked_store.save_round_batch(
+			&round,
+			&kernel.excess,
+			&swaps,
+			&receipts,
+			fee_output.as_ref(),
+			&round_decoys,
+		)?;
+		for swap in &swaps {
+			self.replicate(swap);
+			self.publish(Event::SwapIncluded {
+				commit: swap.input.commit.to_hex(),
+				round_id,
+				kernel_hash: kernel_hash.to_hex(),
+			});
+		}
+		self.publish(Event::RoundPosted {
+			round_id,
+			kernel_hash: kernel_hash.to_hex(),
+			swap_count: swaps.len(),
+		});
+
+		Ok(tx)
+	}
+}
+
+impl Server for ServerImpl {
+	fn swap(
+		&self,
+		onion: &Onion,
+		comsig: &ComSignature,
+		expiry_height: u64,
+	) -> Result<(), SwapError> {
+		let result = self.accept_swap(onion, None, comsig, expiry_height);
+		self.record_swap(&result);
+		result
+	}
+
+	fn swap_peeled(
+		&self,
+		onion: &Onion,
+		peeled: (Payload, Onion),
+		comsig: &ComSignature,
+		expiry_height: u64,
+	) -> Result<(), SwapError> {
+		let result = self.accept_swap(onion, Some(peeled), comsig, expiry_height);
+		self.record_swap(&result);
+		result
+	}
+
+	fn execute_round(&self) -> Result<Option<Transaction>, Box<dyn std::error::Error>> {
+		// Rounds never overlap. The server is held for the whole round, so this only catches one
+		// started while the last is waiting to post its transaction again (see 'RoundState').
+		let round = self.round_state.begin(&self.store)?;
+		if let Err(e) = self.clear_interrupted_round() {
+			warn!("Failed to check for an interrupted round: {}", e);
+		}
+		let result = self.run_round();
+		self.end_round(round);
+		result
+	}
+
+	fn retry_round(
+		&self,
+		retry_delay: Duration,
+	) -> Result<Option<Transaction>, Box<dyn std::error::Error>> {
+		let built = {
+			let mut deferred = self.deferred_round.lock().unwrap();
+			let due = deferred.as_ref().map_or(false, |built| {
+				built.last_attempt.elapsed()
+					>= retry_delay * 2u32.pow(built.pending.attempts.saturating_sub(1))
+			});
+			if !due {
+				return Ok(None);
+			}
+			deferred.take().unwrap()
+		};
+		let round = self.round_state.resume(&self.store);
+
+		// The standby may have taken over since the transaction was last posted
+		let locked_store = self.store.lock().unwrap();
+		let primary = self
+			.check_not_standby()
+			.and_then(|_| match &self.replicator {
+				Some(replicator) => replicator.check_lease().map_err(SwapError::NotPrimary),
+				None => Ok(()),
+			});
+		let result = match primary {
+			Ok(()) => self.post_round(locked_store, built),
+			Err(e) => {
+				self.abandon_round(&locked_store, &built, &e.to_string());
+				drop(locked_store);
+				Err(e.into())
+			}
+		};
+		self.end_round(round);
+		result
+	}
+
+	fn status(&self) -> Result<ServerStatus, SwapError> {
+		let mut status = ServerStatus::default();
+		if self.node_stalled.load(Ordering::Relaxed) {
+			status.health = Health::Degraded(SwapError::NodeStalled.to_string());
+		} else if let Err(e) = self.check_not_standby() {
+			status.health = Health::Degraded(e.to_string());
+		} else if let Some(replicator) = &self.replicator {
+			if let Err(reason) = replicator.check_lease() {
+				status.health = Health::Degraded(SwapError::NotPrimary(reason).to_string());
+			}
+		}
+
+		for swap in self.list_swaps()? {
+			match swap.status {
+				SwapStatus::Unprocessed => status.unprocessed += 1,
+				SwapStatus::InProcess { .. } => status.in_process += 1,
+				SwapStatus::Completed { .. } => status.completed += 1,
+				SwapStatus::Failed => status.failed += 1,
+			}
+		}
+		status.features = self.feature_flags()?;
+		Ok(status)
+	}
+
+	fn list_swaps(&self) -> Result<Vec<SwapData>, SwapError> {
+		let locked_store = self.store.lock().unwrap();
+		let swaps: Vec<SwapData> = locked_store
+			.swaps_iter()
+			.map_err(SwapError::StoreError)?
+			.collect();
+		Ok(swaps)
+	}
+
+	fn expire_swaps(&self) -> Result<usize, SwapError> {
+		let height = self
+			.node
+			.get_chain_height()
+			.map_err(|e| SwapError::UnknownError(e.to_string()))?;
+		let locked_store = self.store.lock().unwrap();
+		delete_expired(&locked_store, height).map_err(SwapError::StoreError)
+	}
+
+	fn fee_outputs(&self) -> Result<Vec<FeeOutputData>, SwapError> {
+		let locked_store = self.store.lock().unwrap();
+		let fee_outputs = locked_store
+			.fee_outputs_iter()
+			.map_err(SwapError::StoreError)?
+			.collect();
+		Ok(fee_outputs)
+	}
+
+	fn list_rounds(&self) -> Result<Vec<RoundData>, SwapError> {
+		let locked_store = self.store.lock().unwrap();
+		let mut rounds: Vec<RoundData> = locked_store
+			.rounds_iter()
+			.map_err(SwapError::StoreError)?
+			.collect();
+		self.confirm_rounds(&locked_store, &mut rounds)?;
+
+		rounds.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+		Ok(rounds)
+	}
+
+	fn get_round(
+		&self,
+		kernel_hash: &Hash,
+	) -> Result<Option<(RoundData, Vec<SwapData>)>, SwapError> {
+		let locked_store = self.store.lock().unwrap();
+		let mut rounds: Vec<RoundData> = locked_store
+			.get_round(kernel_hash)
+			.map_err(SwapError::StoreError)?
+			.into_iter()
+			.collect();
+		self.confirm_rounds(&locked_store, &mut rounds)?;
+
+		match rounds.pop() {
+			Some(round) => {
+				let swaps = locked_store
+					.get_swaps_by_kernel(kernel_hash)
+					.map_err(SwapError::StoreError)?;
+				Ok(Some((round, swaps)))
+			}
+			None => Ok(None),
+		}
+	}
+
+	fn check_confirmations(&self) -> Result<usize, SwapError> {
+		let locked_store = self.store.lock().unwrap();
+		let mut rounds: Vec<RoundData> = locked_store
+			.rounds_iter()
+			.map_err(SwapError::StoreError)?
+			.filter(|r| r.confirmed_height.is_none())
+			.collect();
+		let confirmed = self.confirm_rounds(&locked_store, &mut rounds)?;
+
+		// Rounds buried deep enough to be final don't need their transaction any more
+		if let Ok(height) = self.node.get_chain_height() {
+			let final_rounds = locked_store
+				.rounds_iter()
+				.map_err(SwapError::StoreError)?
+				.filter(|r| {
+					r.confirmed_height
+						.map(|h| h + REORG_HORIZON_BLOCKS <= height)
+						.unwrap_or(false)
+				});
+			for round in final_rounds {
+				locked_store
+					.delete_round_tx(&round.kernel_hash)
+					.map_err(SwapError::StoreError)?;
+			}
+		}
+		Ok(confirmed.len())
+	}
+
+	fn handle_reorg(&self, height: u64) -> Result<usize, SwapError> {
+		let locked_store = self.store.lock().unwrap();
+		let rounds: Vec<RoundData> = locked_store
+			.rounds_iter()
+			.map_err(SwapError::StoreError)?
+			.filter(|r| {
+				r.confirmed_height
+					.map(|h| h + REORG_HORIZON_BLOCKS > height)
+					.unwrap_or(false)
+			})
+			.collect();
+
+		let mut reverted = 0;
+		for mut round in rounds {
+			let excess = match locked_store
+				.get_kernel_excess(&round.kernel_hash)
+				.map_err(SwapError::StoreError)?
+			{
+				Some(excess) => excess,
+				None => continue,
+			};
+			// Unlike when confirming rounds, a node that can't be reached fails the check,
+			// so it's tried again rather than leaving a reorged round marked confirmed
+			let kernel_height = self
+				.node
+				.get_kernel_height(&excess)
+				.map_err(|e| SwapError::UnknownError(e.to_string()))?;
+			if kernel_height == round.confirmed_height {
+				continue;
+			}
+			round.confirmed_height = kernel_height;
+			if kernel_height.is_some() {
+				// Mined again in a different block of the new chain
+				locked_store
+					.save_round(&round)
+					.map_err(SwapError::StoreError)?;
+				continue;
+			}
+
+			warn!(
+				"ALERT: Kernel of round {} is no longer on chain after a reorg. Reverting its swaps and posting it again.",
+				round.round_id
+			);
+			let swaps: Vec<SwapData> = locked_store
+				.get_swaps_by_kernel(&round.kernel_hash)
+				.map_err(SwapError::StoreError)?
+				.into_iter()
+				.filter(|s| matches!(s.status, SwapStatus::Completed { .. }))
+				.map(|mut s| {
+					s.status = SwapStatus::InProcess {
+						kernel_hash: round.kernel_hash,
+					};
+					s
+				})
+				.collect();
+			locked_store
+				.update_swaps_batch(&swaps)
+				.map_err(SwapError::StoreError)?;
+			locked_store
+				.save_round(&round)
+				.map_err(SwapError::StoreError)?;
+			for swap in &swaps {
+				self.replicate(swap);
+			}
+
+			match locked_store
+				.get_round_tx(&round.kernel_hash)
+				.map_err(SwapError::StoreError)?
+			{
+				Some(tx) => {
+					if let Err(e) = self.node.post_tx(&tx, !self.server_config.dandelion_stem) {
+						warn!(
+							"Failed to post the transaction of round {} again: {}",
+							round.round_id, e
+						);
+					}
+				}
+				None => warn!(
+					"The transaction of round {} wasn't kept, so it can't be posted again",
+					round.round_id
+				),
+			}
+			reverted += 1;
+		}
+		Ok(reverted)
+	}
+
+	fn feature_flags(&self) -> Result<FeatureFlags, SwapError> {
+		self.store
+			.lock()
+			.unwrap()
+			.feature_flags()
+			.map_err(SwapError::StoreError)
+	}
+
+	fn set_feature(&self, feature: Feature, enabled: bool) -> Result<FeatureFlags, SwapError> {
+		let locked_store = self.store.lock().unwrap();
+		let mut flags = locked_store
+			.feature_flags()
+			.map_err(SwapError::StoreError)?;
+		flags.set(feature, enabled);
+		locked_store
+			.save_feature_flags(&flags)
+			.map_err(SwapError::StoreError)?;
+		info!(
+			"{} {}",
+			if enabled { "Enabled" } else { "Disabled" },
+			feature
+		);
+		Ok(flags)
+	}
+
+	fn daily_report(&self, day: u64) -> Result<DailyReport, SwapError> {
+		let activity = self.activity.lock().unwrap().get(day);
+		Ok(DailyReport::new(day, &activity, &self.fee_outputs()?))
+	}
+
+	fn preflight_counts(&self) -> PreflightCounts {
+		self.preflight.lock().unwrap().clone()
+	}
+
+	fn set_node_stalled(&self, stalled: bool) {
+		self.node_stalled.store(stalled, Ordering::Relaxed);
+	}
+
+	fn set_block_time(&self, block_time_s: u64) {
+		self.block_time_s.store(block_time_s, Ordering::Relaxed);
+	}
+
+	fn update_config(&mut self, server_config: ServerConfig) {
+		self.server_config = server_config;
+	}
+
+	fn commit_pending(&self) -> Result<PendingCommitment, SwapError> {
+		// Leaves are sorted so their position doesn't reveal the order swaps were submitted in
+		let mut leaves: Vec<(MerkleHash, Commitment)> = self
+			.list_swaps()?
+			.iter()
+			.filter(|s| s.status == SwapStatus::Unprocessed)
+			.map(|s| {
+				let salt = self.pending_salt(&s.input.commit);
+				let leaf = merkle::hash_leaf(&[&salt[..], &s.input.commit.0[..]].concat());
+				(leaf, s.input.commit)
+			})
+			.collect();
+		leaves.sort_by(|a, b| a.0.cmp(&b.0));
+
+		let tree = MerkleTree::new(leaves.iter().map(|(leaf, _)| leaf.clone()).collect());
+		let indices: HashMap<Commitment, usize> = leaves
+			.iter()
+			.enumerate()
+			.map(|(i, (_, commit))| (commit.clone(), i))
+			.collect();
+
+		let root = tree.root();
+		let size = leaves.len() as u64;
+		let timestamp = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0);
+		let msg = PendingCommitment::message(&root, size, timestamp)?;
+		let key = self.keys.read().unwrap().current.clone();
+		let signature =
+			secp::sign(&key, &msg).map_err(|e| SwapError::UnknownError(e.to_string()))?;
+		let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &key)
+			.map_err(|e| SwapError::UnknownError(e.to_string()))?;
+
+		let commitment = PendingCommitment {
+			root: root.to_hex(),
+			size,
+			timestamp,
+			pubkey,
+			signature,
+		};
+		*self.pending.write().unwrap() = Some(PendingSnapshot {
+			commitment: commitment.clone(),
+			tree,
+			indices,
+		});
+		Ok(commitment)
+	}
+
+	fn get_pending_commitment(&self) -> Option<PendingCommitment> {
+		self.pending
+			.read()
+			.unwrap()
+			.as_ref()
+			.map(|p| p.commitment.clone())
+	}
+
+	fn check_swap(
+		&self,
+		input_commit: &Commitment,
+		comsig: &ComSignature,
+		expiry_height: u64,
+	) -> Result<SwapCheck, SwapError> {
+		self.check_expiry(expiry_height)?;
+		self.verify_comsig(
+			input_commit,
+			comsig,
+			expiry_height,
+			&check_message(input_commit),
+		)?;
+
+		let swap = {
+			let locked_store = self.store.lock().unwrap();
+			if !locked_store
+				.swap_exists(&input_commit)
+				.map_err(SwapError::StoreError)?
+			{
+				return Err(SwapError::SwapNotFound {
+					commit: input_commit.clone(),
+				});
+			}
+			locked_store
+				.get_swap(&input_commit)
+				.map_err(SwapError::StoreError)?
+		};
+
+		let inclusion = self.pending.read().unwrap().as_ref().and_then(|pending| {
+			let index = pending.indices.get(input_commit)?;
+			let proof = pending.tree.proof(*index)?;
+			Some(InclusionProof {
+				salt: self.pending_salt(&input_commit).to_hex(),
+				proof: MerkleProofJson::from(&proof),
+				commitment: pending.commitment.clone(),
+			})
+		});
+
+		let kernel = match swap.status {
+			SwapStatus::Unprocessed | SwapStatus::Failed => None,
+			SwapStatus::InProcess { kernel_hash } | SwapStatus::Completed { kernel_hash, .. } => {
+				self.locate_kernel(&kernel_hash)?
+			}
+		};
+
+		Ok(SwapCheck {
+			status: swap.status.name().to_string(),
+			inclusion,
+			kernel,
+			accepted_at: swap.accepted_at,
+			accepted_round: swap.accepted_round,
+		})
+	}
+
+	fn accepted_round(&self, input_commit: &Commitment) -> Result<u64, SwapError> {
+		let swap = self
+			.store
+			.lock()
+			.unwrap()
+			.get_swap(&input_commit)
+			.map_err(SwapError::StoreError)?;
+		Ok(swap.accepted_round)
+	}
+
+	fn get_receipt(
+		&self,
+		input_commit: &Commitment,
+		comsig: &ComSignature,
+		expiry_height: u64,
+	) -> Result<SwapReceipt, SwapError> {
+		self.check_expiry(expiry_height)?;
+		self.verify_comsig(
+			input_commit,
+			comsig,
+			expiry_height,
+			&receipt_message(input_commit),
+		)?;
+
+		let locked_store = self.store.lock().unwrap();
+		let receipt = locked_store
+			.get_receipt(&input_commit)
+			.map_err(SwapError::StoreError)?;
+		match receipt {
+			Some(receipt) => Ok(SwapReceipt::new(
+				&receipt,
+				self.server_config.advertised_url(),
+			)),
+			None => {
+				let exists = locked_store
+					.swap_exists(&input_commit)
+					.map_err(SwapError::StoreError)?;
+				if exists {
+					Err(SwapError::ReceiptNotFound {
+						commit: input_commit.clone(),
+					})
+				} else {
+					Err(SwapError::SwapNotFound {
+						commit: input_commit.clone(),
+					})
+				}
+			}
+		}
+	}
+
+	fn peel_layer(&self, onion: &Onion) -> Result<(Payload, Onion), SwapError> {
+		self.peel_onion(onion)
+	}
+
+	fn proof_verifier(&self) -> Arc<ProofVerifier> {
+		self.verifier.clone()
+	}
+
+	fn round_state(&self) -> Arc<RoundState> {
+		self.round_state.clone()
+	}
+
+	fn cancel_swap(
+		&self,
+		input_commit: &Commitment,
+		comsig: &ComSignature,
+		expiry_height: u64,
+	) -> Result<(), SwapError> {
+		self.check_not_standby()?;
+		self.check_expiry(expiry_height)?;
+
+		let locked_store = self.store.lock().unwrap();
+		if !locked_store
+			.swap_exists(&input_commit)
+			.map_err(SwapError::StoreError)?
+		{
+			return Err(SwapError::SwapNotFound {
+				commit: input_commit.clone(),
+			});
+		}
+		let swap = locked_store
+			.get_swap(&input_commit)
+			.map_err(SwapError::StoreError)?;
+
+		let msg = cancel_message(input_commit, &swap.output_commit);
+		self.verify_comsig(input_commit, comsig, expiry_height, &msg)?;
+
+		if swap.status != SwapStatus::Unprocessed {
+			return Err(SwapError::SwapNotCancellable {
+				commit: input_commit.clone(),
+			});
+		}
+		locked_store
+			.delete_swap(&input_commit)
+			.map_err(SwapError::StoreError)?;
+		info!("Cancelled swap of {}", input_commit.to_hex());
+		Ok(())
+	}
+
+	fn promote(&self) -> Result<(), SwapError> {
+		let standby = self.standby.as_ref().ok_or(SwapError::UnknownError(
+			ReplicationError::NotStandby.to_string(),
+		))?;
+		standby
+			.promote()
+			.map_err(|e| SwapError::UnknownError(e.to_string()))
+	}
+
+	fn server_keys(&self) -> Result<ServerKeys, SwapError> {
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0);
+
+		let secp = Secp256k1::new();
+		let keys = self.keys.read().unwrap();
+		let current = PublicKey::from_secret_key(&secp, &keys.current)
+			.map_err(|e| SwapError::UnknownError(e.to_string()))?;
+		let previous = match keys.unexpired_previous(now) {
+			Some(previous) => Some(PreviousServerKey {
+				pubkey: PublicKey::from_secret_key(&secp, &previous.key)
+					.map_err(|e| SwapError::UnknownError(e.to_string()))?,
+				expires_at: previous.expires_at,
+			}),
+			None => None,
+		};
+		Ok(ServerKeys { current, previous })
+	}
+
+	fn rotate_key(&self, new_key: SecretKey, expires_at: u64) -> Result<(), SwapError> {
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0);
+
+		let mut keys = self.keys.write().unwrap();
+		if let Some(previous) = keys.unexpired_previous(now) {
+			return Err(SwapError::UnknownError(format!(
+				"The previous key is still accepted until unix time {}, so the key can't be rotated again until then",
+				previous.expires_at
+			)));
+		}
+		let old_key = std::mem::replace(&mut keys.current, new_key);
+		keys.previous = Some(PreviousKey {
+			key: old_key,
+			expires_at,
+		});
+		Ok(())
+	}
+
+	fn info(&self) -> Result<ServerInfo, SwapError> {
+		let height = self
+			.node
+			.get_chain_height()
+			.map_err(|e| SwapError::UnknownError(e.to_string()))?;
+		let next_round_height = match self.server_config.round_interval {
+			ChainInterval::Blocks(interval_blocks) if interval_blocks > 0 => {
+				Some((height / interval_blocks + 1) * interval_blocks)
+			}
+			_ => None,
+		};
+		let rounds: Vec<RoundData> = self
+			.store
+			.lock()
+			.unwrap()
+			.rounds_iter()
+			.map_err(SwapError::StoreError)?
+			.collect();
+
+		Ok(ServerInfo {
+			url: self.server_config.advertised_url(),
+			keys: self.server_keys()?,
+			fee_base: self.get_fee_base(),
+			minimum_fee: self.get_minimum_swap_fee(),
+			chain_height: height,
+			max_expiry_blocks: MAX_EXPIRY_BLOCKS,
+			max_hops: MAX_HOPS,
+			min_output_value: self.server_config.min_output_value,
+			error_codes: error_codes::error_codes(),
+			swap_ttl_blocks: self.swap_ttl_blocks(),
+			interval_s: match self.server_config.round_interval {
+				ChainInterval::Seconds(secs) => Some(secs),
+				ChainInterval::Blocks(_) => None,
+			},
+			interval_blocks: match self.server_config.round_interval {
+				ChainInterval::Seconds(_) => None,
+				ChainInterval::Blocks(blocks) => Some(blocks),
+			},
+			next_round_at: None,
+			next_round_in_s: None,
+			next_round_height,
+			window: self.acceptance_window(),
+			recent_fees: FeeStats::from_rounds(rounds),
+			peers: self
+				.peers
+				.as_ref()
+				.map(|peers| peers.statuses())
+				.unwrap_or_default(),
+			content_encodings: vec![compress::ZSTD_ENCODING.to_string()],
+			cipher_suites: self.cipher_suites(),
+		})
+	}
+
+	fn estimate_fee(&self, hops: u64) -> FeeEstimate {
+		let hop_fee = self.get_minimum_swap_fee();
+		FeeEstimate {
+			hops,
+			fee_base: self.get_fee_base(),
+			hop_fee,
+			total_fee: hop_fee.saturating_mul(hops),
+		}
+	}
+
+	fn stats(&self) -> Result<RoundStats, SwapError> {
+		let rounds: Vec<RoundData> = self
+			.store
+			.lock()
+			.unwrap()
+			.rounds_iter()
+			.map_err(SwapError::StoreError)?
+			.collect();
+		Ok(RoundStats::from_rounds(
+			&rounds,
+			self.started_at.elapsed().as_secs(),
+		))
+	}
+
+	fn open_window(
+		&self,
+		closes_at: Option<u64>,
+		closes_at_height: Option<u64>,
+	) -> Result<AcceptanceWindow, SwapError> {
+		let locked = self.store.lock().unwrap();
+		let round_id = locked.last_round_id().map_err(SwapError::StoreError)? + 1;
+		locked
+			.save_last_round_id(round_id)
+			.map_err(SwapError::StoreError)?;
+
+		let window = AcceptanceWindow {
+			round_id,
+			closes_at,
+			closes_at_height,
+		};
+		*self.window.write().unwrap() = Some(window.clone());
+		Ok(window)
+	}
+
+	fn acceptance_window(&self) -> Option<AcceptanceWindow> {
+		self.window.read().unwrap().clone()
+	}
+
+	fn locate_kernel(&self, kernel_hash: &Hash) -> Result<Option<KernelLocation>, SwapError> {
+		let excess = self
+			.store
+			.lock()
+			.unwrap()
+			.get_kernel_excess(&kernel_hash)
+			.map_err(SwapError::StoreError)?;
+		let excess = match excess {
+			Some(excess) => excess,
+			None => return Ok(None),
+		};
+
+		// Still report the excess if the node can't be reached, just without the height
+		let height = self.node.get_kernel_height(&excess).unwrap_or(None);
+		let excess = excess.to_hex();
+		let kernel_url = self
+			.server_config
+			.explorer_kernel_url
+			.as_ref()
+			.map(|url| url.replace("{excess}", &excess));
+		let block_url = match (&self.server_config.explorer_block_url, height) {
+			(Some(url), Some(height)) => Some(url.replace("{height}", &height.to_string())),
+			_ => None,
+		};
+
+		Ok(Some(KernelLocation {
+			excess,
+			height,
+			kernel_url,
+			block_url,
+		}))
+	}
+
+	fn record_rejection(&self, onion: &Onion, source: Option<String>, error: &SwapError) {
+		let capacity = self
+			.server_config
+			.rejection_log_size
+			.unwrap_or(DEFAULT_REJECTION_LOG_SIZE);
+		if capacity == 0 {
+			return;
+		}
+
+		// Only a prefix of the onion's hash is kept, enough to match it to the client's request
+		let onion_hash = match onion.serialize() {
+			Ok(bytes) => {
+				let mut hasher = Blake2b::new(32);
+				hasher.update(&bytes);
+				grin_util::to_hex(&hasher.finalize().as_bytes()[..8])
+			}
+			Err(_) => String::new(),
+		};
+		let rejection = RejectionData {
+			id: 0,
+			timestamp: SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.map(|d| d.as_secs())
+				.unwrap_or(0),
+			source,
+			onion_hash,
+			error: error_codes::error_name(error).to_string(),
+			message: error.to_string(),
+		};
+		if let Err(e) = self
+			.store
+			.lock()
+			.unwrap()
+			.save_rejection(rejection, capacity)
+		{
+			warn!("Failed to record rejected swap: {}", e);
+		}
+	}
+
+	fn list_rejections(&self, limit: usize) -> Result<Vec<RejectionData>, SwapError> {
+		let rejections = self
+			.store
+			.lock()
+			.unwrap()
+			.rejections(limit)
+			.map_err(SwapError::StoreError)?;
+		Ok(rejections)
+	}
+
+	fn count_api_key_swap(&self, key_name: &str, accepted: bool) {
+		if let Err(e) = self
+			.store
+			.lock()
+			.unwrap()
+			.count_api_key_swap(key_name, accepted)
+		{
+			warn!("Failed to count swap of API key '{}': {}", key_name, e);
+		}
+	}
+
+	fn api_key_swaps(&self) -> Result<Vec<ApiKeySwaps>, SwapError> {
+		self.store
+			.lock()
+			.unwrap()
+			.api_key_swaps()
+			.map_err(SwapError::StoreError)
+	}
+}
+
+#[cfg(test)]
+pub mod mock {
+	use super::{
+		AcceptanceWindow, FeeEstimate, KernelLocation, PendingCommitment, ProofVerifier,
+		RoundState, RoundStats, Server, ServerInfo, ServerKeys, ServerStatus, SwapCheck, SwapError,
+		SwapReceipt,
+	};
+	use crate::config::ServerConfig;
+	use crate::features::{Feature, FeatureFlags};
+	use crate::metrics::PreflightCounts;
+	use crate::onion::Onion;
+	use crate::report::{DailyActivity, DailyReport};
+	use crate::secp::{self, ComSignature, Commitment, PublicKey, Secp256k1, SecretKey};
+	use crate::store::{ApiKeySwaps, FeeOutputData, RejectionData, RoundData, SwapData};
+	use crate::types::Payload;
+
+	use grin_core::core::hash::Hash;
+	use grin_core::core::Transaction;
+	use std::collections::HashMap;
+	use std::sync::Arc;
+	use std::time::Duration;
+
+	pub struct MockServer {
+		errors: HashMap<Onion, SwapError>,
+		verifier: Arc<ProofVerifier>,
+		round_state: Arc<RoundState>,
+	}
+
+	impl MockServer {
+		pub fn new() -> MockServer {
+			MockServer {
+				errors: HashMap::new(),
+				verifier: Arc::new(ProofVerifier::new(1, 1)),
+				round_state: Arc::new(RoundState::default()),
+			}
+		}
+
+		pub fn set_response(&mut self, onion: &Onion, e: SwapError) {
+			self.errors.insert(onion.clone(), e);
+		}
+	}
+
+	impl Server for MockServer {
+		fn swap(
+			&self,
+			onion: &Onion,
+			_comsig: &ComSignature,
+			_expiry_height: u64,
+		) -> Result<(), SwapError> {
+			if let Some(e) = self.errors.get(&onion) {
+				return Err(e.clone());
+			}
+
+			Ok(())
+		}
+
+		fn swap_peeled(
+			&self,
+			onion: &Onion,
+			_peeled: (Payload, Onion),
+			comsig: &ComSignature,
+			expiry_height: u64,
+		) -> Result<(), SwapError> {
+			self.swap(onion, comsig, expiry_height)
+		}
+
+		fn execute_round(&self) -> Result<Option<Transaction>, Box<dyn std::error::Error>> {
+			Ok(None)
+		}
+
+		fn retry_round(
+			&self,
+			_retry_delay: Duration,
+		) -> Result<Option<Transaction>, Box<dyn std::error::Error>> {
+			Ok(None)
+		}
+
+		fn status(&self) -> Result<ServerStatus, SwapError> {
+			Ok(ServerStatus::default())
+		}
+
+		fn list_swaps(&self) -> Result<Vec<SwapData>, SwapError> {
+			Ok(Vec::new())
+		}
+
+		fn expire_swaps(&self) -> Result<usize, SwapError> {
+			Ok(0)
+		}
+
+		fn check_confirmations(&self) -> Result<usize, SwapError> {
+			Ok(0)
+		}
+
+		fn handle_reorg(&self, _height: u64) -> Result<usize, SwapError> {
+			Ok(0)
+		}
+
+		fn fee_outputs(&self) -> Result<Vec<FeeOutputData>, SwapError> {
+			Ok(Vec::new())
+		}
+
+		fn list_rounds(&self) -> Result<Vec<RoundData>, SwapError> {
+			Ok(Vec::new())
+		}
+
+		fn get_round(
+			&self,
+			_kernel_hash: &Hash,
+		) -> Result<Option<(RoundData, Vec<SwapData>)>, SwapError> {
+			Ok(None)
+		}
+
+		fn daily_report(&self, day: u64) -> Result<DailyReport, SwapError> {
+			Ok(DailyReport::new(day, &DailyActivity::default(), &vec![]))
+		}
+
+		fn preflight_counts(&self) -> PreflightCounts {
+			PreflightCounts::default()
+		}
+
+		fn feature_flags(&self) -> Result<FeatureFlags, SwapError> {
+			Ok(FeatureFlags::default())
+		}
+
+		fn set_feature(
+			&self,
+			_feature: Feature,
+			_enabled: bool,
+		) -> Result<FeatureFlags, SwapError> {
+			Err(SwapError::UnknownError(
+				"Not supported by MockServer".to_string(),
+			))
+		}
+
+		fn set_node_stalled(&self, _stalled: bool) {}
+
+		fn set_block_time(&self, _block_time_s: u64) {}
+
+		fn update_config(&mut self, _server_config: ServerConfig) {}
+
+		fn commit_pending(&self) -> Result<PendingCommitment, SwapError> {
+			Err(SwapError::UnknownError(
+				"Not supported by MockServer".to_string(),
+			))
+		}
+
+		fn get_pending_commitment(&self) -> Option<PendingCommitment> {
+			None
+		}
+
+		fn check_swap(
+			&self,
+			input_commit: &Commitment,
+			_comsig: &ComSignature,
+			_expiry_height: u64,
+		) -> Result<SwapCheck, SwapError> {
+			Err(SwapError::SwapNotFound {
+				commit: input_commit.clone(),
+			})
+		}
+
+		fn accepted_round(&self, input_commit: &Commitment) -> Result<u64, SwapError> {
+			Err(SwapError::SwapNotFound {
+				commit: input_commit.clone(),
+			})
+		}
+
+		fn get_receipt(
+			&self,
+			input_commit: &Commitment,
+			_comsig: &ComSignature,
+			_expiry_height: u64,
+		) -> Result<SwapReceipt, SwapError> {
+			Err(SwapError::SwapNotFound {
+				commit: input_commit.clone(),
+			})
+		}
+
+		fn peel_layer(&self, _onion: &Onion) -> Result<(Payload, Onion), SwapError> {
+			Err(SwapError::UnknownError(
+				"Not supported by MockServer".to_string(),
+			))
+		}
+
+		fn proof_verifier(&self) -> Arc<ProofVerifier> {
+			self.verifier.clone()
+		}
+
+		fn round_state(&self) -> Arc<RoundState> {
+			self.round_state.clone()
+		}
+
+		fn cancel_swap(
+			&self,
+			input_commit: &Commitment,
+			_comsig: &ComSignature,
+			_expiry_height: u64,
+		) -> Result<(), SwapError> {
+			Err(SwapError::SwapNotFound {
+				commit: input_commit.clone(),
+			})
+		}
+
+		fn promote(&self) -> Result<(), SwapError> {
+			Ok(())
+		}
+
+		fn server_keys(&self) -> Result<ServerKeys, SwapError> {
+			let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &secp::random_secret())
+				.map_err(|e| SwapError::UnknownError(e.to_string()))?;
+			Ok(ServerKeys {
+				current: pubkey,
+				previous: None,
+			})
+		}
+
+		fn rotate_key(&self, _new_key: SecretKey, _expires_at: u64) -> Result<(), SwapError> {
+			Ok(())
+		}
+
+		fn info(&self) -> Result<ServerInfo, SwapError> {
+			Ok(ServerInfo {
+				url: "http://127.0.0.1:3000/v1".to_string(),
+				keys: self.server_keys()?,
+				fee_base: 1,
+				minimum_fee: 1,
+				chain_height: 0,
+				max_expiry_blocks: super::MAX_EXPIRY_BLOCKS,
+				max_hops: crate::onion::MAX_HOPS,
+				min_output_value: None,
+				error_codes: crate::error_codes::error_codes(),
+				swap_ttl_blocks: super::DEFAULT_SWAP_TTL_BLOCKS,
+				interval_s: Some(1),
+				interval_blocks: None,
+				next_round_at: None,
+				next_round_in_s: None,
+				next_round_height: None,
+				window: None,
+				recent_fees: None,
+				peers: vec![],
+				content_encodings: vec![],
+				cipher_suites: CipherSuite::ALL.to_vec(),
+			})
+		}
+
+		fn estimate_fee(&self, hops: u64) -> FeeEstimate {
+			FeeEstimate {
+				hops,
+				fee_base: 1,
+				hop_fee: 1,
+				total_fee: hops,
+			}
+		}
+
+		fn stats(&self) -> Result<RoundStats, SwapError> {
+			Ok(RoundStats::from_rounds(&[], 0))
+		}
+
+		fn open_window(
+			&self,
+			closes_at: Option<u64>,
+			closes_at_height: Option<u64>,
+		) -> Result<AcceptanceWindow, SwapError> {
+			Ok(AcceptanceWindow {
+				round_id: 1,
+				closes_at,
+				closes_at_height,
+			})
+		}
+
+		fn acceptance_window(&self) -> Option<AcceptanceWindow> {
+			None
+		}
+
+		fn locate_kernel(&self, _kernel_hash: &Hash) -> Result<Option<KernelLocation>, SwapError> {
+			Ok(None)
+		}
+
+		fn record_rejection(&self, _onion: &Onion, _source: Option<String>, _error: &SwapError) {}
+
+		fn list_rejections(&self, _limit: usize) -> Result<Vec<RejectionData>, SwapError> {
+			Ok(Vec::new())
+		}
+
+		fn count_api_key_swap(&self, _key_name: &str, _accepted: bool) {}
+
+		fn api_key_swaps(&self) -> Result<Vec<ApiKeySwaps>, SwapError> {
+			Ok(Vec::new())
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::config::{RoundSelection, ServerConfig, StoreBackend, WalletBackend};
+	use crate::events::{Event, EventBus};
+	use crate::features::{Feature, FeatureFlags};
+	use crate::http::HttpClient;
+	use crate::interval::ChainInterval;
+	use crate::leader::Coordinator;
+	use crate::node::mock::MockGrinNode;
+	use crate::node::GrinNode;
+	use crate::onion::test_util::{self, Hop};
+	use crate::onion::{build_onion, CipherSuite, Onion, MAX_HOPS};
+	use crate::policy::{PolicyRejection, SwapPolicy};
+	use crate::replication::Standby;
+	use crate::secp::test_util::{rand_commit, rand_proof};
+	use crate::secp::{
+		self, ComSignature, Commitment, PublicKey, RangeProof, Secp256k1, SecretKey, SigDomain,
+	};
+	use crate::server::{
+		cancel_message, check_message, receipt_message, select_for_round, FeeStats, Health,
+		ProofVerifier, ReconciliationReport, RoundPhase, RoundStats, Server, ServerImpl, SwapError,
+		FEE_STATS_ROUNDS, MAX_EXPIRY_BLOCKS, REORG_HORIZON_BLOCKS,
+	};
+	use crate::store::{
+		DecoyData, PendingTxData, RoundData, RoundMarker, SwapData, SwapStatus, SwapStore,
+	};
+	use crate::types::Payload;
+	use crate::wallet::{self, mock::MockWallet};
+
+	use grin_core::core::hash::Hashed;
+	use grin_core::core::{
+		Committed, FeeFields, Input, KernelFeatures, OutputFeatures, Transaction, TransactionBody,
+		Weighting,
+	};
+	use grin_core::global::{self, ChainTypes};
+	use grin_util::ToHex;
+	use std::net::TcpListener;
+	use std::panic::{self, AssertUnwindSafe};
+	use std::sync::{mpsc, Arc, PoisonError};
+	use std::time::Duration;
+
+	/// Expiry height of test swaps. The mock node's chain starts at height 100.
+	const EXPIRY_HEIGHT: u64 = 200;
+
+	macro_rules! assert_error_type {
+		($result:expr, $error_type:pat) => {
+			assert!($result.is_err());
+			assert!(if let $error_type = $result.unwrap_err() {
+				true
+			} else {
+				false
+			});
+		};
+	}
+
+	fn new_server(
+		test_name: &str,
+		server_key: &SecretKey,
+		utxos: &Vec<&Commitment>,
+	) -> (ServerImpl, Arc<MockGrinNode>) {
+		new_server_with_wallet(test_name, server_key, utxos, Arc::new(MockWallet::new()))
+	}
+
+	fn new_server_with_wallet(
+		test_name: &str,
+		server_key: &SecretKey,
+		utxos: &Vec<&Commitment>,
+		wallet: Arc<MockWallet>,
+	) -> (ServerImpl, Arc<MockGrinNode>) {
+		global::set_local_chain_type(ChainTypes::AutomatedTesting);
+		let db_root = format!("./target/tmp/.{}", test_name);
+		let _ = std::fs::remove_dir_all(db_root.as_str());
+
+		let config = ServerConfig {
+			key: server_key.clone(),
+			round_interval: ChainInterval::Seconds(1),
+			addr: TcpListener::bind("127.0.0.1:0")
+				.unwrap()
+				.local_addr()
+				.unwrap()
+				.into(),
+			grin_node_url: "127.0.0.1:3413".parse().unwrap(),
+			grin_node_secret_path: None,
+			wallet_owner_url: "127.0.0.1:3420".parse().unwrap(),
+			wallet_owner_secret_path: None,
+			admin_socket_path: None,
+			wallet_backend: WalletBackend::OwnerApi,
+			pending_commitment_interval: None,
+			tip_stall_timeout: None,
+			report_dir: None,
+			round_jitter_percent: None,
+			round_delay_window: None,
+			dandelion_stem: false,
+			standby_addr: None,
+			replication_addr: None,
+			replication_lease: None,
+			previous_key: None,
+			fee_seed: None,
+			state_key: None,
+			kdf: None,
+			acceptance_windows: false,
+			explorer_kernel_url: None,
+			explorer_block_url: None,
+			swap_ttl: None,
+			public_url: None,
+			log_level: None,
+			log_dir: None,
+			zero_conf_max_pending: None,
+			nostr_relays: None,
+			db_root: None,
+			max_swaps_per_round: None,
+			round_selection: RoundSelection::Fifo,
+			min_anonymity_set: None,
+			proxy: None,
+			events_addr: None,
+			store_backend: StoreBackend::Lmdb,
+			min_output_value: None,
+			metrics_addr: None,
+			peers: None,
+			admin_addr: None,
+			nrd_relative_height: None,
+			compress_archived_swaps: false,
+			accepted_cipher_suites: None,
+			peer_pubkeys: None,
+			round_peers: None,
+			intake_cutoff: None,
+			rejection_log_size: None,
+			http_timeout: None,
+			http_retries: None,
+			http_backoff: None,
+			deny_list_path: None,
+			min_input_confirmations: None,
+			api_keys: None,
+			trusted_proxies: None,
+		};
+		let mut mut_node = MockGrinNode::new();
+		for utxo in utxos {
+			mut_node.add_default_utxo(&utxo);
+		}
+		let node = Arc::new(mut_node);
+		let store = SwapStore::new(db_root.as_str()).unwrap();
+
+		let server = ServerImpl::new(config, wallet.clone(), node.clone(), store);
+		(server, node)
+	}
+
+	fn proof(value: u64, fee: u64, input_blind: &SecretKey, hop_excess: &SecretKey) -> RangeProof {
+		let secp = Secp256k1::new();
+		let nonce = secp::random_secret();
+
+		let mut blind = input_blind.clone();
+		blind.add_assign(&secp, &hop_excess).unwrap();
+
+		secp.bullet_proof(
+			value - fee,
+			blind.clone(),
+			nonce.clone(),
+			nonce.clone(),
+			None,
+			None,
+		)
+	}
+
+	/// Binds test comsigs to the server's key and EXPIRY_HEIGHT
+	fn domain(server_key: &SecretKey) -> SigDomain {
+		let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &server_key).unwrap();
+		SigDomain::new(&pubkey, EXPIRY_HEIGHT)
+	}
+
+	fn new_hop(
+		server_key: &SecretKey,
+		hop_excess: &SecretKey,
+		fee: u64,
+		proof: Option<RangeProof>,
+	) -> Hop {
+		let secp = Secp256k1::new();
+		Hop {
+			pubkey: PublicKey::from_secret_key(&secp, &server_key).unwrap(),
+			payload: Payload {
+				excess: hop_excess.clone(),
+				fee: FeeFields::from(fee as u32),
+				rangeproof: proof,
+				owner_kernel: None,
+				min_value_proof: None,
+				next_view_tag: None,
+				fee_change: None,
+			},
+		}
+	}
+
+	/// A server with a single hop swap waiting for its next round, and the swap's input
+	fn new_server_with_swap(
+		test_name: &str,
+	) -> Result<(ServerImpl, Arc<MockGrinNode>, Commitment), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let (server, node) = new_server(test_name, &server_key, &vec![&input_commit]);
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		Ok((server, node, input_commit))
+	}
+
+	/// Coordinates the server's rounds as the only node taking part, so it leads every slot.
+	/// Slots are the chain height.
+	fn leading(
+		server: ServerImpl,
+		node: &Arc<MockGrinNode>,
+	) -> Result<(ServerImpl, Arc<Coordinator>), Box<dyn std::error::Error>> {
+		let coordinator = Arc::new(Coordinator::new(
+			&secp::random_secret(),
+			vec![],
+			ChainInterval::Blocks(1),
+			node.clone(),
+			HttpClient::default(),
+		)?);
+		Ok((server.with_coordinator(coordinator.clone()), coordinator))
+	}
+
+	/// Single hop to demonstrate request validation and onion unwrapping.
+	#[test]
+	fn swap_lifecycle() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let (server, node) = new_server("swap_lifecycle", &server_key, &vec![&input_commit]);
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+
+		// Make sure entry is added to server.
+		let output_commit = secp::add_excess(&input_commit, &hop_excess)?;
+		let output_commit = secp::sub_value(&output_commit, fee)?;
+
+		let expected = SwapData {
+			excess: hop_excess.clone(),
+			output_commit: output_commit.clone(),
+			rangeproof: Some(proof),
+			input: Input::new(OutputFeatures::Plain, input_commit.clone()),
+			fee,
+			onion: Onion {
+				ephemeral_pubkey: test_util::next_ephemeral_pubkey(&onion, &server_key)?,
+				commit: output_commit.clone(),
+				enc_payloads: vec![],
+				view_tag: None,
+				cipher_suite: CipherSuite::ChaCha20,
+			},
+			status: SwapStatus::Unprocessed,
+			expiry_height: EXPIRY_HEIGHT,
+			unconfirmed_input: false,
+			owner_kernel: None,
+			accepted_at: 0,
+			accepted_round: 0,
+			fee_change: None,
+		};
+
+		{
+			let store = server.store.lock().unwrap();
+			assert_eq!(1, store.swaps_iter().unwrap().count());
+			assert!(store.swap_exists(&input_commit).unwrap());
+			let stored = store.get_swap(&input_commit).unwrap();
+			assert!(stored.accepted_at > 0);
+			assert_eq!(
+				SwapData {
+					accepted_at: stored.accepted_at,
+					..expected
+				},
+				stored
+			);
+		}
+
+		let tx = server.execute_round()?;
+		assert!(tx.is_some());
+
+		{
+			// check that status was updated
+			let store = server.store.lock().unwrap();
+			assert!(match store.get_swap(&input_commit)?.status {
+				SwapStatus::InProcess { kernel_hash } =>
+					kernel_hash == tx.unwrap().kernels().first().unwrap().hash(),
+				_ => false,
+			});
+		}
+
+		// check that the transaction was posted
+		let posted_txns = node.get_posted_txns();
+		assert_eq!(posted_txns.len(), 1);
+		let posted_txn: Transaction = posted_txns.into_iter().next().unwrap();
+		assert!(posted_txn.inputs_committed().contains(&input_commit));
+		assert!(posted_txn.outputs_committed().contains(&output_commit));
+		assert_eq!(vec![true], node.get_posted_fluffed());
+
+		// check that the output generated by our wallet was recorded
+		let fee_outputs = server.fee_outputs()?;
+		assert_eq!(1, fee_outputs.len());
+		assert!(posted_txn
+			.outputs_committed()
+			.contains(&fee_outputs[0].output_commit));
+		assert_eq!(
+			posted_txn.kernels().first().unwrap().hash(),
+			fee_outputs[0].kernel_hash
+		);
+
+		posted_txn.validate(Weighting::AsTransaction)?;
+
+		Ok(())
+	}
+
+	/// A swap whose layer was already peeled is validated and saved without peeling it again
+	#[test]
+	fn swap_peeled() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let (server, _node) = new_server("swap_peeled", &server_key, &vec![&input_commit]);
+		let (payload, peeled) = server.peel_layer(&onion)?;
+		server.swap_peeled(&onion, (payload, peeled.clone()), &comsig, EXPIRY_HEIGHT)?;
+
+		let stored = server.store.lock().unwrap().get_swap(&input_commit)?;
+		assert_eq!(hop_excess, stored.excess);
+		assert_eq!(peeled, stored.onion);
+
+		// The rest of the request is still validated
+		let (payload, peeled) = server.peel_layer(&onion)?;
+		assert_error_type!(
+			server.swap_peeled(&onion, (payload, peeled), &comsig, 0),
+			SwapError::InvalidExpiry { .. }
+		);
+
+		Ok(())
+	}
+
+	/// The owner can keep part of the excess to themselves, signing for it with their own kernel,
+	/// which is included in the round transaction alongside the round's kernel.
+	#[test]
+	fn swap_owner_kernel() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let owner_excess = secp::random_secret();
+		let owner_kernel = wallet::build_owner_kernel(&owner_excess)?;
+		let mut output_blind = blind.clone();
+		output_blind.add_assign(&Secp256k1::new(), &owner_excess)?;
+		let proof = proof(value, fee, &output_blind, &hop_excess);
+		let mut hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+		hop.payload.owner_kernel = Some(owner_kernel.clone());
+
+		// A kernel that takes a fee is rejected
+		let mut fee_kernel = owner_kernel.clone();
+		fee_kernel.features = KernelFeatures::Plain {
+			fee: FeeFields::from(1u32),
+		};
+		let mut bad_hop = hop.clone();
+		bad_hop.payload.owner_kernel = Some(fee_kernel);
+		let onion = test_util::create_onion(&input_commit, &vec![bad_hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+		let (server, node) = new_server("swap_owner_kernel", &server_key, &vec![&input_commit]);
+		assert_eq!(
+			Err(SwapError::InvalidOwnerKernel),
+			server.swap(&onion, &comsig, EXPIRY_HEIGHT)
+		);
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+
+		// The server only knows its own part of the excess
+		let output_commit = secp::commit(value - fee, &output_blind)?;
+		let output_commit = secp::add_excess(&output_commit, &hop_excess)?;
+		let swap = server.store.lock().unwrap().get_swap(&input_commit)?;
+		assert_eq!(hop_excess, swap.excess);
+		assert_eq!(output_commit, swap.output_commit);
+		assert_eq!(Some(owner_kernel.clone()), swap.owner_kernel);
+
+		let tx = server.execute_round()?.unwrap();
+		let round_kernel = wallet::round_kernel(&tx).unwrap();
+		assert_ne!(owner_kernel.excess, round_kernel.excess);
+		assert!(
+			match server.store.lock().unwrap().get_swap(&input_commit)?.status {
+				SwapStatus::InProcess { kernel_hash } => kernel_hash == round_kernel.hash(),
+				_ => false,
+			}
+		);
+
+		let posted_txn: Transaction = node.get_posted_txns().into_iter().next().unwrap();
+		assert_eq!(2, posted_txn.kernels().len());
+		assert!(posted_txn.kernels().contains(&owner_kernel));
+		assert!(posted_txn.outputs_committed().contains(&output_commit));
+		posted_txn.validate(Weighting::AsTransaction)?;
+
+		Ok(())
+	}
+
+	/// Change for an over-paid fee is paid out in the round transaction, with the server only
+	/// collecting what's left of the fee. The change's blinding factor is signed for by the
+	/// owner kernel, so no kernel's excess matches the change output.
+	#[test]
+	fn swap_fee_change() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let change_value: u64 = 20_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let owner_excess = secp::random_secret();
+		let (change, owner_kernel) =
+			wallet::build_fee_change(change_value, &secp::random_secret(), &owner_excess)?;
+		let mut output_blind = blind.clone();
+		output_blind.add_assign(&Secp256k1::new(), &owner_excess)?;
+		let proof = proof(value, fee, &output_blind, &hop_excess);
+		let mut hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+		hop.payload.fee_change = Some(change.clone());
+
+		// Change without an owner kernel to sign for it is rejected
+		let onion = test_util::create_onion(&input_commit, &vec![hop.clone()])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+		let (server, node) = new_server("swap_fee_change", &server_key, &vec![&input_commit]);
+		assert!(matches!(
+			server.swap(&onion, &comsig, EXPIRY_HEIGHT),
+			Err(SwapError::InvalidFeeChange(_))
+		));
+
+		hop.payload.owner_kernel = Some(owner_kernel.clone());
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+
+		let output_commit = secp::commit(value - fee, &output_blind)?;
+		let output_commit = secp::add_excess(&output_commit, &hop_excess)?;
+		let swap = server.store.lock().unwrap().get_swap(&input_commit)?;
+		assert_eq!(fee - change_value, swap.fee);
+		assert_eq!(output_commit, swap.output_commit);
+		assert_eq!(Some(change.clone()), swap.fee_change);
+
+		server.execute_round()?.unwrap();
+		let posted_txn: Transaction = node.get_posted_txns().into_iter().next().unwrap();
+		assert!(posted_txn
+			.outputs_committed()
+			.contains(&change.output.commitment()));
+		assert!(posted_txn.outputs_committed().contains(&output_commit));
+		assert_eq!(2, posted_txn.kernels().len());
+		assert!(posted_txn.kernels().contains(&owner_kernel));
+		let change_excess = secp::sub_value(&change.output.commitment(), change_value)?;
+		assert!(posted_txn
+			.kernels()
+			.iter()
+			.all(|k| k.excess != change_excess));
+		posted_txn.validate(Weighting::AsTransaction)?;
+
+		Ok(())
+	}
+
+	/// Proofs verified by the pool aren't verified again when their swap is accepted
+	#[test]
+	fn proof_verifier() -> Result<(), Box<dyn std::error::Error>> {
+		let verifier = ProofVerifier::new(2, 4);
+		let blind = secp::random_secret();
+		let excess = secp::random_secret();
+		let proof = proof(1_000, 100, &blind, &excess);
+		let mut output_blind = blind.clone();
+		output_blind.add_assign(&Secp256k1::new(), &excess)?;
+		let commit = secp::commit(900, &output_blind)?;
+		let other_commit = secp::commit(901, &output_blind)?;
+
+		assert_eq!(Ok(true), verifier.verify(&commit, &proof));
+		assert_eq!(Ok(false), verifier.verify(&other_commit, &proof));
+		assert_eq!(2, verifier.verified.lock().unwrap().results.len());
+
+		assert!(verifier.is_valid(&commit, &proof));
+		assert!(!verifier.is_valid(&other_commit, &proof));
+		assert!(verifier.verified.lock().unwrap().results.is_empty());
+		assert!(verifier.verified.lock().unwrap().order.is_empty());
+
+		// Proofs the pool hasn't seen are verified on the spot
+		assert!(verifier.is_valid(&commit, &proof));
+
+		Ok(())
+	}
+
+	/// Owners can cancel their swaps until they're included in a round,
+	/// but only with a signature over the cancellation of that particular swap.
+	#[test]
+	fn swap_cancel() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let (server, _node) = new_server("swap_cancel", &server_key, &vec![&input_commit]);
+		let cancel = |output_commit: &Commitment| {
+			let msg = cancel_message(&input_commit, output_commit);
+			ComSignature::sign(value, &blind, &domain(&server_key), &msg).unwrap()
+		};
+		let output_commit = secp::add_excess(&input_commit, &hop_excess)?;
+		let output_commit = secp::sub_value(&output_commit, fee)?;
+
+		assert_eq!(
+			Err(SwapError::SwapNotFound {
+				commit: input_commit.clone()
+			}),
+			server.cancel_swap(&input_commit, &cancel(&output_commit), EXPIRY_HEIGHT)
+		);
+
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+
+		// Neither the swap's own signature, nor a cancellation of another swap of the input, will do
+		assert_eq!(
+			Err(SwapError::InvalidComSignature),
+			server.cancel_swap(&input_commit, &comsig, EXPIRY_HEIGHT)
+		);
+		assert_eq!(
+			Err(SwapError::InvalidComSignature),
+			server.cancel_swap(&input_commit, &cancel(&rand_commit()), EXPIRY_HEIGHT)
+		);
+		assert_eq!(
+			Err(SwapError::InvalidExpiry {
+				expiry_height: 100,
+				height: 100
+			}),
+			server.cancel_swap(&input_commit, &cancel(&output_commit), 100)
+		);
+
+		server.cancel_swap(&input_commit, &cancel(&output_commit), EXPIRY_HEIGHT)?;
+		assert!(!server.store.lock().unwrap().swap_exists(&input_commit)?);
+
+		// The input can be swapped again, but once it's in a round it can't be cancelled
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		assert!(server.execute_round()?.is_some());
+		assert_eq!(
+			Err(SwapError::SwapNotCancellable {
+				commit: input_commit.clone()
+			}),
+			server.cancel_swap(&input_commit, &cancel(&output_commit), EXPIRY_HEIGHT)
+		);
+
+		Ok(())
+	}
+
+	/// Swaps get a signed receipt for the round they're included in.
+	#[test]
+	fn swap_receipt() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let (server, _node) = new_server("swap_receipt", &server_key, &vec![&input_commit]);
+		let receipt_sig = |value: u64, blind: &SecretKey, input_commit: &Commitment| {
+			ComSignature::sign(
+				value,
+				blind,
+				&domain(&server_key),
+				&receipt_message(input_commit),
+			)
+		};
+		let unknown_blind = secp::random_secret();
+		let unknown_commit = secp::commit(value, &unknown_blind)?;
+		assert_error_type!(
+			server.get_receipt(
+				&unknown_commit,
+				&receipt_sig(value, &unknown_blind, &unknown_commit)?,
+				EXPIRY_HEIGHT
+			),
+			SwapError::SwapNotFound { .. }
+		);
+
+		server.open_window(None, None)?;
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		let owner_sig = receipt_sig(value, &blind, &input_commit)?;
+		assert_error_type!(
+			server.get_receipt(&input_commit, &owner_sig, EXPIRY_HEIGHT),
+			SwapError::ReceiptNotFound { .. }
+		);
+
+		let tx = server.execute_round()?.unwrap();
+
+		// The receipt links the input to its output, so nobody but the input's owner gets it
+		assert_eq!(
+			Err(SwapError::InvalidComSignature),
+			server.get_receipt(
+				&input_commit,
+				&receipt_sig(value, &unknown_blind, &input_commit)?,
+				EXPIRY_HEIGHT
+			)
+		);
+		assert_error_type!(
+			server.get_receipt(&input_commit, &owner_sig, 0),
+			SwapError::InvalidExpiry { .. }
+		);
+
+		let mut receipt = server.get_receipt(&input_commit, &owner_sig, EXPIRY_HEIGHT)?;
+		assert!(receipt.verify());
+		assert_eq!(1, receipt.round_id);
+		assert_eq!(server.server_config.advertised_url(), receipt.server_url);
+		assert_eq!(input_commit.to_hex(), receipt.input_commit);
+		assert_eq!(
+			tx.kernels().first().unwrap().hash().to_hex(),
+			receipt.kernel_hash
+		);
+		assert_eq!(
+			PublicKey::from_secret_key(&Secp256k1::new(), &server_key)?,
+			receipt.pubkey
+		);
+
+		receipt.round_id = 2;
+		assert!(!receipt.verify());
+
+		Ok(())
+	}
+
+	/// Fees of recent rounds are published via 'info', bucketed to two significant digits
+	#[test]
+	fn recent_fee_stats() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 51_234_567;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let (server, _node) = new_server("recent_fee_stats", &server_key, &vec![&input_commit]);
+		assert_eq!(None, server.info()?.recent_fees);
+
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		server.execute_round()?;
+		assert_eq!(
+			Some(FeeStats {
+				rounds: 1,
+				median_fee: 51_000_000,
+				minimum_fee: 51_000_000,
+			}),
+			server.info()?.recent_fees
+		);
+
+		// Only the most recent rounds are counted
+		let rounds: Vec<RoundData> = (0..FEE_STATS_ROUNDS as u64 + 5)
+			.map(|i| RoundData {
+				round_id: i,
+				kernel_hash: secp::test_util::rand_hash(),
+				timestamp: i,
+				fees: vec![(i + 1) * 1_000_000, 99],
+				anonymity_set: 2,
+				tx_weight: 0,
+				confirmed_height: None,
+			})
+			.collect();
+		let stats = FeeStats::from_rounds(rounds).unwrap();
+		assert_eq!(FEE_STATS_ROUNDS as u64, stats.rounds);
+		assert_eq!(99, stats.minimum_fee);
+		assert_eq!(6_000_000, stats.median_fee);
+
+		Ok(())
+	}
+
+	/// Stats aggregate every recorded round
+	#[test]
+	fn round_stats() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let (server, _node) = new_server("round_stats", &server_key, &vec![&input_commit]);
+		let stats = server.stats()?;
+		assert_eq!(0, stats.rounds_completed);
+		assert_eq!(0.0, stats.average_anonymity_set);
+
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		server.execute_round()?;
+		let stats = server.stats()?;
+		assert_eq!(1, stats.rounds_completed);
+		assert_eq!(1, stats.total_outputs_mixed);
+
+		let rounds: Vec<RoundData> = [2, 3, 7]
+			.iter()
+			.enumerate()
+			.map(|(i, anonymity_set)| RoundData {
+				round_id: i as u64,
+				kernel_hash: secp::test_util::rand_hash(),
+				timestamp: i as u64,
+				fees: vec![1_000_000; *anonymity_set as usize + 1],
+				anonymity_set: *anonymity_set,
+				tx_weight: 0,
+				confirmed_height: None,
+			})
+			.collect();
+		assert_eq!(
+			RoundStats {
+				rounds_completed: 3,
+				average_anonymity_set: 4.0,
+				total_outputs_mixed: 15,
+				uptime_s: 60,
+			},
+			RoundStats::from_rounds(&rounds, 60)
+		);
+
+		Ok(())
+	}
+
+	/// A round whose kernel is reorged out is marked unconfirmed and its transaction posted again,
+	/// until it's confirmed once more.
+	#[test]
+	fn reorged_round() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let (server, node) = new_server("reorged_round", &server_key, &vec![&input_commit]);
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		let tx = server.execute_round()?.unwrap();
+		let kernel = tx.kernels().first().unwrap();
+		node.confirm_kernel(&kernel.excess, 105);
+		assert_eq!(1, server.check_confirmations()?);
+
+		// Nothing changes while the kernel is still on chain
+		node.set_chain_height(104);
+		assert_eq!(0, server.handle_reorg(104)?);
+		assert_eq!(vec![tx.clone()], node.get_posted_txns());
+
+		node.unconfirm_kernel(&kernel.excess);
+		assert_eq!(1, server.handle_reorg(104)?);
+		assert_eq!(None, server.list_rounds()?[0].confirmed_height);
+		assert_eq!(vec![tx.clone(), tx.clone()], node.get_posted_txns());
+		assert_eq!(
+			SwapStatus::InProcess {
+				kernel_hash: kernel.hash()
+			},
+			server.store.lock().unwrap().get_swap(&input_commit)?.status
+		);
+
+		node.confirm_kernel(&kernel.excess, 106);
+		assert_eq!(1, server.check_confirmations()?);
+		assert_eq!(Some(106), server.list_rounds()?[0].confirmed_height);
+
+		// Once final, the round's transaction is no longer kept
+		node.set_chain_height(106 + REORG_HORIZON_BLOCKS);
+		server.check_confirmations()?;
+		assert_eq!(
+			None,
+			server.store.lock().unwrap().get_round_tx(&kernel.hash())?
+		);
+
+		Ok(())
+	}
+
+	/// Swaps beyond 'max_swaps_per_round' are left for a later round, picked by acceptance order or fee.
+	#[test]
+	fn round_selection() {
+		let swap = |fee: u64, accepted_at: u64, expiry_height: u64| SwapData {
+			excess: secp::random_secret(),
+			output_commit: rand_commit(),
+			rangeproof: Some(rand_proof()),
+			input: Input::new(OutputFeatures::Plain, rand_commit()),
+			fee,
+			onion: test_util::rand_onion(),
+			status: SwapStatus::Unprocessed,
+			expiry_height,
+			unconfirmed_input: false,
+			owner_kernel: None,
+			accepted_at,
+			accepted_round: 1,
+			fee_change: None,
+		};
+		// Acceptance time decides the order, even when a shorter TTL gave a later swap an earlier expiry
+		let early = swap(50_000_000, 1_700_000_000, 150);
+		let late_high_fee = swap(90_000_000, 1_700_000_300, 170);
+		let middle = swap(60_000_000, 1_700_000_200, 140);
+		let swaps = vec![early.clone(), late_high_fee.clone(), middle.clone()];
+
+		assert_eq!(
+			swaps,
+			select_for_round(swaps.clone(), None, RoundSelection::Fifo)
+		);
+		assert_eq!(
+			swaps,
+			select_for_round(swaps.clone(), Some(3), RoundSelection::FeePriority)
+		);
+		assert_eq!(
+			vec![early.clone(), middle.clone()],
+			select_for_round(swaps.clone(), Some(2), RoundSelection::Fifo)
+		);
+		assert_eq!(
+			vec![late_high_fee, middle],
+			select_for_round(swaps, Some(2), RoundSelection::FeePriority)
+		);
+	}
+
+	/// Rounds below 'min_anonymity_set' are padded with decoys, funded by the server's fee outputs.
+	/// Decoys pay for their own weight, so they don't change the fees recorded for the round.
+	#[test]
+	fn round_decoys() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let server_key = secp::random_secret();
+		let new_swap =
+			|| -> Result<(Commitment, Onion, ComSignature), Box<dyn std::error::Error>> {
+				let blind = secp::random_secret();
+				let input_commit = secp::commit(value, &blind)?;
+				let hop_excess = secp::random_secret();
+				let proof = proof(value, fee, &blind, &hop_excess);
+				let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+				let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+				let comsig =
+					ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+				Ok((input_commit, onion, comsig))
+			};
+		let (input1, onion1, comsig1) = new_swap()?;
+		let (input2, onion2, comsig2) = new_swap()?;
+
+		let (mut server, node) = new_server("round_decoys", &server_key, &vec![&input1, &input2]);
+		server.server_config.min_anonymity_set = Some(3);
+
+		// No decoys yet, but the fee output funds one
+		server.swap(&onion1, &comsig1, EXPIRY_HEIGHT)?;
+		let tx = server.execute_round()?.unwrap();
+		assert_eq!(1, tx.inputs().len());
+		let decoys: Vec<DecoyData> = server.store.lock().unwrap().decoys_iter()?.collect();
+		assert_eq!(1, decoys.len());
+		let fee_output = server
+			.store
+			.lock()
+			.unwrap()
+			.fee_outputs_iter()?
+			.next()
+			.unwrap();
+		assert_eq!(fee_output.output_commit, decoys[0].commit);
+
+		// The decoy's blind is only stored masked
+		let blind = server.decoy_blind(&decoys[0])?;
+		assert_eq!(secp::commit(decoys[0].amount, &blind)?, decoys[0].commit);
+		let records = server.store.lock().unwrap().export_records()?;
+		assert!(records
+			.iter()
+			.all(|(_, value)| !value.windows(blind.0.len()).any(|w| w == &blind.0[..])));
+
+		// Once confirmed, the decoy pads the next round
+		node.confirm_pool_output(&decoys[0].commit);
+		server.swap(&onion2, &comsig2, EXPIRY_HEIGHT)?;
+		let tx = server.execute_round()?.unwrap();
+		assert_eq!(2, tx.inputs().len());
+		assert_eq!(3, tx.outputs().len());
+		let round = server
+			.store
+			.lock()
+			.unwrap()
+			.get_round(&wallet::round_kernel(&tx).unwrap().hash())?
+			.unwrap();
+		assert_eq!(vec![fee], round.fees);
+		// The decoy's output doesn't count towards the anonymity set
+		assert_eq!(1, round.anonymity_set);
+
+		// The spent decoy is replaced by its new output, alongside the new fee output
+		let new_decoys: Vec<DecoyData> = server.store.lock().unwrap().decoys_iter()?.collect();
+		assert_eq!(2, new_decoys.len());
+		assert!(new_decoys.iter().all(|d| d.commit != decoys[0].commit));
+		let decoy_fee = TransactionBody::weight_by_iok(1, 1, 0) * server.get_fee_base();
+		assert!(new_decoys
+			.iter()
+			.any(|d| d.amount == decoys[0].amount - decoy_fee));
+
+		Ok(())
+	}
+
+	/// Each executed round is recorded, and its confirmation height filled in once it's mined.
+	#[test]
+	fn round_history() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let (server, node) = new_server("round_history", &server_key, &vec![&input_commit]);
+		assert!(server.list_rounds()?.is_empty());
+
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		let tx = server.execute_round()?.unwrap();
+		let kernel = tx.kernels().first().unwrap();
+
+		let rounds = server.list_rounds()?;
+		assert_eq!(1, rounds.len());
+		assert_eq!(kernel.hash(), rounds[0].kernel_hash);
+		assert_eq!(1, rounds[0].swap_count());
+		assert_eq!(1, rounds[0].anonymity_set);
+		assert_eq!(fee, rounds[0].total_fees());
+		assert_eq!(tx.weight(), rounds[0].tx_weight);
+		assert_eq!(None, rounds[0].confirmed_height);
+
+		node.confirm_kernel(&kernel.excess, 105);
+		assert_eq!(Some(105), server.list_rounds()?[0].confirmed_height);
+		assert_eq!(
+			Some(105),
+			server
+				.store
+				.lock()
+				.unwrap()
+				.get_round(&kernel.hash())?
+				.unwrap()
+				.confirmed_height
+		);
+
+		Ok(())
+	}
+
+	/// When the node rejects a round because one swap's input was spent elsewhere, only that swap
+	/// is marked failed, and the round is retried with the others.
+	#[test]
+	fn round_retried_without_double_spent_swap() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let server_key = secp::random_secret();
+
+		let mut swaps = Vec::new();
+		for _ in 0..3 {
+			let blind = secp::random_secret();
+			let input_commit = secp::commit(value, &blind)?;
+			let hop_excess = secp::random_secret();
+			let proof = proof(value, fee, &blind, &hop_excess);
+			let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+			let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+			let comsig =
+				ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+			swaps.push((input_commit, onion, comsig));
+		}
+		let input_commits: Vec<&Commitment> = swaps.iter().map(|(c, _, _)| c).collect();
+		let (server, node) = new_server(
+			"round_retried_without_double_spent_swap",
+			&server_key,
+			&input_commits,
+		);
+		for (_, onion, comsig) in &swaps {
+			server.swap(onion, comsig, EXPIRY_HEIGHT)?;
+		}
+
+		let double_spent = swaps[0].0;
+		node.double_spend(&double_spent);
+		let tx = server.execute_round()?.unwrap();
+		assert_eq!(2, tx.inputs_committed().len());
+		assert!(!tx.inputs_committed().contains(&double_spent));
+		assert_eq!(1, node.get_posted_txns().len());
+
+		let store = server.store.lock().unwrap();
+		assert_eq!(SwapStatus::Failed, store.get_swap(&double_spent)?.status);
+		for (input_commit, _, _) in &swaps[1..] {
+			assert!(matches!(
+				store.get_swap(input_commit)?.status,
+				SwapStatus::InProcess { .. }
+			));
+		}
+		drop(store);
+		assert_eq!(1, server.status()?.failed);
+		assert_eq!(1, server.list_rounds()?.len());
+
+		Ok(())
+	}
+
+	/// A swap's subscribers hear when it's accepted, included in a round, and confirmed,
+	/// and round subscribers follow the round from start to confirmation.
+	#[test]
+	fn round_events() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let events = EventBus::new();
+		let (swap_sender, swap_events) = mpsc::channel();
+		let (round_sender, round_events) = mpsc::channel();
+		events.subscribe_swap(&input_commit, swap_sender);
+		events.subscribe_rounds(round_sender);
+
+		let (server, node) = new_server("round_events", &server_key, &vec![&input_commit]);
+		let server = server.with_events(events);
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		let tx = server.execute_round()?.unwrap();
+		let kernel = tx.kernels().first().unwrap();
+		assert_eq!(0, server.check_confirmations()?);
+		node.confirm_kernel(&kernel.excess, 105);
+		assert_eq!(1, server.check_confirmations()?);
+		assert_eq!(0, server.check_confirmations()?);
+
+		let commit = input_commit.to_hex();
+		let kernel_hash = kernel.hash().to_hex();
+		assert_eq!(
+			vec![
+				Event::SwapAccepted {
+					commit: commit.clone()
+				},
+				Event::SwapIncluded {
+					commit: commit.clone(),
+					round_id: 0,
+					kernel_hash: kernel_hash.clone(),
+				},
+				Event::SwapConfirmed {
+					commit,
+					round_id: 0,
+					height: 105,
+				},
+			],
+			swap_events.try_iter().collect::<Vec<_>>()
+		);
+		assert_eq!(
+			vec![
+				Event::RoundStarted {
+					round_id: 0,
+					swap_count: 1,
+				},
+				Event::RoundPosted {
+					round_id: 0,
+					kernel_hash: kernel_hash.clone(),
+					swap_count: 1,
+				},
+				Event::RoundConfirmed {
+					round_id: 0,
+					kernel_hash,
+					height: 105,
+				},
+			],
+			round_events.try_iter().collect::<Vec<_>>()
+		);
+
+		Ok(())
+	}
+
+	/// Swaps that aren't included in a round before they expire are deleted, and can be resubmitted.
+	#[test]
+	fn swap_expiry() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let (mut server, node) = new_server("swap_expiry", &server_key, &vec![&input_commit]);
+		server.server_config.swap_ttl = Some(ChainInterval::Blocks(10));
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		assert_eq!(110, server.list_swaps()?[0].expiry_height);
+
+		node.set_chain_height(109);
+		assert_eq!(0, server.expire_swaps()?);
+		assert_eq!(1, server.list_swaps()?.len());
+
+		node.set_chain_height(110);
+		assert_eq!(1, server.expire_swaps()?);
+		assert_eq!(0, server.list_swaps()?.len());
+
+		// Expired swaps are also dropped by the round, rather than included in it
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		node.set_chain_height(120);
+		assert!(server.execute_round()?.is_none());
+		assert_eq!(0, server.list_swaps()?.len());
+		assert!(node.get_posted_txns().is_empty());
+
+		// TTLs in seconds are converted to blocks at the observed block time
+		server.server_config.swap_ttl = Some(ChainInterval::Seconds(30 * 60));
+		server.set_block_time(90);
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		assert_eq!(120 + 20, server.list_swaps()?[0].expiry_height);
+
+		Ok(())
+	}
+
+	/// A swap signed to expire before its TTL is up expires when its signature does
+	#[test]
+	fn signed_expiry() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &server_key)?;
+		let domain = SigDomain::new(&pubkey, 105);
+		let comsig = ComSignature::sign(value, &blind, &domain, &onion.serialize()?)?;
+
+		let (server, node) = new_server("signed_expiry", &server_key, &vec![&input_commit]);
+		server.swap(&onion, &comsig, 105)?;
+		assert_eq!(105, server.list_swaps()?[0].expiry_height);
+
+		node.set_chain_height(105);
+		assert!(server.execute_round()?.is_none());
+		assert_eq!(0, server.list_swaps()?.len());
+		assert!(node.get_posted_txns().is_empty());
+
+		Ok(())
+	}
+
+	/// Once a swap's round is posted, its kernel can be located on chain and on the explorer.
+	#[test]
+	fn check_swap_kernel() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let (mut server, node) = new_server("check_swap_kernel", &server_key, &vec![&input_commit]);
+		server.server_config.explorer_kernel_url =
+			Some("https://explorer.example/kernel/{excess}".to_string());
+		server.server_config.explorer_block_url =
+			Some("https://explorer.example/block/{height}".to_string());
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		let check_sig = ComSignature::sign(
+			value,
+			&blind,
+			&domain(&server_key),
+			&check_message(&input_commit),
+		)?;
+		assert_eq!(
+			None,
+			server
+				.check_swap(&input_commit, &check_sig, EXPIRY_HEIGHT)?
+				.kernel
+		);
+
+		let tx = server.execute_round()?.unwrap();
+		let excess = tx.kernels().first().unwrap().excess.to_hex();
+		let kernel = server
+			.check_swap(&input_commit, &check_sig, EXPIRY_HEIGHT)?
+			.kernel
+			.unwrap();
+		assert_eq!(excess, kernel.excess);
+		assert_eq!(None, kernel.height);
+		assert_eq!(
+			Some(format!("https://explorer.example/kernel/{}", excess)),
+			kernel.kernel_url
+		);
+		assert_eq!(None, kernel.block_url);
+
+		node.confirm_kernel(&tx.kernels().first().unwrap().excess, 101);
+		let kernel = server
+			.check_swap(&input_commit, &check_sig, EXPIRY_HEIGHT)?
+			.kernel
+			.unwrap();
+		assert_eq!(Some(101), kernel.height);
+		assert_eq!(
+			Some("https://explorer.example/block/101".to_string()),
+			kernel.block_url
+		);
+
+		Ok(())
+	}
+
+	/// A locked wallet skips the round before anything is built, and the skip is counted
+	#[test]
+	fn wallet_preflight() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let wallet = Arc::new(MockWallet::new());
+		let (server, node) = new_server_with_wallet(
+			"wallet_preflight",
+			&server_key,
+			&vec![&input_commit],
+			wallet.clone(),
+		);
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+
+		wallet.set_locked(true);
+		let err = server.execute_round().unwrap_err();
+		assert!(err.to_string().contains("Wallet isn't open"));
+		assert!(node.get_posted_txns().is_empty());
+		assert_eq!(1, server.preflight_counts().wallet_locked);
+		assert_eq!(0, server.preflight_counts().wallet_unreachable);
+		assert_eq!(
+			SwapStatus::Unprocessed,
+			server.store.lock().unwrap().get_swap(&input_commit)?.status
+		);
+
+		wallet.set_locked(false);
+		assert!(server.execute_round()?.is_some());
+		assert_eq!(1, server.preflight_counts().wallet_locked);
+
+		Ok(())
+	}
+
+	/// A round due while another is executing is skipped, and the marker of a round interrupted
+	/// by a crash is cleared by the next one
+	#[test]
+	fn round_in_progress() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let (server, node) = new_server("round_in_progress", &server_key, &vec![&input_commit]);
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+
+		server.round_state().advance(RoundPhase::Posting);
+		let err = server.execute_round().unwrap_err();
+		assert!(err.to_string().contains("still executing (Posting)"));
+		assert!(node.get_posted_txns().is_empty());
+		server.round_state().advance(RoundPhase::Idle);
+
+		let interrupted = RoundMarker {
+			round_id: 0,
+			started_at: 1_700_000_000,
+		};
+		server
+			.store
+			.lock()
+			.unwrap()
+			.save_round_marker(&interrupted)?;
+		assert!(server.execute_round()?.is_some());
+		assert_eq!(RoundPhase::Idle, server.round_state().phase());
+		assert_eq!(None, server.store.lock().unwrap().round_marker()?);
+
+		server
+			.store
+			.lock()
+			.unwrap()
+			.save_round_marker(&interrupted)?;
+		assert_eq!(Some(interrupted), server.clear_interrupted_round()?);
+		assert_eq!(None, server.clear_interrupted_round()?);
+
+		Ok(())
+	}
+
+	/// A round that panics, even while holding the store, is ended all the same: its marker is
+	/// removed and the next round can begin
+	#[test]
+	fn round_guard() -> Result<(), Box<dyn std::error::Error>> {
+		let (server, _) = new_server("round_guard", &secp::random_secret(), &vec![]);
+		let marker = RoundMarker {
+			round_id: 0,
+			started_at: 1_700_000_000,
+		};
+
+		let panicked = panic::catch_unwind(AssertUnwindSafe(|| {
+			let _round = server.round_state.begin(&server.store).unwrap();
+			let locked_store = server.store.lock().unwrap();
+			locked_store.save_round_marker(&marker).unwrap();
+			assert_eq!(RoundPhase::Building, server.round_state().phase());
+			panic!("round failed");
+		}));
+		assert!(panicked.is_err());
+		assert_eq!(RoundPhase::Idle, server.round_state().phase());
+		let locked_store = server.store.lock().unwrap_or_else(PoisonError::into_inner);
+		assert_eq!(None, locked_store.round_marker()?);
+		drop(locked_store);
+
+		// A kept round stays executing until it's resumed and dropped
+		server.round_state.begin(&server.store)?.keep();
+		assert!(server.round_state.begin(&server.store).is_err());
+		drop(server.round_state.resume(&server.store));
+		assert_eq!(RoundPhase::Idle, server.round_state().phase());
+		Ok(())
+	}
+
+	/// The wallet's output is cancelled once posting the round transaction has failed
+	/// 'POST_TX_ATTEMPTS' times, and the swaps remain unprocessed so they're retried in the next round.
+	#[test]
+	fn post_tx_failure_rollback() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let wallet = Arc::new(MockWallet::new());
+		let (server, node) = new_server_with_wallet(
+			"post_tx_failure_rollback",
+			&server_key,
+			&vec![&input_commit],
+			wallet.clone(),
+		);
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+
+		node.set_post_tx_fails(true);
+		assert!(server.execute_round()?.is_none());
+		for _ in 2..super::POST_TX_ATTEMPTS {
+			assert!(server.retry_round(Duration::ZERO)?.is_none());
+		}
+		assert!(wallet.get_cancelled().is_empty());
+		assert!(server.retry_round(Duration::ZERO).is_err());
+		assert_eq!(RoundPhase::Idle, server.round_state().phase());
+		assert_eq!(1, wallet.get_cancelled().len());
+		assert!(server.fee_outputs()?.is_empty());
+		assert_eq!(0, server.store.lock().unwrap().pending_txs_iter()?.count());
+		assert_eq!(
+			SwapStatus::Unprocessed,
+			server.store.lock().unwrap().get_swap(&input_commit)?.status
+		);
+
+		node.set_post_tx_fails(false);
+		assert!(server.execute_round()?.is_some());
+		assert_eq!(1, wallet.get_cancelled().len());
+		assert_eq!(1, server.fee_outputs()?.len());
+
+		Ok(())
+	}
+
+	/// A transaction the node doesn't accept is deferred, and posted again by the scheduler once its
+	/// backoff has passed. The queued transaction is removed once the round is recorded.
+	/// A transaction the server stopped before recording is posted again next round.
+	#[test]
+	fn post_tx_retry() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let (server, node) = new_server("post_tx_retry", &server_key, &vec![&input_commit]);
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+
+		node.fail_next_posts(super::POST_TX_ATTEMPTS - 1);
+		assert!(server.execute_round()?.is_none());
+		assert!(node.get_posted_txns().is_empty());
+		assert_eq!(RoundPhase::Posting, server.round_state().phase());
+		assert!(server.execute_round().is_err());
+
+		// Nothing's posted again until the backoff has passed
+		assert!(server.retry_round(Duration::from_secs(60))?.is_none());
+		let queued: Vec<PendingTxData> = server.store.lock().unwrap().pending_txs_iter()?.collect();
+		assert_eq!(1, queued[0].attempts);
+
+		assert!(server.retry_round(Duration::ZERO)?.is_none());
+		assert!(server.retry_round(Duration::ZERO)?.is_none());
+		let tx = server.retry_round(Duration::ZERO)?.unwrap();
+		assert_eq!(vec![tx.clone()], node.get_posted_txns());
+		assert_eq!(RoundPhase::Idle, server.round_state().phase());
+		let kernel_hash = wallet::round_kernel(&tx).unwrap().hash();
+		assert_eq!(
+			SwapStatus::InProcess { kernel_hash },
+			server.store.lock().unwrap().get_swap(&input_commit)?.status
+		);
+		assert_eq!(0, server.store.lock().unwrap().pending_txs_iter()?.count());
+
+		// Another server with the same swap queued that transaction, but stopped before recording it
+		let (restarted, restarted_node) =
+			new_server("post_tx_retry_restarted", &server_key, &vec![&input_commit]);
+		restarted.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		restarted
+			.store
+			.lock()
+			.unwrap()
+			.save_pending_tx(&PendingTxData {
+				round_id: 0,
+				kernel_hash,
+				tx: tx.clone(),
+				input_commits: vec![input_commit],
+				attempts: 1,
+				queued_at: 1_700_000_000,
+				handed_off_slot: None,
+			})?;
+		assert!(restarted.execute_round()?.is_none());
+		assert_eq!(vec![tx], restarted_node.get_posted_txns());
+		assert_eq!(
+			SwapStatus::InProcess { kernel_hash },
+			restarted
+				.store
+				.lock()
+				.unwrap()
+				.get_swap(&input_commit)?
+				.status
+		);
+		assert!(restarted
+			.store
+			.lock()
+			.unwrap()
+			.get_round(&kernel_hash)?
+			.is_some());
+		assert_eq!(
+			0,
+			restarted.store.lock().unwrap().pending_txs_iter()?.count()
+		);
+
+		Ok(())
+	}
+
+	/// At startup, a queued transaction the node already has is recorded without posting it again,
+	/// while one the node hasn't seen is left for the next round
+	#[test]
+	fn reconcile_queued_txs() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let (server, _node) = new_server("reconcile_queued_txs", &server_key, &vec![&input_commit]);
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		let tx = server.execute_round()?.unwrap();
+		let kernel_hash = wallet::round_kernel(&tx).unwrap().hash();
+
+		// Another server with the same swap queued that transaction, but stopped before recording it
+		let (restarted, restarted_node) = new_server(
+			"reconcile_queued_txs_restarted",
+			&server_key,
+			&vec![&input_commit],
+		);
+		restarted.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		restarted
+			.store
+			.lock()
+			.unwrap()
+			.save_pending_tx(&PendingTxData {
+				round_id: 0,
+				kernel_hash,
+				tx: tx.clone(),
+				input_commits: vec![input_commit],
+				attempts: 0,
+				queued_at: 1_700_000_000,
+				handed_off_slot: None,
+			})?;
+
+		assert_eq!(0, restarted.reconcile_queued_txs()?);
+		assert_eq!(
+			1,
+			restarted.store.lock().unwrap().pending_txs_iter()?.count()
+		);
+
+		restarted_node.add_pool_output(&tx.outputs().first().unwrap().commitment());
+		assert_eq!(1, restarted.reconcile_queued_txs()?);
+		assert!(restarted_node.get_posted_txns().is_empty());
+		assert_eq!(
+			SwapStatus::InProcess { kernel_hash },
+			restarted
+				.store
+				.lock()
+				.unwrap()
+				.get_swap(&input_commit)?
+				.status
+		);
+		assert_eq!(
+			0,
+			restarted.store.lock().unwrap().pending_txs_iter()?.count()
+		);
+
+		Ok(())
+	}
+
+	/// A leader with no swaps of its own still posts the batches the other nodes handed it
+	#[test]
+	fn leader_posts_batches_without_swaps() -> Result<(), Box<dyn std::error::Error>> {
+		let (follower, _, _) = new_server_with_swap("leader_posts_batches_without_swaps_follower")?;
+		let batch = follower.execute_round()?.unwrap();
+
+		let (leader, node) = new_server(
+			"leader_posts_batches_without_swaps",
+			&secp::random_secret(),
+			&vec![],
+		);
+		let (leader, coordinator) = leading(leader, &node)?;
+		coordinator.receive(coordinator.current_slot(), batch.clone())?;
+
+		assert!(leader.execute_round()?.is_none());
+		assert_eq!(vec![batch], node.get_posted_txns());
+		assert!(coordinator.take_batches().is_empty());
+
+		Ok(())
+	}
+
+	/// A batch that can't be aggregated with the rest is dropped on its own, and the others are
+	/// still posted along with the leader's transaction
+	#[test]
+	fn leader_drops_unaggregatable_batch() -> Result<(), Box<dyn std::error::Error>> {
+		let (follower, _, _) = new_server_with_swap("leader_drops_unaggregatable_batch_follower")?;
+		let batch = follower.execute_round()?.unwrap();
+
+		let (leader, node, _) = new_server_with_swap("leader_drops_unaggregatable_batch")?;
+		let (leader, coordinator) = leading(leader, &node)?;
+		// The second copy spends inputs the first already spends
+		let slot = coordinator.current_slot();
+		coordinator.receive(slot, batch.clone())?;
+		coordinator.receive(slot, batch.clone())?;
+
+		let tx = leader.execute_round()?.unwrap();
+		let posted = node.get_posted_txns();
+		assert_eq!(1, posted.len());
+		assert_eq!(
+			tx.kernels().len() + batch.kernels().len(),
+			posted[0].kernels().len()
+		);
+		assert_eq!(
+			tx.inputs_committed().len() + batch.inputs_committed().len(),
+			posted[0].inputs_committed().len()
+		);
+
+		Ok(())
+	}
+
+	/// A transaction handed to a leader stays queued until the node has it. Once its slot has
+	/// passed without the leader posting it, it's posted directly, and if the node won't take it,
+	/// its swaps are released.
+	#[test]
+	fn handed_off_tx() -> Result<(), Box<dyn std::error::Error>> {
+		let (server, node, input_commit) = new_server_with_swap("handed_off_tx")?;
+		let (server, coordinator) = leading(server, &node)?;
+		let tx = server.execute_round()?.unwrap();
+		let kernel_hash = wallet::round_kernel(&tx).unwrap().hash();
+
+		// As if the transaction had been handed to another leader instead
+		let handed_off = PendingTxData {
+			round_id: 0,
+			kernel_hash,
+			tx: tx.clone(),
+			input_commits: vec![input_commit],
+			attempts: 0,
+			queued_at: 1_700_000_000,
+			handed_off_slot: Some(coordinator.current_slot()),
+		};
+		server.store.lock().unwrap().save_pending_tx(&handed_off)?;
+		assert!(server.execute_round()?.is_none());
+		assert_eq!(1, node.get_posted_txns().len());
+		assert_eq!(1, server.store.lock().unwrap().pending_txs_iter()?.count());
+
+		node.set_chain_height(101);
+		assert!(server.execute_round()?.is_none());
+		assert_eq!(vec![tx.clone(), tx], node.get_posted_txns());
+		assert_eq!(0, server.store.lock().unwrap().pending_txs_iter()?.count());
+		assert_eq!(
+			SwapStatus::InProcess { kernel_hash },
+			server.store.lock().unwrap().get_swap(&input_commit)?.status
+		);
+
+		// The input was spent elsewhere, so the swap fails
+		server.store.lock().unwrap().save_pending_tx(&handed_off)?;
+		node.set_post_tx_fails(true);
+		node.spend_utxo(&input_commit);
+		assert!(server.execute_round()?.is_none());
+		assert_eq!(0, server.store.lock().unwrap().pending_txs_iter()?.count());
+		assert_eq!(
+			SwapStatus::Failed,
+			server.store.lock().unwrap().get_swap(&input_commit)?.status
+		);
+
+		Ok(())
+	}
+
+	/// At startup, swaps of rounds that were mined are completed. A round the node dropped is
+	/// posted again if its transaction was kept, and otherwise its swaps go back to unprocessed,
+	/// or fail if their input was spent elsewhere.
+	#[test]
+	fn reconcile_in_process() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		// Mined while the server was stopped
+		let (server, node) = new_server("reconcile_in_process", &server_key, &vec![&input_commit]);
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		let tx = server.execute_round()?.unwrap();
+		let kernel = wallet::round_kernel(&tx).unwrap().clone();
+		let kernel_hash = kernel.hash();
+		node.confirm_kernel(&kernel.excess, 90);
+		assert_eq!(
+			ReconciliationReport {
+				completed: 1,
+				..ReconciliationReport::default()
+			},
+			server.reconcile_in_process()?
+		);
+		assert_eq!(
+			SwapStatus::Completed {
+				kernel_hash,
+				block_hash: node.get_block_hash(90)?.unwrap(),
+			},
+			server.store.lock().unwrap().get_swap(&input_commit)?.status
+		);
+		assert_eq!(
+			ReconciliationReport::default(),
+			server.reconcile_in_process()?
+		);
+
+		// Dropped by the node, and posted again from the kept transaction
+		let (dropped, dropped_node) = new_server(
+			"reconcile_in_process_dropped",
+			&server_key,
+			&vec![&input_commit],
+		);
+		dropped.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		let tx = dropped.execute_round()?.unwrap();
+		let kernel_hash = wallet::round_kernel(&tx).unwrap().hash();
+		assert_eq!(
+			ReconciliationReport {
+				pending: 1,
+				..ReconciliationReport::default()
+			},
+			dropped.reconcile_in_process()?
+		);
+		assert_eq!(vec![tx.clone(), tx], dropped_node.get_posted_txns());
+
+		// Without the transaction, the swap is processed again in a later round
+		dropped
+			.store
+			.lock()
+			.unwrap()
+			.delete_round_tx(&kernel_hash)?;
+		assert_eq!(
+			ReconciliationReport {
+				unprocessed: 1,
+				..ReconciliationReport::default()
+			},
+			dropped.reconcile_in_process()?
+		);
+		assert_eq!(
+			SwapStatus::Unprocessed,
+			dropped
+				.store
+				.lock()
+				.unwrap()
+				.get_swap(&input_commit)?
+				.status
+		);
+
+		// Unless its input was spent elsewhere
+		let (spent, spent_node) = new_server(
+			"reconcile_in_process_spent",
+			&server_key,
+			&vec![&input_commit],
+		);
+		spent.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		let tx = spent.execute_round()?.unwrap();
+		let kernel_hash = wallet::round_kernel(&tx).unwrap().hash();
+		spent.store.lock().unwrap().delete_round_tx(&kernel_hash)?;
+		spent_node.spend_utxo(&input_commit);
+		assert_eq!(
+			ReconciliationReport {
+				failed: 1,
+				..ReconciliationReport::default()
+			},
+			spent.reconcile_in_process()?
+		);
+		assert_eq!(
+			SwapStatus::Failed,
+			spent.store.lock().unwrap().get_swap(&input_commit)?.status
+		);
+
+		Ok(())
+	}
+
+	/// Pending swaps are included in the signed pending commitment, and check_swap proves it.
+	#[test]
+	fn check_swap_inclusion() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let (server, _node) = new_server("check_swap_inclusion", &server_key, &vec![&input_commit]);
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		let check_sig = ComSignature::sign(
+			value,
+			&blind,
+			&domain(&server_key),
+			&check_message(&input_commit),
+		)?;
+
+		// Not yet committed to
+		let check = server.check_swap(&input_commit, &check_sig, EXPIRY_HEIGHT)?;
+		assert_eq!("unprocessed", check.status);
+		assert!(check.inclusion.is_none());
+
+		let commitment = server.commit_pending()?;
+		assert_eq!(1, commitment.size);
+		assert_eq!(Some(commitment.clone()), server.get_pending_commitment());
+
+		let inclusion = server
+			.check_swap(&input_commit, &check_sig, EXPIRY_HEIGHT)?
+			.inclusion
+			.unwrap();
+		assert_eq!(commitment, inclusion.commitment);
+		assert!(inclusion.verify(&input_commit));
+		assert!(!inclusion.verify(&secp::commit(value, &secp::random_secret())?));
+
+		// Only the input's owner can query it, whether or not it's queued
+		let other_blind = secp::random_secret();
+		let other_sig = ComSignature::sign(
+			value,
+			&other_blind,
+			&domain(&server_key),
+			&check_message(&input_commit),
+		)?;
+		assert_eq!(
+			Err(SwapError::InvalidComSignature),
+			server.check_swap(&input_commit, &other_sig, EXPIRY_HEIGHT)
+		);
+		let cancel_sig = ComSignature::sign(
+			value,
+			&blind,
+			&domain(&server_key),
+			&cancel_message(&input_commit, &rand_commit()),
+		)?;
+		assert_eq!(
+			Err(SwapError::InvalidComSignature),
+			server.check_swap(&input_commit, &cancel_sig, EXPIRY_HEIGHT)
+		);
+
+		// Unknown swaps are reported as not found to their owner
+		let unknown = secp::commit(value, &other_blind)?;
+		let unknown_sig = ComSignature::sign(
+			value,
+			&other_blind,
+			&domain(&server_key),
+			&check_message(&unknown),
+		)?;
+		assert_eq!(
+			Err(SwapError::SwapNotFound {
+				commit: unknown.clone()
+			}),
+			server.check_swap(&unknown, &unknown_sig, EXPIRY_HEIGHT)
+		);
+
+		Ok(())
+	}
+
+	/// Rounds are paused and health is degraded while the node is stalled.
+	#[test]
+	fn node_stalled() -> Result<(), Box<dyn std::error::Error>> {
+		let (server, _node) = new_server("node_stalled", &secp::random_secret(), &vec![]);
+		assert_eq!(Health::Healthy, server.status()?.health);
+
+		server.set_node_stalled(true);
+		assert!(server.execute_round().is_err());
+		assert!(match server.status()?.health {
+			Health::Degraded(_) => true,
+			Health::Healthy => false,
+		});
+
+		server.set_node_stalled(false);
+		assert!(server.execute_round()?.is_none());
+		assert_eq!(Health::Healthy, server.status()?.health);
+
+		Ok(())
+	}
+
+	/// Paused features stay paused until re-enabled, and are reported in the status.
+	#[test]
+	fn feature_flags() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let (server, _node) = new_server("feature_flags", &server_key, &vec![&input_commit]);
+		server.set_feature(Feature::AcceptSubmissions, false)?;
+		server.set_feature(Feature::ExecuteRounds, false)?;
+		assert_eq!(
+			Err(SwapError::SubmissionsPaused),
+			server.swap(&onion, &comsig, EXPIRY_HEIGHT)
+		);
+		assert!(!server.status()?.features.accept_submissions);
+
+		// Swaps can be accepted while rounds are paused, and wait for rounds to resume
+		server.set_feature(Feature::AcceptSubmissions, true)?;
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		assert!(server.execute_round().is_err());
+		assert_eq!(1, server.status()?.unprocessed);
+
+		server.set_feature(Feature::ExecuteRounds, true)?;
+		assert!(server.execute_round()?.is_some());
+		assert_eq!(FeatureFlags::default(), server.feature_flags()?);
+
+		Ok(())
+	}
+
+	/// Info reports the key onions must be encrypted to, and the fee they must pay.
+	#[test]
+	fn server_info() -> Result<(), Box<dyn std::error::Error>> {
+		let server_key = secp::random_secret();
+		let (server, _node) = new_server("server_info", &server_key, &vec![]);
+
+		let info = server.info()?;
+		assert_eq!(
+			PublicKey::from_secret_key(&Secp256k1::new(), &server_key)?,
+			info.keys.current
+		);
+		assert_eq!(server.get_minimum_swap_fee(), info.minimum_fee);
+		assert_eq!(MAX_HOPS, info.max_hops);
+		assert_eq!(Some(1), info.interval_s);
+
+		// Each hop must pay at least the minimum fee
+		let estimate = server.estimate_fee(3);
+		assert_eq!(info.fee_base, estimate.fee_base);
+		assert_eq!(info.minimum_fee, estimate.hop_fee);
+		assert_eq!(3 * info.minimum_fee, estimate.total_fee);
+		assert_eq!(None, info.interval_blocks);
+		assert_eq!(None, info.next_round_height);
+		assert_eq!(None, info.window);
+
+		let window = server.open_window(Some(1_700_000_000), None)?;
+		assert_eq!(1, window.round_id);
+		assert_eq!(Some(window), server.info()?.window);
+
+		Ok(())
+	}
+
+	/// Swaps are rejected once the round's acceptance window has closed, until the next one opens.
+	#[test]
+	fn swap_window_closed() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let (mut server, _node) =
+			new_server("swap_window_closed", &server_key, &vec![&input_commit]);
+		server.server_config.acceptance_windows = true;
+
+		// The mock node's chain is at height 100
+		server.open_window(None, Some(100))?;
+		let result = server.swap(&onion, &comsig, EXPIRY_HEIGHT);
+		assert_eq!(Err(SwapError::WindowClosed { round_id: 1 }), result);
+
+		let window = server.open_window(None, Some(110))?;
+		assert_eq!(2, window.round_id);
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+
+		Ok(())
+	}
+
+	/// Swaps accepted within the intake cutoff before a round are held for the round after it
+	#[test]
+	fn swap_intake_cutoff() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let (mut server, _node) =
+			new_server("swap_intake_cutoff", &server_key, &vec![&input_commit]);
+		server.server_config.intake_cutoff = Some(ChainInterval::Blocks(5));
+
+		// The mock node's chain is at height 100, so round 1's intake has closed
+		server.open_window(None, Some(103))?;
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		assert_eq!(2, server.accepted_round(&input_commit)?);
+		assert!(server.execute_round()?.is_none());
+
+		server.open_window(None, Some(120))?;
+		let tx = server.execute_round()?.unwrap();
+		assert!(tx.inputs_committed().contains(&input_commit));
+
+		Ok(())
+	}
+
+	/// Onions encrypted to the previous key are accepted until its grace window ends.
+	#[test]
+	fn swap_previous_key() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let old_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&old_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&old_key), &onion.serialize()?)?;
+
+		// Grace window has already ended, so the signature bound to the old key is no longer valid
+		let (server, _node) = new_server("swap_expired_key", &old_key, &vec![&input_commit]);
+		server.rotate_key(secp::random_secret(), 0)?;
+		assert_eq!(
+			Err(SwapError::InvalidComSignature),
+			server.swap(&onion, &comsig, EXPIRY_HEIGHT)
+		);
+		assert!(server.server_keys()?.previous.is_none());
+
+		// Still within the grace window
+		let (server, _node) = new_server("swap_previous_key", &old_key, &vec![&input_commit]);
+		let new_key = secp::random_secret();
+		let salt = server.pending_salt(&input_commit);
+		server.rotate_key(new_key.clone(), u64::MAX)?;
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+
+		// The old key can't be dropped by another rotation while it's still accepted
+		assert!(server.rotate_key(secp::random_secret(), u64::MAX).is_err());
+
+		let secp = Secp256k1::new();
+		let keys = server.server_keys()?;
+		assert_eq!(PublicKey::from_secret_key(&secp, &new_key)?, keys.current);
+		assert_eq!(
+			PublicKey::from_secret_key(&secp, &old_key)?,
+			keys.previous.unwrap().pubkey
+		);
+
+		// Salts don't change once the server restarts with the rotated config, which keeps the
+		// old key as the state key
+		let (mut restarted, _node) = new_server(
+			"swap_previous_key_restarted",
+			&new_key,
+			&vec![&input_commit],
+		);
+		restarted.server_config.state_key = Some(old_key.clone());
+		assert_eq!(salt, restarted.pending_salt(&input_commit));
+
+		Ok(())
+	}
+
+	/// A standby rejects swaps and doesn't execute rounds until it's promoted.
 	#[test]
-	fn swap_lifecycle() -> Result<(), Box<dyn std::error::Error>> {
+	fn standby_fenced() -> Result<(), Box<dyn std::error::Error>> {
 		let value: u64 = 200_000_000;
 		let fee: u64 = 50_000_000;
 		let blind = secp::random_secret();
@@ -376,58 +5821,26 @@ mod tests {
 		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
 
 		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
-		let comsig = ComSignature::sign(value, &blind, &onion.serialize()?)?;
-
-		let (server, node) = new_server("swap_lifecycle", &server_key, &vec![&input_commit]);
-		server.swap(&onion, &comsig)?;
-
-		// Make sure entry is added to server.
-		let output_commit = secp::add_excess(&input_commit, &hop_excess)?;
-		let output_commit = secp::sub_value(&output_commit, fee)?;
-
-		let expected = SwapData {
-			excess: hop_excess.clone(),
-			output_commit: output_commit.clone(),
-			rangeproof: Some(proof),
-			input: Input::new(OutputFeatures::Plain, input_commit.clone()),
-			fee,
-			onion: Onion {
-				ephemeral_pubkey: test_util::next_ephemeral_pubkey(&onion, &server_key)?,
-				commit: output_commit.clone(),
-				enc_payloads: vec![],
-			},
-			status: SwapStatus::Unprocessed,
-		};
-
-		{
-			let store = server.store.lock().unwrap();
-			assert_eq!(1, store.swaps_iter().unwrap().count());
-			assert!(store.swap_exists(&input_commit).unwrap());
-			assert_eq!(expected, store.get_swap(&input_commit).unwrap());
-		}
-
-		let tx = server.execute_round()?;
-		assert!(tx.is_some());
-
-		{
-			// check that status was updated
-			let store = server.store.lock().unwrap();
-			assert!(match store.get_swap(&input_commit)?.status {
-				SwapStatus::InProcess { kernel_hash } =>
-					kernel_hash == tx.unwrap().kernels().first().unwrap().hash(),
-				_ => false,
-			});
-		}
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
 
-		// check that the transaction was posted
-		let posted_txns = node.get_posted_txns();
-		assert_eq!(posted_txns.len(), 1);
-		let posted_txn: Transaction = posted_txns.into_iter().next().unwrap();
-		assert!(posted_txn.inputs_committed().contains(&input_commit));
-		assert!(posted_txn.outputs_committed().contains(&output_commit));
-		// todo: check that outputs also contain the commitment generated by our wallet
+		// Primary was just heard from, so promotion isn't allowed yet
+		let (server, _node) = new_server("standby_fenced", &server_key, &vec![&input_commit]);
+		let standby = Arc::new(Standby::new(&server_key, Duration::from_secs(60)));
+		let server = server.with_standby(standby);
+		assert_error_type!(
+			server.swap(&onion, &comsig, EXPIRY_HEIGHT),
+			SwapError::NotPrimary(_)
+		);
+		assert!(server.execute_round().is_err());
+		assert!(server.promote().is_err());
 
-		posted_txn.validate(Weighting::AsTransaction)?;
+		// Once the primary has been silent for twice the lease, the standby takes over
+		let (server, _node) = new_server("standby_promoted", &server_key, &vec![&input_commit]);
+		let standby = Arc::new(Standby::new(&server_key, Duration::from_secs(0)));
+		let server = server.with_standby(standby);
+		server.promote()?;
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		assert_eq!(Health::Healthy, server.status()?.health);
 
 		Ok(())
 	}
@@ -447,11 +5860,11 @@ mod tests {
 
 		let hops: Vec<Hop> = vec![hop.clone(), hop.clone()]; // Multiple payloads
 		let onion = test_util::create_onion(&input_commit, &hops)?;
-		let comsig = ComSignature::sign(value, &blind, &onion.serialize()?)?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
 
 		let (server, _node) =
 			new_server("swap_too_many_payloads", &server_key, &vec![&input_commit]);
-		let result = server.swap(&onion, &comsig);
+		let result = server.swap(&onion, &comsig, EXPIRY_HEIGHT);
 		assert_eq!(
 			Err(SwapError::InvalidPayloadLength {
 				expected: 1,
@@ -485,14 +5898,19 @@ mod tests {
 		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
 
 		let wrong_blind = secp::random_secret();
-		let comsig = ComSignature::sign(value, &wrong_blind, &onion.serialize()?)?;
+		let comsig = ComSignature::sign(
+			value,
+			&wrong_blind,
+			&domain(&server_key),
+			&onion.serialize()?,
+		)?;
 
 		let (server, _node) = new_server(
 			"swap_invalid_com_signature",
 			&server_key,
 			&vec![&input_commit],
 		);
-		let result = server.swap(&onion, &comsig);
+		let result = server.swap(&onion, &comsig, EXPIRY_HEIGHT);
 		assert_eq!(Err(SwapError::InvalidComSignature), result);
 
 		// Make sure no entry is added to the store
@@ -504,6 +5922,72 @@ mod tests {
 		Ok(())
 	}
 
+	/// Returns InvalidComSignature when the signature is bound to a different server's key.
+	#[test]
+	fn swap_comsig_other_server() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let other_key = secp::random_secret();
+		let comsig = ComSignature::sign(value, &blind, &domain(&other_key), &onion.serialize()?)?;
+
+		let (server, _node) = new_server(
+			"swap_comsig_other_server",
+			&server_key,
+			&vec![&input_commit],
+		);
+		let result = server.swap(&onion, &comsig, EXPIRY_HEIGHT);
+		assert_eq!(Err(SwapError::InvalidComSignature), result);
+
+		Ok(())
+	}
+
+	/// Returns InvalidExpiry when the expiry height has passed or is too far in the future.
+	#[test]
+	fn swap_invalid_expiry() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let (server, _node) = new_server("swap_invalid_expiry", &server_key, &vec![&input_commit]);
+
+		for expiry_height in [100, 101 + MAX_EXPIRY_BLOCKS] {
+			let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &server_key)?;
+			let domain = SigDomain::new(&pubkey, expiry_height);
+			let comsig = ComSignature::sign(value, &blind, &domain, &onion.serialize()?)?;
+			let result = server.swap(&onion, &comsig, expiry_height);
+			assert_eq!(
+				Err(SwapError::InvalidExpiry {
+					expiry_height,
+					height: 100,
+				}),
+				result
+			);
+		}
+
+		assert_eq!(
+			0,
+			server.store.lock().unwrap().swaps_iter().unwrap().count()
+		);
+
+		Ok(())
+	}
+
 	/// Returns InvalidRangeProof when the rangeproof fails to verify for the commitment.
 	#[test]
 	fn swap_invalid_rangeproof() -> Result<(), Box<dyn std::error::Error>> {
@@ -519,11 +6003,11 @@ mod tests {
 		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
 
 		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
-		let comsig = ComSignature::sign(value, &blind, &onion.serialize()?)?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
 
 		let (server, _node) =
 			new_server("swap_invalid_rangeproof", &server_key, &vec![&input_commit]);
-		let result = server.swap(&onion, &comsig);
+		let result = server.swap(&onion, &comsig, EXPIRY_HEIGHT);
 		assert_eq!(Err(SwapError::InvalidRangeproof), result);
 
 		// Make sure no entry is added to the store
@@ -548,11 +6032,11 @@ mod tests {
 		let hop = new_hop(&server_key, &hop_excess, fee, None);
 
 		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
-		let comsig = ComSignature::sign(value, &blind, &onion.serialize()?)?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
 
 		let (server, _node) =
 			new_server("swap_missing_rangeproof", &server_key, &vec![&input_commit]);
-		let result = server.swap(&onion, &comsig);
+		let result = server.swap(&onion, &comsig, EXPIRY_HEIGHT);
 		assert_eq!(Err(SwapError::MissingRangeproof), result);
 
 		// Make sure no entry is added to the store
@@ -564,6 +6048,118 @@ mod tests {
 		Ok(())
 	}
 
+	/// Returns OutputTooSmall unless the output is proven to be worth at least 'min_output_value'
+	#[test]
+	fn swap_output_too_small() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let (mut server, _node) =
+			new_server("swap_output_too_small", &server_key, &vec![&input_commit]);
+		server.server_config.min_output_value = Some(100_000_000);
+
+		// No proof of the minimum value
+		let mut hop = new_hop(
+			&server_key,
+			&hop_excess,
+			fee,
+			Some(proof(value, fee, &blind, &hop_excess)),
+		);
+		let onion = test_util::create_onion(&input_commit, &vec![hop.clone()])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+		assert_eq!(
+			Err(SwapError::OutputTooSmall {
+				min_output_value: 100_000_000
+			}),
+			server.swap(&onion, &comsig, EXPIRY_HEIGHT)
+		);
+
+		// The 150_000_000 output can't be proven to be worth 200_000_000
+		server.server_config.min_output_value = Some(200_000_000);
+		hop.payload.min_value_proof = Some(proof(value, fee + 100_000_000, &blind, &hop_excess));
+		let onion = test_util::create_onion(&input_commit, &vec![hop.clone()])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+		assert_eq!(
+			Err(SwapError::OutputTooSmall {
+				min_output_value: 200_000_000
+			}),
+			server.swap(&onion, &comsig, EXPIRY_HEIGHT)
+		);
+		assert_eq!(
+			0,
+			server.store.lock().unwrap().swaps_iter().unwrap().count()
+		);
+
+		// But it can be proven to be worth 100_000_000
+		server.server_config.min_output_value = Some(100_000_000);
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		assert_eq!(
+			1,
+			server.store.lock().unwrap().swaps_iter().unwrap().count()
+		);
+
+		Ok(())
+	}
+
+	/// Onions in any suite are accepted by default, and suites left off 'accepted_cipher_suites'
+	/// are turned away before the onion is peeled
+	#[test]
+	fn swap_cipher_suites() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let server_key = secp::random_secret();
+		let blinds: Vec<SecretKey> = (0..2).map(|_| secp::random_secret()).collect();
+		let commits: Vec<Commitment> = blinds
+			.iter()
+			.map(|blind| secp::commit(value, blind).unwrap())
+			.collect();
+		let (mut server, _node) =
+			new_server("swap_cipher_suites", &server_key, &commits.iter().collect());
+		server.server_config.accepted_cipher_suites = Some(vec![CipherSuite::ChaCha20Rekeyed]);
+
+		let swap_in = |i: usize,
+		               suite: CipherSuite|
+		 -> Result<(Onion, ComSignature), Box<dyn std::error::Error>> {
+			let hop_excess = secp::random_secret();
+			let hop = new_hop(
+				&server_key,
+				&hop_excess,
+				fee,
+				Some(proof(value, fee, &blinds[i], &hop_excess)),
+			);
+			let onion = build_onion(&commits[i], &vec![hop], false, suite)?;
+			let comsig =
+				ComSignature::sign(value, &blinds[i], &domain(&server_key), &onion.serialize()?)?;
+			Ok((onion, comsig))
+		};
+
+		let (onion, comsig) = swap_in(0, CipherSuite::ChaCha20)?;
+		assert_eq!(
+			Err(SwapError::UnsupportedCipherSuite {
+				cipher_suite: CipherSuite::ChaCha20,
+				accepted: vec![CipherSuite::ChaCha20Rekeyed],
+			}),
+			server.swap(&onion, &comsig, EXPIRY_HEIGHT)
+		);
+
+		let (onion, comsig) = swap_in(1, CipherSuite::ChaCha20Rekeyed)?;
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+
+		server.server_config.accepted_cipher_suites = None;
+		assert_eq!(CipherSuite::ALL.to_vec(), server.info()?.cipher_suites);
+		let (onion, comsig) = swap_in(0, CipherSuite::ChaCha20)?;
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		assert_eq!(
+			2,
+			server.store.lock().unwrap().swaps_iter().unwrap().count()
+		);
+		Ok(())
+	}
+
 	/// Returns CoinNotFound when there's no matching output in the UTXO set.
 	#[test]
 	fn swap_utxo_missing() -> Result<(), Box<dyn std::error::Error>> {
@@ -578,10 +6174,10 @@ mod tests {
 		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
 
 		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
-		let comsig = ComSignature::sign(value, &blind, &onion.serialize()?)?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
 
 		let (server, _node) = new_server("swap_utxo_missing", &server_key, &vec![]);
-		let result = server.swap(&onion, &comsig);
+		let result = server.swap(&onion, &comsig, EXPIRY_HEIGHT);
 		assert_eq!(
 			Err(SwapError::CoinNotFound {
 				commit: input_commit.clone()
@@ -598,7 +6194,157 @@ mod tests {
 		Ok(())
 	}
 
-	/// Returns AlreadySwapped when trying to swap the same commitment multiple times.
+	/// Returns CoinNotMature for coinbase outputs that can't be spent in the next block yet.
+	#[test]
+	fn swap_coinbase_not_mature() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let (mut server, _node) = new_server("swap_coinbase_not_mature", &server_key, &vec![]);
+		let mut coinbase_node = MockGrinNode::new();
+		coinbase_node.add_coinbase_utxo(&input_commit, 99);
+		let coinbase_node = Arc::new(coinbase_node);
+		server.node = coinbase_node.clone();
+
+		// Mined at height 99, so it can first be spent at 99 + maturity
+		let spendable_height = 99 + global::coinbase_maturity();
+		let result = server.swap(&onion, &comsig, EXPIRY_HEIGHT);
+		assert_eq!(
+			Err(SwapError::CoinNotMature {
+				commit: input_commit.clone(),
+				spendable_height,
+			}),
+			result
+		);
+		assert_eq!(
+			0,
+			server.store.lock().unwrap().swaps_iter().unwrap().count()
+		);
+
+		coinbase_node.set_chain_height(spendable_height - 1);
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+
+		Ok(())
+	}
+
+	/// Inputs with fewer than 'min_input_confirmations' are rejected, and their swaps are held back
+	/// from rounds if a reorg leaves the input shallower than that again.
+	#[test]
+	fn swap_min_input_confirmations() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let (mut server, _node) = new_server("swap_min_input_confirmations", &server_key, &vec![]);
+		let mut confirmed_node = MockGrinNode::new();
+		confirmed_node.add_confirmed_utxo(&input_commit, 95);
+		let confirmed_node = Arc::new(confirmed_node);
+		server.node = confirmed_node.clone();
+		server.server_config.min_input_confirmations = Some(10);
+
+		// Mined at height 95, so it has 6 confirmations at the mock's height of 100
+		assert_eq!(
+			Err(SwapError::InsufficientConfirmations {
+				commit: input_commit.clone(),
+				confirmations: 6,
+				required: 10,
+			}),
+			server.swap(&onion, &comsig, EXPIRY_HEIGHT)
+		);
+
+		confirmed_node.set_chain_height(104);
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+
+		// Held back while a reorg leaves it a block short
+		confirmed_node.set_chain_height(103);
+		assert!(server.execute_round()?.is_none());
+		confirmed_node.set_chain_height(104);
+		let tx = server.execute_round()?.unwrap();
+		assert_eq!(1, tx.inputs().len());
+
+		Ok(())
+	}
+
+	/// Swaps of outputs still in the mempool are only accepted when enabled, up to the configured
+	/// limit, and wait for the output to confirm before joining a round.
+	#[test]
+	fn swap_unconfirmed_input() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let server_key = secp::random_secret();
+		let new_swap =
+			|| -> Result<(Commitment, Onion, ComSignature), Box<dyn std::error::Error>> {
+				let blind = secp::random_secret();
+				let input_commit = secp::commit(value, &blind)?;
+				let hop_excess = secp::random_secret();
+				let proof = proof(value, fee, &blind, &hop_excess);
+				let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+				let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+				let comsig =
+					ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+				Ok((input_commit, onion, comsig))
+			};
+		let (input_commit, onion, comsig) = new_swap()?;
+		let (other_commit, other_onion, other_comsig) = new_swap()?;
+
+		let (mut server, node) = new_server("swap_unconfirmed_input", &server_key, &vec![]);
+		node.add_pool_output(&input_commit);
+		node.add_pool_output(&other_commit);
+		assert_eq!(
+			Err(SwapError::CoinNotFound {
+				commit: input_commit.clone()
+			}),
+			server.swap(&onion, &comsig, EXPIRY_HEIGHT)
+		);
+
+		server.server_config.zero_conf_max_pending = Some(1);
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		assert!(
+			server
+				.store
+				.lock()
+				.unwrap()
+				.get_swap(&input_commit)?
+				.unconfirmed_input
+		);
+		assert_eq!(
+			Err(SwapError::UnconfirmedLimitReached {
+				commit: other_commit.clone(),
+				limit: 1,
+			}),
+			server.swap(&other_onion, &other_comsig, EXPIRY_HEIGHT)
+		);
+
+		// Held back until the input confirms
+		assert!(server.execute_round()?.is_none());
+		node.confirm_pool_output(&input_commit);
+		let tx = server.execute_round()?.unwrap();
+		assert_eq!(1, tx.inputs().len());
+
+		Ok(())
+	}
+
+	/// Resubmitting the same onion succeeds without changing the stored swap, but a different
+	/// onion for the same commitment returns AlreadySwapped.
 	#[test]
 	fn swap_already_swapped() -> Result<(), Box<dyn std::error::Error>> {
 		let value: u64 = 200_000_000;
@@ -612,13 +6358,31 @@ mod tests {
 		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
 
 		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
-		let comsig = ComSignature::sign(value, &blind, &onion.serialize()?)?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
 
 		let (server, _node) = new_server("swap_already_swapped", &server_key, &vec![&input_commit]);
-		server.swap(&onion, &comsig)?;
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
 
 		// Call swap a second time
-		let result = server.swap(&onion, &comsig);
+		let stored = server.store().lock().unwrap().get_swap(&input_commit)?;
+		server.swap(&onion, &comsig, EXPIRY_HEIGHT)?;
+		assert_eq!(
+			stored,
+			server.store().lock().unwrap().get_swap(&input_commit)?
+		);
+
+		// Swap the same commitment to a different output
+		let other_excess = secp::random_secret();
+		let other_proof = proof(value, fee, &blind, &other_excess);
+		let other_hop = new_hop(&server_key, &other_excess, fee, Some(other_proof));
+		let other_onion = test_util::create_onion(&input_commit, &vec![other_hop])?;
+		let other_comsig = ComSignature::sign(
+			value,
+			&blind,
+			&domain(&server_key),
+			&other_onion.serialize()?,
+		)?;
+		let result = server.swap(&other_onion, &other_comsig, EXPIRY_HEIGHT);
 		assert_eq!(
 			Err(SwapError::AlreadySwapped {
 				commit: input_commit.clone()
@@ -645,11 +6409,11 @@ mod tests {
 		let hop = new_hop(&wrong_server_key, &hop_excess, fee, Some(proof));
 
 		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
-		let comsig = ComSignature::sign(value, &blind, &onion.serialize()?)?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
 
 		let (server, _node) =
 			new_server("swap_peel_onion_failure", &server_key, &vec![&input_commit]);
-		let result = server.swap(&onion, &comsig);
+		let result = server.swap(&onion, &comsig, EXPIRY_HEIGHT);
 
 		assert!(result.is_err());
 		assert_error_type!(result, SwapError::PeelOnionFailure(_));
@@ -671,10 +6435,10 @@ mod tests {
 		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
 
 		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
-		let comsig = ComSignature::sign(value, &blind, &onion.serialize()?)?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
 
 		let (server, _node) = new_server("swap_fee_too_low", &server_key, &vec![&input_commit]);
-		let result = server.swap(&onion, &comsig);
+		let result = server.swap(&onion, &comsig, EXPIRY_HEIGHT);
 		assert_eq!(
 			Err(SwapError::FeeTooLow {
 				minimum_fee: 12_500_000,
@@ -685,4 +6449,51 @@ mod tests {
 
 		Ok(())
 	}
+
+	/// Refuses every swap into one output
+	struct DenyOutput(Commitment);
+
+	impl SwapPolicy for DenyOutput {
+		fn check(&self, _input: &Commitment, output: &Commitment) -> Result<(), PolicyRejection> {
+			match output == &self.0 {
+				true => Err(PolicyRejection {
+					commit: output.clone(),
+					reason: "denied".to_string(),
+				}),
+				false => Ok(()),
+			}
+		}
+	}
+
+	/// Returns PolicyRejected for swaps the configured policy refuses, without saving them.
+	#[test]
+	fn swap_policy_rejected() -> Result<(), Box<dyn std::error::Error>> {
+		let value: u64 = 200_000_000;
+		let fee: u64 = 50_000_000;
+		let blind = secp::random_secret();
+		let input_commit = secp::commit(value, &blind)?;
+
+		let server_key = secp::random_secret();
+		let hop_excess = secp::random_secret();
+		let proof = proof(value, fee, &blind, &hop_excess);
+		let hop = new_hop(&server_key, &hop_excess, fee, Some(proof));
+
+		let onion = test_util::create_onion(&input_commit, &vec![hop])?;
+		let comsig = ComSignature::sign(value, &blind, &domain(&server_key), &onion.serialize()?)?;
+
+		let (server, _node) = new_server("swap_policy_rejected", &server_key, &vec![&input_commit]);
+		let output_commit = server.peel_layer(&onion)?.1.commit;
+		let server = server.with_policy(Arc::new(DenyOutput(output_commit.clone())));
+		let result = server.swap(&onion, &comsig, EXPIRY_HEIGHT);
+		assert_eq!(
+			Err(SwapError::PolicyRejected {
+				commit: output_commit,
+				reason: "denied".to_string(),
+			}),
+			result
+		);
+		assert!(server.list_swaps()?.is_empty());
+
+		Ok(())
+	}
 }
@@ -0,0 +1,258 @@
+use std::ffi::OsString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Environment variable systemd passes the notification socket's path in
+const NOTIFY_SOCKET_ENV: &str = "NOTIFY_SOCKET";
+
+/// Environment variables systemd sets when the service has a watchdog (WatchdogSec=)
+const WATCHDOG_USEC_ENV: &str = "WATCHDOG_USEC";
+const WATCHDOG_PID_ENV: &str = "WATCHDOG_PID";
+
+/// An instance's startup and liveness, which the supervisor reports to the service manager
+pub struct InstanceStatus {
+	ready: AtomicBool,
+	last_heartbeat: Mutex<Instant>,
+}
+
+impl InstanceStatus {
+	pub fn new() -> InstanceStatus {
+		InstanceStatus {
+			ready: AtomicBool::new(false),
+			last_heartbeat: Mutex::new(Instant::now()),
+		}
+	}
+
+	/// Marks the instance as ready, once its wallet, node and store are all usable
+	pub fn set_ready(&self) {
+		self.ready.store(true, Ordering::Relaxed);
+	}
+
+	pub fn is_ready(&self) -> bool {
+		self.ready.load(Ordering::Relaxed)
+	}
+
+	/// Records that the instance's round loop is still running
+	pub fn heartbeat(&self) {
+		*self.last_heartbeat.lock().unwrap() = Instant::now();
+	}
+
+	fn since_heartbeat(&self) -> Duration {
+		self.last_heartbeat.lock().unwrap().elapsed()
+	}
+}
+
+impl Default for InstanceStatus {
+	fn default() -> Self {
+		InstanceStatus::new()
+	}
+}
+
+/// Reports the server's state over systemd's notification protocol (sd_notify).
+/// READY=1 is sent once every instance is ready, then WATCHDOG=1 at half the watchdog interval
+/// for as long as every instance's round loop keeps running, so a hung round gets restarted.
+pub struct Notifier {
+	socket: Option<OsString>,
+	watchdog: Option<Duration>,
+	ready_sent: bool,
+	last_ping: Option<Instant>,
+}
+
+impl Notifier {
+	pub fn new(socket: Option<OsString>, watchdog: Option<Duration>) -> Notifier {
+		Notifier {
+			socket,
+			watchdog,
+			ready_sent: false,
+			last_ping: None,
+		}
+	}
+
+	/// Uses the socket and watchdog interval systemd passed in the environment, if any.
+	/// Without them (e.g. when not run with Type=notify), notifications are skipped.
+	pub fn from_env() -> Notifier {
+		Notifier::new(std::env::var_os(NOTIFY_SOCKET_ENV), watchdog_interval())
+	}
+
+	/// Sends whatever the instances' current state calls for
+	pub fn update(&mut self, instances: &[&InstanceStatus]) -> io::Result<()> {
+		if !self.ready_sent {
+			if !instances.iter().all(|s| s.is_ready()) {
+				return Ok(());
+			}
+			self.send(&format!("READY=1\nMAINPID={}", std::process::id()))?;
+			self.ready_sent = true;
+		}
+
+		if let Some(interval) = self.watchdog {
+			let alive = instances.iter().all(|s| s.since_heartbeat() < interval);
+			let due = self
+				.last_ping
+				.map(|t| t.elapsed() >= interval / 2)
+				.unwrap_or(true);
+			if alive && due {
+				self.send("WATCHDOG=1")?;
+				self.last_ping = Some(Instant::now());
+			}
+		}
+		Ok(())
+	}
+
+	/// Tells the service manager the server is shutting down
+	pub fn stopping(&self) -> io::Result<()> {
+		self.send("STOPPING=1")
+	}
+
+	fn send(&self, state: &str) -> io::Result<()> {
+		let path = match &self.socket {
+			Some(path) => path,
+			None => return Ok(()),
+		};
+		let socket = UnixDatagram::unbound()?;
+		match path.as_bytes().strip_prefix(b"@") {
+			Some(name) => send_abstract(&socket, name, state)?,
+			None => {
+				socket.send_to(state.as_bytes(), path)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+#[cfg(target_os = "linux")]
+fn send_abstract(socket: &UnixDatagram, name: &[u8], state: &str) -> io::Result<()> {
+	use std::os::linux::net::SocketAddrExt;
+	use std::os::unix::net::SocketAddr;
+
+	let addr = SocketAddr::from_abstract_name(name)?;
+	socket.send_to_addr(state.as_bytes(), &addr)?;
+	Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_abstract(_socket: &UnixDatagram, _name: &[u8], _state: &str) -> io::Result<()> {
+	Err(io::Error::new(
+		io::ErrorKind::Unsupported,
+		"Abstract notification sockets are only supported on linux",
+	))
+}
+
+/// How often the service manager expects a watchdog ping, if it set a watchdog for this process
+fn watchdog_interval() -> Option<Duration> {
+	let usec: u64 = std::env::var(WATCHDOG_USEC_ENV).ok()?.parse().ok()?;
+	if let Ok(pid) = std::env::var(WATCHDOG_PID_ENV) {
+		if pid.parse::<u32>().ok()? != std::process::id() {
+			return None;
+		}
+	}
+	Some(Duration::from_micros(usec))
+}
+
+/// Detaches from the terminal to run in the background: forks (the parent exits), starts a new
+/// session, and points stdin/stdout/stderr at /dev/null. The working directory is kept, so
+/// relative paths in the config still resolve.
+/// Must be called before any threads are spawned, since only the calling thread survives a fork.
+pub fn daemonize() -> io::Result<()> {
+	let dev_null = std::fs::OpenOptions::new()
+		.read(true)
+		.write(true)
+		.open("/dev/null")?;
+
+	// SAFETY: no other threads are running, so the child starts from a consistent state
+	unsafe {
+		match libc::fork() {
+			-1 => return Err(io::Error::last_os_error()),
+			0 => {}
+			_ => libc::_exit(0),
+		}
+		if libc::setsid() == -1 {
+			return Err(io::Error::last_os_error());
+		}
+		for fd in 0..3 {
+			if libc::dup2(dev_null.as_raw_fd(), fd) == -1 {
+				return Err(io::Error::last_os_error());
+			}
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{InstanceStatus, Notifier};
+	use std::os::unix::net::UnixDatagram;
+	use std::time::Duration;
+
+	fn recv(socket: &UnixDatagram) -> Option<String> {
+		let mut buf = [0u8; 256];
+		socket
+			.recv(&mut buf)
+			.ok()
+			.map(|len| String::from_utf8_lossy(&buf[..len]).to_string())
+	}
+
+	/// READY=1 waits for every instance, and watchdog pings stop once an instance stops beating
+	#[test]
+	fn notify_ready_and_watchdog() -> Result<(), Box<dyn std::error::Error>> {
+		let dir = "./target/tmp/.notify_ready_and_watchdog";
+		let _ = std::fs::remove_dir_all(dir);
+		std::fs::create_dir_all(dir)?;
+		let path = format!("{}/notify.sock", dir);
+		let socket = UnixDatagram::bind(&path)?;
+		socket.set_nonblocking(true)?;
+
+		let mut notifier = Notifier::new(Some(path.into()), Some(Duration::from_millis(200)));
+		let first = InstanceStatus::new();
+		let second = InstanceStatus::new();
+
+		first.set_ready();
+		notifier.update(&[&first, &second])?;
+		assert_eq!(None, recv(&socket));
+
+		second.set_ready();
+		notifier.update(&[&first, &second])?;
+		assert_eq!(
+			Some(format!("READY=1\nMAINPID={}", std::process::id())),
+			recv(&socket)
+		);
+		assert_eq!(Some("WATCHDOG=1".to_string()), recv(&socket));
+
+		// Not due again until half the interval has passed
+		notifier.update(&[&first, &second])?;
+		assert_eq!(None, recv(&socket));
+
+		std::thread::sleep(Duration::from_millis(150));
+		first.heartbeat();
+		second.heartbeat();
+		notifier.update(&[&first, &second])?;
+		assert_eq!(Some("WATCHDOG=1".to_string()), recv(&socket));
+
+		// An instance that stops beating holds back the pings, so the service gets restarted
+		std::thread::sleep(Duration::from_millis(150));
+		first.heartbeat();
+		std::thread::sleep(Duration::from_millis(100));
+		first.heartbeat();
+		notifier.update(&[&first, &second])?;
+		assert_eq!(None, recv(&socket));
+
+		notifier.stopping()?;
+		assert_eq!(Some("STOPPING=1".to_string()), recv(&socket));
+		Ok(())
+	}
+
+	/// Without a notification socket, nothing is sent
+	#[test]
+	fn notify_disabled() -> Result<(), Box<dyn std::error::Error>> {
+		let status = InstanceStatus::new();
+		status.set_ready();
+		let mut notifier = Notifier::new(None, Some(Duration::from_millis(100)));
+		notifier.update(&[&status])?;
+		notifier.stopping()?;
+		Ok(())
+	}
+}
@@ -0,0 +1,154 @@
+use grin_core::ser::ProtocolVersion;
+use grin_store::Store;
+use std::path::Path;
+use thiserror::Error;
+
+/// Error types for the key-value storage behind a swap store
+#[derive(Clone, Error, Debug, PartialEq)]
+pub enum StorageError {
+	#[error("LMDB error: {0}")]
+	LmdbError(grin_store::lmdb::Error),
+	#[error("sled error: {0}")]
+	SledError(String),
+	#[error("Invalid database path: {0:?}")]
+	InvalidPath(String),
+}
+
+impl From<grin_store::lmdb::Error> for StorageError {
+	fn from(e: grin_store::lmdb::Error) -> StorageError {
+		StorageError::LmdbError(e)
+	}
+}
+
+impl From<sled::Error> for StorageError {
+	fn from(e: sled::Error) -> StorageError {
+		StorageError::SledError(e.to_string())
+	}
+}
+
+/// A change to the database, applied as part of a batch.
+/// Deleting a key that isn't stored does nothing.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WriteOp {
+	Put(Vec<u8>, Vec<u8>),
+	Delete(Vec<u8>),
+}
+
+/// The key-value storage a swap store keeps its records in.
+/// Writes only happen in batches, which are applied atomically: either every operation in a batch
+/// is applied, or none are.
+pub trait SwapStorage: Send + Sync {
+	/// The protocol version records are serialized with
+	fn protocol_version(&self) -> ProtocolVersion;
+
+	/// Reads the value stored under the key, if there is one
+	fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError>;
+
+	/// Whether a value is stored under the key
+	fn exists(&self, key: &[u8]) -> Result<bool, StorageError> {
+		Ok(self.get(key)?.is_some())
+	}
+
+	/// Every key-value pair whose key starts with 'prefix', ordered by key
+	fn iter_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, StorageError>;
+
+	/// Applies the operations in a single write transaction
+	fn write_batch(&self, ops: Vec<WriteOp>) -> Result<(), StorageError>;
+}
+
+/// Storage in an LMDB environment, through grin_store
+pub struct LmdbStorage {
+	db: Store,
+}
+
+impl LmdbStorage {
+	/// Opens (or creates) the 'env_name' environment under 'db_root', keeping records in 'db_name'
+	pub fn open(db_root: &str, env_name: &str, db_name: &str) -> Result<LmdbStorage, StorageError> {
+		let db = Store::new(db_root, Some(env_name), Some(db_name), None)?;
+		Ok(LmdbStorage { db })
+	}
+}
+
+impl SwapStorage for LmdbStorage {
+	fn protocol_version(&self) -> ProtocolVersion {
+		self.db.protocol_version()
+	}
+
+	fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError> {
+		Ok(self.db.batch()?.get_with(key, |_, v| Ok(v.to_vec()))?)
+	}
+
+	fn exists(&self, key: &[u8]) -> Result<bool, StorageError> {
+		Ok(self.db.batch()?.exists(key)?)
+	}
+
+	fn iter_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, StorageError> {
+		let iter = self.db.iter(prefix, |k, v| Ok((k.to_vec(), v.to_vec())))?;
+		Ok(iter.collect())
+	}
+
+	fn write_batch(&self, ops: Vec<WriteOp>) -> Result<(), StorageError> {
+		let batch = self.db.batch()?;
+		for op in ops {
+			match op {
+				WriteOp::Put(key, value) => batch.put(&key[..], &value[..])?,
+				WriteOp::Delete(key) => {
+					if batch.exists(&key[..])? {
+						batch.delete(&key[..])?;
+					}
+				}
+			}
+		}
+		batch.commit()?;
+		Ok(())
+	}
+}
+
+/// Storage in a sled database, which grows as needed, with no map size to configure
+pub struct SledStorage {
+	db: sled::Db,
+}
+
+impl SledStorage {
+	/// Opens (or creates) the database in the 'path' directory
+	pub fn open(path: &Path) -> Result<SledStorage, StorageError> {
+		let db = sled::open(path)?;
+		Ok(SledStorage { db })
+	}
+}
+
+impl SwapStorage for SledStorage {
+	fn protocol_version(&self) -> ProtocolVersion {
+		ProtocolVersion::local()
+	}
+
+	fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StorageError> {
+		Ok(self.db.get(key)?.map(|v| v.to_vec()))
+	}
+
+	fn exists(&self, key: &[u8]) -> Result<bool, StorageError> {
+		Ok(self.db.contains_key(key)?)
+	}
+
+	fn iter_prefix(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, StorageError> {
+		let mut records = Vec::new();
+		for entry in self.db.scan_prefix(prefix) {
+			let (k, v) = entry?;
+			records.push((k.to_vec(), v.to_vec()));
+		}
+		Ok(records)
+	}
+
+	fn write_batch(&self, ops: Vec<WriteOp>) -> Result<(), StorageError> {
+		let mut batch = sled::Batch::default();
+		for op in ops {
+			match op {
+				WriteOp::Put(key, value) => batch.insert(key, value),
+				WriteOp::Delete(key) => batch.remove(key),
+			}
+		}
+		self.db.apply_batch(batch)?;
+		self.db.flush()?;
+		Ok(())
+	}
+}
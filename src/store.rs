@@ -1,28 +1,107 @@
+use crate::compress;
+use crate::config::StoreBackend;
+use crate::features::FeatureFlags;
 use crate::onion::Onion;
-use crate::secp::{self, Commitment, RangeProof, SecretKey};
-use crate::types::{read_optional, write_optional};
+use crate::secp::{
+	self, Commitment, ContextFlag, PublicKey, RangeProof, Secp256k1, SecretKey, Signature,
+	COMPRESSED_PUBLIC_KEY_SIZE,
+};
+use crate::storage::{LmdbStorage, SledStorage, StorageError, SwapStorage, WriteOp};
+use crate::types::{read_optional, write_optional, FeeChange};
 use grin_core::core::hash::Hash;
 
-use grin_core::core::Input;
+use grin_core::core::{Input, Transaction, TxKernel};
 use grin_core::ser::{
 	self, DeserializationMode, ProtocolVersion, Readable, Reader, Writeable, Writer,
 };
-use grin_store::{self as store, Store};
+use grin_store as store;
 use grin_util::ToHex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 use thiserror::Error;
 
 const DB_NAME: &str = "swap";
 const STORE_SUBPATH: &str = "swaps";
+const SLED_DB_NAME: &str = "swap_sled";
+
+/// Largest record a compressed value may expand to. Swaps are a few KB per hop.
+const MAX_RECORD_SIZE: usize = 1024 * 1024;
 
-const CURRENT_VERSION: u8 = 0;
+pub const CURRENT_VERSION: u8 = 11;
 const SWAP_PREFIX: u8 = b'S';
+const FEE_OUTPUT_PREFIX: u8 = b'F';
+const META_PREFIX: u8 = b'M';
+const KERNEL_EXCESS_PREFIX: u8 = b'K';
+const RECEIPT_PREFIX: u8 = b'C';
+const ROUND_PREFIX: u8 = b'R';
+const DECOY_PREFIX: u8 = b'D';
+const PENDING_TX_PREFIX: u8 = b'P';
+const REJECTION_PREFIX: u8 = b'J';
+const OUTPUT_INDEX_PREFIX: u8 = b'O';
+const ROUND_TX_PREFIX: u8 = b'T';
+const KERNEL_INDEX_PREFIX: u8 = b'I';
+const API_KEY_SWAPS_PREFIX: u8 = b'A';
+
+/// Every prefix records are stored under
+const RECORD_PREFIXES: [u8; 13] = [
+	SWAP_PREFIX,
+	FEE_OUTPUT_PREFIX,
+	META_PREFIX,
+	KERNEL_EXCESS_PREFIX,
+	RECEIPT_PREFIX,
+	ROUND_PREFIX,
+	DECOY_PREFIX,
+	PENDING_TX_PREFIX,
+	REJECTION_PREFIX,
+	OUTPUT_INDEX_PREFIX,
+	ROUND_TX_PREFIX,
+	KERNEL_INDEX_PREFIX,
+	API_KEY_SWAPS_PREFIX,
+];
+
+const LAST_ROUND_ID_KEY: &str = "last_round_id";
+const FEATURE_FLAGS_KEY: &str = "feature_flags";
+const WRITE_CHECK_KEY: &str = "write_check";
+const NEXT_REJECTION_KEY: &str = "next_rejection";
+const ROUND_MARKER_KEY: &str = "round_in_progress";
 
 /// Swap statuses
 #[derive(Clone, Debug, PartialEq)]
 pub enum SwapStatus {
 	Unprocessed,
-	InProcess { kernel_hash: Hash },
-	Completed { kernel_hash: Hash, block_hash: Hash },
+	InProcess {
+		kernel_hash: Hash,
+	},
+	Completed {
+		kernel_hash: Hash,
+		block_hash: Hash,
+	},
+	/// The node rejected the round transaction because of the swap, e.g. its input was spent
+	/// elsewhere. It's left out of later rounds.
+	Failed,
+}
+
+impl SwapStatus {
+	/// Short, human-readable name of the status
+	pub fn name(&self) -> &'static str {
+		match self {
+			SwapStatus::Unprocessed => "unprocessed",
+			SwapStatus::InProcess { .. } => "in_process",
+			SwapStatus::Completed { .. } => "completed",
+			SwapStatus::Failed => "failed",
+		}
+	}
+
+	/// Hash of the kernel of the round the swap was included in, if it has been
+	pub fn kernel_hash(&self) -> Option<Hash> {
+		match self {
+			SwapStatus::InProcess { kernel_hash } | SwapStatus::Completed { kernel_hash, .. } => {
+				Some(*kernel_hash)
+			}
+			SwapStatus::Unprocessed | SwapStatus::Failed => None,
+		}
+	}
 }
 
 impl Writeable for SwapStatus {
@@ -43,6 +122,9 @@ impl Writeable for SwapStatus {
 				kernel_hash.write(writer)?;
 				block_hash.write(writer)?;
 			}
+			SwapStatus::Failed => {
+				writer.write_u8(3)?;
+			}
 		};
 
 		Ok(())
@@ -65,6 +147,7 @@ impl Readable for SwapStatus {
 					block_hash,
 				}
 			}
+			3 => SwapStatus::Failed,
 			_ => {
 				return Err(ser::Error::CorruptedData);
 			}
@@ -90,6 +173,21 @@ pub struct SwapData {
 	pub onion: Onion,
 	/// The status of the swap
 	pub status: SwapStatus,
+	/// Chain height at which the swap expires if it hasn't been included in a round yet
+	pub expiry_height: u64,
+	/// Whether the input was still unconfirmed (only in the mempool) when the swap was accepted.
+	/// The swap isn't included in a round until the input confirms.
+	pub unconfirmed_input: bool,
+	/// The owner's kernel for the part of the excess they kept, if any.
+	/// It's included in the round transaction alongside the round's kernel.
+	pub owner_kernel: Option<TxKernel>,
+	/// Unix time (in seconds) the swap was accepted, or 0 if it was saved before this was recorded
+	pub accepted_at: u64,
+	/// Id of the round whose acceptance window was open when the swap was accepted
+	pub accepted_round: u64,
+	/// Change for an over-paid fee, paid out alongside the swap's output.
+	/// 'fee' is what's left of the payload's fee once the change is paid.
+	pub fee_change: Option<FeeChange>,
 }
 
 impl Writeable for SwapData {
@@ -102,6 +200,12 @@ impl Writeable for SwapData {
 		writer.write_u64(self.fee.into())?;
 		self.onion.write(writer)?;
 		self.status.write(writer)?;
+		writer.write_u64(self.expiry_height)?;
+		writer.write_u8(self.unconfirmed_input as u8)?;
+		write_optional(writer, &self.owner_kernel)?;
+		writer.write_u64(self.accepted_at)?;
+		writer.write_u64(self.accepted_round)?;
+		write_optional(writer, &self.fee_change)?;
 
 		Ok(())
 	}
@@ -110,7 +214,7 @@ impl Writeable for SwapData {
 impl Readable for SwapData {
 	fn read<R: Reader>(reader: &mut R) -> Result<SwapData, ser::Error> {
 		let version = reader.read_u8()?;
-		if version != CURRENT_VERSION {
+		if version > CURRENT_VERSION {
 			return Err(ser::Error::UnsupportedProtocolVersion);
 		}
 
@@ -121,6 +225,41 @@ impl Readable for SwapData {
 		let fee = reader.read_u64()?;
 		let onion = Onion::read(reader)?;
 		let status = SwapStatus::read(reader)?;
+		// Swaps saved before expiries were introduced never expire
+		let expiry_height = match version {
+			0 => u64::MAX,
+			_ => reader.read_u64()?,
+		};
+		// Swaps saved before zero-conf support always spent confirmed inputs
+		let unconfirmed_input = match version {
+			0 | 1 => false,
+			_ => reader.read_u8()? != 0,
+		};
+		// Swaps saved before owner kernels were introduced never had one
+		let owner_kernel = match version {
+			0..=3 => None,
+			_ => read_optional(reader)?,
+		};
+		// Swaps saved before acceptance was recorded are ordered ahead of every newer swap
+		let (accepted_at, accepted_round) = match version {
+			0..=5 => (0, 0),
+			_ => (reader.read_u64()?, reader.read_u64()?),
+		};
+		// Swaps saved before fee change was supported never had any
+		let fee_change = match version {
+			0..=6 => None,
+			_ => read_optional(reader)?,
+		};
+		// Swaps saved with versions 8 to 10 may carry a reply key and sealed reply, which are
+		// no longer sent
+		if (8..=10).contains(&version) {
+			if reader.read_u8()? != 0 {
+				secp::read_secret_key(reader)?;
+			}
+			if reader.read_u8()? != 0 {
+				reader.read_bytes_len_prefix()?;
+			}
+		}
 		Ok(SwapData {
 			excess,
 			output_commit,
@@ -129,13 +268,519 @@ impl Readable for SwapData {
 			fee,
 			onion,
 			status,
+			expiry_height,
+			unconfirmed_input,
+			owner_kernel,
+			accepted_at,
+			accepted_round,
+			fee_change,
+		})
+	}
+}
+
+/// The wallet output that collected the server's leftover fees for a round
+#[derive(Clone, Debug, PartialEq)]
+pub struct FeeOutputData {
+	/// The commitment of the wallet's output
+	pub output_commit: Commitment,
+	/// The value of the output
+	pub amount: u64,
+	/// Hash of the round transaction's kernel
+	pub kernel_hash: Hash,
+	/// Unix time (in seconds) the round was executed
+	pub timestamp: u64,
+}
+
+impl Writeable for FeeOutputData {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u8(CURRENT_VERSION)?;
+		writer.write_fixed_bytes(&self.output_commit)?;
+		writer.write_u64(self.amount)?;
+		self.kernel_hash.write(writer)?;
+		writer.write_u64(self.timestamp)?;
+
+		Ok(())
+	}
+}
+
+impl Readable for FeeOutputData {
+	fn read<R: Reader>(reader: &mut R) -> Result<FeeOutputData, ser::Error> {
+		let version = reader.read_u8()?;
+		if version > CURRENT_VERSION {
+			return Err(ser::Error::UnsupportedProtocolVersion);
+		}
+
+		let output_commit = Commitment::read(reader)?;
+		let amount = reader.read_u64()?;
+		let kernel_hash = Hash::read(reader)?;
+		let timestamp = reader.read_u64()?;
+		Ok(FeeOutputData {
+			output_commit,
+			amount,
+			kernel_hash,
+			timestamp,
+		})
+	}
+}
+
+/// An output of the server's own, spent and recreated as a decoy self-swap in rounds with
+/// too few participants. The blind is kept, since the output is spent again by later rounds,
+/// but masked with a key only the server has, so the store alone can't spend it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecoyData {
+	/// The commitment of the decoy output
+	pub commit: Commitment,
+	/// The value of the output
+	pub amount: u64,
+	/// The output's blinding factor, masked unless 'masked' is false
+	pub blind: SecretKey,
+	/// Whether 'blind' is masked. Decoys saved by older versions kept theirs as is.
+	pub masked: bool,
+	/// Hash of the kernel of the round that created the output
+	pub kernel_hash: Hash,
+}
+
+impl Writeable for DecoyData {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u8(CURRENT_VERSION)?;
+		writer.write_fixed_bytes(&self.commit)?;
+		writer.write_u64(self.amount)?;
+		writer.write_fixed_bytes(&self.blind)?;
+		self.kernel_hash.write(writer)?;
+		writer.write_u8(self.masked as u8)?;
+
+		Ok(())
+	}
+}
+
+impl Readable for DecoyData {
+	fn read<R: Reader>(reader: &mut R) -> Result<DecoyData, ser::Error> {
+		let version = reader.read_u8()?;
+		if version > CURRENT_VERSION {
+			return Err(ser::Error::UnsupportedProtocolVersion);
+		}
+
+		let commit = Commitment::read(reader)?;
+		let amount = reader.read_u64()?;
+		let blind = secp::read_secret_key(reader)?;
+		let kernel_hash = Hash::read(reader)?;
+		let masked = match version {
+			0..=9 => false,
+			_ => reader.read_u8()? != 0,
+		};
+		Ok(DecoyData {
+			commit,
+			amount,
+			blind,
+			masked,
+			kernel_hash,
+		})
+	}
+}
+
+/// The decoys spent by a round, and those it created in their place
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RoundDecoys {
+	pub spent: Vec<Commitment>,
+	pub created: Vec<DecoyData>,
+}
+
+/// A round transaction queued to be posted to the node. It's saved before the first attempt and
+/// removed in the same write that records the round, so a transaction is never lost part way.
+/// One handed to the leader of a coordinated round is queued again once its round is recorded,
+/// until the node is seen to have it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingTxData {
+	/// Id of the round's acceptance window
+	pub round_id: u64,
+	/// Hash of the round transaction's kernel
+	pub kernel_hash: Hash,
+	/// The assembled round transaction
+	pub tx: Transaction,
+	/// Input commitments of the swaps included in the transaction
+	pub input_commits: Vec<Commitment>,
+	/// Number of times posting the transaction has failed
+	pub attempts: u32,
+	/// Unix time (in seconds) the transaction was queued
+	pub queued_at: u64,
+	/// Round slot of the leader the transaction was handed to, if it wasn't posted directly
+	pub handed_off_slot: Option<u64>,
+}
+
+impl Writeable for PendingTxData {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u8(CURRENT_VERSION)?;
+		writer.write_u64(self.round_id)?;
+		self.kernel_hash.write(writer)?;
+		self.tx.write(writer)?;
+		writer.write_u64(self.input_commits.len() as u64)?;
+		for commit in &self.input_commits {
+			writer.write_fixed_bytes(commit)?;
+		}
+		writer.write_u32(self.attempts)?;
+		writer.write_u64(self.queued_at)?;
+		write_optional(writer, &self.handed_off_slot)?;
+
+		Ok(())
+	}
+}
+
+impl Readable for PendingTxData {
+	fn read<R: Reader>(reader: &mut R) -> Result<PendingTxData, ser::Error> {
+		let version = reader.read_u8()?;
+		if version > CURRENT_VERSION {
+			return Err(ser::Error::UnsupportedProtocolVersion);
+		}
+
+		let round_id = reader.read_u64()?;
+		let kernel_hash = Hash::read(reader)?;
+		let tx = Transaction::read(reader)?;
+		let num_commits = reader.read_u64()?;
+		let mut input_commits = Vec::new();
+		for _ in 0..num_commits {
+			input_commits.push(Commitment::read(reader)?);
+		}
+		let attempts = reader.read_u32()?;
+		let queued_at = reader.read_u64()?;
+		// Transactions queued before rounds were coordinated were always posted directly
+		let handed_off_slot = match version {
+			0..=8 => None,
+			_ => read_optional(reader)?,
+		};
+		Ok(PendingTxData {
+			round_id,
+			kernel_hash,
+			tx,
+			input_commits,
+			attempts,
+			queued_at,
+			handed_off_slot,
+		})
+	}
+}
+
+/// The input commitments of the swaps included in a round, indexed by the round's kernel hash
+#[derive(Clone, Debug, Default, PartialEq)]
+struct KernelIndexData {
+	input_commits: Vec<Commitment>,
+}
+
+impl Writeable for KernelIndexData {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u8(CURRENT_VERSION)?;
+		writer.write_u64(self.input_commits.len() as u64)?;
+		for commit in &self.input_commits {
+			writer.write_fixed_bytes(commit)?;
+		}
+
+		Ok(())
+	}
+}
+
+impl Readable for KernelIndexData {
+	fn read<R: Reader>(reader: &mut R) -> Result<KernelIndexData, ser::Error> {
+		let version = reader.read_u8()?;
+		if version > CURRENT_VERSION {
+			return Err(ser::Error::UnsupportedProtocolVersion);
+		}
+
+		let num_commits = reader.read_u64()?;
+		let mut input_commits = Vec::new();
+		for _ in 0..num_commits {
+			input_commits.push(Commitment::read(reader)?);
+		}
+		Ok(KernelIndexData { input_commits })
+	}
+}
+
+/// A round the server executed
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoundData {
+	/// Id of the round's acceptance window
+	pub round_id: u64,
+	/// Hash of the round transaction's kernel
+	pub kernel_hash: Hash,
+	/// Unix time (in seconds) the round transaction was posted
+	pub timestamp: u64,
+	/// The fee paid by each swap included in the round
+	pub fees: Vec<u64>,
+	/// Number of distinct outputs the round's participants received, not counting decoys
+	pub anonymity_set: u64,
+	/// Weight of the round transaction
+	pub tx_weight: u64,
+	/// Height of the block the round's kernel was confirmed in, once it has been seen on chain
+	pub confirmed_height: Option<u64>,
+}
+
+impl RoundData {
+	/// Number of swaps included in the round
+	pub fn swap_count(&self) -> usize {
+		self.fees.len()
+	}
+
+	/// Sum of the fees paid by the round's swaps
+	pub fn total_fees(&self) -> u64 {
+		self.fees.iter().sum()
+	}
+}
+
+impl Writeable for RoundData {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u8(CURRENT_VERSION)?;
+		writer.write_u64(self.round_id)?;
+		self.kernel_hash.write(writer)?;
+		writer.write_u64(self.timestamp)?;
+		writer.write_u64(self.fees.len() as u64)?;
+		for fee in &self.fees {
+			writer.write_u64(*fee)?;
+		}
+		writer.write_u64(self.tx_weight)?;
+		write_optional(writer, &self.confirmed_height)?;
+		writer.write_u64(self.anonymity_set)?;
+
+		Ok(())
+	}
+}
+
+impl Readable for RoundData {
+	fn read<R: Reader>(reader: &mut R) -> Result<RoundData, ser::Error> {
+		let version = reader.read_u8()?;
+		if version > CURRENT_VERSION {
+			return Err(ser::Error::UnsupportedProtocolVersion);
+		}
+
+		let round_id = reader.read_u64()?;
+		let kernel_hash = Hash::read(reader)?;
+		let timestamp = reader.read_u64()?;
+		let num_fees = reader.read_u64()?;
+		let mut fees = Vec::new();
+		for _ in 0..num_fees {
+			fees.push(reader.read_u64()?);
+		}
+		// Rounds saved before the full history was kept have no weight or confirmation height
+		let (tx_weight, confirmed_height) = match version {
+			0 | 1 | 2 => (0, None),
+			_ => (reader.read_u64()?, read_optional(reader)?),
+		};
+		// Rounds saved before anonymity sets were recorded never included decoys,
+		// and each of their swaps had an output of its own
+		let anonymity_set = match version {
+			0..=4 => fees.len() as u64,
+			_ => reader.read_u64()?,
+		};
+		Ok(RoundData {
+			round_id,
+			kernel_hash,
+			timestamp,
+			fees,
+			anonymity_set,
+			tx_weight,
+			confirmed_height,
+		})
+	}
+}
+
+/// The server's signed receipt for a swap's inclusion in a round
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReceiptData {
+	/// The commitment of the swap's input
+	pub input_commit: Commitment,
+	/// The commitment of the swap's output
+	pub output_commit: Commitment,
+	/// Hash of the round transaction's kernel
+	pub kernel_hash: Hash,
+	/// Id of the round the swap was included in
+	pub round_id: u64,
+	/// The key the receipt was signed with
+	pub pubkey: PublicKey,
+	/// The server's signature over the receipt
+	pub signature: Signature,
+}
+
+impl Writeable for ReceiptData {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u8(CURRENT_VERSION)?;
+		writer.write_fixed_bytes(&self.input_commit)?;
+		writer.write_fixed_bytes(&self.output_commit)?;
+		self.kernel_hash.write(writer)?;
+		writer.write_u64(self.round_id)?;
+		let secp = Secp256k1::with_caps(ContextFlag::None);
+		writer.write_fixed_bytes(&self.pubkey.serialize_vec(&secp, true)[..])?;
+		self.signature.write(writer)?;
+
+		Ok(())
+	}
+}
+
+impl Readable for ReceiptData {
+	fn read<R: Reader>(reader: &mut R) -> Result<ReceiptData, ser::Error> {
+		let version = reader.read_u8()?;
+		if version > CURRENT_VERSION {
+			return Err(ser::Error::UnsupportedProtocolVersion);
+		}
+
+		let input_commit = Commitment::read(reader)?;
+		let output_commit = Commitment::read(reader)?;
+		let kernel_hash = Hash::read(reader)?;
+		let round_id = reader.read_u64()?;
+		let pubkey_bytes = reader.read_fixed_bytes(COMPRESSED_PUBLIC_KEY_SIZE)?;
+		let secp = Secp256k1::with_caps(ContextFlag::None);
+		let pubkey =
+			PublicKey::from_slice(&secp, &pubkey_bytes).map_err(|_| ser::Error::CorruptedData)?;
+		let signature = Signature::read(reader)?;
+		Ok(ReceiptData {
+			input_commit,
+			output_commit,
+			kernel_hash,
+			round_id,
+			pubkey,
+			signature,
+		})
+	}
+}
+
+/// A swap the server rejected, kept so operators can see why a client's swaps are failing
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RejectionData {
+	/// Sequence number of the rejection, counting every one ever recorded
+	pub id: u64,
+	/// Unix time (in seconds) the swap was rejected
+	pub timestamp: u64,
+	/// Where the request came from: the client's address as reported by a reverse proxy,
+	/// or the transport it arrived over
+	pub source: Option<String>,
+	/// The first 8 bytes of the hash of the rejected onion, hex-encoded
+	pub onion_hash: String,
+	/// The kind of swap error, as named in 'error_codes'
+	pub error: String,
+	/// The error's message
+	pub message: String,
+}
+
+impl Writeable for RejectionData {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u8(CURRENT_VERSION)?;
+		writer.write_u64(self.id)?;
+		writer.write_u64(self.timestamp)?;
+		match &self.source {
+			Some(source) => {
+				writer.write_u8(1)?;
+				writer.write_bytes(source)?;
+			}
+			None => writer.write_u8(0)?,
+		}
+		writer.write_bytes(&self.onion_hash)?;
+		writer.write_bytes(&self.error)?;
+		writer.write_bytes(&self.message)?;
+
+		Ok(())
+	}
+}
+
+impl Readable for RejectionData {
+	fn read<R: Reader>(reader: &mut R) -> Result<RejectionData, ser::Error> {
+		let version = reader.read_u8()?;
+		if version > CURRENT_VERSION {
+			return Err(ser::Error::UnsupportedProtocolVersion);
+		}
+
+		let id = reader.read_u64()?;
+		let timestamp = reader.read_u64()?;
+		let source = match reader.read_u8()? {
+			0 => None,
+			_ => Some(read_string(reader)?),
+		};
+		Ok(RejectionData {
+			id,
+			timestamp,
+			source,
+			onion_hash: read_string(reader)?,
+			error: read_string(reader)?,
+			message: read_string(reader)?,
+		})
+	}
+}
+
+/// Swaps submitted with an API key, counted since the key was first used
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ApiKeySwaps {
+	/// Name of the API key
+	pub name: String,
+	/// Swaps the server accepted
+	pub accepted: u64,
+	/// Swaps the server rejected
+	pub rejected: u64,
+}
+
+impl Writeable for ApiKeySwaps {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u8(CURRENT_VERSION)?;
+		writer.write_bytes(&self.name)?;
+		writer.write_u64(self.accepted)?;
+		writer.write_u64(self.rejected)?;
+
+		Ok(())
+	}
+}
+
+impl Readable for ApiKeySwaps {
+	fn read<R: Reader>(reader: &mut R) -> Result<ApiKeySwaps, ser::Error> {
+		let version = reader.read_u8()?;
+		if version > CURRENT_VERSION {
+			return Err(ser::Error::UnsupportedProtocolVersion);
+		}
+
+		Ok(ApiKeySwaps {
+			name: read_string(reader)?,
+			accepted: reader.read_u64()?,
+			rejected: reader.read_u64()?,
 		})
 	}
 }
 
+/// Saved while a round executes, and removed once it's done. One still saved when no round is
+/// executing was left by a round the server crashed or was killed during.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoundMarker {
+	/// Id of the executing round's acceptance window
+	pub round_id: u64,
+	/// Unix time (in seconds) the round started
+	pub started_at: u64,
+}
+
+impl Writeable for RoundMarker {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u8(CURRENT_VERSION)?;
+		writer.write_u64(self.round_id)?;
+		writer.write_u64(self.started_at)?;
+
+		Ok(())
+	}
+}
+
+impl Readable for RoundMarker {
+	fn read<R: Reader>(reader: &mut R) -> Result<RoundMarker, ser::Error> {
+		let version = reader.read_u8()?;
+		if version > CURRENT_VERSION {
+			return Err(ser::Error::UnsupportedProtocolVersion);
+		}
+
+		Ok(RoundMarker {
+			round_id: reader.read_u64()?,
+			started_at: reader.read_u64()?,
+		})
+	}
+}
+
+fn read_string<R: Reader>(reader: &mut R) -> Result<String, ser::Error> {
+	String::from_utf8(reader.read_bytes_len_prefix()?).map_err(|_| ser::Error::CorruptedData)
+}
+
 /// Storage facility for swap data.
 pub struct SwapStore {
-	db: Store,
+	db: Box<dyn SwapStorage>,
+	/// whether completed and failed swaps are compressed when written
+	compress_archived: bool,
 }
 
 /// Store error types
@@ -143,14 +788,22 @@ pub struct SwapStore {
 pub enum StoreError {
 	#[error("Swap entry already exists for '{0:?}'")]
 	AlreadyExists(Commitment),
+	#[error("A swap from a different input already creates output '{0:?}'")]
+	DuplicateOutput(Commitment),
 	#[error("Error occurred while attempting to open db: {0}")]
-	OpenError(store::lmdb::Error),
+	OpenError(StorageError),
 	#[error("Serialization error occurred: {0}")]
 	SerializationError(ser::Error),
 	#[error("Error occurred while attempting to read from db: {0}")]
-	ReadError(store::lmdb::Error),
+	ReadError(StorageError),
 	#[error("Error occurred while attempting to write to db: {0}")]
-	WriteError(store::lmdb::Error),
+	WriteError(StorageError),
+	#[error("Error occurred while attempting to move db: {0}")]
+	MoveError(std::io::Error),
+	#[error("No record found for '{0}'")]
+	NotFound(String),
+	#[error("Error compressing or decompressing record: {0}")]
+	CompressionError(String),
 }
 
 impl From<ser::Error> for StoreError {
@@ -162,9 +815,70 @@ impl From<ser::Error> for StoreError {
 impl SwapStore {
 	/// Create new chain store
 	pub fn new(db_root: &str) -> Result<SwapStore, StoreError> {
-		let db = Store::new(db_root, Some(DB_NAME), Some(STORE_SUBPATH), None)
-			.map_err(StoreError::OpenError)?;
-		Ok(SwapStore { db })
+		let db =
+			LmdbStorage::open(db_root, DB_NAME, STORE_SUBPATH).map_err(StoreError::OpenError)?;
+		Ok(SwapStore::with_storage(Box::new(db)))
+	}
+
+	/// Opens the store in 'db_root', kept in the given backend
+	pub fn open(db_root: &Path, backend: StoreBackend) -> Result<SwapStore, StoreError> {
+		match backend {
+			StoreBackend::Lmdb => {
+				let db_root = db_root.to_str().ok_or_else(|| {
+					StoreError::OpenError(StorageError::InvalidPath(
+						db_root.to_string_lossy().to_string(),
+					))
+				})?;
+				SwapStore::new(db_root)
+			}
+			StoreBackend::Sled => {
+				let db = SledStorage::open(&db_root.join(db_dir_name(backend)))
+					.map_err(StoreError::OpenError)?;
+				Ok(SwapStore::with_storage(Box::new(db)))
+			}
+		}
+	}
+
+	/// Whether a store has been created in 'db_root', in the given backend
+	pub fn exists(db_root: &Path, backend: StoreBackend) -> bool {
+		db_root.join(db_dir_name(backend)).is_dir()
+	}
+
+	/// A store keeping its records in 'db'
+	pub fn with_storage(db: Box<dyn SwapStorage>) -> SwapStore {
+		SwapStore {
+			db,
+			compress_archived: false,
+		}
+	}
+
+	/// Compresses swaps that are only kept as history (completed or failed) when they're written.
+	/// Compressed records are read back either way, so this can be turned off again at any time.
+	pub fn with_compression(mut self, compress_archived: bool) -> SwapStore {
+		self.compress_archived = compress_archived;
+		self
+	}
+
+	/// Moves the backend's database from 'legacy_root' to 'db_root', unless there's already one there.
+	/// Returns whether a database was moved.
+	pub fn migrate(
+		legacy_root: &Path,
+		db_root: &Path,
+		backend: StoreBackend,
+	) -> Result<bool, StoreError> {
+		let from = legacy_root.join(db_dir_name(backend));
+		let to = db_root.join(db_dir_name(backend));
+		if !from.is_dir() || to.exists() {
+			return Ok(false);
+		}
+
+		std::fs::create_dir_all(db_root).map_err(StoreError::MoveError)?;
+		// Renaming fails across filesystems, in which case the files are copied instead
+		if std::fs::rename(&from, &to).is_err() {
+			copy_dir(&from, &to).map_err(StoreError::MoveError)?;
+			std::fs::remove_dir_all(&from).map_err(StoreError::MoveError)?;
+		}
+		Ok(true)
 	}
 
 	/// Writes a single key-value pair to the database
@@ -174,88 +888,671 @@ impl SwapStore {
 		k: K,
 		value: &Vec<u8>,
 		overwrite: bool,
-	) -> Result<bool, store::lmdb::Error> {
-		let batch = self.db.batch()?;
+	) -> Result<bool, StorageError> {
 		let key = store::to_key(prefix, k);
-		if !overwrite && batch.exists(&key[..])? {
+		if !overwrite && self.db.exists(&key[..])? {
 			Ok(false)
 		} else {
-			batch.put(&key[..], &value[..])?;
-			batch.commit()?;
+			self.db
+				.write_batch(vec![WriteOp::Put(key, value.clone())])?;
 			Ok(true)
 		}
 	}
 
-	/// Reads a single value by key
-	fn read<K: AsRef<[u8]> + Copy, V: Readable>(&self, prefix: u8, k: K) -> Result<V, StoreError> {
-		store::option_to_not_found(self.db.get_ser(&store::to_key(prefix, k)[..], None), || {
-			format!("{}:{}", prefix, k.to_hex())
-		})
-		.map_err(StoreError::ReadError)
+	/// Serializes the value into an operation writing it under the prefixed key
+	fn put<K: AsRef<[u8]>, V: Writeable>(
+		prefix: u8,
+		k: K,
+		value: &V,
+	) -> Result<WriteOp, StoreError> {
+		let data = ser::ser_vec(value, ProtocolVersion::local())?;
+		Ok(WriteOp::Put(store::to_key(prefix, k), data))
 	}
 
-	/// Saves a swap to the database
-	pub fn save_swap(&self, s: &SwapData, overwrite: bool) -> Result<(), StoreError> {
-		let data = ser::ser_vec(&s, ProtocolVersion::local())?;
-		let saved = self
-			.write(SWAP_PREFIX, &s.input.commit, &data, overwrite)
-			.map_err(StoreError::WriteError)?;
-		if !saved {
-			Err(StoreError::AlreadyExists(s.input.commit.clone()))
+	/// Serializes the swap, compressing it if it's archived and compression is enabled
+	fn encode_swap(&self, swap: &SwapData) -> Result<Vec<u8>, StoreError> {
+		let data = ser::ser_vec(swap, ProtocolVersion::local())?;
+		let archived = match swap.status {
+			SwapStatus::Completed { .. } | SwapStatus::Failed => true,
+			SwapStatus::Unprocessed | SwapStatus::InProcess { .. } => false,
+		};
+		if self.compress_archived && archived {
+			compress::compress(&data).map_err(|e| StoreError::CompressionError(e.to_string()))
 		} else {
-			Ok(())
+			Ok(data)
 		}
 	}
 
-	/// Iterator over all swaps.
-	pub fn swaps_iter(&self) -> Result<impl Iterator<Item = SwapData>, StoreError> {
-		let key = store::to_key(SWAP_PREFIX, "");
-		let protocol_version = self.db.protocol_version();
-		self.db
-			.iter(&key[..], move |_, mut v| {
-				ser::deserialize(&mut v, protocol_version, DeserializationMode::default())
-					.map_err(From::from)
-			})
-			.map_err(|e| StoreError::ReadError(e))
+	/// Operations writing the swap, and indexing it by its output commitment
+	fn put_swap(&self, swap: &SwapData) -> Result<Vec<WriteOp>, StoreError> {
+		Ok(vec![
+			WriteOp::Put(
+				store::to_key(SWAP_PREFIX, &swap.input.commit),
+				self.encode_swap(swap)?,
+			),
+			SwapStore::put(OUTPUT_INDEX_PREFIX, &swap.output_commit, &swap.input.commit)?,
+		])
 	}
 
-	/// Checks if a matching swap exists in the database
-	#[allow(dead_code)]
-	pub fn swap_exists(&self, input_commit: &Commitment) -> Result<bool, StoreError> {
-		let key = store::to_key(SWAP_PREFIX, input_commit);
-		self.db
-			.batch()
-			.map_err(StoreError::ReadError)?
-			.exists(&key[..])
-			.map_err(StoreError::ReadError)
+	/// Reads and deserializes the value stored under the key, if there is one
+	fn get_ser<V: Readable>(&self, key: &[u8]) -> Result<Option<V>, StoreError> {
+		match self.db.get(key).map_err(StoreError::ReadError)? {
+			Some(value) => Ok(Some(decode(&value, self.db.protocol_version())?)),
+			None => Ok(None),
+		}
 	}
 
-	/// Reads a swap from the database
-	#[allow(dead_code)]
-	pub fn get_swap(&self, input_commit: &Commitment) -> Result<SwapData, StoreError> {
-		self.read(SWAP_PREFIX, input_commit)
+	/// Reads a single value by key
+	fn read<K: AsRef<[u8]> + Copy, V: Readable>(&self, prefix: u8, k: K) -> Result<V, StoreError> {
+		self.get_ser(&store::to_key(prefix, k)[..])?
+			.ok_or_else(|| StoreError::NotFound(format!("{}:{}", prefix, k.to_hex())))
 	}
+
+	/// Iterator over the values of every record under the prefix.
+	/// Records that can't be deserialized are skipped.
+	fn iter<V: Readable>(&self, prefix: u8) -> Result<impl Iterator<Item = V>, StoreError> {
+		let key = store::to_key(prefix, "");
+		let protocol_version = self.db.protocol_version();
+		let records = self
+			.db
+			.iter_prefix(&key[..])
+			.map_err(StoreError::ReadError)?;
+		Ok(records
+			.into_iter()
+			.filter_map(move |(_, v)| decode(&v, protocol_version).ok()))
+	}
+
+	/// Saves a swap to the database, keyed by its input commitment.
+	/// Fails if a swap from a different input already creates the same output.
+	pub fn save_swap(&self, s: &SwapData, overwrite: bool) -> Result<(), StoreError> {
+		if !overwrite && self.swap_exists(&s.input.commit)? {
+			return Err(StoreError::AlreadyExists(s.input.commit.clone()));
+		}
+		if let Some(existing) = self.get_swap_by_output(&s.output_commit)? {
+			if existing.input.commit != s.input.commit {
+				return Err(StoreError::DuplicateOutput(s.output_commit.clone()));
+			}
+		}
+		self.db
+			.write_batch(self.put_swap(s)?)
+			.map_err(StoreError::WriteError)
+	}
+
+	/// Saves the swaps in a single write transaction, replacing any existing records.
+	/// Either all of them are saved, or none are.
+	pub fn update_swaps_batch(&self, swaps: &Vec<SwapData>) -> Result<(), StoreError> {
+		let mut ops = Vec::with_capacity(swaps.len() * 2);
+		for swap in swaps {
+			ops.extend(self.put_swap(swap)?);
+		}
+		self.db.write_batch(ops).map_err(StoreError::WriteError)
+	}
+
+	/// Records an executed round in a single write transaction: the round itself, its kernel's
+	/// excess, its swaps (with their updated statuses, and indexed by the kernel's hash) and their
+	/// receipts, the output that collected its fees, and its decoys. A failure part way through can't leave swaps in process
+	/// without a round to look them up by. The round's queued transaction is moved out of the queue
+	/// and kept with the round.
+	pub fn save_round_batch(
+		&self,
+		round: &RoundData,
+		kernel_excess: &Commitment,
+		swaps: &Vec<SwapData>,
+		receipts: &Vec<ReceiptData>,
+		fee_output: Option<&FeeOutputData>,
+		decoys: &RoundDecoys,
+	) -> Result<(), StoreError> {
+		let mut ops = Vec::new();
+		if let Some(fee_output) = fee_output {
+			let key = store::to_key(FEE_OUTPUT_PREFIX, &fee_output.output_commit);
+			if self.db.exists(&key[..]).map_err(StoreError::WriteError)? {
+				return Err(StoreError::AlreadyExists(fee_output.output_commit.clone()));
+			}
+			ops.push(SwapStore::put(
+				FEE_OUTPUT_PREFIX,
+				&fee_output.output_commit,
+				fee_output,
+			)?);
+		}
+		ops.push(SwapStore::put(ROUND_PREFIX, &round.kernel_hash, round)?);
+		ops.push(SwapStore::put(
+			KERNEL_EXCESS_PREFIX,
+			&round.kernel_hash,
+			kernel_excess,
+		)?);
+		for swap in swaps {
+			ops.extend(self.put_swap(swap)?);
+		}
+		let index = KernelIndexData {
+			input_commits: swaps
+				.iter()
+				.filter(|s| s.status.kernel_hash() == Some(round.kernel_hash))
+				.map(|s| s.input.commit)
+				.collect(),
+		};
+		ops.push(SwapStore::put(
+			KERNEL_INDEX_PREFIX,
+			&round.kernel_hash,
+			&index,
+		)?);
+		for receipt in receipts {
+			ops.push(SwapStore::put(
+				RECEIPT_PREFIX,
+				&receipt.input_commit,
+				receipt,
+			)?);
+		}
+		for commit in &decoys.spent {
+			ops.push(WriteOp::Delete(store::to_key(DECOY_PREFIX, commit)));
+		}
+		for decoy in &decoys.created {
+			ops.push(SwapStore::put(DECOY_PREFIX, &decoy.commit, decoy)?);
+		}
+		// The queued transaction is kept with the round, to be posted again if a reorg undoes it
+		let pending_key = store::to_key(PENDING_TX_PREFIX, &round.kernel_hash);
+		if let Some(pending) = self.get_ser::<PendingTxData>(&pending_key[..])? {
+			ops.push(SwapStore::put(
+				ROUND_TX_PREFIX,
+				&round.kernel_hash,
+				&pending.tx,
+			)?);
+		}
+		ops.push(WriteOp::Delete(pending_key));
+		self.db.write_batch(ops).map_err(StoreError::WriteError)
+	}
+
+	/// Iterator over all swaps.
+	pub fn swaps_iter(&self) -> Result<impl Iterator<Item = SwapData>, StoreError> {
+		self.iter(SWAP_PREFIX)
+	}
+
+	/// Checks if a matching swap exists in the database
+	#[allow(dead_code)]
+	pub fn swap_exists(&self, input_commit: &Commitment) -> Result<bool, StoreError> {
+		let key = store::to_key(SWAP_PREFIX, input_commit);
+		self.db.exists(&key[..]).map_err(StoreError::ReadError)
+	}
+
+	/// Reads a swap from the database
+	#[allow(dead_code)]
+	pub fn get_swap(&self, input_commit: &Commitment) -> Result<SwapData, StoreError> {
+		self.read(SWAP_PREFIX, input_commit)
+	}
+
+	/// Reads the swap that creates the output commitment, if there is one
+	pub fn get_swap_by_output(
+		&self,
+		output_commit: &Commitment,
+	) -> Result<Option<SwapData>, StoreError> {
+		let input_commit: Option<Commitment> =
+			self.get_ser(&store::to_key(OUTPUT_INDEX_PREFIX, output_commit)[..])?;
+		match input_commit {
+			// The index entry is only trusted if the swap it points to still creates the output
+			Some(input_commit) => Ok(self
+				.get_ser::<SwapData>(&store::to_key(SWAP_PREFIX, &input_commit)[..])?
+				.filter(|s| s.output_commit == *output_commit)),
+			None => Ok(None),
+		}
+	}
+
+	/// Reads the swaps included in the round with the kernel hash, without scanning every swap.
+	/// Swaps since deleted, or no longer in the round, are left out.
+	pub fn get_swaps_by_kernel(&self, kernel_hash: &Hash) -> Result<Vec<SwapData>, StoreError> {
+		let index: Option<KernelIndexData> =
+			self.get_ser(&store::to_key(KERNEL_INDEX_PREFIX, kernel_hash)[..])?;
+		let mut swaps = Vec::new();
+		for input_commit in index.unwrap_or_default().input_commits {
+			// As with outputs, an entry is only trusted if its swap is still in the round
+			let swap = self
+				.get_ser::<SwapData>(&store::to_key(SWAP_PREFIX, &input_commit)[..])?
+				.filter(|s| s.status.kernel_hash() == Some(*kernel_hash));
+			swaps.extend(swap);
+		}
+		Ok(swaps)
+	}
+
+	/// Adds the kernel index entries missing for rounds recorded before kernels were indexed.
+	/// Returns the number of entries added.
+	pub fn index_kernels(&self) -> Result<usize, StoreError> {
+		let mut unindexed: HashMap<Hash, KernelIndexData> = HashMap::new();
+		for round in self.rounds_iter()? {
+			let key = store::to_key(KERNEL_INDEX_PREFIX, &round.kernel_hash);
+			if !self.db.exists(&key[..]).map_err(StoreError::ReadError)? {
+				unindexed.insert(round.kernel_hash, KernelIndexData::default());
+			}
+		}
+		if unindexed.is_empty() {
+			return Ok(0);
+		}
+
+		for swap in self.swaps_iter()? {
+			if let Some(index) = swap
+				.status
+				.kernel_hash()
+				.and_then(|h| unindexed.get_mut(&h))
+			{
+				index.input_commits.push(swap.input.commit);
+			}
+		}
+		let ops = unindexed
+			.iter()
+			.map(|(kernel_hash, index)| SwapStore::put(KERNEL_INDEX_PREFIX, kernel_hash, index))
+			.collect::<Result<Vec<_>, _>>()?;
+		let added = ops.len();
+		self.db.write_batch(ops).map_err(StoreError::WriteError)?;
+		Ok(added)
+	}
+
+	/// Adds the output index entries missing for swaps saved before outputs were indexed.
+	/// When swaps from different inputs create the same output, the index keeps the first one found.
+	/// Returns the number of entries added.
+	pub fn index_outputs(&self) -> Result<usize, StoreError> {
+		let mut ops = Vec::new();
+		let mut indexed = HashSet::new();
+		for swap in self.swaps_iter()? {
+			if !indexed.insert(swap.output_commit)
+				|| self.get_swap_by_output(&swap.output_commit)?.is_some()
+			{
+				continue;
+			}
+			ops.push(SwapStore::put(
+				OUTPUT_INDEX_PREFIX,
+				&swap.output_commit,
+				&swap.input.commit,
+			)?);
+		}
+		let added = ops.len();
+		if added > 0 {
+			self.db.write_batch(ops).map_err(StoreError::WriteError)?;
+		}
+		Ok(added)
+	}
+
+	/// Checks that the database accepts writes, by committing a write that leaves nothing behind
+	pub fn check_writable(&self) -> Result<(), StoreError> {
+		let key = store::to_key(META_PREFIX, WRITE_CHECK_KEY);
+		self.db
+			.write_batch(vec![
+				WriteOp::Put(key.clone(), vec![1u8]),
+				WriteOp::Delete(key),
+			])
+			.map_err(StoreError::WriteError)
+	}
+
+	/// Deletes the swap for the input commitment, if there is one, along with its output's index entry
+	pub fn delete_swap(&self, input_commit: &Commitment) -> Result<(), StoreError> {
+		let key = store::to_key(SWAP_PREFIX, input_commit);
+		let mut ops = vec![WriteOp::Delete(key.clone())];
+		// A record that can't be read is still deleted, leaving its index entry to be ignored
+		if let Ok(Some(swap)) = self.get_ser::<SwapData>(&key[..]) {
+			let indexed = self.get_swap_by_output(&swap.output_commit)?;
+			if indexed.map(|s| s.input.commit == *input_commit) == Some(true) {
+				ops.push(WriteOp::Delete(store::to_key(
+					OUTPUT_INDEX_PREFIX,
+					&swap.output_commit,
+				)));
+			}
+		}
+		self.db.write_batch(ops).map_err(StoreError::WriteError)
+	}
+
+	/// Saves the receipt for a swap's inclusion in a round, replacing any earlier receipt
+	#[allow(dead_code)]
+	pub fn save_receipt(&self, r: &ReceiptData) -> Result<(), StoreError> {
+		let data = ser::ser_vec(&r, ProtocolVersion::local())?;
+		self.write(RECEIPT_PREFIX, &r.input_commit, &data, true)
+			.map_err(StoreError::WriteError)?;
+		Ok(())
+	}
+
+	/// Reads the receipt for the swap of the input commitment, if one was issued
+	pub fn get_receipt(
+		&self,
+		input_commit: &Commitment,
+	) -> Result<Option<ReceiptData>, StoreError> {
+		self.get_ser(&store::to_key(RECEIPT_PREFIX, input_commit)[..])
+	}
+
+	/// Records the wallet output that collected a round's fees
+	#[allow(dead_code)]
+	pub fn save_fee_output(&self, f: &FeeOutputData) -> Result<(), StoreError> {
+		let data = ser::ser_vec(&f, ProtocolVersion::local())?;
+		let saved = self
+			.write(FEE_OUTPUT_PREFIX, &f.output_commit, &data, false)
+			.map_err(StoreError::WriteError)?;
+		if !saved {
+			Err(StoreError::AlreadyExists(f.output_commit.clone()))
+		} else {
+			Ok(())
+		}
+	}
+
+	/// The id of the most recent round an acceptance window was opened for, or 0 if there's been none
+	pub fn last_round_id(&self) -> Result<u64, StoreError> {
+		let key = store::to_key(META_PREFIX, LAST_ROUND_ID_KEY);
+		let round_id: Option<u64> = self.get_ser(&key[..])?;
+		Ok(round_id.unwrap_or(0))
+	}
+
+	/// Records the id of the round an acceptance window was just opened for
+	pub fn save_last_round_id(&self, round_id: u64) -> Result<(), StoreError> {
+		let data = ser::ser_vec(&round_id, ProtocolVersion::local())?;
+		self.write(META_PREFIX, LAST_ROUND_ID_KEY, &data, true)
+			.map_err(StoreError::WriteError)?;
+		Ok(())
+	}
+
+	/// The feature flags last saved by an operator, or the defaults if they've never been changed
+	pub fn feature_flags(&self) -> Result<FeatureFlags, StoreError> {
+		let key = store::to_key(META_PREFIX, FEATURE_FLAGS_KEY);
+		let flags: Option<FeatureFlags> = self.get_ser(&key[..])?;
+		Ok(flags.unwrap_or_default())
+	}
+
+	/// Saves the feature flags, so they persist across restarts
+	pub fn save_feature_flags(&self, flags: &FeatureFlags) -> Result<(), StoreError> {
+		let data = ser::ser_vec(&flags, ProtocolVersion::local())?;
+		self.write(META_PREFIX, FEATURE_FLAGS_KEY, &data, true)
+			.map_err(StoreError::WriteError)?;
+		Ok(())
+	}
+
+	/// The marker of the executing round, or of one interrupted by a crash, if any
+	pub fn round_marker(&self) -> Result<Option<RoundMarker>, StoreError> {
+		let key = store::to_key(META_PREFIX, ROUND_MARKER_KEY);
+		self.get_ser(&key[..])
+	}
+
+	/// Marks a round as executing
+	pub fn save_round_marker(&self, marker: &RoundMarker) -> Result<(), StoreError> {
+		let data = ser::ser_vec(&marker, ProtocolVersion::local())?;
+		self.write(META_PREFIX, ROUND_MARKER_KEY, &data, true)
+			.map_err(StoreError::WriteError)?;
+		Ok(())
+	}
+
+	/// Removes the round marker, once its round is done
+	pub fn delete_round_marker(&self) -> Result<(), StoreError> {
+		let key = store::to_key(META_PREFIX, ROUND_MARKER_KEY);
+		self.db
+			.write_batch(vec![WriteOp::Delete(key)])
+			.map_err(StoreError::WriteError)
+	}
+
+	/// Records the excess of a round's kernel, so the kernel can be looked up on chain by its hash
+	#[allow(dead_code)]
+	pub fn save_kernel_excess(
+		&self,
+		kernel_hash: &Hash,
+		excess: &Commitment,
+	) -> Result<(), StoreError> {
+		let data = ser::ser_vec(&excess, ProtocolVersion::local())?;
+		self.write(KERNEL_EXCESS_PREFIX, kernel_hash, &data, true)
+			.map_err(StoreError::WriteError)?;
+		Ok(())
+	}
+
+	/// Reads the excess of a round's kernel, if one was recorded
+	pub fn get_kernel_excess(&self, kernel_hash: &Hash) -> Result<Option<Commitment>, StoreError> {
+		self.get_ser(&store::to_key(KERNEL_EXCESS_PREFIX, kernel_hash)[..])
+	}
+
+	/// Records a round that was executed, keyed by its kernel hash
+	pub fn save_round(&self, r: &RoundData) -> Result<(), StoreError> {
+		let data = ser::ser_vec(&r, ProtocolVersion::local())?;
+		self.write(ROUND_PREFIX, &r.kernel_hash, &data, true)
+			.map_err(StoreError::WriteError)?;
+		Ok(())
+	}
+
+	/// Reads the round with the given kernel hash, if one was recorded
+	pub fn get_round(&self, kernel_hash: &Hash) -> Result<Option<RoundData>, StoreError> {
+		self.get_ser(&store::to_key(ROUND_PREFIX, kernel_hash)[..])
+	}
+
+	/// Iterator over all recorded rounds, in no particular order.
+	pub fn rounds_iter(&self) -> Result<impl Iterator<Item = RoundData>, StoreError> {
+		self.iter(ROUND_PREFIX)
+	}
+
+	/// Iterator over all recorded fee outputs.
+	pub fn fee_outputs_iter(&self) -> Result<impl Iterator<Item = FeeOutputData>, StoreError> {
+		self.iter(FEE_OUTPUT_PREFIX)
+	}
+
+	/// Queues a round transaction to be posted, replacing any earlier entry for the same kernel
+	pub fn save_pending_tx(&self, p: &PendingTxData) -> Result<(), StoreError> {
+		let data = ser::ser_vec(&p, ProtocolVersion::local())?;
+		self.write(PENDING_TX_PREFIX, &p.kernel_hash, &data, true)
+			.map_err(StoreError::WriteError)?;
+		Ok(())
+	}
+
+	/// Removes a round transaction from the queue, if it's there
+	pub fn delete_pending_tx(&self, kernel_hash: &Hash) -> Result<(), StoreError> {
+		let key = store::to_key(PENDING_TX_PREFIX, kernel_hash);
+		self.db
+			.write_batch(vec![WriteOp::Delete(key)])
+			.map_err(StoreError::WriteError)
+	}
+
+	/// Iterator over the round transactions still queued to be posted
+	pub fn pending_txs_iter(&self) -> Result<impl Iterator<Item = PendingTxData>, StoreError> {
+		self.iter(PENDING_TX_PREFIX)
+	}
+
+	/// Reads the transaction of the round with the given kernel hash, while it's still kept
+	pub fn get_round_tx(&self, kernel_hash: &Hash) -> Result<Option<Transaction>, StoreError> {
+		self.get_ser(&store::to_key(ROUND_TX_PREFIX, kernel_hash)[..])
+	}
+
+	/// Removes the transaction kept for the round, returning whether there was one
+	pub fn delete_round_tx(&self, kernel_hash: &Hash) -> Result<bool, StoreError> {
+		let key = store::to_key(ROUND_TX_PREFIX, kernel_hash);
+		if !self.db.exists(&key[..]).map_err(StoreError::ReadError)? {
+			return Ok(false);
+		}
+		self.db
+			.write_batch(vec![WriteOp::Delete(key)])
+			.map_err(StoreError::WriteError)?;
+		Ok(true)
+	}
+
+	/// Iterator over the decoy outputs available to pad rounds
+	pub fn decoys_iter(&self) -> Result<impl Iterator<Item = DecoyData>, StoreError> {
+		self.iter(DECOY_PREFIX)
+	}
+
+	/// Records a rejected swap under the next sequence number. Only the last 'capacity' rejections
+	/// are kept, each new one overwriting the oldest.
+	pub fn save_rejection(&self, r: RejectionData, capacity: u64) -> Result<(), StoreError> {
+		if capacity == 0 {
+			return Ok(());
+		}
+		let next_key = store::to_key(META_PREFIX, NEXT_REJECTION_KEY);
+		let id: u64 = self.get_ser(&next_key[..])?.unwrap_or(0);
+		let rejection = RejectionData { id, ..r };
+		let ops = vec![
+			SwapStore::put(REJECTION_PREFIX, (id % capacity).to_be_bytes(), &rejection)?,
+			SwapStore::put(META_PREFIX, NEXT_REJECTION_KEY, &(id + 1))?,
+		];
+		self.db.write_batch(ops).map_err(StoreError::WriteError)
+	}
+
+	/// The most recent rejections, newest first
+	pub fn rejections(&self, limit: usize) -> Result<Vec<RejectionData>, StoreError> {
+		let mut rejections: Vec<RejectionData> = self.iter(REJECTION_PREFIX)?.collect();
+		rejections.sort_by(|a, b| b.id.cmp(&a.id));
+		rejections.truncate(limit);
+		Ok(rejections)
+	}
+
+	/// Counts a swap submitted with the named API key, accepted or not
+	pub fn count_api_key_swap(&self, name: &str, accepted: bool) -> Result<(), StoreError> {
+		let key = store::to_key(API_KEY_SWAPS_PREFIX, name);
+		let mut swaps = self
+			.get_ser::<ApiKeySwaps>(&key[..])?
+			.unwrap_or_else(|| ApiKeySwaps {
+				name: name.to_string(),
+				..ApiKeySwaps::default()
+			});
+		if accepted {
+			swaps.accepted += 1;
+		} else {
+			swaps.rejected += 1;
+		}
+		let data = ser::ser_vec(&swaps, ProtocolVersion::local())?;
+		self.write(API_KEY_SWAPS_PREFIX, name, &data, true)
+			.map_err(StoreError::WriteError)?;
+		Ok(())
+	}
+
+	/// The swaps counted for each API key that has been used
+	pub fn api_key_swaps(&self) -> Result<Vec<ApiKeySwaps>, StoreError> {
+		Ok(self.iter(API_KEY_SWAPS_PREFIX)?.collect())
+	}
+
+	/// Every record in the database, as raw key-value pairs
+	pub fn export_records(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>, StoreError> {
+		let mut records = Vec::new();
+		for prefix in RECORD_PREFIXES {
+			let key = store::to_key(prefix, "");
+			records.extend(
+				self.db
+					.iter_prefix(&key[..])
+					.map_err(StoreError::ReadError)?,
+			);
+		}
+		Ok(records)
+	}
+
+	/// Whether the database holds no records at all
+	pub fn is_empty(&self) -> Result<bool, StoreError> {
+		Ok(self.export_records()?.is_empty())
+	}
+
+	/// Writes raw key-value pairs, as exported by 'export_records', in a single write transaction
+	pub fn import_records(&self, records: &Vec<(Vec<u8>, Vec<u8>)>) -> Result<(), StoreError> {
+		let ops = records
+			.iter()
+			.map(|(key, value)| WriteOp::Put(key.clone(), value.clone()))
+			.collect();
+		self.db.write_batch(ops).map_err(StoreError::WriteError)
+	}
+}
+
+/// Name of the directory under 'db_root' the backend keeps its database in
+/// Deserializes a stored value, decompressing it first if it was compressed
+fn decode<V: Readable>(value: &[u8], protocol_version: ProtocolVersion) -> Result<V, StoreError> {
+	let decompressed;
+	let value = if compress::is_compressed(value) {
+		decompressed = compress::decompress(value, MAX_RECORD_SIZE)
+			.map_err(|e| StoreError::CompressionError(e.to_string()))?;
+		&decompressed[..]
+	} else {
+		value
+	};
+	let value = ser::deserialize(
+		&mut &value[..],
+		protocol_version,
+		DeserializationMode::default(),
+	)?;
+	Ok(value)
+}
+
+fn db_dir_name(backend: StoreBackend) -> &'static str {
+	match backend {
+		StoreBackend::Lmdb => DB_NAME,
+		StoreBackend::Sled => SLED_DB_NAME,
+	}
+}
+
+/// Copies the files in 'from' (including those in subdirectories) to 'to'
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+	std::fs::create_dir_all(to)?;
+	for entry in std::fs::read_dir(from)? {
+		let entry = entry?;
+		if entry.file_type()?.is_dir() {
+			copy_dir(&entry.path(), &to.join(entry.file_name()))?;
+		} else {
+			std::fs::copy(entry.path(), to.join(entry.file_name()))?;
+		}
+	}
+	Ok(())
 }
 
 #[cfg(test)]
-mod tests {
+pub mod test_util {
+	use super::{SwapData, SwapStatus, SwapStore};
+	use crate::config::StoreBackend;
 	use crate::onion::test_util::rand_onion;
-	use crate::secp::test_util::{rand_commit, rand_hash, rand_proof};
-	use crate::store::{SwapData, SwapStatus, SwapStore};
-	use crate::{secp, StoreError};
+	use crate::secp;
+	use crate::secp::test_util::{rand_commit, rand_proof};
+
 	use grin_core::core::{Input, OutputFeatures};
 	use grin_core::global::{self, ChainTypes};
 	use rand::RngCore;
-	use std::cmp::Ordering;
+	use std::ops::Deref;
+	use std::path::{Path, PathBuf};
+
+	/// A 'SwapStore' in its own temporary directory under './target/tmp',
+	/// which is deleted when the 'TestStore' is dropped.
+	/// Each directory gets a random suffix, so tests with the same name never share a store.
+	pub struct TestStore {
+		store: Option<SwapStore>,
+		db_root: PathBuf,
+	}
+
+	impl TestStore {
+		pub fn new(test_name: &str) -> TestStore {
+			TestStore::with_backend(test_name, StoreBackend::Lmdb)
+		}
+
+		/// A fixture whose store is kept in the given backend
+		pub fn with_backend(test_name: &str, backend: StoreBackend) -> TestStore {
+			global::set_local_chain_type(ChainTypes::AutomatedTesting);
+			let db_root = PathBuf::from(format!(
+				"./target/tmp/.{}.{:08x}",
+				test_name,
+				rand::thread_rng().next_u32()
+			));
+			let store = SwapStore::open(&db_root, backend).unwrap();
+			TestStore {
+				store: Some(store),
+				db_root,
+			}
+		}
+
+		/// The directory the store lives in
+		pub fn db_root(&self) -> &Path {
+			&self.db_root
+		}
+
+		/// Hands the store over to whatever is under test. The directory is still deleted when
+		/// the fixture is dropped, so the fixture must outlive the store's new owner.
+		pub fn take(&mut self) -> SwapStore {
+			self.store.take().expect("store was already taken")
+		}
+	}
+
+	impl Deref for TestStore {
+		type Target = SwapStore;
+
+		fn deref(&self) -> &SwapStore {
+			self.store.as_ref().unwrap()
+		}
+	}
 
-	fn new_store(test_name: &str) -> SwapStore {
-		global::set_local_chain_type(ChainTypes::AutomatedTesting);
-		let db_root = format!("./target/tmp/.{}", test_name);
-		let _ = std::fs::remove_dir_all(db_root.as_str());
-		SwapStore::new(db_root.as_str()).unwrap()
+	impl Drop for TestStore {
+		fn drop(&mut self) {
+			// Close the database before deleting its files
+			self.store = None;
+			let _ = std::fs::remove_dir_all(&self.db_root);
+		}
 	}
 
-	fn rand_swap_with_status(status: SwapStatus) -> SwapData {
+	/// A swap with random contents and the given status
+	pub fn rand_swap_with_status(status: SwapStatus) -> SwapData {
 		SwapData {
 			excess: secp::random_secret(),
 			output_commit: rand_commit(),
@@ -264,8 +1561,33 @@ mod tests {
 			fee: rand::thread_rng().next_u64(),
 			onion: rand_onion(),
 			status,
+			expiry_height: rand::thread_rng().next_u64(),
+			unconfirmed_input: rand::thread_rng().next_u32() % 2 == 0,
+			owner_kernel: None,
+			accepted_at: rand::thread_rng().next_u64(),
+			accepted_round: rand::thread_rng().next_u64(),
+			fee_change: None,
 		}
 	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::compress;
+	use crate::config::StoreBackend;
+	use crate::features::{Feature, FeatureFlags};
+	use crate::secp;
+	use crate::secp::test_util::{rand_commit, rand_hash};
+	use crate::secp::{Message, PublicKey, Secp256k1};
+	use crate::store::test_util::{rand_swap_with_status, TestStore};
+	use crate::store::{
+		ApiKeySwaps, DecoyData, FeeOutputData, ReceiptData, RejectionData, RoundData, RoundDecoys,
+		RoundMarker, StoreError, SwapData, SwapStatus, SwapStore, CURRENT_VERSION, DB_NAME,
+		ROUND_PREFIX, SWAP_PREFIX,
+	};
+	use grin_core::ser::{self, ProtocolVersion};
+	use rand::RngCore;
+	use std::cmp::Ordering;
 
 	fn rand_swap() -> SwapData {
 		let s = rand::thread_rng().next_u64() % 3;
@@ -286,7 +1608,7 @@ mod tests {
 
 	#[test]
 	fn swap_iter() -> Result<(), Box<dyn std::error::Error>> {
-		let store = new_store("swap_iter");
+		let store = TestStore::new("swap_iter");
 		let mut swaps: Vec<SwapData> = Vec::new();
 		for _ in 0..5 {
 			let swap = rand_swap();
@@ -315,7 +1637,7 @@ mod tests {
 
 	#[test]
 	fn save_swap() -> Result<(), Box<dyn std::error::Error>> {
-		let store = new_store("save_swap");
+		let store = TestStore::new("save_swap");
 
 		let mut swap = rand_swap_with_status(SwapStatus::Unprocessed);
 		assert!(!store.swap_exists(&swap.input.commit)?);
@@ -336,6 +1658,717 @@ mod tests {
 		store.save_swap(&swap, true)?;
 		assert_eq!(swap, store.get_swap(&swap.input.commit)?);
 
+		store.delete_swap(&swap.input.commit)?;
+		assert!(!store.swap_exists(&swap.input.commit)?);
+		store.save_swap(&swap, false)?;
+
+		Ok(())
+	}
+
+	/// Swaps are indexed by output, and a swap into an output another input's swap creates is refused
+	#[test]
+	fn duplicate_output() -> Result<(), Box<dyn std::error::Error>> {
+		let store = TestStore::new("duplicate_output");
+
+		let swap = rand_swap_with_status(SwapStatus::Unprocessed);
+		assert_eq!(None, store.get_swap_by_output(&swap.output_commit)?);
+		store.save_swap(&swap, false)?;
+		assert_eq!(
+			Some(swap.clone()),
+			store.get_swap_by_output(&swap.output_commit)?
+		);
+
+		let mut other = rand_swap_with_status(SwapStatus::Unprocessed);
+		other.output_commit = swap.output_commit.clone();
+		assert_eq!(
+			Err(StoreError::DuplicateOutput(swap.output_commit.clone())),
+			store.save_swap(&other, false)
+		);
+		assert!(!store.swap_exists(&other.input.commit)?);
+
+		// Once the first swap is deleted, the output is free again
+		store.delete_swap(&swap.input.commit)?;
+		assert_eq!(None, store.get_swap_by_output(&swap.output_commit)?);
+		store.save_swap(&other, false)?;
+		assert_eq!(
+			Some(other.clone()),
+			store.get_swap_by_output(&other.output_commit)?
+		);
+
+		// Swaps saved before outputs were indexed are indexed when asked
+		let unindexed = rand_swap_with_status(SwapStatus::Unprocessed);
+		let data = ser::ser_vec(&unindexed, ProtocolVersion::local())?;
+		store.write(SWAP_PREFIX, &unindexed.input.commit, &data, false)?;
+		assert_eq!(None, store.get_swap_by_output(&unindexed.output_commit)?);
+		assert_eq!(1, store.index_outputs()?);
+		assert_eq!(
+			Some(unindexed.clone()),
+			store.get_swap_by_output(&unindexed.output_commit)?
+		);
+		assert_eq!(0, store.index_outputs()?);
+
+		Ok(())
+	}
+
+	#[test]
+	fn save_fee_output() -> Result<(), Box<dyn std::error::Error>> {
+		let store = TestStore::new("save_fee_output");
+		assert_eq!(0, store.fee_outputs_iter()?.count());
+
+		let fee_output = FeeOutputData {
+			output_commit: rand_commit(),
+			amount: rand::thread_rng().next_u64(),
+			kernel_hash: rand_hash(),
+			timestamp: rand::thread_rng().next_u64(),
+		};
+		store.save_fee_output(&fee_output)?;
+		assert_eq!(
+			vec![fee_output.clone()],
+			store.fee_outputs_iter()?.collect::<Vec<_>>()
+		);
+
+		assert_eq!(
+			Err(StoreError::AlreadyExists(fee_output.output_commit.clone())),
+			store.save_fee_output(&fee_output)
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn save_round() -> Result<(), Box<dyn std::error::Error>> {
+		let store = TestStore::new("save_round");
+		assert_eq!(0, store.rounds_iter()?.count());
+
+		let round = RoundData {
+			round_id: 3,
+			kernel_hash: rand_hash(),
+			timestamp: rand::thread_rng().next_u64(),
+			fees: vec![50_000_000, 25_000_000, 60_000_000],
+			anonymity_set: 3,
+			tx_weight: 1_234,
+			confirmed_height: None,
+		};
+		store.save_round(&round)?;
+		assert_eq!(
+			vec![round.clone()],
+			store.rounds_iter()?.collect::<Vec<_>>()
+		);
+		assert_eq!(3, round.swap_count());
+		assert_eq!(135_000_000, round.total_fees());
+
+		// Saving again updates the existing record
+		let confirmed = RoundData {
+			confirmed_height: Some(1_000),
+			..round.clone()
+		};
+		store.save_round(&confirmed)?;
+		assert_eq!(1, store.rounds_iter()?.count());
+		assert_eq!(Some(confirmed), store.get_round(&round.kernel_hash)?);
+
+		// Version 4 rounds had no anonymity set, which was always the number of swaps
+		let current = ser::ser_vec(&round, ProtocolVersion::local())?;
+		let mut v4 = current[..current.len() - 8].to_vec();
+		v4[0] = 4;
+		store.write(ROUND_PREFIX, &round.kernel_hash, &v4, true)?;
+		assert_eq!(Some(round.clone()), store.get_round(&round.kernel_hash)?);
+
+		// Version 2 rounds had no weight or confirmation height either
+		let mut v2 = current[..current.len() - 17].to_vec();
+		v2[0] = 2;
+		store.write(ROUND_PREFIX, &round.kernel_hash, &v2, true)?;
+		assert_eq!(
+			Some(RoundData {
+				tx_weight: 0,
+				..round.clone()
+			}),
+			store.get_round(&round.kernel_hash)?
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn save_receipt() -> Result<(), Box<dyn std::error::Error>> {
+		let store = TestStore::new("save_receipt");
+		let input_commit = rand_commit();
+		assert_eq!(None, store.get_receipt(&input_commit)?);
+
+		let key = secp::random_secret();
+		let receipt = ReceiptData {
+			input_commit: input_commit.clone(),
+			output_commit: rand_commit(),
+			kernel_hash: rand_hash(),
+			round_id: 7,
+			pubkey: PublicKey::from_secret_key(&Secp256k1::new(), &key)?,
+			signature: secp::sign(&key, &Message::from_slice(&[1; 32])?)?,
+		};
+		store.save_receipt(&receipt)?;
+		assert_eq!(Some(receipt), store.get_receipt(&input_commit)?);
+
+		Ok(())
+	}
+
+	#[test]
+	fn kernel_excess() -> Result<(), Box<dyn std::error::Error>> {
+		let store = TestStore::new("kernel_excess");
+		let kernel_hash = rand_hash();
+		assert_eq!(None, store.get_kernel_excess(&kernel_hash)?);
+
+		let excess = rand_commit();
+		store.save_kernel_excess(&kernel_hash, &excess)?;
+		assert_eq!(Some(excess), store.get_kernel_excess(&kernel_hash)?);
+		assert_eq!(None, store.get_kernel_excess(&rand_hash())?);
+
+		Ok(())
+	}
+
+	#[test]
+	fn update_swaps_batch() -> Result<(), Box<dyn std::error::Error>> {
+		let store = TestStore::new("update_swaps_batch");
+
+		let existing = rand_swap_with_status(SwapStatus::Unprocessed);
+		store.save_swap(&existing, false)?;
+
+		let kernel_hash = rand_hash();
+		let swaps = vec![
+			SwapData {
+				status: SwapStatus::InProcess { kernel_hash },
+				..existing.clone()
+			},
+			rand_swap_with_status(SwapStatus::InProcess { kernel_hash }),
+		];
+		store.update_swaps_batch(&swaps)?;
+		for swap in &swaps {
+			assert_eq!(*swap, store.get_swap(&swap.input.commit)?);
+		}
+		assert_eq!(2, store.swaps_iter()?.count());
+
+		Ok(())
+	}
+
+	#[test]
+	fn save_round_batch() -> Result<(), Box<dyn std::error::Error>> {
+		let store = TestStore::new("save_round_batch");
+
+		let kernel_hash = rand_hash();
+		let round = RoundData {
+			round_id: 5,
+			kernel_hash,
+			timestamp: rand::thread_rng().next_u64(),
+			fees: vec![50_000_000],
+			anonymity_set: 1,
+			tx_weight: 1_234,
+			confirmed_height: None,
+		};
+		let swaps = vec![rand_swap_with_status(SwapStatus::InProcess { kernel_hash })];
+		let key = secp::random_secret();
+		let receipts = vec![ReceiptData {
+			input_commit: swaps[0].input.commit.clone(),
+			output_commit: swaps[0].output_commit.clone(),
+			kernel_hash,
+			round_id: 5,
+			pubkey: PublicKey::from_secret_key(&Secp256k1::new(), &key)?,
+			signature: secp::sign(&key, &Message::from_slice(&[1; 32])?)?,
+		}];
+		let fee_output = FeeOutputData {
+			output_commit: rand_commit(),
+			amount: 50_000_000,
+			kernel_hash,
+			timestamp: round.timestamp,
+		};
+		let excess = rand_commit();
+		let decoy = DecoyData {
+			commit: rand_commit(),
+			amount: 20_000_000,
+			blind: secp::random_secret(),
+			masked: true,
+			kernel_hash,
+		};
+		let decoys = RoundDecoys {
+			spent: vec![],
+			created: vec![decoy.clone()],
+		};
+
+		store.save_round_batch(
+			&round,
+			&excess,
+			&swaps,
+			&receipts,
+			Some(&fee_output),
+			&decoys,
+		)?;
+		assert_eq!(Some(round.clone()), store.get_round(&kernel_hash)?);
+		assert_eq!(Some(excess), store.get_kernel_excess(&kernel_hash)?);
+		assert_eq!(swaps[0], store.get_swap(&swaps[0].input.commit)?);
+		assert_eq!(
+			Some(receipts[0].clone()),
+			store.get_receipt(&swaps[0].input.commit)?
+		);
+		assert_eq!(
+			vec![fee_output.clone()],
+			store.fee_outputs_iter()?.collect::<Vec<_>>()
+		);
+		assert_eq!(
+			vec![decoy.clone()],
+			store.decoys_iter()?.collect::<Vec<_>>()
+		);
+		assert_eq!(swaps, store.get_swaps_by_kernel(&kernel_hash)?);
+		assert_eq!(0, store.index_kernels()?);
+
+		// A fee output that's already recorded aborts the whole batch
+		let other = RoundData {
+			kernel_hash: rand_hash(),
+			..round.clone()
+		};
+		let other_swaps = vec![rand_swap_with_status(SwapStatus::Unprocessed)];
+		let respent = RoundDecoys {
+			spent: vec![decoy.commit],
+			created: vec![],
+		};
+		assert_eq!(
+			Err(StoreError::AlreadyExists(fee_output.output_commit.clone())),
+			store.save_round_batch(
+				&other,
+				&excess,
+				&other_swaps,
+				&vec![],
+				Some(&fee_output),
+				&respent,
+			)
+		);
+		assert_eq!(None, store.get_round(&other.kernel_hash)?);
+		assert!(!store.swap_exists(&other_swaps[0].input.commit)?);
+		assert_eq!(1, store.decoys_iter()?.count());
+
+		// Spent decoys are removed
+		store.save_round_batch(&other, &excess, &other_swaps, &vec![], None, &respent)?;
+		assert_eq!(0, store.decoys_iter()?.count());
+
+		// Swaps not in the round aren't indexed under its kernel, and deleted ones drop out
+		assert!(store.get_swaps_by_kernel(&other.kernel_hash)?.is_empty());
+		store.delete_swap(&swaps[0].input.commit)?;
+		assert!(store.get_swaps_by_kernel(&kernel_hash)?.is_empty());
+
+		// Rounds recorded before kernels were indexed are indexed when asked
+		let unindexed = RoundData {
+			kernel_hash: rand_hash(),
+			..round.clone()
+		};
+		let swap = rand_swap_with_status(SwapStatus::Completed {
+			kernel_hash: unindexed.kernel_hash,
+			block_hash: rand_hash(),
+		});
+		store.save_round(&unindexed)?;
+		store.save_swap(&swap, false)?;
+		assert!(store
+			.get_swaps_by_kernel(&unindexed.kernel_hash)?
+			.is_empty());
+		assert_eq!(1, store.index_kernels()?);
+		assert_eq!(
+			vec![swap],
+			store.get_swaps_by_kernel(&unindexed.kernel_hash)?
+		);
+		assert_eq!(0, store.index_kernels()?);
+
+		Ok(())
+	}
+
+	/// Swaps are counted per API key, and the counts carry on from where they were
+	#[test]
+	fn api_key_swaps() -> Result<(), Box<dyn std::error::Error>> {
+		let store = TestStore::new("api_key_swaps");
+		assert!(store.api_key_swaps()?.is_empty());
+
+		store.count_api_key_swap("partner", true)?;
+		store.count_api_key_swap("partner", true)?;
+		store.count_api_key_swap("partner", false)?;
+		store.count_api_key_swap("other", false)?;
+		let mut swaps = store.api_key_swaps()?;
+		swaps.sort_by(|a, b| a.name.cmp(&b.name));
+		assert_eq!(
+			vec![
+				ApiKeySwaps {
+					name: "other".to_string(),
+					accepted: 0,
+					rejected: 1,
+				},
+				ApiKeySwaps {
+					name: "partner".to_string(),
+					accepted: 2,
+					rejected: 1,
+				},
+			],
+			swaps
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn last_round_id() -> Result<(), Box<dyn std::error::Error>> {
+		let store = TestStore::new("last_round_id");
+		assert_eq!(0, store.last_round_id()?);
+
+		store.save_last_round_id(1)?;
+		store.save_last_round_id(42)?;
+		assert_eq!(42, store.last_round_id()?);
+
+		// The write check doesn't disturb anything stored
+		store.check_writable()?;
+		assert_eq!(42, store.last_round_id()?);
+
+		Ok(())
+	}
+
+	#[test]
+	fn feature_flags() -> Result<(), Box<dyn std::error::Error>> {
+		let store = TestStore::new("feature_flags");
+		assert_eq!(FeatureFlags::default(), store.feature_flags()?);
+
+		let mut flags = FeatureFlags::default();
+		flags.set(Feature::AcceptSubmissions, false);
+		store.save_feature_flags(&flags)?;
+		assert!(!store.feature_flags()?.accept_submissions);
+		assert!(store.feature_flags()?.execute_rounds);
+
+		Ok(())
+	}
+
+	#[test]
+	fn round_marker() -> Result<(), Box<dyn std::error::Error>> {
+		let store = TestStore::new("round_marker");
+		assert_eq!(None, store.round_marker()?);
+
+		let marker = RoundMarker {
+			round_id: 7,
+			started_at: 1_700_000_000,
+		};
+		store.save_round_marker(&marker)?;
+		assert_eq!(Some(marker), store.round_marker()?);
+		store.delete_round_marker()?;
+		assert_eq!(None, store.round_marker()?);
+
+		Ok(())
+	}
+
+	/// The sled backend stores, reads, iterates and deletes records just like LMDB does
+	#[test]
+	fn sled_backend() -> Result<(), Box<dyn std::error::Error>> {
+		let store = TestStore::with_backend("sled_backend", StoreBackend::Sled);
+
+		let swap = rand_swap_with_status(SwapStatus::Unprocessed);
+		assert!(!store.swap_exists(&swap.input.commit)?);
+		store.save_swap(&swap, false)?;
+		assert_eq!(swap, store.get_swap(&swap.input.commit)?);
+		assert_eq!(
+			Err(StoreError::AlreadyExists(swap.input.commit.clone())),
+			store.save_swap(&swap, false)
+		);
+
+		let round = RoundData {
+			round_id: 1,
+			kernel_hash: rand_hash(),
+			timestamp: 1_700_000_000,
+			fees: vec![swap.fee],
+			anonymity_set: 1,
+			tx_weight: 42,
+			confirmed_height: None,
+		};
+		let mut included = swap.clone();
+		included.status = SwapStatus::InProcess {
+			kernel_hash: round.kernel_hash,
+		};
+		let fee_output = FeeOutputData {
+			output_commit: rand_commit(),
+			amount: swap.fee,
+			kernel_hash: round.kernel_hash,
+			timestamp: round.timestamp,
+		};
+		let excess = rand_commit();
+		store.save_round_batch(
+			&round,
+			&excess,
+			&vec![included.clone()],
+			&vec![],
+			Some(&fee_output),
+			&RoundDecoys::default(),
+		)?;
+		assert_eq!(
+			vec![included.clone()],
+			store.swaps_iter()?.collect::<Vec<_>>()
+		);
+		assert_eq!(Some(round.clone()), store.get_round(&round.kernel_hash)?);
+		assert_eq!(Some(excess), store.get_kernel_excess(&round.kernel_hash)?);
+		assert_eq!(
+			vec![fee_output],
+			store.fee_outputs_iter()?.collect::<Vec<_>>()
+		);
+
+		store.check_writable()?;
+		store.delete_swap(&swap.input.commit)?;
+		assert!(!store.swap_exists(&swap.input.commit)?);
+		assert!(matches!(
+			store.get_swap(&swap.input.commit),
+			Err(StoreError::NotFound(_))
+		));
+
+		Ok(())
+	}
+
+	/// An existing database is moved to the new location, unless one is already there
+	#[test]
+	fn migrate() -> Result<(), Box<dyn std::error::Error>> {
+		let mut legacy = TestStore::new("migrate");
+		let swap = rand_swap();
+		legacy.save_swap(&swap, false)?;
+		drop(legacy.take());
+
+		let db_root = legacy.db_root().join("moved");
+		assert!(SwapStore::migrate(
+			legacy.db_root(),
+			&db_root,
+			StoreBackend::Lmdb
+		)?);
+		assert!(!legacy.db_root().join(DB_NAME).exists());
+		let moved = SwapStore::new(db_root.to_str().unwrap())?;
+		assert_eq!(swap, moved.get_swap(&swap.input.commit)?);
+
+		// Nothing left to move
+		assert!(!SwapStore::migrate(
+			legacy.db_root(),
+			&db_root,
+			StoreBackend::Lmdb
+		)?);
+
+		Ok(())
+	}
+
+	/// Each fixture gets its own directory, which is removed once it's dropped
+	#[test]
+	fn test_store_teardown() -> Result<(), Box<dyn std::error::Error>> {
+		let store = TestStore::new("test_store_teardown");
+		let other = TestStore::new("test_store_teardown");
+		assert_ne!(store.db_root(), other.db_root());
+
+		let swap = rand_swap();
+		store.save_swap(&swap, false)?;
+		assert!(!other.swap_exists(&swap.input.commit)?);
+
+		let db_root = store.db_root().to_path_buf();
+		assert!(db_root.exists());
+		drop(store);
+		assert!(!db_root.exists());
+
+		Ok(())
+	}
+
+	/// Moving a set of swaps through each status leaves every swap updated, and no others touched
+	#[test]
+	fn update_statuses() -> Result<(), Box<dyn std::error::Error>> {
+		let store = TestStore::new("update_statuses");
+		let mut round: Vec<SwapData> = (0..5)
+			.map(|_| rand_swap_with_status(SwapStatus::Unprocessed))
+			.collect();
+		let untouched = rand_swap_with_status(SwapStatus::Unprocessed);
+		for swap in round.iter().chain(std::iter::once(&untouched)) {
+			store.save_swap(swap, false)?;
+		}
+
+		let kernel_hash = rand_hash();
+		let block_hash = rand_hash();
+		for status in vec![
+			SwapStatus::InProcess { kernel_hash },
+			SwapStatus::Completed {
+				kernel_hash,
+				block_hash,
+			},
+		] {
+			for swap in round.iter_mut() {
+				swap.status = status.clone();
+				store.save_swap(swap, true)?;
+			}
+			for swap in &round {
+				assert_eq!(*swap, store.get_swap(&swap.input.commit)?);
+			}
+		}
+
+		assert_eq!(untouched, store.get_swap(&untouched.input.commit)?);
+		assert_eq!(6, store.swaps_iter()?.count());
+
+		Ok(())
+	}
+
+	/// Swaps can be picked out of the iterator by status
+	#[test]
+	fn swap_iter_by_status() -> Result<(), Box<dyn std::error::Error>> {
+		let store = TestStore::new("swap_iter_by_status");
+		let swaps: Vec<SwapData> = (0..12).map(|_| rand_swap()).collect();
+		for swap in &swaps {
+			store.save_swap(swap, false)?;
+		}
+
+		for name in vec!["Unprocessed", "InProcess", "Completed"] {
+			let mut expected: Vec<SwapData> = swaps
+				.iter()
+				.filter(|s| s.status.name() == name)
+				.cloned()
+				.collect();
+			expected.sort_by(|a, b| a.input.commit.partial_cmp(&b.input.commit).unwrap());
+			let found: Vec<SwapData> = store
+				.swaps_iter()?
+				.filter(|s| s.status.name() == name)
+				.collect();
+			assert_eq!(expected, found);
+		}
+
+		Ok(())
+	}
+
+	/// Only archived swaps are compressed, and compressed swaps are read back whether or not
+	/// compression is still enabled
+	#[test]
+	fn compressed_archived_swaps() -> Result<(), Box<dyn std::error::Error>> {
+		let mut fixture = TestStore::new("compressed_archived_swaps");
+		let store = fixture.take().with_compression(true);
+		let unprocessed = rand_swap_with_status(SwapStatus::Unprocessed);
+		let completed = rand_swap_with_status(SwapStatus::Completed {
+			kernel_hash: rand_hash(),
+			block_hash: rand_hash(),
+		});
+		store.save_swap(&unprocessed, false)?;
+		store.save_swap(&completed, false)?;
+
+		let raw = |swap: &SwapData| {
+			let key = grin_store::to_key(SWAP_PREFIX, &swap.input.commit);
+			store.db.get(&key[..]).unwrap().unwrap()
+		};
+		assert!(!compress::is_compressed(&raw(&unprocessed)));
+		assert!(compress::is_compressed(&raw(&completed)));
+		assert_eq!(completed, store.get_swap(&completed.input.commit)?);
+		assert_eq!(2, store.swaps_iter()?.count());
+
+		let store = store.with_compression(false);
+		assert_eq!(completed, store.get_swap(&completed.input.commit)?);
+		assert_eq!(unprocessed, store.get_swap(&unprocessed.input.commit)?);
+		Ok(())
+	}
+
+	/// Swaps saved by older versions are read with defaults for newer fields,
+	/// and records from newer versions are rejected rather than misread.
+	#[test]
+	fn swap_version_migration() -> Result<(), Box<dyn std::error::Error>> {
+		let store = TestStore::new("swap_version_migration");
+		let swap = rand_swap();
+		let current = ser::ser_vec(&swap, ProtocolVersion::local())?;
+		assert_eq!(CURRENT_VERSION, current[0]);
+
+		// Versions 8 to 10 had a reply key and sealed reply, which are dropped
+		let mut v10 = current.clone();
+		v10[0] = 10;
+		v10.extend_from_slice(&[1u8; 33]);
+		v10.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0, 2, 7, 7]);
+		store.write(SWAP_PREFIX, &swap.input.commit, &v10, true)?;
+		assert_eq!(swap, store.get_swap(&swap.input.commit)?);
+
+		// Version 7 had none
+		let mut v7 = current.clone();
+		v7[0] = 7;
+		store.write(SWAP_PREFIX, &swap.input.commit, &v7, true)?;
+		assert_eq!(swap, store.get_swap(&swap.input.commit)?);
+
+		// Version 6 had no fee change
+		let mut v6 = current[..current.len() - 1].to_vec();
+		v6[0] = 6;
+		store.write(SWAP_PREFIX, &swap.input.commit, &v6, true)?;
+		assert_eq!(swap, store.get_swap(&swap.input.commit)?);
+
+		// Version 5 didn't record when the swap was accepted
+		let mut v5 = current[..current.len() - 17].to_vec();
+		v5[0] = 5;
+		store.write(SWAP_PREFIX, &swap.input.commit, &v5, true)?;
+		let swap = SwapData {
+			accepted_at: 0,
+			accepted_round: 0,
+			fee_change: None,
+			..swap
+		};
+		assert_eq!(swap, store.get_swap(&swap.input.commit)?);
+
+		// Version 3 had no owner kernel
+		let mut v3 = current[..current.len() - 18].to_vec();
+		v3[0] = 3;
+		store.write(SWAP_PREFIX, &swap.input.commit, &v3, true)?;
+		assert_eq!(swap, store.get_swap(&swap.input.commit)?);
+
+		// Version 1 had no unconfirmed input flag
+		let mut v1 = current[..current.len() - 19].to_vec();
+		v1[0] = 1;
+		store.write(SWAP_PREFIX, &swap.input.commit, &v1, true)?;
+		assert_eq!(
+			SwapData {
+				unconfirmed_input: false,
+				..swap.clone()
+			},
+			store.get_swap(&swap.input.commit)?
+		);
+
+		// Version 0 had no expiry height either
+		let mut v0 = current[..current.len() - 27].to_vec();
+		v0[0] = 0;
+		store.write(SWAP_PREFIX, &swap.input.commit, &v0, true)?;
+		let migrated = store.get_swap(&swap.input.commit)?;
+		assert_eq!(u64::MAX, migrated.expiry_height);
+		assert_eq!(
+			SwapData {
+				expiry_height: u64::MAX,
+				unconfirmed_input: false,
+				..swap.clone()
+			},
+			migrated
+		);
+
+		let mut future = current.clone();
+		future[0] = CURRENT_VERSION + 1;
+		store.write(SWAP_PREFIX, &swap.input.commit, &future, true)?;
+		assert!(store.get_swap(&swap.input.commit).is_err());
+
+		Ok(())
+	}
+
+	/// Only the most recent rejections are kept, each new one overwriting the oldest
+	#[test]
+	fn rejection_log() -> Result<(), Box<dyn std::error::Error>> {
+		let store = TestStore::new("rejection_log");
+		for i in 0..5 {
+			let rejection = RejectionData {
+				id: 0,
+				timestamp: 1_700_000_000 + i,
+				source: if i % 2 == 0 {
+					Some("203.0.113.7".to_string())
+				} else {
+					None
+				},
+				onion_hash: "0011223344556677".to_string(),
+				error: "fee_too_low".to_string(),
+				message: format!("Fee too low {}", i),
+			};
+			store.save_rejection(rejection, 3)?;
+		}
+
+		let rejections = store.rejections(10)?;
+		assert_eq!(
+			vec![4, 3, 2],
+			rejections.iter().map(|r| r.id).collect::<Vec<u64>>()
+		);
+		assert_eq!("Fee too low 4", rejections[0].message);
+		assert_eq!(Some("203.0.113.7".to_string()), rejections[0].source);
+		assert_eq!(None, rejections[1].source);
+		assert_eq!(2, store.rejections(2)?.len());
+
+		// Nothing is recorded while the log is turned off
+		store.save_rejection(rejections[0].clone(), 0)?;
+		assert_eq!(3, store.rejections(10)?.len());
 		Ok(())
 	}
 }
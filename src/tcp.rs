@@ -0,0 +1,101 @@
+//! JSON-RPC over HTTP on a TCP socket, served with hyper directly like 'uds' does.
+//!
+//! jsonrpc_http_server doesn't pass on the address of the peer a request arrived from, which
+//! is needed to tell requests relayed by a trusted reverse proxy from anyone else's. Each request
+//! is given its peer's address as a 'PeerAddr' extension before it reaches the middleware.
+
+use crate::uds;
+
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request};
+use jsonrpc_core::{MetaIoHandler, Metadata};
+use jsonrpc_http_server::RequestMiddlewareAction;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread::{spawn, JoinHandle};
+use tokio::sync::oneshot;
+use tracing::error;
+
+/// The address of the peer a request arrived from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeerAddr(pub SocketAddr);
+
+/// A running listener on a TCP socket
+pub struct TcpServer {
+	shutdown: oneshot::Sender<()>,
+	handle: JoinHandle<()>,
+}
+
+impl TcpServer {
+	pub fn close(self) {
+		let _ = self.shutdown.send(());
+		let _ = self.handle.join();
+	}
+}
+
+/// Serves 'io' at 'addr'. Requests go through 'middleware' first, as they would with
+/// jsonrpc_http_server, and the ones it lets through are handled with the metadata 'extractor'
+/// returns for them.
+pub fn start<M, E, R>(
+	addr: &SocketAddr,
+	io: MetaIoHandler<M>,
+	extractor: E,
+	middleware: R,
+	max_request_body_size: usize,
+) -> std::io::Result<TcpServer>
+where
+	M: Metadata,
+	E: Fn(&Request<Body>) -> M + Send + Sync + 'static,
+	R: Fn(Request<Body>) -> RequestMiddlewareAction + Send + Sync + 'static,
+{
+	let runtime = tokio::runtime::Builder::new_multi_thread()
+		.enable_all()
+		.build()?;
+	let listener = std::net::TcpListener::bind(addr)?;
+	listener.set_nonblocking(true)?;
+	let incoming = {
+		let _guard = runtime.enter();
+		let listener = tokio::net::TcpListener::from_std(listener)?;
+		hyper::server::conn::AddrIncoming::from_listener(listener)
+			.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+	};
+
+	let io = Arc::new(io);
+	let extractor = Arc::new(extractor);
+	let middleware = Arc::new(middleware);
+	let (shutdown, shutdown_rx) = oneshot::channel();
+	let handle = spawn(move || {
+		runtime.block_on(async move {
+			let make_service = make_service_fn(move |conn: &AddrStream| {
+				let peer = PeerAddr(conn.remote_addr());
+				let io = io.clone();
+				let extractor = extractor.clone();
+				let middleware = middleware.clone();
+				async move {
+					Ok::<_, Infallible>(service_fn(move |mut request: Request<Body>| {
+						request.extensions_mut().insert(peer);
+						uds::serve(
+							request,
+							io.clone(),
+							extractor.clone(),
+							middleware.clone(),
+							max_request_body_size,
+						)
+					}))
+				}
+			});
+			let server = hyper::Server::builder(incoming)
+				.serve(make_service)
+				.with_graceful_shutdown(async {
+					let _ = shutdown_rx.await;
+				});
+			if let Err(e) = server.await {
+				error!("TCP listener failed: {}", e);
+			}
+		});
+	});
+
+	Ok(TcpServer { shutdown, handle })
+}
@@ -0,0 +1,199 @@
+//! JSON-RPC over HTTP on a unix socket, for single-host deployments where the API shouldn't be
+//! reachable over the network at all.
+//!
+//! jsonrpc_http_server only binds TCP sockets, so this serves the same handler and request
+//! middleware with hyper directly. Access is left to the socket file's permissions.
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use jsonrpc_core::{MetaIoHandler, Metadata};
+use jsonrpc_http_server::RequestMiddlewareAction;
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread::{spawn, JoinHandle};
+use tokio::sync::oneshot;
+use tracing::error;
+
+/// A running listener on a unix socket. The socket file is removed once it's closed.
+pub struct UnixServer {
+	path: PathBuf,
+	shutdown: oneshot::Sender<()>,
+	handle: JoinHandle<()>,
+}
+
+impl UnixServer {
+	pub fn close(self) {
+		let _ = self.shutdown.send(());
+		let _ = self.handle.join();
+		let _ = std::fs::remove_file(&self.path);
+	}
+}
+
+/// Serves 'io' on the unix socket at 'path', replacing any stale socket file left there.
+/// Requests go through 'middleware' first, as they would with jsonrpc_http_server, and the
+/// ones it lets through are handled with the metadata 'extractor' returns for them.
+#[cfg(unix)]
+pub fn start<M, E, R>(
+	path: &Path,
+	io: MetaIoHandler<M>,
+	extractor: E,
+	middleware: R,
+	max_request_body_size: usize,
+) -> std::io::Result<UnixServer>
+where
+	M: Metadata,
+	E: Fn(&Request<Body>) -> M + Send + Sync + 'static,
+	R: Fn(Request<Body>) -> RequestMiddlewareAction + Send + Sync + 'static,
+{
+	use std::os::unix::fs::FileTypeExt;
+
+	if let Ok(metadata) = std::fs::symlink_metadata(path) {
+		if !metadata.file_type().is_socket() {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::AlreadyExists,
+				format!("{} exists and isn't a socket", path.display()),
+			));
+		}
+		std::fs::remove_file(path)?;
+	}
+
+	let runtime = tokio::runtime::Builder::new_multi_thread()
+		.enable_all()
+		.build()?;
+	let listener = {
+		let _guard = runtime.enter();
+		tokio::net::UnixListener::bind(path)?
+	};
+
+	let io = Arc::new(io);
+	let extractor = Arc::new(extractor);
+	let middleware = Arc::new(middleware);
+	let (shutdown, shutdown_rx) = oneshot::channel();
+	let handle = spawn(move || {
+		runtime.block_on(async move {
+			let make_service = make_service_fn(move |_| {
+				let io = io.clone();
+				let extractor = extractor.clone();
+				let middleware = middleware.clone();
+				async move {
+					Ok::<_, Infallible>(service_fn(move |request| {
+						serve(
+							request,
+							io.clone(),
+							extractor.clone(),
+							middleware.clone(),
+							max_request_body_size,
+						)
+					}))
+				}
+			});
+			let accept = hyper::server::accept::poll_fn(move |cx| {
+				listener
+					.poll_accept(cx)
+					.map(|res| Some(res.map(|(stream, _)| stream)))
+			});
+			let server = hyper::Server::builder(accept)
+				.serve(make_service)
+				.with_graceful_shutdown(async {
+					let _ = shutdown_rx.await;
+				});
+			if let Err(e) = server.await {
+				error!("Unix socket listener failed: {}", e);
+			}
+		});
+	});
+
+	Ok(UnixServer {
+		path: path.to_path_buf(),
+		shutdown,
+		handle,
+	})
+}
+
+#[cfg(not(unix))]
+pub fn start<M, E, R>(
+	path: &Path,
+	_io: MetaIoHandler<M>,
+	_extractor: E,
+	_middleware: R,
+	_max_request_body_size: usize,
+) -> std::io::Result<UnixServer>
+where
+	M: Metadata,
+	E: Fn(&Request<Body>) -> M + Send + Sync + 'static,
+	R: Fn(Request<Body>) -> RequestMiddlewareAction + Send + Sync + 'static,
+{
+	Err(std::io::Error::new(
+		std::io::ErrorKind::Unsupported,
+		format!(
+			"Can't listen on {}: unix sockets aren't supported on this platform",
+			path.display()
+		),
+	))
+}
+
+/// Handles a request the way jsonrpc_http_server would, for both this listener and 'tcp'
+pub async fn serve<M, E, R>(
+	request: Request<Body>,
+	io: Arc<MetaIoHandler<M>>,
+	extractor: Arc<E>,
+	middleware: Arc<R>,
+	max_request_body_size: usize,
+) -> hyper::Result<Response<Body>>
+where
+	M: Metadata,
+	E: Fn(&Request<Body>) -> M,
+	R: Fn(Request<Body>) -> RequestMiddlewareAction,
+{
+	let request = match middleware(request) {
+		RequestMiddlewareAction::Respond { response, .. } => return response.await,
+		RequestMiddlewareAction::Proceed { request, .. } => request,
+	};
+	if request.method() != Method::POST {
+		return Ok(text_response(
+			StatusCode::METHOD_NOT_ALLOWED,
+			"Only POST requests are supported",
+		));
+	}
+
+	let meta = extractor(&request);
+	let mut body = request.into_body();
+	let mut bytes = Vec::new();
+	while let Some(chunk) = hyper::body::HttpBody::data(&mut body).await {
+		bytes.extend_from_slice(&chunk?);
+		if bytes.len() > max_request_body_size {
+			return Ok(text_response(
+				StatusCode::PAYLOAD_TOO_LARGE,
+				"Request body is too large",
+			));
+		}
+	}
+	let request = match String::from_utf8(bytes) {
+		Ok(request) => request,
+		Err(_) => {
+			return Ok(text_response(
+				StatusCode::BAD_REQUEST,
+				"Request body is not valid UTF-8",
+			))
+		}
+	};
+
+	let content = io.handle_request(&request, meta).await.unwrap_or_default();
+	Ok(Response::builder()
+		.status(StatusCode::OK)
+		.header(
+			hyper::header::CONTENT_TYPE,
+			"application/json; charset=utf-8",
+		)
+		.body(Body::from(content))
+		.expect("Valid response"))
+}
+
+fn text_response(code: StatusCode, content: &str) -> Response<Body> {
+	Response::builder()
+		.status(code)
+		.header(hyper::header::CONTENT_TYPE, "text/plain; charset=utf-8")
+		.body(Body::from(content.to_string()))
+		.expect("Valid response")
+}
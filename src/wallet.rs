@@ -1,24 +1,44 @@
-use crate::secp;
+use crate::http::HttpClient;
+use crate::secp::{self, Commitment};
+use crate::types::FeeChange;
 
-use grin_api::client;
 use grin_api::json_rpc::{build_request, Request, Response};
 use grin_core::core::{
-	FeeFields, Input, Inputs, KernelFeatures, Output, Transaction, TransactionBody, TxKernel,
+	FeeFields, Input, Inputs, KernelFeatures, NRDRelativeHeight, Output, OutputFeatures,
+	Transaction, TransactionBody, TxKernel,
 };
-use grin_core::libtx::secp_ser;
-use grin_keychain::BlindingFactor;
+use grin_core::global;
+use grin_core::libtx::{proof, secp_ser};
+use grin_keychain::{BlindingFactor, ExtKeychain, Keychain, SwitchCommitmentType};
 use grin_util::{ToHex, ZeroingString};
 use grin_wallet_api::{EncryptedRequest, EncryptedResponse, JsonId, Token};
 use secp256k1zkp::{ContextFlag, PublicKey, Secp256k1, SecretKey};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 use thiserror::Error;
+use tracing::{debug, info, warn};
 
+/// The wallet functionality required by mwixnet, used to collect the server's fees.
+/// Implementations may talk to an external wallet (see 'HttpWallet') or derive keys locally
+/// (see 'EmbeddedWallet').
 pub trait Wallet: Send + Sync {
 	/// Builds an output for the wallet with the provided amount.
 	fn build_output(&self, amount: u64) -> Result<(BlindingFactor, Output), WalletError>;
+
+	/// Releases any wallet state (e.g. locked outputs) held for an output from 'build_output'
+	/// that won't be included in a transaction after all, so it can't block later rounds.
+	fn cancel_output(&self, output_commit: &Commitment) -> Result<(), WalletError>;
+
+	/// Checks that the wallet can be reached and is ready to build outputs.
+	fn ping(&self) -> Result<(), WalletError>;
+
+	/// Called before the outputs of round 'round_id' are built. Wallets that derive their keys
+	/// locally may derive them under the round.
+	fn start_round(&self, _round_id: u64) {}
 }
 
 /// Error types for interacting with wallets
@@ -26,6 +46,8 @@ pub trait Wallet: Send + Sync {
 pub enum WalletError {
 	#[error("Error building kernel's fee fields: {0:?}")]
 	KernelFeeError(grin_core::core::transaction::Error),
+	#[error("Invalid NRD relative height: {0:?}")]
+	RelativeHeightError(grin_core::core::transaction::Error),
 	#[error("Error computing kernel's excess: {0:?}")]
 	KernelExcessError(secp256k1zkp::Error),
 	#[error("Error computing kernel's signature message: {0:?}")]
@@ -46,9 +68,168 @@ pub enum WalletError {
 	ApiCommError(grin_api::Error),
 	#[error("Error decoding JSON-RPC response: {0:?}")]
 	ResponseParseError(grin_api::json_rpc::Error),
+	#[error("Keychain error: {0:?}")]
+	KeychainError(grin_keychain::Error),
+	#[error("Error building output rangeproof: {0:?}")]
+	RangeproofError(grin_core::libtx::Error),
+	#[error("Transaction body is not in canonical order: {0:?}")]
+	TxOrderError(grin_core::core::transaction::Error),
+	#[error("JSON-RPC error from wallet: {0}")]
+	RpcError(String),
+	#[error("Wallet owner API returned an error: {0}")]
+	OwnerApiError(String),
+	#[error(
+		"Round transaction pays out {actual} in fees and earnings, but the swaps paid {expected}"
+	)]
+	FeeMismatch { expected: u64, actual: u64 },
+	#[error("Wallet output is worth {actual}, but {expected} was left after the network fee")]
+	EarningsMismatch { expected: u64, actual: u64 },
+}
+
+impl WalletError {
+	/// Whether the error may be caused by the wallet having lost our session (e.g. by restarting),
+	/// which reopening the wallet fixes. Once the wallet restarts, it can't decrypt our requests,
+	/// and tokens from before the restart are rejected.
+	fn is_session_error(&self) -> bool {
+		matches!(
+			self,
+			WalletError::ApiCommError(_)
+				| WalletError::DecryptResponseError(_)
+				| WalletError::RpcError(_)
+				| WalletError::OwnerApiError(_)
+		)
+	}
+}
+
+/// Why the wallet can't build a round's outputs, as found by 'preflight'
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum PreflightFailure {
+	#[error(
+		"Wallet can't be reached ({0}). Check that its owner API is running at 'wallet_owner_url'."
+	)]
+	Unreachable(String),
+	#[error("Wallet isn't open ({0}). Check that it's unlocked and the password is correct.")]
+	Locked(String),
+}
+
+/// Checks, before a round's outputs are built, that the wallet can be reached and is open.
+/// A wallet that answers with an error is taken to be locked, since that's what it does until
+/// it's opened with the right password.
+pub fn preflight(wallet: &Arc<dyn Wallet>) -> Result<(), PreflightFailure> {
+	match wallet.ping() {
+		Ok(()) => Ok(()),
+		Err(WalletError::ApiCommError(e)) => Err(PreflightFailure::Unreachable(e.to_string())),
+		Err(e) => Err(PreflightFailure::Locked(e.to_string())),
+	}
+}
+
+/// An output built by the wallet to collect the leftover fees of a transaction
+#[derive(Clone, Debug, PartialEq)]
+pub struct WalletOutput {
+	pub commit: Commitment,
+	pub amount: u64,
+	pub blind: SecretKey,
+}
+
+/// A decoy self-swap, spending one of the server's own outputs into a new wallet output.
+/// The decoy pays for its own weight out of the spent output's value.
+pub struct Decoy {
+	pub input: Input,
+	pub output: Output,
+	/// the new output's blinding factor less the spent output's
+	pub excess: SecretKey,
+	/// the fee paid for the decoy's input and output
+	pub fee: u64,
+	pub wallet_output: WalletOutput,
+}
+
+/// Builds a decoy spending the output 'commit' (with value 'amount' and blinding factor 'blind')
+/// into a new output from the wallet. Returns None when the output isn't worth enough to pay
+/// for the decoy, and for the new output to be spent later.
+pub fn build_decoy(
+	wallet: &Arc<dyn Wallet>,
+	commit: &Commitment,
+	amount: u64,
+	blind: &SecretKey,
+	fee_base: u64,
+) -> Result<Option<Decoy>, WalletError> {
+	let fee = TransactionBody::weight_by_iok(1, 1, 0) * fee_base;
+	let fee_to_spend = TransactionBody::weight_by_iok(1, 0, 0) * fee_base;
+	if amount <= fee + fee_to_spend {
+		return Ok(None);
+	}
+
+	let output_amount = amount - fee;
+	let (output_blind, output) = wallet.build_output(output_amount)?;
+	let secp = Secp256k1::with_caps(ContextFlag::Commit);
+	let output_blind = SecretKey::from_slice(&secp, &output_blind.as_ref())
+		.map_err(WalletError::OutputBlindError)?;
+	let excess = secp
+		.blind_sum(vec![output_blind.clone()], vec![blind.clone()])
+		.map_err(WalletError::KernelExcessError)?;
+
+	Ok(Some(Decoy {
+		input: Input::new(OutputFeatures::Plain, commit.clone()),
+		wallet_output: WalletOutput {
+			commit: output.commitment(),
+			amount: output_amount,
+			blind: output_blind,
+		},
+		output,
+		excess,
+		fee,
+	}))
+}
+
+/// Cancels a wallet output that won't be used. Failures are only logged,
+/// since the error that caused the rollback is the one worth reporting.
+pub fn rollback_output(wallet: &Arc<dyn Wallet>, wallet_output: &WalletOutput) {
+	if let Err(e) = wallet.cancel_output(&wallet_output.commit) {
+		warn!(
+			"Failed to cancel wallet output {}: {}",
+			wallet_output.commit.to_hex(),
+			e
+		);
+	}
+}
+
+/// The kind of kernel a round's transaction is signed with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RoundKernelKind {
+	Plain,
+	/// A NoRecentDuplicate kernel: no other kernel with the same excess can be mined within
+	/// 'relative_height' blocks of it
+	NoRecentDuplicate {
+		relative_height: u16,
+	},
+}
+
+impl RoundKernelKind {
+	/// The configured kind of round kernel: NRD when a relative height is set, plain otherwise
+	pub fn new(nrd_relative_height: Option<u16>) -> RoundKernelKind {
+		match nrd_relative_height {
+			Some(relative_height) => RoundKernelKind::NoRecentDuplicate { relative_height },
+			None => RoundKernelKind::Plain,
+		}
+	}
+
+	fn features(&self, fee: FeeFields) -> Result<KernelFeatures, WalletError> {
+		match self {
+			RoundKernelKind::Plain => Ok(KernelFeatures::Plain { fee }),
+			RoundKernelKind::NoRecentDuplicate { relative_height } => {
+				Ok(KernelFeatures::NoRecentDuplicate {
+					fee,
+					relative_height: NRDRelativeHeight::new(*relative_height as u64)
+						.map_err(WalletError::RelativeHeightError)?,
+				})
+			}
+		}
+	}
 }
 
 /// Builds and verifies a 'Transaction' using the provided components.
+/// Returns the wallet's output along with the transaction, if any fees were left over to collect.
+/// If building the transaction fails after the wallet's output was built, the output is cancelled.
 pub fn assemble_tx(
 	wallet: &Arc<dyn Wallet>,
 	inputs: &Vec<Input>,
@@ -56,17 +237,21 @@ pub fn assemble_tx(
 	fee_base: u64,
 	total_fee: u64,
 	excesses: &Vec<SecretKey>,
-) -> Result<Transaction, WalletError> {
-	let secp = Secp256k1::with_caps(ContextFlag::Commit);
-	let txn_inputs = Inputs::from(inputs.as_slice());
+	owner_kernels: &Vec<TxKernel>,
+	kernel_kind: RoundKernelKind,
+) -> Result<(Transaction, Option<WalletOutput>), WalletError> {
 	let mut txn_outputs = outputs.clone();
-	let mut txn_excesses = excesses.clone();
+	let mut output_blinds = Vec::new();
 	let mut kernel_fee = total_fee;
+	let mut wallet_output = None;
 
 	// calculate fee required if we add our own output
-	let fee_required =
-		TransactionBody::weight_by_iok(inputs.len() as u64, (outputs.len() + 1) as u64, 1)
-			* fee_base;
+	let num_kernels = (owner_kernels.len() + 1) as u64;
+	let fee_required = TransactionBody::weight_by_iok(
+		inputs.len() as u64,
+		(outputs.len() + 1) as u64,
+		num_kernels,
+	) * fee_base;
 
 	// calculate fee to spend the output to ensure there's enough leftover to cover the fees for spending it
 	let fee_to_spend = TransactionBody::weight_by_iok(1, 0, 0) * fee_base;
@@ -76,11 +261,95 @@ pub fn assemble_tx(
 		let amount = total_fee - fee_required;
 		kernel_fee -= amount;
 
-		let wallet_output = wallet.build_output(amount)?;
-		txn_outputs.push(wallet_output.1);
+		let (blind, output) = wallet.build_output(amount)?;
+		let secp = Secp256k1::with_caps(ContextFlag::Commit);
+		wallet_output = Some(WalletOutput {
+			commit: output.commitment(),
+			amount,
+			blind: SecretKey::from_slice(&secp, &blind.as_ref())
+				.map_err(WalletError::OutputBlindError)?,
+		});
+		txn_outputs.push(output);
+		output_blinds.push(blind);
+	}
+
+	let tx = build_tx(
+		inputs,
+		&txn_outputs,
+		excesses,
+		&output_blinds,
+		owner_kernels,
+		kernel_fee,
+		kernel_kind,
+	)
+	.and_then(|tx| {
+		check_fees(&tx, total_fee, fee_required, &wallet_output)?;
+		Ok(tx)
+	});
+	match tx {
+		Ok(tx) => Ok((tx, wallet_output)),
+		Err(e) => {
+			// don't leave the wallet holding onto an output that won't make it on chain
+			if let Some(wallet_output) = &wallet_output {
+				rollback_output(wallet, wallet_output);
+			}
+			Err(e)
+		}
+	}
+}
+
+/// Checks the round transaction pays out exactly the swaps' 'total_fee': its kernels' fees and the
+/// wallet's output must add up to it, and the wallet's output must hold all that's left once the
+/// network fee for the transaction's weight ('fee_required') is paid. A mismatch means a bug (or
+/// a misbehaving wallet), so the round is better aborted than posted.
+fn check_fees(
+	tx: &Transaction,
+	total_fee: u64,
+	fee_required: u64,
+	wallet_output: &Option<WalletOutput>,
+) -> Result<(), WalletError> {
+	let earnings = wallet_output.as_ref().map(|o| o.amount).unwrap_or(0);
+	if tx.fee() + earnings != total_fee {
+		return Err(WalletError::FeeMismatch {
+			expected: total_fee,
+			actual: tx.fee() + earnings,
+		});
+	}
 
-		let output_excess = SecretKey::from_slice(&secp, &wallet_output.0.as_ref())
+	if let Some(wallet_output) = wallet_output {
+		let expected = total_fee.saturating_sub(fee_required);
+		// The wallet's output must commit to the amount it was asked for, or the transaction
+		// won't balance
+		let commit = secp::commit(wallet_output.amount, &wallet_output.blind)
 			.map_err(WalletError::OutputBlindError)?;
+		let included = tx.outputs().iter().any(|o| o.commitment() == commit);
+		if wallet_output.amount != expected || commit != wallet_output.commit || !included {
+			return Err(WalletError::EarningsMismatch {
+				expected,
+				actual: wallet_output.amount,
+			});
+		}
+	}
+	Ok(())
+}
+
+/// Signs the kernel and builds the 'Transaction'. The blinding factors of any outputs
+/// not already accounted for by 'excesses' are included in the kernel excess.
+/// The swaps' owner kernels account for the rest, and are included alongside the round's kernel.
+fn build_tx(
+	inputs: &Vec<Input>,
+	outputs: &Vec<Output>,
+	excesses: &Vec<SecretKey>,
+	output_blinds: &Vec<BlindingFactor>,
+	owner_kernels: &Vec<TxKernel>,
+	kernel_fee: u64,
+	kernel_kind: RoundKernelKind,
+) -> Result<Transaction, WalletError> {
+	let secp = Secp256k1::with_caps(ContextFlag::Commit);
+	let mut txn_excesses = excesses.clone();
+	for blind in output_blinds {
+		let output_excess =
+			SecretKey::from_slice(&secp, &blind.as_ref()).map_err(WalletError::OutputBlindError)?;
 		txn_excesses.push(output_excess);
 	}
 
@@ -93,9 +362,10 @@ pub fn assemble_tx(
 		.map_err(WalletError::KernelExcessError)?;
 
 	// build and verify kernel
-	let mut kernel = TxKernel::with_features(KernelFeatures::Plain {
-		fee: FeeFields::new(0, kernel_fee).map_err(WalletError::KernelFeeError)?,
-	});
+	let mut kernel = TxKernel::with_features(
+		kernel_kind
+			.features(FeeFields::new(0, kernel_fee).map_err(WalletError::KernelFeeError)?)?,
+	);
 	let msg = kernel
 		.msg_to_sign()
 		.map_err(WalletError::KernelSigMessageError)?;
@@ -103,17 +373,108 @@ pub fn assemble_tx(
 	kernel.excess_sig = secp::sign(&kern_excess, &msg).map_err(WalletError::KernelSigError)?;
 	kernel.verify().map_err(WalletError::KernelVerifyError)?;
 
-	// assemble the transaction
-	let tx = Transaction::new(txn_inputs, &txn_outputs, &[kernel])
-		.with_offset(BlindingFactor::from_secret_key(offset));
+	// assemble the transaction, sorting the inputs, outputs and kernels into canonical order
+	// so their positions reveal nothing about the order the swaps were submitted in
+	let mut txn_inputs = inputs.clone();
+	txn_inputs.sort_unstable();
+	let mut txn_outputs = outputs.clone();
+	txn_outputs.sort_unstable();
+	let mut kernels = vec![kernel];
+	kernels.extend(owner_kernels.iter().cloned());
+	kernels.sort_unstable();
+	let body = TransactionBody::init(
+		Inputs::from(txn_inputs.as_slice()),
+		&txn_outputs,
+		&kernels,
+		true,
+	)
+	.map_err(WalletError::TxOrderError)?;
+	let tx = Transaction {
+		offset: BlindingFactor::from_secret_key(offset),
+		body,
+	};
 	Ok(tx)
 }
 
-/// HTTP (JSONRPC) implementation of the 'Wallet' trait.
+/// Builds the fee-free kernel a swap's owner signs for the part of the blinding delta
+/// they keep from the server, with 'owner_excess' as its blinding factor
+pub fn build_owner_kernel(owner_excess: &SecretKey) -> Result<TxKernel, WalletError> {
+	let mut kernel = TxKernel::with_features(KernelFeatures::Plain {
+		fee: FeeFields::zero(),
+	});
+	let msg = kernel
+		.msg_to_sign()
+		.map_err(WalletError::KernelSigMessageError)?;
+	kernel.excess = secp::commit(0, owner_excess).map_err(WalletError::KernelExcessError)?;
+	kernel.excess_sig = secp::sign(owner_excess, &msg).map_err(WalletError::KernelSigError)?;
+	kernel.verify().map_err(WalletError::KernelVerifyError)?;
+	Ok(kernel)
+}
+
+/// Whether 'kernel' is a valid owner kernel: a fee-free plain kernel with a valid signature
+pub fn is_owner_kernel(kernel: &TxKernel) -> bool {
+	let fee_free = match kernel.features {
+		KernelFeatures::Plain { fee } => u64::from(fee) == 0,
+		_ => false,
+	};
+	fee_free && kernel.verify().is_ok()
+}
+
+/// Builds an output paying 'value' nanogrin of over-paid fee back to the swap's owner, blinded
+/// with 'blind', along with the owner kernel that balances it. The kernel is signed with
+/// 'owner_excess' plus 'blind', so the change output has no kernel of its own.
+pub fn build_fee_change(
+	value: u64,
+	blind: &SecretKey,
+	owner_excess: &SecretKey,
+) -> Result<(FeeChange, TxKernel), WalletError> {
+	let commit = secp::commit(value, blind).map_err(WalletError::KernelExcessError)?;
+	let proof = Secp256k1::new().bullet_proof(
+		value,
+		blind.clone(),
+		secp::random_secret(),
+		secp::random_secret(),
+		None,
+		None,
+	);
+	let mut kernel_excess = owner_excess.clone();
+	kernel_excess
+		.add_assign(&Secp256k1::new(), blind)
+		.map_err(WalletError::KernelExcessError)?;
+	let change = FeeChange {
+		value,
+		output: Output::new(OutputFeatures::Plain, commit, proof),
+	};
+	Ok((change, build_owner_kernel(&kernel_excess)?))
+}
+
+/// The round's own kernel in a transaction built by 'assemble_tx'.
+/// Owner kernels never pay a fee, so it's the only kernel that does.
+pub fn round_kernel(tx: &Transaction) -> Option<&TxKernel> {
+	tx.kernels().iter().find(|k| match k.features {
+		KernelFeatures::Plain { fee } | KernelFeatures::NoRecentDuplicate { fee, .. } => {
+			u64::from(fee) > 0
+		}
+		_ => false,
+	})
+}
+
+/// HTTP (JSONRPC) implementation of the 'Wallet' trait, using grin-wallet's V3 owner API.
+/// Requests are encrypted with a key agreed by ECDH ('init_secure_api'), and authorized by the
+/// token returned by 'open_wallet'. Both are lost whenever the wallet restarts, so the channel is
+/// re-initialized and the wallet reopened as soon as a request fails.
 #[derive(Clone)]
 pub struct HttpWallet {
+	client: HttpClient,
 	wallet_owner_url: SocketAddr,
 	wallet_owner_secret: Option<String>,
+	wallet_pass: ZeroingString,
+	session: Arc<RwLock<WalletSession>>,
+}
+
+/// The encrypted channel to the wallet, and the token of the opened wallet
+#[derive(Clone)]
+struct WalletSession {
 	shared_key: SecretKey,
 	token: Token,
 }
@@ -132,35 +493,108 @@ pub struct ECDHPubkey {
 impl HttpWallet {
 	/// Calls the 'open_wallet' using the RPC API.
 	pub fn open_wallet(
+		client: &HttpClient,
 		wallet_owner_url: &SocketAddr,
 		wallet_owner_secret: &Option<String>,
 		wallet_pass: &ZeroingString,
 	) -> Result<HttpWallet, WalletError> {
-		println!("Opening wallet at {}", wallet_owner_url);
-		let shared_key = HttpWallet::init_secure_api(&wallet_owner_url, &wallet_owner_secret)?;
+		info!("Opening wallet at {}", wallet_owner_url);
+		let session =
+			HttpWallet::open_session(client, wallet_owner_url, wallet_owner_secret, wallet_pass)?;
+		info!("Connected to wallet");
+
+		Ok(HttpWallet {
+			client: client.clone(),
+			wallet_owner_url: wallet_owner_url.clone(),
+			wallet_owner_secret: wallet_owner_secret.clone(),
+			wallet_pass: wallet_pass.clone(),
+			session: Arc::new(RwLock::new(session)),
+		})
+	}
+
+	/// Initializes the encrypted channel, then opens the wallet over it
+	fn open_session(
+		client: &HttpClient,
+		wallet_owner_url: &SocketAddr,
+		wallet_owner_secret: &Option<String>,
+		wallet_pass: &ZeroingString,
+	) -> Result<WalletSession, WalletError> {
+		let shared_key =
+			HttpWallet::init_secure_api(&client, &wallet_owner_url, &wallet_owner_secret)?;
 
 		let open_wallet_params = json!({
 			"name": null,
 			"password": wallet_pass.to_string()
 		});
 		let token: Token = HttpWallet::send_enc_request(
+			&client,
 			&wallet_owner_url,
 			&wallet_owner_secret,
 			"open_wallet",
 			&open_wallet_params,
 			&shared_key,
 		)?;
-		println!("Connected to wallet");
+		Ok(WalletSession { shared_key, token })
+	}
 
-		Ok(HttpWallet {
-			wallet_owner_url: wallet_owner_url.clone(),
-			wallet_owner_secret: wallet_owner_secret.clone(),
-			shared_key: shared_key.clone(),
-			token: token.clone(),
-		})
+	/// Replaces the session 'failed' with a new one, unless another request already replaced it
+	fn reconnect(&self, failed: &WalletSession) -> Result<WalletSession, WalletError> {
+		let mut session = self.session.write().unwrap();
+		if session.shared_key != failed.shared_key {
+			return Ok(session.clone());
+		}
+
+		info!("Reopening wallet at {}", self.wallet_owner_url);
+		*session = HttpWallet::open_session(
+			&self.client,
+			&self.wallet_owner_url,
+			&self.wallet_owner_secret,
+			&self.wallet_pass,
+		)?;
+		info!("Reconnected to wallet");
+		Ok(session.clone())
 	}
 
+	/// Calls 'method' with the open wallet's token added to 'params'.
+	/// If the call fails because the wallet restarted (or the session was otherwise lost),
+	/// the wallet is reopened and the call retried once.
+	fn call<D: serde::de::DeserializeOwned>(
+		&self,
+		method: &str,
+		params: serde_json::Value,
+	) -> Result<D, WalletError> {
+		let session = self.session.read().unwrap().clone();
+		match self.call_in_session(&session, method, params.clone()) {
+			Err(e) if e.is_session_error() => {
+				warn!(
+					"Wallet call '{}' failed: {}. Reopening the wallet.",
+					method, e
+				);
+				let session = self.reconnect(&session)?;
+				self.call_in_session(&session, method, params)
+			}
+			result => result,
+		}
+	}
+
+	fn call_in_session<D: serde::de::DeserializeOwned>(
+		&self,
+		session: &WalletSession,
+		method: &str,
+		mut params: serde_json::Value,
+	) -> Result<D, WalletError> {
+		params["token"] = json!(session.token.keychain_mask.clone().unwrap().0);
+		HttpWallet::send_enc_request(
+			&self.client,
+			&self.wallet_owner_url,
+			&self.wallet_owner_secret,
+			method,
+			&params,
+			&session.shared_key,
+		)
+	}
 	fn init_secure_api(
+		client: &HttpClient,
 		wallet_owner_url: &SocketAddr,
 		wallet_owner_secret: &Option<String>,
 	) -> Result<SecretKey, WalletError> {
@@ -172,6 +606,7 @@ impl HttpWallet {
 		});
 
 		let response_pk: ECDHPubkey = HttpWallet::send_json_request(
+			&client,
 			&wallet_owner_url,
 			&wallet_owner_secret,
 			"init_secure_api",
@@ -190,12 +625,14 @@ impl HttpWallet {
 	}
 
 	fn send_enc_request<D: serde::de::DeserializeOwned>(
+		client: &HttpClient,
 		wallet_owner_url: &SocketAddr,
 		wallet_owner_secret: &Option<String>,
 		method: &str,
 		params: &serde_json::Value,
 		shared_key: &SecretKey,
 	) -> Result<D, WalletError> {
+		debug!("Calling wallet owner API '{}'", method);
 		let url = format!("http://{}{}", wallet_owner_url, ENDPOINT);
 		let req = json!({
 			"method": method,
@@ -205,33 +642,49 @@ impl HttpWallet {
 		});
 		let enc_req = EncryptedRequest::from_json(&JsonId::IntId(1), &req, &shared_key)
 			.map_err(WalletError::EncryptRequestError)?;
-		let res = client::post::<EncryptedRequest, EncryptedResponse>(
-			url.as_str(),
-			wallet_owner_secret.clone(),
-			&enc_req,
-		)
-		.map_err(WalletError::ApiCommError)?;
+		let res = client
+			.post::<EncryptedRequest, EncryptedResponse>(
+				url.as_str(),
+				wallet_owner_secret.clone(),
+				&enc_req,
+			)
+			.map_err(WalletError::ApiCommError)?;
 		let decrypted = res
 			.decrypt(&shared_key)
 			.map_err(WalletError::DecryptResponseError)?;
 		let response: Response =
 			serde_json::from_value(decrypted).map_err(WalletError::DecodeResponseError)?;
-		let ok = response.result.unwrap().get("Ok").unwrap().clone();
-		let parsed = serde_json::from_value(ok).map_err(WalletError::DecodeResponseError)?;
-		Ok(parsed)
+		if let Some(error) = response.error {
+			return Err(WalletError::RpcError(error.message));
+		}
+		let result = response
+			.result
+			.ok_or_else(|| WalletError::RpcError("Response has no result".to_string()))?;
+		match (result.get("Ok"), result.get("Err")) {
+			(Some(ok), _) => {
+				serde_json::from_value(ok.clone()).map_err(WalletError::DecodeResponseError)
+			}
+			(None, Some(err)) => Err(WalletError::OwnerApiError(err.to_string())),
+			(None, None) => Err(WalletError::RpcError(format!(
+				"Unexpected result: {}",
+				result
+			))),
+		}
 	}
 
 	fn send_json_request<D: serde::de::DeserializeOwned>(
+		client: &HttpClient,
 		wallet_owner_url: &SocketAddr,
 		wallet_owner_secret: &Option<String>,
 		method: &str,
 		params: &serde_json::Value,
 	) -> Result<D, WalletError> {
+		debug!("Calling wallet owner API '{}'", method);
 		let url = format!("http://{}{}", wallet_owner_url, ENDPOINT);
 		let req = build_request(method, params);
-		let res =
-			client::post::<Request, Response>(url.as_str(), wallet_owner_secret.clone(), &req)
-				.map_err(WalletError::ApiCommError)?;
+		let res = client
+			.post::<Request, Response>(url.as_str(), wallet_owner_secret.clone(), &req)
+			.map_err(WalletError::ApiCommError)?;
 		let parsed = res
 			.clone()
 			.into_result()
@@ -254,33 +707,171 @@ impl Wallet for HttpWallet {
 	/// Builds an 'Output' for the wallet using the 'build_output' RPC API.
 	fn build_output(&self, amount: u64) -> Result<(BlindingFactor, Output), WalletError> {
 		let req_json = json!({
-			"token": self.token.keychain_mask.clone().unwrap().0,
 			"features": "Plain",
 			"amount":  amount
 		});
-		let output: OutputWithBlind = HttpWallet::send_enc_request(
-			&self.wallet_owner_url,
-			&self.wallet_owner_secret,
-			"build_output",
-			&req_json,
-			&self.shared_key,
-		)?;
+		let output: OutputWithBlind = self.call("build_output", req_json)?;
 		Ok((output.blind, output.output))
 	}
+
+	/// grin-wallet's 'build_output' only advances the account's derivation index, without locking
+	/// any of the wallet's outputs, so there is nothing to release. The unused key index is skipped.
+	fn cancel_output(&self, _output_commit: &Commitment) -> Result<(), WalletError> {
+		Ok(())
+	}
+
+	/// Calls the 'node_height' RPC API, which only succeeds while the wallet is open
+	fn ping(&self) -> Result<(), WalletError> {
+		self.call::<serde_json::Value>("node_height", json!({}))?;
+		Ok(())
+	}
+}
+
+/// Account the outputs of a wallet with a dedicated fee seed are derived under, as
+/// 'm/1/<round_id>/<index>'
+const ROUND_KEY_ACCOUNT: u32 = 1;
+
+/// Wallet that derives its outputs locally from a seed, without the need for a running grin-wallet.
+/// Outputs are derived using the same keychain and rangeproof scheme as grin-wallet, so they can
+/// be recovered by restoring a grin-wallet from the same seed.
+#[derive(Clone)]
+pub struct EmbeddedWallet {
+	keychain: ExtKeychain,
+	/// key index of the last output built
+	last_index: Arc<AtomicU32>,
+	/// the round outputs are being built for, when keys are derived per round
+	round: Option<Arc<AtomicU32>>,
+}
+
+impl EmbeddedWallet {
+	/// Creates an embedded wallet using the bytes of the server key as the seed.
+	pub fn from_secret_key(server_key: &SecretKey) -> Result<EmbeddedWallet, WalletError> {
+		EmbeddedWallet::from_seed(&server_key.0)
+	}
+
+	/// Creates an embedded wallet with the provided seed.
+	pub fn from_seed(seed: &[u8]) -> Result<EmbeddedWallet, WalletError> {
+		let keychain = ExtKeychain::from_seed(seed, !global::is_mainnet())
+			.map_err(WalletError::KeychainError)?;
+		Ok(EmbeddedWallet {
+			keychain,
+			last_index: Arc::new(AtomicU32::new(0)),
+			round: None,
+		})
+	}
+
+	/// Creates an embedded wallet with a seed of its own, rather than the server key's. Each
+	/// round's outputs are derived under the round's id (truncated to 32 bits), so the key
+	/// path of any fee output follows from the round it was built in.
+	pub fn from_fee_seed(seed: &SecretKey) -> Result<EmbeddedWallet, WalletError> {
+		let mut wallet = EmbeddedWallet::from_seed(&seed.0)?;
+		wallet.round = Some(Arc::new(AtomicU32::new(0)));
+		Ok(wallet)
+	}
+}
+
+impl Wallet for EmbeddedWallet {
+	/// Builds an 'Output' by deriving a new key locally.
+	/// The key index is the current unix time, or one past the last index used when several
+	/// outputs (e.g. decoys) are built within the same second, so each output derives a distinct key.
+	fn build_output(&self, amount: u64) -> Result<(BlindingFactor, Output), WalletError> {
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs() as u32)
+			.unwrap_or(0);
+		let next_index = |last: u32| now.max(last + 1);
+		let last = self
+			.last_index
+			.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+				Some(next_index(last))
+			})
+			.unwrap_or_else(|last| last);
+		let index = next_index(last);
+		let key_id = match &self.round {
+			Some(round) => ExtKeychain::derive_key_id(
+				3,
+				ROUND_KEY_ACCOUNT,
+				round.load(Ordering::SeqCst),
+				index,
+				0,
+			),
+			None => ExtKeychain::derive_key_id(3, 0, 0, index, 0),
+		};
+
+		let blind = self
+			.keychain
+			.derive_key(amount, &key_id, SwitchCommitmentType::Regular)
+			.map_err(WalletError::KeychainError)?;
+		let commit = self
+			.keychain
+			.commit(amount, &key_id, SwitchCommitmentType::Regular)
+			.map_err(WalletError::KeychainError)?;
+		let rangeproof = proof::create(
+			&self.keychain,
+			&proof::ProofBuilder::new(&self.keychain),
+			amount,
+			&key_id,
+			SwitchCommitmentType::Regular,
+			None,
+		)
+		.map_err(WalletError::RangeproofError)?;
+
+		let output = Output::new(OutputFeatures::Plain, commit, rangeproof);
+		Ok((BlindingFactor::from_secret_key(blind), output))
+	}
+
+	/// Embedded outputs are derived statelessly, so there is nothing to release.
+	fn cancel_output(&self, _output_commit: &Commitment) -> Result<(), WalletError> {
+		Ok(())
+	}
+
+	/// The embedded wallet runs in-process, so it's always reachable.
+	fn ping(&self) -> Result<(), WalletError> {
+		Ok(())
+	}
+
+	fn start_round(&self, round_id: u64) {
+		if let Some(round) = &self.round {
+			round.store(round_id as u32, Ordering::SeqCst);
+		}
+	}
 }
 
 #[cfg(test)]
 pub mod mock {
 	use super::{Wallet, WalletError};
-	use crate::secp;
+	use crate::secp::{self, Commitment};
 
 	use grin_core::core::{Output, OutputFeatures};
 	use grin_keychain::BlindingFactor;
 	use secp256k1zkp::Secp256k1;
+	use std::sync::{Arc, RwLock};
 
 	/// HTTP (JSONRPC) implementation of the 'Wallet' trait.
 	#[derive(Clone)]
-	pub struct MockWallet {}
+	pub struct MockWallet {
+		cancelled: Arc<RwLock<Vec<Commitment>>>,
+		locked: Arc<RwLock<bool>>,
+	}
+
+	impl MockWallet {
+		pub fn new() -> MockWallet {
+			MockWallet {
+				cancelled: Arc::new(RwLock::new(Vec::new())),
+				locked: Arc::new(RwLock::new(false)),
+			}
+		}
+
+		/// Makes 'ping' fail as a locked wallet's owner API would, until unlocked again
+		pub fn set_locked(&self, locked: bool) {
+			*self.locked.write().unwrap() = locked;
+		}
+
+		/// Outputs that were cancelled via 'cancel_output'
+		pub fn get_cancelled(&self) -> Vec<Commitment> {
+			self.cancelled.read().unwrap().clone()
+		}
+	}
 
 	impl Wallet for MockWallet {
 		/// Builds an 'Output' for the wallet using the 'build_output' RPC API.
@@ -299,5 +890,219 @@ pub mod mock {
 			let output = Output::new(OutputFeatures::Plain, commit.clone(), proof);
 			Ok((BlindingFactor::from_secret_key(blind), output))
 		}
+
+		fn cancel_output(&self, output_commit: &Commitment) -> Result<(), WalletError> {
+			self.cancelled.write().unwrap().push(output_commit.clone());
+			Ok(())
+		}
+
+		fn ping(&self) -> Result<(), WalletError> {
+			if *self.locked.read().unwrap() {
+				return Err(WalletError::OwnerApiError("Wallet is locked".to_string()));
+			}
+			Ok(())
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		assemble_tx, build_tx, check_fees, round_kernel, EmbeddedWallet, RoundKernelKind, Wallet,
+		WalletError, WalletOutput,
+	};
+	use crate::secp;
+	use crate::secp::test_util::{rand_commit, rand_proof};
+
+	use grin_core::core::{Input, Inputs, KernelFeatures, Output, OutputFeatures, TransactionBody};
+	use grin_core::global::{self, ChainTypes, DEFAULT_ACCEPT_FEE_BASE};
+	use secp256k1zkp::{ContextFlag, Secp256k1, SecretKey};
+	use std::sync::Arc;
+
+	/// The embedded wallet builds outputs with valid rangeproofs that match the returned blind.
+	#[test]
+	fn embedded_build_output() -> Result<(), Box<dyn std::error::Error>> {
+		global::set_local_chain_type(ChainTypes::AutomatedTesting);
+		let wallet = EmbeddedWallet::from_secret_key(&secp::random_secret())?;
+
+		let amount = 1_234_567;
+		let (blind, output) = wallet.build_output(amount)?;
+		output.verify_proof()?;
+
+		let secp = Secp256k1::with_caps(ContextFlag::Commit);
+		let blind = SecretKey::from_slice(&secp, blind.as_ref())?;
+		assert_eq!(secp::commit(amount, &blind)?, output.commitment());
+
+		// Outputs built within the same second still derive distinct keys
+		let (_, other) = wallet.build_output(amount)?;
+		assert_ne!(output.commitment(), other.commitment());
+
+		Ok(())
+	}
+
+	/// A wallet with a fee seed of its own derives each round's outputs under the round
+	#[test]
+	fn fee_seed_build_output() -> Result<(), Box<dyn std::error::Error>> {
+		global::set_local_chain_type(ChainTypes::AutomatedTesting);
+		let seed = secp::random_secret();
+		let wallet = EmbeddedWallet::from_fee_seed(&seed)?;
+
+		let amount = 1_234_567;
+		wallet.start_round(7);
+		let (blind, output) = wallet.build_output(amount)?;
+		output.verify_proof()?;
+		let secp = Secp256k1::with_caps(ContextFlag::Commit);
+		let blind = SecretKey::from_slice(&secp, blind.as_ref())?;
+		assert_eq!(secp::commit(amount, &blind)?, output.commitment());
+
+		// Neither the server key's wallet nor another round derive the same key
+		let (_, unseeded) = EmbeddedWallet::from_secret_key(&seed)?.build_output(amount)?;
+		assert_ne!(output.commitment(), unseeded.commitment());
+		let other_round = EmbeddedWallet::from_fee_seed(&seed)?;
+		other_round.start_round(8);
+		let (_, other) = other_round.build_output(amount)?;
+		assert_ne!(output.commitment(), other.commitment());
+
+		Ok(())
+	}
+
+	/// Transactions list their inputs and outputs in the same order, however the swaps were ordered.
+	#[test]
+	fn build_tx_canonical_order() -> Result<(), Box<dyn std::error::Error>> {
+		let mut inputs: Vec<Input> = (0..5)
+			.map(|_| Input::new(OutputFeatures::Plain, rand_commit()))
+			.collect();
+		let mut outputs: Vec<Output> = (0..5)
+			.map(|_| Output::new(OutputFeatures::Plain, rand_commit(), rand_proof()))
+			.collect();
+		let excesses: Vec<SecretKey> = (0..5).map(|_| secp::random_secret()).collect();
+
+		let plain = RoundKernelKind::Plain;
+		let tx = build_tx(
+			&inputs,
+			&outputs,
+			&excesses,
+			&vec![],
+			&vec![],
+			1_000_000,
+			plain,
+		)?;
+		inputs.reverse();
+		outputs.reverse();
+		let reversed = build_tx(
+			&inputs,
+			&outputs,
+			&excesses,
+			&vec![],
+			&vec![],
+			1_000_000,
+			plain,
+		)?;
+
+		assert_eq!(tx.inputs(), reversed.inputs());
+		assert_eq!(tx.outputs(), reversed.outputs());
+		inputs.sort_unstable();
+		assert_eq!(Inputs::from(inputs.as_slice()), tx.inputs());
+
+		Ok(())
+	}
+
+	/// The assembled round transaction pays out exactly the swaps' fees, and a transaction that
+	/// doesn't is caught before it's posted
+	#[test]
+	fn assemble_tx_fees() -> Result<(), Box<dyn std::error::Error>> {
+		global::set_local_chain_type(ChainTypes::AutomatedTesting);
+		let wallet: Arc<dyn Wallet> =
+			Arc::new(EmbeddedWallet::from_secret_key(&secp::random_secret())?);
+		let inputs = vec![Input::new(OutputFeatures::Plain, rand_commit())];
+		let outputs = vec![Output::new(
+			OutputFeatures::Plain,
+			rand_commit(),
+			rand_proof(),
+		)];
+		let excesses = vec![secp::random_secret()];
+		let total_fee = 50_000_000;
+		let fee_required = TransactionBody::weight_by_iok(1, 2, 1) * DEFAULT_ACCEPT_FEE_BASE;
+
+		let (tx, wallet_output) = assemble_tx(
+			&wallet,
+			&inputs,
+			&outputs,
+			DEFAULT_ACCEPT_FEE_BASE,
+			total_fee,
+			&excesses,
+			&vec![],
+			RoundKernelKind::Plain,
+		)?;
+		let wallet_output = wallet_output.unwrap();
+		assert_eq!(total_fee - fee_required, wallet_output.amount);
+		assert_eq!(fee_required, tx.fee());
+
+		assert!(matches!(
+			check_fees(
+				&tx,
+				total_fee + 1,
+				fee_required,
+				&Some(wallet_output.clone())
+			),
+			Err(WalletError::FeeMismatch { .. })
+		));
+		let overstated = WalletOutput {
+			amount: wallet_output.amount + 1,
+			..wallet_output.clone()
+		};
+		assert!(matches!(
+			check_fees(&tx, total_fee + 1, fee_required, &Some(overstated)),
+			Err(WalletError::EarningsMismatch { .. })
+		));
+		check_fees(&tx, total_fee, fee_required, &Some(wallet_output))?;
+		Ok(())
+	}
+
+	/// With a relative height configured, the round's fee-paying kernel is an NRD kernel
+	#[test]
+	fn build_tx_nrd_kernel() -> Result<(), Box<dyn std::error::Error>> {
+		let inputs = vec![Input::new(OutputFeatures::Plain, rand_commit())];
+		let outputs = vec![Output::new(
+			OutputFeatures::Plain,
+			rand_commit(),
+			rand_proof(),
+		)];
+		let excesses = vec![secp::random_secret()];
+
+		let nrd = RoundKernelKind::new(Some(1440));
+		let tx = build_tx(
+			&inputs,
+			&outputs,
+			&excesses,
+			&vec![],
+			&vec![],
+			1_000_000,
+			nrd,
+		)?;
+		let kernel = round_kernel(&tx).unwrap();
+		match kernel.features {
+			KernelFeatures::NoRecentDuplicate {
+				fee,
+				relative_height,
+			} => {
+				assert_eq!(1_000_000, u64::from(fee));
+				assert_eq!(1440, u64::from(relative_height));
+			}
+			_ => panic!("expected an NRD kernel, found {:?}", kernel.features),
+		}
+
+		assert_eq!(RoundKernelKind::Plain, RoundKernelKind::new(None));
+		assert!(build_tx(
+			&inputs,
+			&outputs,
+			&excesses,
+			&vec![],
+			&vec![],
+			1_000_000,
+			RoundKernelKind::new(Some(0)),
+		)
+		.is_err());
+		Ok(())
 	}
 }
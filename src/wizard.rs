@@ -0,0 +1,518 @@
+//! Guided setup for 'init-config', used when it's run from a terminal.
+//!
+//! Each setting is asked for in turn, suggesting what was passed on the command line or else the
+//! chosen network's default, and is checked before moving on: the node and wallet are looked for
+//! on this machine, the node's api is queried with the chosen secret, and the bind address is
+//! tried, so the config written is one the server can start with.
+
+use crate::config::{self, ListenAddr, WalletBackend};
+use crate::http::{HttpClient, RetryPolicy};
+use crate::interval::ChainInterval;
+use crate::node::{GrinNode, HttpGrinNode};
+
+use grin_core::global::ChainTypes;
+use grin_util::file;
+use std::io::{BufRead, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::time::Duration;
+use thiserror::Error;
+
+/// How long to wait for a local node or wallet to answer
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Settings given on the command line, suggested in place of the network's defaults
+#[derive(Clone, Debug, Default)]
+pub struct Given {
+	pub grin_node_url: Option<SocketAddr>,
+	pub grin_node_secret_path: Option<String>,
+	pub wallet_backend: Option<WalletBackend>,
+	pub wallet_owner_url: Option<SocketAddr>,
+	pub wallet_owner_secret_path: Option<String>,
+	pub addr: Option<ListenAddr>,
+	pub round_interval: Option<ChainInterval>,
+}
+
+/// The settings a new config is written with
+#[derive(Clone, Debug, PartialEq)]
+pub struct Setup {
+	pub chain_type: ChainTypes,
+	pub grin_node_url: SocketAddr,
+	pub grin_node_secret_path: Option<String>,
+	pub wallet_backend: WalletBackend,
+	pub wallet_owner_url: SocketAddr,
+	pub wallet_owner_secret_path: Option<String>,
+	pub addr: ListenAddr,
+	pub round_interval: ChainInterval,
+}
+
+impl Setup {
+	/// The settings used without the wizard: those given, and the network's defaults for the rest
+	pub fn from_given(
+		chain_type: ChainTypes,
+		given: &Given,
+		default_interval: ChainInterval,
+	) -> Setup {
+		Setup {
+			chain_type,
+			grin_node_url: given
+				.grin_node_url
+				.unwrap_or_else(|| config::grin_node_url(&chain_type)),
+			grin_node_secret_path: given.grin_node_secret_path.clone().or_else(|| {
+				config::node_secret_path(&chain_type)
+					.to_str()
+					.map(|p| p.to_owned())
+			}),
+			wallet_backend: given.wallet_backend.unwrap_or_default(),
+			wallet_owner_url: given
+				.wallet_owner_url
+				.unwrap_or_else(|| config::wallet_owner_url(&chain_type)),
+			wallet_owner_secret_path: given.wallet_owner_secret_path.clone().or_else(|| {
+				config::wallet_owner_secret_path(&chain_type)
+					.to_str()
+					.map(|p| p.to_owned())
+			}),
+			addr: given
+				.addr
+				.clone()
+				.unwrap_or_else(|| "0.0.0.0:3000".parse().unwrap()),
+			round_interval: given.round_interval.unwrap_or(default_interval),
+		}
+	}
+}
+
+/// Error types for the wizard
+#[derive(Error, Debug)]
+pub enum WizardError {
+	#[error("Setup cancelled, nothing was written")]
+	Aborted,
+	#[error("Terminal error: {0}")]
+	IoError(std::io::Error),
+}
+
+impl From<std::io::Error> for WizardError {
+	fn from(e: std::io::Error) -> WizardError {
+		WizardError::IoError(e)
+	}
+}
+
+/// Asks for each setting on 'output', reading the answers from 'input'
+pub struct Wizard<R: BufRead, W: Write> {
+	input: R,
+	output: W,
+}
+
+impl<R: BufRead, W: Write> Wizard<R, W> {
+	pub fn new(input: R, output: W) -> Wizard<R, W> {
+		Wizard { input, output }
+	}
+
+	/// Walks through the settings, starting from 'chain_type' and those given
+	pub fn run(
+		&mut self,
+		chain_type: ChainTypes,
+		given: &Given,
+		default_interval: ChainInterval,
+	) -> Result<Setup, WizardError> {
+		writeln!(
+			self.output,
+			"Setting up a new mwixnet server. Press enter to accept the suggestion in brackets."
+		)?;
+
+		let default_network = network_name(&chain_type);
+		let chain_type =
+			self.ask_parsed("Network (mainnet/testnet)", default_network, |s| match s {
+				"mainnet" => Ok(ChainTypes::Mainnet),
+				"testnet" => Ok(ChainTypes::Testnet),
+				_ => Err("enter 'mainnet' or 'testnet'".to_string()),
+			})?;
+		let defaults = Setup::from_given(chain_type, given, default_interval);
+
+		let (grin_node_url, grin_node_secret_path) = loop {
+			self.detect("grin node", &defaults.grin_node_url)?;
+			let url = self.ask_parsed(
+				"Grin node api address",
+				&defaults.grin_node_url.to_string(),
+				parse_socket_addr,
+			)?;
+			let secret_path =
+				self.ask_secret_path("Grin node api secret file", &defaults.grin_node_secret_path)?;
+			match node_height(&url, &secret_path) {
+				Ok(height) => {
+					writeln!(self.output, "Connected to the node, at height {}.", height)?;
+					break (url, secret_path);
+				}
+				Err(e) => {
+					writeln!(self.output, "Couldn't query the node: {}", e)?;
+					if self.confirm("Use this node anyway?", false)? {
+						break (url, secret_path);
+					}
+				}
+			}
+		};
+
+		let wallet_found = probe(&defaults.wallet_owner_url);
+		let default_backend = match (given.wallet_backend, wallet_found) {
+			(Some(backend), _) => backend,
+			(None, true) => WalletBackend::OwnerApi,
+			(None, false) => WalletBackend::Embedded,
+		};
+		if wallet_found {
+			writeln!(
+				self.output,
+				"Found a grin wallet owner api at {}.",
+				defaults.wallet_owner_url
+			)?;
+		}
+		let wallet_backend = self.ask_parsed(
+			"Fee wallet (owner_api/embedded/seed)",
+			backend_name(&default_backend),
+			|s| {
+				s.parse::<WalletBackend>()
+					.map_err(|_| "enter 'owner_api', 'embedded' or 'seed'".to_string())
+			},
+		)?;
+
+		let (wallet_owner_url, wallet_owner_secret_path) = match wallet_backend {
+			WalletBackend::OwnerApi => loop {
+				let url = self.ask_parsed(
+					"Wallet owner api address",
+					&defaults.wallet_owner_url.to_string(),
+					parse_socket_addr,
+				)?;
+				let secret_path = self.ask_secret_path(
+					"Wallet owner api secret file",
+					&defaults.wallet_owner_secret_path,
+				)?;
+				if probe(&url) {
+					break (url, secret_path);
+				}
+				writeln!(self.output, "Nothing is listening at {}.", url)?;
+				if self.confirm("Use this wallet anyway?", false)? {
+					break (url, secret_path);
+				}
+			},
+			// The owner api isn't used, so the defaults are kept in case it's switched to later
+			_ => (
+				defaults.wallet_owner_url,
+				defaults.wallet_owner_secret_path.clone(),
+			),
+		};
+
+		let addr = loop {
+			let addr = self.ask_parsed(
+				"Address to serve the api on",
+				&defaults.addr.to_string(),
+				|s| {
+					s.parse::<ListenAddr>()
+						.map_err(|e| format!("{}, or 'unix:' and a socket path", e))
+				},
+			)?;
+			match check_bind(&addr) {
+				Ok(()) => break addr,
+				Err(e) => {
+					writeln!(self.output, "Can't listen on {}: {}", addr, e)?;
+					if self.confirm("Use this address anyway?", false)? {
+						break addr;
+					}
+				}
+			}
+		};
+
+		let round_interval = self.ask_parsed(
+			"Time between rounds (e.g. '12h' or '720 blocks')",
+			&defaults.round_interval.to_string(),
+			|s| s.parse::<ChainInterval>().map_err(|e| e.to_string()),
+		)?;
+
+		let setup = Setup {
+			chain_type,
+			grin_node_url,
+			grin_node_secret_path,
+			wallet_backend,
+			wallet_owner_url,
+			wallet_owner_secret_path,
+			addr,
+			round_interval,
+		};
+		self.summarize(&setup)?;
+		if !self.confirm("Write the config with these settings?", true)? {
+			return Err(WizardError::Aborted);
+		}
+		Ok(setup)
+	}
+
+	/// Reads an answer, or the default if none is given
+	fn ask(&mut self, question: &str, default: &str) -> Result<String, WizardError> {
+		write!(self.output, "{} [{}]: ", question, default)?;
+		self.output.flush()?;
+		let mut line = String::new();
+		if self.input.read_line(&mut line)? == 0 {
+			return Err(WizardError::Aborted);
+		}
+		let answer = line.trim();
+		Ok(match answer {
+			"" => default.to_string(),
+			answer => answer.to_string(),
+		})
+	}
+
+	/// Asks until an answer passes 'parse'
+	fn ask_parsed<T, F>(
+		&mut self,
+		question: &str,
+		default: &str,
+		parse: F,
+	) -> Result<T, WizardError>
+	where
+		F: Fn(&str) -> Result<T, String>,
+	{
+		loop {
+			let answer = self.ask(question, default)?;
+			match parse(&answer) {
+				Ok(value) => return Ok(value),
+				Err(e) => writeln!(self.output, "Invalid answer: {}", e)?,
+			}
+		}
+	}
+
+	fn confirm(&mut self, question: &str, default: bool) -> Result<bool, WizardError> {
+		let default = if default { "y" } else { "n" };
+		self.ask_parsed(question, default, |s| match s {
+			"y" | "yes" => Ok(true),
+			"n" | "no" => Ok(false),
+			_ => Err("enter 'y' or 'n'".to_string()),
+		})
+	}
+
+	/// Asks for a secret file, which must exist and hold a secret. 'none' is accepted for an api
+	/// that's not protected by one.
+	fn ask_secret_path(
+		&mut self,
+		question: &str,
+		default: &Option<String>,
+	) -> Result<Option<String>, WizardError> {
+		let default = match default {
+			Some(path) if Path::new(path).exists() => path.as_str(),
+			_ => "none",
+		};
+		self.ask_parsed(&format!("{} (or 'none')", question), default, |s| {
+			if s == "none" {
+				return Ok(None);
+			}
+			check_secret_path(s).map(|()| Some(s.to_string()))
+		})
+	}
+
+	fn detect(&mut self, name: &str, addr: &SocketAddr) -> Result<(), WizardError> {
+		if probe(addr) {
+			writeln!(self.output, "Found a {} listening at {}.", name, addr)?;
+		} else {
+			writeln!(self.output, "No {} found at {}.", name, addr)?;
+		}
+		Ok(())
+	}
+
+	fn summarize(&mut self, setup: &Setup) -> Result<(), WizardError> {
+		let none = "none".to_string();
+		writeln!(self.output)?;
+		writeln!(
+			self.output,
+			"network:         {}",
+			network_name(&setup.chain_type)
+		)?;
+		writeln!(self.output, "grin node:       {}", setup.grin_node_url)?;
+		writeln!(
+			self.output,
+			"node secret:     {}",
+			setup.grin_node_secret_path.as_ref().unwrap_or(&none)
+		)?;
+		writeln!(
+			self.output,
+			"fee wallet:      {}",
+			backend_name(&setup.wallet_backend)
+		)?;
+		if setup.wallet_backend == WalletBackend::OwnerApi {
+			writeln!(self.output, "wallet:          {}", setup.wallet_owner_url)?;
+			writeln!(
+				self.output,
+				"wallet secret:   {}",
+				setup.wallet_owner_secret_path.as_ref().unwrap_or(&none)
+			)?;
+		}
+		writeln!(self.output, "listening on:    {}", setup.addr)?;
+		writeln!(self.output, "round interval:  {}", setup.round_interval)?;
+		Ok(())
+	}
+}
+
+fn network_name(chain_type: &ChainTypes) -> &'static str {
+	match chain_type {
+		ChainTypes::Testnet => "testnet",
+		_ => "mainnet",
+	}
+}
+
+fn backend_name(backend: &WalletBackend) -> &'static str {
+	match backend {
+		WalletBackend::OwnerApi => "owner_api",
+		WalletBackend::Embedded => "embedded",
+		WalletBackend::Seed => "seed",
+	}
+}
+
+fn parse_socket_addr(s: &str) -> Result<SocketAddr, String> {
+	config::parse_addr(s).map_err(|e| e.to_string())
+}
+
+/// Whether something accepts connections at 'addr'
+fn probe(addr: &SocketAddr) -> bool {
+	TcpStream::connect_timeout(addr, PROBE_TIMEOUT).is_ok()
+}
+
+/// The node's chain height, as a check that its api is up and the secret is accepted
+fn node_height(url: &SocketAddr, secret_path: &Option<String>) -> Result<u64, String> {
+	let client = HttpClient::default()
+		.with_timeout(PROBE_TIMEOUT)
+		.with_retry(RetryPolicy {
+			retries: 0,
+			backoff: Duration::from_secs(0),
+		});
+	let node = HttpGrinNode::new(url, &file::get_first_line(secret_path.clone()), client);
+	node.get_chain_height().map_err(|e| e.to_string())
+}
+
+fn check_secret_path(path: &str) -> Result<(), String> {
+	match std::fs::read_to_string(path) {
+		Ok(contents)
+			if contents
+				.lines()
+				.next()
+				.map_or(false, |l| !l.trim().is_empty()) =>
+		{
+			Ok(())
+		}
+		Ok(_) => Err(format!("{} is empty", path)),
+		Err(e) => Err(format!("can't read {}: {}", path, e)),
+	}
+}
+
+/// Checks the server will be able to listen on 'addr'
+fn check_bind(addr: &ListenAddr) -> Result<(), String> {
+	match addr {
+		ListenAddr::Tcp(addr) => TcpListener::bind(addr)
+			.map(|_| ())
+			.map_err(|e| e.to_string()),
+		ListenAddr::Unix(path) => match path.parent() {
+			Some(dir) if !dir.as_os_str().is_empty() && !dir.is_dir() => {
+				Err(format!("directory {} doesn't exist", dir.display()))
+			}
+			_ => Ok(()),
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Given, Setup, Wizard, WizardError};
+	use crate::config::{ListenAddr, WalletBackend};
+	use crate::interval::ChainInterval;
+
+	use grin_core::global::ChainTypes;
+	use std::io::Cursor;
+	use std::net::{SocketAddr, TcpListener};
+	use std::path::PathBuf;
+
+	const INTERVAL: ChainInterval = ChainInterval::Seconds(12 * 60 * 60);
+
+	/// An address nothing is listening on
+	fn closed_addr() -> SocketAddr {
+		TcpListener::bind("127.0.0.1:0")
+			.unwrap()
+			.local_addr()
+			.unwrap()
+	}
+
+	fn run(answers: &str, given: &Given) -> (Result<Setup, WizardError>, String) {
+		let mut output = Vec::new();
+		let result = Wizard::new(Cursor::new(answers.to_string()), &mut output).run(
+			ChainTypes::Mainnet,
+			given,
+			INTERVAL,
+		);
+		(result, String::from_utf8(output).unwrap())
+	}
+
+	/// Answers are validated and asked for again, and unreachable services need confirming
+	#[test]
+	fn wizard() -> Result<(), Box<dyn std::error::Error>> {
+		let dir = PathBuf::from("./target/tmp/.wizard");
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir)?;
+		let secret = dir.join("foreign_api_secret");
+		std::fs::write(&secret, "secret\n")?;
+		let secret = secret.to_str().unwrap();
+
+		let node_addr = closed_addr();
+		let given = Given {
+			grin_node_url: Some(node_addr),
+			..Given::default()
+		};
+		let answers = [
+			"regtest",                     // invalid network
+			"testnet",                     // network
+			"",                            // node address, as given
+			"./target/tmp/.wizard/nosuch", // missing secret file
+			secret,                        // node secret
+			"y",                           // unreachable node, use anyway
+			"embedded",                    // fee wallet
+			"not an address",              // invalid bind address
+			"127.0.0.1:0",                 // bind address
+			"720 blocks",                  // round interval
+			"",                            // write the config
+		]
+		.join("\n");
+		let (setup, output) = run(&answers, &given);
+		let setup = setup?;
+		assert_eq!(
+			Setup {
+				chain_type: ChainTypes::Testnet,
+				grin_node_url: node_addr,
+				grin_node_secret_path: Some(secret.to_string()),
+				wallet_backend: WalletBackend::Embedded,
+				addr: ListenAddr::Tcp("127.0.0.1:0".parse()?),
+				round_interval: ChainInterval::Blocks(720),
+				..Setup::from_given(ChainTypes::Testnet, &given, INTERVAL)
+			},
+			setup
+		);
+		assert!(output.contains(&format!("No grin node found at {}", node_addr)));
+		assert!(output.contains("Couldn't query the node"));
+		assert_eq!(3, output.matches("Invalid answer").count());
+
+		// Declining to write the config, or running out of answers, writes nothing
+		let answers = ["", "", "none", "y", "seed", "", "", "n"].join("\n");
+		let (setup, _) = run(&answers, &given);
+		assert!(matches!(setup, Err(WizardError::Aborted)));
+		let (setup, _) = run("mainnet\n", &given);
+		assert!(matches!(setup, Err(WizardError::Aborted)));
+
+		Ok(())
+	}
+
+	/// A wallet listening on the owner api address is found and suggested
+	#[test]
+	fn detect_wallet() -> Result<(), Box<dyn std::error::Error>> {
+		let wallet = TcpListener::bind("127.0.0.1:0")?;
+		let given = Given {
+			grin_node_url: Some(closed_addr()),
+			wallet_owner_url: Some(wallet.local_addr()?),
+			..Given::default()
+		};
+		let answers = ["", "", "none", "y", "", "", "none", "127.0.0.1:0", "", ""].join("\n");
+		let (setup, output) = run(&answers, &given);
+		assert_eq!(WalletBackend::OwnerApi, setup?.wallet_backend);
+		assert!(output.contains("Found a grin wallet owner api"));
+		Ok(())
+	}
+}